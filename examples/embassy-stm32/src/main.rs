@@ -0,0 +1,60 @@
+//! Sets the flow of an SFC6xxx on USART1 of an STM32F411 (a "Black Pill" board) and reads it back
+//! once a second, on the Embassy executor. PA9 is TX and PA10 is RX, wired to the device
+//! through an RS-485 transceiver or directly to its UART.
+//!
+//! Build with `cargo build --release` from this directory, flash with probe-rs or any other
+//! tool.
+#![no_std]
+#![no_main]
+
+use core::future::Future;
+use core::time::Duration;
+
+use embassy_executor::Spawner;
+use embassy_stm32::usart::{self, BufferedUart};
+use embassy_stm32::{bind_interrupts, peripherals};
+use embassy_time::Timer;
+use panic_halt as _;
+use sfc6xxx_rs::async_device::AsyncDevice;
+use sfc6xxx_rs::sfc_core::async_transport::{Delay, FromEmbeddedIo};
+
+bind_interrupts!(struct Irqs {
+    USART1 => usart::BufferedInterruptHandler<peripherals::USART1>;
+});
+
+/// The timeouts of the driver on the Embassy timer
+struct EmbassyDelay;
+
+impl Delay for EmbassyDelay {
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()> {
+        Timer::after_micros(duration.as_micros() as u64)
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(Default::default());
+
+    let mut config = usart::Config::default();
+    config.baudrate = 115200;
+    let mut tx_buffer = [0_u8; 64];
+    let mut rx_buffer = [0_u8; 64];
+    let uart = BufferedUart::new(
+        p.USART1,
+        Irqs,
+        p.PA10,
+        p.PA9,
+        &mut tx_buffer,
+        &mut rx_buffer,
+        config,
+    )
+    .unwrap();
+
+    let mut device = AsyncDevice::new(FromEmbeddedIo(uart), EmbassyDelay, 0).await.unwrap();
+    device.set_setpoint(2.5).await.unwrap();
+    loop {
+        // a failed read is just tried again on the next round
+        let _flow = device.read_measured_value().await;
+        Timer::after_secs(1).await;
+    }
+}
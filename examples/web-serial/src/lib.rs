@@ -0,0 +1,159 @@
+//! Drives an SFC6xxx from a browser through the Web Serial API. The page opens the port and
+//! hands a pair of callbacks to [connect], the driver runs on them and on `setTimeout`:
+//! ```js
+//! import init, { connect } from "./pkg/sfc6xxx_web_serial.js";
+//!
+//! await init();
+//! const port = await navigator.serial.requestPort();
+//! await port.open({ baudRate: 115200 });
+//! const reader = port.readable.getReader();
+//! const writer = port.writable.getWriter();
+//!
+//! const device = await connect(
+//!     async () => {
+//!         const { value, done } = await reader.read();
+//!         return done ? new Uint8Array() : value;
+//!     },
+//!     (bytes) => writer.write(bytes),
+//!     0,
+//! );
+//! await device.set_setpoint(2.5);
+//! console.log(await device.read_measured_value());
+//! ```
+//!
+//! Build with `wasm-pack build --target web` from this directory, or `cargo build --release`
+//! for just the wasm module.
+
+use core::future::Future;
+use core::time::Duration;
+
+use js_sys::{Date, Function, Promise, Uint8Array};
+use sfc6xxx_rs::async_device::AsyncDevice;
+use sfc6xxx_rs::sfc_core::async_transport::{AsyncTransport, Delay};
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &Function, timeout: i32) -> JsValue;
+}
+
+/// A rejected promise or a throwing callback, as the error of the stream
+fn js_error(error: JsValue) -> DeviceError {
+    std::io::Error::other(format!("{:?}", error)).into()
+}
+
+/// An [AsyncTransport] on two JavaScript callbacks. `read` resolves to the next chunk of bytes
+/// as a `Uint8Array`, an empty one once the port is closed, and `write` to nothing once the
+/// bytes were written.
+struct JsStream {
+    read: Function,
+    write: Function,
+    /// The read that is still running when the connection stopped waiting for it, its bytes
+    /// are picked up by the next read
+    reading: Option<JsFuture>,
+    /// What is left of the last chunk
+    chunk: Vec<u8>,
+}
+
+impl AsyncTransport for JsStream {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        if self.chunk.is_empty() {
+            if self.reading.is_none() {
+                let promise = self.read.call0(&JsValue::NULL).map_err(js_error)?;
+                self.reading = Some(JsFuture::from(Promise::resolve(&promise)));
+            }
+            let chunk = self.reading.as_mut().unwrap().await;
+            self.reading = None;
+            self.chunk = Uint8Array::new(&chunk.map_err(js_error)?).to_vec();
+        }
+        let count = buf.len().min(self.chunk.len());
+        buf[..count].copy_from_slice(&self.chunk[..count]);
+        self.chunk.drain(..count);
+        Ok(count)
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), DeviceError> {
+        let bytes = Uint8Array::from(buf);
+        let promise = self.write.call1(&JsValue::NULL, &bytes).map_err(js_error)?;
+        JsFuture::from(Promise::resolve(&promise)).await.map_err(js_error)?;
+        Ok(())
+    }
+
+    // the writer has handed the bytes to the port once its promise resolved
+    async fn flush(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// A [Delay] on `setTimeout`, with the clock of `Date.now()`
+struct JsDelay;
+
+impl Delay for JsDelay {
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()> {
+        let milliseconds = duration.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+        let timer = Promise::new(&mut |resolve, _| {
+            set_timeout(&resolve, milliseconds);
+        });
+        async move {
+            let _ = JsFuture::from(timer).await;
+        }
+    }
+
+    fn now(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(Date::now() / 1000.0))
+    }
+}
+
+fn to_js(error: DeviceError) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+/// An SFC6xxx behind a Web Serial port. Every method returns a promise, which rejects with the
+/// message of the [DeviceError].
+#[wasm_bindgen]
+pub struct Sfc6xxx {
+    device: AsyncDevice<JsStream, JsDelay>,
+}
+
+/// Connects to the device with the given slave address on the `read` and `write` callbacks
+#[wasm_bindgen]
+pub async fn connect(
+    read: Function,
+    write: Function,
+    slave_address: u8,
+) -> Result<Sfc6xxx, JsError> {
+    let stream = JsStream {
+        read,
+        write,
+        reading: None,
+        chunk: Vec::new(),
+    };
+    let device = AsyncDevice::new(stream, JsDelay, slave_address).await.map_err(to_js)?;
+    Ok(Sfc6xxx { device })
+}
+
+#[wasm_bindgen]
+impl Sfc6xxx {
+    pub async fn get_setpoint(&mut self) -> Result<f32, JsError> {
+        self.device.get_setpoint().await.map_err(to_js)
+    }
+
+    pub async fn set_setpoint(&mut self, setpoint: f32) -> Result<(), JsError> {
+        self.device.set_setpoint(setpoint).await.map_err(to_js)
+    }
+
+    pub async fn read_measured_value(&mut self) -> Result<f32, JsError> {
+        self.device.read_measured_value().await.map_err(to_js)
+    }
+
+    pub async fn measure_temperature(&mut self) -> Result<f32, JsError> {
+        self.device.measure_temperature().await.map_err(to_js)
+    }
+
+    pub async fn get_serial_number(&mut self) -> Result<String, JsError> {
+        self.device.get_serial_number().await.map_err(to_js)
+    }
+}
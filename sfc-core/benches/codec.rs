@@ -0,0 +1,160 @@
+//! The SHDLC codec on the payloads a device exchanges: run with `cargo bench -p sfc-core`.
+//! A worst case payload needs every byte escaped, a typical one none or a few.
+//!
+//! Baseline on a shared x86_64 Linux VM, where runs differ by up to 15% and the round trips by
+//! more. Before is the codec that stuffed in two passes and decoded one byte at a time, after
+//! sums the checksum while stuffing and copies the runs of plain bytes at once when decoding:
+//!
+//! | benchmark               | before  | after  |
+//! |-------------------------|---------|--------|
+//! | to_shdlc/float          | 97 ns   | 89 ns  |
+//! | to_shdlc/buffered       | 374 ns  | 349 ns |
+//! | to_shdlc/escaped        | 488 ns  | 483 ns |
+//! | from_shdlc/float        | 72 ns   | 87 ns  |
+//! | from_shdlc/buffered     | 244 ns  | 244 ns |
+//! | from_shdlc/escaped      | 425 ns  | 435 ns |
+//! | calculate_check_sum/255 | 12 ns   | 14 ns  |
+//! | round_trip/float        | 62 ns   | 65 ns  |
+//! | round_trip/buffered     | 508 ns  | 526 ns |
+//! | round_trip/escaped      | 857 ns  | 888 ns |
+//! | decoder/float           | 36 ns   | 31 ns  |
+//! | decoder/buffered        | 774 ns  | 334 ns |
+//! | decoder/escaped         | 1.46 us | 920 ns |
+//!
+//! Building requests, before and after stuffing them straight into the frame with the checksum
+//! summed up by `Checksum`, measured back to back. The `SmallFrame` is within the noise:
+//!
+//! | benchmark           | before | after  |
+//! |---------------------|--------|--------|
+//! | to_shdlc/float      | 108 ns | 91 ns  |
+//! | to_shdlc/buffered   | 493 ns | 364 ns |
+//! | to_shdlc/escaped    | 571 ns | 480 ns |
+//! | mosi_frame/float    | 114 ns | 99 ns  |
+//! | mosi_frame/buffered | 414 ns | 376 ns |
+//! | mosi_frame/escaped  | 769 ns | 476 ns |
+//! | mosi_frame/small    | 16 ns  | 18 ns  |
+//!
+//! Decoding, before and after `from_shdlc` copied the runs of plain bytes at once and every
+//! decoder found the next special byte eight bytes at a time, measured back to back. The
+//! payloads of a float are within the noise:
+//!
+//! | benchmark           | before  | after  |
+//! |---------------------|---------|--------|
+//! | from_shdlc/float    | 68 ns   | 72 ns  |
+//! | from_shdlc/buffered | 249 ns  | 109 ns |
+//! | from_shdlc/escaped  | 429 ns  | 427 ns |
+//! | round_trip/float    | 48 ns   | 56 ns  |
+//! | round_trip/buffered | 523 ns  | 336 ns |
+//! | round_trip/escaped  | 861 ns  | 754 ns |
+//! | decoder/float       | 31 ns   | 25 ns  |
+//! | decoder/buffered    | 389 ns  | 237 ns |
+//! | decoder/escaped     | 1.06 us | 935 ns |
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use sfc_core::shdlc::{
+    FrameDecoder, MISOFrame, MOSIFrame, START_STOP, SmallFrame, calculate_check_sum, from_shdlc,
+    to_shdlc,
+};
+
+/// The chunk a serial port hands over at a time in the benchmarks of the decoder
+const CHUNK: usize = 20;
+
+/// A response header followed by the data, ready for [to_shdlc]
+fn response(data: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0x00, 0x08, 0x00, data.len() as u8];
+    raw.extend_from_slice(data);
+    raw
+}
+
+fn payloads() -> [(&'static str, Vec<u8>); 3] {
+    [
+        // a measured value
+        ("float", 2.5_f32.to_be_bytes().to_vec()),
+        // a full buffered read of the SFC5xxx with values that need no escaping
+        ("buffered", (0..251).map(|i| 0x20 + (i % 0x50) as u8).collect()),
+        ("escaped", vec![START_STOP; 251]),
+    ]
+}
+
+fn stuffing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("to_shdlc");
+    for (name, data) in payloads() {
+        let raw = response(&data);
+        group.throughput(Throughput::Bytes(raw.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &raw, |b, raw| {
+            b.iter(|| to_shdlc(black_box(raw)).unwrap())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("from_shdlc");
+    for (name, data) in payloads() {
+        let stuffed = to_shdlc(&response(&data)).unwrap();
+        group.throughput(Throughput::Bytes(stuffed.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &stuffed, |b, stuffed| {
+            b.iter(|| from_shdlc(black_box(stuffed)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn checksum(c: &mut Criterion) {
+    let data: Vec<u8> = (0..=254).collect();
+    c.bench_function("calculate_check_sum/255", |b| {
+        b.iter(|| calculate_check_sum(black_box(&data)))
+    });
+}
+
+fn encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mosi_frame");
+    for (name, data) in payloads() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| MOSIFrame::new(0, 0x08, black_box(data)).unwrap())
+        });
+    }
+    group.bench_function("small", |b| {
+        b.iter(|| SmallFrame::sized(0, 0x08, black_box(&[0x01])).unwrap())
+    });
+    group.finish();
+}
+
+fn round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("round_trip");
+    for (name, data) in payloads() {
+        let stuffed = to_shdlc(&response(&data)).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| {
+                let request = MOSIFrame::new(0, 0x08, black_box(data)).unwrap();
+                let response = MISOFrame::from_bytes(black_box(&stuffed)).unwrap();
+                (request.into_raw().len(), response.into_data().len())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn decoder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decoder");
+    for (name, data) in payloads() {
+        let stuffed = to_shdlc(&response(&data)).unwrap();
+        group.throughput(Throughput::Bytes(stuffed.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &stuffed, |b, stuffed| {
+            let mut decoder = FrameDecoder::new();
+            b.iter(|| {
+                for chunk in black_box(stuffed).chunks(CHUNK) {
+                    let (_, complete) = decoder.feed(chunk).unwrap();
+                    if complete {
+                        return decoder.miso_frame().unwrap().data().len();
+                    }
+                }
+                unreachable!("the frame is complete after its last chunk")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, stuffing, checksum, encoding, round_trip, decoder);
+criterion_main!(benches);
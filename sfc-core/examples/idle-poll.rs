@@ -0,0 +1,101 @@
+// polls a device that never answers over a transport that returns from reads at once, like a
+// port opened with a zero timeout, and reports the CPU time the waiting costs under a few spin
+// policies: run with `cargo run --release --example idle-poll`. The CPU time is read from
+// /proc and only reported on Linux.
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use sfc_core::connection::{Connection, SpinPolicy};
+use sfc_core::error::DeviceError;
+use sfc_core::shdlc::MOSIFrame;
+use sfc_core::transport::Transport;
+
+const RUN: Duration = Duration::from_secs(2);
+
+/// A disconnected line, every read returns at once with nothing
+struct Disconnected {
+    reads: u64,
+}
+
+impl Read for Disconnected {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads += 1;
+        Ok(0)
+    }
+}
+
+impl Write for Disconnected {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Disconnected {
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _timeout: Duration) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn clear_input(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// The time this thread ran on a CPU, from the first field of its schedstat
+fn cpu_time() -> Option<Duration> {
+    let schedstat = std::fs::read_to_string("/proc/thread-self/schedstat").ok()?;
+    let nanos = schedstat.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_nanos(nanos))
+}
+
+fn measure(name: &str, policy: SpinPolicy) -> Result<(), DeviceError> {
+    let mut connection = Connection::new(Disconnected { reads: 0 });
+    connection.set_spin_policy(policy);
+    let cpu_before = cpu_time();
+    let start = Instant::now();
+    let mut commands = 0;
+    while start.elapsed() < RUN {
+        let frame = MOSIFrame::new(0, 0x08, &[0x01])?;
+        match connection.transact_once(frame) {
+            Err(DeviceError::Timeout) => commands += 1,
+            other => panic!("a disconnected line answered: {:?}", other.map(|_| ())),
+        }
+    }
+    let elapsed = start.elapsed();
+    let reads = connection.with_transport(|port| port.reads);
+    let cpu = match (cpu_before, cpu_time()) {
+        (Some(before), Some(after)) => {
+            format!("{:.1}% CPU", (after - before).as_secs_f64() / elapsed.as_secs_f64() * 100.0)
+        }
+        _ => "CPU time unavailable".to_string(),
+    };
+    println!("{}: {} timeouts, {} reads, {}", name, commands, reads, cpu);
+    Ok(())
+}
+
+fn main() -> Result<(), DeviceError> {
+    // reading again at once forever, how the receive loop waits without a sleep
+    measure(
+        "never sleeping",
+        SpinPolicy {
+            spins: u32::MAX,
+            sleep: None,
+        },
+    )?;
+    measure(
+        "64 spins, then 1ms sleeps",
+        SpinPolicy {
+            spins: 64,
+            sleep: Some(Duration::from_millis(1)),
+        },
+    )?;
+    measure("default", SpinPolicy::default())?;
+    Ok(())
+}
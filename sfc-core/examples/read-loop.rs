@@ -0,0 +1,116 @@
+// times a tight loop of commands against a transport that answers from memory, what is left
+// is the cost of the connection itself: run with `cargo run --release --example read-loop`.
+// The buffered reads compare copying the response out with reading it where it was received.
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use sfc_core::connection::Connection;
+use sfc_core::error::DeviceError;
+use sfc_core::shdlc::{MOSIFrame, START_STOP, to_shdlc};
+use sfc_core::transport::Transport;
+
+const COMMANDS: u32 = 200_000;
+
+/// Answers every request with the same measured value
+struct Answering {
+    response: Vec<u8>,
+    pending: VecDeque<u8>,
+}
+
+impl Read for Answering {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        let count = buf.len().min(self.pending.len());
+        for (byte, pending) in buf.iter_mut().zip(self.pending.drain(..count)) {
+            *byte = pending;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for Answering {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.last() == Some(&START_STOP) {
+            self.pending.extend(&self.response);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Answering {
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _timeout: Duration) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn clear_input(&mut self) -> Result<(), DeviceError> {
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Times the command, `read` gets the response
+fn time<R>(
+    connection: &mut Connection<Answering>,
+    name: &str,
+    mut read: impl FnMut(&mut Connection<Answering>, MOSIFrame) -> Result<R, DeviceError>,
+) -> Result<(), DeviceError> {
+    let start = Instant::now();
+    for _ in 0..COMMANDS {
+        let frame = MOSIFrame::new(0, 0x08, &[0x01])?;
+        read(connection, frame)?;
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{}: {} commands in {:?}, {:?} per command",
+        name,
+        COMMANDS,
+        elapsed,
+        elapsed / COMMANDS
+    );
+    Ok(())
+}
+
+/// A connection answering every command with the data
+fn answering(data: &[u8]) -> Result<Connection<Answering>, DeviceError> {
+    let mut raw = vec![0x00, 0x08, 0x00, data.len() as u8];
+    raw.extend_from_slice(data);
+    let answering = Answering {
+        response: to_shdlc(&raw)?.to_vec(),
+        pending: VecDeque::new(),
+    };
+    Ok(Connection::new(answering))
+}
+
+fn main() -> Result<(), DeviceError> {
+    let mut connection = answering(&2.5_f32.to_be_bytes())?;
+    time(&mut connection, "measured value", |connection, frame| {
+        let response = connection.transact(frame)?;
+        assert_eq!(response.into_data().len(), 4);
+        Ok(())
+    })?;
+
+    // a full buffer of a buffered read, 12 bytes of header and 60 values
+    let buffered: Vec<u8> = (0..252).map(|i| i as u8).collect();
+    let mut connection = answering(&buffered)?;
+    time(&mut connection, "buffered read, copied", |connection, frame| {
+        let response = connection.transact(frame)?;
+        Ok(response.into_data().iter().map(|&b| b as u32).sum::<u32>())
+    })?;
+    time(&mut connection, "buffered read, borrowed", |connection, frame| {
+        connection.transact_and_read(frame, |response| {
+            Ok(response.data().iter().map(|&b| b as u32).sum::<u32>())
+        })
+    })?;
+    Ok(())
+}
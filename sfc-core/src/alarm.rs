@@ -0,0 +1,439 @@
+//! Alarms on the measured flow, available with `std`. An [Alarm] looks at every [Measurement]
+//! of a stream and becomes active once the flow has been outside its bounds for a while, like
+//! "more than 5% off the setpoint for over 2 seconds":
+//! ```no_run
+//! # fn run<C: sfc_core::flow_controller::FlowController>(device: &mut C) {
+//! use std::time::Duration;
+//! use sfc_core::alarm::{Alarm, AlarmConfig};
+//!
+//! let alarm = Alarm::new(AlarmConfig::deviation(5.0, Duration::from_secs(2)))
+//!     .on_transition(|transition, measurement| {
+//!         eprintln!("{} at {} {}", transition, measurement.value, measurement.unit)
+//!     });
+//! for measurement in device.measurements(Duration::from_millis(100)).with_alarm(alarm) {
+//!     if let Ok(measurement) = measurement {
+//!         println!("{} {:?}", measurement.measurement.value, measurement.alarm.state);
+//!     }
+//! }
+//! # }
+//! ```
+//! The async measurement stream of the SFC6xxx is annotated the same way by mapping its items
+//! through [Alarm::annotate].
+//!
+//! The time a value has been out of bounds is taken from the timestamps of the measurements, so
+//! the evaluation can be tested with made up samples. Once active the alarm clears as soon as the
+//! flow is back within its bounds by the [AlarmConfig::deadband], which keeps a flow sitting on
+//! a bound from toggling it.
+
+use std::fmt::Display;
+use std::time::{Duration, SystemTime};
+
+use crate::error::DeviceError;
+use crate::measurement::Measurement;
+
+/// A limit on the measured flow
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    /// A flow in the unit of the measurement
+    Absolute(f32),
+    /// A distance from the setpoint in percent of it. A measurement without a setpoint is
+    /// never outside of it, and at a zero setpoint any flow is, an absolute bound suits
+    /// processes that close the valve.
+    PercentOfSetpoint(f32),
+}
+
+/// When an [Alarm] becomes active and clears
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AlarmConfig {
+    /// The flow above which the alarm goes off
+    pub high: Option<Bound>,
+    /// The flow below which the alarm goes off
+    pub low: Option<Bound>,
+    /// How long the flow has to stay beyond a bound before the alarm is active, zero activates
+    /// it with the first measurement
+    pub persistence: Duration,
+    /// How far back inside the bound the flow has to come to clear the alarm, in the unit of
+    /// the measurement
+    pub deadband: f32,
+}
+
+impl AlarmConfig {
+    /// An alarm for a flow more than `percent` above or below the setpoint for `persistence`
+    pub fn deviation(percent: f32, persistence: Duration) -> Self {
+        Self {
+            high: Some(Bound::PercentOfSetpoint(percent)),
+            low: Some(Bound::PercentOfSetpoint(percent)),
+            persistence,
+            deadband: 0.0,
+        }
+    }
+}
+
+/// Which bound an alarm is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmKind {
+    High,
+    Low,
+}
+
+impl Display for AlarmKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::High => write!(f, "high"),
+            Self::Low => write!(f, "low"),
+        }
+    }
+}
+
+/// Whether an alarm is going off
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmState {
+    Normal,
+    Active(AlarmKind),
+}
+
+/// A change of the [AlarmState]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmTransition {
+    /// The flow stayed beyond a bound for the persistence time
+    Activated(AlarmKind),
+    /// The flow came back within the bound and the deadband
+    Cleared(AlarmKind),
+}
+
+impl Display for AlarmTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Activated(kind) => write!(f, "{} flow alarm", kind),
+            Self::Cleared(kind) => write!(f, "{} flow alarm cleared", kind),
+        }
+    }
+}
+
+/// What an [Alarm] made of one measurement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlarmStatus {
+    /// The state after the measurement
+    pub state: AlarmState,
+    /// The change the measurement caused, if any
+    pub transition: Option<AlarmTransition>,
+}
+
+/// A [Measurement] with the state of an alarm
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmedMeasurement {
+    pub measurement: Measurement,
+    pub alarm: AlarmStatus,
+}
+
+type Callback = Box<dyn FnMut(AlarmTransition, &Measurement) + Send>;
+
+/// Evaluates an [AlarmConfig] over a stream of measurements, see the [module](self)
+/// documentation
+pub struct Alarm {
+    config: AlarmConfig,
+    state: AlarmState,
+    /// The bound the flow is beyond while the alarm is not active yet, and since when
+    beyond: Option<(AlarmKind, SystemTime)>,
+    callback: Option<Callback>,
+}
+
+impl Alarm {
+    pub fn new(config: AlarmConfig) -> Self {
+        Self {
+            config,
+            state: AlarmState::Normal,
+            beyond: None,
+            callback: None,
+        }
+    }
+
+    /// Calls `callback` with every transition and the measurement that caused it
+    pub fn on_transition(
+        mut self,
+        callback: impl FnMut(AlarmTransition, &Measurement) + Send + 'static,
+    ) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn config(&self) -> &AlarmConfig {
+        &self.config
+    }
+
+    /// The state after the last measurement
+    pub fn state(&self) -> AlarmState {
+        self.state
+    }
+
+    /// Updates the state with the next measurement
+    pub fn evaluate(&mut self, measurement: &Measurement) -> AlarmStatus {
+        let transition = self.transition(measurement);
+        if let (Some(transition), Some(callback)) = (transition, &mut self.callback) {
+            callback(transition, measurement);
+        }
+        AlarmStatus {
+            state: self.state,
+            transition,
+        }
+    }
+
+    /// Evaluates a measurement read from a device, failed reads are passed on and leave the
+    /// state as it is
+    pub fn annotate(
+        &mut self,
+        measurement: Result<Measurement, DeviceError>,
+    ) -> Result<AlarmedMeasurement, DeviceError> {
+        let measurement = measurement?;
+        let alarm = self.evaluate(&measurement);
+        Ok(AlarmedMeasurement { measurement, alarm })
+    }
+
+    fn transition(&mut self, measurement: &Measurement) -> Option<AlarmTransition> {
+        let value = measurement.value;
+        let high = self.limit(self.config.high, AlarmKind::High, measurement.setpoint);
+        let low = self.limit(self.config.low, AlarmKind::Low, measurement.setpoint);
+        let beyond = match (high, low) {
+            (Some(high), _) if value > high => Some(AlarmKind::High),
+            (_, Some(low)) if value < low => Some(AlarmKind::Low),
+            _ => None,
+        };
+
+        if let AlarmState::Active(kind) = self.state {
+            let deadband = self.config.deadband;
+            let back = match kind {
+                AlarmKind::High => high.is_none_or(|high| value <= high - deadband),
+                AlarmKind::Low => low.is_none_or(|low| value >= low + deadband),
+            };
+            if !back {
+                return None;
+            }
+            self.state = AlarmState::Normal;
+            // a flow that swung past the other bound starts its persistence now
+            self.beyond = beyond.map(|beyond| (beyond, measurement.timestamp));
+            return Some(AlarmTransition::Cleared(kind));
+        }
+
+        let Some(kind) = beyond else {
+            self.beyond = None;
+            return None;
+        };
+        let since = match self.beyond {
+            Some((previous, since)) if previous == kind => since,
+            _ => measurement.timestamp,
+        };
+        // a clock that went backwards counts as no time passed
+        let elapsed = measurement
+            .timestamp
+            .duration_since(since)
+            .unwrap_or_default();
+        if elapsed < self.config.persistence {
+            self.beyond = Some((kind, since));
+            return None;
+        }
+        self.state = AlarmState::Active(kind);
+        self.beyond = None;
+        Some(AlarmTransition::Activated(kind))
+    }
+
+    fn limit(&self, bound: Option<Bound>, kind: AlarmKind, setpoint: Option<f32>) -> Option<f32> {
+        match bound? {
+            Bound::Absolute(limit) => Some(limit),
+            Bound::PercentOfSetpoint(percent) => {
+                let setpoint = setpoint?;
+                let distance = setpoint.abs() * percent / 100.0;
+                Some(match kind {
+                    AlarmKind::High => setpoint + distance,
+                    AlarmKind::Low => setpoint - distance,
+                })
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Alarm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Alarm")
+            .field("config", &self.config)
+            .field("state", &self.state)
+            .field("beyond", &self.beyond)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+/// Annotates the measurements of an iterator, returned by
+/// [Measurements::with_alarm](crate::measurement::Measurements::with_alarm)
+#[derive(Debug)]
+pub struct WithAlarm<I> {
+    measurements: I,
+    alarm: Alarm,
+}
+
+impl<I> WithAlarm<I> {
+    pub(crate) fn new(measurements: I, alarm: Alarm) -> Self {
+        Self {
+            measurements,
+            alarm,
+        }
+    }
+
+    pub fn alarm(&self) -> &Alarm {
+        &self.alarm
+    }
+
+    /// Returns the measurements and the alarm
+    pub fn into_parts(self) -> (I, Alarm) {
+        (self.measurements, self.alarm)
+    }
+}
+
+impl<I: Iterator<Item = Result<Measurement, DeviceError>>> Iterator for WithAlarm<I> {
+    type Item = Result<AlarmedMeasurement, DeviceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let measurement = self.measurements.next()?;
+        Some(self.alarm.annotate(measurement))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gasunit::{GasUnit, Prefixes, TimeBases, Units};
+    use crate::measurement::ValueScale;
+    use std::sync::{Arc, Mutex};
+    use std::time::UNIX_EPOCH;
+
+    /// A reading of `value` at a setpoint of 10, `ms` milliseconds into the run
+    fn sample(ms: u64, value: f32) -> Measurement {
+        Measurement {
+            timestamp: UNIX_EPOCH + Duration::from_millis(ms),
+            monotonic: None,
+            value,
+            unit: GasUnit {
+                unit_prefex: Prefixes::Base,
+                medium_unit: Units::StandardLiter,
+                timebase: TimeBases::Minute,
+            },
+            scale: ValueScale::Physical,
+            serial_number: None,
+            address: None,
+            setpoint: Some(10.0),
+            full_scale: None,
+        }
+    }
+
+    /// The transitions caused by values read every 100ms
+    fn run(alarm: &mut Alarm, values: &[f32]) -> Vec<Option<AlarmTransition>> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| alarm.evaluate(&sample(i as u64 * 100, value)).transition)
+            .collect()
+    }
+
+    #[test]
+    fn activates_after_the_persistence() {
+        let mut alarm = Alarm::new(AlarmConfig::deviation(5.0, Duration::from_millis(200)));
+        let transitions = run(&mut alarm, &[10.0, 10.6, 10.6, 10.6, 10.6, 10.2]);
+        assert_eq!(
+            transitions,
+            [
+                None,
+                None,
+                None,
+                Some(AlarmTransition::Activated(AlarmKind::High)),
+                None,
+                Some(AlarmTransition::Cleared(AlarmKind::High)),
+            ]
+        );
+        assert_eq!(alarm.state(), AlarmState::Normal);
+    }
+
+    #[test]
+    fn short_excursions_restart_the_persistence() {
+        let mut alarm = Alarm::new(AlarmConfig::deviation(5.0, Duration::from_millis(200)));
+        let transitions = run(&mut alarm, &[9.0, 9.0, 10.0, 9.0, 9.0, 11.0, 11.0, 9.0]);
+        assert!(transitions.iter().all(Option::is_none));
+        assert_eq!(alarm.state(), AlarmState::Normal);
+    }
+
+    #[test]
+    fn deadband_keeps_the_alarm_active() {
+        let config = AlarmConfig {
+            low: Some(Bound::Absolute(5.0)),
+            deadband: 0.5,
+            ..AlarmConfig::default()
+        };
+        let mut alarm = Alarm::new(config);
+        let transitions = run(&mut alarm, &[4.0, 5.2, 4.9, 5.4, 5.5]);
+        assert_eq!(
+            transitions[0],
+            Some(AlarmTransition::Activated(AlarmKind::Low))
+        );
+        assert_eq!(transitions[1..4], [None, None, None]);
+        assert_eq!(
+            transitions[4],
+            Some(AlarmTransition::Cleared(AlarmKind::Low))
+        );
+    }
+
+    #[test]
+    fn swinging_to_the_other_bound() {
+        let mut alarm = Alarm::new(AlarmConfig::deviation(5.0, Duration::from_millis(100)));
+        let transitions = run(&mut alarm, &[11.0, 11.0, 9.0, 9.0]);
+        assert_eq!(
+            transitions,
+            [
+                None,
+                Some(AlarmTransition::Activated(AlarmKind::High)),
+                Some(AlarmTransition::Cleared(AlarmKind::High)),
+                Some(AlarmTransition::Activated(AlarmKind::Low)),
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_bounds_need_a_setpoint() {
+        let mut alarm = Alarm::new(AlarmConfig::deviation(5.0, Duration::ZERO));
+        let unknown = Measurement {
+            setpoint: None,
+            ..sample(0, 50.0)
+        };
+        assert_eq!(alarm.evaluate(&unknown).state, AlarmState::Normal);
+    }
+
+    #[test]
+    fn callback_and_failed_reads() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&seen);
+        let alarm = Alarm::new(AlarmConfig::deviation(5.0, Duration::ZERO)).on_transition(
+            move |transition, measurement| {
+                record.lock().unwrap().push((transition, measurement.value))
+            },
+        );
+        let reads = vec![
+            Ok(sample(0, 12.0)),
+            Err(DeviceError::Timeout),
+            Ok(sample(200, 10.0)),
+        ];
+        let annotated: Vec<_> = WithAlarm::new(reads.into_iter(), alarm).collect();
+        assert_eq!(
+            annotated[0].as_ref().unwrap().alarm.state,
+            AlarmState::Active(AlarmKind::High)
+        );
+        assert!(matches!(annotated[1], Err(DeviceError::Timeout)));
+        assert_eq!(
+            annotated[2].as_ref().unwrap().alarm.state,
+            AlarmState::Normal
+        );
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (AlarmTransition::Activated(AlarmKind::High), 12.0),
+                (AlarmTransition::Cleared(AlarmKind::High), 10.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,737 @@
+//! The request/response cycle of the [connection](crate::connection) module for async streams,
+//! available with the `async` feature. An [AsyncConnection] talks over an [AsyncTransport] and
+//! measures its timeouts with a [Delay], see [async_transport](crate::async_transport) for the
+//! adapters to tokio, futures-io and embedded-io-async streams. No runtime is needed otherwise.
+//!
+//! Frames are decoded, responses checked and failed commands retried by the same code as on a
+//! blocking [Connection](crate::connection::Connection), so both behave the same way.
+
+use core::future::{Future, poll_fn};
+use core::pin::{Pin, pin};
+use core::task::Poll;
+use core::time::Duration;
+
+use crate::async_transport::{AsyncTransport, Delay};
+use crate::error::DeviceError;
+use crate::exchange::{
+    PROBE_ATTEMPTS, PROBE_COMMAND, Receiver, Request, Settings, expired, is_framing_error,
+};
+#[cfg(feature = "log")]
+use crate::exchange::{Hex, Named};
+
+use crate::shdlc::{CommandKind, MISOFrame, MOSIFrame, START_STOP};
+use crate::stats::CommStats;
+
+pub use crate::exchange::{
+    DEFAULT_INTER_BYTE_TIMEOUT, DEFAULT_RESPONSE_TIMEOUT, RetryConfig, Timeouts, ValidationLevel,
+};
+
+/// An async stream to a device together with the timeouts used while waiting for a response.
+/// The timeouts, the pairing of requests and responses and retrying work like on a blocking
+/// [Connection](crate::connection::Connection), every wait is a future of the [Delay] raced
+/// against the stream.
+///
+/// There is no generic way to discard the input an async stream has buffered. In strict mode
+/// (see [AsyncConnection::set_clear_stale_input]) the bytes that are ready to be read when a
+/// request is sent are read and dropped instead.
+///
+/// # Cancellation
+/// The future of a command can be dropped at any point, for example when another branch of a
+/// `select!` wins, as long as the [AsyncTransport] loses no bytes when a read is dropped. Every
+/// byte received goes through the decoder kept by the connection, not a buffer of the future.
+/// The next command first waits for the response of the dropped one, for at most the response
+/// timeout, and drops it once its address and command number match. That way a late response
+/// is never taken for the next one. Whether the device executed the dropped command is not
+/// known.
+#[derive(Debug)]
+pub struct AsyncConnection<T: AsyncTransport, D: Delay> {
+    port: T,
+    delay: D,
+    receiver: Receiver,
+    settings: Settings,
+    stats: CommStats,
+    /// The command whose future was dropped before its response was read
+    in_flight: Option<InFlight>,
+}
+
+/// A command that was sent, or was being sent, and has not been answered yet
+#[derive(Clone, Copy, Debug)]
+struct InFlight {
+    address: u8,
+    command: u8,
+    kind: CommandKind,
+    /// The whole request was written
+    sent: bool,
+}
+
+impl<T: AsyncTransport, D: Delay> AsyncConnection<T, D> {
+    /// Wraps a stream using the default response and inter-byte timeouts, which are measured
+    /// with the delay
+    pub fn new(port: T, delay: D) -> Self {
+        Self {
+            port,
+            delay,
+            receiver: Receiver::new(),
+            settings: Settings::default(),
+            stats: CommStats::default(),
+            in_flight: None,
+        }
+    }
+
+    /// Returns how long the device has to start answering a query, see
+    /// [AsyncConnection::timeouts] for the other kinds of command
+    pub fn response_timeout(&self) -> Duration {
+        self.settings.timeouts.query
+    }
+
+    /// Sets how long the device has to start answering a request, the same for every kind of
+    /// command
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.settings.timeouts = Timeouts::uniform(timeout);
+    }
+
+    /// Returns how long the device has to start answering each kind of command
+    pub fn timeouts(&self) -> Timeouts {
+        self.settings.timeouts
+    }
+
+    /// Sets how long the device has to start answering each kind of command
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.settings.timeouts = timeouts;
+    }
+
+    /// Returns the time allowed between two bytes of a response
+    pub fn inter_byte_timeout(&self) -> Duration {
+        self.settings.inter_byte_timeout
+    }
+
+    /// Sets the time allowed between two bytes of a response
+    pub fn set_inter_byte_timeout(&mut self, timeout: Duration) {
+        self.settings.inter_byte_timeout = timeout;
+    }
+
+    /// Returns true if pending input is discarded before every request
+    pub fn clear_stale_input(&self) -> bool {
+        self.settings.clear_stale_input
+    }
+
+    /// Sets whether input that is pending when a request is sent is discarded first. On by
+    /// default. Turned off, bytes read past the end of a response are kept for the next request.
+    pub fn set_clear_stale_input(&mut self, clear: bool) {
+        self.settings.clear_stale_input = clear;
+    }
+
+    /// Returns the retry configuration, [None] if commands are never retried
+    pub fn retry(&self) -> Option<RetryConfig> {
+        self.settings.retry
+    }
+
+    /// Sets how commands sent with [AsyncConnection::transact] are retried. [None], the
+    /// default, turns retrying off.
+    pub fn set_retry(&mut self, retry: Option<RetryConfig>) {
+        self.settings.retry = retry;
+    }
+
+    /// Returns how closely responses are checked
+    pub fn validation_level(&self) -> ValidationLevel {
+        self.settings.validation
+    }
+
+    /// Sets which checks of a response fail the command, see [ValidationLevel].
+    /// [ValidationLevel::Standard] by default.
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.settings.validation = level;
+    }
+
+    /// Returns the counters of the commands sent since the connection was created or the
+    /// counters were reset. Round trip times need a [Delay] with a clock.
+    pub fn stats(&self) -> &CommStats {
+        &self.stats
+    }
+
+    /// Sets the counters of [AsyncConnection::stats] back to zero
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    /// Returns the delay the timeouts are measured with
+    pub fn delay(&self) -> &D {
+        &self.delay
+    }
+
+    /// Returns the underlying stream. Reading from it directly can take bytes that belong to a
+    /// response.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.port
+    }
+
+    /// Swaps in a new stream and returns the old one, keeping the settings. Bytes kept from the
+    /// old stream are dropped, so is the response to a cancelled command.
+    pub fn reconnect(&mut self, port: T) -> T {
+        self.receiver.clear();
+        self.in_flight = None;
+        core::mem::replace(&mut self.port, port)
+    }
+
+    /// Returns the underlying stream
+    pub fn into_inner(self) -> T {
+        self.port
+    }
+
+    /// Sends the frame to the device and waits for its response, retrying according to the
+    /// [RetryConfig]. A response with an error state is returned as [DeviceError::StateResponse].
+    ///
+    /// Dropping the future before it completes leaves the connection usable, see
+    /// [cancellation](AsyncConnection#cancellation). The command may have been executed any
+    /// number of times up to the attempts of the [RetryConfig]. A frame marked
+    /// [NonIdempotent](crate::shdlc::Idempotency::NonIdempotent) is only retried with
+    /// [RetryConfig::retry_non_idempotent].
+    pub async fn transact<const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+    ) -> Result<MISOFrame, DeviceError> {
+        let retry = self.settings.retry;
+        self.run(frame, retry).await
+    }
+
+    /// Sends the frame to the device and waits for its response without ever retrying. Dropping
+    /// the future leaves the connection usable, the command was executed once or not at all.
+    pub async fn transact_once<const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+    ) -> Result<MISOFrame, DeviceError> {
+        self.run(frame, None).await
+    }
+
+    /// Gets the connection back in step with the device at the address after the stream got
+    /// garbled, like [Connection::resync](crate::connection::Connection::resync). Dropping the
+    /// future is safe, the next command or resync starts over.
+    pub async fn resync(&mut self, address: u8) -> Result<(), DeviceError> {
+        #[cfg(feature = "log")]
+        log::warn!("resynchronizing with address {}", address);
+        #[cfg(feature = "tracing")]
+        tracing::warn!(address, "resynchronizing");
+
+        self.receiver.clear();
+        self.in_flight = None;
+        self.discard_ready_input().await?;
+        // a lone delimiter is a legal no-op that ends any frame the device is in the middle of
+        self.send(&[START_STOP]).await?;
+        self.drain().await?;
+
+        let probe = MOSIFrame::new(address, PROBE_COMMAND, &[])?.into_raw();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let request = (address, PROBE_COMMAND, CommandKind::Query, None);
+            match self.exchange(request, &probe).await {
+                Ok(_) | Err(DeviceError::StateResponse(_)) => return Ok(()),
+                Err(e) if attempts >= PROBE_ATTEMPTS => return Err(e),
+                Err(_) => self.receiver.clear(),
+            }
+        }
+    }
+
+    async fn run<const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+        retry: Option<RetryConfig>,
+    ) -> Result<MISOFrame, DeviceError> {
+        let address = frame.get_address();
+        let command = frame.get_command_number();
+        let kind = frame.kind();
+        let name = frame.name();
+        let retry = retry.filter(|retry| retry.retries(frame.idempotency()));
+        let raw = frame.into_raw();
+
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        let start = self.delay.now();
+        #[cfg(feature = "log")]
+        log::debug!("command {} to address {} started", Named(command, name), address);
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "shdlc_command",
+            command,
+            name,
+            address,
+            duration_us = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+
+        let request = (address, command, kind, name);
+        let exchange = async {
+            match retry {
+                Some(retry) => self.exchange_with_retry(request, &raw, retry).await,
+                None => self.exchange(request, &raw).await,
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let exchange = tracing::Instrument::instrument(exchange, span.clone());
+        let result = exchange.await;
+        self.stats.record_command(address, &result);
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        let elapsed = start.zip(self.delay.now()).map(|(start, now)| now.saturating_sub(start));
+
+        #[cfg(feature = "log")]
+        match &result {
+            Ok(_) => log::debug!(
+                "command {} to address {} finished{}",
+                Named(command, name),
+                address,
+                Took(elapsed, "in")
+            ),
+            Err(e) => log::debug!(
+                "command {} to address {} failed{}: {}",
+                Named(command, name),
+                address,
+                Took(elapsed, "after"),
+                e
+            ),
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            if let Some(elapsed) = elapsed {
+                span.record("duration_us", elapsed.as_micros() as u64);
+            }
+            span.record("outcome", crate::exchange::outcome(&result));
+            if let Err(e) = &result {
+                span.in_scope(|| tracing::warn!(error = %e, "command failed"));
+            }
+        }
+
+        result
+    }
+
+    async fn exchange_with_retry(
+        &mut self,
+        request: Request,
+        raw: &[u8],
+        retry: RetryConfig,
+    ) -> Result<MISOFrame, DeviceError> {
+        let (address, ..) = request;
+        let mut attempts = 0;
+        let mut framing_errors = 0;
+        loop {
+            attempts += 1;
+            match self.exchange(request, raw).await {
+                Err(e) if retry.should_retry(&e) => {
+                    if attempts >= retry.max_attempts {
+                        return Err(exhausted(attempts, e));
+                    }
+                    framing_errors = if is_framing_error(&e) { framing_errors + 1 } else { 0 };
+                    #[cfg(feature = "log")]
+                    log::warn!(
+                        "retrying command {:#04x} to address {} after attempt {}: {}",
+                        request.1,
+                        address,
+                        attempts,
+                        e
+                    );
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempt = attempts, error = %e, "retrying command");
+                    self.stats.record_retry(address);
+                    self.delay.delay(retry.delay).await;
+                    if retry.resync_after.is_some_and(|after| framing_errors >= after) {
+                        framing_errors = 0;
+                        // the retry tells whether it helped, its own error adds nothing
+                        let _ = self.resync(address).await;
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn exchange(&mut self, request: Request, raw: &[u8]) -> Result<MISOFrame, DeviceError> {
+        let (address, command, kind, _) = request;
+        // only cleared once finished, dropping the recovery too leaves it for the next command
+        if let Some(cancelled) = self.in_flight {
+            self.recover(cancelled).await?;
+            self.in_flight = None;
+        }
+
+        self.in_flight = Some(InFlight { address, command, kind, sent: false });
+        let result = self.request(request, raw).await;
+        self.in_flight = None;
+        result
+    }
+
+    /// Sends the request and reads its response, if the future is dropped `in_flight` tells the
+    /// next command what was left unanswered
+    async fn request(&mut self, request: Request, raw: &[u8]) -> Result<MISOFrame, DeviceError> {
+        let (address, command, kind, _) = request;
+        if self.settings.clear_stale_input {
+            self.receiver.clear();
+            self.discard_ready_input().await?;
+        } else {
+            self.receiver.drop_partial_frame();
+        }
+        self.send(raw).await?;
+        self.in_flight = Some(InFlight { address, command, kind, sent: true });
+        let sent = self.delay.now();
+
+        let Self { port, delay, receiver, settings, stats, .. } = self;
+        // runs from the end of the request for as long as no frame has started
+        let mut response = pin!(delay.delay(settings.timeouts.for_kind(kind)));
+        let result = async {
+            loop {
+                receive_frame(port, delay, receiver, settings, response.as_mut()).await?;
+                if let Some(frame) = settings.accept(receiver.response()?, address, command)? {
+                    return Ok(MISOFrame::from(frame));
+                }
+            }
+        }
+        .await;
+        let round_trip = sent.zip(delay.now()).map(|(sent, now)| now.saturating_sub(sent));
+        stats.record_attempt(address, &result, round_trip);
+        result
+    }
+
+    /// Reads the response to a command whose future was dropped and drops it, so it isn't taken
+    /// for the response of the next command. A partly received response is finished from the
+    /// bytes the decoder already has. Gives up after its response timeout, or once the stream
+    /// turns out garbled.
+    async fn recover(&mut self, cancelled: InFlight) -> Result<(), DeviceError> {
+        #[cfg(feature = "log")]
+        log::debug!(
+            "waiting for the response to cancelled command {:#04x} to address {}",
+            cancelled.command,
+            cancelled.address
+        );
+        if !cancelled.sent {
+            // ends a request that was cut off while it was written
+            self.send(&[START_STOP]).await?;
+        }
+
+        let Self { port, delay, receiver, settings, .. } = self;
+        let mut response = pin!(delay.delay(settings.timeouts.for_kind(cancelled.kind)));
+        loop {
+            match receive_frame(port, delay, receiver, settings, response.as_mut()).await {
+                Ok(()) => {
+                    let answered = receiver.response().is_ok_and(|frame| {
+                        frame.get_address() == cancelled.address
+                            && frame.get_command_number() == cancelled.command
+                    });
+                    if answered {
+                        return Ok(());
+                    }
+                }
+                Err(e) if matches!(e, DeviceError::Timeout) || is_framing_error(&e) => {
+                    receiver.drop_partial_frame();
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes the whole request
+    async fn send(&mut self, raw: &[u8]) -> Result<(), DeviceError> {
+        #[cfg(feature = "log")]
+        log::trace!("sent {}", Hex(raw));
+        let write = async {
+            self.port.write_all(raw).await?;
+            self.port.flush().await
+        };
+        // a stream that stops taking bytes would otherwise stall the command forever
+        match within(write, self.delay.delay(self.settings.timeouts.query)).await {
+            Some(written) => written,
+            None => Err(stalled()),
+        }
+    }
+
+    /// Reads and drops the bytes that can be read without waiting
+    async fn discard_ready_input(&mut self) -> Result<(), DeviceError> {
+        let mut buf = [0_u8; 64];
+        // the read is polled once before the timer, which is always ready
+        while let Some(read) = within(self.port.read(&mut buf), core::future::ready(())).await {
+            if read? == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and discards bytes until the line stays quiet for the inter-byte timeout, for at
+    /// most the response timeout of a query
+    async fn drain(&mut self) -> Result<(), DeviceError> {
+        let Self { port, delay, settings, .. } = self;
+        let quiet = async {
+            let mut buf = [0_u8; 64];
+            loop {
+                let quiet = delay.delay(settings.inter_byte_timeout);
+                let Some(read) = within(port.read(&mut buf), quiet).await else {
+                    break;
+                };
+                if read? == 0 {
+                    break;
+                }
+            }
+            Ok(())
+        };
+        within(quiet, delay.delay(settings.timeouts.query)).await.unwrap_or(Ok(()))
+    }
+}
+
+/// Reads the bytes of one frame, from start to end delimiter, into the receiver. The response
+/// timer is the one started when the request was sent, it only applies until a frame starts.
+async fn receive_frame<T: AsyncTransport, D: Delay>(
+    port: &mut T,
+    delay: &D,
+    receiver: &mut Receiver,
+    settings: &Settings,
+    mut response: Pin<&mut impl Future<Output = ()>>,
+) -> Result<(), DeviceError> {
+    loop {
+        if receiver.next_frame()? {
+            return Ok(());
+        }
+
+        // noise outside of a frame doesn't count as the response starting
+        let in_frame = receiver.in_frame();
+        let read = receiver.fill_async(port);
+        let read = if in_frame {
+            within(read, delay.delay(settings.inter_byte_timeout)).await
+        } else {
+            within(read, response.as_mut()).await
+        };
+        match read {
+            None => return Err(expired(in_frame)),
+            Some(Ok(0)) => return Err(closed(in_frame)),
+            Some(result) => {
+                result?;
+            }
+        }
+    }
+}
+
+/// How long a command took for a log record, nothing when the [Delay] has no clock
+#[cfg(feature = "log")]
+struct Took(Option<Duration>, &'static str);
+
+#[cfg(feature = "log")]
+impl core::fmt::Display for Took {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(elapsed) => write!(f, " {} {:?}", self.1, elapsed),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The error once every attempt of a command failed
+#[cfg(feature = "std")]
+fn exhausted(attempts: u32, last: DeviceError) -> DeviceError {
+    DeviceError::RetriesExhausted(attempts, Box::new(last))
+}
+
+/// Without std there is no box to keep the last error in, it is returned as it is
+#[cfg(not(feature = "std"))]
+fn exhausted(_attempts: u32, last: DeviceError) -> DeviceError {
+    last
+}
+
+/// The error for a stream that stopped taking bytes
+#[cfg(feature = "std")]
+fn stalled() -> DeviceError {
+    std::io::Error::new(std::io::ErrorKind::WriteZero, "the transport stopped taking bytes").into()
+}
+
+/// Without std there is no io::Error to tell a stalled stream apart, the device just never
+/// answered
+#[cfg(not(feature = "std"))]
+fn stalled() -> DeviceError {
+    DeviceError::Timeout
+}
+
+/// The error for a stream that ended while waiting for a response
+#[cfg(feature = "std")]
+fn closed(_in_frame: bool) -> DeviceError {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "the transport was closed").into()
+}
+
+/// Without std a closed stream looks like a device that stopped sending
+#[cfg(not(feature = "std"))]
+fn closed(in_frame: bool) -> DeviceError {
+    expired(in_frame)
+}
+
+/// Runs the future until it completes or the timer runs out first, which returns [None]. The
+/// future is always polled before the timer.
+async fn within<F: Future>(future: F, timer: impl Future<Output = ()>) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let mut timer = pin!(timer);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        timer.as_mut().poll(cx).map(|_| None)
+    })
+    .await
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use crate::async_transport::{FromTokio, TokioDelay};
+    use crate::error::StateResponseError;
+    use crate::shdlc::to_shdlc;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+
+    type Duplex = AsyncConnection<FromTokio<DuplexStream>, TokioDelay>;
+
+    fn response(command: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x00, command, 0x00, data.len() as u8];
+        frame.extend_from_slice(data);
+        to_shdlc(&frame).unwrap().to_vec()
+    }
+
+    fn request() -> MOSIFrame {
+        MOSIFrame::new(0, 0x91, &[]).unwrap()
+    }
+
+    /// Answers every request that arrives on the other end with the next of the responses
+    fn device(responses: Vec<Vec<u8>>) -> Duplex {
+        let (port, mut device) = duplex(64);
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 64];
+            let mut frame = Vec::new();
+            let mut responses = responses.into_iter();
+            while let Ok(read) = device.read(&mut buf).await {
+                if read == 0 {
+                    break;
+                }
+                frame.extend_from_slice(&buf[..read]);
+                if frame.len() > 1 && frame.last() == Some(&START_STOP) {
+                    frame.clear();
+                    match responses.next() {
+                        Some(response) => device.write_all(&response).await.unwrap(),
+                        None => break,
+                    }
+                }
+            }
+            // keep the stream open so a missing response is a timeout and not a closed stream
+            std::future::pending::<()>().await;
+        });
+        let mut connection = AsyncConnection::new(FromTokio(port), TokioDelay);
+        connection.set_response_timeout(Duration::from_millis(50));
+        connection
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let mut connection = device(vec![response(0x91, &[0x00, 0x01, 0xC2, 0x00])]);
+        let frame = connection.transact(request()).await.unwrap();
+        assert_eq!(frame.into_data().as_slice(), &[0x00, 0x01, 0xC2, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn no_response_is_a_timeout() {
+        let mut connection = device(vec![]);
+        let start = Instant::now();
+        assert!(matches!(connection.transact(request()).await, Err(DeviceError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn stalled_response_is_an_incomplete_frame() {
+        let mut half = response(0x91, &[0, 0, 0, 0]);
+        half.truncate(4);
+        let mut connection = device(vec![half]);
+        assert!(matches!(connection.transact(request()).await, Err(DeviceError::IncompleteFrame)));
+    }
+
+    #[tokio::test]
+    async fn error_state_is_returned() {
+        let mut connection = device(vec![to_shdlc(&[0x00, 0x91, 0x04, 0x00]).unwrap().to_vec()]);
+        assert!(matches!(
+            connection.transact(request()).await,
+            Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+        ));
+    }
+
+    #[tokio::test]
+    async fn retries_corrupted_responses() {
+        let mut corrupted = response(0x91, &[1, 2, 3, 4]);
+        corrupted[5] ^= 0x01;
+        let mut connection = device(vec![corrupted, response(0x91, &[1, 2, 3, 4])]);
+        connection.set_retry(Some(RetryConfig {
+            delay: Duration::from_millis(1),
+            ..Default::default()
+        }));
+        let frame = connection.transact(request()).await.unwrap();
+        assert_eq!(frame.into_data().as_slice(), &[1, 2, 3, 4]);
+        let stats = connection.stats();
+        assert_eq!((stats.commands, stats.retries, stats.checksum_errors), (1, 1, 1));
+        assert!(stats.round_trip.is_some());
+    }
+
+    #[tokio::test]
+    async fn stale_input_is_discarded() {
+        let (port, mut device) = duplex(64);
+        let mut connection = AsyncConnection::new(FromTokio(port), TokioDelay);
+        connection.set_response_timeout(Duration::from_millis(20));
+        // a late answer that is already waiting when the request is sent
+        device.write_all(&response(0x91, &[9, 9, 9, 9])).await.unwrap();
+        assert!(matches!(connection.transact(request()).await, Err(DeviceError::Timeout)));
+    }
+
+    /// Answers the first request with half a response and the rest of it later, by which time
+    /// the command was given up on
+    #[tokio::test]
+    async fn dropped_command_leaves_no_half_response() {
+        let (port, mut device) = duplex(64);
+        let mut connection = AsyncConnection::new(FromTokio(port), TokioDelay);
+        connection.set_response_timeout(Duration::from_millis(100));
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 64];
+            let stale = response(0x91, &[9, 9, 9, 9]);
+            assert!(device.read(&mut buf).await.unwrap() > 0);
+            device.write_all(&stale[..5]).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            device.write_all(&stale[5..]).await.unwrap();
+            assert!(device.read(&mut buf).await.unwrap() > 0);
+            device.write_all(&response(0x91, &[1, 2, 3, 4])).await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let dropped =
+            tokio::time::timeout(Duration::from_millis(10), connection.transact(request())).await;
+        assert!(dropped.is_err());
+        let frame = connection.transact(request()).await.unwrap();
+        assert_eq!(frame.into_data().as_slice(), &[1, 2, 3, 4]);
+    }
+
+    /// A response to the same command that only arrives once the next one was sent would pass
+    /// the echo check of the next one
+    #[tokio::test]
+    async fn late_response_to_a_dropped_command_is_not_taken() {
+        let (port, mut device) = duplex(64);
+        let mut connection = AsyncConnection::new(FromTokio(port), TokioDelay);
+        connection.set_response_timeout(Duration::from_millis(100));
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 64];
+            assert!(device.read(&mut buf).await.unwrap() > 0);
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            device.write_all(&response(0x91, &[9, 9, 9, 9])).await.unwrap();
+            assert!(device.read(&mut buf).await.unwrap() > 0);
+            device.write_all(&response(0x91, &[1, 2, 3, 4])).await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let dropped =
+            tokio::time::timeout(Duration::from_millis(10), connection.transact(request())).await;
+        assert!(dropped.is_err());
+        let frame = connection.transact(request()).await.unwrap();
+        assert_eq!(frame.into_data().as_slice(), &[1, 2, 3, 4]);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn durations_need_a_clock() {
+        assert_eq!(Took(None, "in").to_string(), "");
+        assert_eq!(Took(Some(Duration::from_millis(2)), "after").to_string(), " after 2ms");
+    }
+}
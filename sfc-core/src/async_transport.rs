@@ -0,0 +1,323 @@
+//! What an [AsyncConnection](crate::async_connection::AsyncConnection) needs from the platform,
+//! available with the `async` feature. [AsyncTransport] is the byte stream to the device and
+//! [Delay] the timer behind the timeouts, so the connection runs on any executor: tokio,
+//! async-std, smol or Embassy on a microcontroller.
+//!
+//! The stream traits of the common async ecosystems are adapted by wrapping a stream:
+//! - `FromTokio` for tokio's `AsyncRead` and `AsyncWrite` (requires `tokio`), with
+//!   `TokioDelay` as the timer
+//! - `FromFutures` for the `AsyncRead` and `AsyncWrite` of futures-io, used by async-std and
+//!   smol (requires `futures-io`)
+//! - `FromEmbeddedIo` for the `Read` and `Write` of embedded-io-async, used by Embassy (requires
+//!   `embedded-io-async`)
+//!
+//! A timer is a few lines on any executor, for example on Embassy (the whole program is in
+//! `examples/embassy-stm32` of the repository, `examples/web-serial` has one on the `setTimeout`
+//! of a browser):
+//! ```ignore
+//! struct EmbassyDelay;
+//!
+//! impl Delay for EmbassyDelay {
+//!     fn delay(&self, duration: Duration) -> impl Future<Output = ()> {
+//!         embassy_time::Timer::after_micros(duration.as_micros() as u64)
+//!     }
+//! }
+//! ```
+
+use core::future::Future;
+use core::time::Duration;
+
+use crate::error::DeviceError;
+
+/// A byte stream to a device that is read and written without blocking
+pub trait AsyncTransport {
+    /// Reads at least one byte into the buffer and returns how many were read, 0 once the
+    /// stream has ended. Dropping the future before it completes must not lose bytes.
+    fn read(&mut self, buf: &mut [u8]) -> impl Future<Output = Result<usize, DeviceError>>;
+
+    /// Writes every byte of the buffer
+    fn write_all(&mut self, buf: &[u8]) -> impl Future<Output = Result<(), DeviceError>>;
+
+    /// Waits until the written bytes have left the stream
+    fn flush(&mut self) -> impl Future<Output = Result<(), DeviceError>>;
+}
+
+/// The timer an async connection measures its timeouts and retry delays with. It is the only
+/// clock the connection reads, so it runs where `std::time::Instant` panics, like in a browser.
+pub trait Delay {
+    /// Returns a future that completes once the duration has passed
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()>;
+
+    /// The time passed since a fixed point of the timer. The `log` and `tracing` records report
+    /// how long a command took with it. The default is a timer without a clock, which leaves
+    /// the durations out.
+    fn now(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// An [AsyncTransport] on a tokio stream, like a `tokio_serial::SerialStream`
+#[cfg(feature = "tokio")]
+#[derive(Debug, Default)]
+pub struct FromTokio<T>(pub T);
+
+#[cfg(feature = "tokio")]
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin> AsyncTransport for FromTokio<T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        use tokio::io::AsyncReadExt;
+        loop {
+            match self.0.read(buf).await {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), DeviceError> {
+        use tokio::io::AsyncWriteExt;
+        Ok(self.0.write_all(buf).await?)
+    }
+
+    async fn flush(&mut self) -> Result<(), DeviceError> {
+        use tokio::io::AsyncWriteExt;
+        Ok(self.0.flush().await?)
+    }
+}
+
+/// A [Delay] on [tokio::time::sleep]. It needs a runtime with the time driver enabled.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioDelay;
+
+#[cfg(feature = "tokio")]
+impl Delay for TokioDelay {
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()> {
+        tokio::time::sleep(duration)
+    }
+
+    fn now(&self) -> Option<Duration> {
+        static START: std::sync::OnceLock<tokio::time::Instant> = std::sync::OnceLock::new();
+        Some(START.get_or_init(tokio::time::Instant::now).elapsed())
+    }
+}
+
+/// An [AsyncTransport] on a futures-io stream, like the ones of async-std and smol
+#[cfg(feature = "futures-io")]
+#[derive(Debug, Default)]
+pub struct FromFutures<T>(pub T);
+
+#[cfg(feature = "futures-io")]
+impl<T: futures_io::AsyncRead + futures_io::AsyncWrite + Unpin> AsyncTransport for FromFutures<T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        let mut stream = std::pin::Pin::new(&mut self.0);
+        loop {
+            match std::future::poll_fn(|cx| stream.as_mut().poll_read(cx, buf)).await {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    async fn write_all(&mut self, mut buf: &[u8]) -> Result<(), DeviceError> {
+        let mut stream = std::pin::Pin::new(&mut self.0);
+        while !buf.is_empty() {
+            match std::future::poll_fn(|cx| stream.as_mut().poll_write(cx, buf)).await {
+                Ok(0) => Err(std::io::Error::from(std::io::ErrorKind::WriteZero))?,
+                Ok(written) => buf = buf.get(written..).unwrap_or_default(),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => Err(e)?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), DeviceError> {
+        let mut stream = std::pin::Pin::new(&mut self.0);
+        Ok(std::future::poll_fn(|cx| stream.as_mut().poll_flush(cx)).await?)
+    }
+}
+
+/// An [AsyncTransport] on an embedded-io-async stream, like an Embassy UART
+#[cfg(feature = "embedded-io-async")]
+#[derive(Debug, Default)]
+pub struct FromEmbeddedIo<T>(pub T);
+
+#[cfg(feature = "embedded-io-async")]
+impl<T: embedded_io_async::Read + embedded_io_async::Write> AsyncTransport for FromEmbeddedIo<T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        self.0.read(buf).await.map_err(embedded_error)
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), DeviceError> {
+        self.0.write_all(buf).await.map_err(embedded_error)
+    }
+
+    async fn flush(&mut self) -> Result<(), DeviceError> {
+        self.0.flush().await.map_err(embedded_error)
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+fn embedded_error<E: embedded_io_async::Error>(error: E) -> DeviceError {
+    DeviceError::EmbeddedIoError(error.kind())
+}
+
+#[cfg(all(test, feature = "tokio", feature = "futures-io", feature = "embedded-io-async"))]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Answers every frame written to it with the next response
+    #[derive(Default)]
+    struct Scripted {
+        responses: VecDeque<Vec<u8>>,
+        pending: VecDeque<u8>,
+        written: Vec<u8>,
+    }
+
+    impl Scripted {
+        fn new(responses: &[&[u8]]) -> Self {
+            Self {
+                responses: responses.iter().map(|r| r.to_vec()).collect(),
+                ..Default::default()
+            }
+        }
+
+        fn receive(&mut self, buf: &[u8]) {
+            self.written.extend_from_slice(buf);
+            if self.written.len() > 1 && self.written.last() == Some(&0x7E) {
+                self.written.clear();
+                self.pending.extend(self.responses.pop_front().unwrap_or_default());
+            }
+        }
+
+        /// Pending until a response is there, the tests wake up through their timer
+        fn take(&mut self, buf: &mut [u8]) -> Option<usize> {
+            if self.pending.is_empty() {
+                return None;
+            }
+            let count = buf.len().min(self.pending.len());
+            for (slot, byte) in buf.iter_mut().zip(self.pending.drain(..count)) {
+                *slot = byte;
+            }
+            Some(count)
+        }
+    }
+
+    impl futures_io::AsyncRead for Scripted {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            match self.get_mut().take(buf) {
+                Some(read) => Poll::Ready(Ok(read)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    impl futures_io::AsyncWrite for Scripted {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            // one byte at a time so partial writes are covered
+            self.get_mut().receive(&buf[..1]);
+            Poll::Ready(Ok(1))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[derive(Debug)]
+    struct Broken;
+
+    impl embedded_io_async::Error for Broken {
+        fn kind(&self) -> embedded_io_async::ErrorKind {
+            embedded_io_async::ErrorKind::BrokenPipe
+        }
+    }
+
+    impl embedded_io_async::ErrorType for Scripted {
+        type Error = Broken;
+    }
+
+    impl embedded_io_async::Read for Scripted {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Broken> {
+            match self.take(buf) {
+                Some(read) => Ok(read),
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    impl embedded_io_async::Write for Scripted {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Broken> {
+            if self.responses.is_empty() && self.pending.is_empty() {
+                return Err(Broken);
+            }
+            self.receive(buf);
+            Ok(buf.len())
+        }
+    }
+
+    const BAUDRATE: &[u8] = &[0x7E, 0x00, 0x91, 0x00, 0x04, 0x00, 0x01, 0xC2, 0x00, 0xA7, 0x7E];
+
+    fn request() -> crate::shdlc::MOSIFrame {
+        crate::shdlc::MOSIFrame::new(0, 0x91, &[]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn futures_io_stream() {
+        use crate::async_connection::AsyncConnection;
+        let mut connection =
+            AsyncConnection::new(FromFutures(Scripted::new(&[BAUDRATE])), TokioDelay);
+        let frame = connection.transact(request()).await.unwrap();
+        assert_eq!(frame.into_data().as_slice(), &[0x00, 0x01, 0xC2, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn embedded_io_stream() {
+        use crate::async_connection::AsyncConnection;
+        let mut connection =
+            AsyncConnection::new(FromEmbeddedIo(Scripted::new(&[BAUDRATE])), TokioDelay);
+        let frame = connection.transact(request()).await.unwrap();
+        assert_eq!(frame.into_data().as_slice(), &[0x00, 0x01, 0xC2, 0x00]);
+
+        match connection.transact(request()).await {
+            Err(DeviceError::EmbeddedIoError(kind)) => {
+                assert_eq!(kind, embedded_io_async::ErrorKind::BrokenPipe)
+            }
+            other => panic!("expected a broken pipe, got {:?}", other),
+        }
+    }
+
+    /// A timer that has run out straight away
+    struct Expired;
+
+    impl Delay for Expired {
+        fn delay(&self, _: Duration) -> impl Future<Output = ()> {
+            std::future::ready(())
+        }
+    }
+
+    #[tokio::test]
+    async fn timeouts_come_from_the_delay() {
+        use crate::async_connection::AsyncConnection;
+        let mut connection = AsyncConnection::new(FromFutures(Scripted::new(&[])), Expired);
+        // the default response timeout would take 600ms on a real timer
+        let start = std::time::Instant::now();
+        assert!(matches!(connection.transact(request()).await, Err(DeviceError::Timeout)));
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
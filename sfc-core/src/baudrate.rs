@@ -0,0 +1,114 @@
+//! The line speeds the SFC5xxx and SFC6xxx talk SHDLC at. Both drivers take and return a
+//! [Baudrate] instead of a bare number, so a rate the devices don't support is caught before it
+//! is sent:
+//! ```
+//! use sfc_core::baudrate::Baudrate;
+//! use sfc_core::error::DeviceError;
+//!
+//! assert_eq!(Baudrate::try_from(57600).unwrap(), Baudrate::B57600);
+//! assert!(matches!(Baudrate::try_from(9600), Err(DeviceError::UnsupportedBaudrate(9600))));
+//! assert_eq!(u32::from(Baudrate::B115200), 115200);
+//! ```
+//! [Baudrate::Other] is for rates a newer firmware may add, it is sent as given and returned for
+//! a rate reported by a device that isn't one of the known ones.
+
+use core::fmt::Display;
+
+use crate::error::DeviceError;
+
+/// A line speed in bits per second, see the [module documentation](self)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Baudrate {
+    B19200,
+    B38400,
+    B57600,
+    /// The rate both devices ship with
+    #[default]
+    B115200,
+    /// A rate that isn't one of the documented ones, not checked
+    Other(u32),
+}
+
+impl Baudrate {
+    /// The documented rates, fastest first. This is the order to try them in when the rate a
+    /// device was set to is unknown, the default comes first.
+    pub const DETECTION_ORDER: [Baudrate; 4] =
+        [Self::B115200, Self::B57600, Self::B38400, Self::B19200];
+
+    /// The rate a device reported, [Baudrate::Other] if it isn't a documented one
+    pub fn from_device(baudrate: u32) -> Self {
+        Self::try_from(baudrate).unwrap_or(Self::Other(baudrate))
+    }
+
+    /// The rate in bits per second
+    pub fn bits_per_second(&self) -> u32 {
+        match self {
+            Self::B19200 => 19200,
+            Self::B38400 => 38400,
+            Self::B57600 => 57600,
+            Self::B115200 => 115200,
+            Self::Other(baudrate) => *baudrate,
+        }
+    }
+}
+
+impl From<Baudrate> for u32 {
+    fn from(baudrate: Baudrate) -> Self {
+        baudrate.bits_per_second()
+    }
+}
+
+/// Accepts the documented rates, everything else fails with [DeviceError::UnsupportedBaudrate].
+/// Use [Baudrate::Other] to send another rate anyway.
+impl TryFrom<u32> for Baudrate {
+    type Error = DeviceError;
+
+    fn try_from(baudrate: u32) -> Result<Self, Self::Error> {
+        match baudrate {
+            19200 => Ok(Self::B19200),
+            38400 => Ok(Self::B38400),
+            57600 => Ok(Self::B57600),
+            115200 => Ok(Self::B115200),
+            _ => Err(DeviceError::UnsupportedBaudrate(baudrate)),
+        }
+    }
+}
+
+/// Formats the rate like `115200 baud`
+impl Display for Baudrate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} baud", self.bits_per_second())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documented_rates_round_trip() {
+        for baudrate in Baudrate::DETECTION_ORDER {
+            assert_eq!(Baudrate::try_from(u32::from(baudrate)).unwrap(), baudrate);
+            assert_eq!(Baudrate::from_device(baudrate.bits_per_second()), baudrate);
+        }
+        assert_eq!(Baudrate::default(), Baudrate::DETECTION_ORDER[0]);
+        assert_eq!(Baudrate::B38400.to_string(), "38400 baud");
+    }
+
+    #[test]
+    fn unsupported_rates_are_rejected() {
+        for baudrate in [0, 9600, 115201, 230400] {
+            assert!(matches!(
+                Baudrate::try_from(baudrate),
+                Err(DeviceError::UnsupportedBaudrate(rate)) if rate == baudrate
+            ));
+            assert_eq!(Baudrate::from_device(baudrate), Baudrate::Other(baudrate));
+        }
+        assert_eq!(u32::from(Baudrate::Other(230400)), 230400);
+        assert_eq!(
+            Baudrate::try_from(9600).unwrap_err().to_string(),
+            "9600 baud is not supported, the devices take 19200, 38400, 57600 and 115200"
+        );
+    }
+}
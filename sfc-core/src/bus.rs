@@ -0,0 +1,205 @@
+//! Several devices with different slave addresses on one RS-485 line. The [SharedBus] owns the
+//! transport and hands out a [BusHandle] per slave address, every request and its response is
+//! exchanged while holding the bus so frames of different devices never interleave.
+//! ```no_run
+//! use sfc_core::bus::SharedBus;
+//! use sfc_core::transport::TcpTransport;
+//! let bus = SharedBus::new(TcpTransport::connect("10.0.0.5:4001").unwrap());
+//! let mut first = bus.handle(1);
+//! let mut second = bus.handle(2);
+//! let first_baudrate = first.transact(0x91, &[]).unwrap();
+//! let second_baudrate = second.transact(0x91, &[]).unwrap();
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::connection::{Connection, Line};
+use crate::error::DeviceError;
+use crate::shdlc::{MISOFrame, MOSIFrame};
+use crate::transport::Transport;
+
+/// A transport shared by every device on the line. Cloning the bus gives another reference to
+/// the same transport.
+#[derive(Debug)]
+pub struct SharedBus<T: Transport> {
+    port: Arc<Mutex<Line<T>>>,
+}
+
+impl<T: Transport> SharedBus<T> {
+    pub fn new(port: T) -> Self {
+        Self {
+            port: Arc::new(Mutex::new(Line::new(port))),
+        }
+    }
+
+    /// Returns a handle for the device with the given slave address
+    pub fn handle(&self, address: u8) -> BusHandle<T> {
+        BusHandle {
+            address,
+            connection: Connection::shared(Arc::clone(&self.port)),
+        }
+    }
+
+    /// Changes the line speed of the transport for every device on the bus. Waits for the
+    /// exchange currently in progress to finish.
+    pub fn set_baud_rate(&self, baud_rate: u32) -> Result<(), DeviceError> {
+        lock(&self.port).transport.set_baud_rate(baud_rate)
+    }
+}
+
+impl<T: Transport> Clone for SharedBus<T> {
+    fn clone(&self) -> Self {
+        Self {
+            port: Arc::clone(&self.port),
+        }
+    }
+}
+
+/// The connection to one slave address on a [SharedBus]. Every handle has its own timeouts and
+/// retry configuration.
+#[derive(Debug)]
+pub struct BusHandle<T: Transport> {
+    address: u8,
+    connection: Connection<T>,
+}
+
+impl<T: Transport> BusHandle<T> {
+    /// Returns the slave address the handle talks to
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the connection used by the handle, to change its timeouts or retries
+    pub fn connection(&mut self) -> &mut Connection<T> {
+        &mut self.connection
+    }
+
+    /// Sends a command to the device of this handle and waits for its response, like
+    /// [Connection::transact] does.
+    pub fn transact(&mut self, command: u8, data: &[u8]) -> Result<MISOFrame, DeviceError> {
+        let frame = MOSIFrame::new(self.address, command, data)?;
+        self.connection.transact(frame)
+    }
+}
+
+impl<T: Transport> From<BusHandle<T>> for Connection<T> {
+    fn from(handle: BusHandle<T>) -> Self {
+        handle.connection
+    }
+}
+
+/// Locks the transport. A thread that panicked while holding the lock can't have left the
+/// transport in a worse state than an unplugged cable, so poisoning is ignored.
+pub(crate) fn lock<T>(port: &Mutex<T>) -> MutexGuard<'_, T> {
+    port.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::shdlc::{from_shdlc, to_shdlc};
+
+    /// Answers every request with the slave address as data, a few bytes per read to give other
+    /// threads the chance to interleave
+    #[derive(Default)]
+    struct BusMock {
+        pending: Vec<u8>,
+        // one entry per request and one per fully read response
+        log: Vec<(char, u8)>,
+        interleaved: bool,
+        baud_rate: u32,
+    }
+
+    impl Read for BusMock {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() {
+                return Err(std::io::Error::new(ErrorKind::TimedOut, "no response pending"));
+            }
+            thread::sleep(Duration::from_micros(100));
+            let count = buf.len().min(3).min(self.pending.len());
+            buf[..count].copy_from_slice(&self.pending[..count]);
+            self.pending.drain(..count);
+            if self.pending.is_empty() {
+                let address = self.log.last().unwrap().1;
+                self.log.push(('R', address));
+            }
+            Ok(count)
+        }
+    }
+
+    impl Write for BusMock {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if !self.pending.is_empty() {
+                self.interleaved = true;
+            }
+            let request = from_shdlc(buf).unwrap();
+            let (address, command) = (request[0], request[1]);
+            self.log.push(('W', address));
+            self.pending = to_shdlc(&[address, command, 0, 1, address]).unwrap().to_vec();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for BusMock {
+        fn timeout(&self) -> Duration {
+            Duration::ZERO
+        }
+
+        fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), DeviceError> {
+            self.baud_rate = baud_rate;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frames_never_interleave() {
+        let bus = SharedBus::new(BusMock::default());
+        let threads: Vec<_> = [1, 2]
+            .into_iter()
+            .map(|address| {
+                let mut handle = bus.handle(address);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let res = handle.transact(0x91, &[]).unwrap();
+                        assert_eq!(res.get_address(), address);
+                        assert_eq!(res.into_data().as_slice(), &[address]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let line = lock(&bus.port);
+        let mock = &line.transport;
+        assert!(!mock.interleaved);
+        assert_eq!(mock.log.len(), 200);
+        for pair in mock.log.chunks(2) {
+            assert_eq!(pair[0].0, 'W');
+            assert_eq!(pair[1], ('R', pair[0].1));
+        }
+    }
+
+    #[test]
+    fn baud_rate_is_shared() {
+        let bus = SharedBus::new(BusMock::default());
+        let mut first = Connection::from(bus.handle(1));
+        first.set_baud_rate(19200).unwrap();
+        assert_eq!(lock(&bus.port).transport.baud_rate, 19200);
+        bus.set_baud_rate(57600).unwrap();
+        assert_eq!(bus.handle(2).connection().with_transport(|p| p.baud_rate), 57600);
+    }
+}
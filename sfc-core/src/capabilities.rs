@@ -0,0 +1,263 @@
+//! What the firmware of a device supports. Firmware revisions take different subsets of the
+//! commands of their family, the drivers offer `Device::capabilities`, which reads the version
+//! once, derives [Capabilities] from the [table] of the family and keeps them. The methods of a
+//! [Feature] check them first and fail with [DeviceError::UnsupportedFirmware] without sending
+//! the command:
+//! ```
+//! use sfc_core::capabilities::{Capabilities, Feature};
+//! use sfc_core::names::DeviceFamily;
+//!
+//! let capabilities = Capabilities::for_firmware(DeviceFamily::Sfc5xxx, (1, 40));
+//! assert!(capabilities.supports_buffered_read);
+//! assert!(!capabilities.supports(Feature::TwoSensors));
+//! assert!(capabilities.require(Feature::TwoSensors).is_err());
+//! ```
+//! Works without std.
+//!
+//! # The table
+//! | Family | Firmware | Feature |
+//! | --- | --- | --- |
+//! | SFC6xxx | 1.0 | averaging up to 100 measurements in one read |
+//! | SFC5xxx | 1.0 | reading the buffer of measured values |
+//! | SFC5xxx | 1.0 | the pressure dependent gain and the inlet pressure |
+//! | SFC5xxx | 1.0 | the gas temperature compensation and the inlet temperature |
+//! | SFC5xxx | 1.48 | reading the flow of both sensors |
+//!
+//! A feature a later firmware extends, like a higher averaging count, gets another row. A new
+//! feature gets a variant of [Feature] and a field of [Capabilities].
+
+use core::fmt::Display;
+
+use crate::error::DeviceError;
+use crate::names::DeviceFamily;
+use crate::shdlc::Version;
+
+/// A part of the commands of a family that only some firmware supports
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Feature {
+    /// Reading the flow of both sensors, with or without setting the setpoint
+    TwoSensors,
+    /// Reading the buffer of measured values
+    BufferedRead,
+    /// Averaging this many measurements in the device with one read
+    Averaging(u8),
+    /// The pressure dependent gain of the controller and the inlet pressure it uses
+    PressureDependentGain,
+    /// The gas temperature compensation and the inlet temperature it uses
+    GasTemperatureCompensation,
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TwoSensors => write!(f, "reading both sensors"),
+            Self::BufferedRead => write!(f, "reading the measurement buffer"),
+            Self::Averaging(count) => write!(f, "averaging {} measurements", count),
+            Self::PressureDependentGain => write!(f, "the pressure dependent gain"),
+            Self::GasTemperatureCompensation => write!(f, "the gas temperature compensation"),
+        }
+    }
+}
+
+/// The firmware, as major and minor version, from which on a family supports a feature. A row
+/// of [Feature::Averaging] raises the highest count to its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FirmwareFeature {
+    pub family: DeviceFamily,
+    pub since: (u8, u8),
+    pub feature: Feature,
+}
+
+const fn row(family: DeviceFamily, since: (u8, u8), feature: Feature) -> FirmwareFeature {
+    FirmwareFeature {
+        family,
+        since,
+        feature,
+    }
+}
+
+/// Every feature of both families with the first firmware that supports it, see the
+/// [module](self) for the table as a whole
+pub fn table() -> &'static [FirmwareFeature] {
+    use DeviceFamily::*;
+    use Feature::*;
+    const TABLE: &[FirmwareFeature] = &[
+        row(Sfc6xxx, (1, 0), Averaging(100)),
+        row(Sfc5xxx, (1, 0), BufferedRead),
+        row(Sfc5xxx, (1, 0), PressureDependentGain),
+        row(Sfc5xxx, (1, 0), GasTemperatureCompensation),
+        row(Sfc5xxx, (1, 48), TwoSensors),
+    ];
+    TABLE
+}
+
+/// What the firmware of a device supports, derived from the [table]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities {
+    pub family: DeviceFamily,
+    /// The firmware the capabilities are derived from, as major and minor version
+    pub firmware: (u8, u8),
+    /// See [Feature::TwoSensors]
+    pub supports_two_sensors: bool,
+    /// See [Feature::BufferedRead]
+    pub supports_buffered_read: bool,
+    /// The most measurements one averaged read takes, 0 without averaged reads
+    pub max_averaging_count: u8,
+    /// See [Feature::PressureDependentGain]
+    pub supports_pressure_dependent_gain: bool,
+    /// See [Feature::GasTemperatureCompensation]
+    pub supports_gas_temperature_compensation: bool,
+}
+
+impl Capabilities {
+    /// The capabilities of a device of the family with this firmware, as major and minor version
+    pub fn for_firmware(family: DeviceFamily, firmware: (u8, u8)) -> Self {
+        let mut capabilities = Self {
+            family,
+            firmware,
+            supports_two_sensors: false,
+            supports_buffered_read: false,
+            max_averaging_count: 0,
+            supports_pressure_dependent_gain: false,
+            supports_gas_temperature_compensation: false,
+        };
+        let rows = table().iter().filter(|row| row.family == family && row.since <= firmware);
+        for row in rows {
+            match row.feature {
+                Feature::TwoSensors => capabilities.supports_two_sensors = true,
+                Feature::BufferedRead => capabilities.supports_buffered_read = true,
+                Feature::Averaging(count) => {
+                    capabilities.max_averaging_count = capabilities.max_averaging_count.max(count)
+                }
+                Feature::PressureDependentGain => {
+                    capabilities.supports_pressure_dependent_gain = true
+                }
+                Feature::GasTemperatureCompensation => {
+                    capabilities.supports_gas_temperature_compensation = true
+                }
+            }
+        }
+        capabilities
+    }
+
+    /// The capabilities of a device of the family that answered with this version
+    pub fn for_version(family: DeviceFamily, version: &Version) -> Self {
+        Self::for_firmware(family, (version.firmware_major, version.firmware_minor))
+    }
+
+    /// Whether the firmware supports the feature
+    pub fn supports(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::TwoSensors => self.supports_two_sensors,
+            Feature::BufferedRead => self.supports_buffered_read,
+            Feature::Averaging(count) => count <= self.max_averaging_count,
+            Feature::PressureDependentGain => self.supports_pressure_dependent_gain,
+            Feature::GasTemperatureCompensation => self.supports_gas_temperature_compensation,
+        }
+    }
+
+    /// Fails with [DeviceError::UnsupportedFirmware] if the firmware doesn't support the feature
+    pub fn require(&self, feature: Feature) -> Result<(), DeviceError> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(DeviceError::UnsupportedFirmware {
+                feature,
+                firmware: self.firmware,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_before_the_first_release() {
+        for family in [DeviceFamily::Sfc6xxx, DeviceFamily::Sfc5xxx] {
+            let capabilities = Capabilities::for_firmware(family, (0, 9));
+            assert!(!capabilities.supports_two_sensors);
+            assert!(!capabilities.supports_buffered_read);
+            assert_eq!(capabilities.max_averaging_count, 0);
+            assert!(!capabilities.supports_pressure_dependent_gain);
+            assert!(!capabilities.supports_gas_temperature_compensation);
+        }
+    }
+
+    #[test]
+    fn features_arrive_with_their_firmware() {
+        let sfc5xxx = |firmware| Capabilities::for_firmware(DeviceFamily::Sfc5xxx, firmware);
+        let expected = [
+            // firmware, two sensors, buffered read, pressure, temperature
+            ((0, 255), false, false, false, false),
+            ((1, 0), false, true, true, true),
+            ((1, 47), false, true, true, true),
+            ((1, 48), true, true, true, true),
+            ((1, 255), true, true, true, true),
+            ((2, 0), true, true, true, true),
+        ];
+        for ((major, minor), two_sensors, buffered, pressure, temperature) in expected {
+            let capabilities = sfc5xxx((major, minor));
+            assert_eq!(capabilities.firmware, (major, minor));
+            assert_eq!(capabilities.supports_two_sensors, two_sensors, "{}.{}", major, minor);
+            assert_eq!(capabilities.supports_buffered_read, buffered, "{}.{}", major, minor);
+            assert_eq!(capabilities.supports_pressure_dependent_gain, pressure);
+            assert_eq!(capabilities.supports_gas_temperature_compensation, temperature);
+            assert_eq!(capabilities.max_averaging_count, 0);
+        }
+    }
+
+    #[test]
+    fn the_families_have_their_own_rows() {
+        for firmware in [(1, 0), (1, 4), (1, 48), (3, 255)] {
+            let sfc6xxx = Capabilities::for_firmware(DeviceFamily::Sfc6xxx, firmware);
+            assert_eq!(sfc6xxx.max_averaging_count, 100);
+            assert!(!sfc6xxx.supports_two_sensors);
+            assert!(!sfc6xxx.supports_buffered_read);
+            assert!(!sfc6xxx.supports_pressure_dependent_gain);
+        }
+    }
+
+    #[test]
+    fn averaging_is_supported_up_to_the_highest_count() {
+        let capabilities = Capabilities::for_firmware(DeviceFamily::Sfc6xxx, (1, 0));
+        assert!(capabilities.supports(Feature::Averaging(0)));
+        assert!(capabilities.supports(Feature::Averaging(100)));
+        match capabilities.require(Feature::Averaging(101)) {
+            Err(DeviceError::UnsupportedFirmware { feature, firmware }) => {
+                assert_eq!(feature, Feature::Averaging(101));
+                assert_eq!(firmware, (1, 0));
+            }
+            other => panic!("expected unsupported firmware, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_version_picks_the_firmware() {
+        let version = Version {
+            firmware_major: 1,
+            firmware_minor: 47,
+            debug: false,
+            hardware_major: 2,
+            hardware_minor: 0,
+            protocol_major: 2,
+            protocol_minor: 0,
+        };
+        let capabilities = Capabilities::for_version(DeviceFamily::Sfc5xxx, &version);
+        assert_eq!(capabilities, Capabilities::for_firmware(DeviceFamily::Sfc5xxx, (1, 47)));
+        capabilities.require(Feature::PressureDependentGain).unwrap();
+        assert!(capabilities.require(Feature::TwoSensors).is_err());
+    }
+
+    #[test]
+    fn every_row_is_in_order_of_its_family() {
+        for family in [DeviceFamily::Sfc6xxx, DeviceFamily::Sfc5xxx] {
+            let since: Vec<_> =
+                table().iter().filter(|row| row.family == family).map(|row| row.since).collect();
+            assert!(since.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", since);
+        }
+    }
+}
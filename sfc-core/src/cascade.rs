@@ -0,0 +1,348 @@
+//! One controller following another, available with `std`. In a cascade the measured value of
+//! an upstream controller, like the pressure ahead of a line, decides the setpoint of a
+//! downstream one. A [Cascade] reads the upstream value, maps it through a transfer function,
+//! limits how fast the result changes and writes it as the downstream setpoint:
+//! ```
+//! # fn run<C: sfc_core::flow_controller::FlowController + Send + 'static>(pressure: C, flow: C)
+//! #     -> Result<(), sfc_core::cascade::CascadeError> {
+//! use std::time::Duration;
+//!
+//! use sfc_core::cascade::{Cascade, CascadeConfig, FailurePolicy};
+//!
+//! // 1 l/min of flow for every bar of pressure
+//! let mut cascade = Cascade::new(pressure, flow, |bar| bar * 1.0);
+//! cascade.set_config(CascadeConfig {
+//!     interval: Duration::from_millis(200),
+//!     max_rate: Some(0.5),
+//!     failure_policy: FailurePolicy::Zero,
+//! });
+//! cascade.step()?;
+//! let running = cascade.start();
+//! std::thread::sleep(Duration::from_secs(10));
+//! println!("{:?}", running.status().setpoint);
+//! let (pressure, flow) = running.stop().into_inner();
+//! # Ok(())
+//! # }
+//! ```
+//! [Cascade::step] runs one update where it is called, [Cascade::start] runs them on a thread of
+//! its own until [RunningCascade::stop]. On two devices of one RS-485 line, give the cascade the
+//! devices of two handles of the same [SharedBus](crate::bus::SharedBus).
+
+use std::fmt::Display;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::DeviceError;
+use crate::flow_controller::FlowController;
+
+/// What a [Cascade] does with the downstream setpoint when an update fails
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Leave the downstream setpoint at the last one written
+    #[default]
+    HoldLast,
+    /// Set the downstream setpoint to zero
+    Zero,
+}
+
+/// How a [Cascade] updates the downstream setpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CascadeConfig {
+    /// The time between two updates of a [running](Cascade::start) cascade, which bounds how
+    /// often the setpoint is written. 100ms by default.
+    pub interval: Duration,
+    /// The most the downstream setpoint changes per second, in the unit of the downstream
+    /// controller. [None], the default, writes every new setpoint right away.
+    pub max_rate: Option<f32>,
+    pub failure_policy: FailurePolicy,
+}
+
+impl Default for CascadeConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            max_rate: None,
+            failure_policy: FailurePolicy::default(),
+        }
+    }
+}
+
+/// Why an update of a [Cascade] failed. The [FailurePolicy] has been applied when it is
+/// returned.
+#[derive(Debug)]
+pub enum CascadeError {
+    /// Reading the upstream controller failed
+    Upstream(DeviceError),
+    /// Reading or setting the downstream controller failed
+    Downstream(DeviceError),
+}
+
+impl Display for CascadeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Upstream(e) => write!(f, "upstream: {}", e),
+            Self::Downstream(e) => write!(f, "downstream: {}", e),
+        }
+    }
+}
+
+/// A downstream controller following the measured value of an upstream one, see the
+/// [module documentation](self)
+#[derive(Debug)]
+pub struct Cascade<U, D, F> {
+    upstream: U,
+    downstream: D,
+    transfer: F,
+    config: CascadeConfig,
+    /// the latest value read upstream
+    measured: Option<f32>,
+    /// the setpoint last written downstream, [None] while it isn't known
+    setpoint: Option<f32>,
+    /// when the setpoint was last written
+    updated: Option<Instant>,
+    /// read from the downstream controller when first needed
+    full_scale: Option<f32>,
+}
+
+impl<U, D, F> Cascade<U, D, F>
+where
+    U: FlowController,
+    D: FlowController,
+    F: FnMut(f32) -> f32,
+{
+    /// Creates a cascade that sets `downstream` to `transfer` of the value measured by
+    /// `upstream`. Nothing is sent before the first update, the config is the default until
+    /// changed.
+    pub fn new(upstream: U, downstream: D, transfer: F) -> Self {
+        Self {
+            upstream,
+            downstream,
+            transfer,
+            config: CascadeConfig::default(),
+            measured: None,
+            setpoint: None,
+            updated: None,
+            full_scale: None,
+        }
+    }
+
+    /// Returns how the downstream setpoint is updated
+    pub fn config(&self) -> CascadeConfig {
+        self.config
+    }
+
+    /// Sets how the downstream setpoint is updated
+    ///
+    /// # Panics
+    /// If the maximum rate is negative or not a number
+    pub fn set_config(&mut self, config: CascadeConfig) {
+        assert!(
+            config.max_rate.is_none_or(|rate| rate >= 0.0),
+            "the rate of a cascade can't be negative"
+        );
+        self.config = config;
+    }
+
+    /// The value the upstream controller measured in the latest update that read it
+    pub fn measured(&self) -> Option<f32> {
+        self.measured
+    }
+
+    /// The setpoint last written downstream, [None] before the first update and after the
+    /// policy failed to zero it
+    pub fn setpoint(&self) -> Option<f32> {
+        self.setpoint
+    }
+
+    /// Returns the upstream controller
+    pub fn upstream_mut(&mut self) -> &mut U {
+        &mut self.upstream
+    }
+
+    /// Returns the downstream controller
+    pub fn downstream_mut(&mut self) -> &mut D {
+        &mut self.downstream
+    }
+
+    /// Returns the upstream and the downstream controller
+    pub fn into_inner(self) -> (U, D) {
+        (self.upstream, self.downstream)
+    }
+
+    /// Updates the downstream setpoint once, see [Cascade::step_at]
+    pub fn step(&mut self) -> Result<f32, CascadeError> {
+        self.step_at(Instant::now())
+    }
+
+    /// Updates the downstream setpoint as of `now` and returns the setpoint written. The
+    /// transfer of the upstream value is kept between zero and the downstream full scale, a
+    /// result that is not a number is taken as zero. Under a maximum rate the setpoint moves
+    /// towards it by at most the rate times the time since the last update, or one interval
+    /// for the first one, which starts from the setpoint the downstream controller has. The
+    /// full scale is read on the first update and kept.
+    ///
+    /// A failure applies the [FailurePolicy] before it is returned and the next update ramps
+    /// from the setpoint held or zeroed. A read-only downstream controller fails with
+    /// [DeviceError::ReadOnly] without anything being sent.
+    pub fn step_at(&mut self, now: Instant) -> Result<f32, CascadeError> {
+        let result = self.update(now);
+        if result.is_err() {
+            if self.config.failure_policy == FailurePolicy::Zero {
+                self.setpoint = self.downstream.set_setpoint(0.0).ok().map(|()| 0.0);
+            }
+            // the setpoint is held from now on, the next update ramps away from it again
+            self.updated = Some(now);
+        }
+        result
+    }
+
+    fn update(&mut self, now: Instant) -> Result<f32, CascadeError> {
+        if self.downstream.is_read_only() {
+            return Err(CascadeError::Downstream(DeviceError::ReadOnly("run cascade")));
+        }
+        let measured = self.upstream.read_measured_value().map_err(CascadeError::Upstream)?;
+        self.measured = Some(measured);
+        let full_scale = self.full_scale()?;
+        let target = match (self.transfer)(measured) {
+            target if target.is_nan() => 0.0,
+            target => target.max(0.0).min(full_scale),
+        };
+        let setpoint = match self.config.max_rate {
+            Some(rate) => {
+                let current = match self.setpoint {
+                    Some(setpoint) => setpoint,
+                    None => self.downstream.get_setpoint().map_err(CascadeError::Downstream)?,
+                };
+                let elapsed = self
+                    .updated
+                    .map_or(self.config.interval, |updated| now.saturating_duration_since(updated));
+                let change = rate * elapsed.as_secs_f32();
+                target.min(current + change).max(current - change)
+            }
+            None => target,
+        };
+        self.downstream.set_setpoint(setpoint).map_err(CascadeError::Downstream)?;
+        self.setpoint = Some(setpoint);
+        self.updated = Some(now);
+        Ok(setpoint)
+    }
+
+    fn full_scale(&mut self) -> Result<f32, CascadeError> {
+        if let Some(full_scale) = self.full_scale {
+            return Ok(full_scale);
+        }
+        let full_scale = self.downstream.get_full_scale().map_err(CascadeError::Downstream)?;
+        Ok(*self.full_scale.insert(full_scale))
+    }
+}
+
+impl<U, D, F> Cascade<U, D, F>
+where
+    U: FlowController + Send + 'static,
+    D: FlowController + Send + 'static,
+    F: FnMut(f32) -> f32 + Send + 'static,
+{
+    /// Runs an update every [CascadeConfig::interval] on a thread of its own, the first one
+    /// right away. A late update moves the later ones instead of running several back to back.
+    pub fn start(self) -> RunningCascade<U, D, F> {
+        let status = Arc::new(RwLock::new(CascadeStatus::default()));
+        let (stop, stopped) = mpsc::channel();
+        let published = Arc::clone(&status);
+        let thread = thread::spawn(move || run(self, &published, &stopped));
+        RunningCascade {
+            status,
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// What the updates of a [RunningCascade] did so far
+#[derive(Debug, Default)]
+pub struct CascadeStatus {
+    /// The value the upstream controller measured in the latest update that read it
+    pub measured: Option<f32>,
+    /// The setpoint last written downstream, see [Cascade::setpoint]
+    pub setpoint: Option<f32>,
+    /// The error of the latest update, [None] if it succeeded
+    pub error: Option<CascadeError>,
+    /// When the latest update finished, [None] before the first one
+    pub updated: Option<Instant>,
+    /// The number of updates so far, failed ones included
+    pub updates: u64,
+    /// The number of updates in a row that failed
+    pub consecutive_failures: u32,
+}
+
+/// A [Cascade] updating on a thread of its own, returned by [Cascade::start]
+#[derive(Debug)]
+pub struct RunningCascade<U, D, F> {
+    status: Arc<RwLock<CascadeStatus>>,
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<Cascade<U, D, F>>>,
+}
+
+impl<U, D, F> RunningCascade<U, D, F> {
+    /// The status after the latest update. Updating waits while it is borrowed, so don't hold
+    /// on to it.
+    pub fn status(&self) -> RwLockReadGuard<'_, CascadeStatus> {
+        self.status.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stops updating once the update in progress has finished and returns the cascade. The
+    /// downstream controller keeps the setpoint last written.
+    // the thread is only taken here and when dropped, nothing a device sends can make it missing
+    #[allow(clippy::expect_used)]
+    pub fn stop(mut self) -> Cascade<U, D, F> {
+        let _ = self.stop.send(());
+        let thread = self.thread.take().expect("the thread is only taken here");
+        thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+/// Stops updating and drops the cascade, [RunningCascade::stop] returns it instead
+impl<U, D, F> Drop for RunningCascade<U, D, F> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.stop.send(());
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run<U, D, F>(
+    mut cascade: Cascade<U, D, F>,
+    status: &RwLock<CascadeStatus>,
+    stop: &mpsc::Receiver<()>,
+) -> Cascade<U, D, F>
+where
+    U: FlowController,
+    D: FlowController,
+    F: FnMut(f32) -> f32,
+{
+    let mut due = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= due {
+            let result = cascade.step_at(now);
+            let mut status = status.write().unwrap_or_else(PoisonError::into_inner);
+            status.consecutive_failures = match result {
+                Ok(_) => 0,
+                Err(_) => status.consecutive_failures + 1,
+            };
+            status.error = result.err();
+            status.measured = cascade.measured;
+            status.setpoint = cascade.setpoint;
+            status.updated = Some(Instant::now());
+            status.updates += 1;
+            due = due.max(now) + cascade.config.interval;
+            continue;
+        }
+        match stop.recv_timeout(due - now) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return cascade,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Converging a controller to settings kept in a file, available with `std`. The drivers offer
+//! `Device::apply_config`, which reads the settings a [DeviceConfig] asks for, compares them and
+//! only writes the ones that differ:
+//! ```
+//! use sfc_core::config::{DeviceConfig, Setting};
+//!
+//! let desired = DeviceConfig {
+//!     controller_gain: Some(1.5),
+//!     calibration: Some(2),
+//!     ..DeviceConfig::default()
+//! };
+//! // the order they are applied in
+//! assert_eq!(desired.settings(), vec![Setting::Calibration(2), Setting::ControllerGain(1.5)]);
+//! ```
+//! With the `serde` feature a [DeviceConfig] can be read from any format serde supports, settings
+//! that are missing are left alone and unknown ones are an error.
+
+use std::fmt::Display;
+
+use crate::error::DeviceError;
+use crate::gasunit::GasUnit;
+
+/// The settings a controller should have. [None] leaves a setting as it is.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct DeviceConfig {
+    pub address: Option<u8>,
+    pub baudrate: Option<u32>,
+    pub controller_gain: Option<f32>,
+    /// Only on the SFC6xxx
+    pub initial_step: Option<f32>,
+    /// The index of the active calibration, stored so it is kept after a reset
+    pub calibration: Option<u32>,
+    /// Only on the SFC5xxx
+    pub setpoint_persistent: Option<bool>,
+    /// The unit of the setpoint and measured flow, only on the SFC5xxx
+    pub unit: Option<GasUnit>,
+}
+
+impl DeviceConfig {
+    /// Every setting that is not [None], in the order they are applied. The calibration comes
+    /// first since the unit and gain belong to it, the address and baudrate last since the
+    /// device only answers at the new ones afterwards.
+    pub fn settings(&self) -> Vec<Setting> {
+        [
+            self.calibration.map(Setting::Calibration),
+            self.unit.map(Setting::Unit),
+            self.controller_gain.map(Setting::ControllerGain),
+            self.initial_step.map(Setting::InitialStep),
+            self.setpoint_persistent.map(Setting::SetpointPersistent),
+            self.baudrate.map(Setting::Baudrate),
+            self.address.map(Setting::Address),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// A single setting of a [DeviceConfig] with its value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Setting {
+    Calibration(u32),
+    Unit(GasUnit),
+    ControllerGain(f32),
+    InitialStep(f32),
+    SetpointPersistent(bool),
+    Baudrate(u32),
+    Address(u8),
+}
+
+impl Setting {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Calibration(_) => "calibration",
+            Self::Unit(_) => "unit",
+            Self::ControllerGain(_) => "controller gain",
+            Self::InitialStep(_) => "initial step",
+            Self::SetpointPersistent(_) => "setpoint persistent",
+            Self::Baudrate(_) => "baudrate",
+            Self::Address(_) => "address",
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            Self::Calibration(index) => index.to_string(),
+            Self::Unit(unit) => unit.to_string(),
+            Self::ControllerGain(gain) => gain.to_string(),
+            Self::InitialStep(step) => step.to_string(),
+            Self::SetpointPersistent(persistent) => persistent.to_string(),
+            Self::Baudrate(baudrate) => baudrate.to_string(),
+            Self::Address(address) => address.to_string(),
+        }
+    }
+}
+
+/// Formats the setting like `controller gain 1.5`
+impl Display for Setting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name(), self.value())
+    }
+}
+
+/// Why a setting of a [ConfigDiff] was left alone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The device doesn't have the setting
+    Unsupported,
+    /// The device can set it but not report it, so it can't be compared
+    Unreadable,
+    /// An earlier setting failed to apply
+    AfterFailure,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "not supported by this device"),
+            Self::Unreadable => write!(f, "can't be read back from this device"),
+            Self::AfterFailure => write!(f, "an earlier setting failed"),
+        }
+    }
+}
+
+/// How a device compared to a [DeviceConfig] and what applying it did. Every setting of the
+/// desired config ends up in exactly one of `changed`, `unchanged`, `skipped` or `failed`.
+#[derive(Debug)]
+pub struct ConfigDiff {
+    /// The values read from the device before anything was written, only for the settings that
+    /// were compared
+    pub current: DeviceConfig,
+    /// The settings that differed and were written, in that order. In a dry run the ones that
+    /// would have been.
+    pub changed: Vec<Setting>,
+    /// The settings the device already had
+    pub unchanged: Vec<Setting>,
+    pub skipped: Vec<(Setting, SkipReason)>,
+    /// The setting that failed to apply and why, the ones after it are skipped
+    pub failed: Option<(Setting, DeviceError)>,
+    /// Nothing was written
+    pub dry_run: bool,
+}
+
+impl ConfigDiff {
+    /// Compares the desired settings with the ones read from the device. `skip` gives the reason
+    /// for the settings the driver can't compare, they are neither read nor written.
+    pub fn compare(
+        desired: &DeviceConfig,
+        current: DeviceConfig,
+        skip: impl Fn(&Setting) -> Option<SkipReason>,
+    ) -> Self {
+        let present = current.settings();
+        let mut diff = Self {
+            current,
+            changed: Vec::new(),
+            unchanged: Vec::new(),
+            skipped: Vec::new(),
+            failed: None,
+            dry_run: true,
+        };
+        for setting in desired.settings() {
+            if let Some(reason) = skip(&setting) {
+                diff.skipped.push((setting, reason));
+            } else if present.contains(&setting) {
+                diff.unchanged.push(setting);
+            } else {
+                diff.changed.push(setting);
+            }
+        }
+        diff
+    }
+
+    /// Writes the changed settings in order with `write`. The first one that fails is moved to
+    /// [ConfigDiff::failed] and the ones after it to [ConfigDiff::skipped].
+    pub fn apply(&mut self, mut write: impl FnMut(Setting) -> Result<(), DeviceError>) {
+        self.dry_run = false;
+        let mut pending = std::mem::take(&mut self.changed).into_iter();
+        for setting in pending.by_ref() {
+            match write(setting) {
+                Ok(()) => self.changed.push(setting),
+                Err(e) => {
+                    self.failed = Some((setting, e));
+                    break;
+                }
+            }
+        }
+        self.skipped
+            .extend(pending.map(|setting| (setting, SkipReason::AfterFailure)));
+    }
+
+    /// True if the device has every desired setting now
+    pub fn is_converged(&self) -> bool {
+        self.skipped.is_empty() && self.failed.is_none() && (!self.dry_run || self.changed.is_empty())
+    }
+
+    fn current_value(&self, setting: &Setting) -> String {
+        self.current
+            .settings()
+            .into_iter()
+            .find(|current| current.name() == setting.name())
+            .map(|current| current.value())
+            .unwrap_or_else(|| "?".to_string())
+    }
+}
+
+/// Formats one setting per line, like `calibration: 0 -> 2`
+impl Display for ConfigDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut lines = Vec::new();
+        if self.dry_run {
+            lines.push("dry run, nothing written".to_string());
+        }
+        for setting in &self.changed {
+            let value = format!("{} -> {}", self.current_value(setting), setting.value());
+            lines.push(format!("{}: {}", setting.name(), value));
+        }
+        for setting in &self.unchanged {
+            lines.push(format!("{}: {} (unchanged)", setting.name(), setting.value()));
+        }
+        if let Some((setting, error)) = &self.failed {
+            let value = format!("{} -> {}", self.current_value(setting), setting.value());
+            lines.push(format!("{}: {} failed, {}", setting.name(), value, error));
+        }
+        for (setting, reason) in &self.skipped {
+            lines.push(format!("{}: {} skipped, {}", setting.name(), setting.value(), reason));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StateResponseError;
+
+    #[test]
+    fn applies_in_order_and_stops_at_a_failure() {
+        let desired = DeviceConfig {
+            address: Some(3),
+            baudrate: Some(57600),
+            controller_gain: Some(2.0),
+            calibration: Some(1),
+            unit: None,
+            ..DeviceConfig::default()
+        };
+        let current = DeviceConfig {
+            address: Some(0),
+            baudrate: Some(115200),
+            controller_gain: Some(2.0),
+            ..DeviceConfig::default()
+        };
+        let mut diff = ConfigDiff::compare(&desired, current, |setting| {
+            matches!(setting, Setting::Calibration(_)).then_some(SkipReason::Unreadable)
+        });
+        assert_eq!(diff.changed, vec![Setting::Baudrate(57600), Setting::Address(3)]);
+        assert_eq!(diff.unchanged, vec![Setting::ControllerGain(2.0)]);
+        assert_eq!(diff.skipped, vec![(Setting::Calibration(1), SkipReason::Unreadable)]);
+
+        let mut written = Vec::new();
+        diff.apply(|setting| {
+            written.push(setting);
+            Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+        });
+        assert_eq!(written, vec![Setting::Baudrate(57600)]);
+        assert!(diff.changed.is_empty());
+        assert!(matches!(diff.failed, Some((Setting::Baudrate(57600), _))));
+        assert_eq!(diff.skipped[1], (Setting::Address(3), SkipReason::AfterFailure));
+        assert!(!diff.is_converged());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn missing_settings_are_left_alone() {
+        let config: DeviceConfig =
+            serde_json::from_str(r#"{"address": 2, "controller_gain": 1.5}"#).unwrap();
+        assert_eq!(config.settings(), vec![Setting::ControllerGain(1.5), Setting::Address(2)]);
+        assert!(serde_json::from_str::<DeviceConfig>(r#"{"adress": 2}"#).is_err());
+    }
+}
@@ -0,0 +1,2364 @@
+//! The request/response cycle shared by every device. A [Connection] owns the [Transport] of a
+//! device, writes MOSI frames to it and collects the MISO frame that answers them.
+//!
+//! ## Tracing
+//! With the `tracing` feature every command runs inside a `shdlc_command` span at the debug
+//! level. Its fields are:
+//! - `command`: the command byte
+//! - `name`: the name of the command, see [command_name](crate::names::command_name), only
+//!   for frames marked with their device family
+//! - `address`: the slave address the command was sent to
+//! - `duration_us`: the time the command took including retries, in microseconds
+//! - `outcome`: `ok`, or what went wrong: `timeout`, `incomplete_frame`, `checksum`, `framing`,
+//!   `device_state`, `retries_exhausted`, `deadline`, `io` or `other`
+//!
+//! A warning event with the `attempt` and `error` fields is emitted inside the span before a
+//! command is retried, and one with the `error` field when a command fails.
+
+use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::Poll;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arrayvec::ArrayVec;
+
+use crate::error::DeviceError;
+use crate::exchange::{
+    PROBE_ATTEMPTS, PROBE_COMMAND, Receiver, Request, Settings, expired, is_busy,
+    is_framing_error,
+};
+#[cfg(feature = "log")]
+use crate::exchange::{Hex, Named};
+#[cfg(feature = "tracing")]
+use crate::exchange::outcome;
+use crate::middleware::{self, FrameMiddleware};
+use crate::shdlc::{CommandKind, Idempotency, MISOFrame, MISOFrameRef, MOSIFrame, START_STOP};
+use crate::stats::CommStats;
+use crate::bus::lock;
+use crate::transport::Transport;
+
+pub use crate::exchange::{
+    BootGrace, BusyPolicy, DEFAULT_INTER_BYTE_TIMEOUT, DEFAULT_RESPONSE_TIMEOUT, RetryConfig,
+    Rs485Config, SpinPolicy, Timeouts, ValidationLevel,
+};
+
+/// How many writes in a row may make no progress before sending a frame fails
+const STALLED_WRITES: u32 = 3;
+
+/// The transport of a device together with the timeouts used while waiting for a response.
+///
+/// Two limits apply while receiving. The response timeout is the time from sending a request
+/// until the start of the response arrives; when it runs out [DeviceError::Timeout] is returned.
+/// It depends on the [CommandKind] of the frame, see [Timeouts]. Once the response has started
+/// every following byte has to arrive within the inter-byte timeout of the one before it, a
+/// response that stalls mid frame fails with [DeviceError::IncompleteFrame]. Bytes received
+/// before the start of a frame are discarded and don't count as the response starting, so line
+/// noise can't keep a request waiting forever.
+///
+/// Requests and responses are paired up in two ways. Input that is still pending when a request
+/// is sent is discarded first (see [Connection::set_clear_stale_input]), and a response is only
+/// accepted if it echoes the address and command of the request. Any other complete frame is a
+/// late answer to an earlier request and is skipped.
+///
+/// A read can return more than the response, like the start of a duplicated or late frame.
+/// Those bytes are kept with the transport and are the first ones looked at by the next request,
+/// unless stale input is cleared.
+///
+/// Failed commands are only sent again when a [RetryConfig] is set.
+///
+/// [Connection::start] sends a request without waiting for the response, which is then collected
+/// by polling the returned [PendingCommand].
+///
+/// A [FrameMiddleware] set with [Connection::set_middleware] sees the requests before they are
+/// sent and the responses they got.
+///
+/// The connection changes the timeout of the transport before every read, the timeout set on
+/// the transport itself is not used.
+pub struct Connection<T: Transport> {
+    port: Port<T>,
+    settings: Settings,
+    stats: CommStats,
+    middleware: Option<Box<dyn FrameMiddleware>>,
+}
+
+#[derive(Debug)]
+enum Port<T> {
+    // the receive buffer makes the line large, boxed to keep devices small to move
+    Owned(Box<Line<T>>),
+    Shared(Arc<Mutex<Line<T>>>),
+}
+
+/// A transport together with the bytes read from it that no response used yet
+#[derive(Debug)]
+pub(crate) struct Line<T> {
+    pub(crate) transport: T,
+    receiver: Receiver,
+}
+
+impl<T> Line<T> {
+    pub(crate) fn new(transport: T) -> Self {
+        Self {
+            transport,
+            receiver: Receiver::new(),
+        }
+    }
+}
+
+/// The line while a command is running, locked if it is shared
+enum PortGuard<'a, T> {
+    Owned(&'a mut Line<T>),
+    Shared(MutexGuard<'a, Line<T>>),
+}
+
+impl<T> Deref for PortGuard<'_, T> {
+    type Target = Line<T>;
+
+    fn deref(&self) -> &Line<T> {
+        match self {
+            PortGuard::Owned(port) => port,
+            PortGuard::Shared(port) => port,
+        }
+    }
+}
+
+impl<T> DerefMut for PortGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Line<T> {
+        match self {
+            PortGuard::Owned(port) => port,
+            PortGuard::Shared(port) => port,
+        }
+    }
+}
+
+impl<T: Transport> Connection<T> {
+    /// Wraps a transport using [DEFAULT_RESPONSE_TIMEOUT] and [DEFAULT_INTER_BYTE_TIMEOUT]
+    pub fn new(port: T) -> Self {
+        Self {
+            port: Port::Owned(Box::new(Line::new(port))),
+            settings: Settings::default(),
+            stats: CommStats::default(),
+            middleware: None,
+        }
+    }
+
+    /// A connection to one of the devices on a [SharedBus](crate::bus::SharedBus), using the
+    /// default timeouts
+    pub(crate) fn shared(port: Arc<Mutex<Line<T>>>) -> Self {
+        Self {
+            port: Port::Shared(port),
+            settings: Settings::default(),
+            stats: CommStats::default(),
+            middleware: None,
+        }
+    }
+
+    /// Returns how long the device has to start answering a query, see
+    /// [Connection::timeouts] for the other kinds of command
+    pub fn response_timeout(&self) -> Duration {
+        self.settings.timeouts.query
+    }
+
+    /// Sets how long the device has to start answering a request, the same for every kind of
+    /// command
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.settings.timeouts = Timeouts::uniform(timeout);
+    }
+
+    /// Returns how long the device has to start answering each kind of command
+    pub fn timeouts(&self) -> Timeouts {
+        self.settings.timeouts
+    }
+
+    /// Sets how long the device has to start answering each kind of command
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.settings.timeouts = timeouts;
+    }
+
+    /// Returns the time allowed between two bytes of a response
+    pub fn inter_byte_timeout(&self) -> Duration {
+        self.settings.inter_byte_timeout
+    }
+
+    /// Sets the time allowed between two bytes of a response
+    pub fn set_inter_byte_timeout(&mut self, timeout: Duration) {
+        self.settings.inter_byte_timeout = timeout;
+    }
+
+    /// Returns true if pending input is discarded before every request
+    pub fn clear_stale_input(&self) -> bool {
+        self.settings.clear_stale_input
+    }
+
+    /// Sets whether input that is still pending when a request is sent, like the response to a
+    /// request that timed out, is discarded before sending. On by default, which is the strict
+    /// mode: every response has to arrive after its request. Turned off the connection is
+    /// pipelined, bytes read past the end of a response are kept and the next request looks at
+    /// them before reading from the transport.
+    pub fn set_clear_stale_input(&mut self, clear: bool) {
+        self.settings.clear_stale_input = clear;
+    }
+
+    /// Returns how many requests [Connection::pipeline] writes ahead of their responses
+    pub fn pipeline_window(&self) -> usize {
+        self.settings.pipeline_window
+    }
+
+    /// Sets how many requests [Connection::pipeline] may have written that were not answered
+    /// yet. 1, the default, sends one request at a time, 0 counts as 1.
+    pub fn set_pipeline_window(&mut self, window: usize) {
+        self.settings.pipeline_window = window.max(1);
+    }
+
+    /// Returns how a transport that returns empty reads is waited on
+    pub fn spin_policy(&self) -> SpinPolicy {
+        self.settings.spin
+    }
+
+    /// Sets how often a transport that returns from a read with no bytes is read again before
+    /// the connection sleeps between reads, see [SpinPolicy]
+    pub fn set_spin_policy(&mut self, policy: SpinPolicy) {
+        self.settings.spin = policy;
+    }
+
+    /// Returns the retry configuration, [None] if commands are never retried
+    pub fn retry(&self) -> Option<RetryConfig> {
+        self.settings.retry
+    }
+
+    /// Sets how commands sent with [Connection::transact] are retried. [None], the default,
+    /// turns retrying off.
+    pub fn set_retry(&mut self, retry: Option<RetryConfig>) {
+        self.settings.retry = retry;
+    }
+
+    /// Returns how a device that is still booting after a reset is waited for, [None] if it
+    /// isn't
+    pub fn boot_grace(&self) -> Option<BootGrace> {
+        self.settings.boot_grace
+    }
+
+    /// Sets how a device that answers with [StateResponseError::FatalError] right after a reset
+    /// is waited for, see [BootGrace]. [None], the default, fails the command with the first
+    /// fatal error like any other state.
+    ///
+    /// [StateResponseError::FatalError]: crate::error::StateResponseError::FatalError
+    pub fn set_boot_grace(&mut self, grace: Option<BootGrace>) {
+        self.settings.boot_grace = grace;
+    }
+
+    /// Returns how commands the device answers with a busy state are sent again, [None] if they
+    /// aren't
+    pub fn busy_retry(&self) -> Option<BusyPolicy> {
+        self.settings.busy_retry
+    }
+
+    /// Sets how a command the device answers with [StateResponseError::SensorBusy] is sent
+    /// again, see [BusyPolicy]. [None], the default, fails the command with the busy state.
+    ///
+    /// [StateResponseError::SensorBusy]: crate::error::StateResponseError::SensorBusy
+    pub fn set_busy_retry(&mut self, policy: Option<BusyPolicy>) {
+        self.settings.busy_retry = policy;
+    }
+
+    /// Starts the [BootGrace] window. Call it when a reset is sent, the drivers do for their
+    /// reset commands.
+    pub fn mark_reset(&mut self) {
+        self.settings.last_reset = Some(Instant::now());
+    }
+
+    /// Returns how long ago the last reset was sent through this connection, see
+    /// [Connection::mark_reset]. [None] if there wasn't one.
+    pub fn since_reset(&self) -> Option<Duration> {
+        self.settings.last_reset.map(|reset| reset.elapsed())
+    }
+
+    /// Returns how closely responses are checked
+    pub fn validation_level(&self) -> ValidationLevel {
+        self.settings.validation
+    }
+
+    /// Sets which checks of a response fail the command, see [ValidationLevel].
+    /// [ValidationLevel::Standard] by default.
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.settings.validation = level;
+    }
+
+    /// Returns how requests are sent over RS-485, [None] if the adapter switches direction by
+    /// itself
+    pub fn rs485(&self) -> Option<Rs485Config> {
+        self.settings.rs485
+    }
+
+    /// Sets how requests are sent over an RS-485 adapter with manual direction control. [None],
+    /// the default, writes the request and reads the response straight away.
+    pub fn set_rs485(&mut self, rs485: Option<Rs485Config>) {
+        self.settings.rs485 = rs485;
+    }
+
+    /// Changes the line speed of the underlying transport. On a
+    /// [SharedBus](crate::bus::SharedBus) this changes the speed for every device on the bus.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), DeviceError> {
+        self.with_transport(|port| port.set_baud_rate(baud_rate))?;
+        self.settings.baud_rate = Some(baud_rate);
+        Ok(())
+    }
+
+    /// Returns the line speed last set with [Connection::set_baud_rate], [None] if it was never
+    /// changed through the connection
+    pub fn baud_rate(&self) -> Option<u32> {
+        self.settings.baud_rate
+    }
+
+    /// Swaps in a new transport, for example after a USB adapter was unplugged and plugged back
+    /// in, and returns the old one. The timeouts and other settings of the connection are kept
+    /// and the baud rate last set with [Connection::set_baud_rate] is applied to the new
+    /// transport. Bytes kept from the old transport are dropped. On a
+    /// [SharedBus](crate::bus::SharedBus) the transport of every device on the bus is replaced.
+    pub fn reconnect(&mut self, mut transport: T) -> Result<T, DeviceError> {
+        if let Some(baud_rate) = self.settings.baud_rate {
+            transport.set_baud_rate(baud_rate)?;
+        }
+        let mut line = self.lock().0;
+        line.receiver.clear();
+        Ok(std::mem::replace(&mut line.transport, transport))
+    }
+
+    /// Hands every request to the middleware before it is sent and every response to it once
+    /// received, see [FrameMiddleware]. Replaces the middleware set before.
+    pub fn set_middleware(&mut self, middleware: Box<dyn FrameMiddleware>) {
+        self.middleware = Some(middleware);
+    }
+
+    /// Removes the middleware and returns it, [None] if there is none
+    pub fn take_middleware(&mut self) -> Option<Box<dyn FrameMiddleware>> {
+        self.middleware.take()
+    }
+
+    /// Returns the counters of the commands sent since the connection was created or the
+    /// counters were reset
+    pub fn stats(&self) -> &CommStats {
+        &self.stats
+    }
+
+    /// Sets the counters of [Connection::stats] back to zero
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    /// Runs the closure with the underlying transport, locking the bus first if the transport
+    /// is shared. Reading from the transport directly can take bytes that belong to a response.
+    pub fn with_transport<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        match &mut self.port {
+            Port::Owned(line) => f(&mut line.transport),
+            Port::Shared(line) => f(&mut lock(line).transport),
+        }
+    }
+
+    /// Sends the frame to the device and waits for its response, retrying according to the
+    /// [RetryConfig]. A response with an error state is returned as [DeviceError::StateResponse].
+    /// When every attempt failed [DeviceError::RetriesExhausted] wraps the last error. A frame
+    /// marked [Idempotency::NonIdempotent] is only retried with
+    /// [RetryConfig::retry_non_idempotent].
+    pub fn transact<const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+    ) -> Result<MISOFrame, DeviceError> {
+        self.transact_and_read(frame, |response| Ok(response.into()))
+    }
+
+    /// Like [Connection::transact], but hands the response to `read` as a [MISOFrameRef] over
+    /// the buffer it was received into instead of copying it out. For reading values in a tight
+    /// loop, the frame can't outlive the call:
+    /// ```no_run
+    /// # use sfc_core::{connection::Connection, error::DeviceError, transport::Transport};
+    /// # fn run<T: Transport>(connection: &mut Connection<T>) -> Result<(), DeviceError> {
+    /// use sfc_core::shdlc::{MOSIFrame, TranslationError};
+    ///
+    /// let frame = MOSIFrame::new(0, 0x08, &[0x01])?;
+    /// let value = connection.transact_and_read(frame, |response| {
+    ///     let data = response.data();
+    ///     let bytes = data
+    ///         .try_into()
+    ///         .map_err(|_| TranslationError::NotEnoughData(4, data.len() as u8))?;
+    ///     Ok(f32::from_be_bytes(bytes))
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    /// An error returned by `read` is passed on as it is, it doesn't cause a retry. The frame
+    /// borrows the buffer of the connection, so it can't be returned from `read`:
+    /// ```compile_fail
+    /// # use sfc_core::{connection::Connection, error::DeviceError, transport::Transport};
+    /// # fn run<T: Transport>(connection: &mut Connection<T>) -> Result<(), DeviceError> {
+    /// # use sfc_core::shdlc::MOSIFrame;
+    /// let frame = MOSIFrame::new(0, 0x08, &[0x01])?;
+    /// let data = connection.transact_and_read(frame, |response| Ok(response.data()))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transact_and_read<R, const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+        read: impl FnOnce(MISOFrameRef<'_>) -> Result<R, DeviceError>,
+    ) -> Result<R, DeviceError> {
+        let retry = self.settings.retry;
+        self.run(frame, retry, None, read)
+    }
+
+    /// Like [Connection::transact], but gives up at the deadline for hosts where every operation
+    /// has a time budget. Each wait for the response is cut to the time that is left and a retry
+    /// that would start after the deadline is not made, both return
+    /// [DeviceError::DeadlineExceeded]. Resynchronizing between retries is skipped. Writing the
+    /// request and waiting for the lock of a [SharedBus](crate::bus::SharedBus) are not bounded.
+    pub fn transact_with_deadline<const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+        deadline: Instant,
+    ) -> Result<MISOFrame, DeviceError> {
+        self.transact_with_deadline_and_read(frame, deadline, |response| Ok(response.into()))
+    }
+
+    /// [Connection::transact_with_deadline] reading the response like
+    /// [Connection::transact_and_read]
+    pub fn transact_with_deadline_and_read<R, const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+        deadline: Instant,
+        read: impl FnOnce(MISOFrameRef<'_>) -> Result<R, DeviceError>,
+    ) -> Result<R, DeviceError> {
+        let retry = self.settings.retry;
+        self.run(frame, retry, Some(deadline), read)
+    }
+
+    /// Sends the frame to the device and waits for its response without ever retrying. Used
+    /// for commands that must not be executed twice, like changing the address or baudrate. A
+    /// device still booting after a reset didn't execute the command, it is sent again for the
+    /// [BootGrace] all the same.
+    pub fn transact_once<const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+    ) -> Result<MISOFrame, DeviceError> {
+        self.transact_once_and_read(frame, |response| Ok(response.into()))
+    }
+
+    /// [Connection::transact_once] reading the response like [Connection::transact_and_read]
+    pub fn transact_once_and_read<R, const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+        read: impl FnOnce(MISOFrameRef<'_>) -> Result<R, DeviceError>,
+    ) -> Result<R, DeviceError> {
+        self.run(frame, None, None, read)
+    }
+
+    /// Sends the frames and returns their responses in the same order. The requests are written
+    /// in batches of [Connection::pipeline_window], each batch before reading its responses.
+    /// Sending the next request while the device answers the previous one saves a round trip per
+    /// command on firmware that takes requests early, and the writes on those that don't.
+    ///
+    /// The responses have to arrive in turn, each echoing the address and command of the next
+    /// request. When one doesn't or fails in any other way, the responses still in flight are
+    /// waited for and discarded and the remaining frames, the failed one included, are sent one
+    /// at a time like [Connection::transact], retries included. The first error then fails the
+    /// whole pipeline. Commands may be executed twice this way, so only pipeline the ones that
+    /// read from the device: when a frame marked [Idempotency::NonIdempotent] may have been
+    /// executed already, the pipeline fails with the error instead of sending it again, unless
+    /// the [RetryConfig] opts into retrying such commands. The echo only names the command: on
+    /// firmware that drops requests while it answers, the response to a later request of the
+    /// same command can stand in for a dropped one. Keep the window at 1 for such firmware.
+    ///
+    /// Requests are always sent one at a time over RS-485 with manual direction control, the
+    /// line can't be driven while the device answers.
+    pub fn pipeline<const N: usize>(
+        &mut self,
+        frames: Vec<MOSIFrame<N>>,
+    ) -> Result<Vec<MISOFrame>, DeviceError> {
+        let (mut line, settings, stats, middleware) = self.lock();
+        for frame in &frames {
+            middleware::transmit(middleware, frame)?;
+        }
+        let responses = settings.pipeline(&mut line, frames, stats)?;
+        if let Some(middleware) = middleware {
+            responses.iter().for_each(|response| middleware.on_receive(response));
+        }
+        Ok(responses)
+    }
+
+    /// Gets the connection back in step with the device at the address after the stream got
+    /// garbled, for example by unplugging the cable in the middle of a frame. Discards all
+    /// pending input, sends a lone frame delimiter to end any frame the device is still
+    /// receiving, drops whatever arrives in the next moment and then probes the device with Get
+    /// Version. Returns once a probe is answered by a well formed frame, an error state
+    /// included, or the error of the last probe after a few attempts.
+    pub fn resync(&mut self, address: u8) -> Result<(), DeviceError> {
+        let (mut line, settings, stats, _) = self.lock();
+        settings.resync(&mut line, address, stats)
+    }
+
+    /// Sends the frame to the device and returns straight away. The response is collected by
+    /// polling the returned [PendingCommand], which borrows the connection until it is dropped.
+    /// On a [SharedBus](crate::bus::SharedBus) the bus stays locked for as long as the command is
+    /// pending. A pending command is never retried.
+    pub fn start<const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+    ) -> Result<PendingCommand<'_, T>, DeviceError> {
+        let address = frame.get_address();
+        let command = frame.get_command_number();
+        let kind = frame.kind();
+        let (mut line, settings, stats, middleware) = self.lock();
+        middleware::transmit(middleware, &frame)?;
+        let raw = frame.into_raw();
+
+        settings.prepare(&mut line)?;
+        let sent = settings.send(&mut line.transport, &raw)?;
+        Ok(PendingCommand {
+            line,
+            settings,
+            stats,
+            middleware,
+            address,
+            command,
+            kind,
+            sent,
+            last_byte: None,
+            finished: false,
+        })
+    }
+
+    fn run<R, const N: usize>(
+        &mut self,
+        frame: MOSIFrame<N>,
+        retry: Option<RetryConfig>,
+        until: Option<Instant>,
+        read: impl FnOnce(MISOFrameRef<'_>) -> Result<R, DeviceError>,
+    ) -> Result<R, DeviceError> {
+        // the whole exchange, retries included, happens under the lock of a shared bus so
+        // frames of different devices never interleave
+        let request = (
+            frame.get_address(),
+            frame.get_command_number(),
+            frame.kind(),
+            frame.name(),
+        );
+        let retry = retry.filter(|retry| retry.retries(frame.idempotency()));
+        let (mut line, settings, stats, middleware) = self.lock();
+        middleware::transmit(middleware, &frame)?;
+        let raw = frame.into_raw();
+        settings.run(&mut line, request, &raw, retry, until, stats)?;
+        let response = line.receiver.response()?;
+        if let Some(middleware) = middleware {
+            middleware.on_receive(&response.into());
+        }
+        read(response)
+    }
+
+    fn lock(
+        &mut self,
+    ) -> (PortGuard<'_, T>, &Settings, &mut CommStats, &mut Option<Box<dyn FrameMiddleware>>) {
+        let line = match &mut self.port {
+            Port::Owned(line) => PortGuard::Owned(line),
+            Port::Shared(line) => PortGuard::Shared(lock(line)),
+        };
+        (line, &self.settings, &mut self.stats, &mut self.middleware)
+    }
+}
+
+/// Formats the settings, the baud rate last set and the counters of the connection. It never
+/// touches the transport, nor locks the bus the connection may share, so formatting it doesn't
+/// block and the transport needn't be [Debug](std::fmt::Debug).
+impl<T: Transport> std::fmt::Debug for Connection<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stats = &self.stats;
+        f.debug_struct("Connection")
+            .field("shared", &matches!(self.port, Port::Shared(_)))
+            .field("settings", &self.settings)
+            .field("middleware", &self.middleware.is_some())
+            .field("commands", &stats.commands)
+            .field("retries", &stats.retries)
+            .field("failures", &stats.failures)
+            .field("timeouts", &stats.timeouts)
+            .field("round_trip", &stats.round_trip)
+            .finish()
+    }
+}
+
+/// A command that was sent by [Connection::start] and whose response has not been collected
+/// yet. Dropping it abandons the command, a late response is then discarded as stale input or
+/// skipped as the answer to another command by the next request.
+pub struct PendingCommand<'a, T: Transport> {
+    line: PortGuard<'a, T>,
+    settings: &'a Settings,
+    stats: &'a mut CommStats,
+    middleware: &'a mut Option<Box<dyn FrameMiddleware>>,
+    address: u8,
+    command: u8,
+    kind: CommandKind,
+    sent: Instant,
+    last_byte: Option<Instant>,
+    finished: bool,
+}
+
+impl<T: Transport> PendingCommand<'_, T> {
+    /// Reads whatever bytes are available without waiting and returns the response once it is
+    /// complete. The response and inter-byte timeouts of the connection still apply and are
+    /// checked on every poll. Polling again after the command finished returns
+    /// [DeviceError::AlreadyFinished].
+    pub fn poll(&mut self) -> Poll<Result<MISOFrame, DeviceError>> {
+        if self.finished {
+            return Poll::Ready(Err(DeviceError::AlreadyFinished));
+        }
+        let result = self.poll_response();
+        if let Poll::Ready(result) = &result {
+            self.finished = true;
+            let round_trip = self.sent.elapsed();
+            self.stats.record_attempt(self.address, result, Some(round_trip));
+            self.stats.record_command(self.address, result);
+            if let (Some(middleware), Ok(response)) = (self.middleware.as_mut(), result) {
+                middleware.on_receive(response);
+            }
+        }
+        result
+    }
+
+    fn poll_response(&mut self) -> Poll<Result<MISOFrame, DeviceError>> {
+        // a previous read may have returned more than one frame
+        loop {
+            match self.line.receiver.next_frame() {
+                Ok(true) => {
+                    if let Some(result) = self.accept() {
+                        return Poll::Ready(result);
+                    }
+                }
+                Ok(false) => break,
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+        }
+
+        let in_frame = self.line.receiver.in_frame();
+        let (since, limit) =
+            self.settings.deadline(self.kind, self.sent, self.last_byte, in_frame);
+        if since.elapsed() >= limit {
+            return Poll::Ready(Err(expired(in_frame)));
+        }
+        let line = &mut *self.line;
+        if let Err(e) = line.transport.set_timeout(Duration::ZERO) {
+            return Poll::Ready(Err(e));
+        }
+        match line.receiver.fill(&mut line.transport) {
+            Ok(0) => Poll::Pending,
+            Ok(_) => {
+                self.last_byte = Some(Instant::now());
+                match self.line.receiver.next_frame() {
+                    Ok(true) => self.accept().map_or(Poll::Pending, Poll::Ready),
+                    Ok(false) => Poll::Pending,
+                    Err(e) => Poll::Ready(Err(e.into())),
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                ) =>
+            {
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e.into())),
+        }
+    }
+
+    /// Checks the frame the receiver just completed, [None] if it answers another command
+    fn accept(&mut self) -> Option<Result<MISOFrame, DeviceError>> {
+        let response = match self.line.receiver.response() {
+            Ok(response) => response,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let accepted = self.settings.accept(response, self.address, self.command);
+        accepted.map(|frame| frame.map(MISOFrame::from)).transpose()
+    }
+}
+
+impl<T: Transport> std::fmt::Debug for PendingCommand<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingCommand")
+            .field("address", &self.address)
+            .field("command", &self.command)
+            .field("sent", &self.sent)
+            .finish()
+    }
+}
+
+impl Settings {
+    fn run<T: Transport>(
+        &self,
+        line: &mut Line<T>,
+        (address, command, kind, name): Request,
+        raw: &[u8],
+        retry: Option<RetryConfig>,
+        until: Option<Instant>,
+        stats: &mut CommStats,
+    ) -> Result<(), DeviceError> {
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        let start = Instant::now();
+        #[cfg(feature = "log")]
+        log::debug!("command {} to address {} started", Named(command, name), address);
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "shdlc_command",
+            command,
+            name,
+            address,
+            duration_us = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+
+        let request = (address, command, kind, name);
+        let result = self.exchange_while_waiting(line, request, raw, retry, until, stats);
+        stats.record_command(address, &result);
+
+        #[cfg(feature = "log")]
+        match &result {
+            Ok(_) => log::debug!(
+                "command {} to address {} finished in {:?}",
+                Named(command, name),
+                address,
+                start.elapsed()
+            ),
+            Err(e) => log::debug!(
+                "command {} to address {} failed after {:?}: {}",
+                Named(command, name),
+                address,
+                start.elapsed(),
+                e
+            ),
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("duration_us", start.elapsed().as_micros() as u64);
+            span.record("outcome", outcome(&result));
+            if let Err(e) = &result {
+                tracing::warn!(error = %e, "command failed");
+            }
+        }
+
+        result
+    }
+
+    fn pipeline<T: Transport, const N: usize>(
+        &self,
+        line: &mut Line<T>,
+        frames: Vec<MOSIFrame<N>>,
+        stats: &mut CommStats,
+    ) -> Result<Vec<MISOFrame>, DeviceError> {
+        let requests: Vec<_> = frames
+            .into_iter()
+            .map(|frame| {
+                let request = (
+                    frame.get_address(),
+                    frame.get_command_number(),
+                    frame.kind(),
+                    frame.name(),
+                );
+                (request, frame.idempotency(), frame.into_raw())
+            })
+            .collect();
+        // sending a command again after the pipeline failed is a retry of it
+        let retries = |idempotency| {
+            idempotency == Idempotency::Idempotent
+                || self.retry.is_some_and(|retry| retry.retry_non_idempotent)
+        };
+        let mut responses = Vec::with_capacity(requests.len());
+
+        if self.pipeline_window > 1
+            && self.rs485.is_none()
+            && let Err(e) = self.pipelined(line, &requests, &mut responses, stats)
+        {
+            // the answers to the requests still in flight would be taken for the next ones
+            self.drain(&mut line.transport)?;
+            line.receiver.clear();
+            // the rest of the batch was written, the device may have executed any of it
+            let failed = responses.len();
+            let written = (failed / self.pipeline_window + 1) * self.pipeline_window;
+            let sent = requests.get(failed..written.min(requests.len())).unwrap_or_default();
+            if sent.iter().any(|&(_, idempotency, _)| !retries(idempotency)) {
+                return Err(e);
+            }
+            #[cfg(feature = "log")]
+            log::warn!(
+                "sending the last {} pipelined commands one at a time: {}",
+                requests.len() - responses.len(),
+                e
+            );
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                remaining = requests.len() - responses.len(),
+                error = %e,
+                "falling back to one command at a time"
+            );
+        }
+
+        for &(request, idempotency, ref raw) in requests.iter().skip(responses.len()) {
+            let retry = self.retry.filter(|retry| retry.retries(idempotency));
+            self.run(line, request, raw, retry, None, stats)?;
+            responses.push(MISOFrame::from(line.receiver.response()?));
+        }
+        Ok(responses)
+    }
+
+    /// Writes the requests in batches of the pipeline window ahead of their responses and
+    /// collects the responses in turn. Stops at the first one that fails or doesn't answer the
+    /// next request.
+    fn pipelined<T: Transport, const N: usize>(
+        &self,
+        line: &mut Line<T>,
+        requests: &[(Request, Idempotency, ArrayVec<u8, N>)],
+        responses: &mut Vec<MISOFrame>,
+        stats: &mut CommStats,
+    ) -> Result<(), DeviceError> {
+        self.prepare(line)?;
+        let mut answered = Instant::now();
+        // a whole batch is answered before the next is written, a request the device dropped
+        // then ends in a timeout and the response to a later one can't be taken for it
+        for batch in requests.chunks(self.pipeline_window) {
+            let mut written = Vec::with_capacity(batch.len());
+            for (_, _, raw) in batch {
+                written.push(self.send(&mut line.transport, raw)?);
+            }
+            for (&((address, command, kind, _), _, _), sent) in batch.iter().zip(written) {
+                // the device answers in turn, it only starts on this request after the previous
+                let result = self
+                    .receive_frame(line, kind, sent.max(answered), None)
+                    .and_then(|()| {
+                        let response = line.receiver.response()?;
+                        let echo = (response.get_address(), response.get_command_number());
+                        match self.accept(response, address, command)? {
+                            Some(response) => Ok(MISOFrame::from(response)),
+                            None => Err(DeviceError::UnexpectedResponse(echo.0, echo.1)),
+                        }
+                    });
+                stats.record_attempt(address, &result, Some(sent.elapsed()));
+                let response = result?;
+                stats.record_command(address, &Ok::<_, DeviceError>(()));
+                answered = Instant::now();
+                responses.push(response);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the request like [Settings::exchange_with_retry] and sends it again while the
+    /// device answers that it is still booting, see [BootGrace], or busy, see [BusyPolicy]
+    fn exchange_while_waiting<T: Transport>(
+        &self,
+        line: &mut Line<T>,
+        request: Request,
+        raw: &[u8],
+        retry: Option<RetryConfig>,
+        until: Option<Instant>,
+        stats: &mut CommStats,
+    ) -> Result<(), DeviceError> {
+        let (address, ..) = request;
+        let mut boot_attempts = 0;
+        let mut busy_since = None;
+        loop {
+            let result = match retry {
+                Some(retry) => self.exchange_with_retry(line, request, raw, retry, until, stats),
+                None => self.exchange(line, request, raw, until, stats),
+            };
+            let Err(e) = &result else {
+                return result;
+            };
+            let booting = match (self.boot_grace, self.last_reset) {
+                (Some(grace), Some(reset)) if grace.is_booting(e, reset.elapsed()) => Some(grace),
+                _ => None,
+            };
+            let (wait, reason) = match (booting, self.busy_retry) {
+                (Some(grace), _) => {
+                    boot_attempts += 1;
+                    (grace.backoff(boot_attempts), "still booting")
+                }
+                (None, Some(policy)) if is_busy(e) => {
+                    let waited = busy_since.get_or_insert_with(Instant::now).elapsed();
+                    if waited >= policy.max_wait {
+                        return Err(DeviceError::StillBusy(waited));
+                    }
+                    (policy.poll_interval.min(policy.max_wait - waited), "busy")
+                }
+                _ => return result,
+            };
+            if until.is_some_and(|until| Instant::now() + wait >= until) {
+                return Err(DeviceError::DeadlineExceeded);
+            }
+            #[cfg(feature = "log")]
+            log::info!(
+                "command {:#04x} to address {} sent again, the device is {}",
+                request.1,
+                address,
+                reason
+            );
+            #[cfg(feature = "tracing")]
+            tracing::info!(reason, "sending the command again");
+            #[cfg(not(any(feature = "log", feature = "tracing")))]
+            let _ = reason;
+            stats.record_retry(address);
+            thread::sleep(wait);
+        }
+    }
+
+    fn exchange_with_retry<T: Transport>(
+        &self,
+        line: &mut Line<T>,
+        request: Request,
+        raw: &[u8],
+        retry: RetryConfig,
+        until: Option<Instant>,
+        stats: &mut CommStats,
+    ) -> Result<(), DeviceError> {
+        let (address, ..) = request;
+        let mut attempts = 0;
+        let mut framing_errors = 0;
+        loop {
+            attempts += 1;
+            match self.exchange(line, request, raw, until, stats) {
+                Err(e) if retry.should_retry(&e) => {
+                    if attempts >= retry.max_attempts {
+                        return Err(DeviceError::RetriesExhausted(attempts, Box::new(e)));
+                    }
+                    // a retry that can't even start before the deadline isn't made
+                    if until.is_some_and(|until| Instant::now() + retry.delay >= until) {
+                        return Err(DeviceError::DeadlineExceeded);
+                    }
+                    framing_errors = if is_framing_error(&e) { framing_errors + 1 } else { 0 };
+                    #[cfg(feature = "log")]
+                    log::warn!(
+                        "retrying command {:#04x} to address {} after attempt {}: {}",
+                        request.1,
+                        address,
+                        attempts,
+                        e
+                    );
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempt = attempts, error = %e, "retrying command");
+                    stats.record_retry(address);
+                    thread::sleep(retry.delay);
+                    // a resync takes up to a response timeout per probe, more than a budget has
+                    let resync = retry.resync_after.is_some_and(|after| framing_errors >= after);
+                    if resync && until.is_none() {
+                        framing_errors = 0;
+                        // the retry tells whether it helped, its own error adds nothing
+                        let _ = self.resync(line, address, stats);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn exchange<T: Transport>(
+        &self,
+        line: &mut Line<T>,
+        request: Request,
+        raw: &[u8],
+        until: Option<Instant>,
+        stats: &mut CommStats,
+    ) -> Result<(), DeviceError> {
+        let (address, ..) = request;
+        if until.is_some_and(|until| Instant::now() >= until) {
+            return Err(DeviceError::DeadlineExceeded);
+        }
+        self.prepare(line)?;
+        let sent = self.send(&mut line.transport, raw)?;
+
+        let result = self.receive_response(line, request, sent, until);
+        stats.record_attempt(address, &result, Some(sent.elapsed()));
+        result
+    }
+
+    fn receive_response<T: Transport>(
+        &self,
+        line: &mut Line<T>,
+        (address, command, kind, _): Request,
+        sent: Instant,
+        until: Option<Instant>,
+    ) -> Result<(), DeviceError> {
+        loop {
+            self.receive_frame(line, kind, sent, until)?;
+            if self.accept(line.receiver.response()?, address, command)?.is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn resync<T: Transport>(
+        &self,
+        line: &mut Line<T>,
+        address: u8,
+        stats: &mut CommStats,
+    ) -> Result<(), DeviceError> {
+        #[cfg(feature = "log")]
+        log::warn!("resynchronizing with address {}", address);
+        #[cfg(feature = "tracing")]
+        tracing::warn!(address, "resynchronizing");
+
+        line.receiver.clear();
+        line.transport.clear_input()?;
+        // a lone delimiter is a legal no-op that ends any frame the device is in the middle of
+        self.send(&mut line.transport, &[START_STOP])?;
+        self.drain(&mut line.transport)?;
+
+        let probe = MOSIFrame::new(address, PROBE_COMMAND, &[])?.into_raw();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let request = (address, PROBE_COMMAND, CommandKind::Query, None);
+            match self.exchange(line, request, &probe, None, stats) {
+                Ok(_) | Err(DeviceError::StateResponse(_)) => return Ok(()),
+                Err(e) if attempts >= PROBE_ATTEMPTS => return Err(e),
+                Err(_) => line.receiver.clear(),
+            }
+        }
+    }
+
+    /// Reads and discards bytes until the line stays quiet for the inter-byte timeout, for at
+    /// most the response timeout of a query
+    fn drain<T: Transport>(&self, port: &mut T) -> Result<(), DeviceError> {
+        let start = Instant::now();
+        let mut buf = [0_u8; 64];
+        port.set_timeout(self.inter_byte_timeout)?;
+        while start.elapsed() < self.timeouts.query {
+            match port.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the line ready for a new request. A frame that a failed or abandoned command left
+    /// half received is dropped, in strict mode the bytes kept from earlier reads are as well.
+    fn prepare<T: Transport>(&self, line: &mut Line<T>) -> Result<(), DeviceError> {
+        if self.clear_stale_input {
+            line.receiver.clear();
+            line.transport.clear_input()?;
+        } else {
+            line.receiver.drop_partial_frame();
+        }
+        Ok(())
+    }
+
+    /// Writes the request, switching the direction of an RS-485 line around it. Returns when the
+    /// wait for the response starts.
+    fn send<T: Transport>(&self, port: &mut T, raw: &[u8]) -> Result<Instant, DeviceError> {
+        #[cfg(feature = "log")]
+        log::trace!("sent {}", Hex(raw));
+        let Some(rs485) = self.rs485 else {
+            write_all(port, raw)?;
+            return Ok(Instant::now());
+        };
+
+        if rs485.rts_on_send {
+            port.set_request_to_send(true)?;
+        }
+        // the line has to stay driven until the last byte is out, not just queued
+        let written = write_all(port, raw).and_then(|_| Ok(port.flush()?));
+        if rs485.rts_on_send {
+            port.set_request_to_send(false)?;
+        }
+        written?;
+        thread::sleep(rs485.turnaround);
+        Ok(Instant::now())
+    }
+
+    /// Returns when the current wait started and how long it may take
+    pub(crate) fn deadline(
+        &self,
+        kind: CommandKind,
+        sent: Instant,
+        last_byte: Option<Instant>,
+        in_frame: bool,
+    ) -> (Instant, Duration) {
+        match last_byte {
+            Some(at) if in_frame => (at, self.inter_byte_timeout),
+            _ => (sent, self.timeouts.for_kind(kind)),
+        }
+    }
+
+    /// Reads the bytes of one frame, from start to end delimiter, into the receiver of the line.
+    /// A wait that would last past `until` is cut short and returns
+    /// [DeviceError::DeadlineExceeded].
+    fn receive_frame<T: Transport>(
+        &self,
+        line: &mut Line<T>,
+        kind: CommandKind,
+        sent: Instant,
+        until: Option<Instant>,
+    ) -> Result<(), DeviceError> {
+        let Line { transport: port, receiver } = line;
+        let mut last_byte: Option<Instant> = None;
+        let mut empty_reads = 0;
+
+        loop {
+            if receiver.next_frame()? {
+                return Ok(());
+            }
+
+            // noise outside of a frame doesn't count as the response starting
+            let in_frame = receiver.in_frame();
+            let (since, limit) = self.deadline(kind, sent, last_byte, in_frame);
+            let remaining = match limit.checked_sub(since.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return Err(expired(in_frame)),
+            };
+            let left = until.map(|until| until.saturating_duration_since(Instant::now()));
+            if left.is_some_and(|left| left.is_zero()) {
+                return Err(DeviceError::DeadlineExceeded);
+            }
+            let capped = left.is_some_and(|left| left < remaining);
+            let wait = left.map_or(remaining, |left| left.min(remaining));
+            port.set_timeout(wait)?;
+
+            match receiver.fill(port) {
+                // a transport that returns nothing instead of waiting is asked again at once only
+                // as often as the spin policy allows
+                Ok(0) if empty_reads < self.spin.spins => {
+                    empty_reads += 1;
+                    thread::yield_now();
+                }
+                Ok(0) => {
+                    let sleep = self.spin.sleep.unwrap_or(self.inter_byte_timeout);
+                    thread::sleep(wait.min(sleep));
+                }
+                Ok(_) => {
+                    last_byte = Some(Instant::now());
+                    empty_reads = 0;
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    if capped {
+                        return Err(DeviceError::DeadlineExceeded);
+                    }
+                    return Err(expired(in_frame));
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Writes every byte of the frame, a transport may take only part of it per call. Gives up when
+/// the transport stops taking bytes, a frame cut short would only cause a timeout later.
+fn write_all<T: Transport>(port: &mut T, raw: &[u8]) -> Result<(), DeviceError> {
+    let mut written = 0;
+    let mut stalled = 0;
+    while written < raw.len() {
+        match port.write(raw.get(written..).unwrap_or_default()) {
+            Ok(0) => stalled += 1,
+            Ok(count) => {
+                written += count;
+                stalled = 0;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => stalled += 1,
+            Err(e) => return Err(e.into()),
+        }
+
+        if stalled > STALLED_WRITES {
+            return Err(DeviceError::IoError(std::io::Error::new(
+                ErrorKind::WriteZero,
+                format!("the transport took only {} of {} bytes", written, raw.len()),
+            )));
+        }
+        if stalled > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{Read, Write};
+    use std::thread;
+
+    use super::*;
+    use crate::error::StateResponseError;
+    use crate::middleware::CommandBlacklist;
+    use crate::shdlc::{
+        MAX_PAYLOAD, SmallFrame, TranslationError, calculate_check_sum, to_shdlc,
+    };
+
+    /// Answers every written request with the next script of chunks, each chunk arriving after
+    /// a delay measured from the previous read. The scripts of requests written before the
+    /// last one was answered are queued. Stale bytes are readable before the first request.
+    struct ScriptedPort {
+        stale: Vec<u8>,
+        scripts: VecDeque<Vec<(u64, Vec<u8>)>>,
+        chunks: VecDeque<(Duration, Vec<u8>)>,
+        timeout: Duration,
+        writes: usize,
+        reads: usize,
+        // every write, flush and RTS change, and reads once a frame was written
+        events: Vec<&'static str>,
+        written: Vec<Vec<u8>>,
+        // a frame is only complete once its closing delimiter was written
+        partial: Vec<u8>,
+        half_writes: bool,
+        zero_writes: usize,
+        // reads return nothing instead of waiting out the timeout
+        empty_reads: bool,
+    }
+
+    impl ScriptedPort {
+        fn new(scripts: Vec<Vec<(u64, Vec<u8>)>>) -> Self {
+            Self {
+                stale: Vec::new(),
+                scripts: scripts.into(),
+                chunks: VecDeque::new(),
+                timeout: Duration::ZERO,
+                writes: 0,
+                reads: 0,
+                events: Vec::new(),
+                written: Vec::new(),
+                partial: Vec::new(),
+                half_writes: false,
+                zero_writes: 0,
+                empty_reads: false,
+            }
+        }
+
+        fn write_calls(&self) -> usize {
+            self.events.iter().filter(|&&event| event == "write").count()
+        }
+
+        fn timed_out(&self) -> std::io::Error {
+            thread::sleep(self.timeout);
+            std::io::Error::new(ErrorKind::TimedOut, "no data")
+        }
+    }
+
+    impl Read for ScriptedPort {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if !self.stale.is_empty() {
+                let stale = std::mem::take(&mut self.stale);
+                buf[..stale.len()].copy_from_slice(&stale);
+                return Ok(stale.len());
+            }
+            if self.writes > 0 {
+                self.reads += 1;
+                self.events.push("read");
+            }
+
+            let timeout = self.timeout;
+            let Some((delay, _)) = self.chunks.front_mut() else {
+                if self.empty_reads {
+                    return Ok(0);
+                }
+                return Err(self.timed_out());
+            };
+            if *delay > timeout {
+                *delay -= timeout;
+                return Err(self.timed_out());
+            }
+            thread::sleep(*delay);
+            let (_, bytes) = self.chunks.pop_front().unwrap();
+            assert!(bytes.len() <= buf.len());
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            Ok(bytes.len())
+        }
+    }
+
+    impl Write for ScriptedPort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.events.push("write");
+            if self.zero_writes > 0 {
+                self.zero_writes -= 1;
+                return Ok(0);
+            }
+            let count = if self.half_writes { buf.len().div_ceil(2) } else { buf.len() };
+            self.partial.extend_from_slice(&buf[..count]);
+            if self.partial.last() != Some(&START_STOP) {
+                return Ok(count);
+            }
+
+            self.writes += 1;
+            self.written.push(std::mem::take(&mut self.partial));
+            let script = self.scripts.pop_front().unwrap_or_default();
+            self.chunks
+                .extend(script.into_iter().map(|(ms, bytes)| (Duration::from_millis(ms), bytes)));
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.events.push("flush");
+            Ok(())
+        }
+    }
+
+    impl Transport for ScriptedPort {
+        fn timeout(&self) -> Duration {
+            self.timeout
+        }
+
+        fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+            self.timeout = timeout;
+            Ok(())
+        }
+
+        fn set_request_to_send(&mut self, level: bool) -> Result<(), DeviceError> {
+            self.events.push(if level { "rts on" } else { "rts off" });
+            Ok(())
+        }
+    }
+
+    fn response_to(command: u8, data: &[u8]) -> Vec<u8> {
+        let mut raw = vec![0x00, command, 0x00, data.len() as u8];
+        raw.extend_from_slice(data);
+        to_shdlc(&raw).unwrap().to_vec()
+    }
+
+    fn response(data: &[u8]) -> Vec<u8> {
+        response_to(0x01, data)
+    }
+
+    fn corrupted(data: &[u8]) -> Vec<u8> {
+        let mut frame = response(data);
+        // the first data byte, none of the test data needs escaping
+        frame[5] ^= 0x01;
+        frame
+    }
+
+    fn connection(chunks: Vec<(u64, Vec<u8>)>) -> Connection<ScriptedPort> {
+        connection_with(vec![chunks])
+    }
+
+    fn connection_with(scripts: Vec<Vec<(u64, Vec<u8>)>>) -> Connection<ScriptedPort> {
+        let mut connection = Connection::new(ScriptedPort::new(scripts));
+        connection.set_response_timeout(Duration::from_millis(100));
+        connection.set_inter_byte_timeout(Duration::from_millis(30));
+        connection
+    }
+
+    fn request() -> MOSIFrame {
+        MOSIFrame::new(0, 0x01, &[]).unwrap()
+    }
+
+    #[test]
+    fn slow_but_complete_response() {
+        let frame = response(&[1, 2, 3, 4]);
+        let (head, tail) = frame.split_at(3);
+        // the first byte may take most of the response timeout, the rest just needs to keep up
+        let mut connection = connection(vec![(70, head.to_vec()), (20, tail.to_vec())]);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn round_trips_are_counted_in_the_latency_histogram() {
+        let delays = [0, 5, 5, 20, 70];
+        let mut connection = connection_with(
+            delays.iter().map(|&delay| vec![(delay, response(&[1]))]).collect(),
+        );
+        for _ in delays {
+            connection.transact(request()).unwrap();
+        }
+        let histogram = connection.stats().latency_histogram();
+        // below 1ms, 4-8ms, 16-32ms and 64-128ms
+        assert_eq!(histogram.counts()[..8], [1, 0, 0, 2, 0, 1, 0, 1]);
+        assert_eq!(histogram.total(), 5);
+
+        connection.reset_stats();
+        assert_eq!(connection.stats().latency_histogram().total(), 0);
+    }
+
+    #[test]
+    fn no_response_is_a_timeout() {
+        let mut connection = connection(vec![]);
+        let start = Instant::now();
+        assert!(matches!(connection.transact(request()), Err(DeviceError::Timeout)));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn stalled_response_is_an_incomplete_frame() {
+        let frame = response(&[1, 2, 3, 4]);
+        let (head, tail) = frame.split_at(3);
+        let mut connection = connection(vec![(5, head.to_vec()), (60, tail.to_vec())]);
+        let start = Instant::now();
+        assert!(matches!(connection.transact(request()), Err(DeviceError::IncompleteFrame)));
+        // gave up after the inter-byte timeout, not the response timeout
+        assert!(start.elapsed() < Duration::from_millis(80));
+    }
+
+    #[test]
+    fn trickling_noise_does_not_extend_the_deadline() {
+        let noise = (0..40).map(|_| (10, vec![0x55])).collect();
+        let mut connection = connection(noise);
+        let start = Instant::now();
+        assert!(matches!(connection.transact(request()), Err(DeviceError::Timeout)));
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+
+    #[test]
+    fn a_long_response_is_read_at_once() {
+        let data = [0x20; 200];
+        let mut connection = connection(vec![(0, response(&data))]);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &data);
+        assert_eq!(connection.with_transport(|p| p.reads), 1);
+    }
+
+    #[test]
+    fn pipelined_requests_are_written_ahead_of_their_responses() {
+        let scripts = (1..=4).map(|command| vec![(0, response_to(command, &[command]))]);
+        let mut connection = connection_with(scripts.collect());
+        connection.set_pipeline_window(3);
+        let frames = (1..=4).map(|command| MOSIFrame::new(0, command, &[]).unwrap());
+        let responses = connection.pipeline(frames.collect()).unwrap();
+
+        let data: Vec<_> = responses.into_iter().map(|r| r.into_data()[0]).collect();
+        assert_eq!(data, [1, 2, 3, 4]);
+        let events = connection.with_transport(|p| p.events.clone());
+        assert_eq!(events[..4], ["write", "write", "write", "read"]);
+        assert_eq!(events.iter().filter(|&&event| event == "write").count(), 4);
+        assert_eq!(connection.stats().commands, 4);
+    }
+
+    #[test]
+    fn pipeline_falls_back_to_one_request_at_a_time() {
+        let answer = |command| vec![(0, response_to(command, &[command]))];
+        // the second request is answered out of turn while the third is in flight
+        let mut scripts = vec![answer(1), answer(9), answer(3)];
+        scripts.extend((2..=4).map(answer));
+        let mut connection = connection_with(scripts);
+        connection.set_pipeline_window(3);
+        let frames = (1..=4).map(|command| MOSIFrame::new(0, command, &[]).unwrap());
+        let responses = connection.pipeline(frames.collect()).unwrap();
+
+        let data: Vec<_> = responses.into_iter().map(|r| r.into_data()[0]).collect();
+        assert_eq!(data, [1, 2, 3, 4]);
+        let written = connection.with_transport(|p| p.written.clone());
+        let commands: Vec<_> = written.iter().map(|frame| frame[2]).collect();
+        assert_eq!(commands, [1, 2, 3, 2, 3, 4]);
+        assert_eq!(connection.stats().commands, 4);
+    }
+
+    #[test]
+    fn pipeline_window_of_one_is_lock_step() {
+        let scripts = (1..=3).map(|command| vec![(0, response_to(command, &[command]))]);
+        let mut lock_step = connection_with(scripts.collect());
+        let frames = (1..=3).map(|command| MOSIFrame::new(0, command, &[]).unwrap());
+        assert_eq!(lock_step.pipeline(frames.collect()).unwrap().len(), 3);
+        // every request is answered before the next is written
+        let events = lock_step.with_transport(|p| p.events.clone());
+        assert_eq!(events.iter().filter(|&&event| event == "write").count(), 3);
+        assert!(events.windows(2).all(|pair| pair[0] != "write" || pair[1] == "read"));
+
+        // an error fails the pipeline
+        let mut silent = connection(vec![]);
+        silent.set_pipeline_window(0);
+        assert_eq!(silent.pipeline_window(), 1);
+        assert!(matches!(silent.pipeline(vec![request()]), Err(DeviceError::Timeout)));
+    }
+
+    #[test]
+    fn a_full_response_of_escaped_bytes() {
+        let data = [START_STOP; MAX_PAYLOAD];
+        let frame = response(&data);
+        assert!(frame.len() > 2 * MAX_PAYLOAD);
+        let chunks = |size| frame.chunks(size).map(|chunk| (0, chunk.to_vec())).collect();
+        // the second response as fast as the connection reads it
+        let mut connection = connection_with(vec![chunks(200), chunks(256)]);
+        let read = connection.transact_and_read(request(), |response| {
+            assert_eq!(response.data(), &data);
+            Ok(response.data().len())
+        });
+        assert_eq!(read.unwrap(), MAX_PAYLOAD);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &data);
+    }
+
+    #[test]
+    fn empty_reads_wait_before_reading_again() {
+        let mut connection = connection(vec![]);
+        connection.with_transport(|p| p.empty_reads = true);
+        let start = Instant::now();
+        assert!(matches!(connection.transact_once(request()), Err(DeviceError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        // one read per inter-byte timeout
+        assert!(connection.with_transport(|p| p.reads) <= 5);
+    }
+
+    #[test]
+    fn empty_reads_spin_before_sleeping() {
+        let mut connection = connection(vec![]);
+        connection.with_transport(|p| p.empty_reads = true);
+        connection.set_spin_policy(SpinPolicy {
+            spins: 20,
+            sleep: Some(Duration::from_millis(10)),
+        });
+        let start = Instant::now();
+        assert!(matches!(connection.transact_once(request()), Err(DeviceError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        // the spins, then one read per sleep
+        let reads = connection.with_transport(|p| p.reads);
+        assert!((21..=32).contains(&reads), "{} reads", reads);
+    }
+
+    #[test]
+    fn noise_before_the_frame_is_discarded() {
+        let mut chunk = vec![0x55, 0xAA];
+        chunk.extend(response(&[9]));
+        let mut connection = connection(vec![(0, chunk)]);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[9]);
+        assert_eq!(connection.with_transport(|p| p.reads), 1);
+    }
+
+    #[test]
+    fn closing_delimiter_in_its_own_read() {
+        let frame = response(&[1, 2]);
+        let (head, tail) = frame.split_at(frame.len() - 1);
+        let mut connection = connection(vec![(0, head.to_vec()), (5, tail.to_vec())]);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1, 2]);
+    }
+
+    #[test]
+    fn opening_delimiter_in_its_own_read() {
+        let frame = response(&[1, 2]);
+        let (head, tail) = frame.split_at(1);
+        let mut connection = connection(vec![(0, head.to_vec()), (5, tail.to_vec())]);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1, 2]);
+    }
+
+    #[test]
+    fn one_byte_per_read() {
+        let frame = response(&[0x7E, 0x7D, 0x11, 0x13]);
+        let chunks = frame.iter().map(|&b| (0, vec![b])).collect();
+        let mut connection = connection(chunks);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[0x7E, 0x7D, 0x11, 0x13]);
+    }
+
+    #[test]
+    fn split_at_every_boundary() {
+        let frame = response(&[1, 0x7E, 3]);
+        for split in 1..frame.len() {
+            let (head, tail) = frame.split_at(split);
+            let mut connection = connection(vec![(0, head.to_vec()), (1, tail.to_vec())]);
+            let res = connection.transact(request()).unwrap();
+            assert_eq!(res.into_data().as_slice(), &[1, 0x7E, 3], "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn a_frame_cut_short_does_not_hide_the_response() {
+        let frame = response(&[1, 2, 3, 4]);
+        for cut in 1..frame.len() - 1 {
+            let mut bytes = frame[..cut].to_vec();
+            bytes.extend_from_slice(&response(&[5]));
+            let mut connection = connection(vec![(0, bytes)]);
+            let res = connection.transact(request()).unwrap();
+            assert_eq!(res.into_data().as_slice(), &[5], "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn two_frames_in_one_read() {
+        // the first one answers another command and is skipped, the second is in the same read
+        let mut chunk = response_to(0x02, &[1]);
+        chunk.extend(response(&[2]));
+        let mut connection = connection(vec![(0, chunk)]);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[2]);
+        assert_eq!(connection.with_transport(|p| p.reads), 1);
+    }
+
+    #[test]
+    fn frame_followed_by_the_start_of_another() {
+        let mut chunk = response(&[1]);
+        chunk.extend(&response(&[2])[..3]);
+        let mut connection = connection(vec![(0, chunk)]);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1]);
+    }
+
+    #[test]
+    fn reconnect_drops_bytes_of_the_old_transport() {
+        let mut chunk = response(&[1]);
+        chunk.extend(response(&[2]));
+        let mut connection = connection(vec![(0, chunk)]);
+        connection.set_clear_stale_input(false);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[1]);
+
+        let old = connection.reconnect(ScriptedPort::new(vec![vec![(0, response(&[3]))]])).unwrap();
+        assert_eq!(old.writes, 1);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[3]);
+        assert_eq!(connection.response_timeout(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn stale_input_is_cleared() {
+        let mut connection = connection(vec![(5, response(&[2]))]);
+        // the late answer to an earlier request of the same command
+        connection.with_transport(|p| p.stale = response(&[1]));
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[2]);
+    }
+
+    #[test]
+    fn response_to_another_command_is_skipped() {
+        let mut connection = connection(vec![(0, response_to(0x02, &[1])), (5, response(&[2]))]);
+        connection.set_clear_stale_input(false);
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[2]);
+    }
+
+    #[test]
+    fn pipelined_response_is_kept_for_the_next_request() {
+        // the second response arrives in the same read as the first one
+        let mut chunk = response(&[1]);
+        chunk.extend(response(&[2]));
+        let mut connection = connection_with(vec![vec![(0, chunk)], vec![]]);
+        connection.set_clear_stale_input(false);
+        let first = connection.transact(request()).unwrap();
+        assert_eq!(first.into_data().as_slice(), &[1]);
+        let second = connection.transact(request()).unwrap();
+        assert_eq!(second.into_data().as_slice(), &[2]);
+        assert_eq!(connection.with_transport(|p| (p.writes, p.reads)), (2, 1));
+    }
+
+    #[test]
+    fn pipelined_response_is_completed_by_the_next_read() {
+        let mut chunk = response(&[1]);
+        let second = response(&[2]);
+        chunk.extend(&second[..3]);
+        let script = vec![vec![(0, chunk)], vec![(0, second[3..].to_vec())]];
+        let mut connection = connection_with(script);
+        connection.set_clear_stale_input(false);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[1]);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[2]);
+        assert_eq!(connection.with_transport(|p| p.reads), 2);
+    }
+
+    #[test]
+    fn strict_mode_drops_the_extra_bytes() {
+        let mut chunk = response(&[1]);
+        chunk.extend(response(&[2]));
+        let mut connection = connection_with(vec![vec![(0, chunk)], vec![(0, response(&[3]))]]);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[1]);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[3]);
+    }
+
+    #[test]
+    fn half_received_frame_is_dropped_before_the_next_request() {
+        let frame = response(&[1]);
+        let script = vec![vec![(0, frame[..4].to_vec())], vec![(0, response(&[2]))]];
+        let mut connection = connection_with(script);
+        connection.set_clear_stale_input(false);
+        assert!(matches!(connection.transact(request()), Err(DeviceError::IncompleteFrame)));
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[2]);
+    }
+
+    /// Slightly malformed responses, each followed by a good one: a corrupted checksum, a byte
+    /// of padding after the data, an answer from another address and an error state with a
+    /// corrupted checksum
+    fn malformed() -> [Vec<(u64, Vec<u8>)>; 4] {
+        let checksum = calculate_check_sum(&[0x00, 0x01, 0x00, 0x01, 7]);
+        let padded = vec![START_STOP, 0x00, 0x01, 0x00, 0x01, 7, 0xAA, checksum, START_STOP];
+        let other_address = to_shdlc(&[0x01, 0x01, 0x00, 0x01, 7]).unwrap().to_vec();
+        let mut error_state = to_shdlc(&[0x00, 0x01, 0x04, 0x00]).unwrap().to_vec();
+        error_state[5] ^= 0x01;
+        [corrupted(&[1]), padded, other_address, error_state]
+            .map(|frame| vec![(0, frame), (5, response(&[2]))])
+    }
+
+    /// The result of each malformed response at the level
+    fn validated(level: ValidationLevel) -> Vec<Result<Vec<u8>, DeviceError>> {
+        malformed()
+            .into_iter()
+            .map(|chunks| {
+                let mut connection = connection(chunks);
+                connection.set_validation_level(level);
+                let frame = connection.transact(request())?;
+                Ok(frame.into_data().to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn strict_validation_rejects_every_malformed_response() {
+        let results = validated(ValidationLevel::Strict);
+        assert!(matches!(results[0], Err(DeviceError::InvalidChecksum(_, _))));
+        assert!(matches!(
+            results[1],
+            Err(DeviceError::ShdlcError(TranslationError::TrailingData(1)))
+        ));
+        assert!(matches!(results[2], Err(DeviceError::UnexpectedResponse(1, 0x01))));
+        assert!(matches!(results[3], Err(DeviceError::InvalidChecksum(_, _))));
+    }
+
+    #[test]
+    fn standard_validation_rejects_corrupted_responses() {
+        let results = validated(ValidationLevel::Standard);
+        assert!(matches!(results[0], Err(DeviceError::InvalidChecksum(_, _))));
+        assert_eq!(results[1].as_ref().unwrap(), &[7]);
+        assert_eq!(results[2].as_ref().unwrap(), &[2]);
+        // the checksum is checked before the state
+        assert!(matches!(results[3], Err(DeviceError::InvalidChecksum(_, _))));
+        let connection = Connection::new(ScriptedPort::new(vec![]));
+        assert_eq!(connection.validation_level(), ValidationLevel::Standard);
+    }
+
+    #[test]
+    fn lenient_validation_only_fails_on_error_states() {
+        let results = validated(ValidationLevel::Lenient);
+        // the corrupted data byte is returned as it was received
+        assert_eq!(results[0].as_ref().unwrap(), &[0]);
+        assert_eq!(results[1].as_ref().unwrap(), &[7]);
+        assert_eq!(results[2].as_ref().unwrap(), &[2]);
+        assert!(matches!(
+            results[3],
+            Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+        ));
+    }
+
+    fn garbled(data: &[u8]) -> Vec<u8> {
+        let mut frame = response(data);
+        // an escape followed by a byte that is never escaped
+        frame.insert(3, 0x7D);
+        frame
+    }
+
+    fn version() -> Vec<u8> {
+        response_to(0xD1, &[1, 2, 0, 3, 0, 1, 0])
+    }
+
+    #[test]
+    fn resync_sends_a_lone_delimiter_and_probes() {
+        let frame = response(&[1, 2, 3]);
+        // the rest of a frame the device was sending arrives after the delimiter
+        let script = vec![vec![(0, frame[4..].to_vec())], vec![(0, version())]];
+        let mut connection = connection_with(script);
+        connection.resync(0).unwrap();
+        let written = connection.with_transport(|p| p.written.clone());
+        assert_eq!(written[0], [START_STOP]);
+        assert_eq!(written[1], MOSIFrame::new(0, 0xD1, &[]).unwrap().into_raw().as_slice());
+        assert_eq!(written.len(), 2);
+    }
+
+    #[test]
+    fn resync_accepts_an_error_state() {
+        let unknown = to_shdlc(&[0x00, 0xD1, 0x02, 0x00]).unwrap().to_vec();
+        let mut connection = connection_with(vec![vec![], vec![(0, unknown)]]);
+        connection.resync(0).unwrap();
+    }
+
+    #[test]
+    fn resync_gives_up() {
+        let mut connection = connection_with(vec![]);
+        connection.set_response_timeout(Duration::from_millis(10));
+        assert!(matches!(connection.resync(0), Err(DeviceError::Timeout)));
+        // the delimiter and every probe
+        assert_eq!(connection.with_transport(|p| p.writes), 1 + PROBE_ATTEMPTS as usize);
+    }
+
+    #[test]
+    fn retry_resyncs_after_framing_errors() {
+        let mut connection = connection_with(vec![
+            vec![(0, garbled(&[1]))],
+            vec![(0, corrupted(&[1]))],
+            // the delimiter and the probe
+            vec![],
+            vec![(0, version())],
+            vec![(0, response(&[1]))],
+        ]);
+        connection.set_retry(Some(RetryConfig {
+            resync_after: Some(2),
+            ..Default::default()
+        }));
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[1]);
+        let written = connection.with_transport(|p| p.written.clone());
+        assert_eq!(written.len(), 5);
+        assert_eq!(written[2], [START_STOP]);
+        assert_eq!(written[4], written[0]);
+    }
+
+    #[test]
+    fn short_writes_still_send_the_whole_frame() {
+        let mut connection = connection(vec![(0, response(&[1]))]);
+        connection.with_transport(|p| p.half_writes = true);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[1]);
+        let (written, calls) = connection.with_transport(|p| (p.written.clone(), p.write_calls()));
+        assert_eq!(written, [request().into_raw().to_vec()]);
+        // 6 bytes go out as 3, 2 and 1
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn a_few_stalled_writes_are_retried() {
+        let mut connection = connection(vec![(0, response(&[1]))]);
+        connection.with_transport(|p| p.zero_writes = STALLED_WRITES as usize);
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[1]);
+    }
+
+    #[test]
+    fn stalled_transport_fails_the_command() {
+        let mut connection = connection(vec![(0, response(&[1]))]);
+        connection.with_transport(|p| p.zero_writes = usize::MAX);
+        match connection.transact(request()) {
+            Err(DeviceError::IoError(e)) => assert_eq!(e.kind(), ErrorKind::WriteZero),
+            other => panic!("expected a write error, got {:?}", other),
+        }
+        let calls = connection.with_transport(|p| p.write_calls());
+        assert_eq!(calls, STALLED_WRITES as usize + 1);
+    }
+
+    #[test]
+    fn rts_is_raised_only_while_sending() {
+        let mut connection = connection(vec![(0, response(&[1]))]);
+        connection.set_rs485(Some(Rs485Config {
+            rts_on_send: true,
+            turnaround: Duration::from_millis(20),
+        }));
+        let start = Instant::now();
+        assert_eq!(connection.transact(request()).unwrap().into_data().as_slice(), &[1]);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let events = connection.with_transport(|p| p.events.clone());
+        assert_eq!(events, ["rts on", "write", "flush", "rts off", "read"]);
+    }
+
+    #[test]
+    fn turnaround_without_rts() {
+        let mut connection = connection(vec![(0, response(&[1]))]);
+        connection.set_rs485(Some(Rs485Config {
+            rts_on_send: false,
+            turnaround: Duration::ZERO,
+        }));
+        connection.transact(request()).unwrap();
+        assert_eq!(connection.with_transport(|p| p.events.clone()), ["write", "flush", "read"]);
+    }
+
+    #[test]
+    fn rts_is_lowered_for_every_attempt() {
+        let mut connection = connection_with(vec![
+            vec![(0, corrupted(&[1]))],
+            vec![(0, response(&[1]))],
+        ]);
+        connection.set_retry(Some(RetryConfig::default()));
+        connection.set_rs485(Some(Rs485Config {
+            rts_on_send: true,
+            turnaround: Duration::ZERO,
+        }));
+        connection.transact(request()).unwrap();
+        let mut events = connection.with_transport(|p| p.events.clone());
+        // clearing stale input before the second attempt reads as well
+        events.dedup();
+        let attempt = ["rts on", "write", "flush", "rts off", "read"];
+        assert_eq!(events, [attempt, attempt].concat());
+    }
+
+    #[test]
+    fn pending_command_is_polled_to_completion() {
+        let frame = response(&[1, 2, 3, 4]);
+        let chunks = frame.chunks(4).map(|c| (0, c.to_vec())).collect();
+        let mut connection = connection(chunks);
+        let mut pending = connection.start(request()).unwrap();
+        assert!(pending.poll().is_pending());
+        assert!(pending.poll().is_pending());
+        match pending.poll() {
+            Poll::Ready(Ok(res)) => assert_eq!(res.into_data().as_slice(), &[1, 2, 3, 4]),
+            other => panic!("expected a response, got {:?}", other),
+        }
+        assert!(matches!(pending.poll(), Poll::Ready(Err(DeviceError::AlreadyFinished))));
+    }
+
+    #[test]
+    fn pending_command_split_at_every_boundary() {
+        let frame = response(&[1, 0x7E, 3]);
+        for split in 1..frame.len() {
+            let (head, tail) = frame.split_at(split);
+            let mut connection = connection(vec![(0, head.to_vec()), (0, tail.to_vec())]);
+            let mut pending = connection.start(request()).unwrap();
+            assert!(pending.poll().is_pending(), "split at {}", split);
+            match pending.poll() {
+                Poll::Ready(Ok(res)) => assert_eq!(res.into_data().as_slice(), &[1, 0x7E, 3]),
+                other => panic!("expected a response split at {}, got {:?}", split, other),
+            }
+        }
+    }
+
+    #[test]
+    fn pending_command_times_out() {
+        let mut connection = connection(vec![]);
+        let mut pending = connection.start(request()).unwrap();
+        let start = Instant::now();
+        let result = loop {
+            if let Poll::Ready(result) = pending.poll() {
+                break result;
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+        assert!(matches!(result, Err(DeviceError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    /// Keeps the commands of the requests and the responses it sees
+    #[derive(Clone, Default)]
+    struct Recorder {
+        requests: Arc<Mutex<Vec<u8>>>,
+        responses: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl FrameMiddleware for Recorder {
+        fn on_transmit(&mut self, frame: &MOSIFrame) -> middleware::Action {
+            self.requests.lock().unwrap().push(frame.get_command_number());
+            middleware::Action::Continue
+        }
+
+        fn on_receive(&mut self, frame: &MISOFrame) {
+            self.responses.lock().unwrap().push(frame.to_raw().to_vec());
+        }
+    }
+
+    #[test]
+    fn middleware_sees_every_request_and_response() {
+        let answer = |command| vec![(0, response_to(command, &[command]))];
+        let mut connection = connection_with(vec![answer(1), answer(2), answer(3), answer(1)]);
+        let recorder = Recorder::default();
+        connection.set_middleware(Box::new(recorder.clone()));
+
+        connection.transact(request()).unwrap();
+        let frames = (2..=3).map(|command| MOSIFrame::new(0, command, &[]).unwrap());
+        connection.pipeline(frames.collect()).unwrap();
+        let mut pending = connection.start(SmallFrame::sized(0, 0x01, &[]).unwrap()).unwrap();
+        while pending.poll().is_pending() {}
+        drop(pending);
+
+        assert_eq!(*recorder.requests.lock().unwrap(), [1, 2, 3, 1]);
+        let expected: Vec<_> = [1, 2, 3, 1].map(|command| response_to(command, &[command])).into();
+        assert_eq!(*recorder.responses.lock().unwrap(), expected);
+        assert!(connection.take_middleware().is_some());
+        assert!(connection.take_middleware().is_none());
+    }
+
+    #[test]
+    fn a_blocked_request_is_never_written() {
+        let mut connection = connection(vec![(0, response(&[]))]);
+        connection.set_middleware(Box::new(CommandBlacklist::new([0x01])));
+        assert!(matches!(
+            connection.transact(request()),
+            Err(DeviceError::BlockedByMiddleware(0x01, _))
+        ));
+        assert!(matches!(
+            connection.pipeline(vec![MOSIFrame::new(0, 0x02, &[]).unwrap(), request()]),
+            Err(DeviceError::BlockedByMiddleware(0x01, _))
+        ));
+        assert!(matches!(connection.start(request()), Err(DeviceError::BlockedByMiddleware(..))));
+        assert_eq!(connection.with_transport(|p| p.writes), 0);
+        assert_eq!(connection.stats().commands, 0);
+    }
+
+    #[test]
+    fn retries_corrupted_responses() {
+        let mut connection = connection_with(vec![
+            vec![(0, corrupted(&[1, 2]))],
+            vec![(0, corrupted(&[1, 2]))],
+            vec![(0, response(&[1, 2]))],
+        ]);
+        connection.set_retry(Some(RetryConfig::default()));
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1, 2]);
+        assert_eq!(connection.with_transport(|p| p.writes), 3);
+    }
+
+    #[test]
+    fn stalled_device_returns_at_the_deadline() {
+        let mut connection = connection(vec![]);
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(30);
+        assert!(matches!(
+            connection.transact_with_deadline(request(), deadline),
+            Err(DeviceError::DeadlineExceeded)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert!(start.elapsed() < Duration::from_millis(60));
+    }
+
+    #[test]
+    fn response_stalling_mid_frame_returns_at_the_deadline() {
+        let frame = response(&[1, 2, 3, 4]);
+        let mut connection = connection(vec![(0, frame[..3].to_vec()), (25, frame[3..].to_vec())]);
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(10);
+        assert!(matches!(
+            connection.transact_with_deadline(request(), deadline),
+            Err(DeviceError::DeadlineExceeded)
+        ));
+        assert!(start.elapsed() < Duration::from_millis(25));
+    }
+
+    #[test]
+    fn retry_that_does_not_fit_is_skipped() {
+        let mut connection = connection_with(vec![
+            vec![(0, corrupted(&[1, 2]))],
+            vec![(0, response(&[1, 2]))],
+        ]);
+        connection.set_retry(Some(RetryConfig {
+            delay: Duration::from_millis(50),
+            ..Default::default()
+        }));
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(30);
+        assert!(matches!(
+            connection.transact_with_deadline(request(), deadline),
+            Err(DeviceError::DeadlineExceeded)
+        ));
+        assert!(start.elapsed() < Duration::from_millis(30));
+        assert_eq!(connection.with_transport(|p| p.writes), 1);
+
+        // with the budget for it the retry is made
+        let deadline = Instant::now() + Duration::from_millis(500);
+        let res = connection.transact_with_deadline(request(), deadline).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1, 2]);
+    }
+
+    #[test]
+    fn nothing_is_sent_after_the_deadline() {
+        let mut connection = connection(vec![(0, response(&[1, 2]))]);
+        assert!(matches!(
+            connection.transact_with_deadline(request(), Instant::now()),
+            Err(DeviceError::DeadlineExceeded)
+        ));
+        assert_eq!(connection.with_transport(|p| p.writes), 0);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut connection = connection_with(vec![vec![(0, corrupted(&[1, 2]))]; 3]);
+        connection.set_retry(Some(RetryConfig {
+            max_attempts: 2,
+            ..Default::default()
+        }));
+        match connection.transact(request()) {
+            Err(DeviceError::RetriesExhausted(2, last)) => {
+                assert!(matches!(*last, DeviceError::InvalidChecksum(_, _)))
+            }
+            other => panic!("expected, DeviceError::RetriesExhausted got {:?}", other),
+        }
+        assert_eq!(connection.with_transport(|p| p.writes), 2);
+    }
+
+    #[test]
+    fn parameter_error_is_not_retried() {
+        let error = to_shdlc(&[0x00, 0x01, 0x04, 0x00]).unwrap().to_vec();
+        let mut connection = connection_with(vec![vec![(0, error)], vec![(0, response(&[1]))]]);
+        connection.set_retry(Some(RetryConfig::default()));
+        assert!(matches!(
+            connection.transact(request()),
+            Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+        ));
+        assert_eq!(connection.with_transport(|p| p.writes), 1);
+    }
+
+    #[test]
+    fn fatal_errors_are_sent_again_while_booting() {
+        let fatal = || vec![(0, to_shdlc(&[0x00, 0x01, 0x7F, 0x00]).unwrap().to_vec())];
+        let scripts = vec![fatal(), fatal(), vec![(0, response(&[1]))]];
+        let mut connection = connection_with(scripts.clone());
+        connection.set_boot_grace(Some(BootGrace::default()));
+        connection.mark_reset();
+        let start = Instant::now();
+        let res = connection.transact_once(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1]);
+        assert_eq!(connection.with_transport(|p| p.writes), 3);
+        assert_eq!(connection.stats().retries, 2);
+        // 10ms and then 20ms
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        // without a reset the first fatal error fails the command
+        let mut connection = connection_with(scripts);
+        connection.set_boot_grace(Some(BootGrace::default()));
+        assert_eq!(connection.since_reset(), None);
+        assert!(matches!(
+            connection.transact(request()),
+            Err(DeviceError::StateResponse(StateResponseError::FatalError))
+        ));
+        assert_eq!(connection.with_transport(|p| p.writes), 1);
+    }
+
+    #[test]
+    fn fatal_errors_after_a_reset_fail_by_default() {
+        let fatal = || vec![(0, to_shdlc(&[0x00, 0x01, 0x7F, 0x00]).unwrap().to_vec())];
+        let mut connection = connection_with(vec![fatal(), vec![(0, response(&[1]))]]);
+        assert_eq!(Settings::default().boot_grace, None);
+        assert_eq!(connection.boot_grace(), None);
+        connection.mark_reset();
+        assert!(matches!(
+            connection.transact(request()),
+            Err(DeviceError::StateResponse(StateResponseError::FatalError))
+        ));
+        assert_eq!(connection.with_transport(|p| p.writes), 1);
+    }
+
+    #[test]
+    fn boot_grace_ends_with_its_window() {
+        let grace = BootGrace {
+            window: Duration::from_millis(50),
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(25),
+        };
+        let fatal = DeviceError::StateResponse(StateResponseError::FatalError);
+        assert!(grace.is_booting(&fatal, Duration::from_millis(49)));
+        assert!(!grace.is_booting(&fatal, Duration::from_millis(50)));
+        assert!(!grace.is_booting(&DeviceError::Timeout, Duration::ZERO));
+        let backoffs: Vec<_> = (1..=4).map(|attempt| grace.backoff(attempt).as_millis()).collect();
+        assert_eq!(backoffs, [10, 20, 25, 25]);
+
+        let fatal = || vec![(0, to_shdlc(&[0x00, 0x01, 0x7F, 0x00]).unwrap().to_vec())];
+        let mut connection = connection_with(vec![fatal(); 10]);
+        connection.set_boot_grace(Some(grace));
+        connection.mark_reset();
+        assert!(matches!(
+            connection.transact(request()),
+            Err(DeviceError::StateResponse(StateResponseError::FatalError))
+        ));
+        // 0, 10, 30, 55 and 80ms after the reset
+        assert!(connection.with_transport(|p| p.writes) < 6);
+        assert!(connection.since_reset().unwrap() >= grace.window);
+    }
+
+    #[test]
+    fn busy_answers_are_polled_within_the_budget() {
+        let busy = || vec![(0, to_shdlc(&[0x00, 0x01, 0x42, 0x00]).unwrap().to_vec())];
+        let scripts = vec![busy(), busy(), vec![(0, response(&[1]))]];
+        let mut connection = connection_with(scripts.clone());
+        assert_eq!(connection.busy_retry(), None);
+        assert!(matches!(
+            connection.transact(request()),
+            Err(DeviceError::StateResponse(StateResponseError::SensorBusy))
+        ));
+
+        let mut connection = connection_with(scripts);
+        let policy = BusyPolicy::new(Duration::from_millis(100), Duration::from_millis(5));
+        connection.set_busy_retry(Some(policy));
+        let res = connection.transact(request()).unwrap();
+        assert_eq!(res.into_data().as_slice(), &[1]);
+        assert_eq!(connection.with_transport(|p| p.writes), 3);
+
+        let mut connection = connection_with(vec![busy(); 20]);
+        connection.set_busy_retry(Some(BusyPolicy::new(
+            Duration::from_millis(30),
+            Duration::from_millis(20),
+        )));
+        match connection.transact(request()) {
+            Err(DeviceError::StillBusy(waited)) => assert!(waited >= Duration::from_millis(30)),
+            other => panic!("expected still busy, got {:?}", other),
+        }
+        // right away, after 20ms and at the end of the budget
+        assert_eq!(connection.with_transport(|p| p.writes), 3);
+    }
+
+    #[test]
+    fn transact_once_never_retries() {
+        let mut connection = connection_with(vec![vec![(0, corrupted(&[1]))], vec![(0, response(&[1]))]]);
+        connection.set_retry(Some(RetryConfig::default()));
+        assert!(matches!(connection.transact_once(request()), Err(DeviceError::InvalidChecksum(_, _))));
+        assert_eq!(connection.with_transport(|p| p.writes), 1);
+    }
+
+    #[test]
+    fn non_idempotent_commands_are_retried_only_when_opted_in() {
+        let scripts = || vec![vec![(0, corrupted(&[1]))], vec![(0, response(&[1]))]];
+        let reset = || request().with_idempotency(Idempotency::NonIdempotent);
+        let mut connection = connection_with(scripts());
+        connection.set_retry(Some(RetryConfig::default()));
+        assert!(matches!(connection.transact(reset()), Err(DeviceError::InvalidChecksum(_, _))));
+        assert_eq!(connection.with_transport(|p| p.writes), 1);
+
+        let mut connection = connection_with(scripts());
+        connection.set_retry(Some(RetryConfig {
+            retry_non_idempotent: true,
+            ..Default::default()
+        }));
+        assert_eq!(connection.transact(reset()).unwrap().into_data().as_slice(), &[1]);
+        assert_eq!(connection.with_transport(|p| p.writes), 2);
+    }
+
+    #[test]
+    fn pipeline_does_not_send_non_idempotent_commands_again() {
+        let answer = |command| vec![(0, response_to(command, &[command]))];
+        let scripts = || {
+            let mut scripts = vec![answer(1), answer(9), answer(3)];
+            scripts.extend((2..=4).map(answer));
+            scripts
+        };
+        // the third request of the failed batch was written before the error
+        let frames = || {
+            (1..=4).map(|command| {
+                let frame = MOSIFrame::new(0, command, &[]).unwrap();
+                match command {
+                    3 => frame.with_idempotency(Idempotency::NonIdempotent),
+                    _ => frame,
+                }
+            })
+        };
+        let mut connection = connection_with(scripts());
+        connection.set_pipeline_window(3);
+        connection.set_retry(Some(RetryConfig::default()));
+        let error = connection.pipeline(frames().collect()).unwrap_err();
+        assert!(matches!(error, DeviceError::UnexpectedResponse(0, 9)), "{:?}", error);
+        assert_eq!(connection.with_transport(|p| p.written.len()), 3);
+
+        let mut connection = connection_with(scripts());
+        connection.set_pipeline_window(3);
+        connection.set_retry(Some(RetryConfig {
+            retry_non_idempotent: true,
+            ..Default::default()
+        }));
+        assert_eq!(connection.pipeline(frames().collect()).unwrap().len(), 4);
+    }
+
+    #[cfg(feature = "log")]
+    mod log_records {
+        use std::cell::RefCell;
+
+        use super::*;
+
+        thread_local! {
+            // per thread so the other tests running in parallel don't end up in here
+            static RECORDS: RefCell<Vec<(log::Level, String)>> = const { RefCell::new(Vec::new()) };
+        }
+
+        struct CaptureLogger;
+
+        impl log::Log for CaptureLogger {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                RECORDS.with_borrow_mut(|r| r.push((record.level(), record.args().to_string())));
+            }
+
+            fn flush(&self) {}
+        }
+
+        #[test]
+        fn transaction_is_logged() {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+
+            let mut connection = connection_with(vec![
+                vec![(0, corrupted(&[1]))],
+                vec![(0, response(&[1]))],
+            ]);
+            connection.set_retry(Some(RetryConfig::default()));
+            connection.transact(request()).unwrap();
+
+            let records = RECORDS.take();
+            let levels: Vec<log::Level> = records.iter().map(|(level, _)| *level).collect();
+            use log::Level::*;
+            assert_eq!(levels, [Debug, Trace, Trace, Warn, Warn, Trace, Trace, Debug]);
+            assert_eq!(records[1].1, "sent 7e 00 01 00 fe 7e");
+            assert!(records[7].1.starts_with("command 0x01 to address 0 finished in"));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    mod spans {
+        use std::fmt::Debug;
+        use std::sync::{Arc, Mutex};
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Subscriber};
+        use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+        use tracing_subscriber::registry::LookupSpan;
+
+        use super::*;
+
+        #[derive(Default, Debug)]
+        struct Captured {
+            spans: Vec<(String, Vec<(String, String)>)>,
+            // event message and the name of the span it happened in
+            events: Vec<(String, Option<String>)>,
+        }
+
+        struct Fields<'a>(&'a mut Vec<(String, String)>);
+
+        impl Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0.push((field.name().to_string(), format!("{:?}", value)));
+            }
+        }
+
+        struct CaptureLayer(Arc<Mutex<Captured>>);
+
+        impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for CaptureLayer {
+            fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+                let mut fields = Vec::new();
+                attrs.record(&mut Fields(&mut fields));
+                let mut captured = self.0.lock().unwrap();
+                captured.spans.push((attrs.metadata().name().to_string(), fields));
+            }
+
+            fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+                let mut captured = self.0.lock().unwrap();
+                let (_, fields) = captured.spans.last_mut().unwrap();
+                values.record(&mut Fields(fields));
+            }
+
+            fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+                let mut fields = Vec::new();
+                event.record(&mut Fields(&mut fields));
+                let message = fields
+                    .into_iter()
+                    .find(|(name, _)| name == "message")
+                    .map(|(_, value)| value)
+                    .unwrap_or_default();
+                let span = ctx.event_span(event).map(|s| s.name().to_string());
+                self.0.lock().unwrap().events.push((message, span));
+            }
+        }
+
+        #[test]
+        fn command_span() {
+            let captured = Arc::new(Mutex::new(Captured::default()));
+            let subscriber = tracing_subscriber::registry().with(CaptureLayer(Arc::clone(&captured)));
+
+            tracing::subscriber::with_default(subscriber, || {
+                let mut connection = connection_with(vec![
+                    vec![(0, corrupted(&[1]))],
+                    vec![(0, response(&[1]))],
+                ]);
+                connection.set_retry(Some(RetryConfig::default()));
+                connection.transact(request()).unwrap();
+            });
+
+            let captured = captured.lock().unwrap();
+            assert_eq!(captured.spans.len(), 1);
+            let (name, fields) = &captured.spans[0];
+            assert_eq!(name, "shdlc_command");
+            let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+            assert_eq!(field("command"), Some("1"));
+            assert_eq!(field("address"), Some("0"));
+            assert_eq!(field("outcome"), Some("\"ok\""));
+            assert!(field("duration_us").is_some());
+            assert_eq!(
+                captured.events,
+                [("retrying command".to_string(), Some("shdlc_command".to_string()))]
+            );
+        }
+    }
+}
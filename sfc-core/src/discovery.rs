@@ -0,0 +1,549 @@
+//! Finding the serial ports devices are connected to. Sensirion's SCC1-USB cable and most other
+//! RS-485 adapters are built on an FTDI or Silicon Labs USB bridge, so the ports are recognised by
+//! their USB vendor and product IDs instead of a hard coded name like "/dev/ttyUSB0" or "COM4".
+//!
+//! Every candidate carries the USB serial string of its cable, which can be used to pin a
+//! specific cable when several are plugged in:
+//! ```no_run
+//! use sfc_core::discovery::find_sensirion_ports;
+//! let port = find_sensirion_ports()
+//!     .into_iter()
+//!     .find(|p| p.serial_number.as_deref() == Some("FT4ABCDE"));
+//! ```
+//!
+//! Once a device was found, a [ConnectionProfile] of its port, address, baudrate and serial
+//! number skips the search the next time. The drivers export one with
+//! `Device::connection_profile` and connect from it with `Device::connect_with_profile`, which
+//! checks the serial number and searches as the [DiscoveryPolicy] says when another device
+//! answers. Both are [profile_of] and [connect_using] for their [ProfiledDevice], the search
+//! itself is [connect_with_profile]:
+//! ```no_run
+//! use sfc_core::discovery::{ConnectionProfile, DiscoveryPolicy, connect_with_profile};
+//! # fn connect(profile: &ConnectionProfile, _: Option<std::time::Duration>)
+//! #     -> Result<((), String), sfc_core::error::DeviceError> { unimplemented!() }
+//! # let saved: ConnectionProfile = unimplemented!();
+//! let (device, profile) = connect_with_profile(&saved, &DiscoveryPolicy::scan(), connect)?;
+//! if profile != saved {
+//!     // the device moved, save the profile again
+//! }
+//! # Ok::<(), sfc_core::error::DeviceError>(())
+//! ```
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use serialport::{DataBits, FlowControl, Parity, SerialPortInfo, SerialPortType, StopBits, UsbPortInfo};
+
+use crate::baudrate::Baudrate;
+use crate::connection::DEFAULT_RESPONSE_TIMEOUT;
+use crate::error::DeviceError;
+use crate::flow_controller::FlowController;
+use crate::identity::IdentityField;
+
+/// The platform specific serial port type returned by [open_port] and [open_first_detected]
+#[cfg(unix)]
+pub type NativePort = serialport::TTYPort;
+/// The platform specific serial port type returned by [open_port] and [open_first_detected]
+#[cfg(windows)]
+pub type NativePort = serialport::COMPort;
+
+/// FTDI's USB vendor ID, used by the SCC1-USB cable
+pub const FTDI_VID: u16 = 0x0403;
+/// Silicon Labs' USB vendor ID
+pub const SILABS_VID: u16 = 0x10C4;
+
+/// FT232R (the bridge inside the SCC1-USB cable), FT2232, FT4232, FT232H and FT-X
+const FTDI_PIDS: [u16; 5] = [0x6001, 0x6010, 0x6011, 0x6014, 0x6015];
+/// CP210x, CP2105 and CP2108
+const SILABS_PIDS: [u16; 3] = [0xEA60, 0xEA70, 0xEA71];
+
+/// The kind of cable a port was recognised as. Sorted from most to least likely to have a
+/// Sensirion device on the other end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CableKind {
+    /// A cable whose USB descriptor names Sensirion, like the SCC1-USB
+    Sensirion,
+    /// A generic FTDI USB to serial bridge
+    Ftdi,
+    /// A generic Silicon Labs CP210x USB to serial bridge
+    SiLabs,
+}
+
+/// A serial port that is likely to have a Sensirion device connected to it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortCandidate {
+    /// The name used to open the port, e.g. "/dev/ttyUSB0" or "COM4"
+    pub port_name: String,
+    /// The USB serial string of the cable if it reports one
+    pub serial_number: Option<String>,
+    pub kind: CableKind,
+    pub vid: u16,
+    pub pid: u16,
+}
+
+/// Lists the serial ports that look like a Sensirion cable or a common USB to serial bridge.
+/// Sensirion cables come first, the rest keep the order the operating system reported them in.
+/// An error while listing the ports is treated as no ports being present.
+pub fn find_sensirion_ports() -> Vec<PortCandidate> {
+    serialport::available_ports()
+        .map(filter_ports)
+        .unwrap_or_default()
+}
+
+/// Opens the first port returned by [find_sensirion_ports] at the given baudrate
+pub fn open_first_detected(baud_rate: u32) -> Result<(NativePort, PortCandidate), DeviceError> {
+    let candidate = find_sensirion_ports().into_iter().next().ok_or_else(|| {
+        serialport::Error::new(
+            serialport::ErrorKind::NoDevice,
+            "no Sensirion cable or USB serial bridge was found",
+        )
+    })?;
+    let port = open_port(&candidate.port_name, baud_rate)?;
+    Ok((port, candidate))
+}
+
+/// Opens a serial port with the settings SHDLC uses: 8 data bits, no parity, one stop bit and
+/// no flow control, with [DEFAULT_RESPONSE_TIMEOUT] as the read timeout
+pub fn open_port(port_name: &str, baud_rate: u32) -> Result<NativePort, DeviceError> {
+    let port = serialport::new(port_name, baud_rate)
+        .data_bits(DataBits::Eight)
+        .parity(Parity::None)
+        .stop_bits(StopBits::One)
+        .flow_control(FlowControl::None)
+        .timeout(DEFAULT_RESPONSE_TIMEOUT)
+        .open_native()?;
+    Ok(port)
+}
+
+/// How to reach one device again without searching for it, see the
+/// [module documentation](self). Serializable with `serde`, the baudrate as bits per second.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionProfile {
+    /// The port the device was found on, like "/dev/ttyUSB0" or "COM4". Port names change when
+    /// cables are plugged in another order, the [DiscoveryPolicy] decides where else to look.
+    pub port_hint: Option<String>,
+    /// The serial number of the device, which decides whether the right device answered
+    pub serial: String,
+    pub address: u8,
+    #[cfg_attr(feature = "serde", serde(with = "bits_per_second"))]
+    pub baudrate: Baudrate,
+}
+
+#[cfg(feature = "serde")]
+mod bits_per_second {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::baudrate::Baudrate;
+
+    pub(super) fn serialize<S: Serializer>(baudrate: &Baudrate, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(baudrate.bits_per_second())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Baudrate, D::Error> {
+        Ok(Baudrate::from_device(u32::deserialize(d)?))
+    }
+}
+
+/// Where [connect_with_profile] looks for the device of a profile that doesn't answer on its
+/// port or where another device answers instead
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiscoveryPolicy {
+    /// Nowhere, the error of the profile is returned
+    Never,
+    /// Every address at every baudrate on every port, in the order given, until the device with
+    /// the serial number of the profile answers
+    Scan {
+        /// The ports to look on. When empty, the port of the profile and then the ports
+        /// [find_sensirion_ports] finds.
+        ports: Vec<String>,
+        baudrates: Vec<Baudrate>,
+        addresses: RangeInclusive<u8>,
+        /// How long each address has to answer
+        timeout: Duration,
+    },
+}
+
+impl DiscoveryPolicy {
+    /// A scan of the port of the profile and the detected ports at every rate of
+    /// [Baudrate::DETECTION_ORDER] and the addresses 0 to 15, each with 50ms to answer
+    pub fn scan() -> Self {
+        Self::Scan {
+            ports: Vec::new(),
+            baudrates: Baudrate::DETECTION_ORDER.to_vec(),
+            addresses: 0..=15,
+            timeout: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Connects to the device of a profile and returns it together with the profile it was found
+/// with, which differs from the given one if the device had to be searched for.
+///
+/// `connect` opens the port of the profile it is given at its baudrate and returns the device at
+/// its address together with the serial number the device answered with. The timeout is the
+/// response timeout to use while searching, [None] for the default. The profile is tried first,
+/// a device with another serial number fails with [DeviceError::IdentityMismatch] and is dropped
+/// before the [DiscoveryPolicy] is followed. A port that fails to open is skipped, a search that
+/// doesn't find the device fails with [DeviceError::PortError].
+pub fn connect_with_profile<D, F>(
+    profile: &ConnectionProfile,
+    fallback: &DiscoveryPolicy,
+    mut connect: F,
+) -> Result<(D, ConnectionProfile), DeviceError>
+where
+    F: FnMut(&ConnectionProfile, Option<Duration>) -> Result<(D, String), DeviceError>,
+{
+    let first = match &profile.port_hint {
+        Some(_) => match connect(profile, None) {
+            Ok((device, serial)) if serial == profile.serial => {
+                return Ok((device, profile.clone()));
+            }
+            Ok((_, found)) => Err(DeviceError::IdentityMismatch {
+                field: IdentityField::SerialNumber,
+                expected: profile.serial.clone(),
+                found,
+            }),
+            Err(e) => Err(e),
+        },
+        None => Err(not_found(profile)),
+    };
+    let DiscoveryPolicy::Scan {
+        ports,
+        baudrates,
+        addresses,
+        timeout,
+    } = fallback
+    else {
+        return first.map(|device| (device, profile.clone()));
+    };
+
+    let ports = if ports.is_empty() {
+        let detected = find_sensirion_ports().into_iter().map(|port| port.port_name);
+        scan_ports(profile.port_hint.as_ref(), detected)
+    } else {
+        ports.clone()
+    };
+    'port: for port in ports {
+        for &baudrate in baudrates {
+            for address in addresses.clone() {
+                let candidate = ConnectionProfile {
+                    port_hint: Some(port.clone()),
+                    serial: profile.serial.clone(),
+                    address,
+                    baudrate,
+                };
+                // the profile itself was tried already
+                if candidate == *profile {
+                    continue;
+                }
+                match connect(&candidate, Some(*timeout)) {
+                    Ok((device, serial)) if serial == profile.serial => {
+                        return Ok((device, candidate));
+                    }
+                    Err(DeviceError::PortError(_)) => continue 'port,
+                    _ => {}
+                }
+            }
+        }
+    }
+    Err(not_found(profile))
+}
+
+/// A device [connect_using] can look for and [profile_of] can describe, sfc5xxx-rs and
+/// sfc6xxx-rs implement it for their devices
+pub trait ProfiledDevice: FlowController + Sized {
+    /// What the device talks over, opened for every port and baudrate that is tried
+    type Port;
+
+    /// The device at `address` on the port, answering within `timeout` if one is given
+    fn on_port(
+        port: Self::Port,
+        address: u8,
+        timeout: Option<Duration>,
+    ) -> Result<Self, DeviceError>;
+
+    /// Goes back to the default timeouts once the device was found
+    fn reset_timeouts(&mut self);
+
+    /// The address the device is talked to at
+    fn address(&self) -> u8;
+
+    /// Reads the baudrate the device is set to
+    fn read_baudrate(&mut self) -> Result<Baudrate, DeviceError>;
+}
+
+/// [connect_with_profile] for a [ProfiledDevice], opening the ports with `open`, which is given
+/// the name of a port and the baudrate to open it at. The device is looked for with the timeout
+/// of the [DiscoveryPolicy] and gets the default timeouts back once it answered.
+pub fn connect_using<D, F>(
+    profile: &ConnectionProfile,
+    fallback: &DiscoveryPolicy,
+    mut open: F,
+) -> Result<(D, ConnectionProfile), DeviceError>
+where
+    D: ProfiledDevice,
+    F: FnMut(&str, u32) -> Result<D::Port, DeviceError>,
+{
+    connect_with_profile(profile, fallback, |candidate, timeout| {
+        let port_name = candidate.port_hint.as_deref().unwrap_or_default();
+        let port = open(port_name, candidate.baudrate.into())?;
+        let mut device = D::on_port(port, candidate.address, timeout)?;
+        let serial = device.get_serial_number()?;
+        device.reset_timeouts();
+        Ok((device, serial))
+    })
+}
+
+/// The profile to connect to the device again with, with the serial number and baudrate read
+/// from the device. The device doesn't know the name of its port, the hint is kept as given.
+pub fn profile_of<D: ProfiledDevice>(
+    device: &mut D,
+    port_hint: Option<&str>,
+) -> Result<ConnectionProfile, DeviceError> {
+    Ok(ConnectionProfile {
+        port_hint: port_hint.map(str::to_string),
+        serial: device.get_serial_number()?,
+        address: device.address(),
+        baudrate: device.read_baudrate()?,
+    })
+}
+
+/// The ports a scan without ports of its own looks on: the port of the profile first, then the
+/// detected ports without it
+fn scan_ports(
+    port_hint: Option<&String>,
+    detected: impl IntoIterator<Item = String>,
+) -> Vec<String> {
+    let detected = detected.into_iter().filter(|port| Some(port) != port_hint);
+    port_hint.cloned().into_iter().chain(detected).collect()
+}
+
+fn not_found(profile: &ConnectionProfile) -> DeviceError {
+    let message = format!("no device with the serial number {} was found", profile.serial);
+    serialport::Error::new(serialport::ErrorKind::NoDevice, message).into()
+}
+
+fn filter_ports(ports: Vec<SerialPortInfo>) -> Vec<PortCandidate> {
+    let mut candidates: Vec<PortCandidate> = ports
+        .into_iter()
+        .filter_map(|port| match port.port_type {
+            SerialPortType::UsbPort(info) => classify(&info).map(|kind| PortCandidate {
+                port_name: port.port_name,
+                serial_number: info.serial_number,
+                kind,
+                vid: info.vid,
+                pid: info.pid,
+            }),
+            _ => None,
+        })
+        .collect();
+    // stable so ports of the same kind keep their order
+    candidates.sort_by_key(|c| c.kind);
+    candidates
+}
+
+fn classify(info: &UsbPortInfo) -> Option<CableKind> {
+    let names_sensirion = |s: &Option<String>| {
+        s.as_deref().is_some_and(|s| {
+            let s = s.to_ascii_lowercase();
+            s.contains("sensirion") || s.contains("scc1")
+        })
+    };
+
+    if names_sensirion(&info.manufacturer) || names_sensirion(&info.product) {
+        Some(CableKind::Sensirion)
+    } else if info.vid == FTDI_VID && FTDI_PIDS.contains(&info.pid) {
+        Some(CableKind::Ftdi)
+    } else if info.vid == SILABS_VID && SILABS_PIDS.contains(&info.pid) {
+        Some(CableKind::SiLabs)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb(name: &str, vid: u16, pid: u16, product: Option<&str>) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid,
+                serial_number: Some(format!("SN-{}", name)),
+                manufacturer: None,
+                product: product.map(str::to_string),
+            }),
+        }
+    }
+
+    #[test]
+    fn recognises_bridges() {
+        let ports = vec![
+            usb("cp210x", SILABS_VID, 0xEA60, None),
+            usb("ftdi", FTDI_VID, 0x6001, Some("FT232R USB UART")),
+            usb("arduino", 0x2341, 0x0043, None),
+            SerialPortInfo {
+                port_name: "pci".to_string(),
+                port_type: SerialPortType::PciPort,
+            },
+        ];
+        let found = filter_ports(ports);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].port_name, "ftdi");
+        assert_eq!(found[0].kind, CableKind::Ftdi);
+        assert_eq!(found[0].serial_number.as_deref(), Some("SN-ftdi"));
+        assert_eq!(found[1].port_name, "cp210x");
+        assert_eq!(found[1].kind, CableKind::SiLabs);
+    }
+
+    #[test]
+    fn sensirion_cables_come_first() {
+        let ports = vec![
+            usb("generic", FTDI_VID, 0x6015, None),
+            usb("scc1-a", FTDI_VID, 0x6001, Some("SCC1-USB")),
+            usb("scc1-b", FTDI_VID, 0x6001, Some("Sensirion Sensor Cable")),
+        ];
+        let names: Vec<String> = filter_ports(ports).into_iter().map(|p| p.port_name).collect();
+        assert_eq!(names, ["scc1-a", "scc1-b", "generic"]);
+    }
+
+    #[test]
+    fn unknown_product_id_is_ignored() {
+        let ports = vec![usb("ftdi", FTDI_VID, 0x1234, None)];
+        assert!(filter_ports(ports).is_empty());
+    }
+
+    fn profile() -> ConnectionProfile {
+        ConnectionProfile {
+            port_hint: Some("/dev/ttyUSB0".to_string()),
+            serial: "EMU6000001".to_string(),
+            address: 0,
+            baudrate: Baudrate::B115200,
+        }
+    }
+
+    /// A line with the devices at a port, baudrate and address, answering with their serial
+    /// numbers. Every attempt is recorded, a port that isn't listed fails to open.
+    fn line<'a>(
+        devices: &'a [(&'a str, Baudrate, u8, &'a str)],
+        attempts: &'a mut Vec<(String, Baudrate, u8)>,
+    ) -> impl FnMut(&ConnectionProfile, Option<Duration>) -> Result<((), String), DeviceError> + 'a
+    {
+        move |candidate, _| {
+            let port = candidate.port_hint.clone().unwrap();
+            attempts.push((port.clone(), candidate.baudrate, candidate.address));
+            if !devices.iter().any(|device| device.0 == port) {
+                return Err(serialport::Error::new(serialport::ErrorKind::NoDevice, "").into());
+            }
+            devices
+                .iter()
+                .find(|device| {
+                    (device.0, device.1, device.2) == (&port, candidate.baudrate, candidate.address)
+                })
+                .map(|device| ((), device.3.to_string()))
+                .ok_or(DeviceError::Timeout)
+        }
+    }
+
+    fn scan(ports: &[&str]) -> DiscoveryPolicy {
+        DiscoveryPolicy::Scan {
+            ports: ports.iter().map(|port| port.to_string()).collect(),
+            baudrates: vec![Baudrate::B115200, Baudrate::B19200],
+            addresses: 0..=2,
+            timeout: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn a_matching_profile_connects_without_searching() {
+        let mut attempts = Vec::new();
+        let devices = [("/dev/ttyUSB0", Baudrate::B115200, 0, "EMU6000001")];
+        let (_, found) = connect_with_profile(&profile(), &scan(&[]), line(&devices, &mut attempts))
+            .unwrap();
+        assert_eq!(found, profile());
+        assert_eq!(attempts.len(), 1);
+    }
+
+    #[test]
+    fn another_device_on_the_port_is_searched_past() {
+        let devices = [
+            ("/dev/ttyUSB0", Baudrate::B115200, 0, "EMU6000002"),
+            ("/dev/ttyUSB1", Baudrate::B19200, 2, "EMU6000001"),
+        ];
+        let mut attempts = Vec::new();
+        let never = DiscoveryPolicy::Never;
+        let error =
+            connect_with_profile(&profile(), &never, line(&devices, &mut attempts)).unwrap_err();
+        match error {
+            DeviceError::IdentityMismatch { field, expected, found } => {
+                assert_eq!(field, IdentityField::SerialNumber);
+                assert_eq!((expected.as_str(), found.as_str()), ("EMU6000001", "EMU6000002"));
+            }
+            other => panic!("expected another serial number, got {:?}", other),
+        }
+
+        let mut attempts = Vec::new();
+        let policy = scan(&["/dev/ttyACM0", "/dev/ttyUSB0", "/dev/ttyUSB1"]);
+        let (_, found) =
+            connect_with_profile(&profile(), &policy, line(&devices, &mut attempts)).unwrap();
+        assert_eq!(found.port_hint.as_deref(), Some("/dev/ttyUSB1"));
+        assert_eq!((found.baudrate, found.address), (Baudrate::B19200, 2));
+        assert_eq!(found.serial, "EMU6000001");
+        // the port that doesn't open is given up on, the profile isn't tried twice
+        let tried = |port: &str| attempts.iter().filter(|attempt| attempt.0 == port).count();
+        assert_eq!(tried("/dev/ttyACM0"), 1);
+        assert_eq!((tried("/dev/ttyUSB0"), tried("/dev/ttyUSB1")), (6, 6));
+    }
+
+    #[test]
+    fn the_port_of_the_profile_is_scanned_once_and_first() {
+        let hint = "/dev/ttyUSB1".to_string();
+        let detected = ["/dev/ttyUSB0", "/dev/ttyUSB1", "/dev/ttyACM0"].map(str::to_string);
+        assert_eq!(
+            scan_ports(Some(&hint), detected.clone()),
+            ["/dev/ttyUSB1", "/dev/ttyUSB0", "/dev/ttyACM0"]
+        );
+        assert_eq!(scan_ports(None, detected.clone()), detected);
+    }
+
+    #[test]
+    fn a_device_found_nowhere_fails() {
+        let devices = [("/dev/ttyUSB0", Baudrate::B115200, 1, "EMU6000002")];
+        let mut attempts = Vec::new();
+        let policy = scan(&["/dev/ttyUSB0"]);
+        let error =
+            connect_with_profile(&profile(), &policy, line(&devices, &mut attempts)).unwrap_err();
+        assert!(matches!(error, DeviceError::PortError(_)));
+        assert_eq!(attempts.len(), 6);
+
+        let profile = ConnectionProfile { port_hint: None, ..profile() };
+        let mut attempts = Vec::new();
+        let never = DiscoveryPolicy::Never;
+        let error =
+            connect_with_profile(&profile, &never, line(&devices, &mut attempts)).unwrap_err();
+        assert!(matches!(error, DeviceError::PortError(_)));
+        assert!(attempts.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn profiles_round_trip() {
+        let profile = ConnectionProfile {
+            baudrate: Baudrate::B57600,
+            ..profile()
+        };
+        let json = serde_json::to_string(&profile).unwrap();
+        assert_eq!(
+            json,
+            r#"{"port_hint":"/dev/ttyUSB0","serial":"EMU6000001","address":0,"baudrate":57600}"#
+        );
+        assert_eq!(serde_json::from_str::<ConnectionProfile>(&json).unwrap(), profile);
+        let other = r#"{"port_hint":null,"serial":"X","address":3,"baudrate":230400}"#;
+        let other: ConnectionProfile = serde_json::from_str(other).unwrap();
+        assert_eq!(other.baudrate, Baudrate::Other(230400));
+        assert_eq!(other.port_hint, None);
+    }
+}
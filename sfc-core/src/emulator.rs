@@ -0,0 +1,554 @@
+//! The transport, fault injection and framing shared by the emulators of the drivers, available
+//! with the `emulator` feature. An [Emulator] takes the requests a driver writes, hands each to
+//! a [CommandTable] and puts the answer on the line the way a real device would, a driver only
+//! implements the commands of its device family:
+//! ```
+//! use sfc_core::connection::Connection;
+//! use sfc_core::emulator::{CommandTable, Emulator, Fault, STATE_UNKNOWN_COMMAND};
+//! use sfc_core::shdlc::MOSIFrame;
+//!
+//! /// A device at address 0 that only knows its baudrate
+//! #[derive(Debug)]
+//! struct Baudrate;
+//!
+//! impl CommandTable for Baudrate {
+//!     fn address(&self) -> u8 {
+//!         0
+//!     }
+//!
+//!     fn execute(&mut self, command: u8, _data: &[u8]) -> Result<Vec<u8>, u8> {
+//!         match command {
+//!             0x91 => Ok(115200_u32.to_be_bytes().to_vec()),
+//!             _ => Err(STATE_UNKNOWN_COMMAND),
+//!         }
+//!     }
+//!
+//!     fn set_line_baudrate(&mut self, _baud_rate: u32) {}
+//! }
+//!
+//! let emulator = Emulator::new(Baudrate);
+//! let handle = emulator.handle();
+//! let mut connection = Connection::new(emulator);
+//! let response = connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap()).unwrap();
+//! assert_eq!(response.into_data().as_slice(), &115200_u32.to_be_bytes());
+//!
+//! handle.inject_fault(Fault::ErrorState(0x42));
+//! assert!(connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap()).is_err());
+//! ```
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::error::DeviceError;
+use crate::shdlc::{MISOFrame, START_STOP, calculate_check_sum, from_shdlc};
+use crate::transport::Transport;
+
+/// The state byte of a request with the wrong amount of data
+pub const STATE_DATA_SIZE: u8 = 0x01;
+/// The state byte of a command the device doesn't know
+pub const STATE_UNKNOWN_COMMAND: u8 = 0x02;
+/// The state byte of a parameter out of range
+pub const STATE_PARAMETER: u8 = 0x04;
+/// The state byte of a calibration index without a valid calibration
+pub const STATE_INVALID_CALIBRATION: u8 = 0x33;
+/// The state byte of a sensor that is still busy
+pub const STATE_BUSY: u8 = 0x42;
+/// The state byte of a fatal error, as sent by firmware that hasn't finished booting
+pub const STATE_FATAL: u8 = 0x7F;
+
+/// Misbehaviour that can be queued on the emulator. Each fault is applied to the next response
+/// the emulator would send and is then consumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Process the request but never answer it, the driver will see a timeout
+    DropResponse,
+    /// Send the response with a checksum that does not match its contents
+    CorruptChecksum,
+    /// Only send the first bytes of the response, counted after byte stuffing
+    TruncateAfter(usize),
+    /// Answer with only the first bytes of the data, in a frame with a matching length and
+    /// checksum, like a firmware that sends less than the command defines
+    ShortData(usize),
+    /// Hold the response back for the given number of milliseconds of real time
+    DelayMs(u64),
+    /// Send the given bytes, for example line noise or a partial frame, before the response
+    InjectBytes(Vec<u8>),
+    /// Send the response twice
+    DuplicateResponse,
+    /// Answer with the given state byte and no data instead of processing the request. For
+    /// example `ErrorState(0x01)` answers with a
+    /// [DataSizeError](crate::error::StateResponseError::DataSizeError).
+    ErrorState(u8),
+}
+
+/// The commands of an emulated device. The [Emulator] decodes the requests, drops the ones
+/// with a wrong checksum or for another address and applies the queued faults, the table only
+/// answers the requests addressed to it.
+pub trait CommandTable {
+    /// The slave address the device currently answers to
+    fn address(&self) -> u8;
+
+    /// Runs a command, returning the data of the response or the state byte of an error
+    fn execute(&mut self, command: u8, data: &[u8]) -> Result<Vec<u8>, u8>;
+
+    /// Called with the baudrate the driver sets on the transport
+    fn set_line_baudrate(&mut self, baud_rate: u32);
+
+    /// Whether requests are ignored right now, like by a device that is still booting. Checked
+    /// before a request is decoded, never by default.
+    fn booting(&mut self) -> bool {
+        false
+    }
+
+    /// Whether requests are answered with a fatal error (0x7F) right now instead of being run,
+    /// like by firmware that takes requests before it finished starting up. Never by default.
+    fn failing(&mut self) -> bool {
+        false
+    }
+
+    /// Whether a request is taken while the response to the previous one hasn't been read yet.
+    /// Firmware that can't pipeline requests drops it instead, taken by default.
+    fn pipelining(&self) -> bool {
+        true
+    }
+}
+
+/// The emulated device, a [Transport] over the shared state of the device. Keep an
+/// [EmulatorHandle] around to inspect or manipulate it after it was handed to a connection.
+#[derive(Debug)]
+pub struct Emulator<T> {
+    state: Arc<Mutex<EmulatorState<T>>>,
+    received: Vec<u8>,
+    timeout: Duration,
+    /// the value of [EmulatorState::plugged_in] when this transport was created
+    plugged_in: u32,
+}
+
+impl<T: CommandTable> Emulator<T> {
+    pub fn new(table: T) -> Self {
+        Self::plugged_in(Arc::new(Mutex::new(EmulatorState::new(table))))
+    }
+
+    fn plugged_in(state: Arc<Mutex<EmulatorState<T>>>) -> Self {
+        let plugged_in = lock(&state).plugged_in;
+        Self {
+            state,
+            received: Vec::new(),
+            timeout: Duration::from_millis(600),
+            plugged_in,
+        }
+    }
+
+    /// Returns a handle sharing the state of this emulator
+    pub fn handle(&self) -> EmulatorHandle<T> {
+        EmulatorHandle {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<T: CommandTable> Read for Emulator<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = lock(&self.state);
+        state.check_plugged_in(self.plugged_in)?;
+        if let Some(ready_at) = state.ready_at {
+            let now = Instant::now();
+            if ready_at > now {
+                let wait = (ready_at - now).min(self.timeout);
+                // the lock is not held while waiting so a handle can still be used
+                drop(state);
+                std::thread::sleep(wait);
+                state = lock(&self.state);
+                if Instant::now() < ready_at {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "emulator response is delayed",
+                    ));
+                }
+            }
+            state.ready_at = None;
+        }
+        if state.outgoing.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "emulator has no response pending",
+            ));
+        }
+
+        let count = buf.len().min(state.outgoing.len());
+        for (slot, byte) in buf.iter_mut().zip(state.outgoing.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl<T: CommandTable> Write for Emulator<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = lock(&self.state);
+        state.check_plugged_in(self.plugged_in)?;
+        for &byte in buf {
+            if byte != START_STOP {
+                if !self.received.is_empty() {
+                    self.received.push(byte);
+                }
+                continue;
+            }
+
+            // a delimiter directly after a start byte is treated as the start of a new frame
+            if self.received.len() > 1 {
+                self.received.push(byte);
+                state.receive_frame(&self.received);
+                self.received.clear();
+            } else {
+                self.received.clear();
+                self.received.push(byte);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: CommandTable> Transport for Emulator<T> {
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), DeviceError> {
+        lock(&self.state).table.set_line_baudrate(baud_rate);
+        Ok(())
+    }
+}
+
+/// A shared reference to the state of an [Emulator] that stays usable after the emulator has
+/// been moved into a connection or device
+#[derive(Debug)]
+pub struct EmulatorHandle<T> {
+    state: Arc<Mutex<EmulatorState<T>>>,
+}
+
+// not derived, which would only implement Clone for tables that are Clone themselves
+impl<T> Clone for EmulatorHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<T: CommandTable> EmulatorHandle<T> {
+    /// Simulates pulling the cable. Every transport created so far fails with
+    /// [std::io::ErrorKind::BrokenPipe] from now on, the device itself keeps its state.
+    pub fn unplug(&self) {
+        let mut state = lock(&self.state);
+        state.plugged_in += 1;
+        state.outgoing.clear();
+        state.ready_at = None;
+    }
+
+    /// Plugs the cable back in and returns a fresh transport to the same device
+    pub fn replug(&self) -> Emulator<T> {
+        Emulator::plugged_in(Arc::clone(&self.state))
+    }
+
+    /// Queues a fault to be applied to the next response
+    pub fn inject_fault(&self, fault: Fault) {
+        lock(&self.state).faults.push_back(fault);
+    }
+
+    /// Answers every request with the busy state (0x42) while set, like a sensor that never
+    /// finishes what it is doing. [Fault::ErrorState] is busy for a single response.
+    pub fn set_busy(&self, busy: bool) {
+        lock(&self.state).busy = busy;
+    }
+
+    /// Returns every MOSI frame that reached the emulator, including ones addressed to another
+    /// device, as (address, command, data) tuples.
+    pub fn requests(&self) -> Vec<(u8, u8, Vec<u8>)> {
+        lock(&self.state).requests.clone()
+    }
+
+    /// Returns the last MOSI frame that reached the emulator as an (address, command, data)
+    /// tuple, [None] if nothing was received yet
+    pub fn last_request(&self) -> Option<(u8, u8, Vec<u8>)> {
+        lock(&self.state).requests.last().cloned()
+    }
+
+    /// Runs `f` on the command table of the device, for the drivers to look at or change the
+    /// state of their emulated device
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut lock(&self.state).table)
+    }
+}
+
+fn lock<T>(state: &Mutex<EmulatorState<T>>) -> MutexGuard<'_, EmulatorState<T>> {
+    // a panicking test thread should not take every other user of the emulator down with it
+    state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Debug)]
+struct EmulatorState<T> {
+    table: T,
+    /// set by [EmulatorHandle::set_busy]
+    busy: bool,
+    faults: VecDeque<Fault>,
+    outgoing: VecDeque<u8>,
+    /// set by [Fault::DelayMs], nothing can be read before then
+    ready_at: Option<Instant>,
+    requests: Vec<(u8, u8, Vec<u8>)>,
+    /// counts the simulated unplugs, transports from before the last one are dead
+    plugged_in: u32,
+}
+
+enum Response {
+    Data(Vec<u8>),
+    Error(u8),
+}
+
+impl<T: CommandTable> EmulatorState<T> {
+    fn new(table: T) -> Self {
+        Self {
+            table,
+            busy: false,
+            faults: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            ready_at: None,
+            requests: Vec::new(),
+            plugged_in: 0,
+        }
+    }
+
+    fn check_plugged_in(&self, plugged_in: u32) -> std::io::Result<()> {
+        if plugged_in == self.plugged_in {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "emulator was unplugged"))
+        }
+    }
+
+    fn receive_frame(&mut self, raw: &[u8]) {
+        if self.table.booting() {
+            return;
+        }
+
+        let Ok(decoded) = from_shdlc(raw) else {
+            return;
+        };
+        // address, command, length and checksum are the bare minimum
+        let Some((&checksum, content @ [_, _, _, ..])) = decoded.split_last() else {
+            return;
+        };
+        if calculate_check_sum(content) != checksum {
+            // a real device silently discards corrupted frames
+            return;
+        }
+
+        let [address, command, _, ref data @ ..] = *content else {
+            return;
+        };
+        self.requests.push((address, command, data.to_vec()));
+        if address != self.table.address() {
+            return;
+        }
+        // still busy sending the previous response
+        if !self.table.pipelining() && !self.outgoing.is_empty() {
+            return;
+        }
+
+        if self.table.failing() {
+            self.outgoing.extend(encode_response(address, command, STATE_FATAL, &[], false));
+            return;
+        }
+        if self.busy {
+            self.outgoing.extend(encode_response(address, command, STATE_BUSY, &[], false));
+            return;
+        }
+
+        let fault = self.faults.pop_front();
+        let response = match fault {
+            Some(Fault::ErrorState(state)) => Response::Error(state),
+            Some(Fault::DropResponse) => {
+                let _ = self.handle(command, data);
+                return;
+            }
+            Some(Fault::ShortData(count)) => match self.handle(command, data) {
+                Response::Data(mut data) => {
+                    data.truncate(count);
+                    Response::Data(data)
+                }
+                response => response,
+            },
+            _ => self.handle(command, data),
+        };
+
+        // the response always comes from the address the request was sent to, even when the
+        // request changed the address
+        let corrupt = fault == Some(Fault::CorruptChecksum);
+        let frame = match response {
+            Response::Data(data) => encode_response(address, command, 0, &data, corrupt),
+            Response::Error(state) => encode_response(address, command, state, &[], corrupt),
+        };
+
+        match fault {
+            Some(Fault::TruncateAfter(count)) => {
+                self.outgoing.extend(frame.iter().take(count));
+            }
+            Some(Fault::DelayMs(ms)) => {
+                self.ready_at = Some(Instant::now() + Duration::from_millis(ms));
+                self.outgoing.extend(frame);
+            }
+            Some(Fault::InjectBytes(bytes)) => {
+                self.outgoing.extend(bytes);
+                self.outgoing.extend(frame);
+            }
+            Some(Fault::DuplicateResponse) => {
+                self.outgoing.extend(frame.iter().chain(&frame));
+            }
+            _ => self.outgoing.extend(frame),
+        }
+    }
+
+    fn handle(&mut self, command: u8, data: &[u8]) -> Response {
+        match self.table.execute(command, data) {
+            Ok(data) => Response::Data(data),
+            Err(state) => Response::Error(state),
+        }
+    }
+}
+
+/// Builds a byte stuffed MISO frame, with a checksum that doesn't match if `corrupt`. Data
+/// that doesn't fit a frame is answered with [STATE_DATA_SIZE] instead.
+fn encode_response(address: u8, command: u8, state: u8, data: &[u8], corrupt: bool) -> Vec<u8> {
+    let frame = MISOFrame::from_parts(address, command, state, data)
+        .or_else(|_| MISOFrame::from_parts(address, command, STATE_DATA_SIZE, &[]));
+    let Ok(mut frame) = frame else {
+        return Vec::new();
+    };
+    if corrupt {
+        let checksum = frame.get_checksum().wrapping_add(1);
+        frame = frame.with_checksum(checksum);
+    }
+    frame.to_raw().to_vec()
+}
+
+/// The bytes of a string as the devices send it, terminated by a null byte
+pub fn encode_string(string: &str) -> Vec<u8> {
+    CString::new(string)
+        .map(CString::into_bytes_with_nul)
+        .unwrap_or_else(|_| vec![0])
+}
+
+/// Reads the data of a request as a big endian f32, [STATE_DATA_SIZE] if it isn't four bytes
+pub fn read_f32(data: &[u8]) -> Result<f32, u8> {
+    let bytes: [u8; 4] = data.try_into().map_err(|_| STATE_DATA_SIZE)?;
+    Ok(f32::from_be_bytes(bytes))
+}
+
+/// Reads the data of a request as a big endian u32, [STATE_DATA_SIZE] if it isn't four bytes
+pub fn read_u32(data: &[u8]) -> Result<u32, u8> {
+    let bytes: [u8; 4] = data.try_into().map_err(|_| STATE_DATA_SIZE)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shdlc::MOSIFrame;
+
+    /// Echoes the data of every request
+    #[derive(Debug, Default)]
+    struct Echo {
+        booting: bool,
+        line_baudrate: u32,
+    }
+
+    impl CommandTable for Echo {
+        fn address(&self) -> u8 {
+            2
+        }
+
+        fn execute(&mut self, _command: u8, data: &[u8]) -> Result<Vec<u8>, u8> {
+            Ok(data.to_vec())
+        }
+
+        fn set_line_baudrate(&mut self, baud_rate: u32) {
+            self.line_baudrate = baud_rate;
+        }
+
+        fn booting(&mut self) -> bool {
+            self.booting
+        }
+    }
+
+    fn send(emulator: &mut Emulator<Echo>, address: u8, data: &[u8]) -> Vec<u8> {
+        let request = MOSIFrame::new(address, 0x30, data).unwrap();
+        emulator.write_all(&request.into_raw()).unwrap();
+        let mut response = [0; 64];
+        match emulator.read(&mut response) {
+            Ok(count) => response[..count].to_vec(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_requests_for_its_address_are_answered() {
+        let mut emulator = Emulator::new(Echo::default());
+        let handle = emulator.handle();
+        let response = send(&mut emulator, 2, &[0x7E, 0x01]);
+        let expected = MISOFrame::from_parts(2, 0x30, 0, &[0x7E, 0x01]).unwrap();
+        assert_eq!(response, expected.to_raw().to_vec());
+        assert!(send(&mut emulator, 3, &[0x01]).is_empty());
+        assert_eq!(handle.requests().len(), 2);
+        assert_eq!(handle.last_request(), Some((3, 0x30, vec![0x01])));
+
+        handle.with(|echo| echo.booting = true);
+        assert!(send(&mut emulator, 2, &[0x01]).is_empty());
+        assert_eq!(handle.requests().len(), 2, "a booting device doesn't look at requests");
+    }
+
+    #[test]
+    fn faults_are_applied_to_one_response() {
+        let mut emulator = Emulator::new(Echo::default());
+        let handle = emulator.handle();
+        handle.inject_fault(Fault::CorruptChecksum);
+        let corrupted = MISOFrame::from_bytes(&send(&mut emulator, 2, &[0x01])).unwrap();
+        assert!(!corrupted.validate_checksum());
+        let response = MISOFrame::from_bytes(&send(&mut emulator, 2, &[0x01])).unwrap();
+        assert!(response.validate_checksum());
+
+        handle.inject_fault(Fault::ShortData(1));
+        let short = MISOFrame::from_bytes(&send(&mut emulator, 2, &[0x01, 0x02])).unwrap();
+        assert_eq!(short.into_data().as_slice(), &[0x01]);
+
+        handle.set_busy(true);
+        let busy = MISOFrame::from_bytes(&send(&mut emulator, 2, &[0x01])).unwrap();
+        assert_eq!(busy.get_state(), STATE_BUSY);
+    }
+
+    #[test]
+    fn an_unplugged_transport_stays_dead() {
+        let mut emulator = Emulator::new(Echo::default());
+        let handle = emulator.handle();
+        handle.unplug();
+        let error = emulator.write(&[START_STOP]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+
+        let mut replugged = handle.replug();
+        replugged.set_baud_rate(19200).unwrap();
+        assert_eq!(handle.with(|echo| echo.line_baudrate), 19200);
+        assert!(!send(&mut replugged, 2, &[0x01]).is_empty());
+        assert!(emulator.read(&mut [0; 8]).is_err());
+    }
+}
@@ -1,34 +1,244 @@
 //! Contains error types that can occur when attempting to communicate with the mass flow
 //! controller.
+use crate::capabilities::Feature;
+use crate::gasunit::GasUnit;
+#[cfg(feature = "std")]
+use crate::identity::IdentityField;
+use crate::limits::Limit;
 use crate::shdlc::TranslationError;
 
 use arrayvec::CapacityError;
 
-use std::fmt::Display;
+use core::fmt::Display;
 
 /// An aggregate error type that covers every error that can occur when attempting to communicate 
 /// with the mass flow controller.
 #[derive(Debug)]
 pub enum DeviceError {
     /// An error when writing data or reading data from the device.
+    #[cfg(feature = "std")]
     IoError(std::io::Error),
+    /// An error of an embedded-io stream when writing data or reading data from the device.
+    #[cfg(feature = "embedded-io")]
+    EmbeddedIoError(embedded_io::ErrorKind),
     ShdlcError(TranslationError),
     StateResponse(StateResponseError),
+    #[cfg(feature = "serialport")]
     PortError(serialport::Error),
     /// An Invalid Checksum. The first value of the tuple is the recivied checksum and the second
     /// value was the expected checksum.
     InvalidChecksum(u8, u8),
     /// An invalid string was sent from the device. Either missing the null terminator byte
-    /// or was not valid ASCII.
+    /// or was not valid ASCII. Bytes after the null terminator are ignored.
     InvalidString,
+    /// The device did not start answering within the response timeout
+    Timeout,
+    /// The device started a response but stopped sending before the frame was complete
+    IncompleteFrame,
+    /// The command was abandoned because it could not finish before the deadline it was given
+    DeadlineExceeded,
+    /// A command still failed after retrying it. The first value of the tuple is the number of
+    /// attempts made and the second value is the error of the last attempt. Without std the
+    /// error of the last attempt is returned instead.
+    #[cfg(feature = "std")]
+    RetriesExhausted(u32, Box<DeviceError>),
+    /// A value in the given unit is a mass flow, a pressure or has no timebase, so it can't be
+    /// converted into a volume rate
+    #[cfg(feature = "uom")]
+    NotAVolumeRate(GasUnit),
+    /// A value in the given unit is not a pressure, or the device is not calibrated for one
+    NotAPressure(GasUnit),
+    /// No valid calibration on the device is for the gas with this ID
+    NoCalibrationForGas(u32),
+    /// The raw thermal conductivity measured does not match the gas that should be plumbed. The
+    /// first value of the tuple is the measured value and the second value was the expected
+    /// value, both in ticks.
+    GasMismatch(u16, u16),
+    /// The device is not the one an [IdentityFilter](crate::identity::IdentityFilter) asks for,
+    /// `found` is what it answered for the `field` that doesn't match `expected`
+    #[cfg(feature = "std")]
+    IdentityMismatch {
+        field: IdentityField,
+        expected: String,
+        found: String,
+    },
+    /// The firmware of the device, as major and minor version, doesn't support the feature.
+    /// Nothing was sent to the device, see [crate::capabilities].
+    UnsupportedFirmware { feature: Feature, firmware: (u8, u8) },
+    /// The device still answered with [StateResponseError::SensorBusy] after the command was
+    /// sent again for this long, the budget of the busy policy of the connection
+    StillBusy(core::time::Duration),
+    /// The setpoint, the first value of the tuple, is outside the soft limit set on the host,
+    /// the second value. Nothing was sent to the device. See [crate::limits].
+    SoftLimit(f32, Limit),
+    /// The baudrate is not one the devices support, see [crate::baudrate]
+    UnsupportedBaudrate(u32),
+    /// A response from the address, the first value of the tuple, to the command, the second
+    /// value, that doesn't match the request. Only returned with `ValidationLevel::Strict`,
+    /// otherwise such a response is skipped as a late answer to an earlier request.
+    UnexpectedResponse(u8, u8),
+    /// The command, named by the value, changes the device and the device is in read-only mode.
+    /// Nothing was sent to the device.
+    ReadOnly(&'static str),
+    /// A [FrameMiddleware](crate::middleware::FrameMiddleware) of the connection refused to send
+    /// the command, the first value of the tuple, for the reason that is the second value.
+    /// Nothing was sent to the device.
+    #[cfg(feature = "std")]
+    BlockedByMiddleware(u8, String),
+    /// A [PendingCommand](crate::connection::PendingCommand) was polled again after it returned
+    /// the result of the command
+    #[cfg(feature = "std")]
+    AlreadyFinished,
+}
+
+impl DeviceError {
+    /// A number that identifies the error for FFI, exit codes and alerting, stable across
+    /// releases. Codes are only ever added, a code once given keeps its meaning. The high byte
+    /// groups them:
+    ///
+    /// | Codes | Errors |
+    /// | --- | --- |
+    /// | `0x0101..` | the link: I/O, the port, timeouts and retries |
+    /// | `0x0201..` | the frames and responses, `0x0210..` for each [TranslationError] |
+    /// | `0x0300..=0x037F` | the state a device answered with, see [StateResponseError::code] |
+    /// | `0x0380..` | other conditions of the device, found by the host |
+    /// | `0x0401..` | checks on the host before anything was sent |
+    ///
+    /// ```
+    /// use sfc_core::error::{DeviceError, StateResponseError};
+    ///
+    /// assert_eq!(DeviceError::Timeout.code(), 0x0104);
+    /// assert_eq!(DeviceError::StateResponse(StateResponseError::SensorBusy).code(), 0x0342);
+    /// ```
+    pub fn code(&self) -> u16 {
+        match self {
+            #[cfg(feature = "std")]
+            Self::IoError(_) => 0x0101,
+            #[cfg(feature = "serialport")]
+            Self::PortError(_) => 0x0102,
+            #[cfg(feature = "embedded-io")]
+            Self::EmbeddedIoError(_) => 0x0103,
+            Self::Timeout => 0x0104,
+            Self::IncompleteFrame => 0x0105,
+            Self::DeadlineExceeded => 0x0106,
+            #[cfg(feature = "std")]
+            Self::RetriesExhausted(..) => 0x0107,
+            Self::InvalidChecksum(..) => 0x0201,
+            Self::InvalidString => 0x0202,
+            Self::UnexpectedResponse(..) => 0x0203,
+            Self::ShdlcError(e) => 0x0210 + e.code(),
+            Self::StateResponse(e) => 0x0300 | e.code(),
+            Self::NoCalibrationForGas(_) => 0x0380,
+            Self::GasMismatch(..) => 0x0381,
+            #[cfg(feature = "std")]
+            Self::IdentityMismatch { .. } => 0x0382,
+            Self::UnsupportedFirmware { .. } => 0x0383,
+            Self::StillBusy(_) => 0x0384,
+            Self::SoftLimit(..) => 0x0401,
+            Self::UnsupportedBaudrate(_) => 0x0402,
+            Self::NotAPressure(_) => 0x0403,
+            #[cfg(feature = "uom")]
+            Self::NotAVolumeRate(_) => 0x0404,
+            Self::ReadOnly(_) => 0x0405,
+            #[cfg(feature = "std")]
+            Self::BlockedByMiddleware(..) => 0x0406,
+            #[cfg(feature = "std")]
+            Self::AlreadyFinished => 0x0407,
+        }
+    }
+
+    /// The coarse class of the error, see [ErrorCategory]. A [DeviceError::RetriesExhausted]
+    /// is in the class of the error of its last attempt.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            #[cfg(feature = "std")]
+            Self::RetriesExhausted(_, last) => last.category(),
+            // too much data only ever comes from the caller, the rest from a response
+            Self::ShdlcError(TranslationError::DataTooLarge) => ErrorCategory::Usage,
+            Self::ShdlcError(_)
+            | Self::InvalidChecksum(..)
+            | Self::InvalidString
+            | Self::UnexpectedResponse(..) => ErrorCategory::Protocol,
+            Self::StateResponse(_)
+            | Self::NoCalibrationForGas(_)
+            | Self::GasMismatch(..)
+            | Self::UnsupportedFirmware { .. }
+            | Self::StillBusy(_) => ErrorCategory::Device,
+            #[cfg(feature = "std")]
+            Self::IdentityMismatch { .. } => ErrorCategory::Device,
+            Self::SoftLimit(..)
+            | Self::UnsupportedBaudrate(_)
+            | Self::NotAPressure(_)
+            | Self::ReadOnly(_) => ErrorCategory::Usage,
+            #[cfg(feature = "uom")]
+            Self::NotAVolumeRate(_) => ErrorCategory::Usage,
+            #[cfg(feature = "std")]
+            Self::BlockedByMiddleware(..) | Self::AlreadyFinished => ErrorCategory::Usage,
+            _ => ErrorCategory::Transport,
+        }
+    }
+
+    /// Whether the device refused the command because its configuration is locked, see
+    /// [StateResponseError::is_configuration_locked]
+    pub fn is_configuration_locked(&self) -> bool {
+        match self {
+            #[cfg(feature = "std")]
+            Self::RetriesExhausted(_, last) => last.is_configuration_locked(),
+            Self::StateResponse(e) => e.is_configuration_locked(),
+            _ => false,
+        }
+    }
+}
+
+/// The coarse class of a [DeviceError], for deciding what to do about it without matching
+/// every variant
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The line or port failed, or the device didn't answer in time. Retrying or reconnecting
+    /// may help.
+    Transport,
+    /// A response arrived but was garbled or didn't match the request
+    Protocol,
+    /// The device answered with an error, or its state doesn't fit what was asked
+    Device,
+    /// The request was refused on the host before it was sent, like a setpoint beyond a soft
+    /// limit. Sending it again won't help.
+    Usage,
+}
+
+impl Display for ErrorCategory {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Transport => write!(f, "transport"),
+            Self::Protocol => write!(f, "protocol"),
+            Self::Device => write!(f, "device"),
+            Self::Usage => write!(f, "usage"),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ErrorCategory {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Transport => defmt::write!(f, "transport"),
+            Self::Protocol => defmt::write!(f, "protocol"),
+            Self::Device => defmt::write!(f, "device"),
+            Self::Usage => defmt::write!(f, "usage"),
+        }
+    }
 }
 
 impl Display for DeviceError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
+            #[cfg(feature = "std")]
             Self::IoError(e) => e.fmt(f),
+            #[cfg(feature = "embedded-io")]
+            Self::EmbeddedIoError(kind) => write!(f, "the stream to the device failed: {:?}", kind),
             Self::ShdlcError(e) => e.fmt(f),
             Self::StateResponse(e) => e.fmt(f),
+            #[cfg(feature = "serialport")]
             Self::PortError(e) => e.fmt(f),
             Self::InvalidChecksum(recived, expected) => write!(
                 f,
@@ -36,10 +246,183 @@ impl Display for DeviceError {
                 recived, expected
             ),
             Self::InvalidString => write!(f, "invalid string data found"),
+            Self::Timeout => write!(f, "the device did not respond in time"),
+            Self::IncompleteFrame => write!(f, "the device stopped sending in the middle of a frame"),
+            Self::DeadlineExceeded => write!(f, "the command did not finish before its deadline"),
+            #[cfg(feature = "std")]
+            Self::RetriesExhausted(attempts, last) => {
+                write!(f, "command failed after {} attempts, last error: {}", attempts, last)
+            }
+            #[cfg(feature = "uom")]
+            Self::NotAVolumeRate(unit) => write!(f, "a value in {} is not a volume rate", unit),
+            Self::NotAPressure(unit) => write!(f, "a value in {} is not a pressure", unit),
+            Self::NoCalibrationForGas(gas_id) => {
+                write!(f, "no valid calibration for gas {} on the device", gas_id)
+            }
+            Self::GasMismatch(measured, expected) => write!(
+                f,
+                "thermal conductivity of {} ticks does not match the expected {} ticks",
+                measured, expected
+            ),
+            #[cfg(feature = "std")]
+            Self::IdentityMismatch {
+                field,
+                expected,
+                found,
+            } => write!(f, "the device's {} {:?} does not match {:?}", field, found, expected),
+            Self::UnsupportedFirmware {
+                feature,
+                firmware: (major, minor),
+            } => write!(f, "firmware {}.{} does not support {}", major, minor, feature),
+            Self::StillBusy(waited) => {
+                write!(f, "the sensor was still busy after waiting {:?}", waited)
+            }
+            Self::SoftLimit(setpoint, limit) => {
+                write!(f, "setpoint {} is beyond the {}", setpoint, limit)
+            }
+            Self::UnsupportedBaudrate(baudrate) => write!(
+                f,
+                "{} baud is not supported, the devices take 19200, 38400, 57600 and 115200",
+                baudrate
+            ),
+            Self::UnexpectedResponse(address, command) => write!(
+                f,
+                "unexpected response from address {} to command {:#04x}",
+                address, command
+            ),
+            Self::ReadOnly(command) => {
+                write!(f, "the device is read-only, {} was not sent", command)
+            }
+            #[cfg(feature = "std")]
+            Self::BlockedByMiddleware(command, reason) => {
+                write!(f, "command {:#04x} was blocked by middleware: {}", command, reason)
+            }
+            #[cfg(feature = "std")]
+            Self::AlreadyFinished => write!(f, "the command already finished"),
+        }
+    }
+}
+
+/// `core::error::Error`, so also `std::error::Error`, with and without std. The source is the
+/// error a variant wraps: the error of the port or stream, the translation or state error and
+/// the error of the last attempt of [DeviceError::RetriesExhausted].
+impl core::error::Error for DeviceError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Self::IoError(e) => Some(e),
+            Self::ShdlcError(e) => Some(e),
+            Self::StateResponse(e) => Some(e),
+            #[cfg(feature = "serialport")]
+            Self::PortError(e) => Some(e),
+            #[cfg(feature = "std")]
+            Self::RetriesExhausted(_, last) => Some(last.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for DeviceError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            #[cfg(feature = "std")]
+            Self::IoError(e) => defmt::write!(f, "{}", defmt::Display2Format(e)),
+            #[cfg(feature = "embedded-io")]
+            Self::EmbeddedIoError(kind) => {
+                defmt::write!(f, "the stream to the device failed: {}", kind)
+            }
+            Self::ShdlcError(e) => defmt::write!(f, "{}", e),
+            Self::StateResponse(e) => defmt::write!(f, "{}", e),
+            #[cfg(feature = "serialport")]
+            Self::PortError(e) => defmt::write!(f, "{}", defmt::Display2Format(e)),
+            Self::InvalidChecksum(recived, expected) => defmt::write!(
+                f,
+                "checksum recived: {=u8:#x} did not match expected value: {=u8:#x}",
+                recived,
+                expected
+            ),
+            Self::InvalidString => defmt::write!(f, "invalid string data found"),
+            Self::Timeout => defmt::write!(f, "the device did not respond in time"),
+            Self::IncompleteFrame => {
+                defmt::write!(f, "the device stopped sending in the middle of a frame")
+            }
+            Self::DeadlineExceeded => {
+                defmt::write!(f, "the command did not finish before its deadline")
+            }
+            #[cfg(feature = "std")]
+            Self::RetriesExhausted(attempts, last) => defmt::write!(
+                f,
+                "command failed after {} attempts, last error: {}",
+                attempts,
+                **last
+            ),
+            #[cfg(feature = "uom")]
+            Self::NotAVolumeRate(unit) => {
+                defmt::write!(f, "a value in {} is not a volume rate", unit)
+            }
+            Self::NotAPressure(unit) => defmt::write!(f, "a value in {} is not a pressure", unit),
+            Self::NoCalibrationForGas(gas_id) => {
+                defmt::write!(f, "no valid calibration for gas {} on the device", gas_id)
+            }
+            Self::GasMismatch(measured, expected) => defmt::write!(
+                f,
+                "thermal conductivity of {} ticks does not match the expected {} ticks",
+                measured,
+                expected
+            ),
+            #[cfg(feature = "std")]
+            Self::IdentityMismatch {
+                field,
+                expected,
+                found,
+            } => defmt::write!(
+                f,
+                "the device's {} {=str:?} does not match {=str:?}",
+                field,
+                found.as_str(),
+                expected.as_str()
+            ),
+            Self::UnsupportedFirmware {
+                feature,
+                firmware: (major, minor),
+            } => defmt::write!(f, "firmware {}.{} does not support {}", major, minor, feature),
+            Self::StillBusy(waited) => defmt::write!(
+                f,
+                "the sensor was still busy after waiting {=u64}ms",
+                waited.as_millis() as u64
+            ),
+            Self::SoftLimit(setpoint, limit) => {
+                defmt::write!(f, "setpoint {} is beyond the {}", setpoint, limit)
+            }
+            Self::UnsupportedBaudrate(baudrate) => defmt::write!(
+                f,
+                "{} baud is not supported, the devices take 19200, 38400, 57600 and 115200",
+                baudrate
+            ),
+            Self::UnexpectedResponse(address, command) => defmt::write!(
+                f,
+                "unexpected response from address {} to command {=u8:#x}",
+                address,
+                command
+            ),
+            Self::ReadOnly(command) => {
+                defmt::write!(f, "the device is read-only, {=str} was not sent", command)
+            }
+            #[cfg(feature = "std")]
+            Self::BlockedByMiddleware(command, reason) => defmt::write!(
+                f,
+                "command {=u8:#x} was blocked by middleware: {=str}",
+                command,
+                reason.as_str()
+            ),
+            #[cfg(feature = "std")]
+            Self::AlreadyFinished => defmt::write!(f, "the command already finished"),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for DeviceError {
     fn from(value: std::io::Error) -> Self {
         Self::IoError(value)
@@ -58,6 +441,7 @@ impl From<StateResponseError> for DeviceError {
     }
 }
 
+#[cfg(feature = "serialport")]
 impl From<serialport::Error> for DeviceError {
     fn from(value: serialport::Error) -> Self {
         Self::PortError(value)
@@ -71,13 +455,20 @@ impl From<CapacityError> for DeviceError {
 }
 
 /// Errors sent back from a MISO frame.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StateResponseError {
     /// Illegal data size of the MOSI frame. Either an invalid frame was sent or
     /// the firmware does not support the requested feature
     DataSizeError,
     /// The device does not know this command.
     UnknownCommand,
+    /// The command needs an access level the device isn't at. An SFC5xxx locked against
+    /// configuration changes refuses the commands that change its stored settings with it.
+    NoAccessRight,
+    /// The stored configuration of the device is locked, a change of it is refused
+    ConfigurationLocked,
+    /// The device is unlocked, but at a lower access level than the command needs
+    AccessLevelTooLow,
     /// A sent parameter is out of range.
     ParameterError,
     /// NACK recived from the I2C device.
@@ -105,7 +496,10 @@ impl From<u8> for StateResponseError {
         match value {
             0x01 => Self::DataSizeError,
             0x02 => Self::UnknownCommand,
+            0x03 => Self::NoAccessRight,
             0x04 => Self::ParameterError,
+            0x20 => Self::ConfigurationLocked,
+            0x21 => Self::AccessLevelTooLow,
             0x29 => Self::I2CNackError,
             0x2A => Self::I2CMasterHoldError,
             0x2B => Self::CRCError,
@@ -120,12 +514,55 @@ impl From<u8> for StateResponseError {
     }
 }
 
+impl StateResponseError {
+    /// The error code the device sends in the state byte, [StateResponseError::FatalError] for
+    /// the codes this crate doesn't know. Part of [DeviceError::code].
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::DataSizeError => 0x01,
+            Self::UnknownCommand => 0x02,
+            Self::NoAccessRight => 0x03,
+            Self::ParameterError => 0x04,
+            Self::ConfigurationLocked => 0x20,
+            Self::AccessLevelTooLow => 0x21,
+            Self::I2CNackError => 0x29,
+            Self::I2CMasterHoldError => 0x2A,
+            Self::CRCError => 0x2B,
+            Self::DataWriteError => 0x2C,
+            Self::MeasureLoopNotRunning => 0x2D,
+            Self::CommandNotAllowed => 0x32,
+            Self::InvalidCalibration => 0x33,
+            Self::SensorBusy => 0x42,
+            Self::FatalError => 0x7F,
+        }
+    }
+
+    /// Whether the device refused the command because its configuration is locked, with any
+    /// of the access level states
+    pub fn is_configuration_locked(&self) -> bool {
+        matches!(
+            self,
+            Self::NoAccessRight | Self::ConfigurationLocked | Self::AccessLevelTooLow
+        )
+    }
+}
+
 impl Display for StateResponseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::DataSizeError => write!(f, "illegal data size of MOSI frame or invalid frame"),
             Self::UnknownCommand => write!(f, "the device does not support or know this command"),
+            Self::NoAccessRight => write!(
+                f,
+                "no access right for the command, the configuration of the device is locked"
+            ),
             Self::ParameterError => write!(f, "the sent parameter was out of range"),
+            Self::ConfigurationLocked => {
+                write!(f, "the configuration of the device is locked against changes")
+            }
+            Self::AccessLevelTooLow => {
+                write!(f, "the command needs a higher access level than the device is unlocked to")
+            }
             Self::I2CNackError => write!(f, "NACK recived from the I2C device"),
             Self::I2CMasterHoldError => write!(f, "master hold not released from I2C device"),
             Self::CRCError => write!(f, "checksum miss match occured"),
@@ -144,3 +581,258 @@ impl Display for StateResponseError {
         }
     }
 }
+
+impl core::error::Error for StateResponseError {}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for StateResponseError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::DataSizeError => {
+                defmt::write!(f, "illegal data size of MOSI frame or invalid frame")
+            }
+            Self::UnknownCommand => {
+                defmt::write!(f, "the device does not support or know this command")
+            }
+            Self::NoAccessRight => defmt::write!(
+                f,
+                "no access right for the command, the configuration of the device is locked"
+            ),
+            Self::ParameterError => defmt::write!(f, "the sent parameter was out of range"),
+            Self::ConfigurationLocked => {
+                defmt::write!(f, "the configuration of the device is locked against changes")
+            }
+            Self::AccessLevelTooLow => defmt::write!(
+                f,
+                "the command needs a higher access level than the device is unlocked to"
+            ),
+            Self::I2CNackError => defmt::write!(f, "NACK recived from the I2C device"),
+            Self::I2CMasterHoldError => {
+                defmt::write!(f, "master hold not released from I2C device")
+            }
+            Self::CRCError => defmt::write!(f, "checksum miss match occured"),
+            Self::DataWriteError => {
+                defmt::write!(f, "sensor data read back differs from written value")
+            }
+            Self::MeasureLoopNotRunning => defmt::write!(
+                f,
+                "sensor mesaure loop not running or runs on wrong gas number"
+            ),
+            Self::InvalidCalibration => {
+                defmt::write!(f, "no valid gas calibration at given index")
+            }
+            Self::SensorBusy => defmt::write!(
+                f,
+                "the sensor is busy at the moment, it takes 300ms to power-up after reset"
+            ),
+            Self::CommandNotAllowed => {
+                defmt::write!(f, "command is not allowed in the current state")
+            }
+            Self::FatalError => defmt::write!(f, "an error without a specific code occured"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    /// One error of every variant, with the code and category it has to keep
+    fn golden() -> Vec<(DeviceError, u16, ErrorCategory)> {
+        use ErrorCategory::*;
+        let unit = GasUnit::from_be_bytes([0, 0, 0]);
+        let state = |error| DeviceError::StateResponse(error);
+        vec![
+            (DeviceError::IoError(std::io::Error::other("gone")), 0x0101, Transport),
+            (DeviceError::Timeout, 0x0104, Transport),
+            (DeviceError::IncompleteFrame, 0x0105, Transport),
+            (DeviceError::DeadlineExceeded, 0x0106, Transport),
+            (DeviceError::RetriesExhausted(3, Box::new(DeviceError::Timeout)), 0x0107, Transport),
+            (DeviceError::InvalidChecksum(1, 2), 0x0201, Protocol),
+            (DeviceError::InvalidString, 0x0202, Protocol),
+            (DeviceError::UnexpectedResponse(0, 0x08), 0x0203, Protocol),
+            (TranslationError::DataTooLarge.into(), 0x0210, Usage),
+            (TranslationError::NotEnoughData(4, 2).into(), 0x0211, Protocol),
+            (TranslationError::MissingEscapedData(0x00).into(), 0x0212, Protocol),
+            (TranslationError::FrameEndInData.into(), 0x0213, Protocol),
+            (TranslationError::NoData.into(), 0x0214, Protocol),
+            (TranslationError::TrailingData(1).into(), 0x0215, Protocol),
+            (TranslationError::MissingDelimiter.into(), 0x0216, Protocol),
+            (TranslationError::UnknownValue(9).into(), 0x0217, Protocol),
+            (TranslationError::TooMuchData(4, 5).into(), 0x0218, Protocol),
+            (state(StateResponseError::DataSizeError), 0x0301, Device),
+            (state(StateResponseError::UnknownCommand), 0x0302, Device),
+            (state(StateResponseError::NoAccessRight), 0x0303, Device),
+            (state(StateResponseError::ParameterError), 0x0304, Device),
+            (state(StateResponseError::ConfigurationLocked), 0x0320, Device),
+            (state(StateResponseError::AccessLevelTooLow), 0x0321, Device),
+            (state(StateResponseError::I2CNackError), 0x0329, Device),
+            (state(StateResponseError::I2CMasterHoldError), 0x032A, Device),
+            (state(StateResponseError::CRCError), 0x032B, Device),
+            (state(StateResponseError::DataWriteError), 0x032C, Device),
+            (state(StateResponseError::MeasureLoopNotRunning), 0x032D, Device),
+            (state(StateResponseError::CommandNotAllowed), 0x0332, Device),
+            (state(StateResponseError::InvalidCalibration), 0x0333, Device),
+            (state(StateResponseError::SensorBusy), 0x0342, Device),
+            (state(StateResponseError::FatalError), 0x037F, Device),
+            (DeviceError::NoCalibrationForGas(7), 0x0380, Device),
+            (DeviceError::GasMismatch(1, 2), 0x0381, Device),
+            (
+                DeviceError::IdentityMismatch {
+                    field: IdentityField::SerialNumber,
+                    expected: "EMU6000001".to_string(),
+                    found: "EMU6000002".to_string(),
+                },
+                0x0382,
+                Device,
+            ),
+            (
+                DeviceError::UnsupportedFirmware {
+                    feature: Feature::TwoSensors,
+                    firmware: (1, 4),
+                },
+                0x0383,
+                Device,
+            ),
+            (DeviceError::StillBusy(Duration::from_millis(500)), 0x0384, Device),
+            (DeviceError::SoftLimit(5.0, Limit::Max(4.0)), 0x0401, Usage),
+            (DeviceError::UnsupportedBaudrate(9600), 0x0402, Usage),
+            (DeviceError::NotAPressure(unit), 0x0403, Usage),
+            (DeviceError::ReadOnly("setpoint"), 0x0405, Usage),
+            (DeviceError::BlockedByMiddleware(0x91, "blacklisted".to_string()), 0x0406, Usage),
+            (DeviceError::AlreadyFinished, 0x0407, Usage),
+            #[cfg(feature = "serialport")]
+            (
+                DeviceError::PortError(serialport::Error::new(serialport::ErrorKind::NoDevice, "")),
+                0x0102,
+                Transport,
+            ),
+            #[cfg(feature = "embedded-io")]
+            (DeviceError::EmbeddedIoError(embedded_io::ErrorKind::Other), 0x0103, Transport),
+            #[cfg(feature = "uom")]
+            (DeviceError::NotAVolumeRate(unit), 0x0404, Usage),
+        ]
+    }
+
+    /// Doesn't compile once a variant is added, so it gets a code and a row in [golden]
+    fn covered(error: &DeviceError) {
+        match error {
+            DeviceError::IoError(_)
+            | DeviceError::ShdlcError(_)
+            | DeviceError::StateResponse(_)
+            | DeviceError::InvalidChecksum(..)
+            | DeviceError::InvalidString
+            | DeviceError::Timeout
+            | DeviceError::IncompleteFrame
+            | DeviceError::DeadlineExceeded
+            | DeviceError::RetriesExhausted(..)
+            | DeviceError::NotAPressure(_)
+            | DeviceError::NoCalibrationForGas(_)
+            | DeviceError::GasMismatch(..)
+            | DeviceError::IdentityMismatch { .. }
+            | DeviceError::UnsupportedFirmware { .. }
+            | DeviceError::StillBusy(_)
+            | DeviceError::SoftLimit(..)
+            | DeviceError::UnsupportedBaudrate(_)
+            | DeviceError::UnexpectedResponse(..)
+            | DeviceError::ReadOnly(_)
+            | DeviceError::BlockedByMiddleware(..)
+            | DeviceError::AlreadyFinished => {}
+            #[cfg(feature = "serialport")]
+            DeviceError::PortError(_) => {}
+            #[cfg(feature = "embedded-io")]
+            DeviceError::EmbeddedIoError(_) => {}
+            #[cfg(feature = "uom")]
+            DeviceError::NotAVolumeRate(_) => {}
+        }
+    }
+
+    #[test]
+    fn codes_and_categories_never_change() {
+        for (error, code, category) in golden() {
+            covered(&error);
+            assert_eq!(error.code(), code, "{:?}", error);
+            assert_eq!(error.category(), category, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn every_error_has_its_own_code() {
+        let table = golden();
+        let codes: HashSet<_> = table.iter().map(|(error, ..)| error.code()).collect();
+        assert_eq!(codes.len(), table.len());
+    }
+
+    #[test]
+    fn state_codes_are_the_bytes_the_device_sends() {
+        for (error, ..) in golden() {
+            if let DeviceError::StateResponse(state) = error {
+                assert_eq!(StateResponseError::from(state.code() as u8), state);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_compose_with_boxed_errors() {
+        fn read() -> Result<u32, DeviceError> {
+            Err(DeviceError::IoError(std::io::Error::other("gone")))
+        }
+        fn run() -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(read()?)
+        }
+        let error = run().unwrap_err();
+        let error = error.downcast_ref::<DeviceError>().unwrap();
+        let source = std::error::Error::source(error).unwrap();
+        assert_eq!(source.to_string(), "gone");
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn sources_follow_the_wrapped_errors() {
+        use std::error::Error;
+
+        let busy = StateResponseError::SensorBusy;
+        let error = DeviceError::RetriesExhausted(3, Box::new(busy.into()));
+        let last = error.source().unwrap();
+        assert!(matches!(last.downcast_ref(), Some(DeviceError::StateResponse(_))));
+        assert_eq!(last.source().unwrap().downcast_ref(), Some(&busy));
+        assert!(last.source().unwrap().source().is_none());
+
+        let error = DeviceError::from(TranslationError::NoData);
+        assert_eq!(error.source().unwrap().downcast_ref(), Some(&TranslationError::NoData));
+        for (error, ..) in golden() {
+            let wraps = matches!(
+                error,
+                DeviceError::IoError(_)
+                    | DeviceError::ShdlcError(_)
+                    | DeviceError::StateResponse(_)
+                    | DeviceError::RetriesExhausted(..)
+            );
+            #[cfg(feature = "serialport")]
+            let wraps = wraps || matches!(error, DeviceError::PortError(_));
+            assert_eq!(error.source().is_some(), wraps, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn retries_exhausted_is_in_the_class_of_the_last_attempt() {
+        let busy = DeviceError::StateResponse(StateResponseError::SensorBusy);
+        let error = DeviceError::RetriesExhausted(3, Box::new(busy));
+        assert_eq!(error.category(), ErrorCategory::Device);
+        assert_eq!(error.code(), 0x0107);
+    }
+
+    #[test]
+    fn access_level_states_are_a_locked_configuration() {
+        for code in [0x03, 0x20, 0x21] {
+            let error = DeviceError::StateResponse(StateResponseError::from(code));
+            assert!(error.is_configuration_locked(), "{:?}", error);
+            let error = DeviceError::RetriesExhausted(2, Box::new(error));
+            assert!(error.is_configuration_locked());
+        }
+        assert!(!DeviceError::from(StateResponseError::ParameterError).is_configuration_locked());
+        assert!(!DeviceError::ReadOnly("setpoint").is_configuration_locked());
+    }
+}
@@ -0,0 +1,557 @@
+//! The parts of the request/response cycle that don't depend on how bytes reach the device,
+//! shared by the blocking [Connection](crate::connection::Connection) and the async connection.
+//! Nothing in here needs std.
+
+use core::time::Duration;
+
+use crate::error::{DeviceError, StateResponseError};
+use crate::shdlc::{CommandKind, FrameDecoder, Idempotency, MISOFrameRef, TranslationError};
+
+/// The default time a device has to start answering a request
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_millis(600);
+/// The default time allowed between two bytes of a response
+pub const DEFAULT_INTER_BYTE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// The command used to check that a device answers after resynchronizing, Get Version is
+/// implemented by every SHDLC device
+pub(crate) const PROBE_COMMAND: u8 = 0xD1;
+/// How often the probe is sent before a resynchronization gives up
+pub(crate) const PROBE_ATTEMPTS: u32 = 3;
+
+/// Controls if and how often a failed command is sent again. Only errors caused by the
+/// transmission are retried, an error state returned by the device (like
+/// [StateResponseError::ParameterError]) would just be returned again and never is. Commands
+/// marked [Idempotency::NonIdempotent] are only retried with
+/// [RetryConfig::retry_non_idempotent].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryConfig {
+    /// How often a command is sent at most, including the first attempt
+    pub max_attempts: u32,
+    /// How long to wait before sending the command again
+    pub delay: Duration,
+    /// Retry when the device did not answer in time ([DeviceError::Timeout])
+    pub on_timeout: bool,
+    /// Retry when the response had a wrong checksum ([DeviceError::InvalidChecksum])
+    pub on_checksum_error: bool,
+    /// Retry when the response was cut off or could not be decoded
+    /// ([DeviceError::IncompleteFrame] and [DeviceError::ShdlcError])
+    pub on_framing_error: bool,
+    /// Resynchronize with the device (see
+    /// [Connection::resync](crate::connection::Connection::resync)) before the next attempt once
+    /// this many attempts in a row failed with a checksum or framing error. [None] never does.
+    pub resync_after: Option<u32>,
+    /// Retry commands that are not idempotent too, like a reset or a new slave address. A lost
+    /// response doesn't tell whether the device executed the command, so the retry may execute
+    /// it a second time.
+    pub retry_non_idempotent: bool,
+}
+
+impl RetryConfig {
+    /// Returns true if a command that failed with this error should be sent again
+    pub fn should_retry(&self, error: &DeviceError) -> bool {
+        match error {
+            DeviceError::Timeout => self.on_timeout,
+            DeviceError::InvalidChecksum(_, _) => self.on_checksum_error,
+            DeviceError::IncompleteFrame | DeviceError::ShdlcError(_) => self.on_framing_error,
+            _ => false,
+        }
+    }
+
+    /// Returns true if commands of this idempotency are retried at all
+    pub fn retries(&self, idempotency: Idempotency) -> bool {
+        idempotency == Idempotency::Idempotent || self.retry_non_idempotent
+    }
+}
+
+/// Returns true if the error means the two ends may disagree on where a frame starts
+pub(crate) fn is_framing_error(error: &DeviceError) -> bool {
+    matches!(
+        error,
+        DeviceError::InvalidChecksum(_, _) | DeviceError::IncompleteFrame | DeviceError::ShdlcError(_)
+    )
+}
+
+impl Default for RetryConfig {
+    /// Three attempts 10ms apart, retrying every transmission error of idempotent commands
+    /// without resynchronizing
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            delay: Duration::from_millis(10),
+            on_timeout: true,
+            on_checksum_error: true,
+            on_framing_error: true,
+            resync_after: None,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+/// How a device that is still starting up after a reset is waited for. Some firmware takes
+/// requests before it finished booting and answers them with
+/// [StateResponseError::FatalError]. Within `window` of a reset sent through the same connection
+/// that error means "not yet" and the command is sent again, first after `initial_backoff` and
+/// then twice as long each time up to `max_backoff`. Outside the window, or without a reset, it
+/// is as fatal as ever. This doesn't depend on a [RetryConfig].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BootGrace {
+    /// How long after a reset a fatal error is taken for a device that is still booting
+    pub window: Duration,
+    /// How long to wait before the first attempt after a fatal error
+    pub initial_backoff: Duration,
+    /// The longest wait between two attempts
+    pub max_backoff: Duration,
+}
+
+#[cfg(feature = "std")]
+impl BootGrace {
+    /// Returns true if the error, received this long after the last reset, means the device is
+    /// still booting
+    pub fn is_booting(&self, error: &DeviceError, since_reset: Duration) -> bool {
+        matches!(error, DeviceError::StateResponse(StateResponseError::FatalError))
+            && since_reset < self.window
+    }
+
+    /// How long to wait before the next attempt after this many attempts while booting
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for BootGrace {
+    /// A second after the reset, three times the 300ms a device needs, waiting 10ms at first and
+    /// 160ms at most
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(160),
+        }
+    }
+}
+
+/// How long a command the device answers with [StateResponseError::SensorBusy] is sent again.
+/// The sensor is in the middle of something and takes the command once it is done, so the
+/// command is sent every `poll_interval` until it is taken or `max_wait` has passed since the
+/// first busy answer. Then the command fails with [DeviceError::StillBusy].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusyPolicy {
+    /// How long the command is sent again at most
+    pub max_wait: Duration,
+    /// How long to wait before sending it again
+    pub poll_interval: Duration,
+}
+
+#[cfg(feature = "std")]
+impl BusyPolicy {
+    /// Waits up to `max_wait`, sending the command every `poll_interval`
+    pub const fn new(max_wait: Duration, poll_interval: Duration) -> Self {
+        Self {
+            max_wait,
+            poll_interval,
+        }
+    }
+}
+
+/// Returns true if the device answered that it is busy
+#[cfg(feature = "std")]
+pub(crate) fn is_busy(error: &DeviceError) -> bool {
+    matches!(error, DeviceError::StateResponse(StateResponseError::SensorBusy))
+}
+
+/// How closely a response is checked before its data is used. A check either fails the command,
+/// is reported as a warning through the `log` and `tracing` features and otherwise ignored, or
+/// is skipped altogether:
+///
+/// | Check | Strict | Standard | Lenient |
+/// |---|---|---|---|
+/// | Frame decodes and carries the declared data length | error | error | error |
+/// | Address and command echo the request | error | skipped with a warning | skipped |
+/// | No bytes between the data and the checksum | error | warning | ignored |
+/// | Checksum | error | error | warning |
+/// | State byte is zero | error | error | error |
+///
+/// The checksum is checked before the state, so a corrupted response is a
+/// [DeviceError::InvalidChecksum], which can be retried, rather than an error state the device
+/// never sent. A response that fails the echo check is a late answer to an earlier request: with
+/// [ValidationLevel::Strict] it fails with [DeviceError::UnexpectedResponse] instead of being
+/// skipped, so a pipelined connection (stale input kept) gets the error as soon as a late
+/// answer is read. Extra bytes are reported as [TranslationError::TrailingData].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValidationLevel {
+    /// Every check fails the command
+    Strict,
+    /// The checks the connection has always made, with warnings for what it lets through
+    #[default]
+    Standard,
+    /// Only what is needed to read the data, for firmware that pads or miscomputes its
+    /// responses
+    Lenient,
+}
+
+/// How long the device has to start answering, by the [CommandKind] of the request. A reset or
+/// a long average takes the device far longer than reading a setting, a single timeout had to
+/// be as long as the slowest command and let a dead device hold up every query for as long.
+/// The default gives every kind [DEFAULT_RESPONSE_TIMEOUT].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Commands answered from memory, like reading a setting or the serial number
+    pub query: Duration,
+    /// A single measurement
+    pub measurement: Duration,
+    /// An averaged measurement, before `per_averaged_value` is added for each value averaged
+    pub averaged_measurement: Duration,
+    /// Added to `averaged_measurement` for every value the device averages
+    pub per_averaged_value: Duration,
+    /// A reset or a switch of the calibration
+    pub reset: Duration,
+}
+
+impl Timeouts {
+    /// The same timeout for every kind of command, nothing added per averaged value
+    pub const fn uniform(timeout: Duration) -> Self {
+        Self {
+            query: timeout,
+            measurement: timeout,
+            averaged_measurement: timeout,
+            per_averaged_value: Duration::ZERO,
+            reset: timeout,
+        }
+    }
+
+    /// Returns how long a command of this kind may take to start answering
+    pub fn for_kind(&self, kind: CommandKind) -> Duration {
+        match kind {
+            CommandKind::Query => self.query,
+            CommandKind::Measurement => self.measurement,
+            CommandKind::AveragedMeasurement(count) => {
+                self.averaged_measurement + self.per_averaged_value * count as u32
+            }
+            CommandKind::Reset => self.reset,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::uniform(DEFAULT_RESPONSE_TIMEOUT)
+    }
+}
+
+/// How a request is sent over an RS-485 adapter that doesn't switch the direction of the line
+/// by itself. The driver is enabled through the request to send line while the request is
+/// written and released once every byte left the adapter.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rs485Config {
+    /// Raise the request to send line while sending and lower it again for the response
+    pub rts_on_send: bool,
+    /// How long to wait after sending before reading the response, the time the adapter needs
+    /// to turn the line around
+    pub turnaround: Duration,
+}
+
+/// How the receive loop waits on a transport that returns from a read with no bytes instead of
+/// waiting out its timeout, like a port opened with a zero timeout. Such a transport is read
+/// again at once, after yielding the thread, for up to `spins` empty reads in a row. Every
+/// further empty read is followed by a sleep, so an idle line doesn't keep a core busy. A
+/// transport that waits out its timeout never returns an empty read and isn't affected.
+///
+/// The default sleeps after every empty read for the inter-byte timeout, spinning longer trades
+/// CPU time for a response picked up sooner.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpinPolicy {
+    /// How many empty reads in a row are followed by another read straight away
+    pub spins: u32,
+    /// How long to sleep after each empty read past `spins`, never longer than the wait left.
+    /// [None] sleeps for the inter-byte timeout.
+    pub sleep: Option<Duration>,
+}
+
+/// The address and command a response has to echo, the kind of command that decides how long
+/// it may take and the name of the command for the logs
+pub(crate) type Request = (u8, u8, CommandKind, Option<&'static str>);
+
+#[derive(Debug)]
+pub(crate) struct Settings {
+    pub(crate) timeouts: Timeouts,
+    pub(crate) inter_byte_timeout: Duration,
+    pub(crate) clear_stale_input: bool,
+    pub(crate) retry: Option<RetryConfig>,
+    pub(crate) validation: ValidationLevel,
+    #[cfg(feature = "std")]
+    pub(crate) boot_grace: Option<BootGrace>,
+    #[cfg(feature = "std")]
+    pub(crate) busy_retry: Option<BusyPolicy>,
+    /// when the last reset was sent through the connection, for the boot grace
+    #[cfg(feature = "std")]
+    pub(crate) last_reset: Option<std::time::Instant>,
+    #[cfg(feature = "std")]
+    pub(crate) rs485: Option<Rs485Config>,
+    #[cfg(feature = "std")]
+    pub(crate) baud_rate: Option<u32>,
+    #[cfg(feature = "std")]
+    pub(crate) pipeline_window: usize,
+    #[cfg(feature = "std")]
+    pub(crate) spin: SpinPolicy,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            timeouts: Timeouts::default(),
+            inter_byte_timeout: DEFAULT_INTER_BYTE_TIMEOUT,
+            clear_stale_input: true,
+            retry: None,
+            validation: ValidationLevel::default(),
+            #[cfg(feature = "std")]
+            boot_grace: None,
+            #[cfg(feature = "std")]
+            busy_retry: None,
+            #[cfg(feature = "std")]
+            last_reset: None,
+            #[cfg(feature = "std")]
+            rs485: None,
+            #[cfg(feature = "std")]
+            baud_rate: None,
+            #[cfg(feature = "std")]
+            pipeline_window: 1,
+            #[cfg(feature = "std")]
+            spin: SpinPolicy::default(),
+        }
+    }
+}
+
+/// The most bytes read from the transport at once, about a whole buffered read of the SFC5xxx
+const READ_CHUNK: usize = 256;
+
+/// Bytes read from the transport that were not decoded yet, and the frame being decoded from
+/// them. It lives as long as the transport, so every command decodes its response into the same
+/// buffer and the bytes read past a response are still there for the next one.
+#[derive(Debug)]
+pub(crate) struct Receiver {
+    buff: [u8; READ_CHUNK],
+    start: usize,
+    end: usize,
+    decoder: FrameDecoder,
+}
+
+impl Receiver {
+    pub(crate) fn new() -> Self {
+        Self {
+            buff: [0_u8; READ_CHUNK],
+            start: 0,
+            end: 0,
+            decoder: FrameDecoder::for_responses(),
+        }
+    }
+
+    pub(crate) fn in_frame(&self) -> bool {
+        self.decoder.in_frame()
+    }
+
+    /// Drops the buffered bytes and any partly received frame
+    pub(crate) fn clear(&mut self) {
+        self.start = self.end;
+        self.decoder.reset();
+    }
+
+    /// Drops a partly received frame but keeps the buffered bytes
+    pub(crate) fn drop_partial_frame(&mut self) {
+        self.decoder.reset();
+    }
+
+    /// Decodes the buffered bytes, returning true once a frame is complete. The frame is then
+    /// parsed by [Receiver::response] until the next call.
+    pub(crate) fn next_frame(&mut self) -> Result<bool, TranslationError> {
+        let result = self.decoder.feed(self.buff.get(self.start..self.end).unwrap_or_default());
+        match result {
+            Ok((consumed, complete)) => {
+                self.start += consumed;
+                #[cfg(feature = "log")]
+                if complete {
+                    log::trace!("received {}", Hex(self.decoder.frame().unwrap_or_default()));
+                }
+                Ok(complete)
+            }
+            Err(e) => {
+                self.start = self.end;
+                Err(e)
+            }
+        }
+    }
+
+    /// The frame [Receiver::next_frame] completed, parsed where it was received
+    pub(crate) fn response(&mut self) -> Result<MISOFrameRef<'_>, TranslationError> {
+        self.decoder.miso_frame()
+    }
+
+    /// Reads more bytes, only called once the buffered ones are decoded. Takes what the
+    /// transport reports as received if it can tell, up to a whole buffer otherwise.
+    #[cfg(feature = "std")]
+    pub(crate) fn fill<T: crate::transport::Transport>(&mut self, port: &mut T) -> std::io::Result<usize> {
+        let chunk = match port.bytes_to_read() {
+            Some(available) if available > 0 => available.min(READ_CHUNK),
+            _ => READ_CHUNK,
+        };
+        let read = port.read(self.buff.get_mut(..chunk).unwrap_or_default())?;
+        self.start = 0;
+        // a transport can't have read more than it was given
+        self.end = read.min(chunk);
+        Ok(read)
+    }
+
+    /// Reads more bytes from an async stream, only called once the buffered ones are decoded.
+    /// Cancelling the read loses no bytes.
+    #[cfg(feature = "async")]
+    pub(crate) async fn fill_async<T>(&mut self, port: &mut T) -> Result<usize, DeviceError>
+    where
+        T: crate::async_transport::AsyncTransport,
+    {
+        let read = port.read(&mut self.buff).await?;
+        self.start = 0;
+        self.end = read.min(READ_CHUNK);
+        Ok(read)
+    }
+}
+
+impl Settings {
+    /// Checks a received frame as the [ValidationLevel] asks. Returns [None] if it is not the
+    /// response to the command.
+    pub(crate) fn accept<'a>(
+        &self,
+        frame: MISOFrameRef<'a>,
+        address: u8,
+        command: u8,
+    ) -> Result<Option<MISOFrameRef<'a>>, DeviceError> {
+        let level = self.validation;
+        if frame.get_address() != address || frame.get_command_number() != command {
+            match level {
+                ValidationLevel::Strict => Err(DeviceError::UnexpectedResponse(
+                    frame.get_address(),
+                    frame.get_command_number(),
+                ))?,
+                ValidationLevel::Standard => warn(format_args!(
+                    "skipped a response from address {} to command {:#04x}",
+                    frame.get_address(),
+                    frame.get_command_number()
+                )),
+                ValidationLevel::Lenient => {}
+            }
+            return Ok(None);
+        }
+
+        let trailing = frame.trailing_bytes();
+        if trailing > 0 {
+            match level {
+                ValidationLevel::Strict => Err(TranslationError::TrailingData(trailing))?,
+                ValidationLevel::Standard => warn(format_args!(
+                    "the response to command {:#04x} carried {} bytes past its data",
+                    command, trailing
+                )),
+                ValidationLevel::Lenient => {}
+            }
+        }
+
+        if !frame.validate_checksum() {
+            if level == ValidationLevel::Lenient {
+                warn(format_args!(
+                    "ignored the checksum {:#04x} of the response to command {:#04x}, expected \
+                     {:#04x}",
+                    frame.get_checksum(),
+                    command,
+                    frame.calculate_check_sum()
+                ));
+            } else {
+                #[cfg(feature = "log")]
+                log::warn!(
+                    "checksum of the response to command {:#04x} was {:#04x}, expected {:#04x}",
+                    command,
+                    frame.get_checksum(),
+                    frame.calculate_check_sum()
+                );
+                Err(DeviceError::InvalidChecksum(
+                    frame.get_checksum(),
+                    frame.calculate_check_sum(),
+                ))?;
+            }
+        }
+
+        if !frame.is_ok() {
+            Err(StateResponseError::from(frame.get_state()))?;
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+/// Reports a check that failed through the `log` and `tracing` features
+#[cfg_attr(not(any(feature = "log", feature = "tracing")), allow(unused_variables))]
+fn warn(message: core::fmt::Arguments<'_>) {
+    #[cfg(feature = "log")]
+    log::warn!("{}", message);
+    #[cfg(feature = "tracing")]
+    tracing::warn!("{}", message);
+}
+
+/// Formats a command as its number followed by its name, if it has one
+#[cfg(feature = "log")]
+pub(crate) struct Named(pub(crate) u8, pub(crate) Option<&'static str>);
+
+#[cfg(feature = "log")]
+impl core::fmt::Display for Named {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#04x}", self.0)?;
+        match self.1 {
+            Some(name) => write!(f, " ({})", name),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Formats bytes as space separated hex
+#[cfg(feature = "log")]
+pub(crate) struct Hex<'a>(pub(crate) &'a [u8]);
+
+#[cfg(feature = "log")]
+impl core::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The value of the `outcome` span field
+#[cfg(feature = "tracing")]
+pub(crate) fn outcome<T>(result: &Result<T, DeviceError>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(DeviceError::Timeout) => "timeout",
+        Err(DeviceError::IncompleteFrame) => "incomplete_frame",
+        Err(DeviceError::InvalidChecksum(_, _)) => "checksum",
+        Err(DeviceError::ShdlcError(_)) => "framing",
+        Err(DeviceError::StateResponse(_)) => "device_state",
+        Err(DeviceError::RetriesExhausted(_, _)) => "retries_exhausted",
+        Err(DeviceError::DeadlineExceeded) => "deadline",
+        Err(DeviceError::IoError(_)) => "io",
+        Err(_) => "other",
+    }
+}
+
+pub(crate) fn expired(in_frame: bool) -> DeviceError {
+    if in_frame {
+        DeviceError::IncompleteFrame
+    } else {
+        DeviceError::Timeout
+    }
+}
@@ -0,0 +1,138 @@
+//! The operations every Sensirion mass flow controller shares, so process code can be written
+//! once for a mixed fleet. sfc5xxx-rs and sfc6xxx-rs implement [FlowController] for their
+//! devices:
+//! ```
+//! use sfc_core::error::DeviceError;
+//! use sfc_core::flow_controller::FlowController;
+//!
+//! /// Sets the flow and waits until the measured flow is within 1% of the full scale
+//! fn settle<C: FlowController>(controller: &mut C, setpoint: f32) -> Result<f32, DeviceError> {
+//!     controller.set_setpoint(setpoint)?;
+//!     let tolerance = controller.get_full_scale()? * 0.01;
+//!     loop {
+//!         let flow = controller.read_measured_value()?;
+//!         if (flow - setpoint).abs() <= tolerance {
+//!             return Ok(flow);
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::error::DeviceError;
+use crate::gasunit::GasUnit;
+#[cfg(feature = "std")]
+use crate::measurement::Measurements;
+#[cfg(feature = "std")]
+use crate::profile::{Profile, ProfileResult};
+use crate::shdlc::{DeviceString, Version};
+
+/// A mass flow controller. Flows and setpoints are physical values in the unit returned by
+/// [FlowController::get_gas_unit].
+pub trait FlowController {
+    /// Sets the flow setpoint, between 0 and [FlowController::get_full_scale]
+    fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError>;
+
+    /// Returns the flow setpoint
+    fn get_setpoint(&mut self) -> Result<f32, DeviceError>;
+
+    /// Returns the latest measured flow
+    fn read_measured_value(&mut self) -> Result<f32, DeviceError>;
+
+    /// Returns the average of the given number of flow measurements
+    fn read_average_measured_value(&mut self, measurement_count: u8) -> Result<f32, DeviceError>;
+
+    /// Returns the largest flow of the active calibration
+    fn get_full_scale(&mut self) -> Result<f32, DeviceError>;
+
+    /// Returns the unit flows and setpoints are in
+    fn get_gas_unit(&mut self) -> Result<GasUnit, DeviceError>;
+
+    /// Resets the device, it does not answer until it has started again
+    fn reset(&mut self) -> Result<(), DeviceError>;
+
+    /// Returns the firmware, hardware and protocol versions
+    fn get_version(&mut self) -> Result<Version, DeviceError>;
+
+    /// Returns the serial number
+    fn get_serial_number(&mut self) -> Result<DeviceString, DeviceError>;
+
+    /// Whether the controller refuses every command that changes it with
+    /// [DeviceError::ReadOnly], so helpers that would change it fail before they send anything.
+    /// Never by default.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Reads a [crate::measurement::Measurement] every `interval`, see
+    /// [crate::measurement::Measurements]
+    #[cfg(feature = "std")]
+    fn measurements(&mut self, interval: std::time::Duration) -> Measurements<'_, Self>
+    where
+        Self: Sized,
+    {
+        Measurements::new(self, interval)
+    }
+
+    /// Runs the segments of a profile and measures the flow every `sample_interval`, see the
+    /// [crate::profile] module. Fails without changing the setpoint if the unit, serial number
+    /// or setpoint can't be read first, and with [DeviceError::ReadOnly] without sending anything
+    /// to a [read-only](FlowController::is_read_only) controller. A command failing afterwards
+    /// zeroes the setpoint and returns the partial result with
+    /// [crate::profile::ProfileResult::aborted] set.
+    ///
+    /// # Panics
+    /// If `sample_interval` is zero
+    #[cfg(feature = "std")]
+    fn run_profile(
+        &mut self,
+        profile: &Profile,
+        sample_interval: std::time::Duration,
+    ) -> Result<ProfileResult, DeviceError> {
+        crate::profile::run(self, profile, sample_interval)
+    }
+}
+
+/// A boxed controller, so controllers of different families can be handled together as
+/// `Box<dyn FlowController>`
+#[cfg(feature = "std")]
+impl<C: FlowController + ?Sized> FlowController for Box<C> {
+    fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError> {
+        (**self).set_setpoint(setpoint)
+    }
+
+    fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
+        (**self).get_setpoint()
+    }
+
+    fn read_measured_value(&mut self) -> Result<f32, DeviceError> {
+        (**self).read_measured_value()
+    }
+
+    fn read_average_measured_value(&mut self, measurement_count: u8) -> Result<f32, DeviceError> {
+        (**self).read_average_measured_value(measurement_count)
+    }
+
+    fn get_full_scale(&mut self) -> Result<f32, DeviceError> {
+        (**self).get_full_scale()
+    }
+
+    fn get_gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
+        (**self).get_gas_unit()
+    }
+
+    fn reset(&mut self) -> Result<(), DeviceError> {
+        (**self).reset()
+    }
+
+    fn get_version(&mut self) -> Result<Version, DeviceError> {
+        (**self).get_version()
+    }
+
+    fn get_serial_number(&mut self) -> Result<DeviceString, DeviceError> {
+        (**self).get_serial_number()
+    }
+
+    fn is_read_only(&self) -> bool {
+        (**self).is_read_only()
+    }
+}
@@ -0,0 +1,163 @@
+//! Mean, spread and extremes of a burst of readings, available with `std`. This is the usual
+//! stability check before accepting a calibration point: read some 50 values and look at how
+//! much they vary.
+//! ```
+//! use sfc_core::flow_statistics::RunningStatistics;
+//!
+//! let mut statistics = RunningStatistics::new();
+//! for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+//!     statistics.push(value);
+//! }
+//! let summary = statistics.summary().unwrap();
+//! assert_eq!((summary.mean, summary.peak_to_peak), (5.0, 7.0));
+//! ```
+//! The values are accumulated with Welford's algorithm, which updates the mean and the squared
+//! deviations from it with every value. Unlike a sum of squares it stays accurate when the
+//! values are large and close together, like a flow that is already settled.
+
+use crate::error::DeviceError;
+
+/// The statistics of a burst of readings. The drivers return them from their
+/// `sample_statistics` methods.
+#[derive(Debug)]
+pub struct FlowStatistics {
+    /// How many values were read
+    pub count: u32,
+    pub mean: f32,
+    /// The sample standard deviation, divided by `count - 1`. Zero for a single value.
+    pub std_dev: f32,
+    pub min: f32,
+    pub max: f32,
+    /// `max - min`
+    pub peak_to_peak: f32,
+    /// Every value in the order it was read, if they were kept
+    pub samples: Option<Vec<f32>>,
+    /// The error that ended the burst before every value was read. The statistics only cover
+    /// the values read before it.
+    pub interrupted: Option<DeviceError>,
+}
+
+impl FlowStatistics {
+    /// The statistics of the given values, keeping them in [FlowStatistics::samples]. [None]
+    /// if there are no values.
+    pub fn from_samples(samples: &[f32]) -> Option<Self> {
+        let mut statistics = RunningStatistics::new();
+        samples.iter().for_each(|&value| statistics.push(value));
+        Some(Self {
+            samples: Some(samples.to_vec()),
+            ..statistics.summary()?
+        })
+    }
+
+    /// Returns true if every value of the burst was read
+    pub fn is_complete(&self) -> bool {
+        self.interrupted.is_none()
+    }
+}
+
+/// Statistics updated one value at a time, see the [module documentation](self)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RunningStatistics {
+    count: u32,
+    mean: f64,
+    /// the sum of the squared deviations from the mean
+    squared_deviations: f64,
+    min: f32,
+    max: f32,
+}
+
+impl RunningStatistics {
+    /// Statistics without any values yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value
+    pub fn push(&mut self, value: f32) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        let value = f64::from(value);
+        let delta = value - self.mean;
+        self.mean += delta / f64::from(self.count);
+        self.squared_deviations += delta * (value - self.mean);
+    }
+
+    /// How many values were added
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The statistics of the values so far, without the samples. [None] before the first value.
+    pub fn summary(&self) -> Option<FlowStatistics> {
+        if self.count == 0 {
+            return None;
+        }
+        let variance = if self.count > 1 {
+            self.squared_deviations / f64::from(self.count - 1)
+        } else {
+            0.0
+        };
+        Some(FlowStatistics {
+            count: self.count,
+            mean: self.mean as f32,
+            std_dev: variance.sqrt() as f32,
+            min: self.min,
+            max: self.max,
+            peak_to_peak: self.max - self.min,
+            samples: None,
+            interrupted: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_dataset() {
+        let statistics =
+            FlowStatistics::from_samples(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).unwrap();
+        assert_eq!(statistics.count, 8);
+        assert_eq!(statistics.mean, 5.0);
+        // the population standard deviation is 2, the sample one sqrt(32 / 7)
+        assert!((statistics.std_dev - (32.0_f32 / 7.0).sqrt()).abs() < 1e-6);
+        assert_eq!((statistics.min, statistics.max), (2.0, 9.0));
+        assert_eq!(statistics.peak_to_peak, 7.0);
+        assert_eq!(statistics.samples.unwrap().len(), 8);
+        assert!(statistics.interrupted.is_none());
+    }
+
+    /// Large values close together, where subtracting the squared mean from the mean square
+    /// loses every digit of the variance
+    #[test]
+    fn large_offset() {
+        let mut statistics = RunningStatistics::new();
+        for value in [4.0, 7.0, 13.0, 16.0] {
+            statistics.push(1e6 + value);
+        }
+        let summary = statistics.summary().unwrap();
+        assert_eq!(summary.mean, 1e6 + 10.0);
+        assert!((summary.std_dev - 30.0_f32.sqrt()).abs() < 1e-4);
+        assert_eq!(summary.peak_to_peak, 12.0);
+    }
+
+    #[test]
+    fn single_and_no_values() {
+        assert!(RunningStatistics::new().summary().is_none());
+        assert!(FlowStatistics::from_samples(&[]).is_none());
+
+        let single = FlowStatistics::from_samples(&[-1.5]).unwrap();
+        assert_eq!((single.mean, single.std_dev), (-1.5, 0.0));
+        assert_eq!(
+            (single.min, single.max, single.peak_to_peak),
+            (-1.5, -1.5, 0.0)
+        );
+    }
+}
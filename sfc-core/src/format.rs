@@ -0,0 +1,265 @@
+//! Flows written for people, with their unit and as much precision as the controller resolves.
+//! A [Flow] knows its unit and, if it is known, the full scale of the controller, which decides
+//! the number of decimals and adds the percent of the full scale:
+//! ```
+//! use sfc_core::format::{Flow, FlowFormat};
+//! use sfc_core::gasunit::{GasUnit, Prefixes, TimeBases, Units};
+//!
+//! let unit = GasUnit {
+//!     unit_prefex: Prefixes::Micro,
+//!     medium_unit: Units::LiterLiquid,
+//!     timebase: TimeBases::Minute,
+//! };
+//! let flow = Flow::new(19.8234, unit).with_full_scale(20.0);
+//! assert_eq!(flow.to_string(), "19.82 μl/min (99.1% FS)");
+//!
+//! let ascii = FlowFormat {
+//!     show_percent: false,
+//!     ascii_units: true,
+//!     ..FlowFormat::default()
+//! };
+//! assert_eq!(flow.display(ascii).to_string(), "19.82 ul/min");
+//! ```
+//! [Precision::Auto] shows four significant digits of the full scale, so a 2 l/min controller
+//! gets three decimals and a 200 ml/min one a single decimal. Without a full scale the value is
+//! written as is.
+
+use core::fmt::{Display, Formatter, Result};
+
+use crate::gasunit::{GasUnit, Prefixes, TimeBases};
+
+/// How many decimals a [Flow] is written with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Precision {
+    /// Four significant digits of the full scale, or the value as is if the full scale is not
+    /// known
+    #[default]
+    Auto,
+    /// Always this many decimals
+    Decimals(u8),
+}
+
+/// How a [Flow] is written. The default has [Precision::Auto], the percent of the full scale and
+/// the units as the device names them, with a `μ` for micro.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlowFormat {
+    pub precision: Precision,
+    /// Add the percent of the full scale, like `(99.1% FS)`, when the full scale is known
+    pub show_percent: bool,
+    /// Write micro as `u` for logs and terminals that only take ASCII
+    pub ascii_units: bool,
+}
+
+impl Default for FlowFormat {
+    fn default() -> Self {
+        Self {
+            precision: Precision::Auto,
+            show_percent: true,
+            ascii_units: false,
+        }
+    }
+}
+
+/// A flow in a unit, see the [module documentation](self). Its [Display] uses the default
+/// [FlowFormat].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Flow {
+    pub value: f32,
+    pub unit: GasUnit,
+    /// The full scale of the calibration the flow was measured with, in the same unit
+    pub full_scale: Option<f32>,
+}
+
+impl Flow {
+    /// A flow without a known full scale
+    pub fn new(value: f32, unit: GasUnit) -> Self {
+        Self {
+            value,
+            unit,
+            full_scale: None,
+        }
+    }
+
+    /// Sets the full scale the precision and the percent are derived from
+    pub fn with_full_scale(mut self, full_scale: f32) -> Self {
+        self.full_scale = Some(full_scale);
+        self
+    }
+
+    /// The flow as a percentage of the full scale, [None] without a usable full scale
+    pub fn percent_of_full_scale(&self) -> Option<f32> {
+        self.full_scale
+            .filter(|full_scale| full_scale.is_finite() && *full_scale > 0.0)
+            .map(|full_scale| self.value / full_scale * 100.0)
+    }
+
+    /// Writes the flow with the given format
+    pub fn display(&self, format: FlowFormat) -> FlowDisplay<'_> {
+        FlowDisplay { flow: self, format }
+    }
+
+    /// The number of decimals the value is written with, [None] for as is
+    fn decimals(&self, precision: Precision) -> Option<usize> {
+        match precision {
+            Precision::Decimals(decimals) => Some(decimals.into()),
+            Precision::Auto => {
+                let exponent = self.full_scale.and_then(decimal_exponent)?;
+                Some((3 - exponent).clamp(0, 9) as usize)
+            }
+        }
+    }
+}
+
+impl Display for Flow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.display(FlowFormat::default()).fmt(f)
+    }
+}
+
+/// A [Flow] with a [FlowFormat], returned by [Flow::display]
+#[derive(Clone, Copy, Debug)]
+pub struct FlowDisplay<'a> {
+    flow: &'a Flow,
+    format: FlowFormat,
+}
+
+impl Display for FlowDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let Self { flow, format } = self;
+        match flow.decimals(format.precision) {
+            Some(decimals) => write!(f, "{:.*}", decimals, flow.value)?,
+            None => write!(f, "{}", flow.value)?,
+        }
+        write!(f, " ")?;
+        write_unit(f, &flow.unit, format.ascii_units)?;
+        if let Some(percent) = flow.percent_of_full_scale().filter(|_| format.show_percent) {
+            write!(f, " ({:.1}% FS)", percent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the unit like its [Display], with `u` for micro if only ASCII is allowed
+fn write_unit(f: &mut Formatter<'_>, unit: &GasUnit, ascii: bool) -> Result {
+    if !ascii {
+        return write!(f, "{}", unit);
+    }
+    match unit.unit_prefex {
+        Prefixes::Micro => write!(f, "u")?,
+        prefix => write!(f, "{}", prefix)?,
+    }
+    write!(f, "{}", unit.medium_unit)?;
+    match unit.timebase {
+        TimeBases::Microsecond => write!(f, "/us"),
+        timebase => write!(f, "{}", timebase),
+    }
+}
+
+/// The power of ten of the leading digit, 0 for 2.5 and -2 for 0.05. [None] for zero, negative
+/// and infinite values. Counted by hand, `log10` needs std.
+fn decimal_exponent(value: f32) -> Option<i32> {
+    if !value.is_finite() || value <= 0.0 {
+        return None;
+    }
+    let mut value = value;
+    let mut exponent = 0;
+    while value >= 10.0 {
+        value /= 10.0;
+        exponent += 1;
+    }
+    while value < 1.0 {
+        value *= 10.0;
+        exponent -= 1;
+    }
+    Some(exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gasunit::Units;
+
+    fn unit(unit_prefex: Prefixes, timebase: TimeBases) -> GasUnit {
+        GasUnit {
+            unit_prefex,
+            medium_unit: Units::StandardLiter,
+            timebase,
+        }
+    }
+
+    /// Checks the value, unit and full scale of every case against the expected text
+    fn check(format: FlowFormat, cases: &[(f32, GasUnit, Option<f32>, &str)]) {
+        for &(value, unit, full_scale, expected) in cases {
+            let flow = Flow {
+                value,
+                unit,
+                full_scale,
+            };
+            assert_eq!(flow.display(format).to_string(), expected, "{:?}", flow);
+        }
+    }
+
+    #[test]
+    fn formatted_flows() {
+        let slm = unit(Prefixes::Base, TimeBases::Minute);
+        let sccm = unit(Prefixes::Milli, TimeBases::Minute);
+        let ul_min = unit(Prefixes::Micro, TimeBases::Minute);
+        let ul_us = unit(Prefixes::Micro, TimeBases::Microsecond);
+        check(
+            FlowFormat::default(),
+            &[
+                (1.9823, slm, Some(2.0), "1.982 l/min (99.1% FS)"),
+                (2.5, slm, None, "2.5 l/min"),
+                (0.0, slm, Some(5.0), "0.000 l/min (0.0% FS)"),
+                (1.0, slm, Some(0.0), "1 l/min"),
+                (123.456, sccm, Some(200.0), "123.5 ml/min (61.7% FS)"),
+                (1500.4, sccm, Some(2000.0), "1500 ml/min (75.0% FS)"),
+                (1500.4, sccm, Some(20000.0), "1500 ml/min (7.5% FS)"),
+                (0.4217, ul_min, Some(20.0), "0.42 μl/min (2.1% FS)"),
+                (0.000032, ul_us, Some(0.05), "0.00003 μl/μs (0.1% FS)"),
+            ],
+        );
+        check(
+            FlowFormat {
+                ascii_units: true,
+                ..FlowFormat::default()
+            },
+            &[
+                (0.4217, ul_min, Some(20.0), "0.42 ul/min (2.1% FS)"),
+                (0.000032, ul_us, Some(0.05), "0.00003 ul/us (0.1% FS)"),
+                (0.000032, ul_us, None, "0.000032 ul/us"),
+                (1.9823, slm, Some(2.0), "1.982 l/min (99.1% FS)"),
+            ],
+        );
+        check(
+            FlowFormat {
+                show_percent: false,
+                ..FlowFormat::default()
+            },
+            &[(1.9823, slm, Some(2.0), "1.982 l/min")],
+        );
+        check(
+            FlowFormat {
+                precision: Precision::Decimals(2),
+                ..FlowFormat::default()
+            },
+            &[
+                (1.9823, slm, Some(2.0), "1.98 l/min (99.1% FS)"),
+                (1.9823, slm, None, "1.98 l/min"),
+                (0.000032, ul_us, None, "0.00 μl/μs"),
+            ],
+        );
+    }
+
+    #[test]
+    fn exponents() {
+        assert_eq!(decimal_exponent(2.5), Some(0));
+        assert_eq!(decimal_exponent(10.0), Some(1));
+        assert_eq!(decimal_exponent(0.05), Some(-2));
+        assert_eq!(decimal_exponent(0.0), None);
+        assert_eq!(decimal_exponent(f32::INFINITY), None);
+    }
+}
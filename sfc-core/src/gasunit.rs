@@ -1,12 +1,16 @@
 //! Gas calibrations come with units of measurments. The format is a (SI prefix * Flow unit)/Time
 //! Unit
 
-use std::fmt::Display;
+use core::fmt::Display;
+
+use crate::error::DeviceError;
 
 /// GasUnit contains a base unit its SI prefix and the time base such as: centimeter per
 /// minute. Often used when checking current calibration settings of a device. 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GasUnit {
+    #[cfg_attr(feature = "serde", serde(rename = "unit_prefix"))]
     pub unit_prefex: Prefixes,
     pub medium_unit: Units,
     pub timebase: TimeBases,
@@ -20,10 +24,74 @@ impl GasUnit {
             timebase: bytes[2].into()
         }
     }
+
+    /// Converts a value in this unit into a volume rate, available with the `uom` feature.
+    /// Norm and standard liters are taken as the volume they are at their reference
+    /// conditions. Mass flows, pressures and units without a timebase return
+    /// [DeviceError::NotAVolumeRate].
+    #[cfg(feature = "uom")]
+    pub fn to_volume_rate(&self, value: f32) -> Result<uom::si::f32::VolumeRate, DeviceError> {
+        use uom::si::volume_rate::cubic_meter_per_second;
+
+        let not_a_volume_rate = || DeviceError::NotAVolumeRate(*self);
+        let volume = matches!(
+            self.medium_unit,
+            Units::NormLiter | Units::StandardLiter | Units::LiterLiquid
+        );
+        if !volume || self.unit_prefex == Prefixes::Undefined {
+            return Err(not_a_volume_rate());
+        }
+        let seconds = self.timebase.seconds().ok_or_else(not_a_volume_rate)?;
+        // a liter is a thousandth of a cubic meter
+        let cubic_meters = power_of_ten(i8::from(self.unit_prefex) - 3);
+        Ok(uom::si::f32::VolumeRate::new::<cubic_meter_per_second>(
+            (value as f64 * cubic_meters / seconds) as f32,
+        ))
+    }
+
+    /// What a value in this unit measures, see [Units::kind]
+    pub fn kind(&self) -> UnitKind {
+        self.medium_unit.kind()
+    }
+
+    /// Converts a pressure in this unit into the same pressure in `to`, like 25 mbar into
+    /// 2500 Pa. The timebase is ignored, pressures have none. Units that aren't pressures or have
+    /// no prefix return [DeviceError::NotAPressure] with the first of them.
+    pub fn convert_pressure(&self, value: f32, to: GasUnit) -> Result<f32, DeviceError> {
+        let pascal = |unit: GasUnit| match (unit.medium_unit.pascal(), unit.unit_prefex) {
+            (Some(per_unit), prefix) if prefix != Prefixes::Undefined => {
+                Ok(per_unit * power_of_ten(prefix.into()))
+            }
+            _ => Err(DeviceError::NotAPressure(unit)),
+        };
+        Ok((value as f64 * pascal(*self)? / pascal(to)?) as f32)
+    }
+}
+
+/// Ten to the power of the exponent, f64::powi needs std
+fn power_of_ten(exponent: i8) -> f64 {
+    let power = (0..exponent.unsigned_abs()).fold(1.0, |power, _| power * 10.0);
+    if exponent < 0 { 1.0 / power } else { power }
+}
+
+/// Formats the unit like `ml/min`
+impl Display for GasUnit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}{}{}", self.unit_prefex, self.medium_unit, self.timebase)
+    }
+}
+
+/// Formats the unit like `ml/min`
+#[cfg(feature = "defmt")]
+impl defmt::Format for GasUnit {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}{}{}", self.unit_prefex, self.medium_unit, self.timebase)
+    }
 }
 
 /// SI prefixes that the device can transmit
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Prefixes {
     Yocto, // -24
     Zepto, // -21
@@ -108,7 +176,7 @@ impl From<Prefixes> for i8 {
 }
 
 impl Display for Prefixes {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::Yocto => write!(f, "y"),
             Self::Zepto => write!(f, "z"),
@@ -136,8 +204,39 @@ impl Display for Prefixes {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Prefixes {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Yocto => defmt::write!(f, "y"),
+            Self::Zepto => defmt::write!(f, "z"),
+            Self::Atto => defmt::write!(f, "a"),
+            Self::Femto => defmt::write!(f, "f"),
+            Self::Pico => defmt::write!(f, "p"),
+            Self::Nano => defmt::write!(f, "n"),
+            Self::Micro => defmt::write!(f, "μ"),
+            Self::Milli => defmt::write!(f, "m"),
+            Self::Centi => defmt::write!(f, "c"),
+            Self::Deci => defmt::write!(f, "d"),
+            Self::Base => defmt::write!(f, ""),
+            Self::Deca => defmt::write!(f, "da"),
+            Self::Hecto => defmt::write!(f, "h"),
+            Self::Kilo => defmt::write!(f, "k"),
+            Self::Mega => defmt::write!(f, "M"),
+            Self::Giga => defmt::write!(f, "G"),
+            Self::Tera => defmt::write!(f, "T"),
+            Self::Peta => defmt::write!(f, "P"),
+            Self::Exa => defmt::write!(f, "E"),
+            Self::Zetta => defmt::write!(f, "Z"),
+            Self::Yotta => defmt::write!(f, "Y"),
+            Self::Undefined => defmt::write!(f, ""),
+        }
+    }
+}
+
 /// Diffrent units of flow the device can be calibrated to
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Units {
     NormLiter,
     StandardLiter,
@@ -145,11 +244,50 @@ pub enum Units {
     Gram,
     Pascal,
     Bar,
+    #[cfg_attr(feature = "serde", serde(rename = "MeterH2O"))]
     MeterH20,
+    #[cfg_attr(feature = "serde", serde(rename = "InchH2O"))]
     InchH20,
     Undefined,
 }
 
+/// What a medium unit measures
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UnitKind {
+    /// A volume flow, in norm, standard or liquid liters
+    Volume,
+    /// A mass flow, in grams
+    Mass,
+    /// A pressure, the device controls pressure instead of flow
+    Pressure,
+    Undefined,
+}
+
+impl Units {
+    /// What a value in this unit measures
+    pub fn kind(&self) -> UnitKind {
+        match self {
+            Self::NormLiter | Self::StandardLiter | Self::LiterLiquid => UnitKind::Volume,
+            Self::Gram => UnitKind::Mass,
+            Self::Pascal | Self::Bar | Self::MeterH20 | Self::InchH20 => UnitKind::Pressure,
+            Self::Undefined => UnitKind::Undefined,
+        }
+    }
+
+    /// How many pascal one of this unit is, [None] for units that aren't pressures. The water
+    /// columns are conventional, at 4°C and standard gravity.
+    pub fn pascal(&self) -> Option<f64> {
+        match self {
+            Self::Pascal => Some(1.0),
+            Self::Bar => Some(100_000.0),
+            Self::MeterH20 => Some(9_806.65),
+            Self::InchH20 => Some(249.088_91),
+            _ => None,
+        }
+    }
+}
+
 impl From<u8> for Units {
     fn from(value: u8) -> Self {
         match value {
@@ -183,7 +321,7 @@ impl From<Units> for u8 {
 }
 
 impl Display for Units {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::NormLiter | Self::StandardLiter | Self::LiterLiquid => write!(f, "l"),
             Self::Gram => write!(f, "g"),
@@ -196,11 +334,28 @@ impl Display for Units {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Units {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::NormLiter | Self::StandardLiter | Self::LiterLiquid => defmt::write!(f, "l"),
+            Self::Gram => defmt::write!(f, "g"),
+            Self::Pascal => defmt::write!(f, "Pa"),
+            Self::Bar => defmt::write!(f, "bar"),
+            Self::MeterH20 => defmt::write!(f, "mH20"),
+            Self::InchH20 => defmt::write!(f, "iH20"),
+            Self::Undefined => defmt::write!(f, ""),
+        }
+    }
+}
+
 /// Timescales for the calibrations
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TimeBases {
     None,
     Microsecond,
+    #[cfg_attr(feature = "serde", serde(rename = "Millisecond"))]
     Milisecond,
     Second,
     Minute,
@@ -224,6 +379,22 @@ impl From<u8> for TimeBases {
     }
 }
 
+impl TimeBases {
+    /// The length of the timebase in seconds, `None` when there is none
+    #[cfg(feature = "uom")]
+    fn seconds(&self) -> Option<f64> {
+        match self {
+            Self::Microsecond => Some(1e-6),
+            Self::Milisecond => Some(1e-3),
+            Self::Second => Some(1.0),
+            Self::Minute => Some(60.0),
+            Self::Hour => Some(3600.0),
+            Self::Day => Some(86400.0),
+            Self::None | Self::Undefined => None,
+        }
+    }
+}
+
 impl From<TimeBases> for u8 {
     fn from(value: TimeBases) -> Self {
         match value {
@@ -240,7 +411,7 @@ impl From<TimeBases> for u8 {
 }
 
 impl Display for TimeBases {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::None => write!(f, ""),
             Self::Microsecond => write!(f, "/μs"),
@@ -253,3 +424,133 @@ impl Display for TimeBases {
         }
     }
 }
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for TimeBases {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::None => defmt::write!(f, ""),
+            Self::Microsecond => defmt::write!(f, "/μs"),
+            Self::Milisecond => defmt::write!(f, "/ms"),
+            Self::Second => defmt::write!(f, "/s"),
+            Self::Minute => defmt::write!(f, "/min"),
+            Self::Hour => defmt::write!(f, "/h"),
+            Self::Day => defmt::write!(f, "/day"),
+            Self::Undefined => defmt::write!(f, ""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "uom")]
+    #[test]
+    fn volume_rates() {
+        use uom::si::f32::VolumeRate;
+        use uom::si::volume_rate::{cubic_meter_per_second, liter_per_minute};
+
+        let unit = |unit_prefex, medium_unit, timebase| GasUnit {
+            unit_prefex,
+            medium_unit,
+            timebase,
+        };
+        let slm = unit(Prefixes::Base, Units::StandardLiter, TimeBases::Minute);
+        assert_eq!(slm.to_volume_rate(2.5).unwrap(), VolumeRate::new::<liter_per_minute>(2.5));
+
+        // 1.5 ml/min is 1.5e-6 m³ in 60s
+        let ml_min = unit(Prefixes::Milli, Units::LiterLiquid, TimeBases::Minute);
+        let rate = ml_min.to_volume_rate(1.5).unwrap().get::<cubic_meter_per_second>();
+        assert!((rate - 2.5e-8).abs() < 1e-14);
+
+        // 36 kl/h is 36 m³ in 3600s
+        let kl_h = unit(Prefixes::Kilo, Units::NormLiter, TimeBases::Hour);
+        let rate = kl_h.to_volume_rate(36.0).unwrap().get::<cubic_meter_per_second>();
+        assert!((rate - 0.01).abs() < 1e-8);
+
+        // 4 μl/ms is 4e-9 m³ in 1e-3s
+        let ul_ms = unit(Prefixes::Micro, Units::LiterLiquid, TimeBases::Milisecond);
+        let rate = ul_ms.to_volume_rate(4.0).unwrap().get::<cubic_meter_per_second>();
+        assert!((rate - 4e-6).abs() < 1e-12);
+    }
+
+    #[cfg(feature = "uom")]
+    #[test]
+    fn only_volumes_per_time_are_volume_rates() {
+        let grams = GasUnit {
+            unit_prefex: Prefixes::Base,
+            medium_unit: Units::Gram,
+            timebase: TimeBases::Minute,
+        };
+        let bar = GasUnit {
+            medium_unit: Units::Bar,
+            timebase: TimeBases::None,
+            ..grams
+        };
+        let liters = GasUnit {
+            medium_unit: Units::StandardLiter,
+            timebase: TimeBases::None,
+            ..grams
+        };
+        for unit in [grams, bar, liters] {
+            assert!(matches!(
+                unit.to_volume_rate(1.0),
+                Err(DeviceError::NotAVolumeRate(u)) if u == unit
+            ));
+        }
+        assert_eq!(
+            grams.to_volume_rate(1.0).unwrap_err().to_string(),
+            "a value in g/min is not a volume rate"
+        );
+    }
+
+    #[test]
+    fn pressure_conversions() {
+        let unit = |unit_prefex, medium_unit| GasUnit {
+            unit_prefex,
+            medium_unit,
+            timebase: TimeBases::None,
+        };
+        let mbar = unit(Prefixes::Milli, Units::Bar);
+        let pa = unit(Prefixes::Base, Units::Pascal);
+        let kpa = unit(Prefixes::Kilo, Units::Pascal);
+        let mh2o = unit(Prefixes::Base, Units::MeterH20);
+        let inh2o = unit(Prefixes::Base, Units::InchH20);
+        assert_eq!(mbar.convert_pressure(25.0, pa).unwrap(), 2500.0);
+        assert_eq!(pa.convert_pressure(2500.0, kpa).unwrap(), 2.5);
+        assert_eq!(mh2o.convert_pressure(1.0, pa).unwrap(), 9806.65);
+        let inches = mh2o.convert_pressure(1.0, inh2o).unwrap();
+        assert!((inches - 39.37).abs() < 0.01, "{}", inches);
+        assert_eq!(mbar.kind(), UnitKind::Pressure);
+
+        let slm = unit(Prefixes::Base, Units::StandardLiter);
+        let undefined = unit(Prefixes::Undefined, Units::Bar);
+        assert!(matches!(
+            slm.convert_pressure(1.0, pa),
+            Err(DeviceError::NotAPressure(u)) if u == slm
+        ));
+        assert!(matches!(
+            pa.convert_pressure(1.0, undefined),
+            Err(DeviceError::NotAPressure(u)) if u == undefined
+        ));
+        assert_eq!(slm.kind(), UnitKind::Volume);
+        assert_eq!(Units::Gram.kind(), UnitKind::Mass);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let unit = GasUnit {
+            unit_prefex: Prefixes::Milli,
+            medium_unit: Units::MeterH20,
+            timebase: TimeBases::Milisecond,
+        };
+        let json = serde_json::to_string(&unit).unwrap();
+        assert_eq!(
+            json,
+            r#"{"unit_prefix":"Milli","medium_unit":"MeterH2O","timebase":"Millisecond"}"#
+        );
+        assert_eq!(serde_json::from_str::<GasUnit>(&json).unwrap(), unit);
+    }
+}
@@ -0,0 +1,230 @@
+//! Commands to every controller of a rig at once, available with `std`. A [DeviceGroup] runs an
+//! operation on each of its named members and keeps going when some of them fail, so one
+//! controller that stopped answering doesn't leave the others flowing:
+//! ```
+//! # fn run<C: sfc_core::flow_controller::FlowController + Send>(a: C, b: C) {
+//! use sfc_core::group::{DeviceGroup, Execution};
+//!
+//! let mut group = DeviceGroup::new(vec![("nitrogen".to_string(), a), ("oxygen".to_string(), b)]);
+//! group.set_execution(Execution::Parallel);
+//! if let Err(error) = group.zero_all().into_result() {
+//!     // lists every device that failed and why
+//!     eprintln!("{}", error);
+//! }
+//! # }
+//! ```
+//! The results are kept per device in a [GroupResult], [GroupResult::into_result] turns them into
+//! the values of every device or a [GroupError] naming the ones that failed. The operations of a
+//! group send [idempotent](crate::shdlc::Idempotency) commands only, running one again after
+//! some devices failed leaves the others as they are.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::error::DeviceError;
+use crate::flow_controller::FlowController;
+
+/// How a [DeviceGroup] runs an operation on its members
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Execution {
+    /// One device after the other, in the order they were given
+    #[default]
+    Sequential,
+    /// Every device on a thread of its own, all at once. Devices on separate ports answer
+    /// together, devices sharing a line still wait for each other.
+    Parallel,
+}
+
+/// An operation failed on one member of a [DeviceGroup]
+#[derive(Debug)]
+pub struct MemberError {
+    /// The name the device was given in the group
+    pub device: String,
+    pub error: DeviceError,
+}
+
+impl Display for MemberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.device, self.error)
+    }
+}
+
+/// An operation on a [DeviceGroup] failed on some of its members
+#[derive(Debug)]
+pub struct GroupError {
+    /// The devices that failed, ordered by name
+    pub failed: Vec<MemberError>,
+    /// The number of devices the operation ran on, including the failed ones
+    pub total: usize,
+}
+
+impl Display for GroupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} devices failed", self.failed.len(), self.total)?;
+        for (i, error) in self.failed.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            write!(f, "{}{}", separator, error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of an operation on every member of a [DeviceGroup], by device name
+#[derive(Debug)]
+pub struct GroupResult<T> {
+    pub results: BTreeMap<String, Result<T, DeviceError>>,
+}
+
+impl<T> GroupResult<T> {
+    /// True if the operation succeeded on every device
+    pub fn is_ok(&self) -> bool {
+        self.results.values().all(Result::is_ok)
+    }
+
+    /// The result of one device, `None` if no device has this name
+    pub fn get(&self, device: &str) -> Option<&Result<T, DeviceError>> {
+        self.results.get(device)
+    }
+
+    /// The names and errors of the devices the operation failed on
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &DeviceError)> {
+        self.results
+            .iter()
+            .filter_map(|(device, result)| result.as_ref().err().map(|e| (device.as_str(), e)))
+    }
+
+    /// Returns the value of every device, or the errors of all devices that failed
+    pub fn into_result(self) -> Result<BTreeMap<String, T>, GroupError> {
+        let total = self.results.len();
+        let mut values = BTreeMap::new();
+        let mut failed = Vec::new();
+        for (device, result) in self.results {
+            match result {
+                Ok(value) => {
+                    values.insert(device, value);
+                }
+                Err(error) => failed.push(MemberError { device, error }),
+            }
+        }
+        if failed.is_empty() {
+            Ok(values)
+        } else {
+            Err(GroupError { failed, total })
+        }
+    }
+}
+
+/// Named controllers that are commanded together, see the [module documentation](self)
+#[derive(Debug)]
+pub struct DeviceGroup<C: FlowController> {
+    members: Vec<(String, C)>,
+    execution: Execution,
+}
+
+impl<C: FlowController> DeviceGroup<C> {
+    /// Creates a group from named controllers. Operations run [Execution::Sequential] until
+    /// changed.
+    ///
+    /// # Panics
+    /// If two devices have the same name
+    pub fn new(members: Vec<(String, C)>) -> Self {
+        for (i, (name, _)) in members.iter().enumerate() {
+            assert!(
+                members.iter().take(i).all(|(other, _)| other != name),
+                "the device name {} is used twice",
+                name
+            );
+        }
+        Self {
+            members,
+            execution: Execution::default(),
+        }
+    }
+
+    /// Returns how operations run on the members
+    pub fn execution(&self) -> Execution {
+        self.execution
+    }
+
+    /// Sets how operations run on the members
+    pub fn set_execution(&mut self, execution: Execution) {
+        self.execution = execution;
+    }
+
+    /// The number of devices
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Returns the controller with the given name
+    pub fn get_mut(&mut self, device: &str) -> Option<&mut C> {
+        self.members
+            .iter_mut()
+            .find(|(name, _)| name == device)
+            .map(|(_, controller)| controller)
+    }
+
+    /// Returns the named controllers in the order they were given
+    pub fn into_inner(self) -> Vec<(String, C)> {
+        self.members
+    }
+}
+
+impl<C: FlowController + Send> DeviceGroup<C> {
+    /// Runs an operation on every device. A device that fails doesn't keep the operation from
+    /// running on the others, the result of each is returned by its name. Running it again
+    /// repeats it on every device, only do so with operations that are idempotent.
+    pub fn for_each<T, F>(&mut self, operation: F) -> GroupResult<T>
+    where
+        T: Send,
+        F: Fn(&mut C) -> Result<T, DeviceError> + Sync,
+    {
+        let results = match self.execution {
+            Execution::Sequential => self
+                .members
+                .iter_mut()
+                .map(|(name, controller)| (name.clone(), operation(controller)))
+                .collect(),
+            Execution::Parallel => std::thread::scope(|scope| {
+                let operation = &operation;
+                let threads: Vec<_> = self
+                    .members
+                    .iter_mut()
+                    .map(|(name, controller)| {
+                        (name.clone(), scope.spawn(move || operation(controller)))
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|(name, thread)| {
+                        // a panicking operation panics the caller, as it would sequentially
+                        let result = thread
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                        (name, result)
+                    })
+                    .collect()
+            }),
+        };
+        GroupResult { results }
+    }
+
+    /// Sets the same setpoint on every device
+    pub fn set_all_setpoints(&mut self, setpoint: f32) -> GroupResult<()> {
+        self.for_each(|controller| controller.set_setpoint(setpoint))
+    }
+
+    /// Sets every device to zero flow
+    pub fn zero_all(&mut self) -> GroupResult<()> {
+        self.set_all_setpoints(0.0)
+    }
+
+    /// Reads the measured flow of every device
+    pub fn read_all(&mut self) -> GroupResult<f32> {
+        self.for_each(|controller| controller.read_measured_value())
+    }
+}
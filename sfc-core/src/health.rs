@@ -0,0 +1,183 @@
+//! Checking that a controller is ready before a run, available with `std`. The drivers offer
+//! `Device::health_check`, which runs the checks of this module and the ones only their device
+//! family supports:
+//! ```
+//! # fn run<C: sfc_core::flow_controller::FlowController>(controller: &mut C) {
+//! use sfc_core::health::{self, HealthRequirements};
+//!
+//! let requirements = HealthRequirements {
+//!     min_firmware: Some((1, 2)),
+//!     ..HealthRequirements::default()
+//! };
+//! let report = health::check(controller, &requirements);
+//! if !report.is_healthy() {
+//!     println!("{}", report);
+//! }
+//! # }
+//! ```
+//! A command that fails only fails the item it belongs to, the other items are still checked.
+
+use std::fmt::Display;
+
+use crate::error::DeviceError;
+use crate::flow_controller::FlowController;
+use crate::shdlc::Version;
+
+/// What a controller has to meet to be reported healthy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HealthRequirements {
+    /// The oldest accepted firmware as major and minor version, any firmware if [None]
+    pub min_firmware: Option<(u8, u8)>,
+    /// How far from zero the measured flow may be while the setpoint is zero, as a fraction of
+    /// the full scale. 1% by default, not checked if [None].
+    pub zero_flow_tolerance: Option<f32>,
+}
+
+impl Default for HealthRequirements {
+    fn default() -> Self {
+        Self {
+            min_firmware: None,
+            zero_flow_tolerance: Some(0.01),
+        }
+    }
+}
+
+/// The result of a single item of a [HealthReport]
+#[derive(Clone, Debug, PartialEq)]
+pub enum CheckOutcome {
+    Passed,
+    /// The item failed for the given reason, or a command it needed failed
+    Failed(String),
+    /// The item was not checked for the given reason
+    Skipped(&'static str),
+}
+
+impl CheckOutcome {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed(_))
+    }
+}
+
+/// A command the item needed failed
+impl From<DeviceError> for CheckOutcome {
+    fn from(error: DeviceError) -> Self {
+        Self::Failed(error.to_string())
+    }
+}
+
+impl Display for CheckOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Passed => write!(f, "passed"),
+            Self::Failed(reason) => write!(f, "failed, {}", reason),
+            Self::Skipped(reason) => write!(f, "skipped, {}", reason),
+        }
+    }
+}
+
+/// The outcome of a health check, item by item
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthReport {
+    /// The device answers at all
+    pub link: CheckOutcome,
+    /// The firmware is at least [HealthRequirements::min_firmware]
+    pub firmware: CheckOutcome,
+    /// The device has no errors latched
+    pub device_errors: CheckOutcome,
+    /// The active calibration is valid
+    pub calibration: CheckOutcome,
+    /// The measured flow is within [HealthRequirements::zero_flow_tolerance] of zero while the
+    /// setpoint is zero
+    pub zero_flow: CheckOutcome,
+    /// The version read for the link and firmware items
+    pub version: Option<Version>,
+}
+
+impl HealthReport {
+    /// The overall verdict, true if no item failed
+    pub fn is_healthy(&self) -> bool {
+        self.items().iter().all(|(_, outcome)| !outcome.is_failed())
+    }
+
+    /// Every item with its name, in the order they are checked
+    pub fn items(&self) -> [(&'static str, &CheckOutcome); 5] {
+        [
+            ("link", &self.link),
+            ("firmware", &self.firmware),
+            ("device errors", &self.device_errors),
+            ("calibration", &self.calibration),
+            ("zero flow", &self.zero_flow),
+        ]
+    }
+}
+
+impl Display for HealthReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verdict = if self.is_healthy() { "healthy" } else { "unhealthy" };
+        write!(f, "{}", verdict)?;
+        for (name, outcome) in self.items() {
+            write!(f, "\n{}: {}", name, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the checks every controller supports: the link and firmware through
+/// [FlowController::get_version] and the flow while the setpoint is zero. The device errors and
+/// calibration are left [CheckOutcome::Skipped] for the driver to fill in. The zero flow item is
+/// skipped while the setpoint is not zero, the setpoint is never changed.
+pub fn check<C: FlowController + ?Sized>(
+    controller: &mut C,
+    requirements: &HealthRequirements,
+) -> HealthReport {
+    let (link, version) = match controller.get_version() {
+        Ok(version) => (CheckOutcome::Passed, Some(version)),
+        Err(e) => (e.into(), None),
+    };
+    let firmware = match (requirements.min_firmware, version) {
+        (None, _) => CheckOutcome::Skipped("no minimum firmware required"),
+        (Some(_), None) => CheckOutcome::Failed("the version could not be read".to_string()),
+        (Some((major, minor)), Some(version)) => {
+            if (version.firmware_major, version.firmware_minor) >= (major, minor) {
+                CheckOutcome::Passed
+            } else {
+                CheckOutcome::Failed(format!(
+                    "firmware {}.{} is older than {}.{}",
+                    version.firmware_major, version.firmware_minor, major, minor
+                ))
+            }
+        }
+    };
+    let zero_flow = match requirements.zero_flow_tolerance {
+        Some(tolerance) => check_zero_flow(controller, tolerance).unwrap_or_else(Into::into),
+        None => CheckOutcome::Skipped("no zero flow tolerance given"),
+    };
+
+    HealthReport {
+        link,
+        firmware,
+        device_errors: CheckOutcome::Skipped("not supported by this device"),
+        calibration: CheckOutcome::Skipped("not supported by this device"),
+        zero_flow,
+        version,
+    }
+}
+
+fn check_zero_flow<C: FlowController + ?Sized>(
+    controller: &mut C,
+    tolerance: f32,
+) -> Result<CheckOutcome, DeviceError> {
+    if controller.get_setpoint()? != 0.0 {
+        return Ok(CheckOutcome::Skipped("the setpoint is not zero"));
+    }
+    let limit = controller.get_full_scale()? * tolerance;
+    let flow = controller.read_measured_value()?;
+    if flow.abs() <= limit {
+        Ok(CheckOutcome::Passed)
+    } else {
+        Ok(CheckOutcome::Failed(format!(
+            "{} flows with a zero setpoint, at most {} allowed",
+            flow, limit
+        )))
+    }
+}
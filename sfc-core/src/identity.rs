@@ -0,0 +1,162 @@
+//! Making sure a command goes to the intended device, available with `std`. On a bench with
+//! several controllers it is easy to open the wrong port. The drivers offer
+//! `Device::assert_identity` and an `identity` option on their builders, which read what an
+//! [IdentityFilter] asks for and fail with [DeviceError::IdentityMismatch] if the device is
+//! another one:
+//! ```
+//! use sfc_core::error::DeviceError;
+//! use sfc_core::identity::{IdentityField, IdentityFilter};
+//!
+//! let filter = IdentityFilter::serial_number("EMU6000001");
+//! let read = |field| match field {
+//!     IdentityField::SerialNumber => Ok("EMU6000002".to_string()),
+//!     _ => unreachable!("only the serial number is asked for"),
+//! };
+//! assert!(matches!(filter.check(read), Err(DeviceError::IdentityMismatch { .. })));
+//! ```
+
+use std::fmt::Display;
+
+use crate::error::DeviceError;
+
+/// What a device has to be. Only the fields that are set are read and compared, the default
+/// filter accepts every device without sending anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IdentityFilter {
+    /// The exact serial number
+    pub serial_number: Option<String>,
+    /// The start of the article code, like `1-101-` for a family of products
+    pub article_code_prefix: Option<String>,
+    /// The exact product type, like `SFC6000`. The SFC5xxx has no product type, its product
+    /// name is compared instead.
+    pub product_type: Option<String>,
+}
+
+impl IdentityFilter {
+    /// A filter for the device with this serial number only
+    pub fn serial_number(serial_number: impl Into<String>) -> Self {
+        Self {
+            serial_number: Some(serial_number.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Whether the filter checks nothing
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Reads every field that is set with `read`, in the order of [IdentityField], and stops at
+    /// the first that doesn't match. A failing read fails the check with its error.
+    pub fn check<F>(&self, mut read: F) -> Result<(), DeviceError>
+    where
+        F: FnMut(IdentityField) -> Result<String, DeviceError>,
+    {
+        let fields = [
+            (IdentityField::SerialNumber, &self.serial_number),
+            (IdentityField::ArticleCode, &self.article_code_prefix),
+            (IdentityField::ProductType, &self.product_type),
+        ];
+        for (field, expected) in fields {
+            let Some(expected) = expected else { continue };
+            let found = read(field)?;
+            let matches = match field {
+                IdentityField::ArticleCode => found.starts_with(expected.as_str()),
+                _ => &found == expected,
+            };
+            if !matches {
+                return Err(DeviceError::IdentityMismatch {
+                    field,
+                    expected: expected.clone(),
+                    found,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A value an [IdentityFilter] compares
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IdentityField {
+    SerialNumber,
+    ArticleCode,
+    ProductType,
+}
+
+impl Display for IdentityField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SerialNumber => write!(f, "serial number"),
+            Self::ArticleCode => write!(f, "article code"),
+            Self::ProductType => write!(f, "product type"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(field: IdentityField) -> Result<String, DeviceError> {
+        Ok(match field {
+            IdentityField::SerialNumber => "EMU6000001",
+            IdentityField::ArticleCode => "1-101-651-01",
+            IdentityField::ProductType => "SFC6000",
+        }
+        .to_string())
+    }
+
+    #[test]
+    fn the_empty_filter_reads_nothing() {
+        let filter = IdentityFilter::default();
+        assert!(filter.is_empty());
+        filter.check(|_| panic!("nothing is read")).unwrap();
+    }
+
+    #[test]
+    fn every_field_that_is_set_has_to_match() {
+        let filter = IdentityFilter {
+            serial_number: Some("EMU6000001".to_string()),
+            article_code_prefix: Some("1-101-".to_string()),
+            product_type: Some("SFC6000".to_string()),
+        };
+        filter.check(device).unwrap();
+
+        let filter = IdentityFilter {
+            article_code_prefix: Some("1-100-".to_string()),
+            ..filter
+        };
+        match filter.check(device) {
+            Err(DeviceError::IdentityMismatch {
+                field,
+                expected,
+                found,
+            }) => {
+                assert_eq!(field, IdentityField::ArticleCode);
+                assert_eq!(expected, "1-100-");
+                assert_eq!(found, "1-101-651-01");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn serial_numbers_and_product_types_match_exactly() {
+        let filter = IdentityFilter::serial_number("EMU600000");
+        assert!(filter.check(device).is_err());
+        let filter = IdentityFilter {
+            product_type: Some("SFC".to_string()),
+            ..IdentityFilter::default()
+        };
+        assert!(filter.check(device).is_err());
+    }
+
+    #[test]
+    fn a_failing_read_fails_the_check() {
+        let filter = IdentityFilter::serial_number("EMU6000001");
+        let error = filter.check(|_| Err(DeviceError::Timeout)).unwrap_err();
+        assert!(matches!(error, DeviceError::Timeout));
+    }
+}
@@ -2,8 +2,140 @@
 //! This library provides shared types and utilities for controlling Sensirions Mass Flow Controllers. Currently it is used by Sfc6xxx-rs and Sfc5xxx-rs
 //! ## Features
 //! - Translating to and from SHDLC in the [shdlc] module
+//! - Naming the commands of both device families in the [names] module
+//! - Knowing which commands the firmware of a device supports in the [capabilities] module
 //! - Handling Shared Device Errors in the [error] module
 //! - Handling common units across devices in the [gasunit] module
+//! - Checking line speeds against the ones the devices support in the [baudrate] module
+//! - Writing code for every device type at once with the [flow_controller] module
+//! - Keeping setpoints within limits set on the host in the [limits] module
+//! - Writing flows with their unit and the percent of the full scale in the [format] module
+//! - Counting commands, retries and errors of a connection in the [stats] module
+//! - Abstracting the connection to a device in the `transport` module
+//! - Sending requests and receiving responses in the `connection` module, and on async streams
+//!   in the `async_connection` module (requires `async`) on any executor
+//! - Sharing one line between several devices in the `bus` module
+//! - Recording readings as CSV or JSON in the `measurement` module
+//! - Raising alarms when the flow stays out of bounds while measuring in the `alarm` module
+//! - Summarizing how stable a burst of readings is in the `flow_statistics` module
+//! - Blending gases at a fixed ratio with several controllers in the `mixer` module
+//! - Setting one controller from the measured value of another in the `cascade` module
+//! - Commanding every controller of a rig at once, whichever of them fail, in the `group` module
+//! - Running setpoint profiles of holds and ramps while measuring in the `profile` module
+//! - Checking that a controller is ready before a run in the `health` module
+//! - Making sure a command goes to the intended device in the `identity` module
+//! - Converging a controller to settings kept in a file in the `config` module
+//! - Recording a compact binary trace of the traffic in the `trace` module (requires
+//!   `trace-postcard`)
+//! - Looking at, logging or refusing the frames of a connection in the `middleware` module
+//! - Replaying frames captured from a device in the `replay` module
+//! - Testing drivers against a serial port that answers expected frames in the `mock` module
+//!   (requires `testing`)
+//! - Decoding raw captures of the line into a readable transcript in the `transcript` module
+//! - Finding connected Sensirion cables in the `discovery` module (requires `serialport`)
+//! ## Feature flags
+//! - `std` (default): the blocking connection and everything else that needs an operating
+//!   system, the `bus`, `connection`, `transport`, `measurement`, `alarm`, `flow_statistics`,
+//!   `mixer`, `cascade`, `group`, `profile`, `health`, `identity`, `config`, `middleware`,
+//!   `replay` and `transcript` modules. Without it the crate is `no_std` and needs no allocator,
+//!   [shdlc], [names], [capabilities], [gasunit], [baudrate], [error] and the async connection
+//!   are left.
+//! - `serialport` (default): implements [transport::Transport] for every serial port and adds
+//!   `DeviceError::PortError` and the `discovery` module. Without it the crate has no platform
+//!   dependencies.
+//! - `log`: emits records for every frame, command and retry through the log crate in the
+//!   [connection] module.
+//! - `tracing`: wraps every command in a span of the tracing crate, see [connection] for the
+//!   fields.
+//! - `async`: adds the `async_connection` module and the traits it runs on in the
+//!   `async_transport` module, without depending on any runtime.
+//! - `defmt`: implements `defmt::Format` for the errors, the frames, [gasunit] and
+//!   [shdlc::Version], with the same messages as their `Display` implementations. Works without
+//!   std.
+//! - `serde`: `Serialize` and `Deserialize` for [gasunit], [shdlc::Version] and, with std, the
+//!   `config::DeviceConfig`. Misspelled fields and variants are renamed, `unit_prefex` is
+//!   `unit_prefix` on the wire.
+//! - `json`: line delimited JSON for the `measurement` module through serde_json, enables `std`
+//!   and `serde`.
+//! - `uom`: [gasunit::GasUnit::to_volume_rate] converts a value in a unit into a uom
+//!   `VolumeRate`. Works without std.
+//! - `metrics`: reports the counters of the [stats] module through the metrics facade as
+//!   well, enables `std`.
+//! - `trace-postcard`: adds the `trace` module, a ring of postcard encoded records that works
+//!   without std and, with std, the decoder for the host. Enables `serde`.
+//! - `testing`: adds the `mock` module with `MockPort`, a serial port that checks the frames a
+//!   driver sends and answers them as queued. Enables `serialport`.
+//! - `emulator`: adds the `emulator` module, the transport, fault injection and framing of the
+//!   in-process devices of the drivers, which only implement their commands. Enables `std`.
+//! - `embedded-io`: adds `DeviceError::EmbeddedIoError` for the errors of embedded-io streams.
+//! - `tokio`, `futures-io` and `embedded-io-async`: adapt the streams of those crates to the
+//!   async connection, see `async_transport`. Each enables `async`, the first two also `std`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+// nothing received from a device may panic the driver, see "Panics" in the README
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::unreachable
+    )
+)]
+
+#[cfg(feature = "std")]
+pub mod alarm;
+#[cfg(feature = "std")]
+pub mod bus;
+#[cfg(feature = "std")]
+pub mod connection;
+#[cfg(feature = "async")]
+pub mod async_connection;
+#[cfg(feature = "async")]
+pub mod async_transport;
+#[cfg(any(feature = "std", feature = "async"))]
+mod exchange;
+pub mod baudrate;
 pub mod gasunit;
+pub mod flow_controller;
+pub mod format;
+pub mod limits;
 pub mod shdlc;
+pub mod names;
+pub mod capabilities;
 pub mod error;
+pub mod stats;
+#[cfg(feature = "serialport")]
+pub mod discovery;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod middleware;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "testing")]
+pub mod mock;
+#[cfg(feature = "emulator")]
+pub mod emulator;
+#[cfg(feature = "std")]
+pub mod measurement;
+#[cfg(feature = "std")]
+pub mod flow_statistics;
+#[cfg(feature = "std")]
+pub mod mixer;
+#[cfg(feature = "std")]
+pub mod cascade;
+#[cfg(feature = "std")]
+pub mod group;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod health;
+#[cfg(feature = "std")]
+pub mod identity;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod transcript;
+#[cfg(feature = "trace-postcard")]
+pub mod trace;
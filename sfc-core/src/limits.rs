@@ -0,0 +1,156 @@
+//! Host side limits on the setpoint, below whatever the device itself accepts. Process safety
+//! may allow a line no more than 80% of the controller's full scale, the devices check every
+//! setpoint they write against their [SoftLimits] before sending it:
+//! ```
+//! use sfc_core::limits::{Limit, LimitPolicy, SoftLimits};
+//! use sfc_core::error::DeviceError;
+//!
+//! let limits = SoftLimits::new(0.0, 4.0);
+//! assert_eq!(limits.apply(2.5).unwrap(), 2.5);
+//! assert!(matches!(limits.apply(4.5), Err(DeviceError::SoftLimit(4.5, Limit::Max(4.0)))));
+//!
+//! let clamping = limits.with_policy(LimitPolicy::Clamp);
+//! assert_eq!(clamping.apply(4.5).unwrap(), 4.0);
+//! ```
+//! The limits are physical values in the unit the device measures in. Setpoints in other scales
+//! are converted before they are checked.
+
+use core::fmt::Display;
+
+use crate::error::DeviceError;
+
+/// What happens to a setpoint outside the [SoftLimits]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LimitPolicy {
+    /// Fail with [DeviceError::SoftLimit] without sending anything
+    #[default]
+    Error,
+    /// Send the nearest limit instead
+    Clamp,
+}
+
+/// The limit a setpoint was outside of, with its value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Min(f32),
+    Max(f32),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Min(min) => write!(f, "soft minimum of {}", min),
+            Self::Max(max) => write!(f, "soft maximum of {}", max),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Limit {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Min(min) => defmt::write!(f, "soft minimum of {}", min),
+            Self::Max(max) => defmt::write!(f, "soft maximum of {}", max),
+        }
+    }
+}
+
+/// The range of setpoints a device may be given, see the [module documentation](self). The
+/// default allows every setpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SoftLimits {
+    pub min: f32,
+    pub max: f32,
+    pub policy: LimitPolicy,
+}
+
+impl Default for SoftLimits {
+    fn default() -> Self {
+        Self {
+            min: f32::NEG_INFINITY,
+            max: f32::INFINITY,
+            policy: LimitPolicy::default(),
+        }
+    }
+}
+
+impl SoftLimits {
+    /// Limits from `min` to `max`, both allowed, that reject setpoints outside them
+    ///
+    /// # Panics
+    /// If `min` is above `max` or either is NaN
+    pub fn new(min: f32, max: f32) -> Self {
+        assert!(min <= max, "soft limits from {} to {} are empty", min, max);
+        Self {
+            min,
+            max,
+            ..Self::default()
+        }
+    }
+
+    /// Sets what happens to setpoints outside the limits
+    pub fn with_policy(mut self, policy: LimitPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns true if the limits allow every setpoint
+    pub fn is_unlimited(&self) -> bool {
+        self.min == f32::NEG_INFINITY && self.max == f32::INFINITY
+    }
+
+    /// Returns the setpoint to send: the setpoint itself if it is within the limits, and
+    /// otherwise the nearest limit or [DeviceError::SoftLimit], depending on the policy. NaN is
+    /// returned as is for the device to reject.
+    pub fn apply(&self, setpoint: f32) -> Result<f32, DeviceError> {
+        let limit = if setpoint < self.min {
+            Limit::Min(self.min)
+        } else if setpoint > self.max {
+            Limit::Max(self.max)
+        } else {
+            return Ok(setpoint);
+        };
+        match (self.policy, limit) {
+            (LimitPolicy::Error, limit) => Err(DeviceError::SoftLimit(setpoint, limit)),
+            (LimitPolicy::Clamp, Limit::Min(min)) => Ok(min),
+            (LimitPolicy::Clamp, Limit::Max(max)) => Ok(max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_are_inclusive() {
+        let limits = SoftLimits::new(0.5, 4.0);
+        for setpoint in [0.5, 2.0, 4.0] {
+            assert_eq!(limits.apply(setpoint).unwrap(), setpoint);
+        }
+        assert!(matches!(
+            limits.apply(0.0),
+            Err(DeviceError::SoftLimit(0.0, Limit::Min(0.5)))
+        ));
+        assert!(limits.apply(f32::NAN).unwrap().is_nan());
+
+        let clamping = limits.with_policy(LimitPolicy::Clamp);
+        assert_eq!(clamping.apply(0.0).unwrap(), 0.5);
+        assert_eq!(clamping.apply(5.0).unwrap(), 4.0);
+    }
+
+    #[test]
+    fn default_allows_everything() {
+        assert!(SoftLimits::default().is_unlimited());
+        assert!(!SoftLimits::new(0.0, f32::INFINITY).is_unlimited());
+        assert_eq!(SoftLimits::default().apply(f32::MAX).unwrap(), f32::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "soft limits from 2 to 1 are empty")]
+    fn empty_limits() {
+        SoftLimits::new(2.0, 1.0);
+    }
+}
@@ -0,0 +1,527 @@
+//! Measurements as records for a data pipeline, available with the `std` feature. A
+//! [Measurement] carries the flow together with when and where it was read, and is written as
+//! CSV or, with the `json` feature, as line delimited JSON by a [MeasurementWriter]. The devices
+//! return single ones from their `_recorded` reads, which attach the unit and serial number they
+//! keep from the first of them instead of asking the device every time.
+//!
+//! Every [FlowController] reads them at a fixed interval with
+//! [FlowController::measurements]:
+//! ```no_run
+//! # fn run<C: sfc_core::flow_controller::FlowController>(device: &mut C) -> std::io::Result<()> {
+//! use std::time::Duration;
+//! use sfc_core::measurement::{Format, MeasurementWriter};
+//!
+//! let file = std::fs::File::create("flow.csv")?;
+//! let mut writer = MeasurementWriter::new(file, Format::Csv);
+//! for measurement in device.measurements(Duration::from_millis(100)).take(600) {
+//!     match measurement {
+//!         Ok(measurement) => writer.write(&measurement)?,
+//!         Err(e) => eprintln!("reading failed: {}", e),
+//!     }
+//! }
+//! writer.flush()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::alarm::{Alarm, WithAlarm};
+use crate::error::DeviceError;
+use crate::flow_controller::FlowController;
+use crate::format::Flow;
+use crate::gasunit::GasUnit;
+
+/// What a value is relative to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ValueScale {
+    /// A physical value in the unit of the measurement
+    Physical,
+    /// A fraction of the full scale, from 0.0 to 1.0
+    Normalized,
+    /// Scaled by a factor configured on the device
+    UserDefined,
+}
+
+impl Display for ValueScale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Physical => write!(f, "physical"),
+            Self::Normalized => write!(f, "normalized"),
+            Self::UserDefined => write!(f, "user_defined"),
+        }
+    }
+}
+
+/// One flow reading and where it came from. Measurements are ordered by the time they were
+/// read, see the [PartialOrd] implementation.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Measurement {
+    /// When the value was read, as seconds since the unix epoch in CSV and JSON
+    #[cfg_attr(feature = "serde", serde(with = "unix_seconds"))]
+    pub timestamp: SystemTime,
+    /// When the value was read on the monotonic clock of this process, which doesn't jump when
+    /// the system time is set. Neither written nor read as CSV or JSON.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub monotonic: Option<Instant>,
+    pub value: f32,
+    pub unit: GasUnit,
+    pub scale: ValueScale,
+    pub serial_number: Option<String>,
+    /// The slave address of the device, if it is known. Only written as JSON, and only when
+    /// known.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub address: Option<u8>,
+    /// The setpoint when the value was read, if it is known
+    pub setpoint: Option<f32>,
+    /// The full scale of the calibration the value was read with, if it is known. Only written
+    /// as JSON, and only when known.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub full_scale: Option<f32>,
+}
+
+impl Measurement {
+    /// A value read just now, stamped with both clocks. The serial number, address, setpoint and
+    /// full scale are unknown until set.
+    pub fn new(value: f32, unit: GasUnit, scale: ValueScale) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            monotonic: Some(Instant::now()),
+            value,
+            unit,
+            scale,
+            serial_number: None,
+            address: None,
+            setpoint: None,
+            full_scale: None,
+        }
+    }
+
+    /// The value as a [Flow] with the unit and full scale, for writing it with a
+    /// [FlowFormat](crate::format::FlowFormat). [None] unless it is a physical value.
+    pub fn flow(&self) -> Option<Flow> {
+        (self.scale == ValueScale::Physical).then_some(Flow {
+            value: self.value,
+            unit: self.unit,
+            full_scale: self.full_scale,
+        })
+    }
+
+    /// The column names of [Measurement::to_csv_row], without a line break
+    pub fn csv_header() -> &'static str {
+        "timestamp,value,unit,scale,serial_number,setpoint"
+    }
+
+    /// The measurement as one CSV line without the line break. Fields containing a comma, a
+    /// quote or a line break are quoted, missing values are empty.
+    pub fn to_csv_row(&self) -> String {
+        let serial_number = self.serial_number.as_deref().map(escape).unwrap_or_default();
+        let setpoint = self.setpoint.map(|s| s.to_string()).unwrap_or_default();
+        format!(
+            "{:.6},{},{},{},{},{}",
+            seconds(self.timestamp),
+            self.value,
+            escape(&self.unit.to_string()),
+            self.scale,
+            serial_number,
+            setpoint
+        )
+    }
+}
+
+/// By the time the values were read, on the monotonic clock when both have been stamped with
+/// it and by the system time otherwise. Measurements read at the same time but differing
+/// otherwise are unordered.
+impl PartialOrd for Measurement {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let by_time = match (self.monotonic, other.monotonic) {
+            (Some(this), Some(other)) => this.cmp(&other),
+            _ => self.timestamp.cmp(&other.timestamp),
+        };
+        match by_time {
+            Ordering::Equal if self != other => None,
+            ordering => Some(ordering),
+        }
+    }
+}
+
+/// `2.5 ml/min from EMU6000001 at 1700000000.250`, values that aren't physical are followed by
+/// their scale and the source is the serial number or else the address
+impl Display for Measurement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.flow() {
+            Some(flow) => write!(f, "{}", flow)?,
+            None => write!(f, "{} ({})", self.value, self.scale)?,
+        }
+        match (&self.serial_number, self.address) {
+            (Some(serial_number), _) => write!(f, " from {}", serial_number)?,
+            (None, Some(address)) => write!(f, " from address {}", address)?,
+            (None, None) => {}
+        }
+        write!(f, " at {:.3}", seconds(self.timestamp))
+    }
+}
+
+/// Quotes a CSV field when it needs to be, doubling the quotes inside it
+fn escape(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Seconds since the unix epoch, negative before it
+fn seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(before) => -before.duration().as_secs_f64(),
+    }
+}
+
+#[cfg(feature = "serde")]
+mod unix_seconds {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(time: &SystemTime, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(super::seconds(*time))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<SystemTime, D::Error> {
+        let seconds = f64::deserialize(d)?;
+        let since = Duration::try_from_secs_f64(seconds.abs()).map_err(serde::de::Error::custom)?;
+        Ok(if seconds < 0.0 { UNIX_EPOCH - since } else { UNIX_EPOCH + since })
+    }
+}
+
+/// How a [MeasurementWriter] writes its rows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A header line followed by one [Measurement::to_csv_row] per line
+    Csv,
+    /// One JSON object per line, with the field names of [Measurement]. Requires `json`.
+    #[cfg(feature = "json")]
+    JsonLines,
+}
+
+/// Appends measurements to a file or any other writer, flushing it at an interval so a
+/// process that is killed loses at most the rows since the last flush
+#[derive(Debug)]
+pub struct MeasurementWriter<W: Write> {
+    writer: io::BufWriter<W>,
+    format: Format,
+    flush_interval: Duration,
+    last_flush: Instant,
+    header_written: bool,
+}
+
+impl<W: Write> MeasurementWriter<W> {
+    /// Writes in the given format, flushing at least once a second
+    pub fn new(writer: W, format: Format) -> Self {
+        Self {
+            writer: io::BufWriter::new(writer),
+            format,
+            flush_interval: Duration::from_secs(1),
+            last_flush: Instant::now(),
+            header_written: false,
+        }
+    }
+
+    /// Writes the CSV header before the first row, for appending to a file that already has one
+    /// use `false`
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header_written = !header;
+        self
+    }
+
+    /// Sets how long rows may sit in the buffer before it is flushed, `Duration::ZERO` flushes
+    /// every row
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Appends one measurement
+    pub fn write(&mut self, measurement: &Measurement) -> io::Result<()> {
+        match self.format {
+            Format::Csv => {
+                if !self.header_written {
+                    writeln!(self.writer, "{}", Measurement::csv_header())?;
+                    self.header_written = true;
+                }
+                writeln!(self.writer, "{}", measurement.to_csv_row())?;
+            }
+            #[cfg(feature = "json")]
+            Format::JsonLines => {
+                serde_json::to_writer(&mut self.writer, measurement)?;
+                writeln!(self.writer)?;
+            }
+        }
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out every buffered row
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+
+    /// Flushes the rows and returns the writer
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+/// Reads a [FlowController] at a fixed interval, see [FlowController::measurements]. The unit,
+/// serial number and setpoint are read once before the first value, nothing else can change
+/// them while the controller is borrowed.
+#[derive(Debug)]
+pub struct Measurements<'a, C: FlowController> {
+    controller: &'a mut C,
+    interval: Duration,
+    next: Option<Instant>,
+    context: Option<(GasUnit, String, f32, f32)>,
+}
+
+impl<'a, C: FlowController> Measurements<'a, C> {
+    pub(crate) fn new(controller: &'a mut C, interval: Duration) -> Self {
+        Self {
+            controller,
+            interval,
+            next: None,
+            context: None,
+        }
+    }
+
+    /// Evaluates an [Alarm] over the measurements, see the [alarm](crate::alarm) module
+    pub fn with_alarm(self, alarm: Alarm) -> WithAlarm<Self> {
+        WithAlarm::new(self, alarm)
+    }
+
+    fn measure(&mut self) -> Result<Measurement, DeviceError> {
+        let (unit, serial_number, setpoint, full_scale) = match &self.context {
+            Some(context) => context.clone(),
+            None => {
+                let context = (
+                    self.controller.get_gas_unit()?,
+                    self.controller.get_serial_number()?.to_string(),
+                    self.controller.get_setpoint()?,
+                    self.controller.get_full_scale()?,
+                );
+                self.context.insert(context).clone()
+            }
+        };
+        let value = self.controller.read_measured_value()?;
+        Ok(Measurement {
+            serial_number: Some(serial_number),
+            setpoint: Some(setpoint),
+            full_scale: Some(full_scale),
+            ..Measurement::new(value, unit, ValueScale::Physical)
+        })
+    }
+}
+
+/// Never ends, a failed read is returned and the next one is tried at the next interval
+impl<C: FlowController> Iterator for Measurements<'_, C> {
+    type Item = Result<Measurement, DeviceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let now = Instant::now();
+        let due = *self.next.get_or_insert(now);
+        if due > now {
+            thread::sleep(due - now);
+        }
+        // a late read moves the schedule instead of catching up with a burst
+        self.next = Some(due.max(now) + self.interval);
+        Some(self.measure())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gasunit::{Prefixes, TimeBases, Units};
+
+    fn measurement() -> Measurement {
+        Measurement {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_250),
+            monotonic: None,
+            value: 2.5,
+            unit: GasUnit {
+                unit_prefex: Prefixes::Milli,
+                medium_unit: Units::StandardLiter,
+                timebase: TimeBases::Minute,
+            },
+            scale: ValueScale::Physical,
+            serial_number: Some("EMU6000001".to_string()),
+            address: None,
+            setpoint: Some(2.0),
+            full_scale: None,
+        }
+    }
+
+    #[test]
+    fn csv_columns() {
+        assert_eq!(Measurement::csv_header(), "timestamp,value,unit,scale,serial_number,setpoint");
+        assert_eq!(
+            measurement().to_csv_row(),
+            "1700000000.250000,2.5,ml/min,physical,EMU6000001,2"
+        );
+
+        let unknown = Measurement {
+            serial_number: None,
+            setpoint: None,
+            ..measurement()
+        };
+        assert_eq!(unknown.to_csv_row(), "1700000000.250000,2.5,ml/min,physical,,");
+    }
+
+    #[test]
+    fn csv_escaping() {
+        let odd = Measurement {
+            serial_number: Some("a,\"b\"\nc".to_string()),
+            ..measurement()
+        };
+        assert!(odd.to_csv_row().ends_with(",\"a,\"\"b\"\"\nc\",2"));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(measurement().to_string(), "2.5 ml/min from EMU6000001 at 1700000000.250");
+        let normalized = Measurement {
+            value: 0.5,
+            scale: ValueScale::Normalized,
+            serial_number: None,
+            address: Some(3),
+            ..measurement()
+        };
+        assert_eq!(normalized.to_string(), "0.5 (normalized) from address 3 at 1700000000.250");
+
+        let with_full_scale = Measurement {
+            value: 2.4987,
+            full_scale: Some(5.0),
+            ..measurement()
+        };
+        assert_eq!(
+            with_full_scale.to_string(),
+            "2.499 ml/min (50.0% FS) from EMU6000001 at 1700000000.250"
+        );
+    }
+
+    /// Later measurements compare greater, by the monotonic clock when it is known even if the
+    /// system time went backwards in between
+    #[test]
+    fn ordered_by_time() {
+        let first = Measurement::new(1.0, measurement().unit, ValueScale::Physical);
+        let second = Measurement::new(2.0, measurement().unit, ValueScale::Physical);
+        assert!(second.monotonic.unwrap() >= first.monotonic.unwrap());
+        assert!(second.timestamp >= first.timestamp);
+        let later = Measurement {
+            timestamp: first.timestamp - Duration::from_secs(3600),
+            monotonic: Some(first.monotonic.unwrap() + Duration::from_millis(1)),
+            ..second
+        };
+        assert!(later > first);
+
+        let earlier = Measurement {
+            timestamp: measurement().timestamp - Duration::from_secs(1),
+            ..measurement()
+        };
+        assert!(earlier < measurement());
+        let mut shuffled = [measurement(), later, earlier.clone()];
+        shuffled.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(shuffled[0], earlier);
+
+        let same_time = Measurement {
+            value: 3.0,
+            ..measurement()
+        };
+        assert_eq!(same_time.partial_cmp(&measurement()), None);
+        assert_eq!(measurement().partial_cmp(&measurement()), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn writer_adds_the_header_once() {
+        let mut writer = MeasurementWriter::new(Vec::new(), Format::Csv);
+        writer.write(&measurement()).unwrap();
+        writer.write(&measurement()).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], Measurement::csv_header());
+
+        let mut appending = MeasurementWriter::new(Vec::new(), Format::Csv).with_header(false);
+        appending.write(&measurement()).unwrap();
+        assert_eq!(appending.into_inner().unwrap().iter().filter(|&&b| b == b'\n').count(), 1);
+    }
+
+    /// The writer is flushed once the interval has passed, not on every row
+    #[test]
+    fn writer_flushes_periodically() {
+        #[derive(Default)]
+        struct Counting {
+            flushes: usize,
+        }
+
+        impl Write for Counting {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushes += 1;
+                Ok(())
+            }
+        }
+
+        let mut writer = MeasurementWriter::new(Counting::default(), Format::Csv)
+            .with_flush_interval(Duration::from_secs(3600));
+        for _ in 0..10 {
+            writer.write(&measurement()).unwrap();
+        }
+        assert_eq!(writer.writer.get_ref().flushes, 0);
+
+        writer = writer.with_flush_interval(Duration::ZERO);
+        writer.write(&measurement()).unwrap();
+        assert_eq!(writer.writer.get_ref().flushes, 1);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_field_names() {
+        let json = serde_json::to_string(&measurement()).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"timestamp":1700000000.25,"value":2.5,"#,
+                r#""unit":{"unit_prefix":"Milli","medium_unit":"StandardLiter","timebase":"Minute"},"#,
+                r#""scale":"physical","serial_number":"EMU6000001","setpoint":2.0}"#
+            )
+        );
+        assert_eq!(serde_json::from_str::<Measurement>(&json).unwrap(), measurement());
+
+        let mut writer = MeasurementWriter::new(Vec::new(), Format::JsonLines);
+        writer.write(&measurement()).unwrap();
+        let lines = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(lines, json + "\n");
+    }
+}
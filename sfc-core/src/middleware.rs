@@ -0,0 +1,188 @@
+//! Looking at the traffic of a connection without changing the driver, available with `std`. A
+//! [FrameMiddleware] set on a [Connection](crate::connection::Connection), or on a device with
+//! `Device::set_middleware`, sees every request before it is sent and every response it got.
+//! It can refuse to send a request, which then fails with [DeviceError::BlockedByMiddleware],
+//! log the frames for compliance or sleep to slow the line down in a test:
+//! ```
+//! use std::time::Duration;
+//!
+//! use sfc_core::middleware::{Action, FrameMiddleware};
+//! use sfc_core::shdlc::{MISOFrame, MOSIFrame};
+//!
+//! /// Prints every frame and adds some latency to every request
+//! struct Slow;
+//!
+//! impl FrameMiddleware for Slow {
+//!     fn on_transmit(&mut self, frame: &MOSIFrame) -> Action {
+//!         println!("{}", frame);
+//!         std::thread::sleep(Duration::from_millis(5));
+//!         Action::Continue
+//!     }
+//!
+//!     fn on_receive(&mut self, frame: &MISOFrame) {
+//!         println!("{:?}", frame);
+//!     }
+//! }
+//! ```
+//! [CommandBlacklist] is a middleware that refuses a set of commands.
+
+use std::collections::BTreeSet;
+
+use crate::error::DeviceError;
+use crate::shdlc::{MISOFrame, MOSIFrame};
+
+/// What happens to a request after [FrameMiddleware::on_transmit] looked at it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Send the request
+    Continue,
+    /// Don't send the request, the command fails with [DeviceError::BlockedByMiddleware] giving
+    /// this reason
+    Block(String),
+}
+
+/// A hook into the requests and responses of a connection, see the
+/// [module documentation](self). Both methods do nothing by default, so a middleware only
+/// implements the ones it needs.
+///
+/// The middleware is called once per command, not per attempt: a retry or a command sent again
+/// while the device is busy or booting is not passed to it a second time. Responses with an
+/// error state fail the command with [DeviceError::StateResponse] and are not passed to
+/// [FrameMiddleware::on_receive], neither are the probes of
+/// [Connection::resync](crate::connection::Connection::resync).
+///
+/// It is `Send` and `Sync` so a device with a middleware can still be moved to and shared
+/// with other threads.
+pub trait FrameMiddleware: Send + Sync {
+    /// Called with every request before it is sent. [Action::Block] fails the command without
+    /// sending anything. For [Connection::pipeline](crate::connection::Connection::pipeline)
+    /// every frame is looked at before the first is sent, one blocked frame fails all of them.
+    fn on_transmit(&mut self, frame: &MOSIFrame) -> Action {
+        let _ = frame;
+        Action::Continue
+    }
+
+    /// Called with every response accepted as the answer to a request
+    fn on_receive(&mut self, frame: &MISOFrame) {
+        let _ = frame;
+    }
+}
+
+/// Runs the request past the middleware, the error to fail the command with if it is blocked
+pub(crate) fn transmit<const N: usize>(
+    middleware: &mut Option<Box<dyn FrameMiddleware>>,
+    frame: &MOSIFrame<N>,
+) -> Result<(), DeviceError> {
+    let Some(middleware) = middleware else {
+        return Ok(());
+    };
+    match middleware.on_transmit(&frame.widen()?) {
+        Action::Continue => Ok(()),
+        Action::Block(reason) => {
+            Err(DeviceError::BlockedByMiddleware(frame.get_command_number(), reason))
+        }
+    }
+}
+
+/// Refuses to send the commands it holds, for sites where some commands, like changing the
+/// address or a factory reset, must never be sent from the host:
+/// ```
+/// use sfc_core::middleware::{Action, CommandBlacklist, FrameMiddleware};
+/// use sfc_core::shdlc::MOSIFrame;
+///
+/// let mut blacklist = CommandBlacklist::new([0x90, 0x92]);
+/// let reset = MOSIFrame::new(0, 0x92, &[]).unwrap();
+/// assert!(matches!(blacklist.on_transmit(&reset), Action::Block(_)));
+/// let version = MOSIFrame::new(0, 0xD1, &[]).unwrap();
+/// assert_eq!(blacklist.on_transmit(&version), Action::Continue);
+/// ```
+/// A command is refused with every subcommand, whatever address it goes to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandBlacklist {
+    commands: BTreeSet<u8>,
+}
+
+impl CommandBlacklist {
+    /// A blacklist of the command bytes
+    pub fn new(commands: impl IntoIterator<Item = u8>) -> Self {
+        Self {
+            commands: commands.into_iter().collect(),
+        }
+    }
+
+    /// Refuses the command as well
+    pub fn block(&mut self, command: u8) {
+        self.commands.insert(command);
+    }
+
+    /// Sends the command again, returns false if it wasn't refused
+    pub fn allow(&mut self, command: u8) -> bool {
+        self.commands.remove(&command)
+    }
+
+    /// Whether the command is refused
+    pub fn is_blocked(&self, command: u8) -> bool {
+        self.commands.contains(&command)
+    }
+}
+
+impl FrameMiddleware for CommandBlacklist {
+    fn on_transmit(&mut self, frame: &MOSIFrame) -> Action {
+        let command = frame.get_command_number();
+        if !self.is_blocked(command) {
+            return Action::Continue;
+        }
+        match frame.name() {
+            Some(name) => Action::Block(format!("{} ({:#04x}) is blacklisted", name, command)),
+            None => Action::Block(format!("command {:#04x} is blacklisted", command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::names::DeviceFamily;
+
+    #[test]
+    fn only_the_listed_commands_are_blocked() {
+        let mut blacklist = CommandBlacklist::new([0x91]);
+        let version = MOSIFrame::new(0, 0xD1, &[]).unwrap();
+        assert_eq!(blacklist.on_transmit(&version), Action::Continue);
+        let baudrate = MOSIFrame::new(3, 0x91, &[0, 0, 0x96, 0]).unwrap();
+        assert_eq!(
+            blacklist.on_transmit(&baudrate),
+            Action::Block("command 0x91 is blacklisted".to_string())
+        );
+
+        blacklist.block(0xD1);
+        assert!(matches!(blacklist.on_transmit(&version), Action::Block(_)));
+        assert!(blacklist.allow(0x91));
+        assert!(!blacklist.allow(0x91));
+        assert_eq!(blacklist.on_transmit(&baudrate), Action::Continue);
+    }
+
+    #[test]
+    fn a_blocked_command_is_named_if_it_can_be() {
+        let mut blacklist = CommandBlacklist::new([0xD1]);
+        let version = MOSIFrame::new(0, 0xD1, &[]).unwrap().with_family(DeviceFamily::Sfc6xxx);
+        let Action::Block(reason) = blacklist.on_transmit(&version) else {
+            panic!("the version command is blacklisted");
+        };
+        assert!(reason.ends_with("(0xd1) is blacklisted"), "{}", reason);
+    }
+
+    #[test]
+    fn a_blocked_frame_fails_with_its_command() {
+        let mut middleware: Option<Box<dyn FrameMiddleware>> =
+            Some(Box::new(CommandBlacklist::new([0x00])));
+        let setpoint = crate::shdlc::SmallFrame::sized(0, 0x00, &[0x01]).unwrap();
+        match transmit(&mut middleware, &setpoint) {
+            Err(DeviceError::BlockedByMiddleware(0x00, reason)) => {
+                assert_eq!(reason, "command 0x00 is blacklisted")
+            }
+            other => panic!("expected the setpoint to be blocked, got {:?}", other),
+        }
+        assert!(transmit(&mut None, &setpoint).is_ok());
+    }
+}
@@ -0,0 +1,246 @@
+//! Blending gases at a fixed ratio with several controllers, available with `std`. A [Mixer]
+//! owns one [FlowController] per gas and splits a total flow between them:
+//! ```
+//! # fn run<C: sfc_core::flow_controller::FlowController>(nitrogen: C, oxygen: C)
+//! #     -> Result<(), sfc_core::mixer::MixerError> {
+//! use sfc_core::mixer::Mixer;
+//!
+//! // 79% nitrogen and 21% oxygen
+//! let mut mixer = Mixer::new(vec![(nitrogen, 79.0), (oxygen, 21.0)]);
+//! mixer.set_total_flow(2.0)?;
+//! let ratio = mixer.read_actual_ratio()?;
+//! if let Err(errors) = mixer.stop_all() {
+//!     for error in errors {
+//!         eprintln!("could not stop {}", error);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//! The controllers should all use the same gas unit, the mixer adds their flows as they are.
+
+use std::fmt::Display;
+
+use crate::error::DeviceError;
+use crate::flow_controller::FlowController;
+
+/// What [Mixer::set_total_flow] does when a channel would need more than its full scale
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaturationPolicy {
+    /// Fail with [MixerError::Saturated] without changing any setpoint
+    #[default]
+    Error,
+    /// Lower the total flow until the fullest channel is at its full scale, keeping the ratio
+    Rescale,
+}
+
+/// A command to one channel of a [Mixer] failed
+#[derive(Debug)]
+pub struct ChannelError {
+    /// The index of the channel, in the order the controllers were given
+    pub channel: usize,
+    pub error: DeviceError,
+}
+
+impl Display for ChannelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "channel {}: {}", self.channel, self.error)
+    }
+}
+
+/// Why a [Mixer] operation failed
+#[derive(Debug)]
+pub enum MixerError {
+    /// A channel would need a setpoint above its full scale
+    Saturated {
+        channel: usize,
+        setpoint: f32,
+        full_scale: f32,
+    },
+    /// A command to a channel failed. The channels before it have already been changed.
+    Channel(ChannelError),
+}
+
+impl Display for MixerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Saturated {
+                channel,
+                setpoint,
+                full_scale,
+            } => write!(
+                f,
+                "channel {} would need {} but its full scale is {}",
+                channel, setpoint, full_scale
+            ),
+            Self::Channel(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<ChannelError> for MixerError {
+    fn from(value: ChannelError) -> Self {
+        Self::Channel(value)
+    }
+}
+
+/// Controllers blending their gases at fixed ratios, see the [module documentation](self)
+#[derive(Debug)]
+pub struct Mixer<C: FlowController> {
+    channels: Vec<C>,
+    /// the share of every channel in the total flow, adding up to 1
+    shares: Vec<f32>,
+    /// read from the controllers when first needed
+    full_scales: Option<Vec<f32>>,
+    policy: SaturationPolicy,
+}
+
+impl<C: FlowController> Mixer<C> {
+    /// Creates a mixer from controllers and their part of the mix. The parts are relative to
+    /// each other, `[(a, 1.0), (b, 3.0)]` makes a quarter of the flow go through `a`. The
+    /// saturation policy is [SaturationPolicy::Error] until changed.
+    ///
+    /// # Panics
+    /// If a part is negative or not finite, or all of them are zero
+    pub fn new(channels: Vec<(C, f32)>) -> Self {
+        let sum: f32 = channels.iter().map(|(_, part)| part).sum();
+        assert!(
+            channels.iter().all(|(_, part)| part.is_finite() && *part >= 0.0) && sum > 0.0,
+            "the parts of a mix must be positive"
+        );
+        let (channels, parts): (Vec<C>, Vec<f32>) = channels.into_iter().unzip();
+        Self {
+            channels,
+            shares: parts.iter().map(|part| part / sum).collect(),
+            full_scales: None,
+            policy: SaturationPolicy::default(),
+        }
+    }
+
+    /// Returns what happens when a channel would need more than its full scale
+    pub fn saturation_policy(&self) -> SaturationPolicy {
+        self.policy
+    }
+
+    /// Sets what happens when a channel would need more than its full scale
+    pub fn set_saturation_policy(&mut self, policy: SaturationPolicy) {
+        self.policy = policy;
+    }
+
+    /// The share of every channel in the total flow, adding up to 1
+    pub fn ratio(&self) -> &[f32] {
+        &self.shares
+    }
+
+    /// The number of channels
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Returns the controller of a channel
+    pub fn channel_mut(&mut self, channel: usize) -> Option<&mut C> {
+        self.channels.get_mut(channel)
+    }
+
+    /// Returns the controllers in the order they were given
+    pub fn into_inner(self) -> Vec<C> {
+        self.channels
+    }
+
+    /// Splits the total flow between the channels by their ratio and returns the total that
+    /// was set, which is lower than asked for if a channel saturated under
+    /// [SaturationPolicy::Rescale]. A negative total is treated as zero. The full scales are
+    /// read on the first call and kept.
+    ///
+    /// If setting a channel fails the channels before it keep their new setpoint, call
+    /// [Mixer::stop_all] when a wrong mix is unsafe.
+    pub fn set_total_flow(&mut self, total: f32) -> Result<f32, MixerError> {
+        let full_scales = self.full_scales()?;
+        let mut total = total.max(0.0);
+        // how far the fullest channel is above its full scale, 1 when it's right at it
+        let worst = self
+            .shares
+            .iter()
+            .zip(&full_scales)
+            .map(|(share, &full_scale)| (total * share, full_scale))
+            .enumerate()
+            .max_by(|(_, a), (_, b)| (a.0 / a.1).total_cmp(&(b.0 / b.1)));
+        if let Some((channel, (setpoint, full_scale))) =
+            worst.filter(|&(_, (setpoint, full_scale))| setpoint / full_scale > 1.0)
+        {
+            match self.policy {
+                SaturationPolicy::Error => {
+                    return Err(MixerError::Saturated {
+                        channel,
+                        setpoint,
+                        full_scale,
+                    });
+                }
+                SaturationPolicy::Rescale => total /= setpoint / full_scale,
+            }
+        }
+
+        let shares = self.shares.iter().zip(&full_scales);
+        for (channel, (controller, (share, &full_scale))) in
+            self.channels.iter_mut().zip(shares).enumerate()
+        {
+            // rounding must not push the fullest channel past its full scale
+            let setpoint = (total * share).min(full_scale);
+            controller
+                .set_setpoint(setpoint)
+                .map_err(|error| ChannelError { channel, error })?;
+        }
+        Ok(total)
+    }
+
+    /// Measures every channel and returns its share of the total measured flow, in the same
+    /// form as [Mixer::ratio]. All shares are 0 while nothing flows.
+    pub fn read_actual_ratio(&mut self) -> Result<Vec<f32>, MixerError> {
+        let flows = self
+            .channels
+            .iter_mut()
+            .enumerate()
+            .map(|(channel, controller)| {
+                controller.read_measured_value().map_err(|error| ChannelError { channel, error })
+            })
+            .collect::<Result<Vec<f32>, ChannelError>>()?;
+        let total: f32 = flows.iter().sum();
+        if total <= 0.0 {
+            return Ok(vec![0.0; flows.len()]);
+        }
+        Ok(flows.iter().map(|flow| flow / total).collect())
+    }
+
+    /// Sets every channel to zero flow. A channel that fails doesn't keep the others from being
+    /// stopped, the errors of all failed channels are returned together.
+    pub fn stop_all(&mut self) -> Result<(), Vec<ChannelError>> {
+        let errors: Vec<ChannelError> = self
+            .channels
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(channel, controller)| {
+                controller.set_setpoint(0.0).err().map(|error| ChannelError { channel, error })
+            })
+            .collect();
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    fn full_scales(&mut self) -> Result<Vec<f32>, ChannelError> {
+        if let Some(full_scales) = &self.full_scales {
+            return Ok(full_scales.clone());
+        }
+        let full_scales = self
+            .channels
+            .iter_mut()
+            .enumerate()
+            .map(|(channel, controller)| {
+                controller.get_full_scale().map_err(|error| ChannelError { channel, error })
+            })
+            .collect::<Result<Vec<f32>, ChannelError>>()?;
+        Ok(self.full_scales.insert(full_scales).clone())
+    }
+}
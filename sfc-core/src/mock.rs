@@ -0,0 +1,516 @@
+//! A serial port for testing drivers without hardware, available with the `testing` feature. A
+//! [MockPort] is told up front which frames the driver sends and what the device answers to
+//! each, every other frame fails the write. It is a [serialport::SerialPort], so a driver takes
+//! it wherever it takes a port:
+//! ```
+//! use sfc_core::connection::Connection;
+//! use sfc_core::mock::{Expectation, MockPort};
+//! use sfc_core::shdlc::MOSIFrame;
+//!
+//! let port = MockPort::new();
+//! let handle = port.handle();
+//! // the baudrate of the device at address 0
+//! handle.expect(Expectation::new(0, 0x91, &[]).respond(&115200_u32.to_be_bytes()));
+//!
+//! let mut connection = Connection::new(port);
+//! let response = connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap()).unwrap();
+//! assert_eq!(response.into_data().as_slice(), &115200_u32.to_be_bytes());
+//! handle.assert_done();
+//! ```
+//! Frames are compared as they are sent, byte stuffed and with both delimiters, the responses
+//! are stuffed the same way. An [Expectation] can answer with an error state, a wrong checksum,
+//! any raw bytes or nothing at all, and split its response over several reads.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use crate::shdlc::{MAX_PAYLOAD, MISOFrame, MOSIFrame, START_STOP};
+
+/// A frame the driver is expected to send and the response to it, queued with
+/// [MockHandle::expect]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expectation {
+    address: u8,
+    command: u8,
+    request: Vec<u8>,
+    response: Response,
+    chunk: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Response {
+    /// nothing, reading times out
+    Silent,
+    Frame {
+        state: u8,
+        data: Vec<u8>,
+        corrupt_checksum: bool,
+    },
+    Raw(Vec<u8>),
+}
+
+impl Expectation {
+    /// Expects the MOSI frame with this address, command and data, which is answered with
+    /// nothing until a response is set
+    ///
+    /// # Panics
+    /// If the data is longer than [MAX_PAYLOAD]
+    pub fn new(address: u8, command: u8, data: &[u8]) -> Self {
+        assert!(data.len() <= MAX_PAYLOAD, "a frame holds at most {} bytes", MAX_PAYLOAD);
+        // the data fits, the frame is always built
+        let request = MOSIFrame::new(address, command, data).map(|frame| frame.into_raw().to_vec());
+        Self {
+            address,
+            command,
+            request: request.unwrap_or_default(),
+            response: Response::Silent,
+            chunk: None,
+        }
+    }
+
+    /// Answers with the data and the state 0
+    ///
+    /// # Panics
+    /// If the data is longer than [MAX_PAYLOAD]
+    pub fn respond(self, data: &[u8]) -> Self {
+        self.respond_with_state(0, data)
+    }
+
+    /// Answers with the state byte, an error state like 0x03 with no data to test how the
+    /// driver reports it
+    ///
+    /// # Panics
+    /// If the data is longer than [MAX_PAYLOAD]
+    pub fn respond_with_state(mut self, state: u8, data: &[u8]) -> Self {
+        assert!(data.len() <= MAX_PAYLOAD, "a frame holds at most {} bytes", MAX_PAYLOAD);
+        self.response = Response::Frame {
+            state,
+            data: data.to_vec(),
+            corrupt_checksum: false,
+        };
+        self
+    }
+
+    /// Sends the response with a checksum that doesn't match it
+    pub fn corrupt_checksum(mut self) -> Self {
+        if let Response::Frame { corrupt_checksum, .. } = &mut self.response {
+            *corrupt_checksum = true;
+        }
+        self
+    }
+
+    /// Answers with exactly these bytes, for responses no device sends
+    pub fn respond_raw(mut self, bytes: &[u8]) -> Self {
+        self.response = Response::Raw(bytes.to_vec());
+        self
+    }
+
+    /// Hands out the response in reads of at most `size` bytes, so the driver has to read
+    /// several times for one frame
+    ///
+    /// # Panics
+    /// If the size is zero
+    pub fn in_chunks(mut self, size: usize) -> Self {
+        assert!(size > 0, "a read returns at least one byte");
+        self.chunk = Some(size);
+        self
+    }
+
+    /// The frame the driver is expected to send, as it is sent
+    pub fn request(&self) -> &[u8] {
+        &self.request
+    }
+
+    /// The bytes sent in response, byte stuffed and with both delimiters
+    pub fn response(&self) -> Vec<u8> {
+        match &self.response {
+            Response::Silent => Vec::new(),
+            Response::Raw(bytes) => bytes.clone(),
+            Response::Frame { state, data, corrupt_checksum } => {
+                // the data was checked when the response was set
+                let frame = MISOFrame::from_parts(self.address, self.command, *state, data);
+                let Ok(mut frame) = frame else {
+                    return Vec::new();
+                };
+                if *corrupt_checksum {
+                    let checksum = frame.get_checksum().wrapping_add(1);
+                    frame = frame.with_checksum(checksum);
+                }
+                frame.to_raw().to_vec()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    expected: VecDeque<Expectation>,
+    /// the bytes of a frame that isn't complete yet
+    written: Vec<u8>,
+    sent: Vec<Vec<u8>>,
+    /// the reads the responses are handed out in
+    pending: VecDeque<Vec<u8>>,
+    reads: usize,
+    mismatch: Option<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A serial port that answers the frames queued on its [MockHandle], see the
+/// [module documentation](self). A frame that differs from the next one expected, or comes when
+/// none is, fails the write with [ErrorKind::InvalidData] and is kept for
+/// [MockHandle::mismatch]. Reads without a response pending time out straight away.
+#[derive(Clone, Debug)]
+pub struct MockPort {
+    state: Arc<Mutex<State>>,
+    timeout: Duration,
+    baud_rate: u32,
+}
+
+impl MockPort {
+    /// A port at 115200 baud that expects nothing yet
+    pub fn new() -> Self {
+        Self {
+            state: Arc::default(),
+            timeout: Duration::ZERO,
+            baud_rate: 115200,
+        }
+    }
+
+    /// Returns a handle to queue expectations and check what was sent, which stays usable after
+    /// the port was handed to a driver
+    pub fn handle(&self) -> MockHandle {
+        MockHandle {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl Default for MockPort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queues the expectations of a [MockPort] and checks what reached it, returned by
+/// [MockPort::handle]
+#[derive(Clone, Debug)]
+pub struct MockHandle {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockHandle {
+    /// Queues a frame the driver is expected to send next, after the ones queued before
+    pub fn expect(&self, expectation: Expectation) {
+        lock(&self.state).expected.push_back(expectation);
+    }
+
+    /// Returns the number of expected frames that were not sent yet
+    pub fn remaining(&self) -> usize {
+        lock(&self.state).expected.len()
+    }
+
+    /// Returns every complete frame written to the port, expected or not
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        lock(&self.state).sent.clone()
+    }
+
+    /// Returns how many reads returned data, more than one per frame for responses
+    /// [in chunks](Expectation::in_chunks)
+    pub fn reads(&self) -> usize {
+        lock(&self.state).reads
+    }
+
+    /// Returns the first frame that was sent but not expected, together with the frame
+    /// expected in its place if there was one
+    pub fn mismatch(&self) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+        lock(&self.state).mismatch.clone()
+    }
+
+    /// Checks that every expected frame was sent and nothing else was
+    ///
+    /// # Panics
+    /// If a frame was sent that wasn't expected or an expected one wasn't sent
+    // failing the test is what this is for
+    #[allow(clippy::panic)]
+    pub fn assert_done(&self) {
+        let state = lock(&self.state);
+        match &state.mismatch {
+            Some((sent, Some(expected))) => {
+                panic!("sent {:02x?} instead of {:02x?}", sent, expected)
+            }
+            Some((sent, None)) => panic!("sent {:02x?} when nothing was expected", sent),
+            None => {}
+        }
+        let missing: Vec<&[u8]> = state.expected.iter().map(Expectation::request).collect();
+        assert!(missing.is_empty(), "expected frames that were never sent: {:02x?}", missing);
+    }
+}
+
+impl Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = lock(&self.state);
+        let Some(mut chunk) = state.pending.pop_front() else {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "no response pending"));
+        };
+        let count = buf.len().min(chunk.len());
+        let rest = chunk.split_off(count);
+        for (slot, byte) in buf.iter_mut().zip(chunk) {
+            *slot = byte;
+        }
+        if !rest.is_empty() {
+            state.pending.push_front(rest);
+        }
+        state.reads += 1;
+        Ok(count)
+    }
+}
+
+impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = lock(&self.state);
+        state.written.extend_from_slice(buf);
+        // a frame is complete with its closing delimiter
+        if state.written.len() < 2 || state.written.last() != Some(&START_STOP) {
+            return Ok(buf.len());
+        }
+
+        let frame = std::mem::take(&mut state.written);
+        state.sent.push(frame.clone());
+        match state.expected.front() {
+            Some(expectation) if expectation.request == frame => {
+                let response = expectation.response();
+                let size = expectation.chunk.unwrap_or(response.len()).max(1);
+                state.pending.extend(response.chunks(size).map(<[u8]>::to_vec));
+                state.expected.pop_front();
+                Ok(buf.len())
+            }
+            expected => {
+                let expected = expected.map(|expectation| expectation.request.clone());
+                state.mismatch.get_or_insert((frame, expected));
+                Err(std::io::Error::new(ErrorKind::InvalidData, "frame was not expected"))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for MockPort {
+    fn name(&self) -> Option<String> {
+        Some("mock".to_string())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        let state = lock(&self.state);
+        Ok(state.pending.front().map_or(0, |chunk| chunk.len() as u32))
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        if matches!(buffer_to_clear, ClearBuffer::Input | ClearBuffer::All) {
+            lock(&self.state).pending.clear();
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use crate::error::{DeviceError, StateResponseError};
+    use crate::shdlc::MOSIFrame;
+
+    fn connection() -> (Connection<MockPort>, MockHandle) {
+        let port = MockPort::new();
+        let handle = port.handle();
+        (Connection::new(port), handle)
+    }
+
+    #[test]
+    fn frames_are_matched_as_they_are_sent() {
+        let expectation = Expectation::new(0, 0x00, &[0x01, 0x7E, 0x7D]);
+        let frame = MOSIFrame::new(0, 0x00, &[0x01, 0x7E, 0x7D]).unwrap();
+        assert_eq!(expectation.request(), frame.into_raw().as_slice());
+        let response = Expectation::new(0, 0x00, &[]).respond(&[0x11, 0x13, 0x7E]).response();
+        let parsed = crate::shdlc::MISOFrame::from_bytes(&response).unwrap();
+        assert!(parsed.validate_checksum());
+        assert_eq!(parsed.into_data().as_slice(), &[0x11, 0x13, 0x7E]);
+    }
+
+    #[test]
+    fn stuffed_frames_round_trip() {
+        let (mut connection, handle) = connection();
+        handle.expect(Expectation::new(2, 0x00, &[0x01, 0x7E, 0x11]).respond(&[0x7D, 0x13]));
+        let frame = MOSIFrame::new(2, 0x00, &[0x01, 0x7E, 0x11]).unwrap();
+        let response = connection.transact(frame).unwrap();
+        assert_eq!(response.into_data().as_slice(), &[0x7D, 0x13]);
+        assert_eq!(handle.sent().len(), 1);
+        handle.assert_done();
+    }
+
+    #[test]
+    fn error_states_reach_the_caller() {
+        let (mut connection, handle) = connection();
+        handle.expect(Expectation::new(0, 0x91, &[0, 0, 0xE1, 0]).respond_with_state(0x04, &[]));
+        let result = connection.transact(MOSIFrame::new(0, 0x91, &[0, 0, 0xE1, 0]).unwrap());
+        let parameter = StateResponseError::ParameterError;
+        assert!(matches!(result, Err(DeviceError::StateResponse(e)) if e == parameter));
+        handle.assert_done();
+    }
+
+    #[test]
+    fn a_corrupted_checksum_fails() {
+        let (mut connection, handle) = connection();
+        let expectation = Expectation::new(0, 0x91, &[]).respond(&[0, 1, 0xC2, 0]);
+        handle.expect(expectation.corrupt_checksum());
+        let result = connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap());
+        assert!(matches!(result, Err(DeviceError::InvalidChecksum(..))), "{:?}", result);
+    }
+
+    #[test]
+    fn responses_in_chunks_take_several_reads() {
+        let (mut connection, handle) = connection();
+        handle.expect(Expectation::new(0, 0x91, &[]).respond(&[0, 1, 0xC2, 0]).in_chunks(3));
+        let response = connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap()).unwrap();
+        assert_eq!(response.into_data().as_slice(), &[0, 1, 0xC2, 0]);
+        // 11 bytes in reads of 3
+        assert_eq!(handle.reads(), 4);
+    }
+
+    #[test]
+    fn silence_times_out() {
+        let (mut connection, handle) = connection();
+        handle.expect(Expectation::new(0, 0x91, &[]));
+        let result = connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap());
+        assert!(matches!(result, Err(DeviceError::Timeout)), "{:?}", result);
+        handle.assert_done();
+    }
+
+    #[test]
+    fn an_unexpected_frame_fails_the_write() {
+        let (mut connection, handle) = connection();
+        handle.expect(Expectation::new(0, 0x91, &[]).respond(&[0, 1, 0xC2, 0]));
+        let result = connection.transact(MOSIFrame::new(0, 0xD1, &[]).unwrap());
+        assert!(matches!(result, Err(DeviceError::IoError(_))));
+        let (sent, expected) = handle.mismatch().unwrap();
+        assert_eq!(sent, MOSIFrame::new(0, 0xD1, &[]).unwrap().into_raw().as_slice());
+        assert_eq!(expected.unwrap(), Expectation::new(0, 0x91, &[]).request());
+        assert_eq!(handle.remaining(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "instead of")]
+    fn assert_done_reports_the_mismatch() {
+        let (mut connection, handle) = connection();
+        handle.expect(Expectation::new(0, 0x91, &[]));
+        let _ = connection.transact(MOSIFrame::new(0, 0xD1, &[]).unwrap());
+        handle.assert_done();
+    }
+
+    #[test]
+    #[should_panic(expected = "never sent")]
+    fn assert_done_reports_missing_frames() {
+        let (_, handle) = connection();
+        handle.expect(Expectation::new(0, 0x91, &[]));
+        handle.assert_done();
+    }
+}
@@ -0,0 +1,189 @@
+//! The names of the commands of both device families, as their SHDLC guides list them. The
+//! transcript decoder, the frames and the logs of the connections name commands with
+//! [command_name] instead of printing their number:
+//! ```
+//! use sfc_core::names::{DeviceFamily, command_name};
+//!
+//! assert_eq!(command_name(DeviceFamily::Sfc6xxx, 0xD1, None), Some("version"));
+//! assert_eq!(command_name(DeviceFamily::Sfc5xxx, 0x21, Some(0x0A)), Some("converted full scale"));
+//! // a subcommand the guide doesn't list is named after the whole command
+//! assert_eq!(command_name(DeviceFamily::Sfc6xxx, 0x30, Some(0x7F)), Some("raw measurement"));
+//! assert_eq!(command_name(DeviceFamily::Sfc6xxx, 0x7F, None), None);
+//! ```
+
+/// The device families whose commands this crate knows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceFamily {
+    #[default]
+    Sfc6xxx,
+    Sfc5xxx,
+}
+
+/// The name of a command of the family, or of the subcommand of it that the first byte of the
+/// request data selects. A subcommand the family doesn't have, and the data of a command without
+/// subcommands, get the name of the whole command. [None] if the family has no such command.
+pub fn command_name(
+    family: DeviceFamily,
+    command: u8,
+    subcommand: Option<u8>,
+) -> Option<&'static str> {
+    command_entry(family, command, subcommand).map(|&(_, _, name)| name)
+}
+
+/// The entry of the table that names a command like [command_name] does. Its command and
+/// subcommand are the key other tables of commands, like the layouts of the transcript, are
+/// looked up with.
+pub(crate) fn command_entry(
+    family: DeviceFamily,
+    command: u8,
+    subcommand: Option<u8>,
+) -> Option<&'static (u8, Option<u8>, &'static str)> {
+    let named = |subcommand: Option<u8>| {
+        table(family)
+            .iter()
+            .find(|&&(c, s, _)| (c, s) == (command, subcommand))
+    };
+    subcommand.and_then(|subcommand| named(Some(subcommand))).or_else(|| named(None))
+}
+
+/// The commands and subcommands of the family with their names
+pub(crate) fn table(family: DeviceFamily) -> Table {
+    match family {
+        DeviceFamily::Sfc6xxx => SFC6XXX,
+        DeviceFamily::Sfc5xxx => SFC5XXX,
+    }
+}
+
+/// The commands and subcommands of a family with their names. Every command has an entry
+/// without a subcommand, the name of the whole command.
+pub(crate) type Table = &'static [(u8, Option<u8>, &'static str)];
+
+const SFC6XXX: Table = &[
+    (0x00, Some(0x01), "setpoint"),
+    (0x00, None, "setpoint"),
+    (0x03, Some(0x01), "set setpoint and read measured value"),
+    (0x03, None, "set setpoint and read measured value"),
+    (0x08, Some(0x01), "read measured value"),
+    (0x08, Some(0x11), "read average measured value"),
+    (0x08, None, "read measured value"),
+    (0x22, Some(0x00), "controller gain"),
+    (0x22, Some(0x03), "initial step"),
+    (0x22, None, "controller configuration"),
+    (0x30, Some(0x00), "raw flow"),
+    (0x30, Some(0x02), "raw thermal conductivity"),
+    (0x30, Some(0x10), "temperature"),
+    (0x30, None, "raw measurement"),
+    (0x40, Some(0x00), "number of calibrations"),
+    (0x40, Some(0x10), "calibration validity"),
+    (0x40, Some(0x12), "calibration gas id"),
+    (0x40, Some(0x13), "calibration gas unit"),
+    (0x40, Some(0x14), "calibration full scale"),
+    (0x40, None, "calibration information"),
+    (0x44, Some(0x12), "current gas id"),
+    (0x44, Some(0x13), "current gas unit"),
+    (0x44, Some(0x14), "current full scale"),
+    (0x44, None, "current calibration information"),
+    (0x45, None, "calibration"),
+    (0x46, None, "volatile calibration"),
+    (0x90, None, "slave address"),
+    (0x91, None, "baudrate"),
+    (0xD0, Some(0x00), "product type"),
+    (0xD0, Some(0x01), "product name"),
+    (0xD0, Some(0x02), "article code"),
+    (0xD0, Some(0x03), "serial number"),
+    (0xD0, None, "device information"),
+    (0xD1, None, "version"),
+    (0xD3, None, "device reset"),
+];
+
+const SFC5XXX: Table = &[
+    (0x00, Some(0x00), "setpoint, normalized"),
+    (0x00, Some(0x01), "setpoint"),
+    (0x00, None, "setpoint"),
+    (0x02, Some(0x00), "setpoint persistence"),
+    (0x02, None, "setpoint persistence"),
+    (0x03, Some(0x00), "set setpoint and read measured value, normalized"),
+    (0x03, Some(0x01), "set setpoint and read measured value"),
+    (0x03, None, "set setpoint and read measured value"),
+    (0x04, None, "set setpoint and read measured value of both sensors"),
+    (0x08, Some(0x00), "read measured value, normalized"),
+    (0x08, Some(0x01), "read measured value"),
+    (0x08, None, "read measured value"),
+    (0x09, None, "read measured value buffer"),
+    (0x0A, None, "read measured value of both sensors"),
+    (0x20, Some(0x00), "valve input source"),
+    (0x20, Some(0x01), "user defined valve value"),
+    (0x20, None, "valve input source"),
+    (0x21, Some(0x00), "medium unit"),
+    (0x21, Some(0x01), "medium unit with wild cards"),
+    (0x21, Some(0x0A), "converted full scale"),
+    (0x21, None, "medium unit configuration"),
+    (0x22, Some(0x00), "user controller gain"),
+    (0x22, Some(0x10), "pressure dependent gain"),
+    (0x22, Some(0x11), "inlet pressure for gain correction"),
+    (0x22, Some(0x20), "gas temperature compensation"),
+    (0x22, Some(0x21), "inlet temperature for compensation"),
+    (0x22, None, "controller configuration"),
+    (0x30, Some(0x00), "raw flow"),
+    (0x30, Some(0x01), "raw thermal conductivity, valve closed"),
+    (0x30, Some(0x02), "raw thermal conductivity"),
+    (0x30, Some(0x10), "temperature"),
+    (0x30, None, "raw measurement"),
+    (0x40, Some(0x00), "number of calibrations"),
+    (0x40, Some(0x10), "calibration validity"),
+    (0x40, Some(0x11), "calibration gas description"),
+    (0x40, Some(0x12), "calibration gas id"),
+    (0x40, Some(0x13), "calibration gas unit"),
+    (0x40, Some(0x14), "calibration full scale"),
+    (0x40, Some(0x15), "calibration initial conditions"),
+    (0x40, Some(0x16), "calibration recalibration conditions"),
+    (0x40, Some(0x17), "calibration thermal conductivity reference"),
+    (0x40, None, "calibration information"),
+    (0x44, Some(0x11), "current gas description"),
+    (0x44, Some(0x12), "current gas id"),
+    (0x44, Some(0x13), "current gas unit"),
+    (0x44, Some(0x14), "current full scale"),
+    (0x44, Some(0x15), "current initial conditions"),
+    (0x44, Some(0x16), "current recalibration conditions"),
+    (0x44, Some(0x17), "current thermal conductivity reference"),
+    (0x44, None, "current calibration information"),
+    (0x45, None, "calibration"),
+    (0x6E, None, "user memory"),
+    (0x90, None, "slave address"),
+    (0x91, None, "baudrate"),
+    (0x92, None, "factory reset"),
+    (0xD0, Some(0x01), "product name"),
+    (0xD0, Some(0x02), "article code"),
+    (0xD0, Some(0x03), "serial number"),
+    (0xD0, None, "device information"),
+    (0xD1, None, "version"),
+    (0xD2, None, "device error state"),
+    (0xD3, None, "device reset"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_command_is_listed_once_and_as_a_whole() {
+        for table in [SFC6XXX, SFC5XXX] {
+            for (i, &(command, subcommand, _)) in table.iter().enumerate() {
+                let later = table.get(i + 1..).unwrap_or_default();
+                assert!(!later.iter().any(|&(c, s, _)| (c, s) == (command, subcommand)));
+                assert!(table.iter().any(|&(c, s, _)| (c, s) == (command, None)));
+            }
+        }
+    }
+
+    #[test]
+    fn subcommands_fall_back_to_the_whole_command() {
+        let name = |command, subcommand| command_name(DeviceFamily::Sfc5xxx, command, subcommand);
+        assert_eq!(name(0x00, Some(0x00)), Some("setpoint, normalized"));
+        assert_eq!(name(0x00, Some(0x02)), Some("setpoint"));
+        assert_eq!(name(0x90, Some(0x05)), Some("slave address"));
+        assert_eq!(name(0x90, None), Some("slave address"));
+        assert_eq!(name(0xFF, Some(0x00)), None);
+    }
+}
@@ -0,0 +1,284 @@
+//! Setpoint profiles for test benches, available with `std`. A [Profile] is a list of segments
+//! that hold a setpoint or ramp it linearly to a target, run by
+//! [FlowController::run_profile] which measures the flow throughout:
+//! ```no_run
+//! # fn run<C: sfc_core::flow_controller::FlowController>(device: &mut C)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//! use sfc_core::profile::Profile;
+//!
+//! let profile = Profile::new()
+//!     .hold(0.5, Duration::from_secs(30))
+//!     .hold(2.0, Duration::from_secs(60))
+//!     .ramp(0.0, Duration::from_secs(10));
+//! let result = device.run_profile(&profile, Duration::from_millis(500))?;
+//! if let Some(abort) = &result.aborted {
+//!     eprintln!("stopped in segment {}: {}", abort.segment, abort.error);
+//! }
+//! for (segment, record) in result.segments.iter().enumerate() {
+//!     println!("segment {}: {} samples", segment, record.measurements.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! If a command fails while the profile runs, the runner sets the setpoint to zero, stops and
+//! returns what it measured so far with the error in [ProfileResult::aborted]. It doesn't retry
+//! beyond the retries of the connection.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::DeviceError;
+use crate::flow_controller::FlowController;
+use crate::gasunit::GasUnit;
+use crate::measurement::{Measurement, ValueScale};
+
+/// How a segment moves the setpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// Sets the target at the start of the segment
+    Hold,
+    /// Moves the setpoint in a straight line from where the previous segment left it, reaching
+    /// the target at the end of the segment
+    Ramp,
+}
+
+/// One step of a [Profile]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    /// The setpoint in the unit of the controller
+    pub target: f32,
+    pub duration: Duration,
+}
+
+/// Segments run one after another by [FlowController::run_profile]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub segments: Vec<Segment>,
+}
+
+impl Profile {
+    /// An empty profile
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a segment holding `target` for `duration`
+    pub fn hold(mut self, target: f32, duration: Duration) -> Self {
+        self.segments.push(Segment {
+            kind: SegmentKind::Hold,
+            target,
+            duration,
+        });
+        self
+    }
+
+    /// Adds a segment ramping to `target` over `duration`
+    pub fn ramp(mut self, target: f32, duration: Duration) -> Self {
+        self.segments.push(Segment {
+            kind: SegmentKind::Ramp,
+            target,
+            duration,
+        });
+        self
+    }
+
+    /// The sum of the segment durations
+    pub fn duration(&self) -> Duration {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+}
+
+/// What was measured during one segment
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentRecord {
+    pub segment: Segment,
+    /// The setpoint of every measurement is the one the runner had set when it was read
+    pub measurements: Vec<Measurement>,
+}
+
+/// How closely the runner kept to its schedule
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimingStats {
+    /// How many samples were taken
+    pub samples: usize,
+    /// How late a sample was read on average, after the time it was scheduled for
+    pub mean_lateness: Duration,
+    pub max_lateness: Duration,
+    /// The time from the start of the first segment to the end of the last one that ran
+    pub elapsed: Duration,
+}
+
+/// Why a profile stopped before its last segment ended
+#[derive(Debug)]
+pub struct ProfileAbort {
+    /// The index of the segment that was running
+    pub segment: usize,
+    pub error: DeviceError,
+    /// Why setting the setpoint to zero afterwards failed, `None` if it is at zero
+    pub stop_error: Option<DeviceError>,
+}
+
+/// The outcome of [FlowController::run_profile]
+#[derive(Debug)]
+pub struct ProfileResult {
+    /// One record for every segment that was started, the last one is partial if the profile
+    /// was aborted
+    pub segments: Vec<SegmentRecord>,
+    pub timing: TimingStats,
+    /// Set if a command failed and the profile was stopped
+    pub aborted: Option<ProfileAbort>,
+}
+
+impl ProfileResult {
+    /// Returns true if every segment ran to its end
+    pub fn is_complete(&self) -> bool {
+        self.aborted.is_none()
+    }
+}
+
+/// Adds up the lateness of the samples
+#[derive(Default)]
+struct Timing {
+    samples: usize,
+    total_lateness: Duration,
+    max_lateness: Duration,
+}
+
+impl Timing {
+    fn record(&mut self, scheduled: Instant, read: Instant) {
+        let lateness = read.saturating_duration_since(scheduled);
+        self.samples += 1;
+        self.total_lateness += lateness;
+        self.max_lateness = self.max_lateness.max(lateness);
+    }
+
+    fn stats(&self, elapsed: Duration) -> TimingStats {
+        TimingStats {
+            samples: self.samples,
+            mean_lateness: self
+                .total_lateness
+                .checked_div(self.samples as u32)
+                .unwrap_or_default(),
+            max_lateness: self.max_lateness,
+            elapsed,
+        }
+    }
+}
+
+fn sleep_until(time: Instant) {
+    let now = Instant::now();
+    if time > now {
+        thread::sleep(time - now);
+    }
+}
+
+/// Runs a profile, see [FlowController::run_profile]
+pub(crate) fn run<C: FlowController + ?Sized>(
+    controller: &mut C,
+    profile: &Profile,
+    sample_interval: Duration,
+) -> Result<ProfileResult, DeviceError> {
+    assert!(!sample_interval.is_zero(), "the sample interval must not be zero");
+    if controller.is_read_only() {
+        return Err(DeviceError::ReadOnly("run profile"));
+    }
+    let unit = controller.get_gas_unit()?;
+    let serial_number = controller.get_serial_number()?.to_string();
+    let mut setpoint = controller.get_setpoint()?;
+
+    let mut segments = Vec::with_capacity(profile.segments.len());
+    let mut timing = Timing::default();
+    let start = Instant::now();
+    let mut segment_start = start;
+    for (index, segment) in profile.segments.iter().enumerate() {
+        let mut record = Vec::new();
+        let sampled = run_segment(
+            controller,
+            segment,
+            segment_start,
+            sample_interval,
+            &mut setpoint,
+            |scheduled, read, value, setpoint| {
+                timing.record(scheduled, read);
+                record.push(measurement(value, unit, &serial_number, setpoint));
+            },
+        );
+        segments.push(SegmentRecord {
+            segment: *segment,
+            measurements: record,
+        });
+        if let Err(error) = sampled {
+            let stop_error = controller.set_setpoint(0.0).err();
+            return Ok(ProfileResult {
+                segments,
+                timing: timing.stats(start.elapsed()),
+                aborted: Some(ProfileAbort {
+                    segment: index,
+                    error,
+                    stop_error,
+                }),
+            });
+        }
+        // the next segment starts on schedule even if this one ended late
+        segment_start += segment.duration;
+        sleep_until(segment_start);
+    }
+    Ok(ProfileResult {
+        segments,
+        timing: timing.stats(start.elapsed()),
+        aborted: None,
+    })
+}
+
+/// Sets the setpoints of one segment and samples it every interval from its start, leaving
+/// `setpoint` at the last one that was set
+fn run_segment<C, F>(
+    controller: &mut C,
+    segment: &Segment,
+    start: Instant,
+    interval: Duration,
+    setpoint: &mut f32,
+    mut sample: F,
+) -> Result<(), DeviceError>
+where
+    C: FlowController + ?Sized,
+    F: FnMut(Instant, Instant, f32, f32),
+{
+    let from = *setpoint;
+    if segment.kind == SegmentKind::Hold {
+        controller.set_setpoint(segment.target)?;
+        *setpoint = segment.target;
+    }
+    let mut offset = Duration::ZERO;
+    while offset < segment.duration {
+        let scheduled = start + offset;
+        sleep_until(scheduled);
+        if segment.kind == SegmentKind::Ramp {
+            let progress = offset.as_secs_f32() / segment.duration.as_secs_f32();
+            let ramped = from + (segment.target - from) * progress;
+            controller.set_setpoint(ramped)?;
+            *setpoint = ramped;
+        }
+        let read = Instant::now();
+        let value = controller.read_measured_value()?;
+        sample(scheduled, read, value, *setpoint);
+        offset += interval;
+    }
+    if segment.kind == SegmentKind::Ramp {
+        sleep_until(start + segment.duration);
+        controller.set_setpoint(segment.target)?;
+        *setpoint = segment.target;
+    }
+    Ok(())
+}
+
+fn measurement(value: f32, unit: GasUnit, serial_number: &str, setpoint: f32) -> Measurement {
+    Measurement {
+        serial_number: Some(serial_number.to_string()),
+        setpoint: Some(setpoint),
+        ..Measurement::new(value, unit, ValueScale::Physical)
+    }
+}
@@ -0,0 +1,509 @@
+//! Replaying exchanges recorded from a device. A [Capture] holds the frames one driver call sent
+//! and received, [ReplayTransport] plays the received frames back and checks that the driver
+//! sends exactly the recorded ones. The device crates use this to test their decoders against
+//! captures of real firmware.
+//!
+//! ## Capture files
+//! ```text
+//! # comments and empty lines are skipped
+//! source: SFC6000D, firmware 1.4
+//! address: 0
+//! args: 2
+//! tx: 7e 00 40 05 14 00 00 00 02 a4 7e
+//! rx: 7e 00 40 00 04 40 a0 00 00 db 7e
+//! expect: Ok(5.0)
+//! ```
+//! - `source`: where the capture comes from, the product and its firmware or `emulator`
+//! - `address` (optional, 0 by default): the slave address of the device
+//! - `args` (optional): the arguments of the call separated by spaces
+//! - `tx` and `rx`: a frame sent and the response received, as space separated hex bytes
+//!   including the delimiters and byte stuffing, the way the `log` feature prints them at the
+//!   trace level. One pair for every command the call sends, in order.
+//! - `expect`: what the call returned, `Ok(..)` with the debug formatting of the value or
+//!   `Err(..)` with the [code](DeviceError::code) of the error in hex. [Capture::expected] reads it
+//!   back into an [Expected] so the harness compares values rather than text.
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::io::{ErrorKind, Read, Write};
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+use std::time::Duration;
+
+use crate::error::DeviceError;
+use crate::shdlc::START_STOP;
+use crate::transport::Transport;
+
+/// One driver call as recorded from a device
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capture {
+    pub source: String,
+    pub address: u8,
+    pub args: Vec<String>,
+    /// every frame sent together with the response to it
+    pub exchanges: Vec<(Vec<u8>, Vec<u8>)>,
+    pub expect: String,
+}
+
+/// Why a capture file could not be read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureError {
+    /// the line the error is on, starting at 1, or 0 if the whole file is at fault
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.reason)
+        } else {
+            write!(f, "line {}: {}", self.line, self.reason)
+        }
+    }
+}
+
+impl Capture {
+    /// Reads a capture in the format described in the [module documentation](self)
+    pub fn parse(text: &str) -> Result<Self, CaptureError> {
+        let mut capture = Capture::default();
+        let mut source = false;
+        let mut expect = false;
+        let mut sent: Option<Vec<u8>> = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |reason| CaptureError { line: i + 1, reason };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(':').ok_or(error("expected `key: value`"))?;
+            let value = value.trim();
+            match key.trim() {
+                "source" => {
+                    capture.source = value.to_string();
+                    source = true;
+                }
+                "address" => capture.address = value.parse().map_err(|_| error("invalid address"))?,
+                "args" => capture.args = value.split_whitespace().map(str::to_string).collect(),
+                "tx" if sent.is_some() => return Err(error("`tx` without the `rx` of the one before")),
+                "tx" => sent = Some(parse_hex(value).ok_or(error("invalid hex bytes"))?),
+                "rx" => {
+                    let tx = sent.take().ok_or(error("`rx` without a `tx` before it"))?;
+                    let rx = parse_hex(value).ok_or(error("invalid hex bytes"))?;
+                    capture.exchanges.push((tx, rx));
+                }
+                "expect" => {
+                    capture.expect = value.to_string();
+                    expect = true;
+                }
+                _ => return Err(error("unknown key")),
+            }
+        }
+
+        let error = |reason| CaptureError { line: 0, reason };
+        if sent.is_some() {
+            return Err(error("the last `tx` has no `rx`"));
+        }
+        if !source {
+            return Err(error("missing `source`"));
+        }
+        if !expect {
+            return Err(error("missing `expect`"));
+        }
+        Ok(capture)
+    }
+
+    /// Reads the `expect` back into what the call returned
+    pub fn expected(&self) -> Result<Expected, CaptureError> {
+        let error = |reason| CaptureError { line: 0, reason };
+        match Value::parse(&self.expect)? {
+            Value::Tuple(Some(name), values) if name == "Ok" => match values.as_slice() {
+                [value] => Ok(Expected::Ok(value.clone())),
+                _ => Err(error("`Ok` holds more than one value")),
+            },
+            Value::Tuple(Some(name), values) if name == "Err" => match values.as_slice() {
+                [Value::Number(code)] => {
+                    let code = match code.strip_prefix("0x") {
+                        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                        None => code.parse().ok(),
+                    };
+                    Ok(Expected::Err(code.ok_or(error("invalid error code"))?))
+                }
+                _ => Err(error("`Err` holds no error code")),
+            },
+            _ => Err(error("`expect` is neither `Ok(..)` nor `Err(..)`")),
+        }
+    }
+}
+
+/// What a recorded call returned, see [Capture::expected]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expected {
+    /// The value, for the harness to read into the type the call returns
+    Ok(Value),
+    /// The [code](DeviceError::code) of the error
+    Err(u16),
+}
+
+impl Expected {
+    /// The `expect` of a capture of a call that returned `result`
+    pub fn record<T: Debug>(result: &Result<T, DeviceError>) -> String {
+        match result {
+            Ok(value) => format!("Ok({:?})", value),
+            Err(error) => format!("Err({:#06x})", error.code()),
+        }
+    }
+}
+
+/// A value in the syntax its debug formatting has, read from the `expect` of a capture.
+/// Numbers are kept as written and read with [Value::number], into whatever type the call
+/// returns.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A number as written, like `-5.0`
+    Number(String),
+    /// A string, with its escapes resolved
+    Str(String),
+    /// A name on its own, like `true`, `None` or a unit variant
+    Ident(String),
+    /// A tuple, or a tuple struct or variant like `Some(1)` with its name
+    Tuple(Option<String>, Vec<Value>),
+    /// A struct or struct variant with its named fields, in order
+    Struct(String, Vec<(String, Value)>),
+    /// A list, like the debug formatting of a `Vec` or an array
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Reads a value, the whole text has to be one
+    pub fn parse(text: &str) -> Result<Self, CaptureError> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(_) => Err(CaptureError { line: 0, reason: "text after the value" }),
+        }
+    }
+
+    /// Reads a number, or a name like `inf` and `NaN` that parses as one
+    pub fn number<N: FromStr>(&self) -> Option<N> {
+        match self {
+            Value::Number(number) | Value::Ident(number) => number.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the field of a struct with that name
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Struct(_, fields) => {
+                fields.iter().find(|(field, _)| field == name).map(|(_, value)| value)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, CaptureError> {
+    let error = |reason| CaptureError { line: 0, reason };
+    skip_whitespace(chars);
+    match chars.peek().copied().ok_or(error("missing value"))? {
+        '"' => parse_string(chars).map(Value::Str),
+        '(' => {
+            chars.next();
+            Ok(Value::Tuple(None, parse_list(chars, ')')?))
+        }
+        '[' => {
+            chars.next();
+            Ok(Value::List(parse_list(chars, ']')?))
+        }
+        c if c.is_ascii_digit() || c == '-' => {
+            Ok(Value::Number(take_while(chars, |c| c.is_ascii_alphanumeric() || "+-.".contains(c))))
+        }
+        c if c.is_alphabetic() || c == '_' => {
+            let name = take_while(chars, |c| c.is_alphanumeric() || c == '_');
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'(').is_some() {
+                return Ok(Value::Tuple(Some(name), parse_list(chars, ')')?));
+            }
+            if chars.next_if_eq(&'{').is_none() {
+                return Ok(Value::Ident(name));
+            }
+            let mut fields = Vec::new();
+            loop {
+                skip_whitespace(chars);
+                if chars.next_if_eq(&'}').is_some() {
+                    return Ok(Value::Struct(name, fields));
+                }
+                let field = take_while(chars, |c| c.is_alphanumeric() || c == '_');
+                skip_whitespace(chars);
+                if field.is_empty() || chars.next() != Some(':') {
+                    return Err(error("expected `field: value`"));
+                }
+                fields.push((field, parse_value(chars)?));
+                if !list_continues(chars, '}')? {
+                    return Ok(Value::Struct(name, fields));
+                }
+            }
+        }
+        _ => Err(error("unexpected character")),
+    }
+}
+
+/// Reads the values up to the closing delimiter, after the opening one
+fn parse_list(chars: &mut Peekable<Chars>, close: char) -> Result<Vec<Value>, CaptureError> {
+    let mut values = Vec::new();
+    loop {
+        skip_whitespace(chars);
+        if chars.next_if_eq(&close).is_some() {
+            return Ok(values);
+        }
+        values.push(parse_value(chars)?);
+        if !list_continues(chars, close)? {
+            return Ok(values);
+        }
+    }
+}
+
+/// Reads the comma after a value, false if the list ended instead
+fn list_continues(chars: &mut Peekable<Chars>, close: char) -> Result<bool, CaptureError> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(',') => Ok(true),
+        Some(c) if c == close => Ok(false),
+        _ => Err(CaptureError { line: 0, reason: "expected `,` or the end of the list" }),
+    }
+}
+
+fn take_while(chars: &mut Peekable<Chars>, f: impl Fn(char) -> bool) -> String {
+    let mut taken = String::new();
+    while let Some(c) = chars.next_if(|&c| f(c)) {
+        taken.push(c);
+    }
+    taken
+}
+
+/// Reads a string with the escapes its debug formatting uses
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, CaptureError> {
+    let error = |reason| CaptureError { line: 0, reason };
+    chars.next();
+    let mut string = String::new();
+    loop {
+        match chars.next().ok_or(error("unterminated string"))? {
+            '"' => return Ok(string),
+            '\\' => string.push(match chars.next().ok_or(error("unterminated string"))? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'u' => {
+                    let hex = take_while(chars, |c| c != '}');
+                    chars.next();
+                    let code = u32::from_str_radix(hex.trim_start_matches('{'), 16).ok();
+                    code.and_then(char::from_u32).ok_or(error("invalid escape"))?
+                }
+                c => c,
+            }),
+            c => string.push(c),
+        }
+    }
+}
+
+fn parse_hex(value: &str) -> Option<Vec<u8>> {
+    value.split_whitespace().map(|byte| u8::from_str_radix(byte, 16).ok()).collect()
+}
+
+/// A transport that answers every frame with the recorded response. A frame that differs from
+/// the recorded one fails the write with [ErrorKind::InvalidData] and is kept for
+/// [ReplayTransport::mismatch], reads without a response pending time out straight away.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: VecDeque<(Vec<u8>, Vec<u8>)>,
+    written: Vec<u8>,
+    pending: VecDeque<u8>,
+    mismatch: Option<(Vec<u8>, Option<Vec<u8>>)>,
+    timeout: Duration,
+}
+
+impl ReplayTransport {
+    pub fn new(exchanges: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            exchanges: exchanges.into_iter().collect(),
+            written: Vec::new(),
+            pending: VecDeque::new(),
+            mismatch: None,
+            timeout: Duration::ZERO,
+        }
+    }
+
+    /// Returns the number of recorded exchanges that were not used yet
+    pub fn remaining(&self) -> usize {
+        self.exchanges.len()
+    }
+
+    /// Returns the first frame that was sent but not recorded, together with the recorded
+    /// frame expected in its place if there was one
+    pub fn mismatch(&self) -> Option<&(Vec<u8>, Option<Vec<u8>>)> {
+        self.mismatch.as_ref()
+    }
+}
+
+impl Read for ReplayTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "no recorded response pending"));
+        }
+        let count = buf.len().min(self.pending.len());
+        for (slot, byte) in buf.iter_mut().zip(self.pending.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for ReplayTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        // a frame is complete with its closing delimiter
+        if self.written.len() < 2 || self.written.last() != Some(&START_STOP) {
+            return Ok(buf.len());
+        }
+
+        let frame = std::mem::take(&mut self.written);
+        match self.exchanges.front() {
+            Some((tx, _)) if *tx == frame => {
+                let (_, rx) = self.exchanges.pop_front().unwrap_or_default();
+                self.pending.extend(rx);
+                Ok(buf.len())
+            }
+            expected => {
+                let expected = expected.map(|(tx, _)| tx.clone());
+                self.mismatch.get_or_insert((frame, expected));
+                Err(std::io::Error::new(ErrorKind::InvalidData, "frame differs from the capture"))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use crate::shdlc::MOSIFrame;
+
+    const CAPTURE: &str = "# the baudrate of a device
+source: emulator
+tx: 7E 00 91 00 6E 7E
+rx: 7e 00 91 00 04 00 01 c2 00 a7 7e
+expect: Ok(115200)
+";
+
+    #[test]
+    fn parse_a_capture() {
+        let capture = Capture::parse(CAPTURE).unwrap();
+        assert_eq!(capture.source, "emulator");
+        assert_eq!(capture.address, 0);
+        assert!(capture.args.is_empty());
+        assert_eq!(capture.exchanges.len(), 1);
+        assert_eq!(capture.exchanges[0].0, [0x7E, 0x00, 0x91, 0x00, 0x6E, 0x7E]);
+        assert_eq!(capture.expect, "Ok(115200)");
+    }
+
+    #[test]
+    fn the_expected_value_is_read_back() {
+        let capture = Capture::parse(CAPTURE).unwrap();
+        assert_eq!(capture.expected().unwrap(), Expected::Ok(Value::Number("115200".into())));
+
+        let unit = "Ok(GasUnit { unit_prefex: Base, timebase: Minute })";
+        let unit = Capture { expect: unit.into(), ..capture.clone() };
+        let Expected::Ok(value) = unit.expected().unwrap() else {
+            panic!("the capture expects a value");
+        };
+        assert_eq!(value.field("timebase"), Some(&Value::Ident("Minute".into())));
+        assert_eq!(value.field("medium_unit"), None);
+
+        let failed = Capture { expect: "Err(0x0304)".into(), ..capture.clone() };
+        assert_eq!(failed.expected().unwrap(), Expected::Err(0x0304));
+        let failed = Capture { expect: "Err(772)".into(), ..capture };
+        assert_eq!(failed.expected().unwrap(), Expected::Err(0x0304));
+        assert_eq!(Expected::record::<()>(&Err(DeviceError::InvalidString)), "Err(0x0202)");
+        assert_eq!(Expected::record(&Ok(5.0)), "Ok(5.0)");
+    }
+
+    #[test]
+    fn values_in_their_debug_formatting() {
+        let value = |text| Value::parse(text).unwrap();
+        assert_eq!(value("-2.5e-3").number::<f32>(), Some(-2.5e-3));
+        assert_eq!(value("NaN").number::<f32>().map(f32::is_nan), Some(true));
+        assert_eq!(value(r#""a \"b\"\n\u{e9}""#), Value::Str("a \"b\"\né".into()));
+        assert_eq!(
+            value("(1, [true, None], Some(\"x\"))"),
+            Value::Tuple(
+                None,
+                vec![
+                    Value::Number("1".into()),
+                    Value::List(vec![Value::Ident("true".into()), Value::Ident("None".into())]),
+                    Value::Tuple(Some("Some".into()), vec![Value::Str("x".into())]),
+                ]
+            )
+        );
+        assert_eq!(value("[]"), Value::List(Vec::new()));
+        assert_eq!(value("Empty {}"), Value::Struct("Empty".into(), Vec::new()));
+        assert!(Value::parse("(1, 2").is_err());
+        assert!(Value::parse("Ok(1) 2").is_err());
+        assert!(Value::parse("\"open").is_err());
+    }
+
+    #[test]
+    fn malformed_captures() {
+        let error = |text| Capture::parse(text).unwrap_err();
+        assert_eq!(error("source: a\ntx: 7e\ntx: 7e").line, 3);
+        assert_eq!(error("source: a\nrx: 7e").line, 2);
+        assert_eq!(error("source: a\ntx: 7x").reason, "invalid hex bytes");
+        assert_eq!(error("source: a\nexpect: Ok(())\ntx: 7e").reason, "the last `tx` has no `rx`");
+        assert_eq!(error("expect: Ok(())").reason, "missing `source`");
+        assert_eq!(error("source: a\nvalue: 1").reason, "unknown key");
+    }
+
+    #[test]
+    fn replays_the_response() {
+        let capture = Capture::parse(CAPTURE).unwrap();
+        let mut connection = Connection::new(ReplayTransport::new(capture.exchanges));
+        let response = connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap()).unwrap();
+        assert_eq!(response.into_data().as_slice(), &[0x00, 0x01, 0xC2, 0x00]);
+        assert_eq!(connection.with_transport(|t| t.remaining()), 0);
+    }
+
+    #[test]
+    fn unexpected_frame_is_reported() {
+        let capture = Capture::parse(CAPTURE).unwrap();
+        let mut connection = Connection::new(ReplayTransport::new(capture.exchanges));
+        let frame = MOSIFrame::new(0, 0xD1, &[]).unwrap();
+        let raw = MOSIFrame::new(0, 0xD1, &[]).unwrap().into_raw().to_vec();
+        assert!(matches!(connection.transact(frame), Err(DeviceError::IoError(_))));
+        let mismatch = connection.with_transport(|t| t.mismatch().cloned()).unwrap();
+        assert_eq!(mismatch.0, raw);
+        assert_eq!(mismatch.1.unwrap(), [0x7E, 0x00, 0x91, 0x00, 0x6E, 0x7E]);
+    }
+}
@@ -0,0 +1,302 @@
+//! Counters of how the commands of a connection went, to watch the health of a link in a long
+//! running installation without instrumenting every call. Every
+//! [Connection](crate::connection::Connection) and `AsyncConnection` keeps a [CommStats], the
+//! devices return it from their `stats` method.
+//!
+//! With the `metrics` feature the same events are also reported through the
+//! [metrics](https://crates.io/crates/metrics) facade, labelled with the `address` of the device:
+//! - `sfc_commands_total`, `sfc_retries_total` and `sfc_failed_commands_total`
+//! - `sfc_checksum_errors_total`, `sfc_timeouts_total` and `sfc_framing_errors_total`
+//! - `sfc_round_trip_seconds`, a histogram of the answered attempts
+//!
+//! The average round trip hides the odd command that takes far longer than the rest, the
+//! [LatencyHistogram] of [CommStats::latency_histogram] keeps them. Its [Display] is one line
+//! for a log:
+//! ```
+//! use core::time::Duration;
+//! use sfc_core::stats::LatencyHistogram;
+//!
+//! let mut histogram = LatencyHistogram::default();
+//! for millis in [3, 3, 4, 80] {
+//!     histogram.record(Duration::from_millis(millis));
+//! }
+//! assert_eq!(histogram.to_string(), "2-4ms: 2, 4-8ms: 1, 64-128ms: 1");
+//! assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(4)));
+//! assert_eq!(histogram.quantile(0.99), Some(Duration::from_millis(128)));
+//! ```
+
+use core::fmt::Display;
+use core::time::Duration;
+
+#[cfg(any(feature = "std", feature = "async"))]
+use crate::error::DeviceError;
+
+/// What happened on a connection since it was created or [CommStats::reset]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommStats {
+    /// Commands sent, a command that was retried counts once
+    pub commands: u64,
+    /// Attempts sent again after the one before failed
+    pub retries: u64,
+    /// Commands that failed, after every attempt if retried
+    pub failures: u64,
+    /// Responses with a wrong checksum
+    pub checksum_errors: u64,
+    /// Attempts the device did not answer in time
+    pub timeouts: u64,
+    /// Responses that were cut off or could not be decoded
+    pub framing_errors: u64,
+    /// The time from sending a request until its response arrived, as a moving average in
+    /// which the latest answered attempt weighs an eighth. [None] until one was answered, or
+    /// when the connection has no clock.
+    pub round_trip: Option<Duration>,
+    latency: LatencyHistogram,
+}
+
+impl CommStats {
+    /// Sets every counter back to zero and forgets the round trip time and histogram
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// The round trip times of the answered attempts, by how long they took. Emptied by
+    /// [CommStats::reset] with the counters.
+    pub fn latency_histogram(&self) -> &LatencyHistogram {
+        &self.latency
+    }
+
+    /// Counts a command once it finished, retries included
+    #[cfg(any(feature = "std", feature = "async"))]
+    pub(crate) fn record_command<T>(
+        &mut self,
+        address: u8,
+        result: &Result<T, DeviceError>,
+    ) {
+        self.commands += 1;
+        #[cfg(feature = "metrics")]
+        metrics::counter!("sfc_commands_total", "address" => address.to_string()).increment(1);
+        if result.is_err() {
+            self.failures += 1;
+            #[cfg(feature = "metrics")]
+            metrics::counter!("sfc_failed_commands_total", "address" => address.to_string())
+                .increment(1);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = address;
+    }
+
+    /// Counts a command being sent again
+    #[cfg(any(feature = "std", feature = "async"))]
+    pub(crate) fn record_retry(&mut self, address: u8) {
+        self.retries += 1;
+        #[cfg(feature = "metrics")]
+        metrics::counter!("sfc_retries_total", "address" => address.to_string()).increment(1);
+        #[cfg(not(feature = "metrics"))]
+        let _ = address;
+    }
+
+    /// Counts how one attempt went. The round trip is the time from sending the request until
+    /// its response was read, if it was answered and there is a clock.
+    #[cfg(any(feature = "std", feature = "async"))]
+    pub(crate) fn record_attempt<T>(
+        &mut self,
+        address: u8,
+        result: &Result<T, DeviceError>,
+        round_trip: Option<Duration>,
+    ) {
+        let counter = match result {
+            Ok(_) | Err(DeviceError::StateResponse(_)) => {
+                if let Some(sample) = round_trip {
+                    self.record_round_trip(address, sample);
+                }
+                return;
+            }
+            Err(DeviceError::InvalidChecksum(_, _)) => {
+                self.checksum_errors += 1;
+                "sfc_checksum_errors_total"
+            }
+            Err(DeviceError::Timeout) => {
+                self.timeouts += 1;
+                "sfc_timeouts_total"
+            }
+            Err(DeviceError::IncompleteFrame | DeviceError::ShdlcError(_)) => {
+                self.framing_errors += 1;
+                "sfc_framing_errors_total"
+            }
+            Err(_) => return,
+        };
+        #[cfg(feature = "metrics")]
+        metrics::counter!(counter, "address" => address.to_string()).increment(1);
+        #[cfg(not(feature = "metrics"))]
+        let _ = (address, counter);
+    }
+
+    #[cfg(any(feature = "std", feature = "async"))]
+    fn record_round_trip(&mut self, address: u8, sample: Duration) {
+        self.round_trip = Some(match self.round_trip {
+            Some(average) => (average * 7 + sample) / 8,
+            None => sample,
+        });
+        self.latency.record(sample);
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("sfc_round_trip_seconds", "address" => address.to_string())
+            .record(sample.as_secs_f64());
+        #[cfg(not(feature = "metrics"))]
+        let _ = address;
+    }
+}
+
+/// The number of buckets of a [LatencyHistogram]
+pub const LATENCY_BUCKETS: usize = 12;
+
+/// Round trip times counted in buckets of powers of two milliseconds: below 1ms, 1-2ms, 2-4ms
+/// and so on up to 512-1024ms, which covers every response timeout up to a second, and a last
+/// bucket for anything longer. A bucket includes its lower bound. Recording is a count of the
+/// leading zeros of the milliseconds and an increment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Counts a round trip in its bucket
+    pub fn record(&mut self, round_trip: Duration) {
+        let millis = round_trip.as_millis();
+        let bucket = (u128::BITS - millis.leading_zeros()) as usize;
+        if let Some(count) = self.counts.get_mut(bucket.min(LATENCY_BUCKETS - 1)) {
+            *count += 1;
+        }
+    }
+
+    /// The round trips counted in each bucket, shortest first
+    pub fn counts(&self) -> &[u64; LATENCY_BUCKETS] {
+        &self.counts
+    }
+
+    /// The round trips counted in every bucket together
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The shortest round trip that falls into the bucket
+    pub fn lower_bound(bucket: usize) -> Duration {
+        match bucket {
+            0 => Duration::ZERO,
+            _ => Duration::from_millis(1 << (bucket - 1)),
+        }
+    }
+
+    /// The round trip the bucket stops at, [None] for the last bucket which has no end
+    pub fn upper_bound(bucket: usize) -> Option<Duration> {
+        (bucket < LATENCY_BUCKETS - 1).then(|| Duration::from_millis(1 << bucket))
+    }
+
+    /// The upper bound of the bucket holding the quantile `q` between 0 and 1 of the round
+    /// trips, so at least that share of them were shorter. [None] without any round trips, and
+    /// [Duration::MAX] when the quantile falls into the last bucket.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        // rounded up without std, the cast truncates
+        let exact = q.clamp(0.0, 1.0) * total as f64;
+        let rank = (exact as u64 + u64::from(exact > (exact as u64) as f64)).max(1);
+        let mut counted = 0;
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            counted += count;
+            if counted >= rank {
+                return Some(Self::upper_bound(bucket).unwrap_or(Duration::MAX));
+            }
+        }
+        None
+    }
+}
+
+/// Lists the buckets that counted a round trip, like `<1ms: 12, 2-4ms: 3, >=1024ms: 1`, or
+/// `no round trips`
+impl Display for LatencyHistogram {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut first = true;
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            if !first {
+                f.write_str(", ")?;
+            }
+            first = false;
+            let lower = Self::lower_bound(bucket).as_millis();
+            match Self::upper_bound(bucket).map(|upper| upper.as_millis()) {
+                Some(upper) if bucket == 0 => write!(f, "<{}ms: {}", upper, count)?,
+                Some(upper) => write!(f, "{}-{}ms: {}", lower, upper, count)?,
+                None => write!(f, ">={}ms: {}", lower, count)?,
+            }
+        }
+        if first {
+            f.write_str("no round trips")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, any(feature = "std", feature = "async")))]
+mod tests {
+    use super::*;
+    use crate::error::StateResponseError;
+
+    #[test]
+    fn attempts_are_counted_by_their_error() {
+        let mut stats = CommStats::default();
+        stats.record_attempt(0, &Err::<(), _>(DeviceError::InvalidChecksum(1, 2)), None);
+        stats.record_attempt(0, &Err::<(), _>(DeviceError::Timeout), None);
+        stats.record_attempt(0, &Err::<(), _>(DeviceError::IncompleteFrame), None);
+        stats.record_attempt(
+            0,
+            &Err::<(), _>(DeviceError::StateResponse(StateResponseError::ParameterError)),
+            Some(Duration::from_millis(4)),
+        );
+        assert_eq!(stats.checksum_errors, 1);
+        assert_eq!(stats.timeouts, 1);
+        assert_eq!(stats.framing_errors, 1);
+        assert_eq!(stats.round_trip, Some(Duration::from_millis(4)));
+
+        stats.reset();
+        assert_eq!(stats, CommStats::default());
+    }
+
+    #[test]
+    fn round_trip_is_a_moving_average() {
+        let mut stats = CommStats::default();
+        stats.record_round_trip(0, Duration::from_millis(8));
+        stats.record_round_trip(0, Duration::from_millis(16));
+        assert_eq!(stats.round_trip, Some(Duration::from_millis(9)));
+        assert_eq!(stats.latency_histogram().total(), 2);
+    }
+
+    #[test]
+    fn round_trips_land_in_their_bucket() {
+        let mut histogram = LatencyHistogram::default();
+        for (millis, bucket) in [(0, 0), (1, 1), (3, 2), (4, 3), (80, 7), (1023, 10), (1024, 11)] {
+            let before = histogram.counts()[bucket];
+            histogram.record(Duration::from_millis(millis));
+            assert_eq!(histogram.counts()[bucket], before + 1, "{}ms", millis);
+            let round_trip = Duration::from_millis(millis);
+            assert!(LatencyHistogram::lower_bound(bucket) <= round_trip);
+            assert!(LatencyHistogram::upper_bound(bucket).is_none_or(|upper| round_trip < upper));
+        }
+        histogram.record(Duration::from_micros(999));
+        histogram.record(Duration::from_secs(60));
+        assert_eq!(histogram.counts()[0], 2);
+        assert_eq!(histogram.counts()[LATENCY_BUCKETS - 1], 2);
+        assert_eq!(histogram.total(), 9);
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::MAX));
+        assert_eq!(
+            histogram.to_string(),
+            "<1ms: 2, 1-2ms: 1, 2-4ms: 1, 4-8ms: 1, 64-128ms: 1, 512-1024ms: 1, >=1024ms: 2"
+        );
+        assert_eq!(LatencyHistogram::default().to_string(), "no round trips");
+        assert_eq!(LatencyHistogram::default().quantile(0.5), None);
+    }
+}
@@ -0,0 +1,443 @@
+//! A compact binary trace of the bytes exchanged with a device, for targets that can't afford
+//! text logging (requires `trace-postcard`). Every write and read is a [FrameRecord] holding the
+//! direction, a timestamp in ticks of the target's clock and the bytes, encoded with
+//! [postcard](https://crates.io/crates/postcard) and terminated by a COBS delimiter so a
+//! capture can be cut anywhere and still be read from the next record on.
+//!
+//! On the target a [TraceBuffer] keeps the latest records in a fixed ring without an allocator,
+//! [TraceBuffer::drain] moves them out over RTT or to flash. With `async` a
+//! [TracingTransport] records everything an `AsyncConnection` sends and receives.
+//!
+//! On the host [decode] (requires `std`) turns a captured trace back into the frames sent and
+//! received:
+//! ```ignore
+//! let trace = sfc_core::trace::decode(&std::fs::read("trace.bin")?);
+//! for entry in &trace.entries {
+//!     println!("{} {:?} {:?}", entry.timestamp_ticks, entry.direction, entry.frame);
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::shdlc::MAX_STUFFED_FRAME;
+
+/// Whether bytes went to the device or came from it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Written to the device, part of a request
+    Sent,
+    /// Read from the device, part of a response
+    Received,
+}
+
+/// One write to or read from the device. The bytes are a whole frame or a part of one, as
+/// the transport handed them over, still byte stuffed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameRecord<'a> {
+    pub direction: Direction,
+    /// When the bytes were written or read, in ticks of the clock that recorded them
+    pub timestamp_ticks: u64,
+    #[serde(borrow)]
+    pub bytes: &'a [u8],
+}
+
+/// The most bytes one record holds, the longest stuffed frame
+pub const MAX_RECORD_BYTES: usize = MAX_STUFFED_FRAME;
+/// The size of the longest record once encoded: the direction, the timestamp and the length
+/// as varints, the bytes, the COBS overhead and the delimiter
+pub const MAX_ENCODED_RECORD: usize = 1 + 10 + 2 + MAX_RECORD_BYTES + 3 + 1;
+
+/// Encodes a record into the buffer and returns the bytes written, the delimiter included
+fn encode<'b>(record: &FrameRecord<'_>, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+    postcard::to_slice_cobs(record, buf).ok()
+}
+
+/// A ring of encoded records that needs no allocator. Once it is full the oldest records are
+/// overwritten, [TraceBuffer::lost] counts them.
+#[derive(Debug)]
+pub struct TraceBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+    lost: u32,
+}
+
+impl<const N: usize> TraceBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+            lost: 0,
+        }
+    }
+
+    /// Adds a record, overwriting the oldest ones if there's no room for it. Bytes longer than
+    /// [MAX_RECORD_BYTES] or a record larger than the whole ring are not recorded.
+    pub fn record(&mut self, direction: Direction, timestamp_ticks: u64, bytes: &[u8]) {
+        let mut scratch = [0_u8; MAX_ENCODED_RECORD];
+        let record = FrameRecord {
+            direction,
+            timestamp_ticks,
+            bytes,
+        };
+        let encoded = match encode(&record, &mut scratch) {
+            Some(encoded) if bytes.len() <= MAX_RECORD_BYTES && encoded.len() <= N => encoded,
+            _ => {
+                self.lost = self.lost.saturating_add(1);
+                return;
+            }
+        };
+        while N - self.len < encoded.len() {
+            self.drop_oldest();
+        }
+        for &byte in encoded.iter() {
+            if let Some(slot) = self.buf.get_mut((self.head + self.len) % N) {
+                *slot = byte;
+            }
+            self.len += 1;
+        }
+    }
+
+    /// Moves the oldest bytes into the buffer and returns how many were moved. A record can be
+    /// split between two calls, the trace continues where the last call stopped.
+    pub fn drain(&mut self, out: &mut [u8]) -> usize {
+        let count = out.len().min(self.len);
+        for slot in out.iter_mut().take(count) {
+            *slot = self.buf.get(self.head).copied().unwrap_or_default();
+            self.head = (self.head + 1) % N;
+        }
+        self.len -= count;
+        count
+    }
+
+    /// The number of encoded bytes waiting to be drained
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many records were overwritten before they were drained or could not be recorded
+    pub fn lost(&self) -> u32 {
+        self.lost
+    }
+
+    /// Drops everything up to and including the next delimiter
+    fn drop_oldest(&mut self) {
+        while self.len > 0 {
+            let byte = self.buf.get(self.head).copied().unwrap_or_default();
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+            if byte == 0 {
+                break;
+            }
+        }
+        self.lost = self.lost.saturating_add(1);
+    }
+}
+
+impl<const N: usize> Default for TraceBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An [AsyncTransport](crate::async_transport::AsyncTransport) that records every write and
+/// read of the transport it wraps into a [TraceBuffer]. The buffer is shared through a
+/// [RefCell](core::cell::RefCell) so it can be drained while the connection owns the
+/// transport, it is never borrowed across an await. The clock returns the timestamp of a
+/// record, like `|| embassy_time::Instant::now().as_ticks()`.
+#[cfg(feature = "async")]
+pub struct TracingTransport<'a, T, C, const N: usize> {
+    inner: T,
+    trace: &'a core::cell::RefCell<TraceBuffer<N>>,
+    clock: C,
+}
+
+#[cfg(feature = "async")]
+impl<'a, T, C, const N: usize> TracingTransport<'a, T, C, N>
+where
+    T: crate::async_transport::AsyncTransport,
+    C: FnMut() -> u64,
+{
+    pub fn new(inner: T, trace: &'a core::cell::RefCell<TraceBuffer<N>>, clock: C) -> Self {
+        Self { inner, trace, clock }
+    }
+
+    /// Returns the wrapped transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        let ticks = (self.clock)();
+        self.trace.borrow_mut().record(direction, ticks, bytes);
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T, C, const N: usize> crate::async_transport::AsyncTransport for TracingTransport<'_, T, C, N>
+where
+    T: crate::async_transport::AsyncTransport,
+    C: FnMut() -> u64,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, crate::error::DeviceError> {
+        let read = self.inner.read(buf).await?;
+        if read > 0 {
+            self.record(Direction::Received, buf.get(..read).unwrap_or(buf));
+        }
+        Ok(read)
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), crate::error::DeviceError> {
+        self.inner.write_all(buf).await?;
+        self.record(Direction::Sent, buf);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), crate::error::DeviceError> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(feature = "std")]
+pub use host::*;
+
+#[cfg(feature = "std")]
+mod host {
+    use super::{Direction, FrameRecord};
+    use crate::shdlc::{FrameDecoder, MISOFrame, MOSIFrame, TranslationError, from_shdlc};
+
+    /// A frame found in a trace
+    #[derive(Debug)]
+    pub enum TracedFrame {
+        Request(Box<MOSIFrame>),
+        Response(Box<MISOFrame>),
+    }
+
+    /// A frame of a trace with the timestamp of the record it ended in
+    #[derive(Debug)]
+    pub struct TraceEntry {
+        pub direction: Direction,
+        pub timestamp_ticks: u64,
+        /// The frame as it was on the wire, stuffed and with both delimiters
+        pub raw: Vec<u8>,
+        /// The parsed frame, or why it could not be parsed
+        pub frame: Result<TracedFrame, TranslationError>,
+    }
+
+    /// The frames of a decoded trace
+    #[derive(Debug, Default)]
+    pub struct Trace {
+        pub entries: Vec<TraceEntry>,
+        /// Records that could not be decoded, like the one a capture starts in the middle of
+        pub damaged: usize,
+    }
+
+    /// Decodes a trace written by a [TraceBuffer](super::TraceBuffer). The bytes of each
+    /// direction are put back together into frames, a request is parsed into a [MOSIFrame]
+    /// and a response into a [MISOFrame]. Damaged records are skipped and counted.
+    pub fn decode(capture: &[u8]) -> Trace {
+        let mut trace = Trace::default();
+        let mut requests = FrameDecoder::new();
+        let mut responses = FrameDecoder::new();
+
+        for chunk in capture.split_inclusive(|&byte| byte == 0) {
+            let mut chunk = chunk.to_vec();
+            let record: FrameRecord = match postcard::from_bytes_cobs(&mut chunk) {
+                Ok(record) => record,
+                Err(_) => {
+                    trace.damaged += 1;
+                    continue;
+                }
+            };
+            let decoder = match record.direction {
+                Direction::Sent => &mut requests,
+                Direction::Received => &mut responses,
+            };
+            let mut bytes = record.bytes;
+            while !bytes.is_empty() {
+                let (consumed, raw) = match decoder.decode(bytes) {
+                    Ok((consumed, Some(raw))) => (consumed, raw),
+                    Ok((_, None)) => break,
+                    Err(e) => {
+                        trace.entries.push(TraceEntry {
+                            direction: record.direction,
+                            timestamp_ticks: record.timestamp_ticks,
+                            raw: Vec::new(),
+                            frame: Err(e),
+                        });
+                        break;
+                    }
+                };
+                bytes = bytes.get(consumed..).unwrap_or_default();
+                let frame = match record.direction {
+                    Direction::Sent => {
+                        parse_request(&raw).map(|frame| TracedFrame::Request(Box::new(frame)))
+                    }
+                    Direction::Received => MISOFrame::from_bytes(&raw)
+                        .map(|frame| TracedFrame::Response(Box::new(frame))),
+                };
+                trace.entries.push(TraceEntry {
+                    direction: record.direction,
+                    timestamp_ticks: record.timestamp_ticks,
+                    raw: raw.to_vec(),
+                    frame,
+                });
+            }
+        }
+        trace
+    }
+
+    /// Rebuilds a request from its address, command and data
+    fn parse_request(raw: &[u8]) -> Result<MOSIFrame, TranslationError> {
+        let decoded = from_shdlc(raw)?;
+        // address, command, length and checksum
+        let [address, command, length, ref available @ .., _] = *decoded else {
+            return Err(TranslationError::NotEnoughData(4, decoded.len() as u8));
+        };
+        let Some(data) = available.get(..length as usize) else {
+            return Err(TranslationError::NotEnoughData(length, available.len() as u8));
+        };
+        MOSIFrame::new(address, command, data)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::shdlc::MOSIFrame;
+
+    /// Get Baudrate and its response
+    const RESPONSE: &[u8] = &[0x7E, 0x00, 0x91, 0x00, 0x04, 0x00, 0x01, 0xC2, 0x00, 0xA7, 0x7E];
+
+    fn request() -> Vec<u8> {
+        MOSIFrame::new(0, 0x91, &[]).unwrap().into_raw().to_vec()
+    }
+
+    fn drain_all<const N: usize>(buffer: &mut TraceBuffer<N>) -> Vec<u8> {
+        let mut capture = vec![0_u8; buffer.len()];
+        buffer.drain(&mut capture);
+        capture
+    }
+
+    #[test]
+    fn recorded_frames_are_decoded() {
+        let mut buffer = TraceBuffer::<1024>::new();
+        buffer.record(Direction::Sent, 10, &request());
+        // a response that arrived in two reads
+        buffer.record(Direction::Received, 12, &RESPONSE[..4]);
+        buffer.record(Direction::Received, 13, &RESPONSE[4..]);
+
+        let trace = decode(&drain_all(&mut buffer));
+        assert_eq!(trace.damaged, 0);
+        assert_eq!(trace.entries.len(), 2);
+
+        let sent = &trace.entries[0];
+        assert_eq!((sent.direction, sent.timestamp_ticks), (Direction::Sent, 10));
+        match &sent.frame {
+            Ok(TracedFrame::Request(frame)) => {
+                assert_eq!((frame.get_address(), frame.get_command_number()), (0, 0x91))
+            }
+            other => panic!("expected the request, got {:?}", other),
+        }
+
+        let received = &trace.entries[1];
+        assert_eq!((received.direction, received.timestamp_ticks), (Direction::Received, 13));
+        assert_eq!(received.raw, RESPONSE);
+        match &received.frame {
+            Ok(TracedFrame::Response(frame)) => {
+                assert!(frame.validate_checksum());
+                assert_eq!(frame.get_command_number(), 0x91);
+            }
+            other => panic!("expected the response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn full_buffer_overwrites_the_oldest_records() {
+        let mut buffer = TraceBuffer::<64>::new();
+        for ticks in 0..10 {
+            buffer.record(Direction::Received, ticks, RESPONSE);
+        }
+        assert!(buffer.lost() > 0);
+        assert!(buffer.len() <= 64);
+
+        let trace = decode(&drain_all(&mut buffer));
+        assert_eq!(trace.damaged, 0);
+        let ticks: Vec<u64> = trace.entries.iter().map(|e| e.timestamp_ticks).collect();
+        assert_eq!(*ticks.last().unwrap(), 9);
+        assert_eq!(ticks.len() as u32 + buffer.lost(), 10);
+    }
+
+    #[test]
+    fn capture_starting_mid_record_skips_it() {
+        let mut buffer = TraceBuffer::<256>::new();
+        buffer.record(Direction::Sent, 1, &request());
+        buffer.record(Direction::Received, 2, RESPONSE);
+        let capture = drain_all(&mut buffer);
+
+        let trace = decode(&capture[3..]);
+        assert_eq!(trace.damaged, 1);
+        assert_eq!(trace.entries.len(), 1);
+        assert_eq!(trace.entries[0].timestamp_ticks, 2);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn connection_traffic_is_recorded() {
+        use crate::async_connection::AsyncConnection;
+        use crate::async_transport::{AsyncTransport, Delay};
+        use crate::error::DeviceError;
+        use core::cell::RefCell;
+        use core::time::Duration;
+
+        /// Answers with the response once a request was written
+        struct Answering(Vec<u8>);
+
+        impl AsyncTransport for Answering {
+            async fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError> {
+                let count = buf.len().min(self.0.len());
+                buf[..count].copy_from_slice(&self.0[..count]);
+                self.0.drain(..count);
+                Ok(count)
+            }
+
+            async fn write_all(&mut self, _: &[u8]) -> Result<(), DeviceError> {
+                self.0 = RESPONSE.to_vec();
+                Ok(())
+            }
+
+            async fn flush(&mut self) -> Result<(), DeviceError> {
+                Ok(())
+            }
+        }
+
+        struct NoDelay;
+
+        impl Delay for NoDelay {
+            fn delay(&self, _: Duration) -> impl core::future::Future<Output = ()> {
+                core::future::ready(())
+            }
+        }
+
+        let buffer = RefCell::new(TraceBuffer::<256>::new());
+        let mut ticks = 0;
+        let clock = || {
+            ticks += 1;
+            ticks
+        };
+        let transport = TracingTransport::new(Answering(Vec::new()), &buffer, clock);
+        let mut connection = AsyncConnection::new(transport, NoDelay);
+        connection.transact(MOSIFrame::new(0, 0x91, &[]).unwrap()).await.unwrap();
+
+        let trace = decode(&drain_all(&mut buffer.borrow_mut()));
+        let directions: Vec<Direction> = trace.entries.iter().map(|e| e.direction).collect();
+        assert_eq!(directions, [Direction::Sent, Direction::Received]);
+        assert!(trace.entries.iter().all(|e| e.frame.is_ok()));
+    }
+}
@@ -0,0 +1,552 @@
+//! Decoding raw captures of the traffic on the line, available with `std`. Captures from a logic
+//! analyzer or `socat -x` hold the requests and responses as one stream of bytes, [decode] splits
+//! them into frames, tells requests (MOSI) from responses (MISO), names the commands and prints
+//! the values of the ones this crate knows:
+//! ```
+//! use sfc_core::transcript::{self, Hints};
+//!
+//! let capture = [
+//!     0x7e, 0x00, 0xd1, 0x00, 0x2e, 0x7e, // get the version
+//!     0x7e, 0x00, 0xd1, 0x00, 0x07, 0x01, 0x04, 0x00, 0x01, 0x00, 0x02, 0x00, 0x1f, 0x7e,
+//! ];
+//! let frames = transcript::decode(&capture, &Hints::default());
+//! assert_eq!(
+//!     transcript::render(&frames),
+//!     "0x0000  MOSI 00 d1 version\n\
+//!      0x0006  MISO 00 d1 version: firmware 1.4, hardware 1.0, protocol 2.0"
+//! );
+//! ```
+//! Broken frames and bytes outside of frames are reported with their offset, decoding carries on
+//! after them.
+
+use std::fmt::Display;
+
+use crate::error::StateResponseError;
+use crate::gasunit::GasUnit;
+use crate::names::{DeviceFamily, command_entry, command_name};
+use crate::shdlc::{START_STOP, TranslationError, calculate_check_sum, from_shdlc, parse_string};
+
+/// Which device family's commands to name the frames after
+pub type CommandSet = DeviceFamily;
+
+/// How [decode] tells requests from responses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Directions {
+    /// By the length field, which sits at a different place in requests and responses. Frames
+    /// that fit both alternate with the frame before them.
+    #[default]
+    Guess,
+    /// The first frame is a request and the rest alternate
+    Alternate,
+    /// Every frame is a request, like a capture of only the TX line
+    Mosi,
+    /// Every frame is a response, like a capture of only the RX line
+    Miso,
+}
+
+/// What [decode] knows about a capture
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Hints {
+    pub directions: Directions,
+    pub commands: CommandSet,
+}
+
+/// Whether a frame was sent to or by the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Mosi,
+    Miso,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mosi => write!(f, "MOSI"),
+            Self::Miso => write!(f, "MISO"),
+        }
+    }
+}
+
+/// A well formed frame of a capture
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub direction: Direction,
+    pub address: u8,
+    pub command: u8,
+    /// The state byte of a response
+    pub state: Option<u8>,
+    /// The data without byte stuffing
+    pub data: Vec<u8>,
+    /// The name of the command, [None] if the command set doesn't have it
+    pub name: Option<&'static str>,
+    /// The data as text, like `2.5`, `l/min` or `"SFC6000D"`, and as hex bytes for commands
+    /// without a known layout. Empty if there is no data.
+    pub payload: String,
+}
+
+/// Why a span of a capture is not a frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Malformed {
+    /// Bytes outside of any frame, like line noise
+    Garbage,
+    /// A start delimiter without an end, at the end of the capture
+    Incomplete,
+    /// The byte stuffing is broken
+    Stuffing(TranslationError),
+    /// Too short for an address, command, length and checksum
+    TooShort,
+    /// The checksum does not match the contents
+    Checksum { expected: u8, received: u8 },
+    /// The length field doesn't match the frame, in a request or in a response
+    Length,
+}
+
+impl Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Garbage => write!(f, "bytes outside of a frame"),
+            Self::Incomplete => write!(f, "incomplete frame"),
+            Self::Stuffing(e) => write!(f, "broken byte stuffing, {}", e),
+            Self::TooShort => write!(f, "frame too short"),
+            Self::Checksum { expected, received } => write!(
+                f,
+                "checksum {:#04x} does not match {:#04x}",
+                received, expected
+            ),
+            Self::Length => write!(f, "length field does not match the frame"),
+        }
+    }
+}
+
+/// A span of a capture and what it decoded to
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedFrame {
+    /// Where the span starts in the capture
+    pub offset: usize,
+    /// The bytes of the span as captured, with byte stuffing and delimiters
+    pub raw: Vec<u8>,
+    pub frame: Result<Frame, Malformed>,
+}
+
+/// Formats the span as one line of a transcript, like `0x0006  MISO 00 d1 version: ...`
+impl Display for DecodedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#06x}  ", self.offset)?;
+        match &self.frame {
+            Ok(frame) => {
+                write!(
+                    f,
+                    "{} {:02x} {:02x} ",
+                    frame.direction, frame.address, frame.command
+                )?;
+                match frame.name {
+                    Some(name) => write!(f, "{}", name)?,
+                    None => write!(f, "unknown command")?,
+                }
+                if !frame.payload.is_empty() {
+                    write!(f, ": {}", frame.payload)?;
+                }
+                Ok(())
+            }
+            Err(malformed) => write!(f, "{}: {}", malformed, hex(&self.raw)),
+        }
+    }
+}
+
+/// Splits a capture into frames, see the [module](self) documentation. Nothing in the capture
+/// stops the decoding, every byte ends up in exactly one [DecodedFrame].
+pub fn decode(bytes: &[u8], hints: &Hints) -> Vec<DecodedFrame> {
+    let mut spans = Vec::new();
+    let mut garbage: Option<usize> = None;
+    let mut i = 0;
+    while let Some(&byte) = bytes.get(i) {
+        if byte != START_STOP {
+            garbage.get_or_insert(i);
+            i += 1;
+            continue;
+        }
+        if let Some(start) = garbage.take() {
+            spans.push((start, i, Err(Malformed::Garbage)));
+        }
+        match bytes.iter().skip(i + 1).position(|&byte| byte == START_STOP) {
+            None => {
+                spans.push((i, bytes.len(), Err(Malformed::Incomplete)));
+                i = bytes.len();
+            }
+            // two delimiters in a row, the second one starts the frame
+            Some(0) => {
+                garbage = Some(i);
+                i += 1;
+            }
+            Some(len) => {
+                let end = i + len + 2;
+                spans.push((i, end, Ok(())));
+                i = end;
+            }
+        }
+    }
+    if let Some(start) = garbage {
+        spans.push((start, bytes.len(), Err(Malformed::Garbage)));
+    }
+
+    let mut decoder = Decoder {
+        hints: *hints,
+        previous: None,
+        request: None,
+    };
+    spans
+        .into_iter()
+        .map(|(start, end, span)| {
+            let raw = bytes.get(start..end).unwrap_or_default();
+            DecodedFrame {
+                offset: start,
+                raw: raw.to_vec(),
+                frame: span.and_then(|()| decoder.frame(raw)),
+            }
+        })
+        .collect()
+}
+
+/// The transcript of the decoded frames, one line each
+pub fn render(frames: &[DecodedFrame]) -> String {
+    frames
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Decoder {
+    hints: Hints,
+    /// The direction of the last well formed frame
+    previous: Option<Direction>,
+    /// The address, command and data of the last request, a response is decoded with it
+    request: Option<(u8, u8, Vec<u8>)>,
+}
+
+impl Decoder {
+    fn frame(&mut self, raw: &[u8]) -> Result<Frame, Malformed> {
+        let content = from_shdlc(raw).map_err(Malformed::Stuffing)?;
+        let Some((&received, content)) = content.split_last().filter(|_| content.len() >= 4) else {
+            return Err(Malformed::TooShort);
+        };
+        let expected = calculate_check_sum(content);
+        if expected != received {
+            return Err(Malformed::Checksum { expected, received });
+        }
+
+        // address, command, length and data, with the state before the length in a response
+        let [address, command, third, ref rest @ ..] = *content else {
+            return Err(Malformed::TooShort);
+        };
+        let fits_mosi = third as usize == rest.len();
+        let fits_miso = rest
+            .split_first()
+            .is_some_and(|(&length, data)| length as usize == data.len());
+        let alternated = match self.previous {
+            Some(Direction::Mosi) => Direction::Miso,
+            _ => Direction::Mosi,
+        };
+        let direction = match self.hints.directions {
+            Directions::Guess => match (fits_mosi, fits_miso) {
+                (true, true) => alternated,
+                (true, false) => Direction::Mosi,
+                (false, true) => Direction::Miso,
+                (false, false) => return Err(Malformed::Length),
+            },
+            Directions::Alternate => alternated,
+            Directions::Mosi => Direction::Mosi,
+            Directions::Miso => Direction::Miso,
+        };
+        let fits = match direction {
+            Direction::Mosi => fits_mosi,
+            Direction::Miso => fits_miso,
+        };
+        if !fits {
+            return Err(Malformed::Length);
+        }
+        self.previous = Some(direction);
+
+        let frame = match direction {
+            Direction::Mosi => {
+                let data = rest.to_vec();
+                self.request = Some((address, command, data.clone()));
+                let entry = lookup(self.hints.commands, command, &data);
+                let payload = match entry {
+                    Some(entry) => {
+                        let skip = entry.key.1.is_some() as usize;
+                        entry.request.format(data.get(skip..).unwrap_or_default())
+                    }
+                    None => hex(&data),
+                };
+                Frame {
+                    direction,
+                    address,
+                    command,
+                    state: None,
+                    name: command_name(self.hints.commands, command, data.first().copied()),
+                    data,
+                    payload,
+                }
+            }
+            Direction::Miso => {
+                let state = third;
+                let data = rest.get(1..).unwrap_or_default().to_vec();
+                // the subcommand is only in the request
+                let request = match &self.request {
+                    Some((a, c, request)) if (*a, *c) == (address, command) => request.as_slice(),
+                    _ => &[],
+                };
+                let entry = lookup(self.hints.commands, command, request);
+                let payload = if state != 0 {
+                    format!(
+                        "error {:#04x}, {}",
+                        state,
+                        StateResponseError::from(state & 0x7F)
+                    )
+                } else {
+                    match entry {
+                        Some(entry) => entry.response.format(&data),
+                        None => hex(&data),
+                    }
+                };
+                Frame {
+                    direction,
+                    address,
+                    command,
+                    state: Some(state),
+                    name: command_name(self.hints.commands, command, request.first().copied()),
+                    data,
+                    payload,
+                }
+            }
+        };
+        Ok(frame)
+    }
+}
+
+/// The layout of the data of a request or response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Value {
+    Nothing,
+    Float,
+    U32,
+    U16,
+    U8,
+    Bool,
+    /// A calibration index
+    Index,
+    Unit,
+    Text,
+    Version,
+    /// No known layout, printed as hex
+    Raw,
+}
+
+impl Value {
+    /// The data as text, as hex if it doesn't fit the layout
+    fn format(self, data: &[u8]) -> String {
+        if data.is_empty() {
+            return String::new();
+        }
+        let formatted = match (self, data) {
+            (Self::Float, &[a, b, c, d]) => Some(f32::from_be_bytes([a, b, c, d]).to_string()),
+            (Self::U32, &[a, b, c, d]) => Some(u32::from_be_bytes([a, b, c, d]).to_string()),
+            (Self::Index, &[a, b, c, d]) => {
+                Some(format!("index {}", u32::from_be_bytes([a, b, c, d])))
+            }
+            (Self::U16, &[a, b]) => Some(u16::from_be_bytes([a, b]).to_string()),
+            (Self::U8, &[a]) => Some(a.to_string()),
+            (Self::Bool, &[a]) => Some((a != 0).to_string()),
+            (Self::Unit, &[a, b, c]) => Some(GasUnit::from_be_bytes([a, b, c]).to_string()),
+            (Self::Text, data) => parse_string(data).ok().map(|text| format!("{:?}", text)),
+            (
+                Self::Version,
+                &[
+                    fw_major,
+                    fw_minor,
+                    debug,
+                    hw_major,
+                    hw_minor,
+                    p_major,
+                    p_minor,
+                ],
+            ) => {
+                let debug = if debug != 0 { " (debug)" } else { "" };
+                Some(format!(
+                    "firmware {}.{}{}, hardware {}.{}, protocol {}.{}",
+                    fw_major, fw_minor, debug, hw_major, hw_minor, p_major, p_minor
+                ))
+            }
+            _ => None,
+        };
+        formatted.unwrap_or_else(|| hex(data))
+    }
+}
+
+/// The layouts of a command, or one subcommand of it, of a command set. Every command and
+/// subcommand [command_name] names has one, under the same key.
+struct Entry {
+    /// The command and the first byte of the request data that selects what it does
+    key: (u8, Option<u8>),
+    /// The layout of the request data after the subcommand
+    request: Value,
+    response: Value,
+}
+
+const fn entry(command: u8, subcommand: Option<u8>, request: Value, response: Value) -> Entry {
+    Entry {
+        key: (command, subcommand),
+        request,
+        response,
+    }
+}
+
+fn layouts(family: DeviceFamily) -> &'static [Entry] {
+    match family {
+        DeviceFamily::Sfc6xxx => SFC6XXX,
+        DeviceFamily::Sfc5xxx => SFC5XXX,
+    }
+}
+
+/// The entry for a request, under the key of the name of the command. Subcommands the family
+/// doesn't list get the layouts of the whole command like they get its name.
+fn lookup(family: DeviceFamily, command: u8, request: &[u8]) -> Option<&'static Entry> {
+    let &(command, subcommand, _) = command_entry(family, command, request.first().copied())?;
+    layouts(family)
+        .iter()
+        .find(|entry| entry.key == (command, subcommand))
+}
+
+const SFC6XXX: &[Entry] = &[
+    entry(0x00, Some(0x01), Value::Float, Value::Float),
+    entry(0x00, None, Value::Raw, Value::Raw),
+    entry(0x03, Some(0x01), Value::Float, Value::Float),
+    entry(0x03, None, Value::Raw, Value::Raw),
+    entry(0x08, Some(0x01), Value::Nothing, Value::Float),
+    entry(0x08, Some(0x11), Value::U8, Value::Float),
+    entry(0x08, None, Value::Raw, Value::Raw),
+    entry(0x22, Some(0x00), Value::Float, Value::Float),
+    entry(0x22, Some(0x03), Value::Float, Value::Float),
+    entry(0x22, None, Value::Raw, Value::Raw),
+    entry(0x30, Some(0x00), Value::Nothing, Value::U16),
+    entry(0x30, Some(0x02), Value::Nothing, Value::U16),
+    entry(0x30, Some(0x10), Value::Nothing, Value::Float),
+    entry(0x30, None, Value::Raw, Value::Raw),
+    entry(0x40, Some(0x00), Value::Nothing, Value::U32),
+    entry(0x40, Some(0x10), Value::Index, Value::Bool),
+    entry(0x40, Some(0x12), Value::Index, Value::U32),
+    entry(0x40, Some(0x13), Value::Index, Value::Unit),
+    entry(0x40, Some(0x14), Value::Index, Value::Float),
+    entry(0x40, None, Value::Raw, Value::Raw),
+    entry(0x44, Some(0x12), Value::Nothing, Value::U32),
+    entry(0x44, Some(0x13), Value::Nothing, Value::Unit),
+    entry(0x44, Some(0x14), Value::Nothing, Value::Float),
+    entry(0x44, None, Value::Raw, Value::Raw),
+    entry(0x45, None, Value::Index, Value::Index),
+    entry(0x46, None, Value::Index, Value::Nothing),
+    entry(0x90, None, Value::U8, Value::U8),
+    entry(0x91, None, Value::U32, Value::U32),
+    entry(0xD0, Some(0x00), Value::Nothing, Value::Text),
+    entry(0xD0, Some(0x01), Value::Nothing, Value::Text),
+    entry(0xD0, Some(0x02), Value::Nothing, Value::Text),
+    entry(0xD0, Some(0x03), Value::Nothing, Value::Text),
+    entry(0xD0, None, Value::Raw, Value::Raw),
+    entry(0xD1, None, Value::Nothing, Value::Version),
+    entry(0xD3, None, Value::Nothing, Value::Nothing),
+];
+
+/// The value commands take a scale byte, 0x00 for normalized and 0x01 for physical values
+const SFC5XXX: &[Entry] = &[
+    entry(0x00, Some(0x00), Value::Float, Value::Float),
+    entry(0x00, Some(0x01), Value::Float, Value::Float),
+    entry(0x00, None, Value::Raw, Value::Raw),
+    entry(0x02, Some(0x00), Value::Bool, Value::Bool),
+    entry(0x02, None, Value::Raw, Value::Raw),
+    entry(0x03, Some(0x00), Value::Float, Value::Float),
+    entry(0x03, Some(0x01), Value::Float, Value::Float),
+    entry(0x03, None, Value::Raw, Value::Raw),
+    entry(0x04, None, Value::Raw, Value::Raw),
+    entry(0x08, Some(0x00), Value::Nothing, Value::Float),
+    entry(0x08, Some(0x01), Value::Nothing, Value::Float),
+    entry(0x08, None, Value::Raw, Value::Raw),
+    entry(0x09, None, Value::Raw, Value::Raw),
+    entry(0x0A, None, Value::Raw, Value::Raw),
+    entry(0x20, Some(0x00), Value::U8, Value::U8),
+    entry(0x20, Some(0x01), Value::Float, Value::Float),
+    entry(0x20, None, Value::Raw, Value::Raw),
+    entry(0x21, Some(0x00), Value::Unit, Value::Unit),
+    entry(0x21, Some(0x01), Value::Nothing, Value::Unit),
+    entry(0x21, Some(0x0A), Value::Nothing, Value::Float),
+    entry(0x21, None, Value::Raw, Value::Raw),
+    entry(0x22, Some(0x00), Value::Float, Value::Float),
+    entry(0x22, Some(0x10), Value::Bool, Value::Bool),
+    entry(0x22, Some(0x11), Value::Float, Value::Float),
+    entry(0x22, Some(0x20), Value::Bool, Value::Bool),
+    entry(0x22, Some(0x21), Value::Float, Value::Float),
+    entry(0x22, None, Value::Raw, Value::Raw),
+    entry(0x30, Some(0x00), Value::Nothing, Value::U16),
+    entry(0x30, Some(0x01), Value::Nothing, Value::U16),
+    entry(0x30, Some(0x02), Value::Nothing, Value::U16),
+    entry(0x30, Some(0x10), Value::Nothing, Value::Float),
+    entry(0x30, None, Value::Raw, Value::Raw),
+    entry(0x40, Some(0x00), Value::Nothing, Value::U32),
+    entry(0x40, Some(0x10), Value::Index, Value::Bool),
+    entry(0x40, Some(0x11), Value::Index, Value::Text),
+    entry(0x40, Some(0x12), Value::Index, Value::U32),
+    entry(0x40, Some(0x13), Value::Index, Value::Unit),
+    entry(0x40, Some(0x14), Value::Index, Value::Float),
+    entry(0x40, Some(0x15), Value::Index, Value::Raw),
+    entry(0x40, Some(0x16), Value::Index, Value::Raw),
+    entry(0x40, Some(0x17), Value::Index, Value::U16),
+    entry(0x40, None, Value::Raw, Value::Raw),
+    entry(0x44, Some(0x11), Value::Nothing, Value::Text),
+    entry(0x44, Some(0x12), Value::Nothing, Value::U32),
+    entry(0x44, Some(0x13), Value::Nothing, Value::Unit),
+    entry(0x44, Some(0x14), Value::Nothing, Value::Float),
+    entry(0x44, Some(0x15), Value::Nothing, Value::Raw),
+    entry(0x44, Some(0x16), Value::Nothing, Value::Raw),
+    entry(0x44, Some(0x17), Value::Nothing, Value::U16),
+    entry(0x44, None, Value::Raw, Value::Raw),
+    entry(0x45, None, Value::Index, Value::Index),
+    entry(0x6E, None, Value::Raw, Value::Raw),
+    entry(0x90, None, Value::U8, Value::U8),
+    entry(0x91, None, Value::U32, Value::U32),
+    entry(0x92, None, Value::Nothing, Value::Nothing),
+    entry(0xD0, Some(0x01), Value::Nothing, Value::Text),
+    entry(0xD0, Some(0x02), Value::Nothing, Value::Text),
+    entry(0xD0, Some(0x03), Value::Nothing, Value::Text),
+    entry(0xD0, None, Value::Raw, Value::Raw),
+    entry(0xD1, None, Value::Nothing, Value::Version),
+    entry(0xD2, None, Value::Bool, Value::Raw),
+    entry(0xD3, None, Value::Nothing, Value::Nothing),
+];
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::names::table;
+
+    #[test]
+    fn every_named_command_has_a_layout_and_every_layout_a_name() {
+        for family in [DeviceFamily::Sfc6xxx, DeviceFamily::Sfc5xxx] {
+            let named: Vec<_> = table(family).iter().map(|&(c, s, _)| (c, s)).collect();
+            let laid_out: Vec<_> = layouts(family).iter().map(|entry| entry.key).collect();
+            for key in &named {
+                assert!(laid_out.contains(key), "{:?} {:02x?} has no layout", family, key);
+            }
+            for key in &laid_out {
+                assert!(named.contains(key), "{:?} {:02x?} has no name", family, key);
+            }
+            assert_eq!(named.len(), laid_out.len(), "{:?}", family);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! The byte stream a device is connected through. With the `serialport` feature enabled every
+//! serial port is a transport, but anything that can read and write SHDLC frames (an emulator,
+//! a network socket) can be used to drive a device by implementing [Transport].
+//!
+//! Devices behind a serial device server (ser2net, Moxa NPort and similar) can be reached with
+//! [TcpTransport].
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::error::DeviceError;
+
+/// A bidirectional byte stream that a device driver can send MOSI frames over and read MISO
+/// frames back from. Reads should block for at most the configured timeout and return an
+/// [std::io::ErrorKind::TimedOut] error when no data arrived in that window. A timeout of zero
+/// only returns the data that is already available.
+pub trait Transport: Read + Write {
+    /// Returns the current read timeout
+    fn timeout(&self) -> Duration;
+
+    /// Sets how long a read waits for data before giving up
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError>;
+
+    /// Changes the line speed of the transport. Transports without a notion of baudrate can
+    /// ignore this, which is what the default implementation does.
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    /// Drives the request to send line, used by RS-485 adapters that switch the direction of the
+    /// line with it. Transports without such a line can ignore this, which is what the default
+    /// implementation does.
+    fn set_request_to_send(&mut self, _level: bool) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    /// Returns how many received bytes can be read without waiting, [None] if the transport
+    /// can't tell, which is what the default implementation returns. Used to size reads.
+    fn bytes_to_read(&mut self) -> Option<usize> {
+        None
+    }
+
+    /// Discards any bytes that were received but not read yet. The default implementation reads
+    /// with a 1ms timeout until nothing more arrives.
+    fn clear_input(&mut self) -> Result<(), DeviceError> {
+        let timeout = self.timeout();
+        self.set_timeout(Duration::from_millis(1))?;
+        let mut buf = [0_u8; 64];
+        let result = loop {
+            match self.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    break Ok(());
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e.into()),
+            }
+        };
+        self.set_timeout(timeout)?;
+        result
+    }
+}
+
+#[cfg(feature = "serialport")]
+impl<T: serialport::SerialPort> Transport for T {
+    fn timeout(&self) -> Duration {
+        serialport::SerialPort::timeout(self)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        serialport::SerialPort::set_timeout(self, timeout)?;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), DeviceError> {
+        serialport::SerialPort::set_baud_rate(self, baud_rate)?;
+        Ok(())
+    }
+
+    fn set_request_to_send(&mut self, level: bool) -> Result<(), DeviceError> {
+        serialport::SerialPort::write_request_to_send(self, level)?;
+        Ok(())
+    }
+
+    fn bytes_to_read(&mut self) -> Option<usize> {
+        serialport::SerialPort::bytes_to_read(self).ok().map(|count| count as usize)
+    }
+
+    fn clear_input(&mut self) -> Result<(), DeviceError> {
+        serialport::SerialPort::clear(self, serialport::ClearBuffer::Input)?;
+        Ok(())
+    }
+}
+
+/// A raw TCP connection to a serial device server that forwards bytes to and from the serial
+/// line of the device. The read timeout behaves like the timeout of a serial port, so a
+/// connection that stopped answering (for example a half-open one) surfaces as a
+/// [ErrorKind::TimedOut] error instead of blocking forever.
+///
+/// When the connection breaks the transport drops it and reconnects to the same address on the
+/// next read or write.
+/// ```no_run
+/// use sfc_core::transport::TcpTransport;
+/// let transport = TcpTransport::connect("10.0.0.5:4001").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TcpTransport {
+    addresses: Vec<SocketAddr>,
+    stream: Option<TcpStream>,
+    timeout: Duration,
+}
+
+/// The shortest time a connection attempt is given, even if the read timeout is shorter
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(600);
+
+impl TcpTransport {
+    /// Connects to the device server at the given address using a timeout of 600ms, the same
+    /// timeout the device drivers configure for serial ports.
+    pub fn connect<A: ToSocketAddrs>(address: A) -> std::io::Result<Self> {
+        let mut transport = Self {
+            addresses: address.to_socket_addrs()?.collect(),
+            stream: None,
+            timeout: Duration::from_millis(600),
+        };
+        transport.stream()?;
+        Ok(transport)
+    }
+
+    /// Returns true if the transport currently holds an open connection. A broken connection
+    /// is only noticed when it is used.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Returns the open connection, reconnecting first if the last one broke.
+    fn stream(&mut self) -> std::io::Result<&mut TcpStream> {
+        match self.stream {
+            Some(ref mut stream) => Ok(stream),
+            None => Ok(self.stream.insert(self.open()?)),
+        }
+    }
+
+    fn open(&self) -> std::io::Result<TcpStream> {
+        let mut last_error = std::io::Error::new(ErrorKind::InvalidInput, "no address to connect to");
+        for address in &self.addresses {
+            match TcpStream::connect_timeout(address, self.timeout.max(CONNECT_TIMEOUT)) {
+                Ok(stream) => {
+                    apply_timeout(&stream, self.timeout)?;
+                    // frames are tiny and latency matters more than throughput
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Drops the connection if the error means it can't be used anymore and maps socket
+    /// timeouts to [ErrorKind::TimedOut].
+    fn handle_error(&mut self, error: std::io::Error) -> std::io::Error {
+        match error.kind() {
+            // depending on the platform a socket timeout is reported as either of these
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                std::io::Error::new(ErrorKind::TimedOut, "device server did not respond in time")
+            }
+            ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof => {
+                self.stream = None;
+                error
+            }
+            _ => error,
+        }
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let result = self.stream()?.read(buf);
+        match result {
+            Ok(0) if !buf.is_empty() => Err(self.handle_error(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "device server closed the connection",
+            ))),
+            Ok(read) => Ok(read),
+            Err(e) => Err(self.handle_error(e)),
+        }
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.stream()?.write(buf);
+        result.map_err(|e| self.handle_error(e))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.stream()?.flush();
+        result.map_err(|e| self.handle_error(e))
+    }
+}
+
+impl Transport for TcpTransport {
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.timeout = timeout;
+        if let Some(stream) = &self.stream {
+            apply_timeout(stream, timeout)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sockets don't accept a timeout of zero, it is mapped to non-blocking mode instead
+fn apply_timeout(stream: &TcpStream, timeout: Duration) -> std::io::Result<()> {
+    stream.set_nonblocking(timeout.is_zero())?;
+    if !timeout.is_zero() {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn tcp_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0_u8; 4];
+            stream.read_exact(&mut buf).unwrap();
+            // answer in two segments to make sure nothing assumes one read per frame
+            stream.write_all(&buf[..1]).unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_millis(20));
+            stream.write_all(&buf[1..]).unwrap();
+        });
+
+        let mut transport = TcpTransport::connect(address).unwrap();
+        transport.write_all(&[0x7E, 1, 2, 0x7E]).unwrap();
+        let mut buf = [0_u8; 4];
+        transport.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x7E, 1, 2, 0x7E]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn half_open_connection_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // accept and never answer
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(300));
+            drop(stream);
+        });
+
+        let mut transport = TcpTransport::connect(address).unwrap();
+        transport.set_timeout(Duration::from_millis(50)).unwrap();
+        let start = Instant::now();
+        let err = transport.read(&mut [0_u8; 8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_millis(250));
+        assert!(transport.is_connected());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn zero_timeout_does_not_block() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(200));
+            drop(stream);
+        });
+
+        let mut transport = TcpTransport::connect(address).unwrap();
+        transport.set_timeout(Duration::ZERO).unwrap();
+        let start = Instant::now();
+        let err = transport.read(&mut [0_u8; 8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reconnects_after_broken_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // the first connection is closed straight away
+            let (stream, _) = listener.accept().unwrap();
+            drop(stream);
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0_u8; 1];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+
+        let mut transport = TcpTransport::connect(address).unwrap();
+        let err = transport.read(&mut [0_u8; 8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(!transport.is_connected());
+
+        transport.write_all(&[0x42]).unwrap();
+        let mut buf = [0_u8; 1];
+        transport.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x42]);
+        server.join().unwrap();
+    }
+}
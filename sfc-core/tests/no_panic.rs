@@ -0,0 +1,148 @@
+//! Arbitrary bytes from the wire into every public decoder and the connection. Nothing a device
+//! or the line sends may panic the driver, a failure is an error like any other.
+#![cfg(feature = "std")]
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::Duration;
+
+use proptest::prelude::*;
+
+use sfc_core::connection::Connection;
+use sfc_core::error::DeviceError;
+use sfc_core::shdlc::{
+    FrameDecoder, MISOFrame, MISOFrameRef, MOSIFrame, START_STOP, from_shdlc, parse_string,
+};
+use sfc_core::transcript::{self, CommandSet, Directions, Hints};
+use sfc_core::transport::Transport;
+
+/// Answers every request with the same bytes, then times out. Reports reading and writing more
+/// bytes than it was given when `overreport` is set, like a broken driver of an adapter would.
+struct Answering {
+    response: Vec<u8>,
+    read: usize,
+    overreport: bool,
+}
+
+impl Read for Answering {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let rest = self.response.get(self.read..).unwrap_or_default();
+        if rest.is_empty() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        let count = rest.len().min(buf.len());
+        buf[..count].copy_from_slice(&rest[..count]);
+        self.read += count;
+        Ok(if self.overreport { count + 7 } else { count })
+    }
+}
+
+impl Write for Answering {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.read = 0;
+        Ok(if self.overreport { buf.len() + 7 } else { buf.len() })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Answering {
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// Bytes with many delimiters and escapes, so more of them get past the framing
+fn wire() -> impl Strategy<Value = Vec<u8>> {
+    let byte = prop_oneof![
+        4 => any::<u8>(),
+        1 => Just(START_STOP),
+        1 => Just(0x7D),
+        1 => prop::sample::select(vec![0x5E, 0x5D, 0x31, 0x33]),
+    ];
+    prop::collection::vec(byte, 0..600)
+}
+
+fn hints() -> impl Strategy<Value = Hints> {
+    let directions = prop::sample::select(vec![
+        Directions::Guess,
+        Directions::Alternate,
+        Directions::Mosi,
+        Directions::Miso,
+    ]);
+    let commands = prop::sample::select(vec![CommandSet::Sfc6xxx, CommandSet::Sfc5xxx]);
+    (directions, commands).prop_map(|(directions, commands)| Hints {
+        directions,
+        commands,
+    })
+}
+
+proptest! {
+    #[test]
+    fn frames_decode_without_panicking(bytes in wire()) {
+        let _ = from_shdlc(&bytes);
+        let _ = MISOFrame::from_bytes(&bytes);
+        let _ = MISOFrameRef::from_unstuffed(&bytes);
+        let _ = MOSIFrame::decode(&bytes);
+        let _ = parse_string(&bytes);
+        if let Ok(frame) = MISOFrame::from_bytes(&bytes) {
+            let _ = frame.to_raw();
+        }
+        if let Ok(frame) = MOSIFrame::decode(&bytes) {
+            let _ = frame.data();
+            let _ = frame.validate_checksum();
+        }
+    }
+
+    #[test]
+    fn the_stream_decoder_takes_any_bytes(bytes in wire(), split in 0_usize..600) {
+        let (first, second) = bytes.split_at(split.min(bytes.len()));
+        let mut decoder = FrameDecoder::new();
+        for part in [first, second] {
+            let mut rest = part;
+            while let Ok((consumed, complete)) = decoder.feed(rest) {
+                if complete {
+                    let _ = decoder.frame();
+                    let _ = decoder.miso_frame();
+                    let _ = decoder.miso_frame();
+                }
+                rest = rest.get(consumed..).unwrap_or_default();
+                if rest.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transcripts_take_any_capture(bytes in wire(), hints in hints()) {
+        let frames = transcript::decode(&bytes, &hints);
+        let _ = transcript::render(&frames);
+        // every byte is in exactly one frame
+        prop_assert_eq!(frames.iter().map(|frame| frame.raw.len()).sum::<usize>(), bytes.len());
+    }
+
+    #[test]
+    fn a_connection_takes_any_answer(response in wire(), overreport: bool) {
+        let mut connection = Connection::new(Answering { response, read: 0, overreport });
+        connection.set_response_timeout(Duration::from_millis(1));
+        connection.set_inter_byte_timeout(Duration::from_millis(1));
+        let request = MOSIFrame::new(0, 0xD0, &[0x01]).unwrap();
+        let _ = connection.transact(request);
+        let requests = (0..3).map(|_| MOSIFrame::new(0, 0x00, &[0x01]).unwrap()).collect();
+        let _ = connection.pipeline(requests);
+    }
+}
+
+#[cfg(feature = "trace-postcard")]
+proptest! {
+    #[test]
+    fn traces_decode_without_panicking(bytes in wire()) {
+        let _ = sfc_core::trace::decode(&bytes);
+    }
+}
@@ -0,0 +1,58 @@
+//! Decodes captures of the line and compares the transcripts with the ones pinned in fixtures.
+#![cfg(feature = "std")]
+
+use sfc_core::transcript::{self, CommandSet, Direction, Directions, Hints, Malformed};
+
+/// Reads a capture written as hex bytes, with `#` comments
+fn capture(text: &str) -> Vec<u8> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap())
+        .flat_map(str::split_whitespace)
+        .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn sfc6xxx_session() {
+    let bytes = capture(include_str!("fixtures/sfc6xxx_session.hex"));
+    let frames = transcript::decode(&bytes, &Hints::default());
+    assert_eq!(
+        transcript::render(&frames),
+        include_str!("fixtures/sfc6xxx_session.txt").trim_end()
+    );
+    // every byte is in exactly one span
+    assert_eq!(
+        frames.iter().map(|frame| frame.raw.len()).sum::<usize>(),
+        bytes.len()
+    );
+}
+
+#[test]
+fn sfc5xxx_alternating() {
+    let bytes = capture(
+        "7e 02 08 01 01 f3 7e
+         7e 02 08 00 04 3f c0 00 00 f2 7e
+         7e 02 02 01 00 fa 7e
+         7e 02 02 00 01 01 f9 7e
+         7e 02 7d 00 01 7e",
+    );
+    let hints = Hints {
+        directions: Directions::Alternate,
+        commands: CommandSet::Sfc5xxx,
+    };
+    let frames = transcript::decode(&bytes, &hints);
+    assert_eq!(
+        transcript::render(&frames[..4]),
+        "0x0000  MOSI 02 08 read measured value\n\
+         0x0007  MISO 02 08 read measured value: 1.5\n\
+         0x0012  MOSI 02 02 setpoint persistence\n\
+         0x0019  MISO 02 02 setpoint persistence: true"
+    );
+    let frame = frames[1].frame.as_ref().unwrap();
+    assert_eq!(frame.direction, Direction::Miso);
+    assert_eq!(frame.state, Some(0));
+    assert_eq!(frame.data, [0x3f, 0xc0, 0x00, 0x00]);
+
+    assert_eq!(frames[4].offset, 0x21);
+    assert!(matches!(frames[4].frame, Err(Malformed::Stuffing(_))));
+}
@@ -0,0 +1,97 @@
+// two devices on one RS-485 line in a cascade: the flow measured by the upstream device decides
+// the setpoint of the downstream one, which follows at half of it and by no more than a tenth of
+// its unit per second. Run with `cargo run -p sfc5xxx-rs --example cascade -- /dev/ttyUSB0 1 2 60`
+// for the devices at addresses 1 and 2 for 60 seconds, the duration defaults to 30. The
+// downstream device is zeroed when either device fails and closed at the end.
+// tests/examples.rs runs it against the emulator.
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc_core::bus::SharedBus;
+use sfc_core::cascade::{Cascade, CascadeConfig, CascadeError, FailurePolicy};
+use sfc_core::discovery::open_port;
+use sfc_core::flow_controller::FlowController;
+use sfc5xxx_rs::device::Device;
+
+/// The share of the upstream flow the downstream device is set to
+const GAIN: f32 = 0.5;
+/// How often the downstream setpoint is written
+const INTERVAL: Duration = Duration::from_millis(200);
+/// The most the downstream setpoint changes per second
+const MAX_RATE: f32 = 0.1;
+/// How often the state of the cascade is printed
+const REPORT: Duration = Duration::from_secs(1);
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((port, upstream, downstream, duration)) = parse(&args) else {
+        eprintln!("usage: cascade <port> <upstream address> <downstream address> [seconds]");
+        return ExitCode::from(2);
+    };
+
+    // both devices share the port, every exchange holds the line until its response is in
+    let devices = open_port(port, 115200)
+        .map(SharedBus::new)
+        .and_then(|bus| Ok((Device::on_bus(&bus, upstream)?, Device::on_bus(&bus, downstream)?)));
+    let (upstream, downstream) = match devices {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("cascade: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(upstream, downstream, duration) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("cascade: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The port, the upstream and downstream address and the duration from the arguments, [None] if they are not valid
+pub fn parse(args: &[String]) -> Option<(&str, u8, u8, Duration)> {
+    let [port, upstream, downstream, rest @ ..] = args else {
+        return None;
+    };
+    if rest.len() > 1 {
+        return None;
+    }
+    let seconds = rest.first().map_or(Ok(30), |seconds| seconds.parse()).ok()?;
+    Some((port, upstream.parse().ok()?, downstream.parse().ok()?, Duration::from_secs(seconds)))
+}
+
+/// The downstream setpoint for a flow measured upstream
+pub fn transfer(upstream: f32) -> f32 {
+    upstream * GAIN
+}
+
+/// Runs the cascade for the duration and prints what it does, then closes the downstream valve,
+/// also when an update failed. Fails if the first update does, before anything runs unattended.
+pub fn run<U, D>(upstream: U, downstream: D, duration: Duration) -> Result<(), CascadeError>
+where
+    U: FlowController + Send + 'static,
+    D: FlowController + Send + 'static,
+{
+    let mut cascade = Cascade::new(upstream, downstream, transfer as fn(f32) -> f32);
+    cascade.set_config(CascadeConfig {
+        interval: INTERVAL,
+        max_rate: Some(MAX_RATE),
+        failure_policy: FailurePolicy::Zero,
+    });
+    cascade.step()?;
+
+    let running = cascade.start();
+    let start = Instant::now();
+    while let Some(left) = duration.checked_sub(start.elapsed()).filter(|left| !left.is_zero()) {
+        thread::sleep(REPORT.min(left));
+        let status = running.status();
+        match &status.error {
+            Some(e) => println!("update failed {} times in a row: {}", status.consecutive_failures, e),
+            None => println!("upstream {:?}, downstream setpoint {:?}", status.measured, status.setpoint),
+        }
+    }
+    let mut cascade = running.stop();
+    cascade.downstream_mut().set_setpoint(0.0).map_err(CascadeError::Downstream)
+}
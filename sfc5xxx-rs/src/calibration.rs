@@ -1,8 +1,8 @@
-use std::ffi::CStr;
+use sfc_core::{error::DeviceError, shdlc::{TranslationError, parse_string}};
 
-use sfc_core::{error::DeviceError, shdlc::MISOFrame};
-
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CalibrationCondition {
     pub company: String,
     pub operator: String,
@@ -13,6 +13,7 @@ pub struct CalibrationCondition {
     pub calibration_minute: u8,
     pub calibration_temperature: f32,
     pub calibration_inlet_temperature: f32,
+    #[cfg_attr(feature = "serde", serde(rename = "calibration_differential_pressure"))]
     pub calibration_diffrential_pressure: f32,
     pub real_gas_calibration: bool,
     pub calibration_accuracy_setpoint: f32,
@@ -20,34 +21,22 @@ pub struct CalibrationCondition {
 }
 
 impl CalibrationCondition {
-    pub(crate) fn from_miso(frame: MISOFrame) -> Result<Self, DeviceError> {
-        let data = frame.into_data();
-        if data.len() < 127 {
-            return Err(DeviceError::ShdlcError(sfc_core::shdlc::TranslationError::NotEnoughData(127, data.len() as u8)));
-        }
-
-        let company = match CStr::from_bytes_until_nul(&data[..50]) {
-            Ok(s) => match s.to_str() {
-                Ok(s) => s.to_string(),
-                Err(_) => return Err(DeviceError::InvalidString),
-            }
-            Err(_) => return Err(DeviceError::InvalidString),
-        };
-        
-        let operator = match CStr::from_bytes_until_nul(&data[50..100]) {
-            Ok(s) => match s.to_str() {
-                Ok(s) => s.to_string(),
-                Err(_) => return Err(DeviceError::InvalidString),
-            }
-            Err(_) => return Err(DeviceError::InvalidString),
+    /// Parses the 127 byte block the device answers the calibration condition commands with,
+    /// also for blocks read some other way. Only the company and operator are copied out of it.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DeviceError> {
+        let Some(data) = data.first_chunk::<127>() else {
+            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(127, data.len() as u8)));
         };
 
+        let company = parse_string(&data[..50])?;
+        let operator = parse_string(&data[50..100])?;
+
         let calibration_year = u16::from_be_bytes([data[100], data[101]]);
         let calibration_month = data[102];
         let calibration_day = data[103];
         let calibration_hour = data[104];
         let calibration_minute = data[105];
-        let calibration_temperature = f32::from_be_bytes([data[106], data[107], data[109], data[109]]);
+        let calibration_temperature = f32::from_be_bytes([data[106], data[107], data[108], data[109]]);
         let calibration_inlet_temperature = f32::from_be_bytes([data[110], data[111], data[112], data[113]]);
         let calibration_diffrential_pressure = f32::from_be_bytes([data[114], data[115], data[116], data[117]]);
         let real_gas_calibration = data[118] > 0;
@@ -70,4 +59,154 @@ impl CalibrationCondition {
             calibration_accuracy_fullscale,
         })
     }
+
+    /// Encodes the condition into the 127 byte block the device sends
+    #[cfg(any(test, feature = "emulator"))]
+    pub(crate) fn to_bytes(&self) -> [u8; 127] {
+        let mut block = [0_u8; 127];
+        // strings are nul terminated and padded inside their 50 byte fields
+        for (slot, &byte) in block[..49].iter_mut().zip(self.company.as_bytes()) {
+            *slot = byte;
+        }
+        for (slot, &byte) in block[50..99].iter_mut().zip(self.operator.as_bytes()) {
+            *slot = byte;
+        }
+
+        block[100..102].copy_from_slice(&self.calibration_year.to_be_bytes());
+        block[102] = self.calibration_month;
+        block[103] = self.calibration_day;
+        block[104] = self.calibration_hour;
+        block[105] = self.calibration_minute;
+        block[106..110].copy_from_slice(&self.calibration_temperature.to_be_bytes());
+        block[110..114].copy_from_slice(&self.calibration_inlet_temperature.to_be_bytes());
+        block[114..118].copy_from_slice(&self.calibration_diffrential_pressure.to_be_bytes());
+        block[118] = self.real_gas_calibration as u8;
+        block[119..123].copy_from_slice(&self.calibration_accuracy_setpoint.to_be_bytes());
+        block[123..127].copy_from_slice(&self.calibration_accuracy_fullscale.to_be_bytes());
+        block
+    }
+    /// The date and time of the calibration, available with the `time` feature. Slots that
+    /// were never calibrated report zeros, those and any other date that does not exist return
+    /// `None`.
+    #[cfg(feature = "time")]
+    pub fn calibration_datetime(&self) -> Option<time::PrimitiveDateTime> {
+        let month = time::Month::try_from(self.calibration_month).ok()?;
+        let date = time::Date::from_calendar_date(
+            self.calibration_year.into(),
+            month,
+            self.calibration_day,
+        )
+        .ok()?;
+        let time = time::Time::from_hms(self.calibration_hour, self.calibration_minute, 0).ok()?;
+        Some(time::PrimitiveDateTime::new(date, time))
+    }
+
+    /// How long ago the calibration was at `now`, available with the `time` feature. The device
+    /// stores no time zone, `now` must be in the one the calibration was made in. `None` when
+    /// there is no valid calibration date.
+    #[cfg(feature = "time")]
+    pub fn age(&self, now: time::PrimitiveDateTime) -> Option<time::Duration> {
+        Some(now - self.calibration_datetime()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "time")]
+    use time::macros::datetime;
+
+    fn calibrated(year: u16, month: u8, day: u8, hour: u8, minute: u8) -> CalibrationCondition {
+        CalibrationCondition {
+            company: "Sensirion".to_string(),
+            operator: "test".to_string(),
+            calibration_year: year,
+            calibration_month: month,
+            calibration_day: day,
+            calibration_hour: hour,
+            calibration_minute: minute,
+            calibration_temperature: 23.0,
+            calibration_inlet_temperature: 22.5,
+            calibration_diffrential_pressure: 1.0,
+            real_gas_calibration: true,
+            calibration_accuracy_setpoint: 0.5,
+            calibration_accuracy_fullscale: 0.1,
+        }
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn calibration_date() {
+        let condition = calibrated(2024, 3, 14, 9, 30);
+        assert_eq!(condition.calibration_datetime(), Some(datetime!(2024-03-14 9:30)));
+        assert_eq!(
+            condition.age(datetime!(2024-03-16 10:30)),
+            Some(time::Duration::hours(49))
+        );
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn uncalibrated_slot_has_no_date() {
+        let condition = calibrated(0, 0, 0, 0, 0);
+        assert_eq!(condition.calibration_datetime(), None);
+        assert_eq!(condition.age(datetime!(2024-03-16 10:30)), None);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn impossible_dates_are_rejected() {
+        assert_eq!(calibrated(2024, 13, 1, 0, 0).calibration_datetime(), None);
+        assert_eq!(calibrated(2023, 2, 29, 0, 0).calibration_datetime(), None);
+        assert_eq!(calibrated(2024, 2, 29, 24, 0).calibration_datetime(), None);
+    }
+
+    #[test]
+    fn block_round_trips() {
+        let condition = calibrated(2024, 3, 14, 9, 30);
+        let block = condition.to_bytes();
+        assert_eq!(CalibrationCondition::from_bytes(&block).unwrap(), condition);
+
+        // bytes after the block are ignored
+        let mut longer = block.to_vec();
+        longer.extend_from_slice(&[0xFF; 8]);
+        assert_eq!(CalibrationCondition::from_bytes(&longer).unwrap(), condition);
+    }
+
+    #[test]
+    fn strings_end_at_the_first_nul() {
+        let mut block = calibrated(2024, 3, 14, 9, 30).to_bytes();
+        // left over bytes of a longer name after the terminator
+        block[10..14].copy_from_slice(b"Ltd.");
+        block[50..60].copy_from_slice(b"operator  ");
+        let condition = CalibrationCondition::from_bytes(&block).unwrap();
+        assert_eq!(condition.company, "Sensirion");
+        assert_eq!(condition.operator, "operator");
+    }
+
+    #[test]
+    fn malformed_blocks_are_rejected() {
+        let block = calibrated(2024, 3, 14, 9, 30).to_bytes();
+        assert!(matches!(
+            CalibrationCondition::from_bytes(&block[..126]),
+            Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(127, 126)))
+        ));
+
+        // a company filling its whole field has no terminator
+        let mut unterminated = block;
+        unterminated[..50].fill(b'a');
+        assert!(matches!(
+            CalibrationCondition::from_bytes(&unterminated),
+            Err(DeviceError::InvalidString)
+        ));
+    }
+
+    #[test]
+    fn calibration_temperature_uses_all_four_bytes() {
+        let temperature = 23.45_f32;
+        let mut block = [0_u8; 127];
+        block[106..110].copy_from_slice(&temperature.to_be_bytes());
+        let condition = CalibrationCondition::from_bytes(&block).unwrap();
+        assert_eq!(condition.calibration_temperature, temperature);
+    }
 }
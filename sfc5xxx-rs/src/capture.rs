@@ -0,0 +1,235 @@
+//! Sustained capture from the measurement buffer. The device samples into a buffer of its own
+//! at its sampling time and drops values once the buffer is full, a capture that reads too
+//! rarely loses values. [BufferedCapture] reads the buffer at a cadence sized from the sampling
+//! time and the depth of the buffer, hands every value to a sink and reports the sample rate it
+//! sustained and the values the device lost:
+//! ```no_run
+//! # fn run(mut device: sfc5xxx_rs::device::Device<sfc_core::discovery::NativePort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//!
+//! use sfc5xxx_rs::capture::{BufferedCapture, ThroughputConfig};
+//! use sfc5xxx_rs::scaling::Scale;
+//!
+//! let mut values = Vec::with_capacity(10_000);
+//! let capture = BufferedCapture::new(ThroughputConfig::default(), Scale::PhysicalValue);
+//! let report = capture.run(&mut device, Duration::from_secs(10), |read| values.extend_from_slice(read))?;
+//! println!("{:.0} samples/s, {} lost", report.samples_per_second(), report.lost_values);
+//! # Ok(())
+//! # }
+//! ```
+//! A poll reads the buffer until it is empty, with no more reads than the buffer can need, and
+//! allocates nothing: the values are decoded where the response was received and the sink gets
+//! them as a slice. Values lost before the capture started are reported by its first read, read
+//! the buffer once before to only count the ones lost while capturing.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc_core::error::DeviceError;
+use sfc_core::transport::Transport;
+
+use crate::device::{Device, MAX_BUFFERED_VALUES};
+use crate::scaling::Scale;
+
+/// How often the buffer is read, see the [module documentation](self)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThroughputConfig {
+    /// How many values the buffer of the device holds
+    pub buffer_depth: u32,
+    /// How full the buffer may get between two polls, as a fraction of `buffer_depth`. The rest
+    /// is the headroom for a poll that runs late.
+    pub fill: f32,
+}
+
+impl ThroughputConfig {
+    /// The time between two polls at this sampling time in seconds: the time it takes to
+    /// sample `fill` of the buffer, or as many values as a single read returns if that is less,
+    /// but at least one value
+    pub fn poll_interval(&self, sampling_time: f32) -> Duration {
+        let values = (self.buffer_depth as f32 * self.fill).clamp(1.0, MAX_BUFFERED_VALUES as f32);
+        // whole nanoseconds keep round sampling times exact
+        let sampling_time = (f64::from(sampling_time.max(0.0)) * 1e9).round() as u64;
+        Duration::from_nanos(sampling_time) * values as u32
+    }
+
+    /// The most reads a poll makes, enough to empty a full buffer and one more for the values
+    /// sampled meanwhile
+    fn reads_per_poll(&self) -> u32 {
+        self.buffer_depth.div_ceil(MAX_BUFFERED_VALUES as u32) + 1
+    }
+}
+
+impl Default for ThroughputConfig {
+    /// A buffer of 100 values, read when half full
+    fn default() -> Self {
+        Self {
+            buffer_depth: 100,
+            fill: 0.5,
+        }
+    }
+}
+
+/// What a [BufferedCapture] read so far
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CaptureReport {
+    /// The values handed to the sink
+    pub samples: u64,
+    /// The values the device reported as lost because its buffer was full
+    pub lost_values: u64,
+    /// The buffered reads sent
+    pub reads: u64,
+    /// The time from the start of the capture to its latest poll
+    pub elapsed: Duration,
+    /// The sampling time in seconds the device reported, 0 before the first read
+    pub sampling_time: f32,
+}
+
+impl CaptureReport {
+    /// The samples read per second of the capture, 0 before any time passed
+    pub fn samples_per_second(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.samples as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The samples per second the device takes at its sampling time, 0 before the first read
+    pub fn nominal_samples_per_second(&self) -> f64 {
+        if self.sampling_time <= 0.0 {
+            return 0.0;
+        }
+        1.0 / f64::from(self.sampling_time)
+    }
+
+    /// Returns true if the device lost no values
+    pub fn is_lossless(&self) -> bool {
+        self.lost_values == 0
+    }
+}
+
+/// Reads the measurement buffer of a device at a steady cadence, see the
+/// [module documentation](self)
+#[derive(Clone, Debug)]
+pub struct BufferedCapture {
+    config: ThroughputConfig,
+    scale: Scale,
+    report: CaptureReport,
+}
+
+impl BufferedCapture {
+    pub fn new(config: ThroughputConfig, scale: Scale) -> Self {
+        Self {
+            config,
+            scale,
+            report: CaptureReport::default(),
+        }
+    }
+
+    /// Reads the buffer until it is empty, hands the values to `sink` and returns how long to
+    /// wait before the next poll. `elapsed` is the time since the capture started by the clock
+    /// the caller waits with. An error ends the poll, the values read before it are counted.
+    pub fn poll<T: Transport>(
+        &mut self,
+        device: &mut Device<T>,
+        elapsed: Duration,
+        mut sink: impl FnMut(&[f32]),
+    ) -> Result<Duration, DeviceError> {
+        self.report.elapsed = elapsed;
+        for _ in 0..self.config.reads_per_poll() {
+            let read = device.read_measured_flow_buffered(self.scale)?;
+            self.report.reads += 1;
+            self.report.samples += read.values.len() as u64;
+            self.report.lost_values += u64::from(read.lost_values);
+            self.report.sampling_time = read.sampling_time;
+            sink(&read.values);
+            if read.remaning_values == 0 {
+                break;
+            }
+        }
+        Ok(self.config.poll_interval(self.report.sampling_time))
+    }
+
+    /// What the capture read so far
+    pub fn report(&self) -> &CaptureReport {
+        &self.report
+    }
+
+    /// Polls the device for `duration` and returns the report. The polls keep to their
+    /// cadence, one that runs late moves the later ones instead of polling back to back.
+    pub fn run<T: Transport>(
+        mut self,
+        device: &mut Device<T>,
+        duration: Duration,
+        mut sink: impl FnMut(&[f32]),
+    ) -> Result<CaptureReport, DeviceError> {
+        let start = Instant::now();
+        let mut next = start;
+        loop {
+            let interval = self.poll(device, start.elapsed(), &mut sink)?;
+            if start.elapsed() >= duration {
+                return Ok(self.report);
+            }
+            next += interval;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                next = now;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorConfig, Sfc5xxxEmulator};
+
+    #[test]
+    fn cadence_from_sampling_time_and_depth() {
+        let config = ThroughputConfig::default();
+        assert_eq!(config.poll_interval(0.001), Duration::from_millis(50));
+        // never more values than a single read returns
+        let deep = ThroughputConfig {
+            buffer_depth: 1000,
+            fill: 0.5,
+        };
+        assert_eq!(deep.poll_interval(0.001), Duration::from_millis(MAX_BUFFERED_VALUES as u64));
+        assert_eq!(deep.reads_per_poll(), 18);
+        // and at least one
+        let shallow = ThroughputConfig {
+            buffer_depth: 1,
+            fill: 0.5,
+        };
+        assert_eq!(shallow.poll_interval(0.01), Duration::from_millis(10));
+        assert_eq!(config.poll_interval(0.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn a_poll_empties_the_buffer() {
+        let emulator = Sfc5xxxEmulator::new(EmulatorConfig {
+            buffer_depth: 150,
+            ..Default::default()
+        });
+        let handle = emulator.handle();
+        let mut device = Device::new(emulator, 0).unwrap();
+        let config = ThroughputConfig {
+            buffer_depth: 150,
+            fill: 0.5,
+        };
+        let mut capture = BufferedCapture::new(config, Scale::Normilized);
+
+        handle.advance(Duration::from_millis(200));
+        let mut values = 0;
+        let wait = capture.poll(&mut device, Duration::from_millis(200), |read| values += read.len()).unwrap();
+        assert_eq!(wait, Duration::from_millis(60));
+        assert_eq!(handle.buffered_samples(), 0);
+        let report = *capture.report();
+        assert_eq!((report.samples, report.reads, report.lost_values), (150, 3, 50));
+        assert_eq!(values, 150);
+        assert_eq!(report.samples_per_second(), 750.0);
+        assert!((report.nominal_samples_per_second() - 1000.0).abs() < 1e-3);
+        assert!(!report.is_lossless());
+    }
+}
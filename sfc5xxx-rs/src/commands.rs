@@ -0,0 +1,868 @@
+//! Every command of the SFC5xxx as a [Command], for tooling that needs to know what the device
+//! understands without a [Device](crate::device::Device) at hand:
+//! ```
+//! use sfc5xxx_rs::commands::Command;
+//! use sfc5xxx_rs::scaling::Scale;
+//!
+//! let command = Command::ReadMeasuredValue { scale: Scale::PhysicalValue };
+//! assert_eq!(command.name(), "read measured value");
+//! let frame = command.encode(0).unwrap();
+//! assert_eq!(frame.get_command_number(), 0x08);
+//! ```
+//! The [Device](crate::device::Device) builds every frame it sends from a [Command].
+
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::GasUnit;
+use sfc_core::names::{DeviceFamily, command_name};
+use sfc_core::shdlc::{
+    CommandKind, Idempotency, MOSIFrame, ResponseLength, TranslationError, nul_terminated,
+};
+
+use crate::device::MAX_BUFFERED_VALUES;
+use crate::scaling::Scale;
+use crate::valve_config::InputSourceConfig;
+
+/// A command of the SFC5xxx with its arguments. Calibrations are picked by their index, values
+/// are read and written in the given [Scale].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    GetSetpoint {
+        scale: Scale,
+    },
+    /// The bits of the setpoint, an `f32` for [Scale::PhysicalValue]
+    SetSetpoint {
+        scale: Scale,
+        value: u32,
+    },
+    GetSetpointPersistence,
+    SetSetpointPersistence {
+        persist: bool,
+    },
+    SetSetpointAndReadMeasuredValue {
+        scale: Scale,
+        value: f32,
+    },
+    SetSetpointAndReadMeasuredValueTwoSensors {
+        scale: Scale,
+        value: f32,
+    },
+    ReadMeasuredValue {
+        scale: Scale,
+    },
+    /// Takes the buffered values off the device
+    ReadMeasuredValueBuffer {
+        scale: Scale,
+    },
+    ReadMeasuredValueTwoSensors {
+        scale: Scale,
+    },
+    GetValveInputSource,
+    /// Only the kind of source, the value of [InputSourceConfig::UserDefined] is set with
+    /// [Command::SetUserValveValue]
+    SetValveInputSource {
+        source: InputSourceConfig,
+    },
+    GetUserValveValue,
+    SetUserValveValue {
+        value: f32,
+    },
+    GetMediumUnit {
+        include_wild_cards: bool,
+    },
+    SetMediumUnit {
+        unit: GasUnit,
+    },
+    GetConvertedFullScale,
+    GetControllerGain,
+    SetControllerGain {
+        gain: f32,
+    },
+    GetPressureDependentGain,
+    SetPressureDependentGain {
+        enabled: bool,
+    },
+    /// The inlet pressure in bar
+    GetInletPressure,
+    SetInletPressure {
+        pressure: f32,
+    },
+    GetGasTemperatureCompensation,
+    SetGasTemperatureCompensation {
+        enabled: bool,
+    },
+    GetInletTemperature,
+    SetInletTemperature {
+        temperature: f32,
+    },
+    MeasureRawFlow,
+    MeasureRawThermalConductivity {
+        valve_closed: bool,
+    },
+    MeasureTemperature,
+    GetNumberOfCalibrations,
+    GetCalibrationValidity {
+        index: u32,
+    },
+    GetCalibrationGasDescription {
+        index: u32,
+    },
+    GetCalibrationGasId {
+        index: u32,
+    },
+    GetCalibrationGasUnit {
+        index: u32,
+    },
+    GetCalibrationFullScale {
+        index: u32,
+    },
+    GetCalibrationInitialConditions {
+        index: u32,
+    },
+    GetCalibrationRecalibrationConditions {
+        index: u32,
+    },
+    GetCalibrationThermalConductivityReference {
+        index: u32,
+    },
+    GetCurrentGasDescription,
+    GetCurrentGasId,
+    GetCurrentGasUnit,
+    GetCurrentFullScale,
+    GetCurrentInitialConditions,
+    GetCurrentRecalibrationConditions,
+    GetCurrentThermalConductivityReference,
+    /// Takes effect after the next reset
+    SetCalibration {
+        index: u32,
+    },
+    ReadUserMemory {
+        start_address: u8,
+        length: u8,
+    },
+    WriteUserMemory {
+        start_address: u8,
+        data: Vec<u8>,
+    },
+    GetSlaveAddress,
+    SetSlaveAddress {
+        address: u8,
+    },
+    GetBaudrate,
+    SetBaudrate {
+        baudrate: u32,
+    },
+    FactoryReset,
+    GetProductName,
+    GetArticleCode,
+    GetSerialNumber,
+    GetVersion,
+    GetDeviceErrorState {
+        clear: bool,
+    },
+    DeviceReset,
+}
+
+impl Command {
+    /// The command byte of the frame
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::GetSetpoint { .. } | Self::SetSetpoint { .. } => 0x00,
+            Self::GetSetpointPersistence | Self::SetSetpointPersistence { .. } => 0x02,
+            Self::SetSetpointAndReadMeasuredValue { .. } => 0x03,
+            Self::SetSetpointAndReadMeasuredValueTwoSensors { .. } => 0x04,
+            Self::ReadMeasuredValue { .. } => 0x08,
+            Self::ReadMeasuredValueBuffer { .. } => 0x09,
+            Self::ReadMeasuredValueTwoSensors { .. } => 0x0A,
+            Self::GetValveInputSource
+            | Self::SetValveInputSource { .. }
+            | Self::GetUserValveValue
+            | Self::SetUserValveValue { .. } => 0x20,
+            Self::GetMediumUnit { .. }
+            | Self::SetMediumUnit { .. }
+            | Self::GetConvertedFullScale => 0x21,
+            Self::GetControllerGain
+            | Self::SetControllerGain { .. }
+            | Self::GetPressureDependentGain
+            | Self::SetPressureDependentGain { .. }
+            | Self::GetInletPressure
+            | Self::SetInletPressure { .. }
+            | Self::GetGasTemperatureCompensation
+            | Self::SetGasTemperatureCompensation { .. }
+            | Self::GetInletTemperature
+            | Self::SetInletTemperature { .. } => 0x22,
+            Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity { .. }
+            | Self::MeasureTemperature => 0x30,
+            Self::GetNumberOfCalibrations
+            | Self::GetCalibrationValidity { .. }
+            | Self::GetCalibrationGasDescription { .. }
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationGasUnit { .. }
+            | Self::GetCalibrationFullScale { .. }
+            | Self::GetCalibrationInitialConditions { .. }
+            | Self::GetCalibrationRecalibrationConditions { .. }
+            | Self::GetCalibrationThermalConductivityReference { .. } => 0x40,
+            Self::GetCurrentGasDescription
+            | Self::GetCurrentGasId
+            | Self::GetCurrentGasUnit
+            | Self::GetCurrentFullScale
+            | Self::GetCurrentInitialConditions
+            | Self::GetCurrentRecalibrationConditions
+            | Self::GetCurrentThermalConductivityReference => 0x44,
+            Self::SetCalibration { .. } => 0x45,
+            Self::ReadUserMemory { .. } | Self::WriteUserMemory { .. } => 0x6E,
+            Self::GetSlaveAddress | Self::SetSlaveAddress { .. } => 0x90,
+            Self::GetBaudrate | Self::SetBaudrate { .. } => 0x91,
+            Self::FactoryReset => 0x92,
+            Self::GetProductName | Self::GetArticleCode | Self::GetSerialNumber => 0xD0,
+            Self::GetVersion => 0xD1,
+            Self::GetDeviceErrorState { .. } => 0xD2,
+            Self::DeviceReset => 0xD3,
+        }
+    }
+
+    /// What the command makes the device do, which decides its response timeout. The SFC5xxx has
+    /// no averaging command, none of its commands is a [CommandKind::AveragedMeasurement].
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::SetSetpointAndReadMeasuredValueTwoSensors { .. }
+            | Self::ReadMeasuredValue { .. }
+            | Self::ReadMeasuredValueTwoSensors { .. }
+            | Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity { .. }
+            | Self::MeasureTemperature => CommandKind::Measurement,
+            Self::SetCalibration { .. } | Self::FactoryReset | Self::DeviceReset => CommandKind::Reset,
+            _ => CommandKind::Query,
+        }
+    }
+
+    /// Whether the command may be sent again when its response got lost. Writing a value is
+    /// idempotent, taking values off the buffer, clearing the error state, a new address or
+    /// baudrate, switching the calibration and the resets are not.
+    pub fn idempotency(&self) -> Idempotency {
+        // every command is listed so a new one has to be classified
+        match self {
+            Self::GetSetpoint { .. }
+            | Self::SetSetpoint { .. }
+            | Self::GetSetpointPersistence
+            | Self::SetSetpointPersistence { .. }
+            | Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::SetSetpointAndReadMeasuredValueTwoSensors { .. }
+            | Self::ReadMeasuredValue { .. }
+            | Self::ReadMeasuredValueTwoSensors { .. }
+            | Self::GetValveInputSource
+            | Self::SetValveInputSource { .. }
+            | Self::GetUserValveValue
+            | Self::SetUserValveValue { .. }
+            | Self::GetMediumUnit { .. }
+            | Self::SetMediumUnit { .. }
+            | Self::GetConvertedFullScale
+            | Self::GetControllerGain
+            | Self::SetControllerGain { .. }
+            | Self::GetPressureDependentGain
+            | Self::SetPressureDependentGain { .. }
+            | Self::GetInletPressure
+            | Self::SetInletPressure { .. }
+            | Self::GetGasTemperatureCompensation
+            | Self::SetGasTemperatureCompensation { .. }
+            | Self::GetInletTemperature
+            | Self::SetInletTemperature { .. }
+            | Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity { .. }
+            | Self::MeasureTemperature
+            | Self::GetNumberOfCalibrations
+            | Self::GetCalibrationValidity { .. }
+            | Self::GetCalibrationGasDescription { .. }
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationGasUnit { .. }
+            | Self::GetCalibrationFullScale { .. }
+            | Self::GetCalibrationInitialConditions { .. }
+            | Self::GetCalibrationRecalibrationConditions { .. }
+            | Self::GetCalibrationThermalConductivityReference { .. }
+            | Self::GetCurrentGasDescription
+            | Self::GetCurrentGasId
+            | Self::GetCurrentGasUnit
+            | Self::GetCurrentFullScale
+            | Self::GetCurrentInitialConditions
+            | Self::GetCurrentRecalibrationConditions
+            | Self::GetCurrentThermalConductivityReference
+            | Self::ReadUserMemory { .. }
+            | Self::WriteUserMemory { .. }
+            | Self::GetSlaveAddress
+            | Self::GetBaudrate
+            | Self::GetProductName
+            | Self::GetArticleCode
+            | Self::GetSerialNumber
+            | Self::GetVersion
+            | Self::GetDeviceErrorState { clear: false } => Idempotency::Idempotent,
+            Self::ReadMeasuredValueBuffer { .. }
+            | Self::SetCalibration { .. }
+            | Self::SetSlaveAddress { .. }
+            | Self::SetBaudrate { .. }
+            | Self::FactoryReset
+            | Self::GetDeviceErrorState { clear: true }
+            | Self::DeviceReset => Idempotency::NonIdempotent,
+        }
+    }
+
+    /// Whether the command changes the device: the setpoint, a setting, the valve, the
+    /// calibration, the user memory, the address or baudrate, or its state with a reset or by
+    /// clearing the error state. Measuring the thermal conductivity with the valve closed closes
+    /// it. A read-only device refuses these.
+    pub fn writes(&self) -> bool {
+        // every command is listed so a new one has to be classified
+        match self {
+            Self::SetSetpoint { .. }
+            | Self::SetSetpointPersistence { .. }
+            | Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::SetSetpointAndReadMeasuredValueTwoSensors { .. }
+            | Self::SetValveInputSource { .. }
+            | Self::SetUserValveValue { .. }
+            | Self::SetMediumUnit { .. }
+            | Self::SetControllerGain { .. }
+            | Self::SetPressureDependentGain { .. }
+            | Self::SetInletPressure { .. }
+            | Self::SetGasTemperatureCompensation { .. }
+            | Self::SetInletTemperature { .. }
+            | Self::MeasureRawThermalConductivity { valve_closed: true }
+            | Self::SetCalibration { .. }
+            | Self::WriteUserMemory { .. }
+            | Self::SetSlaveAddress { .. }
+            | Self::SetBaudrate { .. }
+            | Self::FactoryReset
+            | Self::GetDeviceErrorState { clear: true }
+            | Self::DeviceReset => true,
+            Self::GetSetpoint { .. }
+            | Self::GetSetpointPersistence
+            | Self::ReadMeasuredValue { .. }
+            | Self::ReadMeasuredValueBuffer { .. }
+            | Self::ReadMeasuredValueTwoSensors { .. }
+            | Self::GetValveInputSource
+            | Self::GetUserValveValue
+            | Self::GetMediumUnit { .. }
+            | Self::GetConvertedFullScale
+            | Self::GetControllerGain
+            | Self::GetPressureDependentGain
+            | Self::GetInletPressure
+            | Self::GetGasTemperatureCompensation
+            | Self::GetInletTemperature
+            | Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity { valve_closed: false }
+            | Self::MeasureTemperature
+            | Self::GetNumberOfCalibrations
+            | Self::GetCalibrationValidity { .. }
+            | Self::GetCalibrationGasDescription { .. }
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationGasUnit { .. }
+            | Self::GetCalibrationFullScale { .. }
+            | Self::GetCalibrationInitialConditions { .. }
+            | Self::GetCalibrationRecalibrationConditions { .. }
+            | Self::GetCalibrationThermalConductivityReference { .. }
+            | Self::GetCurrentGasDescription
+            | Self::GetCurrentGasId
+            | Self::GetCurrentGasUnit
+            | Self::GetCurrentFullScale
+            | Self::GetCurrentInitialConditions
+            | Self::GetCurrentRecalibrationConditions
+            | Self::GetCurrentThermalConductivityReference
+            | Self::ReadUserMemory { .. }
+            | Self::GetSlaveAddress
+            | Self::GetBaudrate
+            | Self::GetProductName
+            | Self::GetArticleCode
+            | Self::GetSerialNumber
+            | Self::GetVersion
+            | Self::GetDeviceErrorState { clear: false } => false,
+        }
+    }
+
+    /// How much data the device answers with, checked before the response is decoded. Strings
+    /// are NUL terminated, a buffered read is a 12 byte header and up to 60 values.
+    pub fn response_length(&self) -> ResponseLength {
+        use ResponseLength::Exact;
+        const STRING: ResponseLength = ResponseLength::Variable {
+            min: 1,
+            validate: nul_terminated,
+        };
+        // every command is listed so a new one has to declare its response
+        match self {
+            Self::SetSetpoint { .. }
+            | Self::SetSetpointPersistence { .. }
+            | Self::SetValveInputSource { .. }
+            | Self::SetUserValveValue { .. }
+            | Self::SetMediumUnit { .. }
+            | Self::SetControllerGain { .. }
+            | Self::SetPressureDependentGain { .. }
+            | Self::SetInletPressure { .. }
+            | Self::SetGasTemperatureCompensation { .. }
+            | Self::SetInletTemperature { .. }
+            | Self::SetCalibration { .. }
+            | Self::WriteUserMemory { .. }
+            | Self::SetSlaveAddress { .. }
+            | Self::SetBaudrate { .. }
+            | Self::FactoryReset
+            | Self::DeviceReset => Exact(0),
+            Self::GetSetpointPersistence
+            | Self::GetValveInputSource
+            | Self::GetPressureDependentGain
+            | Self::GetGasTemperatureCompensation
+            | Self::GetCalibrationValidity { .. }
+            | Self::GetSlaveAddress => Exact(1),
+            Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity { .. }
+            | Self::GetCalibrationThermalConductivityReference { .. }
+            | Self::GetCurrentThermalConductivityReference => Exact(2),
+            Self::GetMediumUnit { .. }
+            | Self::GetCalibrationGasUnit { .. }
+            | Self::GetCurrentGasUnit => Exact(3),
+            Self::GetSetpoint { .. }
+            | Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::ReadMeasuredValue { .. }
+            | Self::GetUserValveValue
+            | Self::GetConvertedFullScale
+            | Self::GetControllerGain
+            | Self::GetInletPressure
+            | Self::GetInletTemperature
+            | Self::MeasureTemperature
+            | Self::GetNumberOfCalibrations
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationFullScale { .. }
+            | Self::GetCurrentGasId
+            | Self::GetCurrentFullScale
+            | Self::GetBaudrate => Exact(4),
+            Self::GetDeviceErrorState { .. } => Exact(5),
+            Self::GetVersion => Exact(7),
+            Self::SetSetpointAndReadMeasuredValueTwoSensors { .. }
+            | Self::ReadMeasuredValueTwoSensors { .. } => Exact(8),
+            Self::GetCalibrationInitialConditions { .. }
+            | Self::GetCalibrationRecalibrationConditions { .. }
+            | Self::GetCurrentInitialConditions
+            | Self::GetCurrentRecalibrationConditions => Exact(127),
+            Self::ReadUserMemory { length, .. } => Exact(*length),
+            Self::ReadMeasuredValueBuffer { .. } => ResponseLength::Variable {
+                min: 12,
+                validate: whole_values,
+            },
+            Self::GetCalibrationGasDescription { .. }
+            | Self::GetCurrentGasDescription
+            | Self::GetProductName
+            | Self::GetArticleCode
+            | Self::GetSerialNumber => STRING,
+        }
+    }
+
+    /// A short name for logs and transcripts, like `read measured value`, the [command_name] of the
+    /// command and the subcommand it is sent with
+    pub fn name(&self) -> &'static str {
+        let subcommand = self.data().first().copied();
+        command_name(DeviceFamily::Sfc5xxx, self.code(), subcommand).unwrap_or("unknown command")
+    }
+
+    /// The frame that sends the command to the device at `address`
+    pub fn encode(&self, address: u8) -> Result<MOSIFrame, TranslationError> {
+        let frame = MOSIFrame::new(address, self.code(), &self.data())?;
+        Ok(frame
+            .with_kind(self.kind())
+            .with_idempotency(self.idempotency())
+            .with_family(DeviceFamily::Sfc5xxx))
+    }
+
+    fn data(&self) -> Vec<u8> {
+        match self {
+            Self::GetSetpoint { scale }
+            | Self::ReadMeasuredValue { scale }
+            | Self::ReadMeasuredValueBuffer { scale }
+            | Self::ReadMeasuredValueTwoSensors { scale } => vec![*scale as u8],
+            Self::SetSetpoint { scale, value } => with_value(*scale as u8, value.to_be_bytes()),
+            Self::GetSetpointPersistence => vec![0x00],
+            Self::SetSetpointPersistence { persist } => vec![0x00, *persist as u8],
+            Self::SetSetpointAndReadMeasuredValue { scale, value }
+            | Self::SetSetpointAndReadMeasuredValueTwoSensors { scale, value } => {
+                with_value(*scale as u8, value.to_be_bytes())
+            }
+            Self::GetValveInputSource => vec![0x00],
+            Self::SetValveInputSource { source } => vec![0x00, (*source).into()],
+            Self::GetUserValveValue => vec![0x01],
+            Self::SetUserValveValue { value } => with_value(0x01, value.to_be_bytes()),
+            Self::GetMediumUnit { include_wild_cards } => vec![*include_wild_cards as u8],
+            Self::SetMediumUnit { unit } => vec![
+                0x00,
+                Into::<i8>::into(unit.unit_prefex).to_le_bytes()[0],
+                unit.medium_unit.into(),
+                unit.timebase.into(),
+            ],
+            Self::GetConvertedFullScale => vec![0x0A],
+            Self::GetControllerGain => vec![0x00],
+            Self::SetControllerGain { gain } => with_value(0x00, gain.to_be_bytes()),
+            Self::GetPressureDependentGain => vec![0x10],
+            Self::SetPressureDependentGain { enabled } => vec![0x10, *enabled as u8],
+            Self::GetInletPressure => vec![0x11],
+            Self::SetInletPressure { pressure } => with_value(0x11, pressure.to_be_bytes()),
+            Self::GetGasTemperatureCompensation => vec![0x20],
+            Self::SetGasTemperatureCompensation { enabled } => vec![0x20, *enabled as u8],
+            Self::GetInletTemperature => vec![0x21],
+            Self::SetInletTemperature { temperature } => {
+                with_value(0x21, temperature.to_be_bytes())
+            }
+            Self::MeasureRawFlow => vec![0x00],
+            Self::MeasureRawThermalConductivity { valve_closed: true } => vec![0x01],
+            Self::MeasureRawThermalConductivity {
+                valve_closed: false,
+            } => vec![0x02],
+            Self::MeasureTemperature => vec![0x10],
+            Self::GetNumberOfCalibrations => vec![0x00],
+            Self::GetCalibrationValidity { index } => with_value(0x10, index.to_be_bytes()),
+            Self::GetCalibrationGasDescription { index } => with_value(0x11, index.to_be_bytes()),
+            Self::GetCalibrationGasId { index } => with_value(0x12, index.to_be_bytes()),
+            Self::GetCalibrationGasUnit { index } => with_value(0x13, index.to_be_bytes()),
+            Self::GetCalibrationFullScale { index } => with_value(0x14, index.to_be_bytes()),
+            Self::GetCalibrationInitialConditions { index } => {
+                with_value(0x15, index.to_be_bytes())
+            }
+            Self::GetCalibrationRecalibrationConditions { index } => {
+                with_value(0x16, index.to_be_bytes())
+            }
+            Self::GetCalibrationThermalConductivityReference { index } => {
+                with_value(0x17, index.to_be_bytes())
+            }
+            Self::GetCurrentGasDescription => vec![0x11],
+            Self::GetCurrentGasId => vec![0x12],
+            Self::GetCurrentGasUnit => vec![0x13],
+            Self::GetCurrentFullScale => vec![0x14],
+            Self::GetCurrentInitialConditions => vec![0x15],
+            Self::GetCurrentRecalibrationConditions => vec![0x16],
+            Self::GetCurrentThermalConductivityReference => vec![0x17],
+            Self::SetCalibration { index } => index.to_be_bytes().to_vec(),
+            Self::ReadUserMemory {
+                start_address,
+                length,
+            } => vec![*start_address, *length],
+            Self::WriteUserMemory {
+                start_address,
+                data,
+            } => [&[*start_address, data.len() as u8], &data[..]].concat(),
+            Self::SetSlaveAddress { address } => vec![*address],
+            Self::SetBaudrate { baudrate } => baudrate.to_be_bytes().to_vec(),
+            Self::GetProductName => vec![0x01],
+            Self::GetArticleCode => vec![0x02],
+            Self::GetSerialNumber => vec![0x03],
+            Self::GetDeviceErrorState { clear } => vec![*clear as u8],
+            Self::GetSlaveAddress
+            | Self::GetBaudrate
+            | Self::FactoryReset
+            | Self::GetVersion
+            | Self::DeviceReset => Vec::new(),
+        }
+    }
+}
+
+/// The validator of a buffered read: the values after the header are 4 bytes each
+fn whole_values(data: &[u8]) -> Result<(), DeviceError> {
+    const MAX: usize = 12 + 4 * MAX_BUFFERED_VALUES;
+    match (data.len(), data.len() % 4) {
+        (found, _) if found > MAX => {
+            Err(TranslationError::TooMuchData(MAX as u8, found as u8))?
+        }
+        (_, 0) => Ok(()),
+        // the last value was cut short
+        (found, cut) => {
+            Err(TranslationError::NotEnoughData((found + 4 - cut) as u8, found as u8))?
+        }
+    }
+}
+
+/// A sub command followed by a big endian value
+fn with_value(sub_command: u8, value: [u8; 4]) -> Vec<u8> {
+    vec![sub_command, value[0], value[1], value[2], value[3]]
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use sfc_core::gasunit::{Prefixes, TimeBases, Units};
+
+    /// Every command with the command byte and data the device methods sent before they were
+    /// built from [Command]
+    pub(crate) fn catalog() -> Vec<(Command, u8, Vec<u8>)> {
+        let value = 2.5_f32.to_be_bytes();
+        let with = |sub: u8| [&[sub], &value[..]].concat();
+        let index = |sub: u8| vec![sub, 0, 0, 0, 2];
+        let physical = Scale::PhysicalValue;
+        let unit = GasUnit {
+            unit_prefex: Prefixes::Milli,
+            medium_unit: Units::StandardLiter,
+            timebase: TimeBases::Minute,
+        };
+        vec![
+            (
+                Command::GetSetpoint {
+                    scale: Scale::Normilized,
+                },
+                0x00,
+                vec![0x00],
+            ),
+            (
+                Command::SetSetpoint {
+                    scale: physical,
+                    value: 2.5_f32.to_bits(),
+                },
+                0x00,
+                with(0x01),
+            ),
+            (Command::GetSetpointPersistence, 0x02, vec![0x00]),
+            (
+                Command::SetSetpointPersistence { persist: true },
+                0x02,
+                vec![0x00, 0x01],
+            ),
+            (
+                Command::SetSetpointAndReadMeasuredValue {
+                    scale: physical,
+                    value: 2.5,
+                },
+                0x03,
+                with(0x01),
+            ),
+            (
+                Command::SetSetpointAndReadMeasuredValueTwoSensors {
+                    scale: Scale::UserDefined,
+                    value: 2.5,
+                },
+                0x04,
+                with(0x02),
+            ),
+            (
+                Command::ReadMeasuredValue { scale: physical },
+                0x08,
+                vec![0x01],
+            ),
+            (
+                Command::ReadMeasuredValueBuffer { scale: physical },
+                0x09,
+                vec![0x01],
+            ),
+            (
+                Command::ReadMeasuredValueTwoSensors { scale: physical },
+                0x0A,
+                vec![0x01],
+            ),
+            (Command::GetValveInputSource, 0x20, vec![0x00]),
+            (
+                Command::SetValveInputSource {
+                    source: InputSourceConfig::UserDefined(2.5),
+                },
+                0x20,
+                vec![0x00, 0x10],
+            ),
+            (Command::GetUserValveValue, 0x20, vec![0x01]),
+            (Command::SetUserValveValue { value: 2.5 }, 0x20, with(0x01)),
+            (
+                Command::GetMediumUnit {
+                    include_wild_cards: true,
+                },
+                0x21,
+                vec![0x01],
+            ),
+            (
+                Command::SetMediumUnit { unit },
+                0x21,
+                vec![0x00, 0xFD, 0x01, 0x04],
+            ),
+            (Command::GetConvertedFullScale, 0x21, vec![0x0A]),
+            (Command::GetControllerGain, 0x22, vec![0x00]),
+            (Command::SetControllerGain { gain: 2.5 }, 0x22, with(0x00)),
+            (Command::GetPressureDependentGain, 0x22, vec![0x10]),
+            (
+                Command::SetPressureDependentGain { enabled: true },
+                0x22,
+                vec![0x10, 0x01],
+            ),
+            (Command::GetInletPressure, 0x22, vec![0x11]),
+            (
+                Command::SetInletPressure { pressure: 2.5 },
+                0x22,
+                with(0x11),
+            ),
+            (Command::GetGasTemperatureCompensation, 0x22, vec![0x20]),
+            (
+                Command::SetGasTemperatureCompensation { enabled: false },
+                0x22,
+                vec![0x20, 0x00],
+            ),
+            (Command::GetInletTemperature, 0x22, vec![0x21]),
+            (
+                Command::SetInletTemperature { temperature: 2.5 },
+                0x22,
+                with(0x21),
+            ),
+            (Command::MeasureRawFlow, 0x30, vec![0x00]),
+            (
+                Command::MeasureRawThermalConductivity { valve_closed: true },
+                0x30,
+                vec![0x01],
+            ),
+            (Command::MeasureTemperature, 0x30, vec![0x10]),
+            (Command::GetNumberOfCalibrations, 0x40, vec![0x00]),
+            (
+                Command::GetCalibrationValidity { index: 2 },
+                0x40,
+                index(0x10),
+            ),
+            (
+                Command::GetCalibrationGasDescription { index: 2 },
+                0x40,
+                index(0x11),
+            ),
+            (Command::GetCalibrationGasId { index: 2 }, 0x40, index(0x12)),
+            (
+                Command::GetCalibrationGasUnit { index: 2 },
+                0x40,
+                index(0x13),
+            ),
+            (
+                Command::GetCalibrationFullScale { index: 2 },
+                0x40,
+                index(0x14),
+            ),
+            (
+                Command::GetCalibrationInitialConditions { index: 2 },
+                0x40,
+                index(0x15),
+            ),
+            (
+                Command::GetCalibrationRecalibrationConditions { index: 2 },
+                0x40,
+                index(0x16),
+            ),
+            (
+                Command::GetCalibrationThermalConductivityReference { index: 2 },
+                0x40,
+                index(0x17),
+            ),
+            (Command::GetCurrentGasDescription, 0x44, vec![0x11]),
+            (Command::GetCurrentGasId, 0x44, vec![0x12]),
+            (Command::GetCurrentGasUnit, 0x44, vec![0x13]),
+            (Command::GetCurrentFullScale, 0x44, vec![0x14]),
+            (Command::GetCurrentInitialConditions, 0x44, vec![0x15]),
+            (Command::GetCurrentRecalibrationConditions, 0x44, vec![0x16]),
+            (
+                Command::GetCurrentThermalConductivityReference,
+                0x44,
+                vec![0x17],
+            ),
+            (Command::SetCalibration { index: 2 }, 0x45, vec![0, 0, 0, 2]),
+            (
+                Command::ReadUserMemory {
+                    start_address: 4,
+                    length: 8,
+                },
+                0x6E,
+                vec![4, 8],
+            ),
+            // escaped bytes in the data
+            (
+                Command::WriteUserMemory {
+                    start_address: 4,
+                    data: vec![0x7E, 0x11],
+                },
+                0x6E,
+                vec![4, 2, 0x7E, 0x11],
+            ),
+            (Command::GetSlaveAddress, 0x90, vec![]),
+            (Command::SetSlaveAddress { address: 5 }, 0x90, vec![5]),
+            (Command::GetBaudrate, 0x91, vec![]),
+            (
+                Command::SetBaudrate { baudrate: 115200 },
+                0x91,
+                vec![0x00, 0x01, 0xC2, 0x00],
+            ),
+            (Command::FactoryReset, 0x92, vec![]),
+            (Command::GetProductName, 0xD0, vec![0x01]),
+            (Command::GetArticleCode, 0xD0, vec![0x02]),
+            (Command::GetSerialNumber, 0xD0, vec![0x03]),
+            (Command::GetVersion, 0xD1, vec![]),
+            (
+                Command::GetDeviceErrorState { clear: true },
+                0xD2,
+                vec![0x01],
+            ),
+            (Command::DeviceReset, 0xD3, vec![]),
+        ]
+    }
+
+    #[test]
+    fn encodes_the_frames_of_the_device() {
+        for (command, code, data) in catalog() {
+            let frame = command.encode(3).unwrap();
+            assert_eq!(frame.get_command_number(), code, "{}", command.name());
+            let expected = MOSIFrame::new(3, code, &data).unwrap();
+            assert_eq!(frame.into_raw(), expected.into_raw(), "{}", command.name());
+        }
+    }
+
+    #[test]
+    fn every_command_is_listed_once() {
+        let variants: std::collections::HashSet<_> = catalog()
+            .iter()
+            .map(|(command, ..)| std::mem::discriminant(command))
+            .collect();
+        assert_eq!(variants.len(), catalog().len());
+        // one entry for each variant
+        assert_eq!(variants.len(), 59);
+    }
+
+    #[test]
+    fn every_command_is_named_by_sfc_core() {
+        for (command, ..) in catalog() {
+            let frame = command.encode(0).unwrap();
+            assert_eq!(frame.family(), Some(DeviceFamily::Sfc5xxx));
+            assert_eq!(frame.name(), Some(command.name()), "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn only_commands_that_change_the_device_for_good_are_not_idempotent() {
+        let not_idempotent: Vec<_> = catalog()
+            .iter()
+            .map(|(command, ..)| command)
+            .filter(|command| command.idempotency() == Idempotency::NonIdempotent)
+            .map(Command::name)
+            .collect();
+        assert_eq!(
+            not_idempotent,
+            [
+                "read measured value buffer",
+                "calibration",
+                "slave address",
+                "baudrate",
+                "factory reset",
+                "device error state",
+                "device reset",
+            ]
+        );
+        let read = Command::GetDeviceErrorState { clear: false };
+        assert_eq!(read.idempotency(), Idempotency::Idempotent);
+        let frame = Command::DeviceReset.encode(0).unwrap();
+        assert_eq!(frame.idempotency(), Idempotency::NonIdempotent);
+    }
+
+    #[test]
+    fn buffered_reads_hold_whole_values() {
+        let length = Command::ReadMeasuredValueBuffer { scale: Scale::PhysicalValue }.response_length();
+        assert!(length.check(&[0; 12]).is_ok());
+        assert!(length.check(&[0; 12 + 4 * MAX_BUFFERED_VALUES]).is_ok());
+        assert!(matches!(
+            length.check(&[0; 8]),
+            Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(12, 8)))
+        ));
+        assert!(matches!(
+            length.check(&[0; 18]),
+            Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(20, 18)))
+        ));
+        assert!(matches!(
+            length.check(&[0; 16 + 4 * MAX_BUFFERED_VALUES]),
+            Err(DeviceError::ShdlcError(TranslationError::TooMuchData(_, _)))
+        ));
+    }
+}
@@ -1,99 +1,432 @@
 use arrayvec::ArrayVec;
-use serialport::SerialPort;
 
+use sfc_core::baudrate::Baudrate;
+use sfc_core::capabilities::{Capabilities, Feature};
 use sfc_core::gasunit::GasUnit;
-use sfc_core::shdlc::{MISOFrame, MOSIFrame, TranslationError, Version};
-use sfc_core::error::{DeviceError, StateResponseError};
-
-use std::ffi::CString;
-
+use sfc_core::shdlc::{MAX_PAYLOAD, MISOFrame, MISOFrameRef, MOSIFrame, TranslationError, Version, parse_string};
+use sfc_core::error::DeviceError;
+use sfc_core::config::{ConfigDiff, DeviceConfig, Setting, SkipReason};
+use sfc_core::flow_controller::FlowController;
+use sfc_core::health::{self, CheckOutcome, HealthReport, HealthRequirements};
+use sfc_core::identity::{IdentityField, IdentityFilter};
+use sfc_core::limits::{LimitPolicy, SoftLimits};
+use sfc_core::measurement::Measurement;
+use sfc_core::middleware::FrameMiddleware;
+use sfc_core::names::DeviceFamily;
+use sfc_core::flow_statistics::{FlowStatistics, RunningStatistics};
+use sfc_core::discovery::{
+    self, ConnectionProfile, DiscoveryPolicy, NativePort, ProfiledDevice, open_first_detected, open_port,
+};
+use sfc_core::bus::SharedBus;
+use sfc_core::connection::{
+    BootGrace, BusyPolicy, Connection, DEFAULT_INTER_BYTE_TIMEOUT, PendingCommand, RetryConfig,
+    Rs485Config, SpinPolicy, Timeouts, ValidationLevel,
+};
+use sfc_core::transport::Transport;
+use sfc_core::stats::CommStats;
+
+use std::time::{Duration, Instant};
+
+use crate::commands::Command;
 use crate::scaling::Scale;
 use crate::valve_config::InputSourceConfig;
 use crate::calibration::CalibrationCondition;
 
 macro_rules! simple_device_function {
-    ($name:ident, $ret_type:ty, $code:literal, $($data:literal),*) => {
+    ($name:ident, $ret_type:ty, $command:expr) => {
        pub fn $name(&mut self) -> Result<$ret_type, DeviceError> {
-           let frame = MOSIFrame::new(self.slave_address, $code, &[$($data,)*])?;
-           let _ = self.port.write(&frame.into_raw())?;
-           let data = self.read_response()?.into_data();
-
-           if data.len() < std::mem::size_of::<$ret_type>() {
-               return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(std::mem::size_of::<$ret_type>() as u8, data.len() as u8)));
-           }
-
-           let bytes_deffined: [u8; core::mem::size_of::<$ret_type>()] = core::array::from_fn(|i| data[i]);
-           Ok(<$ret_type>::from_be_bytes(bytes_deffined))
+           Ok(<$ret_type>::from_be_bytes(self.query($command)?))
        }
     };
 }
 
-pub struct Device<T: SerialPort> {
-    port: T,
+/// How long [Device::reset_device] waits for a busy sensor to take the reset
+const RESET_BUSY: BusyPolicy = BusyPolicy::new(Duration::from_millis(500), Duration::from_millis(20));
+/// How long [Device::factory_reset] waits for a busy sensor, it rewrites the stored settings
+const FACTORY_RESET_BUSY: BusyPolicy = BusyPolicy::new(Duration::from_secs(2), Duration::from_millis(50));
+/// How long [Device::set_callibration] waits for a busy sensor to finish its measurement
+const CALIBRATION_BUSY: BusyPolicy = BusyPolicy::new(Duration::from_secs(1), Duration::from_millis(20));
+
+/// One SFC5xxx on a [Transport], created with [Device::new], [Device::builder] or [Device::open].
+///
+/// # Locked configuration
+/// A device can be locked against changes of its stored configuration. It then answers the commands that change it,
+/// like the address, the baudrate, the calibration, the medium unit or the controller gain, with one of the access
+/// level states: 0x03 ([StateResponseError::NoAccessRight]), 0x20 ([StateResponseError::ConfigurationLocked]) or
+/// 0x21 ([StateResponseError::AccessLevelTooLow]). They fail the setter with their own error rather than a fatal
+/// one, so a setter that works on one unit but not on another points at the lock, and
+/// [DeviceError::is_configuration_locked] tells them apart from other errors. Measuring and setting the setpoint
+/// still work. The SFC5xxx has no command to read whether it is locked, the first refused change tells.
+///
+/// [StateResponseError::NoAccessRight]: sfc_core::error::StateResponseError::NoAccessRight
+/// [StateResponseError::ConfigurationLocked]: sfc_core::error::StateResponseError::ConfigurationLocked
+/// [StateResponseError::AccessLevelTooLow]: sfc_core::error::StateResponseError::AccessLevelTooLow
+pub struct Device<T: Transport> {
+    connection: Connection<T>,
     slave_address: u8,
+    /// the medium unit and serial number attached to recorded values, read with the first of them
+    metadata: Option<(GasUnit, String, f32)>,
+    /// what the firmware supports, derived from the version read for the first of them
+    capabilities: Option<Capabilities>,
+    soft_limits: SoftLimits,
+    read_only: bool,
+}
+
+/// Formats what the device knows without asking it: the address, the settings and counters of the connection,
+/// whether the metadata of recorded values is cached, the capabilities if they were read, the soft limits and
+/// whether it is read-only.
+impl<T: Transport> std::fmt::Debug for Device<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Device")
+            .field("slave_address", &self.slave_address)
+            .field("connection", &self.connection)
+            .field("metadata_cached", &self.metadata.is_some())
+            .field("capabilities", &self.capabilities)
+            .field("soft_limits", &self.soft_limits)
+            .field("read_only", &self.read_only)
+            .finish()
+    }
 }
 
 pub struct DeviceInformation;
 
-impl<T: SerialPort> Device<T> {
+impl<T: Transport> Device<T> {
     pub fn new(port: T, slave_address: u8) -> Result<Self, DeviceError> {
-        
-        Ok(Self {
+        Self::builder(port).address(slave_address).build()
+    }
+
+    /// Configures the device before creating it, for settings [Device::new] doesn't take:
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// use sfc5xxx_rs::device::Device;
+    /// use sfc_core::connection::RetryConfig;
+    /// let port = serialport::new("/dev/ttyUSB0", 115200).open_native().unwrap();
+    /// let device = Device::builder(port)
+    ///     .address(0)
+    ///     .timeout(Duration::from_millis(300))
+    ///     .retries(RetryConfig::default())
+    ///     .probe(true)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder(port: T) -> DeviceBuilder<T> {
+        DeviceBuilder {
             port,
+            address: 0,
+            timeouts: Timeouts::default(),
+            inter_byte_timeout: DEFAULT_INTER_BYTE_TIMEOUT,
+            clear_stale_input: true,
+            retry: None,
+            boot_grace: None,
+            busy_retry: None,
+            rs485: None,
+            spin: SpinPolicy::default(),
+            validation: ValidationLevel::default(),
+            baudrate: None,
+            probe: false,
+            identity: IdentityFilter::default(),
+            read_only: false,
+        }
+    }
+
+    /// Creates a device for one slave address on a [SharedBus] so several devices can share one
+    /// RS-485 line
+    pub fn on_bus(bus: &SharedBus<T>, slave_address: u8) -> Result<Self, DeviceError> {
+        Ok(Self {
+            connection: bus.handle(slave_address).into(),
             slave_address,
+            metadata: None,
+            capabilities: None,
+            soft_limits: SoftLimits::default(),
+            read_only: false,
         })
     }
 
+    /// Connects to the device of a profile like [Device::connect_with_profile], opening the ports with `open`,
+    /// which is given the name of a port and the baudrate to open it at
+    pub fn connect_with_profile_using<F>(
+        profile: &ConnectionProfile,
+        fallback: &DiscoveryPolicy,
+        open: F,
+    ) -> Result<(Self, ConnectionProfile), DeviceError>
+    where
+        F: FnMut(&str, u32) -> Result<T, DeviceError>,
+    {
+        discovery::connect_using(profile, fallback, open)
+    }
+
+    /// Returns how long the device has to start answering a query, see [Device::timeouts]
+    pub fn response_timeout(&self) -> Duration {
+        self.connection.response_timeout()
+    }
+
+    /// Sets how long the device has to start answering a command before the command fails with
+    /// [DeviceError::Timeout], the same for every kind of command. Keep this short to notice a missing device quickly.
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.connection.set_response_timeout(timeout);
+    }
+
+    /// Returns how long the device has to start answering each kind of command
+    pub fn timeouts(&self) -> Timeouts {
+        self.connection.timeouts()
+    }
+
+    /// Sets how long the device has to start answering each kind of command. Reads, averages and
+    /// resets are timed by their own budget, so a short query timeout still notices a missing
+    /// device quickly while a long average gets the time it needs.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.connection.set_timeouts(timeouts);
+    }
+
+    /// Returns the time allowed between two bytes of a response
+    pub fn inter_byte_timeout(&self) -> Duration {
+        self.connection.inter_byte_timeout()
+    }
+
+    /// Sets the time allowed between two bytes of a response before the command fails with
+    /// [DeviceError::IncompleteFrame]
+    pub fn set_inter_byte_timeout(&mut self, timeout: Duration) {
+        self.connection.set_inter_byte_timeout(timeout);
+    }
+
+    /// Returns true if pending input is discarded before every command
+    pub fn clear_stale_input(&self) -> bool {
+        self.connection.clear_stale_input()
+    }
+
+    /// Sets whether input that is still pending when a command is sent, like the response to a
+    /// command that timed out, is discarded first so it can't be mistaken for the new response.
+    /// On by default. When off, bytes read past the end of a response are kept and looked at by
+    /// the next command before reading from the port.
+    pub fn set_clear_stale_input(&mut self, clear: bool) {
+        self.connection.set_clear_stale_input(clear);
+    }
+
+    /// Returns the retry configuration, [None] if commands are never retried
+    pub fn retry(&self) -> Option<RetryConfig> {
+        self.connection.retry()
+    }
+
+    /// Sets how failed commands are retried, off by default. Commands that would have a
+    /// different effect when executed twice are only retried with
+    /// [RetryConfig::retry_non_idempotent], their documentation says so and
+    /// [Command::idempotency] lists them.
+    pub fn set_retry(&mut self, retry: Option<RetryConfig>) {
+        self.connection.set_retry(retry);
+    }
+
+    /// Returns how the device is waited for while it boots after a reset, [None] if it isn't
+    pub fn boot_grace(&self) -> Option<BootGrace> {
+        self.connection.boot_grace()
+    }
+
+    /// Sets how long after [Device::reset_device] or [Device::factory_reset] a
+    /// [StateResponseError::FatalError] means the device is still booting, and how often the command is sent again
+    /// until it answers, see [BootGrace]. Later, or without a reset through this device, the error fails the command
+    /// as usual. [None], the default, leaves the grace off, [BootGrace::default] suits the devices.
+    ///
+    /// [StateResponseError::FatalError]: sfc_core::error::StateResponseError::FatalError
+    pub fn set_boot_grace(&mut self, grace: Option<BootGrace>) {
+        self.connection.set_boot_grace(grace);
+    }
+
+    /// Returns how long ago [Device::reset_device] or [Device::factory_reset] was last sent, [None] if neither was
+    pub fn since_reset(&self) -> Option<Duration> {
+        self.connection.since_reset()
+    }
+
+    /// Returns how commands the device answers as busy are sent again, [None] if they aren't
+    pub fn busy_retry(&self) -> Option<BusyPolicy> {
+        self.connection.busy_retry()
+    }
+
+    /// Sets how long a command the device answers with [StateResponseError::SensorBusy] is sent again before it
+    /// fails with [DeviceError::StillBusy], see [BusyPolicy]. Off by default, the busy state then fails the command.
+    /// [Device::reset_device], [Device::factory_reset] and [Device::set_callibration] wait with a budget of their own
+    /// while it is off.
+    ///
+    /// [StateResponseError::SensorBusy]: sfc_core::error::StateResponseError::SensorBusy
+    pub fn set_busy_retry(&mut self, policy: Option<BusyPolicy>) {
+        self.connection.set_busy_retry(policy);
+    }
+
+    /// Hands every request to the middleware before it is sent and every response to it once received, see
+    /// [FrameMiddleware]. A request it blocks fails with [DeviceError::BlockedByMiddleware] without being sent.
+    /// Replaces the middleware set before.
+    pub fn set_middleware(&mut self, middleware: Box<dyn FrameMiddleware>) {
+        self.connection.set_middleware(middleware);
+    }
+
+    /// Removes the middleware and returns it, [None] if there is none
+    pub fn take_middleware(&mut self) -> Option<Box<dyn FrameMiddleware>> {
+        self.connection.take_middleware()
+    }
+
+    /// Returns how closely responses are checked
+    pub fn validation_level(&self) -> ValidationLevel {
+        self.connection.validation_level()
+    }
+
+    /// Sets which checks of a response fail a command, which are only logged and which are
+    /// skipped, see [ValidationLevel]. [ValidationLevel::Standard] by default.
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.connection.set_validation_level(level);
+    }
+
+    /// Returns how many requests [Device::pipeline] writes ahead of their responses
+    pub fn pipeline_window(&self) -> usize {
+        self.connection.pipeline_window()
+    }
+
+    /// Sets how many requests [Device::pipeline] may have written that were not answered yet.
+    /// 1, the default, sends one request at a time.
+    pub fn set_pipeline_window(&mut self, window: usize) {
+        self.connection.set_pipeline_window(window);
+    }
+
+    /// Returns how a transport that returns empty reads is waited on
+    pub fn spin_policy(&self) -> SpinPolicy {
+        self.connection.spin_policy()
+    }
+
+    /// Sets how often a transport that returns from a read with no bytes, like a port opened
+    /// with a zero timeout, is read again before the device sleeps between reads, see
+    /// [SpinPolicy]. By default it sleeps for the inter-byte timeout after every empty read.
+    pub fn set_spin_policy(&mut self, policy: SpinPolicy) {
+        self.connection.set_spin_policy(policy);
+    }
+
+    /// Returns how many commands were sent, retried and failed, and how long the device takes to
+    /// answer, since the device was created or [Self::reset_stats]
+    pub fn stats(&self) -> &CommStats {
+        self.connection.stats()
+    }
+
+    /// Sets the counters of [Self::stats] back to zero
+    pub fn reset_stats(&mut self) {
+        self.connection.reset_stats();
+    }
+
+    /// Returns how commands are sent over RS-485, [None] if the adapter switches direction by
+    /// itself
+    pub fn rs485(&self) -> Option<Rs485Config> {
+        self.connection.rs485()
+    }
+
+    /// Sets up an RS-485 adapter with manual direction control: the request to send line is
+    /// raised while a command is written and the turnaround delay is waited before reading the
+    /// response. Off by default, which suits adapters that switch direction automatically.
+    pub fn set_rs485(&mut self, rs485: Option<Rs485Config>) {
+        self.connection.set_rs485(rs485);
+    }
+
+    /// Returns whether commands that change the device are refused, see [Device::set_read_only]
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Refuses every command that [changes the device](Command::writes) with [DeviceError::ReadOnly] before
+    /// anything is sent, for diagnostics on a live process line. Readings work as usual. Helpers that change the
+    /// device, like [Device::apply_config], fail before their first command, and so do raw commands from
+    /// [Device::start_command], which can't be classified. Off by default.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Fails with [DeviceError::ReadOnly] in read-only mode, `name` is what was refused
+    fn writable(&self, name: &'static str) -> Result<(), DeviceError> {
+        if self.read_only {
+            return Err(DeviceError::ReadOnly(name));
+        }
+        Ok(())
+    }
+
+    /// Gets back in step with the device after the serial stream got garbled, for example by
+    /// unplugging the cable mid frame. Discards pending input, ends any frame the device is
+    /// still receiving and checks that it answers again. Retries do this by themselves when
+    /// [RetryConfig::resync_after] is set.
+    pub fn resync(&mut self) -> Result<(), DeviceError> {
+        self.connection.resync(self.slave_address)
+    }
+
+    /// Replaces the transport after the old one stopped working, like a USB adapter that was
+    /// unplugged and plugged back in, and probes the device with [Device::get_baudrate].
+    ///
+    /// The timeouts, retry, RS-485 and stale input settings are kept and the baudrate last set
+    /// on the transport is applied to the new one. Bytes received from the old transport are
+    /// dropped. The device keeps no other state on this side, the setpoint and calibration are
+    /// whatever the device itself holds after being replugged, and the unit and serial number of
+    /// recorded values are read again, and so are the capabilities. If the probe fails the new
+    /// transport stays in place so the call can be repeated.
+    pub fn reconnect(&mut self, new_port: T) -> Result<(), DeviceError> {
+        self.metadata = None;
+        self.capabilities = None;
+        self.connection.reconnect(new_port)?;
+        let _ = self.get_baudrate()?;
+        Ok(())
+    }
+
+    /// Sends a raw command without waiting for the response. The returned [PendingCommand] is
+    /// polled until the response arrived and borrows the device until then, it is never retried.
+    /// ```no_run
+    /// # fn run(mut device: sfc5xxx_rs::device::Device<sfc_core::transport::TcpTransport>) {
+    /// let mut pending = device.start_command(0x08, &[0x01]).unwrap();
+    /// let response = loop {
+    ///     if let std::task::Poll::Ready(response) = pending.poll() {
+    ///         break response;
+    ///     }
+    ///     // do other work in the meantime
+    /// };
+    /// # }
+    /// ```
+    pub fn start_command(
+        &mut self,
+        command: u8,
+        data: &[u8],
+    ) -> Result<PendingCommand<'_, T>, DeviceError> {
+        self.writable("raw command")?;
+        let frame = MOSIFrame::new(self.slave_address, command, data)?;
+        self.connection.start(frame)
+    }
+
+    /// Sends the commands and returns their responses in the same order, writing up to
+    /// [Device::pipeline_window] requests before reading a response. When a response doesn't
+    /// match its request or fails the remaining commands are sent one at a time, so a command
+    /// may be executed twice: only pipeline commands that read from the device.
+    /// ```no_run
+    /// # fn run(mut device: sfc5xxx_rs::device::Device<sfc_core::transport::TcpTransport>) {
+    /// use sfc5xxx_rs::commands::Command;
+    /// device.set_pipeline_window(4);
+    /// let responses = device
+    ///     .pipeline(&[Command::GetCalibrationGasId { index: 0 }, Command::GetCalibrationFullScale { index: 0 }])
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn pipeline(&mut self, commands: &[Command]) -> Result<Vec<MISOFrame>, DeviceError> {
+        for command in commands.iter().filter(|command| command.writes()) {
+            self.writable(command.name())?;
+        }
+        let frames = commands
+            .iter()
+            .map(|command| command.encode(self.slave_address))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.connection.pipeline(frames)
+    }
+
     pub fn get_product_name(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0xD0, &[0x01])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        let string = match CString::from_vec_with_nul(data.to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-        Ok(string)
+        self.run(Command::GetProductName, parse_string)
     }
 
     pub fn get_article_code(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0xD0, &[0x02])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        let string = match CString::from_vec_with_nul(data.to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-        Ok(string)
+        self.run(Command::GetArticleCode, parse_string)
     }
 
     pub fn get_serial_number(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0xD0, &[0x03])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        let string = match CString::from_vec_with_nul(data.to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-        Ok(string)
-
+        self.run(Command::GetSerialNumber, parse_string)
     }
 
     pub fn get_version(&mut self) -> Result<Version, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0xD1, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.len() < 7 {
-            Err(TranslationError::NotEnoughData(7, data.len() as u8))?;
-        }
+        let data: [u8; 7] = self.query(Command::GetVersion)?;
 
         Ok(Version {
             firmware_major: data[0],
@@ -106,188 +439,382 @@ impl<T: SerialPort> Device<T> {
         })
     }
 
+    /// Returns what the firmware of the device supports, see [sfc_core::capabilities]. The version is read with
+    /// the first call and kept, later calls cost no command and neither do the checks of the methods that depend
+    /// on a [Feature]. It is read again after [Device::reconnect].
+    pub fn capabilities(&mut self) -> Result<Capabilities, DeviceError> {
+        if let Some(capabilities) = self.capabilities {
+            return Ok(capabilities);
+        }
+        let version = self.get_version()?;
+        Ok(*self.capabilities.insert(Capabilities::for_version(DeviceFamily::Sfc5xxx, &version)))
+    }
+
+    /// Fails with [DeviceError::UnsupportedFirmware] if the firmware doesn't support the feature
+    fn require(&mut self, feature: Feature) -> Result<(), DeviceError> {
+        self.capabilities()?.require(feature)
+    }
+
     // TODO: make this more rusty
+    /// Not retried when clearing the error state unless [RetryConfig::retry_non_idempotent] is
+    /// set, a second attempt would read the cleared state.
     pub fn get_device_error_state(&mut self, clear_after_read: bool) -> Result<(u32, u8), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0xD2, &[clear_after_read as u8])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.len() < 5 {
-            Err(TranslationError::NotEnoughData(5, data.len() as u8))?;
-        }
+        let data: [u8; 5] = self.query(Command::GetDeviceErrorState { clear: clear_after_read })?;
 
         let code = u32::from_be_bytes([data[0],data[1],data[2],data[3]]);
         Ok((code, data[4]))
     }
 
+    /// Only retried with [RetryConfig::retry_non_idempotent], a lost response would send the
+    /// retry to the old address. Refused while the configuration is [locked](Device#locked-configuration).
     pub fn set_slave_address(&mut self, new_addres: u8) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x90, &[new_addres])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
+        self.send(Command::SetSlaveAddress { address: new_addres })?;
+        self.slave_address = new_addres;
         Ok(())
     }
 
     pub fn get_device_address(&mut self) -> Result<u8, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x90, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.is_empty() {
-            Err(TranslationError::NotEnoughData(0, 1))?;
-        }
+        let data: [u8; 1] = self.query(Command::GetSlaveAddress)?;
         Ok(data[0])  
     }
 
-    pub fn set_baudrate(&mut self, buad_rate: u32) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x91, &buad_rate.to_be_bytes())?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+    /// Sets the baudrate the device talks at from its next start. Only retried with
+    /// [RetryConfig::retry_non_idempotent]. Refused while the configuration is [locked](Device#locked-configuration).
+    pub fn set_baudrate(&mut self, baudrate: Baudrate) -> Result<(), DeviceError> {
+        self.send(Command::SetBaudrate { baudrate: baudrate.into() })
     }
 
-    pub fn get_baudrate(&mut self) -> Result<u32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x91, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    /// Returns the baudrate the device is set to, [Baudrate::Other] if it isn't a documented one
+    pub fn get_baudrate(&mut self) -> Result<Baudrate, DeviceError> {
+        let data: [u8; 4] = self.query(Command::GetBaudrate)?;
+        Ok(Baudrate::from_device(u32::from_be_bytes([data[0], data[1], data[2], data[3]])))
+    }
+
+    /// [Device::set_baudrate] with a bare number, failing with
+    /// [DeviceError::UnsupportedBaudrate] for a rate the device doesn't support
+    #[deprecated(note = "use set_baudrate with a Baudrate")]
+    pub fn set_baudrate_u32(&mut self, baudrate: u32) -> Result<(), DeviceError> {
+        self.set_baudrate(Baudrate::try_from(baudrate)?)
+    }
+
+    /// [Device::get_baudrate] as a bare number
+    #[deprecated(note = "use get_baudrate, which returns a Baudrate")]
+    pub fn get_baudrate_u32(&mut self) -> Result<u32, DeviceError> {
+        self.get_baudrate().map(u32::from)
     }
 
+    /// Only retried with [RetryConfig::retry_non_idempotent]. Commands the device answers with a fatal error while it
+    /// boots are sent again with a [boot grace](Device::set_boot_grace). A busy sensor is waited for up to 500ms
+    /// unless [Device::set_busy_retry] says otherwise.
     pub fn reset_device(&mut self) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0xD3, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.metadata = None;
+        self.connection.mark_reset();
+        self.send_busy(Command::DeviceReset, RESET_BUSY)
     }
 
+    /// Only retried with [RetryConfig::retry_non_idempotent]. Commands the device answers with a fatal error while it
+    /// boots are sent again with a [boot grace](Device::set_boot_grace). A busy sensor is waited for up to 2s
+    /// unless [Device::set_busy_retry] says otherwise. Refused while the configuration is
+    /// [locked](Device#locked-configuration).
     pub fn factory_reset(&mut self) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x92, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.metadata = None;
+        self.connection.mark_reset();
+        self.send_busy(Command::FactoryReset, FACTORY_RESET_BUSY)
     }
 
+    /// Sets the setpoint, the bits of an `f32` in the given scale. It is checked against the
+    /// [soft limits](Device::set_soft_limits) first.
     pub fn set_setpoint(&mut self, setpoint: u32, scale: Scale) -> Result<(), DeviceError> {
-        let setpoint_bytes = setpoint.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_address,
-            0x00,
-            &[
-                scale as u8,
-                setpoint_bytes[0],
-                setpoint_bytes[1],
-                setpoint_bytes[2],
-                setpoint_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.write_setpoint(setpoint, scale, None)
     }
 
-    pub fn get_setpoint(&mut self, scale: Scale) -> Result<u32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x00, &[scale as u8])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
+    /// Returns the limits every setpoint is checked against before it is sent
+    pub fn soft_limits(&self) -> SoftLimits {
+        self.soft_limits
+    }
+
+    /// Limits the setpoints this device sends to `min..=max`, physical values in the configured
+    /// medium unit, independent of the full scale of the device. Every method writing a setpoint
+    /// checks it first, and so do profiles and everything else built on them. Normalized
+    /// setpoints are converted with [Device::get_converted_fullscale], which costs one more
+    /// command while limits are set. Raw commands from [Device::start_command] are not checked.
+    /// Setpoints outside the limits fail with [DeviceError::SoftLimit], naming the physical
+    /// value, until [Device::set_soft_limit_policy] is set to clamp them.
+    ///
+    /// # Panics
+    /// If `min` is above `max` or either is NaN
+    pub fn set_soft_limits(&mut self, min: f32, max: f32) {
+        self.soft_limits = SoftLimits::new(min, max).with_policy(self.soft_limits.policy);
+    }
+
+    /// Sets whether setpoints outside the soft limits fail or are clamped to them
+    pub fn set_soft_limit_policy(&mut self, policy: LimitPolicy) {
+        self.soft_limits.policy = policy;
+    }
+
+    /// Allows every setpoint again, keeping the policy
+    pub fn clear_soft_limits(&mut self) {
+        self.soft_limits = SoftLimits::default().with_policy(self.soft_limits.policy);
+    }
+
+    /// Checks a setpoint in the given scale against the soft limits and returns the one to send,
+    /// in the same scale
+    fn limit_setpoint(&mut self, setpoint: f32, scale: Scale) -> Result<f32, DeviceError> {
+        if self.soft_limits.is_unlimited() {
+            return Ok(setpoint);
+        }
+        match scale {
+            Scale::Normilized => {
+                let full_scale = self.get_converted_fullscale()?;
+                let physical = setpoint * full_scale;
+                let limited = self.soft_limits.apply(physical)?;
+                // a setpoint within the limits is sent as given, without rounding through the full scale
+                Ok(if limited == physical { setpoint } else { limited / full_scale })
+            }
+            Scale::PhysicalValue | Scale::UserDefined => self.soft_limits.apply(setpoint),
         }
+    }
+
+    /// [Device::set_setpoint] for a time budget, gives up at the deadline with
+    /// [DeviceError::DeadlineExceeded], see [Connection::transact_with_deadline]
+    pub fn set_setpoint_with_deadline(
+        &mut self,
+        setpoint: u32,
+        scale: Scale,
+        deadline: Instant,
+    ) -> Result<(), DeviceError> {
+        self.write_setpoint(setpoint, scale, Some(deadline))
+    }
+
+    fn write_setpoint(
+        &mut self,
+        setpoint: u32,
+        scale: Scale,
+        deadline: Option<Instant>,
+    ) -> Result<(), DeviceError> {
+        // limiting a normalized setpoint reads the full scale
+        self.writable("setpoint")?;
+        let setpoint = self.limit_setpoint(f32::from_bits(setpoint), scale)?.to_bits();
+        self.run_until(Command::SetSetpoint { scale, value: setpoint }, deadline, |_| Ok(()))
+    }
 
-        Ok(u32::from_be_bytes([data[0],data[1],data[2],data[3]]))
+    pub fn get_setpoint(&mut self, scale: Scale) -> Result<u32, DeviceError> {
+        self.run(Command::GetSetpoint { scale }, read_u32)
     }
 
     pub fn read_measured_flow(&mut self, scale: Scale) -> Result<u32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x08, &[scale as u8])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
+        self.read_flow(scale, None)
+    }
+
+    /// Reads the measured flow as a [Measurement] with the medium unit, serial number, full scale
+    /// and address of the device. The unit, serial number and full scale are read with the first
+    /// recorded value and kept, later ones cost no more commands than [Device::read_measured_flow]. They are read again
+    /// after the calibration or medium unit was changed or the device reset through this device.
+    pub fn read_measured_flow_recorded(&mut self, scale: Scale) -> Result<Measurement, DeviceError> {
+        let (unit, serial_number, full_scale) = match &self.metadata {
+            Some(metadata) => metadata.clone(),
+            None => {
+                let metadata = (self.get_medium_unit_configuration(false)?, self.get_serial_number()?, self.get_converted_fullscale()?);
+                self.metadata.insert(metadata).clone()
+            }
+        };
+        let value = f32::from_bits(self.read_measured_flow(scale)?);
+        Ok(Measurement {
+            serial_number: Some(serial_number),
+            address: Some(self.slave_address),
+            full_scale: Some(full_scale),
+            ..Measurement::new(value, unit, scale.into())
+        })
+    }
+
+    /// [Device::read_measured_flow] for a time budget, gives up at the deadline with
+    /// [DeviceError::DeadlineExceeded], see [Connection::transact_with_deadline]
+    pub fn read_measured_flow_with_deadline(
+        &mut self,
+        scale: Scale,
+        deadline: Instant,
+    ) -> Result<u32, DeviceError> {
+        self.read_flow(scale, Some(deadline))
+    }
+
+    fn read_flow(&mut self, scale: Scale, deadline: Option<Instant>) -> Result<u32, DeviceError> {
+        self.run_until(Command::ReadMeasuredValue { scale }, deadline, read_u32)
+    }
+
+    /// Sends the command and reads the data of its response where it was received, see
+    /// [Connection::transact_and_read]. `read` is only handed data of the
+    /// [Command::response_length], anything else fails before it is called.
+    fn run<R>(&mut self, command: Command, read: impl FnOnce(&[u8]) -> Result<R, DeviceError>) -> Result<R, DeviceError> {
+        self.run_until(command, None, read)
+    }
+
+    /// [Device::run] that gives up at the deadline, if there is one
+    fn run_until<R>(
+        &mut self,
+        command: Command,
+        deadline: Option<Instant>,
+        read: impl FnOnce(&[u8]) -> Result<R, DeviceError>,
+    ) -> Result<R, DeviceError> {
+        if command.writes() {
+            self.writable(command.name())?;
         }
+        let length = command.response_length();
+        let frame = command.encode(self.slave_address)?;
+        let read = |response: MISOFrameRef<'_>| {
+            length.check(response.data())?;
+            read(response.data())
+        };
+        match deadline {
+            Some(deadline) => self.connection.transact_with_deadline_and_read(frame, deadline, read),
+            None => self.connection.transact_and_read(frame, read),
+        }
+    }
 
-        Ok(u32::from_be_bytes([data[0],data[1],data[2],data[3]]))
+    /// Sends a command the device answers without data
+    fn send(&mut self, command: Command) -> Result<(), DeviceError> {
+        self.run(command, |_| Ok(()))
     }
 
-    pub fn read_measured_flow_buffered(&mut self, scale: Scale) -> Result<BufferedRead, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x09, &[scale as u8])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        
-        if data.len() < 12 {
-            Err(TranslationError::NotEnoughData(12, data.len() as u8))?;
+    /// [Device::send] waiting for a busy sensor with `budget` if [Device::set_busy_retry] is off
+    fn send_busy(&mut self, command: Command, budget: BusyPolicy) -> Result<(), DeviceError> {
+        let policy = self.connection.busy_retry();
+        self.connection.set_busy_retry(policy.or(Some(budget)));
+        let result = self.send(command);
+        self.connection.set_busy_retry(policy);
+        result
+    }
+
+    /// [Device::send] for a command of a feature, a read-only device refuses it before the capabilities are read
+    fn send_feature(&mut self, feature: Feature, command: Command) -> Result<(), DeviceError> {
+        if command.writes() {
+            self.writable(command.name())?;
         }
+        self.require(feature)?;
+        self.send(command)
+    }
 
-        Ok(BufferedRead::new(&data))
+    /// Sends a command the device answers with `N` bytes and returns them
+    fn query<const N: usize>(&mut self, command: Command) -> Result<[u8; N], DeviceError> {
+        debug_assert_eq!(command.response_length().min() as usize, N, "{}", command.name());
+        self.run(command, take)
     }
 
-    /// TODO: make feature flag for V1.48
-    pub fn read_measured_flow_two_sensors(&mut self, scale: Scale) -> Result<(f32, f32), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x0A, &[scale as u8])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
+    /// Only retried with [RetryConfig::retry_non_idempotent], reading removes the values from
+    /// the buffer of the device. Fails with [DeviceError::UnsupportedFirmware] on firmware without
+    /// [Feature::BufferedRead].
+    pub fn read_measured_flow_buffered(&mut self, scale: Scale) -> Result<BufferedRead, DeviceError> {
+        self.require(Feature::BufferedRead)?;
+        self.run(Command::ReadMeasuredValueBuffer { scale }, BufferedRead::new)
+    }
+
+    /// Collects `count` values from the measurement buffer with [Device::read_measured_flow_buffered]
+    /// and returns their mean, standard deviation, minimum, maximum and peak to peak. The device
+    /// samples at its own rate, values already waiting in the buffer count and values read past
+    /// the last one needed are dropped. Read the buffer once before to only count new values.
+    /// While the buffer is empty this waits for the missing values to be sampled.
+    ///
+    /// If a read fails after the first values were collected, their statistics are returned with
+    /// the error in [FlowStatistics::interrupted]. An error before that is returned as is.
+    ///
+    /// # Panics
+    /// If `count` is zero
+    pub fn sample_statistics_buffered(&mut self, count: u32, scale: Scale) -> Result<FlowStatistics, DeviceError> {
+        self.sample_buffered(count, scale, false)
+    }
+
+    /// [Device::sample_statistics_buffered] that keeps every value in [FlowStatistics::samples]
+    ///
+    /// # Panics
+    /// If `count` is zero
+    pub fn sample_statistics_buffered_with_samples(&mut self, count: u32, scale: Scale) -> Result<FlowStatistics, DeviceError> {
+        self.sample_buffered(count, scale, true)
+    }
 
-        if data.len() < 8 {
-            Err(TranslationError::NotEnoughData(8, data.len() as u8))?;
+    fn sample_buffered(&mut self, count: u32, scale: Scale, keep_samples: bool) -> Result<FlowStatistics, DeviceError> {
+        assert!(count > 0, "a burst needs at least one reading");
+        let mut statistics = RunningStatistics::new();
+        let mut samples = Vec::new();
+        let mut interrupted = None;
+        while statistics.count() < count {
+            let read = match self.read_measured_flow_buffered(scale) {
+                Ok(read) => read,
+                Err(e) if statistics.count() == 0 => return Err(e),
+                Err(e) => {
+                    interrupted = Some(e);
+                    break;
+                }
+            };
+            let missing = (count - statistics.count()) as usize;
+            for &value in read.values.iter().take(missing) {
+                statistics.push(value);
+                if keep_samples {
+                    samples.push(value);
+                }
+            }
+            if read.remaning_values == 0 && statistics.count() < count {
+                // wait until the missing values have been sampled, at least a millisecond
+                let missing = count - statistics.count();
+                let wait = Duration::try_from_secs_f32(read.sampling_time * missing as f32).unwrap_or_default();
+                std::thread::sleep(wait.max(Duration::from_millis(1)));
+            }
         }
+        // the first values were read or the error returned, a burst has at least one reading
+        let Some(summary) = statistics.summary() else {
+            return Err(TranslationError::NoData.into());
+        };
+        Ok(FlowStatistics {
+            samples: keep_samples.then_some(samples),
+            interrupted,
+            ..summary
+        })
+    }
+
+    /// Reads the flow of both sensors. Fails with [DeviceError::UnsupportedFirmware] on firmware without
+    /// [Feature::TwoSensors], before 1.48.
+    pub fn read_measured_flow_two_sensors(&mut self, scale: Scale) -> Result<(f32, f32), DeviceError> {
+        self.require(Feature::TwoSensors)?;
+        let data: [u8; 8] = self.query(Command::ReadMeasuredValueTwoSensors { scale })?;
         let sensor_1_data = f32::from_be_bytes([data[0], data[1], data[2], data[3]]);
         let sensor_2_data = f32::from_be_bytes([data[4], data[5], data[6], data[7]]);
         Ok((sensor_1_data, sensor_2_data))
     }
 
+    /// Sets the setpoint and reads the measured value in one command. The setpoint is checked
+    /// against the [soft limits](Device::set_soft_limits) first.
     pub fn set_setpoint_and_read_measured_value(&mut self, scale: Scale, setpoint: f32) -> Result<f32, DeviceError> {
-        let setpoint_bytes = setpoint.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x03, &[scale as u8, setpoint_bytes[0], setpoint_bytes[1], setpoint_bytes[2], setpoint_bytes[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
+        self.writable("set setpoint and read measured value")?;
+        let setpoint = self.limit_setpoint(setpoint, scale)?;
+        let data: [u8; 4] = self.query(Command::SetSetpointAndReadMeasuredValue { scale, value: setpoint })?;
 
         Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
     }
 
-    /// TODO: make feature flag for V1.48
+    /// [Device::set_setpoint_and_read_measured_value] for both sensors. Fails with
+    /// [DeviceError::UnsupportedFirmware] on firmware without [Feature::TwoSensors], before 1.48.
     pub fn set_setpoint_and_read_measured_value_two_sensors(&mut self, scale: Scale, setpoint: f32) -> Result<(f32, f32), DeviceError> {
-        let setpoint_bytes = setpoint.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x04, &[scale as u8, setpoint_bytes[0], setpoint_bytes[1], setpoint_bytes[2], setpoint_bytes[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 8 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
+        self.writable("set setpoint and read measured value of both sensors")?;
+        self.require(Feature::TwoSensors)?;
+        let setpoint = self.limit_setpoint(setpoint, scale)?;
+        let data: [u8; 8] = self.query(Command::SetSetpointAndReadMeasuredValueTwoSensors { scale, value: setpoint })?;
 
         let sensor_1_data = f32::from_be_bytes([data[0], data[1], data[2], data[3]]);
         let sensor_2_data = f32::from_be_bytes([data[4], data[5], data[6], data[7]]);
         Ok((sensor_1_data, sensor_2_data))
     }
 
+    /// Keeps the setpoint through a restart. Refused while the configuration is [locked](Device#locked-configuration).
     pub fn make_setpoint_persistant(&mut self, persist: bool) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x02, &[0x00, persist as u8])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        
-        Ok(())
+        self.send(Command::SetSetpointPersistence { persist })
     }
 
     pub fn is_setpoint_persistant(&mut self) -> Result<bool, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x02, &[0x00])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        
-        if data.is_empty() {
-            Err(TranslationError::NotEnoughData(1, 0))?;
-        }
+        let data: [u8; 1] = self.query(Command::GetSetpointPersistence)?;
 
         Ok(data[0] == 1)
     }
 
     pub fn set_valve_input_source(&mut self, config: InputSourceConfig) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x20, &[0x00, config.into()])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
+        self.send(Command::SetValveInputSource { source: config })?;
         use InputSourceConfig::*;
         match config {
             Controller | ForceClosed | ForceOpen | Hold => Ok(()),
@@ -296,57 +823,36 @@ impl<T: SerialPort> Device<T> {
     }
 
     fn set_user_input_source(&mut self, value: f32) -> Result<(), DeviceError> {
-        let value_b = value.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x20, &[0x01, value_b[0], value_b[1], value_b[2], value_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.send(Command::SetUserValveValue { value })
     }
 
     pub fn get_valve_input_source(&mut self) -> Result<InputSourceConfig, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x20, &[0x00])?;
-        let _ =  self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.is_empty() {
-            Err(TranslationError::NotEnoughData(1, 0))?;
-        }
+        let data: [u8; 1] = self.query(Command::GetValveInputSource)?;
         match data[0] {
             0x00 => Ok(InputSourceConfig::Controller),
             0x01 => Ok(InputSourceConfig::ForceClosed),
             0x02 => Ok(InputSourceConfig::ForceOpen),
             0x03 => Ok(InputSourceConfig::Hold),
             0x10 => self.get_user_input_value(),
-            _ => unreachable!(),
+            source => Err(TranslationError::UnknownValue(source).into()),
         }
     }
 
     fn get_user_input_value(&mut self) -> Result<InputSourceConfig, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x20, &[0x01])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
+        let data: [u8; 4] = self.query(Command::GetUserValveValue)?;
         let value = f32::from_be_bytes([data[0], data[1], data[2], data[3]]);
         Ok(InputSourceConfig::UserDefined(value))
     }
 
+    /// Sets the unit the device measures and takes setpoints in. Refused while the configuration is
+    /// [locked](Device#locked-configuration).
     pub fn set_medium_unit_configuration(&mut self, unit: GasUnit) -> Result<(), DeviceError> {
-       let frame = MOSIFrame::new(self.slave_address, 0x21, &[0x00, Into::<i8>::into(unit.unit_prefex).to_le_bytes()[0], unit.medium_unit.into(), unit.timebase.into()])?;
-       let _ = self.port.write(&frame.into_raw())?;
-       let _ = self.read_response()?;
-
-       Ok(())
+       self.metadata = None;
+       self.send(Command::SetMediumUnit { unit })
     }
 
     pub fn get_medium_unit_configuration(&mut self, include_wild_cards: bool) -> Result<GasUnit, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x21, &[include_wild_cards.into()])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 3 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(3, data.len() as u8)));
-        }
+        let data: [u8; 3] = self.query(Command::GetMediumUnit { include_wild_cards })?;
 
         Ok(GasUnit {
             unit_prefex: i8::from_be_bytes([data[0]]).into(),
@@ -356,202 +862,107 @@ impl<T: SerialPort> Device<T> {
     }
 
     pub fn get_converted_fullscale(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x21, &[0x0A])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.len() < 4 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(4, data.len() as u8)));
-        }
+        let data: [u8; 4] = self.query(Command::GetConvertedFullScale)?;
 
         Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
     }
 
+    /// Refused while the configuration is [locked](Device#locked-configuration).
     pub fn set_user_controller_gain(&mut self, gain: f32) -> Result<(), DeviceError> {
-        let gain_b = gain.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x00, gain_b[0], gain_b[1], gain_b[2], gain_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.send(Command::SetControllerGain { gain })
     }
 
     
     pub fn set_pressure_dependant_gain_enable(&mut self, enabled: bool) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x10, enabled.into()])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.send_feature(Feature::PressureDependentGain, Command::SetPressureDependentGain { enabled })
     }
 
     // inlet pressure is in bar
     pub fn set_gain_correction(&mut self, inlet_pressure: f32) -> Result<(), DeviceError> {
-        let pressure_b = inlet_pressure.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x11, pressure_b[0], pressure_b[1], pressure_b[2], pressure_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.send_feature(Feature::PressureDependentGain, Command::SetInletPressure { pressure: inlet_pressure })
     }
 
     pub fn set_gas_temperature_enable(&mut self, enabled: bool) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x20, enabled.into()])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.send_feature(Feature::GasTemperatureCompensation, Command::SetGasTemperatureCompensation { enabled })
     }
 
     pub fn set_inlet_temperature_correction(&mut self, temperature: f32) -> Result<(), DeviceError> {
-        let temp_b = temperature.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x21, temp_b[0], temp_b[1], temp_b[2], temp_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.send_feature(Feature::GasTemperatureCompensation, Command::SetInletTemperature { temperature })
     }
 
     pub fn get_user_controller_gain(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x00])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(4, data.len() as u8)));
-        }
+        let data: [u8; 4] = self.query(Command::GetControllerGain)?;
 
         Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
     }
 
     pub fn get_pressure_dependant_gain(&mut self) -> Result<Option<f32>, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x10])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.is_empty() {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(1, 0)));
-        }
+        self.require(Feature::PressureDependentGain)?;
+        let data: [u8; 1] = self.query(Command::GetPressureDependentGain)?;
 
         if data[0] == 0 {
             return Ok(None);
         }
 
-        let frame = MOSIFrame::new(self.slave_address, 0x022, &[0x11])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        if data.len() < 4 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(1, 0)));
-        }
+        let data: [u8; 4] = self.query(Command::GetInletPressure)?;
         
         Ok(Some(f32::from_be_bytes([data[0], data[1], data[2], data[3]])))
     }
 
     pub fn get_gas_temperature_compensation(&mut self) -> Result<Option<f32>, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x20])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.is_empty() {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(1, 0)));
-        }
+        self.require(Feature::GasTemperatureCompensation)?;
+        let data: [u8; 1] = self.query(Command::GetGasTemperatureCompensation)?;
         if data[0] == 0 {
             return Ok(None);
         }
 
-        let frame = MOSIFrame::new(self.slave_address, 0x22, &[0x21])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(4, data.len() as u8)));
-        }
+        let data: [u8; 4] = self.query(Command::GetInletTemperature)?;
 
         Ok(Some(f32::from_be_bytes([data[0], data[1], data[2], data[3]])))
     }
 
     pub fn measure_raw_flow(&mut self) -> Result<u16, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x30, &[0x00])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 2 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(2, data.len() as u8)));
-        }
+        let data: [u8; 2] = self.query(Command::MeasureRawFlow)?;
 
         Ok(u16::from_be_bytes([data[0], data[1]]))
     }
     
     pub fn measure_raw_thermal_conductivity(&mut self, valve_closed: bool) -> Result<u16, DeviceError> {
-        let d1 = if valve_closed {0x01} else {0x02};
-        let frame = MOSIFrame::new(self.slave_address, 0x30, &[d1])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 2 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(2, data.len() as u8)));
-        }
+        let data: [u8; 2] = self.query(Command::MeasureRawThermalConductivity { valve_closed })?;
 
         Ok(u16::from_be_bytes([data[0], data[1]]))
     }
 
-    simple_device_function!{measure_temperature, f32, 0x30, 0x10}
+    simple_device_function!{measure_temperature, f32, Command::MeasureTemperature}
 
+    /// Selects the calibration used from the next reset. A busy sensor is waited for up to a second unless
+    /// [Device::set_busy_retry] says otherwise. Only retried with [RetryConfig::retry_non_idempotent]. Refused while
+    /// the configuration is [locked](Device#locked-configuration).
     pub fn set_callibration(&mut self, index: u32) -> Result<(), DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x45, &index_b)?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.metadata = None;
+        self.send_busy(Command::SetCalibration { index }, CALIBRATION_BUSY)
     }
 
-    simple_device_function!(get_number_of_calibrations, u32, 0x40, 0x00);
+    simple_device_function!(get_number_of_calibrations, u32, Command::GetNumberOfCalibrations);
 
     pub fn get_calibration_validity(&mut self, index: u32) -> Result<bool, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x10, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.is_empty() {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(1, 0)))
-        }
+        let data: [u8; 1] = self.query(Command::GetCalibrationValidity { index })?;
 
         Ok(data[0] > 0)
     }
 
     pub fn get_calibration_gas_description(&mut self, index: u32) -> Result<String, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x11, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data =  self.read_response()?.into_data();
-        
-        let string = match CString::from_vec_with_nul(data.to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-        Ok(string)
+        self.run(Command::GetCalibrationGasDescription { index }, parse_string)
     }
 
     pub fn get_calibration_gas_id(&mut self, index: u32) -> Result<u32, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x12, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(4, data.len() as u8)));
-        }
+        let data: [u8; 4] = self.query(Command::GetCalibrationGasId { index })?;
 
         Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
     }
 
     pub fn get_calibration_gas_unit(&mut self, index: u32) -> Result<GasUnit, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x13, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 3 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(3, data.len() as u8)));
-        }
+        let data: [u8; 3] = self.query(Command::GetCalibrationGasUnit { index })?;
 
         Ok(GasUnit {
             unit_prefex: i8::from_be_bytes([data[0]]).into(),
@@ -561,159 +972,2114 @@ impl<T: SerialPort> Device<T> {
     }
 
     pub fn get_calibration_fullscale(&mut self, index: u32) -> Result<f32, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x14, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(4, data.len() as u8)));
-        }
+        let data: [u8; 4] = self.query(Command::GetCalibrationFullScale { index })?;
 
         Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
     }
 
     pub fn get_calibration_initial_conditions(&mut self, index: u32) -> Result<CalibrationCondition, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x15, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res_frame = self.read_response()?;
-
-        CalibrationCondition::from_miso(res_frame)
+        self.run(Command::GetCalibrationInitialConditions { index }, CalibrationCondition::from_bytes)
     }
 
     pub fn get_calibration_recalibration_conditions(&mut self, index: u32) -> Result<CalibrationCondition, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x16, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res_frame = self.read_response()?;
-
-        CalibrationCondition::from_miso(res_frame)
+        self.run(Command::GetCalibrationRecalibrationConditions { index }, CalibrationCondition::from_bytes)
     }
 
     pub fn get_calibration_thermal_conductivity_refrence(&mut self, index: u32) -> Result<u16, DeviceError> {
-        let index_b = index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_address, 0x40, &[0x16, index_b[0], index_b[1], index_b[2], index_b[3]])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 2 {
-            return Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(2, data.len() as u8)));
-        }
+        let data: [u8; 2] = self.query(Command::GetCalibrationThermalConductivityReference { index })?;
 
         Ok(u16::from_be_bytes([data[0], data[1]]))
     }
 
     pub fn get_current_gas_description(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x44, &[0x11])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-        
-        let string = match CString::from_vec_with_nul(data.to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-        Ok(string)
+        self.run(Command::GetCurrentGasDescription, parse_string)
     }
 
-    simple_device_function!(get_current_gas_id, u32, 0x44, 0x12);
-    simple_device_function!(get_current_gas_unit, GasUnit, 0x44, 0x13);
-    simple_device_function!(get_current_fullscale, f32, 0x44, 0x14);
+    simple_device_function!(get_current_gas_id, u32, Command::GetCurrentGasId);
+    simple_device_function!(get_current_gas_unit, GasUnit, Command::GetCurrentGasUnit);
+    simple_device_function!(get_current_fullscale, f32, Command::GetCurrentFullScale);
 
     pub fn get_current_initial_calibration_conditions(&mut self) -> Result<CalibrationCondition, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x44, &[0x15])?;
-        let _ = self.port.write(&frame.into_raw());
-        let res_frame = self.read_response()?;
-
-        CalibrationCondition::from_miso(res_frame)
+        self.run(Command::GetCurrentInitialConditions, CalibrationCondition::from_bytes)
     }
 
     pub fn get_current_recalibration_condition(&mut self) -> Result<CalibrationCondition, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x44, &[0x16])?;
-        let _ = self.port.write(&frame.into_raw());
-        let res_frame = self.read_response()?;
-
-        CalibrationCondition::from_miso(res_frame)
+        self.run(Command::GetCurrentRecalibrationConditions, CalibrationCondition::from_bytes)
     }
 
-    simple_device_function!(get_current_thermal_conducitvity_refrence, u16, 0x44, 0x17);
+    simple_device_function!(get_current_thermal_conducitvity_refrence, u16, Command::GetCurrentThermalConductivityReference);
 
     pub fn read_user_memory(&mut self, start_address: u8, bytes_to_read: u8) -> Result<Vec<u8>, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_address, 0x6E, &[start_address, bytes_to_read])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        Ok(data.to_vec())
+        self.run(Command::ReadUserMemory { start_address, length: bytes_to_read }, |data| Ok(data.to_vec()))
     }
 
+    /// Refused while the configuration is [locked](Device#locked-configuration).
     pub fn write_user_memory(&mut self, start_address: u8, data: &[u8]) -> Result<(), DeviceError> {
-        let len = data.len() as u8;
-        let mut  frame_data = vec![start_address, len];
-        frame_data.extend_from_slice(data);
-        let frame = MOSIFrame::new(self.slave_address, 0x6E, &frame_data)?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
+        self.send(Command::WriteUserMemory { start_address, data: data.to_vec() })
+    }
 
-        Ok(())
+    /// Reads what the filter asks for and fails with [DeviceError::IdentityMismatch] if this is not the expected
+    /// device, so a wrong port or address doesn't command another unit. The SFC5xxx has no product type, the
+    /// product type of the filter is compared with the [product name](Device::get_product_name). See
+    /// [identity](sfc_core::identity).
+    pub fn assert_identity(&mut self, expected: &IdentityFilter) -> Result<(), DeviceError> {
+        expected.check(|field| match field {
+            IdentityField::SerialNumber => self.get_serial_number(),
+            IdentityField::ArticleCode => self.get_article_code(),
+            IdentityField::ProductType => self.get_product_name(),
+        })
     }
 
-    fn read_response(&mut self) -> Result<MISOFrame, DeviceError> {
-        let mut buff = [0_u8; 20];
-        let mut out = ArrayVec::<u8, 518>::new();
-        loop {
-            let s = self.port.read(&mut buff)?;
-            out.try_extend_from_slice(&buff[..s])?;
-            if buff[s - 1] == 0x7E && (s > 1 || out.len() > 1) {
-                break;
-            }
-        }
+    /// The profile to connect to this device again with [Device::connect_with_profile], with the serial number and
+    /// baudrate read from the device. The device doesn't know the name of its port, the hint is kept as given.
+    pub fn connection_profile(&mut self, port_hint: Option<&str>) -> Result<ConnectionProfile, DeviceError> {
+        discovery::profile_of(self, port_hint)
+    }
 
-        let frame = MISOFrame::from_bytes(&out);
+    /// Checks that the device is ready for a run: it answers, its firmware is recent enough, it
+    /// has no errors latched, the active calibration is valid and no flow is measured while the
+    /// setpoint is zero. See [health](sfc_core::health) for the details. The latched errors are
+    /// read without clearing them. Failing commands fail their item, the check itself never
+    /// fails.
+    pub fn health_check(&mut self, requirements: HealthRequirements) -> HealthReport {
+        let mut report = health::check(self, &requirements);
+        report.device_errors = match self.get_device_error_state(false) {
+            Ok((0, _)) => CheckOutcome::Passed,
+            Ok((flags, last_error)) => CheckOutcome::Failed(format!(
+                "error flags {:#010x} are set, the last error was {:#04x}",
+                flags, last_error
+            )),
+            Err(e) => e.into(),
+        };
+        // reading the current calibration fails if it is not valid
+        report.calibration = match self.get_current_gas_id() {
+            Ok(_) => CheckOutcome::Passed,
+            Err(e) => e.into(),
+        };
+        report
+    }
 
-        if !frame.is_ok() {
-            Err(StateResponseError::from(frame.get_state()))?;
+    /// Reads the settings `desired` asks for, compares them and, unless it's a dry run, writes
+    /// the ones that differ in the order of [DeviceConfig::settings], see
+    /// [config](sfc_core::config). The SFC5xxx has no initial step, it is skipped as
+    /// [SkipReason::Unsupported], and the active calibration can't be read, it is skipped as
+    /// [SkipReason::Unreadable]. The unit is compared without wild cards.
+    ///
+    /// A failed read is returned as an error before anything is written. A failed write stops
+    /// the apply and is reported in [ConfigDiff::failed], the settings written before it stay. A
+    /// [read-only](Device::set_read_only) device only takes dry runs.
+    pub fn apply_config(&mut self, desired: &DeviceConfig, dry_run: bool) -> Result<ConfigDiff, DeviceError> {
+        if !dry_run {
+            self.writable("apply config")?;
         }
-
-        if !frame.validate_checksum() {
-            Err(DeviceError::InvalidChecksum(
-                frame.get_checksum(),
-                frame.calculate_check_sum(),
-            ))?;
+        let current = DeviceConfig {
+            address: desired.address.map(|_| self.get_device_address()).transpose()?,
+            baudrate: desired.baudrate.map(|_| self.get_baudrate().map(u32::from)).transpose()?,
+            controller_gain: desired.controller_gain.map(|_| self.get_user_controller_gain()).transpose()?,
+            initial_step: None,
+            calibration: None,
+            setpoint_persistent: desired.setpoint_persistent.map(|_| self.is_setpoint_persistant()).transpose()?,
+            unit: desired.unit.map(|_| self.get_medium_unit_configuration(false)).transpose()?,
+        };
+        let mut diff = ConfigDiff::compare(desired, current, |setting| match setting {
+            Setting::InitialStep(_) => Some(SkipReason::Unsupported),
+            Setting::Calibration(_) => Some(SkipReason::Unreadable),
+            _ => None,
+        });
+        if dry_run {
+            return Ok(diff);
         }
 
-        Ok(frame)
-    }   
+        diff.apply(|setting| match setting {
+            Setting::Unit(unit) => self.set_medium_unit_configuration(unit),
+            Setting::ControllerGain(gain) => self.set_user_controller_gain(gain),
+            Setting::SetpointPersistent(persist) => self.make_setpoint_persistant(persist),
+            Setting::Baudrate(baudrate) => Baudrate::try_from(baudrate).and_then(|baudrate| self.set_baudrate(baudrate)),
+            Setting::Address(address) => self.set_slave_address(address),
+            // skipped as unsupported, never applied
+            Setting::InitialStep(_) | Setting::Calibration(_) => Ok(()),
+        });
+        Ok(diff)
+    }
+
 }
 
-#[derive(Debug, PartialEq)]
-pub struct BufferedRead {
-    pub lost_values: u32,
-    pub remaning_values: u32,
-    pub sampling_time: f32,
-    pub values: ArrayVec<f32, 60>,
+/// Lets [discovery::connect_using] build the device at the candidates it tries
+impl<T: Transport> ProfiledDevice for Device<T> {
+    type Port = T;
+
+    fn on_port(port: T, address: u8, timeout: Option<Duration>) -> Result<Self, DeviceError> {
+        let mut builder = Self::builder(port).address(address);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        builder.build()
+    }
+
+    fn reset_timeouts(&mut self) {
+        self.set_timeouts(Timeouts::default());
+    }
+
+    fn address(&self) -> u8 {
+        self.slave_address
+    }
+
+    fn read_baudrate(&mut self) -> Result<Baudrate, DeviceError> {
+        self.get_baudrate()
+    }
 }
 
-impl BufferedRead {
-    /// assumes data_len has been checked
-    pub(crate) fn new(data: &[u8]) -> Self {
-        let lost_values = u32::from_be_bytes([data[0],data[1],data[2],data[3]]);
-        let remaning_values = u32::from_be_bytes([data[4],data[5],data[6],data[7]]);
-        let sampling_time =  f32::from_be_bytes([data[8],data[9], data[10], data[11]]);
-        let mut values = ArrayVec::new();
-        for chunk in data[12..].chunks(4) {
-           if chunk.len() < 4 || values.len() == 60 {
-               break;
-           }
-           values.push(f32::from_be_bytes([chunk[0],chunk[1],chunk[2],chunk[3]]));
+/// The physical values of the device in its configured medium unit. The device has no averaging
+/// command, averages are taken over separate reads.
+impl<T: Transport> FlowController for Device<T> {
+    fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError> {
+        Device::set_setpoint(self, setpoint.to_bits(), Scale::PhysicalValue)
+    }
+
+    fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
+        Ok(f32::from_bits(Device::get_setpoint(self, Scale::PhysicalValue)?))
+    }
+
+    fn read_measured_value(&mut self) -> Result<f32, DeviceError> {
+        Ok(f32::from_bits(self.read_measured_flow(Scale::PhysicalValue)?))
+    }
+
+    fn read_average_measured_value(&mut self, measurement_count: u8) -> Result<f32, DeviceError> {
+        let count = measurement_count.max(1);
+        let mut sum = 0.0;
+        for _ in 0..count {
+            sum += FlowController::read_measured_value(self)?;
         }
-        Self {
-            lost_values,
-            remaning_values,
+        Ok(sum / count as f32)
+    }
+
+    fn get_full_scale(&mut self) -> Result<f32, DeviceError> {
+        self.get_converted_fullscale()
+    }
+
+    fn get_gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
+        self.get_medium_unit_configuration(false)
+    }
+
+    fn reset(&mut self) -> Result<(), DeviceError> {
+        self.reset_device()
+    }
+
+    fn get_version(&mut self) -> Result<Version, DeviceError> {
+        Device::get_version(self)
+    }
+
+    fn get_serial_number(&mut self) -> Result<String, DeviceError> {
+        Device::get_serial_number(self)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// Configures a [Device] before it is created, returned by [Device::builder]. Every setting
+/// starts out the way [Device::new] leaves it.
+#[derive(Debug)]
+pub struct DeviceBuilder<T: Transport> {
+    port: T,
+    address: u8,
+    timeouts: Timeouts,
+    inter_byte_timeout: Duration,
+    clear_stale_input: bool,
+    retry: Option<RetryConfig>,
+    boot_grace: Option<BootGrace>,
+    busy_retry: Option<BusyPolicy>,
+    rs485: Option<Rs485Config>,
+    spin: SpinPolicy,
+    validation: ValidationLevel,
+    baudrate: Option<Baudrate>,
+    probe: bool,
+    identity: IdentityFilter,
+    read_only: bool,
+}
+
+impl<T: Transport> DeviceBuilder<T> {
+    /// The slave address of the device, 0 by default
+    pub fn address(mut self, address: u8) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How long the device has to start answering a command, see [Device::set_response_timeout]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts = Timeouts::uniform(timeout);
+        self
+    }
+
+    /// How long the device has to start answering each kind of command, see
+    /// [Device::set_timeouts]
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// The time allowed between two bytes of a response, see [Device::set_inter_byte_timeout]
+    pub fn inter_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.inter_byte_timeout = timeout;
+        self
+    }
+
+    /// Whether pending input is discarded before every command, see
+    /// [Device::set_clear_stale_input]
+    pub fn clear_stale_input(mut self, clear: bool) -> Self {
+        self.clear_stale_input = clear;
+        self
+    }
+
+    /// Retries failed commands, see [Device::set_retry]
+    pub fn retries(mut self, retry: RetryConfig) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// How the device is waited for while it boots after a reset, see [Device::set_boot_grace]
+    pub fn boot_grace(mut self, grace: Option<BootGrace>) -> Self {
+        self.boot_grace = grace;
+        self
+    }
+
+    /// Sends commands the device answers as busy again, see [Device::set_busy_retry]
+    pub fn busy_retry(mut self, policy: BusyPolicy) -> Self {
+        self.busy_retry = Some(policy);
+        self
+    }
+
+    /// Drives an RS-485 adapter with manual direction control, see [Device::set_rs485]
+    pub fn rs485(mut self, rs485: Rs485Config) -> Self {
+        self.rs485 = Some(rs485);
+        self
+    }
+
+    /// How a transport that returns empty reads is waited on, see [Device::set_spin_policy]
+    pub fn spin_policy(mut self, policy: SpinPolicy) -> Self {
+        self.spin = policy;
+        self
+    }
+
+    /// How closely responses are checked, see [Device::set_validation_level]
+    pub fn validation_level(mut self, level: ValidationLevel) -> Self {
+        self.validation = level;
+        self
+    }
+
+    /// Switches the port to this line speed before the device is contacted, for a device that
+    /// was set to another rate than the port was opened with. The port is left as it is by
+    /// default.
+    pub fn baudrate(mut self, baudrate: Baudrate) -> Self {
+        self.baudrate = Some(baudrate);
+        self
+    }
+
+    /// Whether [Device::get_baudrate] is sent to check that the device answers before
+    /// [DeviceBuilder::build] returns. Off by default, like [Device::new] the
+    /// device isn't contacted until the first command.
+    pub fn probe(mut self, probe: bool) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Fails [DeviceBuilder::build] with [DeviceError::IdentityMismatch] unless the device is the one the filter
+    /// asks for, see [Device::assert_identity]. Any device by default, a filter contacts the device even without
+    /// the probe.
+    pub fn identity(mut self, filter: IdentityFilter) -> Self {
+        self.identity = filter;
+        self
+    }
+
+    /// Whether commands that change the device are refused, see [Device::set_read_only]. Off by default.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Creates the device with these settings, probes it if enabled and checks its identity
+    pub fn build(self) -> Result<Device<T>, DeviceError> {
+        let mut connection = Connection::new(self.port);
+        connection.set_timeouts(self.timeouts);
+        connection.set_inter_byte_timeout(self.inter_byte_timeout);
+        connection.set_clear_stale_input(self.clear_stale_input);
+        connection.set_retry(self.retry);
+        connection.set_boot_grace(self.boot_grace);
+        connection.set_busy_retry(self.busy_retry);
+        connection.set_rs485(self.rs485);
+        connection.set_spin_policy(self.spin);
+        connection.set_validation_level(self.validation);
+        if let Some(baudrate) = self.baudrate {
+            connection.set_baud_rate(baudrate.into())?;
+        }
+        let mut device = Device {
+            connection,
+            slave_address: self.address,
+            metadata: None,
+            capabilities: None,
+            soft_limits: SoftLimits::default(),
+            read_only: self.read_only,
+        };
+        if self.probe {
+            let _ = device.get_baudrate()?;
+        }
+        device.assert_identity(&self.identity)?;
+        Ok(device)
+    }
+}
+
+impl Device<NativePort> {
+    /// Opens the serial port with the given name with the settings SHDLC needs (see
+    /// [open_port](sfc_core::discovery::open_port)) and probes the device with
+    /// [Device::get_baudrate], so a wrong port or address fails here.
+    /// ```no_run
+    /// use sfc5xxx_rs::device::Device;
+    /// use sfc_core::baudrate::Baudrate;
+    ///
+    /// let device = Device::open("/dev/ttyUSB0", Baudrate::B115200, 0).unwrap();
+    /// ```
+    pub fn open(port_name: &str, baudrate: Baudrate, slave_address: u8) -> Result<Self, DeviceError> {
+        Self::builder(open_port(port_name, baudrate.into())?).address(slave_address).probe(true).build()
+    }
+
+    /// [Device::open] with a bare number, failing with [DeviceError::UnsupportedBaudrate] for a rate
+    /// the device doesn't support
+    #[deprecated(note = "use open with a Baudrate")]
+    pub fn open_u32(port_name: &str, baud_rate: u32, slave_address: u8) -> Result<Self, DeviceError> {
+        Self::open(port_name, Baudrate::try_from(baud_rate)?, slave_address)
+    }
+
+    /// Opens the first Sensirion cable or USB serial bridge found by
+    /// [find_sensirion_ports](sfc_core::discovery::find_sensirion_ports) at 115200 baud.
+    pub fn open_first_detected(slave_address: u8) -> Result<Self, DeviceError> {
+        let (port, _) = open_first_detected(115200)?;
+        Self::new(port, slave_address)
+    }
+
+    /// Opens the port of a profile at its baudrate and checks that the device at its address has its serial
+    /// number. Another device or none at all is looked for as `fallback` says. Returns the device with the profile
+    /// it was found with, which is the given one unless it had to be looked for. See
+    /// [discovery](sfc_core::discovery).
+    /// ```no_run
+    /// use sfc5xxx_rs::device::Device;
+    /// use sfc_core::baudrate::Baudrate;
+    /// use sfc_core::discovery::{ConnectionProfile, DiscoveryPolicy};
+    ///
+    /// let mut device = Device::open("/dev/ttyUSB0", Baudrate::B115200, 0).unwrap();
+    /// let saved = device.connection_profile(Some("/dev/ttyUSB0")).unwrap();
+    /// drop(device);
+    /// let (device, profile) = Device::connect_with_profile(&saved, &DiscoveryPolicy::scan()).unwrap();
+    /// ```
+    pub fn connect_with_profile(
+        profile: &ConnectionProfile,
+        fallback: &DiscoveryPolicy,
+    ) -> Result<(Self, ConnectionProfile), DeviceError> {
+        Self::connect_with_profile_using(profile, fallback, open_port)
+    }
+
+    /// Opens the serial port with the given name again and [reconnects](Device::reconnect) to
+    /// it, using the baudrate last set on the port or 115200 if it was never changed.
+    pub fn reopen(&mut self, port_name: &str) -> Result<(), DeviceError> {
+        let baud_rate = self.connection.baud_rate().unwrap_or(115200);
+        self.reconnect(open_port(port_name, baud_rate)?)
+    }
+}
+
+/// Reads a big endian u32 from the start of the data
+fn read_u32(data: &[u8]) -> Result<u32, DeviceError> {
+    Ok(u32::from_be_bytes(take(data)?))
+}
+
+/// The first `N` bytes of a response, as an array
+pub(crate) fn take<const N: usize>(data: &[u8]) -> Result<[u8; N], DeviceError> {
+    match data.first_chunk() {
+        Some(&bytes) => Ok(bytes),
+        None => Err(TranslationError::NotEnoughData(N as u8, data.len() as u8))?,
+    }
+}
+
+/// The most measurements a single buffered read returns, what fits into a frame payload after
+/// the 12 byte header
+pub const MAX_BUFFERED_VALUES: usize = (MAX_PAYLOAD - 12) / 4;
+const _: () = assert!(12 + 4 * MAX_BUFFERED_VALUES <= MAX_PAYLOAD);
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BufferedRead {
+    pub lost_values: u32,
+    #[cfg_attr(feature = "serde", serde(rename = "remaining_values"))]
+    pub remaning_values: u32,
+    pub sampling_time: f32,
+    pub values: ArrayVec<f32, MAX_BUFFERED_VALUES>,
+}
+
+impl BufferedRead {
+    /// Parses the 12 byte header and the values after it, a partial value at the end is dropped
+    pub(crate) fn new(data: &[u8]) -> Result<Self, DeviceError> {
+        let Some((header, rest)) = data.split_first_chunk::<12>() else {
+            Err(TranslationError::NotEnoughData(12, data.len() as u8))?
+        };
+        let lost_values = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let remaning_values = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let sampling_time = f32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let mut values = ArrayVec::new();
+        for chunk in rest.chunks_exact(4).take(MAX_BUFFERED_VALUES) {
+            values.push(f32::from_be_bytes(take(chunk)?));
+        }
+        Ok(Self {
+            lost_values,
+            remaning_values,
             sampling_time,
             values
+        })
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for BufferedRead {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "BufferedRead {{ lost_values: {}, remaning_values: {}, sampling_time: {}, values: {} }}",
+            self.lost_values,
+            self.remaning_values,
+            self.sampling_time,
+            self.values.as_slice()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use approx::assert_relative_eq;
+
+    use sfc_core::error::StateResponseError;
+    use sfc_core::gasunit::{Prefixes, TimeBases, Units};
+    use sfc_core::connection::DEFAULT_RESPONSE_TIMEOUT;
+    use sfc_core::measurement::ValueScale;
+    use sfc_core::shdlc::{from_shdlc, to_shdlc};
+
+    use super::*;
+    use sfc_core::middleware::CommandBlacklist;
+    use sfc_core::mock::{Expectation, MockHandle, MockPort};
+    use crate::emulator::{EmulatorConfig, EmulatorHandle, Fault, Sfc5xxxEmulator};
+
+    fn create_device() -> (Device<Sfc5xxxEmulator>, EmulatorHandle) {
+        create_device_with(EmulatorConfig::default())
+    }
+
+    fn create_device_with(config: EmulatorConfig) -> (Device<Sfc5xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc5xxxEmulator::new(config);
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    /// A port that fails the test as soon as anything is read or written
+    struct Untouchable;
+
+    impl std::io::Read for Untouchable {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            panic!("read from the port")
+        }
+    }
+
+    impl std::io::Write for Untouchable {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            panic!("wrote to the port")
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            panic!("flushed the port")
+        }
+    }
+
+    impl Transport for Untouchable {
+        fn timeout(&self) -> Duration {
+            Duration::ZERO
+        }
+
+        fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+            panic!("set the timeout of the port")
+        }
+    }
+
+    #[test]
+    fn debug_formatting_never_touches_the_port() {
+        let device = Device::builder(Untouchable)
+            .address(3)
+            .baudrate(Baudrate::B19200)
+            .probe(false)
+            .build()
+            .unwrap();
+        let debug = format!("{:?}", device);
+        assert!(debug.starts_with("Device { slave_address: 3, connection: Connection {"), "{}", debug);
+        assert!(debug.contains("baud_rate: Some(19200)"), "{}", debug);
+        assert!(debug.contains("commands: 0"), "{}", debug);
+        assert!(debug.contains("metadata_cached: false"), "{}", debug);
+    }
+
+    /// A change of the device and the name it is refused with
+    type Change = (&'static str, fn(&mut Device<Untouchable>) -> Result<(), DeviceError>);
+
+    #[test]
+    fn read_only_refuses_every_change_before_the_port() {
+        use sfc_core::profile::Profile;
+
+        let mut device = Device::builder(Untouchable).probe(false).read_only(true).build().unwrap();
+        let changes: &[Change] = &[
+            ("setpoint", |device| device.set_setpoint(1.0f32.to_bits(), Scale::PhysicalValue)),
+            ("setpoint", |device| {
+                let deadline = Instant::now() + Duration::from_secs(1);
+                device.set_setpoint_with_deadline(1.0f32.to_bits(), Scale::Normilized, deadline)
+            }),
+            ("set setpoint and read measured value", |device| {
+                device.set_setpoint_and_read_measured_value(Scale::PhysicalValue, 1.0).map(drop)
+            }),
+            ("set setpoint and read measured value of both sensors", |device| {
+                device.set_setpoint_and_read_measured_value_two_sensors(Scale::PhysicalValue, 1.0).map(drop)
+            }),
+            ("user controller gain", |device| device.set_user_controller_gain(2.0)),
+            ("calibration", |device| device.set_callibration(1)),
+            ("slave address", |device| device.set_slave_address(4)),
+            ("baudrate", |device| device.set_baudrate(Baudrate::B19200)),
+            ("device reset", |device| device.reset_device()),
+            ("factory reset", |device| device.factory_reset()),
+            ("user memory", |device| device.write_user_memory(0, &[1, 2, 3])),
+            ("raw thermal conductivity, valve closed", |device| device.measure_raw_thermal_conductivity(true).map(drop)),
+            ("device error state", |device| device.get_device_error_state(true).map(drop)),
+            ("raw command", |device| device.start_command(0x00, &[0x01]).map(drop)),
+            ("apply config", |device| {
+                let config = DeviceConfig { address: Some(4), ..DeviceConfig::default() };
+                device.apply_config(&config, false).map(drop)
+            }),
+            ("run profile", |device| {
+                let profile = Profile::new().ramp(1.0, Duration::from_secs(1));
+                device.run_profile(&profile, Duration::from_millis(10)).map(drop)
+            }),
+        ];
+        for (name, change) in changes {
+            match change(&mut device) {
+                Err(DeviceError::ReadOnly(refused)) => assert_eq!(refused, *name),
+                other => panic!("{} was not refused: {:?}", name, other),
+            }
+        }
+        for (command, ..) in crate::commands::tests::catalog() {
+            if command.writes() {
+                match device.pipeline(&[Command::GetSetpointPersistence, command.clone()]) {
+                    Err(DeviceError::ReadOnly(refused)) => assert_eq!(refused, command.name()),
+                    other => panic!("{} was not refused: {:?}", command.name(), other),
+                }
+            }
+        }
+        assert_eq!(device.stats().commands, 0);
+    }
+
+    #[test]
+    fn read_only_still_reads() {
+        let emulator = Sfc5xxxEmulator::default();
+        let handle = emulator.handle();
+        let mut device = Device::builder(emulator).read_only(true).build().unwrap();
+        assert!(device.read_only());
+        assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        device.read_measured_flow(Scale::PhysicalValue).unwrap();
+        device.measure_raw_thermal_conductivity(false).unwrap();
+        device.get_device_error_state(false).unwrap();
+        let config = DeviceConfig { address: Some(4), ..DeviceConfig::default() };
+        assert_eq!(device.apply_config(&config, true).unwrap().changed.len(), 1);
+        assert!(matches!(device.set_setpoint(0.5f32.to_bits(), Scale::Normilized), Err(DeviceError::ReadOnly(_))));
+        assert_eq!(handle.setpoint(), 0.0);
+        assert_eq!(handle.address(), 0);
+
+        device.set_read_only(false);
+        device.set_setpoint(0.5f32.to_bits(), Scale::Normilized).unwrap();
+        assert_relative_eq!(handle.setpoint(), 0.5);
+    }
+
+    #[test]
+    fn device_over_tcp() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        use sfc_core::transport::TcpTransport;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // a minimal device server bridging the socket to an emulator
+        let server = std::thread::spawn(move || {
+            let mut emulator = Sfc5xxxEmulator::default();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0_u8; 64];
+            loop {
+                let read = stream.read(&mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                emulator.write_all(&buf[..read]).unwrap();
+                // send the response one byte at a time like a slow serial line would
+                while let Ok(1) = emulator.read(&mut buf[..1]) {
+                    stream.write_all(&buf[..1]).unwrap();
+                }
+            }
+        });
+
+        let mut device = Device::new(TcpTransport::connect(address).unwrap(), 0).unwrap();
+        assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        device.set_setpoint_and_read_measured_value(Scale::PhysicalValue, 1.0).unwrap();
+        assert_relative_eq!(f32::from_bits(device.get_setpoint(Scale::PhysicalValue).unwrap()), 1.0);
+        drop(device);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn responses_have_the_declared_length() {
+        for (command, ..) in crate::commands::tests::catalog() {
+            let (mut device, _) = create_device();
+            let length = device.run(command.clone(), |data| Ok(data.len())).unwrap();
+            assert!(command.response_length().check(&vec![0; length]).is_ok(), "{}", command.name());
+
+            let Some(short) = command.response_length().min().checked_sub(1) else { continue };
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::ShortData(short.into()));
+            assert!(
+                matches!(
+                    device.run(command.clone(), |data| Ok(data.len())),
+                    Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(min, found)))
+                        if min == short + 1 && found == short
+                ),
+                "{}",
+                command.name()
+            );
+        }
+    }
+
+    #[test]
+    fn device_information() {
+        let (mut device, _) = create_device();
+        assert_eq!(device.get_product_name().unwrap(), "SFC5400");
+        assert_eq!(device.get_article_code().unwrap(), "3.000.000");
+        assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        let version = device.get_version().unwrap();
+        assert_eq!((version.firmware_major, version.firmware_minor), (1, 48));
+    }
+
+    #[test]
+    fn set_get_setpoint() {
+        let (mut device, handle) = create_device();
+        device.set_setpoint(2.5_f32.to_bits(), Scale::PhysicalValue).unwrap();
+        assert_relative_eq!(handle.setpoint(), 0.5);
+        let normalized = f32::from_bits(device.get_setpoint(Scale::Normilized).unwrap());
+        assert_relative_eq!(normalized, 0.5);
+        let measured = f32::from_bits(device.read_measured_flow(Scale::PhysicalValue).unwrap());
+        assert_relative_eq!(measured, 2.5);
+    }
+
+    /// The unit and serial number are read with the first recorded value only, and again after
+    /// the medium unit changed
+    #[test]
+    fn recorded_values_read_the_metadata_once() {
+        let (mut device, handle) = create_device();
+        device.set_setpoint(2.5_f32.to_bits(), Scale::PhysicalValue).unwrap();
+        let before = handle.requests().len();
+        let first = device.read_measured_flow_recorded(Scale::PhysicalValue).unwrap();
+        assert_eq!(handle.requests().len(), before + 4);
+        let second = device.read_measured_flow_recorded(Scale::Normilized).unwrap();
+        assert_eq!(handle.requests().len(), before + 5);
+
+        assert_relative_eq!(first.value, 2.5);
+        assert_eq!(first.scale, ValueScale::Physical);
+        assert_eq!(first.serial_number.as_deref(), Some("EMU0000001"));
+        assert_eq!(first.address, Some(0));
+        assert_eq!(first.full_scale, Some(5.0));
+        assert_eq!(first.to_string().split(" from ").next(), Some("2.500 l/min (50.0% FS)"));
+        assert_relative_eq!(second.value, 0.5);
+        assert_eq!(second.scale, ValueScale::Normalized);
+        assert!(first < second);
+
+        let unit = GasUnit {
+            unit_prefex: Prefixes::Milli,
+            medium_unit: Units::StandardLiter,
+            timebase: TimeBases::Minute,
+        };
+        device.set_medium_unit_configuration(unit).unwrap();
+        let before = handle.requests().len();
+        assert_eq!(device.read_measured_flow_recorded(Scale::PhysicalValue).unwrap().unit, unit);
+        assert_eq!(handle.requests().len(), before + 4);
+    }
+
+    #[test]
+    fn setpoint_out_of_range() {
+        let (mut device, _) = create_device();
+        let res = device.set_setpoint(1.5_f32.to_bits(), Scale::Normilized);
+        match res {
+            Err(DeviceError::StateResponse(StateResponseError::ParameterError)) => {}
+            _ => panic!("expected, StateResponseError::ParameterError"),
+        }
+    }
+
+    /// Every method writing a setpoint checks the soft limits, normalized setpoints after
+    /// converting them with the full scale of 5
+    #[test]
+    fn soft_limits_on_every_setpoint() {
+        use sfc_core::limits::Limit;
+
+        let (mut device, handle) = create_device();
+        device.set_soft_limits(0.0, 4.0);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        for result in [
+            device.set_setpoint(4.5_f32.to_bits(), Scale::PhysicalValue),
+            device.set_setpoint_with_deadline(4.5_f32.to_bits(), Scale::UserDefined, deadline),
+            device.set_setpoint(0.9_f32.to_bits(), Scale::Normilized),
+            device.set_setpoint_and_read_measured_value(Scale::PhysicalValue, 4.5).map(drop),
+            device.set_setpoint_and_read_measured_value_two_sensors(Scale::Normilized, 0.9).map(drop),
+            FlowController::set_setpoint(&mut device, 4.5),
+        ] {
+            assert!(matches!(result, Err(DeviceError::SoftLimit(setpoint, Limit::Max(4.0))) if setpoint == 4.5));
+        }
+        assert_relative_eq!(handle.setpoint(), 0.0);
+
+        device.set_setpoint(0.7_f32.to_bits(), Scale::Normilized).unwrap();
+        assert_relative_eq!(handle.setpoint(), 0.7);
+
+        device.set_soft_limit_policy(LimitPolicy::Clamp);
+        device.set_setpoint(0.9_f32.to_bits(), Scale::Normilized).unwrap();
+        assert_relative_eq!(handle.setpoint(), 0.8);
+        device.set_setpoint_and_read_measured_value(Scale::PhysicalValue, 4.5).unwrap();
+        assert_relative_eq!(f32::from_bits(device.get_setpoint(Scale::PhysicalValue).unwrap()), 4.0);
+
+        device.clear_soft_limits();
+        device.set_setpoint(5.0_f32.to_bits(), Scale::PhysicalValue).unwrap();
+        assert_relative_eq!(handle.setpoint(), 1.0);
+    }
+
+    #[test]
+    fn setpoint_persistence_survives_reset() {
+        let (mut device, _) = create_device();
+        device.set_setpoint_and_read_measured_value(Scale::Normilized, 0.25).unwrap();
+        device.reset_device().unwrap();
+        assert_eq!(device.get_setpoint(Scale::Normilized).unwrap(), 0);
+
+        device.make_setpoint_persistant(true).unwrap();
+        assert!(device.is_setpoint_persistant().unwrap());
+        device.set_setpoint_and_read_measured_value(Scale::Normilized, 0.25).unwrap();
+        device.reset_device().unwrap();
+        assert_relative_eq!(f32::from_bits(device.get_setpoint(Scale::Normilized).unwrap()), 0.25);
+    }
+
+    #[test]
+    fn buffered_read_drains_samples() {
+        let (mut device, handle) = create_device();
+        device.set_setpoint_and_read_measured_value(Scale::PhysicalValue, 1.0).unwrap();
+        handle.advance(Duration::from_millis(30));
+
+        let read = device.read_measured_flow_buffered(Scale::PhysicalValue).unwrap();
+        assert_eq!(read.lost_values, 0);
+        assert_eq!(read.remaning_values, 0);
+        assert_relative_eq!(read.sampling_time, 0.001);
+        assert_eq!(read.values.len(), 30);
+        assert!(read.values.iter().all(|&v| (v - 1.0).abs() < 1e-6));
+        assert_eq!(handle.buffered_samples(), 0);
+    }
+
+    #[test]
+    fn buffered_read_reports_remaining_and_lost() {
+        let (mut device, handle) = create_device();
+        handle.advance(Duration::from_millis(150));
+
+        let read = device.read_measured_flow_buffered(Scale::Normilized).unwrap();
+        assert_eq!(read.lost_values, 50);
+        assert_eq!(read.values.len(), 60);
+        assert_eq!(read.remaning_values, 40);
+
+        let read = device.read_measured_flow_buffered(Scale::Normilized).unwrap();
+        assert_eq!(read.lost_values, 0);
+        assert_eq!(read.values.len(), 40);
+        assert_eq!(read.remaning_values, 0);
+    }
+
+    #[test]
+    fn statistics_from_the_buffer() {
+        let (mut device, handle) = create_device();
+        device.set_setpoint(2.5_f32.to_bits(), Scale::PhysicalValue).unwrap();
+        handle.advance(Duration::from_millis(100));
+
+        // takes more than one read of at most 60 values
+        let statistics = device.sample_statistics_buffered_with_samples(80, Scale::PhysicalValue).unwrap();
+        assert_eq!(statistics.count, 80);
+        assert_relative_eq!(statistics.mean, 2.5);
+        assert_relative_eq!(statistics.std_dev, 0.0);
+        assert_relative_eq!(statistics.peak_to_peak, 0.0);
+        assert_eq!(statistics.samples.unwrap().len(), 80);
+        assert!(statistics.interrupted.is_none());
+        // the values past the 80th were read and dropped
+        assert_eq!(handle.buffered_samples(), 0);
+
+        // nothing is buffered, the device samples while this waits
+        let sampling = handle.clone();
+        let sampler = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            sampling.advance(Duration::from_millis(10));
+        });
+        let statistics = device.sample_statistics_buffered(10, Scale::PhysicalValue).unwrap();
+        sampler.join().unwrap();
+        assert_eq!(statistics.count, 10);
+        assert_relative_eq!(statistics.mean, 2.5);
+    }
+
+    #[test]
+    fn statistics_interrupted_between_reads() {
+        let (mut device, handle) = create_device();
+        device.capabilities().unwrap();
+        handle.advance(Duration::from_millis(100));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x2D));
+
+        let statistics = device.sample_statistics_buffered(80, Scale::Normilized).unwrap();
+        assert_eq!(statistics.count, 60);
+        assert!(statistics.samples.is_none());
+        assert!(!statistics.is_complete());
+
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        assert!(device.sample_statistics_buffered(80, Scale::Normilized).is_err());
+    }
+
+    #[test]
+    fn buffer_depth_and_sampling_time_are_configurable() {
+        let config = EmulatorConfig {
+            sampling_time: 0.01,
+            buffer_depth: 5,
+            ..Default::default()
+        };
+        let (mut device, handle) = create_device_with(config);
+        handle.advance(Duration::from_millis(95));
+
+        let read = device.read_measured_flow_buffered(Scale::Normilized).unwrap();
+        assert_relative_eq!(read.sampling_time, 0.01);
+        assert_eq!(read.values.len(), 5);
+        assert_eq!(read.lost_values, 4);
+    }
+
+    #[test]
+    fn medium_unit_changes_converted_fullscale() {
+        let (mut device, _) = create_device();
+        assert_relative_eq!(device.get_converted_fullscale().unwrap(), 5.0);
+
+        let unit = GasUnit {
+            unit_prefex: Prefixes::Milli,
+            medium_unit: Units::StandardLiter,
+            timebase: TimeBases::Second,
+        };
+        device.set_medium_unit_configuration(unit).unwrap();
+        assert_eq!(device.get_medium_unit_configuration(false).unwrap(), unit);
+        assert_relative_eq!(device.get_converted_fullscale().unwrap(), 5000.0 / 60.0, epsilon = 0.001);
+
+        device.set_setpoint_and_read_measured_value(Scale::Normilized, 0.5).unwrap();
+        let measured = f32::from_bits(device.read_measured_flow(Scale::PhysicalValue).unwrap());
+        assert_relative_eq!(measured, 2500.0 / 60.0, epsilon = 0.001);
+    }
+
+    #[test]
+    fn incompatible_medium_unit_is_rejected() {
+        let (mut device, _) = create_device();
+        let unit = GasUnit {
+            unit_prefex: Prefixes::Base,
+            medium_unit: Units::Gram,
+            timebase: TimeBases::Minute,
+        };
+        match device.set_medium_unit_configuration(unit) {
+            Err(DeviceError::StateResponse(StateResponseError::ParameterError)) => {}
+            _ => panic!("expected, StateResponseError::ParameterError"),
+        }
+    }
+
+    #[test]
+    fn calibration_table() {
+        let (mut device, _) = create_device();
+        assert_eq!(device.get_number_of_calibrations().unwrap(), 4);
+        assert!(device.get_calibration_validity(2).unwrap());
+        assert!(!device.get_calibration_validity(3).unwrap());
+        assert_eq!(device.get_calibration_gas_description(2).unwrap(), "CO2");
+        assert_eq!(device.get_calibration_gas_id(2).unwrap(), 3);
+        assert_relative_eq!(device.get_calibration_fullscale(2).unwrap(), 2.5);
+        assert_eq!(device.get_calibration_thermal_conductivity_refrence(2).unwrap(), 830);
+
+        match device.get_calibration_gas_id(3) {
+            Err(DeviceError::StateResponse(StateResponseError::InvalidCalibration)) => {}
+            _ => panic!("expected, StateResponseError::InvalidCalibration"),
+        }
+    }
+
+    #[test]
+    fn calibration_condition_blocks() {
+        let config = EmulatorConfig::default();
+        let expected = config.calibrations[1].as_ref().unwrap().initial_conditions.clone();
+        let (mut device, _) = create_device_with(config);
+
+        assert_eq!(device.get_calibration_initial_conditions(1).unwrap(), expected);
+        assert_eq!(device.get_calibration_recalibration_conditions(1).unwrap(), expected);
+        assert_eq!(device.get_current_initial_calibration_conditions().unwrap(), expected);
+    }
+
+    #[test]
+    fn switch_calibration() {
+        let (mut device, handle) = create_device();
+        device.set_callibration(2).unwrap();
+        assert_eq!(handle.active_calibration(), 2);
+        assert_eq!(device.get_current_gas_id().unwrap(), 3);
+        assert_eq!(device.get_current_gas_description().unwrap(), "CO2");
+        assert_relative_eq!(device.get_current_fullscale().unwrap(), 2.5);
+        assert_eq!(device.get_current_thermal_conducitvity_refrence().unwrap(), 830);
+    }
+
+    #[test]
+    fn valve_input_source() {
+        let (mut device, _) = create_device();
+        device.set_valve_input_source(InputSourceConfig::ForceClosed).unwrap();
+        assert_eq!(device.get_valve_input_source().unwrap(), InputSourceConfig::ForceClosed);
+        device.set_valve_input_source(InputSourceConfig::UserDefined(0.3)).unwrap();
+        assert_eq!(device.get_valve_input_source().unwrap(), InputSourceConfig::UserDefined(0.3));
+    }
+
+    #[test]
+    fn user_memory_round_trip() {
+        let (mut device, _) = create_device();
+        device.write_user_memory(10, &[1, 2, 3, 0x7E, 0x7D]).unwrap();
+        assert_eq!(device.read_user_memory(10, 5).unwrap(), vec![1, 2, 3, 0x7E, 0x7D]);
+    }
+
+    #[test]
+    fn change_slave_address() {
+        let (mut device, handle) = create_device();
+        device.set_slave_address(5).unwrap();
+        assert_eq!(handle.address(), 5);
+        assert_eq!(device.get_device_address().unwrap(), 5);
+    }
+
+    #[test]
+    fn baudrate_round_trip() {
+        let (mut device, handle) = create_device();
+        device.set_baudrate(Baudrate::B57600).unwrap();
+        assert_eq!(handle.baudrate(), 57600);
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B57600);
+
+        // an unchecked rate is sent and refused by the device
+        assert!(matches!(device.set_baudrate(Baudrate::Other(9600)), Err(DeviceError::StateResponse(_))));
+        assert_eq!(handle.baudrate(), 57600);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn baudrate_u32_shims() {
+        let (mut device, handle) = create_device();
+        assert_eq!(device.get_baudrate_u32().unwrap(), 115200);
+        let requests = handle.requests().len();
+        assert!(matches!(device.set_baudrate_u32(9600), Err(DeviceError::UnsupportedBaudrate(9600))));
+        assert_eq!(handle.requests().len(), requests);
+        device.set_baudrate_u32(38400).unwrap();
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B38400);
+    }
+
+    #[test]
+    fn device_error_state_clears() {
+        let (mut device, handle) = create_device();
+        handle.set_device_error(0x0000_0102, 0x04);
+        assert_eq!(device.get_device_error_state(true).unwrap(), (0x0102, 0x04));
+        assert_eq!(device.get_device_error_state(false).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn devices_on_a_shared_bus() {
+        let emulator = Sfc5xxxEmulator::default();
+        let handle = emulator.handle();
+        let bus = SharedBus::new(emulator);
+        let mut device = Device::on_bus(&bus, 0).unwrap();
+        let mut absent = Device::on_bus(&bus, 1).unwrap();
+        absent.set_response_timeout(Duration::from_millis(20));
+
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+        assert!(matches!(absent.get_baudrate(), Err(DeviceError::Timeout)));
+        assert_eq!(handle.requests().len(), 2);
+    }
+
+    #[test]
+    fn dropped_response_times_out() {
+        let (mut device, handle) = create_device();
+        handle.inject_fault(Fault::DropResponse);
+        match device.get_baudrate() {
+            Err(DeviceError::Timeout) => {}
+            _ => panic!("expected, DeviceError::Timeout"),
+        }
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+    }
+
+    #[test]
+    fn corrupted_checksum_is_detected() {
+        let (mut device, handle) = create_device();
+        handle.inject_fault(Fault::CorruptChecksum);
+        match device.get_baudrate() {
+            Err(DeviceError::InvalidChecksum(_, _)) => {}
+            _ => panic!("expected, DeviceError::InvalidChecksum"),
+        }
+    }
+
+    #[test]
+    fn corrupted_checksum_is_retried() {
+        let (mut device, handle) = create_device();
+        device.set_retry(Some(RetryConfig::default()));
+        handle.inject_fault(Fault::CorruptChecksum);
+        handle.inject_fault(Fault::CorruptChecksum);
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+
+        // reading the buffer drains it so it is never sent twice
+        device.capabilities().unwrap();
+        handle.inject_fault(Fault::CorruptChecksum);
+        match device.read_measured_flow_buffered(Scale::PhysicalValue) {
+            Err(DeviceError::InvalidChecksum(_, _)) => {}
+            _ => panic!("expected, DeviceError::InvalidChecksum"),
+        }
+    }
+
+    #[test]
+    fn polled_command() {
+        let (mut device, _) = create_device();
+        let mut pending = device.start_command(0x91, &[]).unwrap();
+        let response = loop {
+            if let std::task::Poll::Ready(response) = pending.poll() {
+                break response.unwrap();
+            }
+        };
+        assert_eq!(response.into_data().as_slice(), &115200_u32.to_be_bytes());
+        drop(pending);
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+    }
+
+    #[test]
+    fn builder_applies_every_setting() {
+        let emulator = Sfc5xxxEmulator::new(EmulatorConfig {
+            address: 3,
+            ..Default::default()
+        });
+        let handle = emulator.handle();
+        let mut device = Device::builder(emulator)
+            .address(3)
+            .timeout(Duration::from_millis(30))
+            .inter_byte_timeout(Duration::from_millis(5))
+            .clear_stale_input(false)
+            .retries(RetryConfig::default())
+            .boot_grace(Some(BootGrace::default()))
+            .busy_retry(BusyPolicy::new(Duration::from_millis(50), Duration::from_millis(5)))
+            .validation_level(ValidationLevel::Strict)
+            .probe(true)
+            .build()
+            .unwrap();
+        assert_eq!(handle.requests().len(), 1);
+        assert_eq!(device.validation_level(), ValidationLevel::Strict);
+        assert_eq!(device.boot_grace(), Some(BootGrace::default()));
+        assert_eq!(device.busy_retry().map(|policy| policy.max_wait), Some(Duration::from_millis(50)));
+        assert_eq!(device.inter_byte_timeout(), Duration::from_millis(5));
+        assert!(!device.clear_stale_input());
+
+        handle.inject_fault(Fault::CorruptChecksum);
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+
+        for _ in 0..3 {
+            handle.inject_fault(Fault::DropResponse);
+        }
+        let start = Instant::now();
+        assert!(device.get_baudrate().is_err());
+        // three attempts, each waiting the 30ms timeout instead of the default 600ms
+        assert!(start.elapsed() < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn builder_does_not_probe_by_default() {
+        let emulator = Sfc5xxxEmulator::default();
+        let handle = emulator.handle();
+        let device = Device::builder(emulator).build().unwrap();
+        assert!(handle.requests().is_empty());
+        assert_eq!(device.response_timeout(), DEFAULT_RESPONSE_TIMEOUT);
+        assert_eq!(device.timeouts(), Timeouts::default());
+    }
+
+    #[test]
+    fn builder_switches_the_port_baudrate() {
+        let emulator = Sfc5xxxEmulator::default();
+        let handle = emulator.handle();
+        let _device = Device::builder(emulator).baudrate(Baudrate::B19200).build().unwrap();
+        assert_eq!(handle.line_baudrate(), 19200);
+        assert!(handle.requests().is_empty());
+    }
+
+    #[test]
+    fn identity_of_the_emulator() {
+        let (mut device, _) = create_device();
+        let filter = IdentityFilter {
+            serial_number: Some("EMU0000001".to_string()),
+            article_code_prefix: Some("3.000.".to_string()),
+            // the product name stands in for the product type
+            product_type: Some("SFC5400".to_string()),
+        };
+        device.assert_identity(&filter).unwrap();
+
+        let filter = IdentityFilter::serial_number("EMU0000002");
+        match device.assert_identity(&filter) {
+            Err(DeviceError::IdentityMismatch { field, expected, found }) => {
+                assert_eq!(field, IdentityField::SerialNumber);
+                assert_eq!(expected, "EMU0000002");
+                assert_eq!(found, "EMU0000001");
+            }
+            other => panic!("expected an identity mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn builder_checks_the_identity() {
+        let device = Device::builder(Sfc5xxxEmulator::default())
+            .identity(IdentityFilter::serial_number("EMU0000001"))
+            .build();
+        assert!(device.is_ok());
+
+        let emulator = Sfc5xxxEmulator::default();
+        let handle = emulator.handle();
+        let result = Device::builder(emulator)
+            .identity(IdentityFilter::serial_number("EMU0000002"))
+            .build();
+        assert!(matches!(result, Err(DeviceError::IdentityMismatch { .. })));
+        // only the serial number was read
+        assert_eq!(handle.requests().len(), 1);
+    }
+
+    /// Opens the emulators by the names of their ports, other ports don't exist
+    fn ports(ports: Vec<(&'static str, EmulatorHandle)>) -> impl FnMut(&str, u32) -> Result<Sfc5xxxEmulator, DeviceError> {
+        move |name, _| match ports.iter().find(|(port, _)| *port == name) {
+            Some((_, handle)) => Ok(handle.replug()),
+            None => Err(serialport::Error::new(serialport::ErrorKind::NoDevice, name).into()),
+        }
+    }
+
+    #[test]
+    fn a_profile_connects_to_the_same_device_again() {
+        let (mut device, handle) = create_device();
+        let profile = device.connection_profile(Some("COM3")).unwrap();
+        assert_eq!(
+            profile,
+            ConnectionProfile {
+                port_hint: Some("COM3".to_string()),
+                serial: "EMU0000001".to_string(),
+                address: 0,
+                baudrate: Baudrate::B115200,
+            }
+        );
+        drop(device);
+
+        let (mut device, found) =
+            Device::connect_with_profile_using(&profile, &DiscoveryPolicy::Never, ports(vec![("COM3", handle.clone())])).unwrap();
+        assert_eq!(found, profile);
+        assert_eq!(handle.last_request().map(|(_, command, _)| command), Some(0xD0));
+        assert_eq!(device.response_timeout(), DEFAULT_RESPONSE_TIMEOUT);
+        device.set_setpoint(0.5f32.to_bits(), Scale::Normilized).unwrap();
+    }
+
+    #[test]
+    fn another_serial_number_falls_back_to_discovery() {
+        let stranger = Sfc5xxxEmulator::new(EmulatorConfig {
+            serial_number: "EMU0000002".to_string(),
+            ..Default::default()
+        });
+        let moved = Sfc5xxxEmulator::new(EmulatorConfig { address: 1, ..Default::default() });
+        let profile = ConnectionProfile {
+            port_hint: Some("COM3".to_string()),
+            serial: "EMU0000001".to_string(),
+            address: 0,
+            baudrate: Baudrate::B115200,
+        };
+        let line = vec![("COM3", stranger.handle()), ("COM4", moved.handle())];
+
+        let result = Device::connect_with_profile_using(&profile, &DiscoveryPolicy::Never, ports(line.clone()));
+        assert!(matches!(result, Err(DeviceError::IdentityMismatch { found, .. }) if found == "EMU0000002"));
+
+        let fallback = DiscoveryPolicy::Scan {
+            ports: vec!["COM3".to_string(), "COM4".to_string()],
+            baudrates: vec![Baudrate::B115200],
+            addresses: 0..=1,
+            timeout: Duration::from_millis(10),
+        };
+        let (mut device, found) = Device::connect_with_profile_using(&profile, &fallback, ports(line)).unwrap();
+        assert_eq!(found.port_hint.as_deref(), Some("COM4"));
+        assert_eq!(found.address, 1);
+        assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        assert_eq!(device.response_timeout(), DEFAULT_RESPONSE_TIMEOUT);
+    }
+
+    /// A device on a port that answers the frames the test queues on the handle
+    fn mocked_device() -> (Device<MockPort>, MockHandle) {
+        let port = MockPort::new();
+        let handle = port.handle();
+        (Device::builder(port).build().unwrap(), handle)
+    }
+
+    /// Expects the frame of the command to the device at address 0
+    fn expect(command: Command) -> Expectation {
+        let frame = command.encode(0).unwrap();
+        Expectation::new(0, frame.get_command_number(), &frame.data().unwrap())
+    }
+
+    #[test]
+    fn a_locked_configuration_is_named_as_the_cause() {
+        let (mut device, handle) = mocked_device();
+        let unit =
+            GasUnit { unit_prefex: Prefixes::Milli, medium_unit: Units::StandardLiter, timebase: TimeBases::Minute };
+        handle.expect(expect(Command::SetMediumUnit { unit }).respond_with_state(0x03, &[]));
+        handle.expect(expect(Command::SetBaudrate { baudrate: 19200 }).respond_with_state(0x03, &[]));
+        handle.expect(expect(Command::SetCalibration { index: 1 }).respond_with_state(0x03, &[]));
+
+        let locked = |result: Result<(), DeviceError>| match result {
+            Err(e @ DeviceError::StateResponse(StateResponseError::NoAccessRight)) => {
+                assert!(e.to_string().contains("locked"), "{}", e);
+                assert_eq!(e.code(), 0x0303);
+            }
+            other => panic!("expected the lock to refuse the change, got {:?}", other),
+        };
+        locked(device.set_medium_unit_configuration(unit));
+        locked(device.set_baudrate(Baudrate::B19200));
+        locked(device.set_callibration(1));
+        handle.assert_done();
+    }
+
+    #[test]
+    fn every_access_level_state_is_a_locked_configuration() {
+        let (mut device, handle) = mocked_device();
+        handle.expect(expect(Command::SetSlaveAddress { address: 4 }).respond_with_state(0x20, &[]));
+        let write = Command::WriteUserMemory { start_address: 0, data: vec![1] };
+        handle.expect(expect(write).respond_with_state(0x21, &[]));
+        handle.expect(expect(Command::SetSetpointPersistence { persist: true }).respond_with_state(0x04, &[]));
+
+        match device.set_slave_address(4) {
+            Err(e @ DeviceError::StateResponse(StateResponseError::ConfigurationLocked)) => {
+                assert!(e.is_configuration_locked());
+                assert_eq!(e.code(), 0x0320);
+            }
+            other => panic!("expected the locked configuration, got {:?}", other),
+        }
+        assert_eq!(device.slave_address, 0);
+        match device.write_user_memory(0, &[1]) {
+            Err(e @ DeviceError::StateResponse(StateResponseError::AccessLevelTooLow)) => {
+                assert!(e.is_configuration_locked());
+                assert!(e.to_string().contains("access level"), "{}", e);
+            }
+            other => panic!("expected a too low access level, got {:?}", other),
+        }
+        // any other refusal is not the lock
+        let error = device.make_setpoint_persistant(true).unwrap_err();
+        assert!(!error.is_configuration_locked(), "{:?}", error);
+        handle.assert_done();
+    }
+
+    #[test]
+    fn a_locked_device_still_takes_setpoints() {
+        let (mut device, handle) = mocked_device();
+        handle.expect(expect(Command::SetControllerGain { gain: 2.0 }).respond_with_state(0x03, &[]));
+        let setpoint = Command::SetSetpoint { scale: Scale::Normilized, value: 0.5f32.to_bits() };
+        handle.expect(expect(setpoint).respond(&[]));
+        assert!(matches!(
+            device.set_user_controller_gain(2.0),
+            Err(DeviceError::StateResponse(StateResponseError::NoAccessRight))
+        ));
+        device.set_setpoint(0.5f32.to_bits(), Scale::Normilized).unwrap();
+        handle.assert_done();
+    }
+
+    #[test]
+    fn a_blacklisted_command_is_never_sent() {
+        let (mut device, handle) = mocked_device();
+        let gain = Command::SetControllerGain { gain: 2.0 }.encode(0).unwrap().get_command_number();
+        device.set_middleware(Box::new(CommandBlacklist::new([gain])));
+        let setpoint = Command::SetSetpoint { scale: Scale::Normilized, value: 0.5f32.to_bits() };
+        handle.expect(expect(setpoint).respond(&[]));
+        assert!(matches!(
+            device.set_user_controller_gain(2.0),
+            Err(DeviceError::BlockedByMiddleware(command, _)) if command == gain
+        ));
+        // other commands pass through
+        device.set_setpoint(0.5f32.to_bits(), Scale::Normilized).unwrap();
+        assert_eq!(handle.sent().len(), 1);
+        handle.assert_done();
+    }
+
+    #[test]
+    fn capabilities_are_read_once() {
+        let (mut device, handle) = create_device();
+        let capabilities = device.capabilities().unwrap();
+        assert_eq!(capabilities.firmware, (1, 48));
+        assert!(capabilities.supports_two_sensors);
+        assert_eq!(capabilities.max_averaging_count, 0);
+        device.read_measured_flow_two_sensors(Scale::PhysicalValue).unwrap();
+        device.read_measured_flow_buffered(Scale::PhysicalValue).unwrap();
+        device.get_pressure_dependant_gain().unwrap();
+        let versions = handle.requests().iter().filter(|&&(_, command, _)| command == 0xD1).count();
+        assert_eq!(versions, 1);
+    }
+
+    #[test]
+    fn firmware_without_two_sensors_fails_fast() {
+        let mut config = EmulatorConfig::default();
+        config.version.firmware_minor = 47;
+        let (mut device, handle) = create_device_with(config);
+        for result in [
+            device.read_measured_flow_two_sensors(Scale::PhysicalValue),
+            device.set_setpoint_and_read_measured_value_two_sensors(Scale::PhysicalValue, 1.0),
+        ] {
+            match result {
+                Err(DeviceError::UnsupportedFirmware { feature, firmware }) => {
+                    assert_eq!(feature, Feature::TwoSensors);
+                    assert_eq!(firmware, (1, 47));
+                }
+                other => panic!("expected unsupported firmware, got {:?}", other),
+            }
+        }
+        assert!(handle.requests().iter().all(|&(_, command, _)| command != 0x0A && command != 0x04));
+        assert_eq!(handle.setpoint(), 0.0);
+        // the other readings don't depend on the firmware
+        device.read_measured_flow(Scale::PhysicalValue).unwrap();
+    }
+
+    fn booting_device(boot_errors: Duration) -> (Device<Sfc5xxxEmulator>, EmulatorHandle) {
+        create_device_with(EmulatorConfig { boot_errors, ..Default::default() })
+    }
+
+    #[test]
+    fn fatal_errors_while_booting_are_waited_out() {
+        let (mut device, _handle) = booting_device(Duration::from_millis(100));
+        device.set_boot_grace(Some(BootGrace::default()));
+        assert_eq!(device.since_reset(), None);
+        for reset in [Device::reset_device, Device::factory_reset] {
+            reset(&mut device).unwrap();
+            let start = Instant::now();
+            assert!(device.since_reset().unwrap() < BootGrace::default().window);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            assert!(start.elapsed() >= Duration::from_millis(80));
+        }
+        assert!(device.stats().retries > 0);
+    }
+
+    #[test]
+    fn fatal_errors_past_the_boot_grace_fail() {
+        let (mut device, _handle) = booting_device(Duration::from_millis(500));
+        device.set_boot_grace(Some(BootGrace { window: Duration::from_millis(100), ..BootGrace::default() }));
+        device.reset_device().unwrap();
+        let start = Instant::now();
+        assert!(matches!(device.get_baudrate(), Err(DeviceError::StateResponse(StateResponseError::FatalError))));
+        // sent again until the window closed, not until the device booted
+        assert!(start.elapsed() >= Duration::from_millis(80));
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        // without the grace the first fatal error fails the command
+        let (mut device, handle) = booting_device(Duration::from_millis(500));
+        device.set_boot_grace(None);
+        device.reset_device().unwrap();
+        let before = handle.requests().len();
+        assert!(device.get_baudrate().is_err());
+        assert_eq!(handle.requests().len(), before + 1);
+    }
+
+    #[test]
+    fn fatal_errors_in_steady_state_stay_fatal() {
+        let (mut device, handle) = create_device();
+        device.set_boot_grace(Some(BootGrace::default()));
+        handle.inject_fault(Fault::ErrorState(0x7F));
+        let before = handle.requests().len();
+        assert!(matches!(device.get_baudrate(), Err(DeviceError::StateResponse(StateResponseError::FatalError))));
+        assert_eq!(handle.requests().len(), before + 1);
+        assert_eq!(device.stats().retries, 0);
+    }
+
+    #[test]
+    fn busy_sensor_is_polled_until_it_answers() {
+        let (mut device, handle) = create_device();
+        device.set_busy_retry(Some(BusyPolicy::new(Duration::from_millis(500), Duration::from_millis(5))));
+        for _ in 0..3 {
+            handle.inject_fault(Fault::ErrorState(0x42));
+        }
+        let before = handle.requests().len();
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+        assert_eq!(handle.requests().len(), before + 4);
+        assert_eq!(device.stats().retries, 3);
+    }
+
+    #[test]
+    fn sensor_that_stays_busy_fails_with_the_time_waited() {
+        let (mut device, handle) = create_device();
+        handle.set_busy(true);
+        let before = handle.requests().len();
+        assert!(matches!(device.get_baudrate(), Err(DeviceError::StateResponse(StateResponseError::SensorBusy))));
+        assert_eq!(handle.requests().len(), before + 1);
+
+        device.set_busy_retry(Some(BusyPolicy::new(Duration::from_millis(50), Duration::from_millis(10))));
+        match device.get_baudrate() {
+            Err(DeviceError::StillBusy(waited)) => {
+                assert!(waited >= Duration::from_millis(50));
+                assert!(waited < Duration::from_millis(200));
+            }
+            other => panic!("expected still busy, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resets_and_calibration_switches_wait_with_their_own_budget() {
+        let (mut device, handle) = create_device();
+        assert_eq!(device.busy_retry(), None);
+        for _ in 0..3 {
+            handle.inject_fault(Fault::ErrorState(0x42));
+        }
+        device.set_callibration(1).unwrap();
+        for _ in 0..3 {
+            handle.inject_fault(Fault::ErrorState(0x42));
+        }
+        device.factory_reset().unwrap();
+
+        handle.set_busy(true);
+        let start = Instant::now();
+        assert!(matches!(device.reset_device(), Err(DeviceError::StillBusy(_))));
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        // the budget of the reset was only for the reset
+        assert_eq!(device.busy_retry(), None);
+        assert!(matches!(device.get_baudrate(), Err(DeviceError::StateResponse(StateResponseError::SensorBusy))));
+    }
+
+    #[test]
+    fn open_missing_port() {
+        let result = Device::open("/dev/does-not-exist", Baudrate::B115200, 0);
+        assert!(matches!(result, Err(DeviceError::PortError(_))));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn open_u32_shim() {
+        let result = Device::open_u32("/dev/does-not-exist", 9600, 0);
+        assert!(matches!(result, Err(DeviceError::UnsupportedBaudrate(9600))));
+        let result = Device::open_u32("/dev/does-not-exist", 115200, 0);
+        assert!(matches!(result, Err(DeviceError::PortError(_))));
+    }
+
+    #[test]
+    fn rs485_turnaround_with_the_emulator() {
+        let (mut device, _) = create_device();
+        device.set_rs485(Some(Rs485Config {
+            rts_on_send: true,
+            turnaround: Duration::from_millis(2),
+        }));
+        assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[ignore = "needs a device behind an RS-485 adapter without automatic direction control"]
+    fn rs485_manual_direction_adapter() {
+        let mut device = Device::open("/dev/ttyUSB0", Baudrate::B115200, 0).unwrap();
+        device.set_rs485(Some(Rs485Config {
+            rts_on_send: true,
+            turnaround: Duration::from_micros(500),
+        }));
+        let _ = device.get_serial_number().unwrap();
+    }
+
+    #[test]
+    fn reconnect_after_the_cable_was_pulled() {
+        let (mut device, handle) = create_device();
+        device.set_setpoint(0.5_f32.to_bits(), Scale::Normilized).unwrap();
+        handle.unplug();
+        assert!(matches!(device.get_setpoint(Scale::Normilized), Err(DeviceError::IoError(_))));
+        device.reconnect(handle.replug()).unwrap();
+        // the setpoint lives in the device and survives the replug
+        let setpoint = f32::from_bits(device.get_setpoint(Scale::Normilized).unwrap());
+        assert_relative_eq!(setpoint, 0.5);
+    }
+
+    #[test]
+    fn pipeline_falls_back_after_an_error() {
+        let commands: Vec<_> = (0..3).map(|index| Command::GetCalibrationGasDescription { index }).collect();
+        let descriptions = |responses: Vec<MISOFrame>| -> Vec<String> {
+            responses.into_iter().map(|response| parse_string(&response.into_data()).unwrap()).collect()
+        };
+
+        // firmware that drops requests while it answers times the second one out
+        let (mut device, handle) = create_device_with(EmulatorConfig { pipelining: false, ..Default::default() });
+        device.set_response_timeout(Duration::from_millis(20));
+        device.set_pipeline_window(3);
+        let before = handle.requests().len();
+        assert_eq!(descriptions(device.pipeline(&commands).unwrap()), ["Air", "N2", "CO2"]);
+        assert_eq!(handle.requests().len() - before, 3 + 2);
+
+        // a failed response sends the whole batch again one at a time
+        let (mut device, handle) = create_device();
+        device.set_pipeline_window(3);
+        handle.inject_fault(Fault::ErrorState(0x01));
+        let before = handle.requests().len();
+        assert_eq!(descriptions(device.pipeline(&commands).unwrap()), ["Air", "N2", "CO2"]);
+        assert_eq!(handle.requests().len() - before, 3 + 3);
+
+        // the first error of the requests sent one at a time fails the pipeline
+        for _ in 0..commands.len() + 1 {
+            handle.inject_fault(Fault::ErrorState(0x01));
+        }
+        assert!(matches!(
+            device.pipeline(&commands),
+            Err(DeviceError::StateResponse(StateResponseError::DataSizeError))
+        ));
+    }
+
+    #[test]
+    fn injected_error_state() {
+        let (mut device, handle) = create_device();
+        handle.inject_fault(Fault::ErrorState(0x01));
+        match device.measure_temperature() {
+            Err(DeviceError::StateResponse(StateResponseError::DataSizeError)) => {}
+            _ => panic!("expected, StateResponseError::DataSizeError"),
+        }
+        assert_relative_eq!(device.measure_temperature().unwrap(), 24.5);
+    }
+
+    /// Answers every request it is sent with the next of a fixed list of response data, from the
+    /// address and command of the request, and keeps everything written to it
+    struct Scripted {
+        answers: VecDeque<Vec<u8>>,
+        response: Vec<u8>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Scripted {
+        /// The transport and the bytes written to it
+        fn new(answers: &[&[u8]]) -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let written = Arc::new(Mutex::new(Vec::new()));
+            let scripted = Self {
+                answers: answers.iter().map(|data| data.to_vec()).collect(),
+                response: Vec::new(),
+                written: written.clone(),
+            };
+            (scripted, written)
+        }
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.response.is_empty() {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            let n = buf.len().min(self.response.len());
+            buf[..n].copy_from_slice(&self.response[..n]);
+            self.response.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            let request = from_shdlc(buf).unwrap();
+            let data = self.answers.pop_front().unwrap();
+            let mut raw = vec![request[0], request[1], 0x00, data.len() as u8];
+            raw.extend_from_slice(&data);
+            self.response = to_shdlc(&raw).unwrap().to_vec();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Scripted {
+        fn timeout(&self) -> Duration {
+            Duration::ZERO
+        }
+
+        fn set_timeout(&mut self, _timeout: Duration) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn thermal_conductivity_reference_uses_its_subcommand() {
+        let (port, written) = Scripted::new(&[&830_u16.to_be_bytes()]);
+        let mut device = Device::new(port, 0).unwrap();
+        assert_eq!(device.get_calibration_thermal_conductivity_refrence(2).unwrap(), 830);
+
+        let request = MOSIFrame::new(0, 0x40, &[0x17, 0, 0, 0, 2]).unwrap().into_raw();
+        assert_eq!(*written.lock().unwrap(), request.as_slice());
+    }
+
+    #[test]
+    fn requests_go_to_the_new_slave_address() {
+        let (port, written) = Scripted::new(&[&[], &[5]]);
+        let mut device = Device::new(port, 0).unwrap();
+        device.set_slave_address(5).unwrap();
+        written.lock().unwrap().clear();
+        assert_eq!(device.get_device_address().unwrap(), 5);
+
+        let request = MOSIFrame::new(5, 0x90, &[]).unwrap().into_raw();
+        assert_eq!(*written.lock().unwrap(), request.as_slice());
+    }
+
+    #[test]
+    fn apply_config() {
+        let (mut device, handle) = create_device();
+        let millis = GasUnit {
+            unit_prefex: Prefixes::Milli,
+            medium_unit: Units::StandardLiter,
+            timebase: TimeBases::Minute,
+        };
+        let desired = DeviceConfig {
+            unit: Some(millis),
+            setpoint_persistent: Some(true),
+            baudrate: Some(115200),
+            initial_step: Some(0.5),
+            calibration: Some(1),
+            ..DeviceConfig::default()
+        };
+        let diff = device.apply_config(&desired, false).unwrap();
+        assert_eq!(diff.changed, vec![Setting::Unit(millis), Setting::SetpointPersistent(true)]);
+        assert_eq!(diff.unchanged, vec![Setting::Baudrate(115200)]);
+        assert_eq!(
+            diff.skipped,
+            vec![
+                (Setting::Calibration(1), SkipReason::Unreadable),
+                (Setting::InitialStep(0.5), SkipReason::Unsupported),
+            ]
+        );
+        assert_eq!(device.get_medium_unit_configuration(false).unwrap(), millis);
+        assert!(device.is_setpoint_persistant().unwrap());
+        assert_eq!(handle.active_calibration(), 0);
+
+        // converged, the second apply only reads
+        let diff = device.apply_config(&desired, false).unwrap();
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.unchanged.len(), 3);
+    }
+
+    /// Every fault class of the emulator against the timeout, retry, stale input and echo
+    /// handling of the driver
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        use crate::scaling::Scale;
+        use crate::valve_config::InputSourceConfig;
+
+        let condition = CalibrationCondition {
+            company: "Sensirion".to_string(),
+            operator: "EMU".to_string(),
+            calibration_year: 2024,
+            calibration_month: 5,
+            calibration_day: 17,
+            calibration_hour: 9,
+            calibration_minute: 30,
+            calibration_temperature: 23.5,
+            calibration_inlet_temperature: 22.0,
+            calibration_diffrential_pressure: 2.0,
+            real_gas_calibration: true,
+            calibration_accuracy_setpoint: 0.5,
+            calibration_accuracy_fullscale: 0.1,
+        };
+        let json = serde_json::to_string(&condition).unwrap();
+        assert!(json.contains(r#""calibration_differential_pressure":2.0"#));
+        assert_eq!(serde_json::from_str::<CalibrationCondition>(&json).unwrap(), condition);
+
+        let read = BufferedRead {
+            lost_values: 1,
+            remaning_values: 2,
+            sampling_time: 0.01,
+            values: [1.0, 1.5].into_iter().collect(),
+        };
+        let json = serde_json::to_string(&read).unwrap();
+        assert!(json.contains(r#""remaining_values":2"#));
+        assert_eq!(serde_json::from_str::<BufferedRead>(&json).unwrap(), read);
+
+        assert_eq!(serde_json::to_string(&Scale::Normilized).unwrap(), r#""Normalized""#);
+        for scale in [Scale::Normilized, Scale::PhysicalValue, Scale::UserDefined] {
+            let json = serde_json::to_string(&scale).unwrap();
+            assert_eq!(serde_json::from_str::<Scale>(&json).unwrap(), scale);
+        }
+        let source = InputSourceConfig::UserDefined(0.25);
+        let json = serde_json::to_string(&source).unwrap();
+        assert_eq!(serde_json::from_str::<InputSourceConfig>(&json).unwrap(), source);
+    }
+
+    mod resilience {
+        use std::task::Poll;
+        use std::time::Instant;
+
+        use sfc_core::shdlc::START_STOP;
+
+        use super::*;
+
+        fn retrying() -> (Device<Sfc5xxxEmulator>, EmulatorHandle) {
+            let (mut device, handle) = create_device();
+            device.set_retry(Some(RetryConfig::default()));
+            (device, handle)
+        }
+
+        #[test]
+        fn stats_count_injected_faults() {
+            let (mut device, handle) = retrying();
+            device.set_response_timeout(Duration::from_millis(30));
+            device.reset_stats();
+            handle.inject_fault(Fault::CorruptChecksum);
+            handle.inject_fault(Fault::CorruptChecksum);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            for _ in 0..3 {
+                handle.inject_fault(Fault::DropResponse);
+            }
+            assert!(device.get_baudrate().is_err());
+
+            let stats = device.stats();
+            assert_eq!(stats.commands, 2);
+            assert_eq!(stats.retries, 4);
+            assert_eq!(stats.failures, 1);
+            assert_eq!(stats.checksum_errors, 2);
+            assert_eq!(stats.timeouts, 3);
+            assert_eq!(stats.framing_errors, 0);
+            assert!(stats.round_trip.is_some());
+        }
+
+        #[test]
+        fn truncated_response_is_an_incomplete_frame() {
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::TruncateAfter(5));
+            assert!(matches!(device.get_baudrate(), Err(DeviceError::IncompleteFrame)));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+        }
+
+        #[test]
+        fn truncated_response_is_retried() {
+            let (mut device, handle) = retrying();
+            handle.inject_fault(Fault::TruncateAfter(1));
+            handle.inject_fault(Fault::TruncateAfter(8));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            assert_eq!(handle.requests().len(), 3);
+        }
+
+        #[test]
+        fn delay_within_the_response_timeout() {
+            let (mut device, handle) = create_device();
+            device.set_response_timeout(Duration::from_millis(200));
+            handle.inject_fault(Fault::DelayMs(30));
+            let start = Instant::now();
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            assert!(start.elapsed() >= Duration::from_millis(30));
+        }
+
+        #[test]
+        fn delay_beyond_the_response_timeout() {
+            let (mut device, handle) = create_device();
+            device.set_response_timeout(Duration::from_millis(30));
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(matches!(device.get_baudrate(), Err(DeviceError::Timeout)));
+
+            // the late response is stale by the time the next request is sent
+            std::thread::sleep(Duration::from_millis(80));
+            assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        }
+
+        #[test]
+        fn delay_beyond_the_response_timeout_is_retried() {
+            let (mut device, handle) = retrying();
+            device.set_response_timeout(Duration::from_millis(30));
+            handle.inject_fault(Fault::DelayMs(50));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        }
+
+        #[test]
+        fn each_kind_of_command_has_its_own_timeout() {
+            let (mut device, handle) = create_device();
+            device.set_timeouts(Timeouts {
+                query: Duration::from_millis(30),
+                measurement: Duration::from_millis(300),
+                reset: Duration::from_millis(300),
+                ..Timeouts::uniform(Duration::from_millis(30))
+            });
+            assert_eq!(device.response_timeout(), Duration::from_millis(30));
+
+            // a measurement gets longer than a query
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(device.read_measured_flow(Scale::PhysicalValue).is_ok());
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(matches!(device.get_baudrate(), Err(DeviceError::Timeout)));
+            std::thread::sleep(Duration::from_millis(80));
+
+            handle.inject_fault(Fault::DelayMs(80));
+            device.reset_device().unwrap();
+
+            // a single timeout applies to every kind again
+            device.set_response_timeout(Duration::from_millis(30));
+            assert_eq!(device.timeouts(), Timeouts::uniform(Duration::from_millis(30)));
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(matches!(device.read_measured_flow(Scale::PhysicalValue), Err(DeviceError::Timeout)));
+        }
+
+        #[test]
+        fn noise_before_the_response_is_discarded() {
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::InjectBytes(vec![0x00, 0x55, 0xAA, 0x13]));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+        }
+
+        #[test]
+        fn partial_frame_before_the_response() {
+            // a frame with all the bytes its length declares, which takes the start of the response as its end
+            let garbage = vec![START_STOP, 0x00, 0x91, 0x00, 0x00, 0x00];
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::InjectBytes(garbage.clone()));
+            assert!(device.get_baudrate().is_err());
+
+            let (mut device, handle) = retrying();
+            handle.inject_fault(Fault::InjectBytes(garbage));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+        }
+
+        #[test]
+        fn partial_response_gives_way_to_the_response() {
+            // the response starts before the frame that was cut short holds its data, no retry needed
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::InjectBytes(vec![START_STOP, 0x00, 0x91, 0x00, 0x04]));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+        }
+
+        #[test]
+        fn duplicated_response_is_cleared_as_stale_input() {
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::DuplicateResponse);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        }
+
+        #[test]
+        fn duplicated_response_is_skipped_by_the_echo_check() {
+            let (mut device, handle) = create_device();
+            device.set_clear_stale_input(false);
+            handle.inject_fault(Fault::DuplicateResponse);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            assert_eq!(device.get_serial_number().unwrap(), "EMU0000001");
+        }
+
+        #[test]
+        fn dropped_response_is_retried() {
+            let (mut device, handle) = retrying();
+            device.set_response_timeout(Duration::from_millis(20));
+            handle.inject_fault(Fault::DropResponse);
+            handle.inject_fault(Fault::DropResponse);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+
+            handle.inject_fault(Fault::DropResponse);
+            handle.inject_fault(Fault::DropResponse);
+            handle.inject_fault(Fault::DropResponse);
+            match device.get_baudrate() {
+                Err(DeviceError::RetriesExhausted(3, last)) => {
+                    assert!(matches!(*last, DeviceError::Timeout))
+                }
+                other => panic!("expected, DeviceError::RetriesExhausted got {:?}", other),
+            }
+        }
+
+        #[test]
+        fn error_state_is_never_retried() {
+            let (mut device, handle) = retrying();
+            handle.inject_fault(Fault::ErrorState(0x04));
+            assert!(matches!(
+                device.get_baudrate(),
+                Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+            ));
+            assert_eq!(handle.requests().len(), 1);
+        }
+
+        #[test]
+        fn resync_probes_the_device() {
+            let (mut device, handle) = create_device();
+            device.resync().unwrap();
+            assert_eq!(handle.last_request(), Some((0, 0xD1, vec![])));
+        }
+
+        #[test]
+        fn retry_resyncs_after_repeated_checksum_errors() {
+            let (mut device, handle) = create_device();
+            device.set_retry(Some(RetryConfig {
+                resync_after: Some(2),
+                ..Default::default()
+            }));
+            handle.inject_fault(Fault::CorruptChecksum);
+            handle.inject_fault(Fault::CorruptChecksum);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            let commands: Vec<u8> = handle.requests().iter().map(|r| r.1).collect();
+            assert_eq!(commands, [0x91, 0x91, 0xD1, 0x91]);
+        }
+
+        #[test]
+        fn polled_command_waits_for_a_delayed_response() {
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::DelayMs(20));
+            let mut pending = device.start_command(0x91, &[]).unwrap();
+            assert!(pending.poll().is_pending());
+            let response = loop {
+                if let Poll::Ready(response) = pending.poll() {
+                    break response.unwrap();
+                }
+                std::thread::sleep(Duration::from_millis(2));
+            };
+            assert_eq!(response.into_data().as_slice(), &115200_u32.to_be_bytes());
+        }
+
+        #[test]
+        fn polled_command_times_out_on_a_dropped_response() {
+            let (mut device, handle) = create_device();
+            device.set_response_timeout(Duration::from_millis(20));
+            handle.inject_fault(Fault::DropResponse);
+            let mut pending = device.start_command(0x91, &[]).unwrap();
+            let result = loop {
+                if let Poll::Ready(result) = pending.poll() {
+                    break result;
+                }
+                std::thread::sleep(Duration::from_millis(2));
+            };
+            assert!(matches!(result, Err(DeviceError::Timeout)));
+        }
+
+        #[test]
+        fn polled_command_with_a_corrupted_checksum() {
+            let (mut device, handle) = create_device();
+            handle.inject_fault(Fault::CorruptChecksum);
+            let mut pending = device.start_command(0x91, &[]).unwrap();
+            assert!(matches!(pending.poll(), Poll::Ready(Err(DeviceError::InvalidChecksum(_, _)))));
+        }
+    }
+
+    /// Random arguments for the commands that take them, checked against what the emulator
+    /// decoded from the request
+    mod properties {
+        use proptest::prelude::*;
+
+        use sfc_core::shdlc::TranslationError;
+
+        use super::*;
+        use crate::emulator::USER_MEMORY_SIZE;
+
+        proptest! {
+            #[test]
+            fn setpoint_round_trips(setpoint in proptest::num::f32::ANY) {
+                let (mut device, handle) = create_device();
+                let result = device.set_setpoint(setpoint.to_bits(), Scale::Normilized);
+
+                let mut expected = vec![Scale::Normilized as u8];
+                expected.extend(setpoint.to_be_bytes());
+                prop_assert_eq!(handle.last_request(), Some((0, 0x00, expected)));
+                // the device rejects everything outside of 0 to full scale, NaN included
+                if (0.0..=1.0).contains(&setpoint) {
+                    prop_assert!(result.is_ok());
+                    prop_assert_eq!(handle.setpoint().to_bits(), setpoint.to_bits());
+                    let read = device.get_setpoint(Scale::Normilized).unwrap();
+                    prop_assert_eq!(read, setpoint.to_bits());
+                } else {
+                    prop_assert!(matches!(
+                        result,
+                        Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+                    ));
+                    prop_assert_eq!(handle.setpoint(), 0.0);
+                }
+            }
+
+            #[test]
+            fn subnormal_setpoints_are_kept_exactly(bits in 1_u32..0x0080_0000) {
+                let (mut device, handle) = create_device();
+                let setpoint = f32::from_bits(bits);
+                prop_assert!(setpoint.is_subnormal());
+                device.set_setpoint_and_read_measured_value(Scale::Normilized, setpoint).unwrap();
+                prop_assert_eq!(handle.setpoint().to_bits(), bits);
+            }
+
+            #[test]
+            fn physical_setpoint_is_scaled_by_the_device(setpoint in -10.0_f32..10.0) {
+                let (mut device, handle) = create_device();
+                let result = device.set_setpoint_and_read_measured_value(Scale::PhysicalValue, setpoint);
+
+                let mut expected = vec![Scale::PhysicalValue as u8];
+                expected.extend(setpoint.to_be_bytes());
+                prop_assert_eq!(handle.last_request(), Some((0, 0x03, expected)));
+                // the default calibration has a full scale of 5 SLM
+                if (0.0..=1.0).contains(&(setpoint / 5.0)) {
+                    assert_relative_eq!(result.unwrap(), setpoint, epsilon = 1e-6);
+                } else {
+                    prop_assert!(result.is_err());
+                }
+            }
+
+            #[test]
+            fn calibration_index(index in prop_oneof![0_u32..8, any::<u32>()]) {
+                let (mut device, handle) = create_device();
+                let result = device.set_callibration(index);
+
+                prop_assert_eq!(handle.last_request(), Some((0, 0x45, index.to_be_bytes().to_vec())));
+                match index {
+                    // the three valid calibrations of the default table
+                    0..=2 => {
+                        prop_assert!(result.is_ok());
+                        prop_assert_eq!(handle.active_calibration(), index);
+                    }
+                    3 => prop_assert!(matches!(
+                        result,
+                        Err(DeviceError::StateResponse(StateResponseError::InvalidCalibration))
+                    )),
+                    _ => prop_assert!(matches!(
+                        result,
+                        Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+                    )),
+                }
+            }
+
+            #[test]
+            fn user_memory_write(start: u8, data in proptest::collection::vec(any::<u8>(), 0..300)) {
+                let (mut device, handle) = create_device();
+                let result = device.write_user_memory(start, &data);
+
+                // start and length take two bytes of the 255 byte payload
+                if data.len() > 253 {
+                    prop_assert!(matches!(
+                        result,
+                        Err(DeviceError::ShdlcError(TranslationError::DataTooLarge))
+                    ));
+                    prop_assert_eq!(handle.last_request(), None);
+                    return Ok(());
+                }
+
+                let mut expected = vec![start, data.len() as u8];
+                expected.extend(&data);
+                prop_assert_eq!(handle.last_request(), Some((0, 0x6E, expected)));
+                if start as usize + data.len() <= USER_MEMORY_SIZE {
+                    prop_assert!(result.is_ok());
+                    prop_assert_eq!(device.read_user_memory(start, data.len() as u8).unwrap(), data);
+                } else {
+                    prop_assert!(matches!(
+                        result,
+                        Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+                    ));
+                }
+            }
+
+            #[test]
+            fn user_memory_read(start: u8, count: u8) {
+                let (mut device, handle) = create_device();
+                let result = device.read_user_memory(start, count);
+
+                prop_assert_eq!(handle.last_request(), Some((0, 0x6E, vec![start, count])));
+                if start as usize + count as usize <= USER_MEMORY_SIZE {
+                    prop_assert_eq!(result.unwrap().len(), count as usize);
+                } else {
+                    prop_assert!(result.is_err());
+                }
+            }
         }
     }
 }
@@ -0,0 +1,767 @@
+//! An in-process SFC5xxx that answers SHDLC frames like a real device would. It implements
+//! [Transport] so it can be handed straight to [Device::new](crate::device::Device::new) and is
+//! meant for testing code built on the driver without any hardware attached.
+//!
+//! Time does not pass on its own inside the emulator. Use [EmulatorHandle::advance] to simulate
+//! elapsed time, which fills the measurement buffer read by
+//! [Device::read_measured_flow_buffered](crate::device::Device::read_measured_flow_buffered).
+//! The one exception is [Fault::DelayMs], which holds a response back for real time so the
+//! timeouts of the driver can be tested.
+//! ```
+//! use sfc5xxx_rs::device::Device;
+//! use sfc5xxx_rs::emulator::Sfc5xxxEmulator;
+//! use sfc_core::baudrate::Baudrate;
+//!
+//! let emulator = Sfc5xxxEmulator::default();
+//! let handle = emulator.handle();
+//! let mut device = Device::new(emulator, 0).unwrap();
+//! handle.advance(std::time::Duration::from_millis(10));
+//! assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+use sfc_core::emulator::{
+    self, CommandTable, Emulator, STATE_DATA_SIZE, STATE_INVALID_CALIBRATION, STATE_PARAMETER,
+    STATE_UNKNOWN_COMMAND, encode_string, read_f32, read_u32,
+};
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::{GasUnit, Prefixes, TimeBases, Units};
+use sfc_core::shdlc::Version;
+use sfc_core::transport::Transport;
+
+use crate::calibration::CalibrationCondition;
+
+pub use sfc_core::emulator::Fault;
+
+/// Size of the user memory accessible through
+/// [Device::read_user_memory](crate::device::Device::read_user_memory)
+pub const USER_MEMORY_SIZE: usize = 128;
+pub use crate::device::MAX_BUFFERED_VALUES;
+
+/// A single calibration stored on the emulated device
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalibrationSlot {
+    pub gas_description: String,
+    pub gas_id: u32,
+    pub unit: GasUnit,
+    pub full_scale: f32,
+    pub initial_conditions: CalibrationCondition,
+    pub recalibration_conditions: CalibrationCondition,
+    pub thermal_conductivity_reference: u16,
+}
+
+/// Everything about the emulated device that is fixed when it is created
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmulatorConfig {
+    /// The slave address the emulator answers to
+    pub address: u8,
+    /// Time between two samples in the measurement buffer, in seconds
+    pub sampling_time: f32,
+    /// How many samples the measurement buffer holds before values are lost
+    pub buffer_depth: usize,
+    /// The calibration table. `None` entries are reported as invalid calibrations.
+    pub calibrations: Vec<Option<CalibrationSlot>>,
+    /// Index into `calibrations` that is active after start up
+    pub active_calibration: u32,
+    pub version: Version,
+    pub product_name: String,
+    pub article_code: String,
+    pub serial_number: String,
+    pub baudrate: u32,
+    /// Raw flow reading in ticks
+    pub raw_flow: u16,
+    /// Raw thermal conductivity reading in ticks
+    pub raw_thermal_conductivity: u16,
+    /// Sensor temperature in degrees celcius
+    pub temperature: f32,
+    /// How long the device answers every request with a fatal error (0x7F) after a reset, like
+    /// firmware that takes requests before it finished starting up. Zero by default.
+    pub boot_errors: Duration,
+    /// Whether a request is taken while the response to the previous one hasn't been read yet.
+    /// Firmware that can't pipeline requests drops it instead, on by default.
+    pub pipelining: bool,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        let conditions = CalibrationCondition {
+            company: "Sensirion".to_string(),
+            operator: "emulator".to_string(),
+            calibration_year: 2024,
+            calibration_month: 3,
+            calibration_day: 14,
+            calibration_hour: 9,
+            calibration_minute: 30,
+            calibration_temperature: 23.0,
+            calibration_inlet_temperature: 22.5,
+            calibration_diffrential_pressure: 1.0,
+            real_gas_calibration: true,
+            calibration_accuracy_setpoint: 0.5,
+            calibration_accuracy_fullscale: 0.1,
+        };
+        let slot = |description: &str, gas_id, full_scale, thermal_conductivity_reference| {
+            Some(CalibrationSlot {
+                gas_description: description.to_string(),
+                gas_id,
+                unit: GasUnit {
+                    unit_prefex: Prefixes::Base,
+                    medium_unit: Units::StandardLiter,
+                    timebase: TimeBases::Minute,
+                },
+                full_scale,
+                initial_conditions: conditions.clone(),
+                recalibration_conditions: conditions.clone(),
+                thermal_conductivity_reference,
+            })
+        };
+
+        Self {
+            address: 0,
+            sampling_time: 0.001,
+            buffer_depth: 100,
+            calibrations: vec![
+                slot("Air", 1, 5.0, 1200),
+                slot("N2", 2, 5.0, 1180),
+                slot("CO2", 3, 2.5, 830),
+                None,
+            ],
+            active_calibration: 0,
+            version: Version {
+                firmware_major: 1,
+                firmware_minor: 48,
+                debug: false,
+                hardware_major: 1,
+                hardware_minor: 0,
+                protocol_major: 2,
+                protocol_minor: 0,
+            },
+            product_name: "SFC5400".to_string(),
+            article_code: "3.000.000".to_string(),
+            serial_number: "EMU0000001".to_string(),
+            baudrate: 115200,
+            raw_flow: 0x2000,
+            raw_thermal_conductivity: 1200,
+            temperature: 24.5,
+            boot_errors: Duration::ZERO,
+            pipelining: true,
+        }
+    }
+}
+
+/// The emulated device. Hand it to [Device::new](crate::device::Device::new) and keep an
+/// [EmulatorHandle] around to inspect or manipulate it afterwards.
+#[derive(Debug)]
+pub struct Sfc5xxxEmulator(Emulator<EmulatorState>);
+
+impl Sfc5xxxEmulator {
+    pub fn new(config: EmulatorConfig) -> Self {
+        Self(Emulator::new(EmulatorState::new(config)))
+    }
+
+    /// Returns a handle sharing the state of this emulator
+    pub fn handle(&self) -> EmulatorHandle {
+        EmulatorHandle(self.0.handle())
+    }
+}
+
+impl Default for Sfc5xxxEmulator {
+    fn default() -> Self {
+        Self::new(EmulatorConfig::default())
+    }
+}
+
+impl Read for Sfc5xxxEmulator {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Sfc5xxxEmulator {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for Sfc5xxxEmulator {
+    fn timeout(&self) -> Duration {
+        self.0.timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.0.set_timeout(timeout)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), DeviceError> {
+        self.0.set_baud_rate(baud_rate)
+    }
+}
+
+/// A shared reference to the state of a [Sfc5xxxEmulator] that stays usable after the emulator
+/// has been moved into a device. Unplugging, faults, the busy state and the requests received
+/// are those of every emulator, see [emulator::EmulatorHandle].
+#[derive(Clone, Debug)]
+pub struct EmulatorHandle(emulator::EmulatorHandle<EmulatorState>);
+
+impl Deref for EmulatorHandle {
+    type Target = emulator::EmulatorHandle<EmulatorState>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl EmulatorHandle {
+    /// Plugs the cable back in and returns a fresh transport to the same device
+    pub fn replug(&self) -> Sfc5xxxEmulator {
+        Sfc5xxxEmulator(self.0.replug())
+    }
+
+    /// Simulates the passing of time, the measurement buffer gets filled with one sample per
+    /// sampling time. Samples that do not fit in the buffer are counted as lost.
+    pub fn advance(&self, elapsed: Duration) {
+        self.0.with(|state| state.advance(elapsed));
+    }
+
+    /// Returns the current setpoint normalized to the full scale (0.0 to 1.0)
+    pub fn setpoint(&self) -> f32 {
+        self.0.with(|state| state.setpoint)
+    }
+
+    /// Returns the currently active calibration index
+    pub fn active_calibration(&self) -> u32 {
+        self.0.with(|state| state.active_calibration)
+    }
+
+    /// Returns the slave address the emulator currently answers to
+    pub fn address(&self) -> u8 {
+        self.0.with(|state| state.address)
+    }
+
+    /// Returns the baudrate the device has been configured to use
+    pub fn baudrate(&self) -> u32 {
+        self.0.with(|state| state.baudrate)
+    }
+
+    /// Returns the baudrate the driver last set on the transport
+    pub fn line_baudrate(&self) -> u32 {
+        self.0.with(|state| state.line_baudrate)
+    }
+
+    /// Returns the number of samples waiting in the measurement buffer
+    pub fn buffered_samples(&self) -> usize {
+        self.0.with(|state| state.buffer.len())
+    }
+
+    /// Sets the device error flags reported by
+    /// [Device::get_device_error_state](crate::device::Device::get_device_error_state)
+    pub fn set_device_error(&self, flags: u32, last_error: u8) {
+        self.0.with(|state| {
+            state.error_flags = flags;
+            state.last_error = last_error;
+        });
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ValveSource {
+    Controller,
+    ForceClosed,
+    ForceOpen,
+    Hold,
+    UserDefined,
+}
+
+/// The emulated SFC5xxx itself, the commands it knows and what they change
+#[derive(Debug)]
+pub struct EmulatorState {
+    config: EmulatorConfig,
+    address: u8,
+    baudrate: u32,
+    line_baudrate: u32,
+    active_calibration: u32,
+    /// normalized to the full scale of the active calibration
+    setpoint: f32,
+    setpoint_persistent: bool,
+    /// normalized value the valve is holding when in [ValveSource::Hold]
+    held_flow: f32,
+    valve_source: ValveSource,
+    valve_user_value: f32,
+    medium_unit: Option<GasUnit>,
+    controller_gain: f32,
+    pressure_dependent_gain: bool,
+    inlet_pressure: f32,
+    gas_temperature_compensation: bool,
+    inlet_temperature: f32,
+    error_flags: u32,
+    last_error: u8,
+    user_memory: [u8; USER_MEMORY_SIZE],
+    buffer: VecDeque<f32>,
+    lost_values: u32,
+    /// nanoseconds that passed since the last sample was taken
+    unsampled_time: u128,
+    /// requests are answered with a fatal error until then, set by a reset
+    failing_until: Option<Instant>,
+}
+
+impl EmulatorState {
+    fn new(config: EmulatorConfig) -> Self {
+        Self {
+            address: config.address,
+            baudrate: config.baudrate,
+            line_baudrate: config.baudrate,
+            active_calibration: config.active_calibration,
+            setpoint: 0.0,
+            setpoint_persistent: false,
+            held_flow: 0.0,
+            valve_source: ValveSource::Controller,
+            valve_user_value: 0.0,
+            medium_unit: None,
+            controller_gain: 1.0,
+            pressure_dependent_gain: false,
+            inlet_pressure: 1.0,
+            gas_temperature_compensation: false,
+            inlet_temperature: 20.0,
+            error_flags: 0,
+            last_error: 0,
+            user_memory: [0; USER_MEMORY_SIZE],
+            buffer: VecDeque::new(),
+            lost_values: 0,
+            unsampled_time: 0,
+            failing_until: None,
+            config,
+        }
+    }
+
+    fn advance(&mut self, elapsed: Duration) {
+        // whole nanoseconds keep the sample count exact for round sampling times
+        let sampling_time = (f64::from(self.config.sampling_time) * 1e9).round() as u128;
+        if sampling_time == 0 {
+            return;
+        }
+
+        self.unsampled_time += elapsed.as_nanos();
+        let samples = self.unsampled_time / sampling_time;
+        self.unsampled_time %= sampling_time;
+
+        let flow = self.measured_flow();
+        for _ in 0..samples {
+            if self.buffer.len() < self.config.buffer_depth {
+                self.buffer.push_back(flow);
+            } else {
+                self.lost_values = self.lost_values.saturating_add(1);
+            }
+        }
+    }
+
+    /// The measured flow normalized to the full scale. The emulated controller is perfect, so
+    /// the flow follows the setpoint instantly.
+    fn measured_flow(&self) -> f32 {
+        match self.valve_source {
+            ValveSource::Controller => self.setpoint,
+            ValveSource::ForceClosed => 0.0,
+            ValveSource::ForceOpen => 1.0,
+            ValveSource::Hold => self.held_flow,
+            ValveSource::UserDefined => self.valve_user_value.clamp(0.0, 1.0),
+        }
+    }
+
+    fn calibration(&self, index: u32) -> Result<&CalibrationSlot, u8> {
+        match self.config.calibrations.get(index as usize) {
+            Some(Some(slot)) => Ok(slot),
+            Some(None) => Err(STATE_INVALID_CALIBRATION),
+            None => Err(STATE_PARAMETER),
+        }
+    }
+
+    /// The medium unit physical values are reported in, wildcards are resolved using the unit of
+    /// the active calibration.
+    fn resolved_unit(&self) -> Result<GasUnit, u8> {
+        let calibration_unit = self.calibration(self.active_calibration)?.unit;
+        let Some(unit) = self.medium_unit else {
+            return Ok(calibration_unit);
+        };
+
+        Ok(GasUnit {
+            unit_prefex: match unit.unit_prefex {
+                Prefixes::Undefined => calibration_unit.unit_prefex,
+                prefix => prefix,
+            },
+            medium_unit: match unit.medium_unit {
+                Units::Undefined => calibration_unit.medium_unit,
+                medium => medium,
+            },
+            timebase: match unit.timebase {
+                TimeBases::Undefined => calibration_unit.timebase,
+                timebase => timebase,
+            },
+        })
+    }
+
+    /// Full scale of the active calibration expressed in the configured medium unit
+    fn converted_full_scale(&self) -> Result<f32, u8> {
+        let slot = self.calibration(self.active_calibration)?;
+        let factor = conversion_factor(slot.unit, self.resolved_unit()?).ok_or(STATE_PARAMETER)?;
+        Ok((f64::from(slot.full_scale) * factor) as f32)
+    }
+
+    fn scale_value(&self, normalized: f32, scale: u8) -> Result<f32, u8> {
+        match scale {
+            0x00 => Ok(normalized),
+            0x01 | 0x02 => Ok(normalized * self.converted_full_scale()?),
+            _ => Err(STATE_PARAMETER),
+        }
+    }
+
+    fn normalize(&self, value: f32, scale: u8) -> Result<f32, u8> {
+        match scale {
+            0x00 => Ok(value),
+            0x01 | 0x02 => Ok(value / self.converted_full_scale()?),
+            _ => Err(STATE_PARAMETER),
+        }
+    }
+
+    fn set_setpoint(&mut self, scale: u8, value: &[u8]) -> Result<(), u8> {
+        let normalized = self.normalize(read_f32(value)?, scale)?;
+        if !(0.0..=1.0).contains(&normalized) {
+            return Err(STATE_PARAMETER);
+        }
+        self.setpoint = normalized;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        if !self.setpoint_persistent {
+            self.setpoint = 0.0;
+        }
+        self.valve_source = ValveSource::Controller;
+        self.buffer.clear();
+        self.lost_values = 0;
+        self.unsampled_time = 0;
+        self.line_baudrate = self.baudrate;
+        if !self.config.boot_errors.is_zero() {
+            self.failing_until = Some(Instant::now() + self.config.boot_errors);
+        }
+    }
+
+    fn factory_reset(&mut self) {
+        self.setpoint_persistent = false;
+        self.medium_unit = None;
+        self.controller_gain = 1.0;
+        self.pressure_dependent_gain = false;
+        self.inlet_pressure = 1.0;
+        self.gas_temperature_compensation = false;
+        self.inlet_temperature = 20.0;
+        self.active_calibration = self.config.active_calibration;
+        self.reset();
+    }
+
+    fn buffered_read(&mut self, scale: u8) -> Result<Vec<u8>, u8> {
+        // validate the scale before draining anything
+        self.scale_value(0.0, scale)?;
+
+        let count = self.buffer.len().min(MAX_BUFFERED_VALUES);
+        let mut response = self.lost_values.to_be_bytes().to_vec();
+        response.extend_from_slice(&((self.buffer.len() - count) as u32).to_be_bytes());
+        response.extend_from_slice(&self.config.sampling_time.to_be_bytes());
+        for value in self.buffer.drain(..count).collect::<Vec<_>>() {
+            response.extend_from_slice(&self.scale_value(value, scale)?.to_be_bytes());
+        }
+        self.lost_values = 0;
+
+        Ok(response)
+    }
+}
+
+impl CommandTable for EmulatorState {
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn set_line_baudrate(&mut self, baud_rate: u32) {
+        self.line_baudrate = baud_rate;
+    }
+
+    fn failing(&mut self) -> bool {
+        match self.failing_until {
+            Some(until) if Instant::now() < until => true,
+            _ => {
+                self.failing_until = None;
+                false
+            }
+        }
+    }
+
+    fn pipelining(&self) -> bool {
+        self.config.pipelining
+    }
+
+    fn execute(&mut self, command: u8, data: &[u8]) -> Result<Vec<u8>, u8> {
+        match (command, data) {
+            // setpoint
+            (0x00, [scale]) => Ok(self.scale_value(self.setpoint, *scale)?.to_be_bytes().to_vec()),
+            (0x00, [scale, value @ ..]) => {
+                self.set_setpoint(*scale, value)?;
+                Ok(Vec::new())
+            }
+            // setpoint persistence
+            (0x02, [0x00]) => Ok(vec![self.setpoint_persistent as u8]),
+            (0x02, [0x00, persist]) => {
+                self.setpoint_persistent = *persist > 0;
+                Ok(Vec::new())
+            }
+            // set setpoint and read the measured value
+            (0x03, [scale, value @ ..]) => {
+                self.set_setpoint(*scale, value)?;
+                Ok(self.scale_value(self.measured_flow(), *scale)?.to_be_bytes().to_vec())
+            }
+            (0x04, [scale, value @ ..]) => {
+                self.set_setpoint(*scale, value)?;
+                let flow = self.scale_value(self.measured_flow(), *scale)?.to_be_bytes();
+                Ok([flow, flow].concat())
+            }
+            // measured value
+            (0x08, [scale]) => Ok(self.scale_value(self.measured_flow(), *scale)?.to_be_bytes().to_vec()),
+            (0x09, [scale]) => self.buffered_read(*scale),
+            (0x0A, [scale]) => {
+                let flow = self.scale_value(self.measured_flow(), *scale)?.to_be_bytes();
+                Ok([flow, flow].concat())
+            }
+            // valve input source
+            (0x20, [0x00]) => Ok(vec![match self.valve_source {
+                ValveSource::Controller => 0x00,
+                ValveSource::ForceClosed => 0x01,
+                ValveSource::ForceOpen => 0x02,
+                ValveSource::Hold => 0x03,
+                ValveSource::UserDefined => 0x10,
+            }]),
+            (0x20, [0x00, source]) => {
+                let held_flow = self.measured_flow();
+                self.valve_source = match source {
+                    0x00 => ValveSource::Controller,
+                    0x01 => ValveSource::ForceClosed,
+                    0x02 => ValveSource::ForceOpen,
+                    0x03 => ValveSource::Hold,
+                    0x10 => ValveSource::UserDefined,
+                    _ => return Err(STATE_PARAMETER),
+                };
+                self.held_flow = held_flow;
+                Ok(Vec::new())
+            }
+            (0x20, [0x01]) => Ok(self.valve_user_value.to_be_bytes().to_vec()),
+            (0x20, [0x01, value @ ..]) => {
+                self.valve_user_value = read_f32(value)?;
+                Ok(Vec::new())
+            }
+            // medium unit configuration
+            (0x21, [0x00]) => Ok(encode_unit(self.resolved_unit()?)),
+            (0x21, [0x01]) => Ok(encode_unit(match self.medium_unit {
+                Some(unit) => unit,
+                None => self.resolved_unit()?,
+            })),
+            (0x21, [0x00, prefix, medium, timebase]) => {
+                let unit = GasUnit::from_be_bytes([*prefix, *medium, *timebase]);
+                let calibration_unit = self.calibration(self.active_calibration)?.unit;
+                let previous = self.medium_unit.replace(unit);
+                if conversion_factor(calibration_unit, self.resolved_unit()?).is_none() {
+                    self.medium_unit = previous;
+                    return Err(STATE_PARAMETER);
+                }
+                Ok(Vec::new())
+            }
+            (0x21, [0x0A]) => Ok(self.converted_full_scale()?.to_be_bytes().to_vec()),
+            // controller configuration
+            (0x22, [0x00]) => Ok(self.controller_gain.to_be_bytes().to_vec()),
+            (0x22, [0x00, value @ ..]) => {
+                self.controller_gain = read_f32(value)?;
+                Ok(Vec::new())
+            }
+            (0x22, [0x10]) => Ok(vec![self.pressure_dependent_gain as u8]),
+            (0x22, [0x10, enabled]) => {
+                self.pressure_dependent_gain = *enabled > 0;
+                Ok(Vec::new())
+            }
+            (0x22, [0x11]) => Ok(self.inlet_pressure.to_be_bytes().to_vec()),
+            (0x22, [0x11, value @ ..]) => {
+                self.inlet_pressure = read_f32(value)?;
+                Ok(Vec::new())
+            }
+            (0x22, [0x20]) => Ok(vec![self.gas_temperature_compensation as u8]),
+            (0x22, [0x20, enabled]) => {
+                self.gas_temperature_compensation = *enabled > 0;
+                Ok(Vec::new())
+            }
+            (0x22, [0x21]) => Ok(self.inlet_temperature.to_be_bytes().to_vec()),
+            (0x22, [0x21, value @ ..]) => {
+                self.inlet_temperature = read_f32(value)?;
+                Ok(Vec::new())
+            }
+            // raw measurements
+            (0x30, [0x00]) => Ok(self.config.raw_flow.to_be_bytes().to_vec()),
+            (0x30, [0x01 | 0x02]) => Ok(self.config.raw_thermal_conductivity.to_be_bytes().to_vec()),
+            (0x30, [0x10]) => Ok(self.config.temperature.to_be_bytes().to_vec()),
+            // calibration information
+            (0x40, [0x00]) => Ok((self.config.calibrations.len() as u32).to_be_bytes().to_vec()),
+            (0x40, [0x10, index @ ..]) => {
+                let index = read_u32(index)?;
+                match self.calibration(index) {
+                    Ok(_) => Ok(vec![1]),
+                    Err(STATE_INVALID_CALIBRATION) => Ok(vec![0]),
+                    Err(e) => Err(e),
+                }
+            }
+            (0x40, [subcommand, index @ ..]) => {
+                let slot = self.calibration(read_u32(index)?)?;
+                calibration_info(slot, *subcommand)
+            }
+            (0x44, [subcommand]) => calibration_info(self.calibration(self.active_calibration)?, *subcommand),
+            // active calibration
+            (0x45, []) => Ok(self.active_calibration.to_be_bytes().to_vec()),
+            (0x45, index) => {
+                let index = read_u32(index)?;
+                self.calibration(index)?;
+                self.active_calibration = index;
+                // switching the calibration closes the valve
+                self.setpoint = 0.0;
+                Ok(Vec::new())
+            }
+            // user memory
+            (0x6E, [start, count]) => {
+                let range = memory_range(*start, *count)?;
+                Ok(self.user_memory.get(range).ok_or(STATE_PARAMETER)?.to_vec())
+            }
+            (0x6E, [start, count, bytes @ ..]) => {
+                if bytes.len() != *count as usize {
+                    return Err(STATE_DATA_SIZE);
+                }
+                let range = memory_range(*start, *count)?;
+                self.user_memory.get_mut(range).ok_or(STATE_PARAMETER)?.copy_from_slice(bytes);
+                Ok(Vec::new())
+            }
+            // communication settings
+            (0x90, []) => Ok(vec![self.address]),
+            (0x90, [address]) => {
+                self.address = *address;
+                Ok(Vec::new())
+            }
+            (0x91, []) => Ok(self.baudrate.to_be_bytes().to_vec()),
+            (0x91, baudrate) => {
+                let baudrate = read_u32(baudrate)?;
+                if ![19200, 38400, 57600, 115200].contains(&baudrate) {
+                    return Err(STATE_PARAMETER);
+                }
+                self.baudrate = baudrate;
+                Ok(Vec::new())
+            }
+            (0x92, []) => {
+                self.factory_reset();
+                Ok(Vec::new())
+            }
+            // device information
+            (0xD0, [0x01]) => Ok(encode_string(&self.config.product_name)),
+            (0xD0, [0x02]) => Ok(encode_string(&self.config.article_code)),
+            (0xD0, [0x03]) => Ok(encode_string(&self.config.serial_number)),
+            (0xD1, []) => {
+                let v = self.config.version;
+                Ok(vec![
+                    v.firmware_major,
+                    v.firmware_minor,
+                    v.debug as u8,
+                    v.hardware_major,
+                    v.hardware_minor,
+                    v.protocol_major,
+                    v.protocol_minor,
+                ])
+            }
+            (0xD2, [clear]) => {
+                let mut response = self.error_flags.to_be_bytes().to_vec();
+                response.push(self.last_error);
+                if *clear > 0 {
+                    self.error_flags = 0;
+                    self.last_error = 0;
+                }
+                Ok(response)
+            }
+            (0xD3, []) => {
+                self.reset();
+                Ok(Vec::new())
+            }
+            (
+                0x00 | 0x02 | 0x03 | 0x04 | 0x08 | 0x09 | 0x0A | 0x20 | 0x21 | 0x22 | 0x30 | 0x40
+                | 0x44 | 0x6E | 0x90 | 0x92 | 0xD0 | 0xD1 | 0xD2 | 0xD3,
+                _,
+            ) => Err(STATE_DATA_SIZE),
+            _ => Err(STATE_UNKNOWN_COMMAND),
+        }
+    }
+}
+
+fn calibration_info(slot: &CalibrationSlot, subcommand: u8) -> Result<Vec<u8>, u8> {
+    match subcommand {
+        0x11 => Ok(encode_string(&slot.gas_description)),
+        0x12 => Ok(slot.gas_id.to_be_bytes().to_vec()),
+        0x13 => Ok(encode_unit(slot.unit)),
+        0x14 => Ok(slot.full_scale.to_be_bytes().to_vec()),
+        0x15 => Ok(slot.initial_conditions.to_bytes().to_vec()),
+        0x16 => Ok(slot.recalibration_conditions.to_bytes().to_vec()),
+        0x17 => Ok(slot.thermal_conductivity_reference.to_be_bytes().to_vec()),
+        _ => Err(STATE_PARAMETER),
+    }
+}
+
+/// Returns the factor a value in `from` has to be multiplied with to be expressed in `to`, or
+/// `None` if the units can't be converted into each other.
+fn conversion_factor(from: GasUnit, to: GasUnit) -> Option<f64> {
+    if from.medium_unit != to.medium_unit {
+        return None;
+    }
+    if from.unit_prefex == Prefixes::Undefined || to.unit_prefex == Prefixes::Undefined {
+        return None;
+    }
+
+    let prefix = 10_f64.powi(i32::from(i8::from(from.unit_prefex)) - i32::from(i8::from(to.unit_prefex)));
+    let timebase = match (timebase_seconds(from.timebase)?, timebase_seconds(to.timebase)?) {
+        (None, None) => 1.0,
+        (Some(from), Some(to)) => to / from,
+        _ => return None,
+    };
+
+    Some(prefix * timebase)
+}
+
+/// The length of a timebase in seconds. `Some(None)` is a unit without a timebase.
+fn timebase_seconds(timebase: TimeBases) -> Option<Option<f64>> {
+    match timebase {
+        TimeBases::None => Some(None),
+        TimeBases::Microsecond => Some(Some(1e-6)),
+        TimeBases::Milisecond => Some(Some(1e-3)),
+        TimeBases::Second => Some(Some(1.0)),
+        TimeBases::Minute => Some(Some(60.0)),
+        TimeBases::Hour => Some(Some(3600.0)),
+        TimeBases::Day => Some(Some(86400.0)),
+        TimeBases::Undefined => None,
+    }
+}
+
+fn memory_range(start: u8, count: u8) -> Result<std::ops::Range<usize>, u8> {
+    let range = start as usize..start as usize + count as usize;
+    if range.end > USER_MEMORY_SIZE {
+        return Err(STATE_PARAMETER);
+    }
+    Ok(range)
+}
+
+fn encode_unit(unit: GasUnit) -> Vec<u8> {
+    vec![
+        i8::from(unit.unit_prefex) as u8,
+        unit.medium_unit.into(),
+        unit.timebase.into(),
+    ]
+}
@@ -1,4 +1,13 @@
+// nothing received from a device may panic the driver, see "Panics" in the sfc-core README
+#![cfg_attr(not(test), deny(clippy::indexing_slicing, clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::unreachable))]
+
 pub mod calibration;
+pub mod capture;
+pub mod commands;
 pub mod device;
+#[cfg(any(test, feature = "emulator"))]
+pub mod emulator;
+pub mod monitor;
+pub mod pressure;
 pub mod scaling;
 pub mod valve_config;
@@ -0,0 +1,313 @@
+//! A live view of a controller from a thread of its own. [Monitor::spawn] takes the [Device],
+//! reads the measured flow and the latched device errors every poll interval and publishes them
+//! as a [Snapshot] any thread can look at. Writes are queued and run between two polls, so the
+//! device is never talked to from two places at once:
+//! ```no_run
+//! # fn run(device: sfc5xxx_rs::device::Device<sfc_core::discovery::NativePort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//!
+//! use sfc5xxx_rs::monitor::Monitor;
+//! use sfc5xxx_rs::scaling::Scale;
+//!
+//! let monitor = Monitor::spawn(device, Duration::from_millis(100), Scale::PhysicalValue);
+//! monitor.queue_setpoint(2.5, Scale::PhysicalValue).wait()?;
+//! if let Some(measurement) = &monitor.snapshot().measurement {
+//!     println!("{}", measurement);
+//! }
+//! let device = monitor.shutdown();
+//! # Ok(())
+//! # }
+//! ```
+//! [Monitor::shutdown] stops the thread and returns the device. Commands still queued then are
+//! dropped, waiting for them fails.
+
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use sfc_core::error::DeviceError;
+use sfc_core::measurement::Measurement;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+use crate::scaling::Scale;
+
+/// What a [Monitor] read in its latest polls
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// The latest measured flow. It is kept when a later read fails.
+    pub measurement: Option<Measurement>,
+    /// The latest error flags and last error code, read without clearing them, see
+    /// [Device::get_device_error_state]. It is kept when a later read fails.
+    pub error_state: Option<(u32, u8)>,
+    /// The error of the latest poll, [None] if both reads succeeded
+    pub error: Option<DeviceError>,
+    /// When the latest poll finished, [None] before the first one
+    pub updated: Option<Instant>,
+    /// The number of polls so far, failed ones included
+    pub polls: u64,
+    /// The number of polls in a row that failed
+    pub consecutive_failures: u32,
+}
+
+impl Snapshot {
+    /// The time since the latest poll finished, [None] before the first one
+    pub fn age(&self) -> Option<Duration> {
+        self.updated.map(|updated| updated.elapsed())
+    }
+
+    /// Returns true if there is a latched device error
+    pub fn has_device_error(&self) -> bool {
+        matches!(self.error_state, Some((flags, _)) if flags != 0)
+    }
+}
+
+type Command<T> = Box<dyn FnOnce(&mut Device<T>) + Send>;
+
+enum Message<T: Transport> {
+    Run(Command<T>),
+    Stop,
+}
+
+/// The result of a command queued on a [Monitor]
+#[derive(Debug)]
+pub struct Queued<R> {
+    result: mpsc::Receiver<Result<R, DeviceError>>,
+}
+
+impl<R> Queued<R> {
+    /// Waits until the command has run and returns its result. Fails with
+    /// [DeviceError::IoError] if the monitor was shut down before running it.
+    pub fn wait(self) -> Result<R, DeviceError> {
+        self.result.recv().unwrap_or_else(|_| {
+            Err(DeviceError::IoError(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the monitor was shut down before running the command",
+            )))
+        })
+    }
+}
+
+/// A device polled on a thread of its own, see the [module documentation](self)
+pub struct Monitor<T: Transport + Send + 'static> {
+    snapshot: Arc<RwLock<Snapshot>>,
+    commands: mpsc::Sender<Message<T>>,
+    thread: Option<JoinHandle<Device<T>>>,
+}
+
+impl<T: Transport + Send + 'static> Monitor<T> {
+    /// Starts polling the device every `interval`, reading the measured flow in `scale`. The
+    /// first poll is right away, a late poll moves the later ones instead of polling several
+    /// times back to back.
+    pub fn spawn(device: Device<T>, interval: Duration, scale: Scale) -> Self {
+        let snapshot = Arc::new(RwLock::new(Snapshot::default()));
+        let (commands, receiver) = mpsc::channel();
+        let published = Arc::clone(&snapshot);
+        let thread = thread::spawn(move || run(device, interval, scale, &published, &receiver));
+        Self {
+            snapshot,
+            commands,
+            thread: Some(thread),
+        }
+    }
+
+    /// The latest snapshot. Polling waits while it is borrowed, so don't hold on to it.
+    pub fn snapshot(&self) -> RwLockReadGuard<'_, Snapshot> {
+        self.snapshot.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The lock the snapshot is published through, for threads that don't own the monitor
+    pub fn shared_snapshot(&self) -> Arc<RwLock<Snapshot>> {
+        Arc::clone(&self.snapshot)
+    }
+
+    /// Queues a command to run on the device before the next poll. Commands run in the order
+    /// they were queued.
+    pub fn queue<R, F>(&self, command: F) -> Queued<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Device<T>) -> Result<R, DeviceError> + Send + 'static,
+    {
+        let (sender, result) = mpsc::sync_channel(1);
+        // if the thread is gone the command is dropped with its sender and waiting fails
+        let _ = self.commands.send(Message::Run(Box::new(move |device| {
+            let _ = sender.send(command(device));
+        })));
+        Queued { result }
+    }
+
+    /// Queues [Device::set_setpoint] with a setpoint in the given scale
+    pub fn queue_setpoint(&self, setpoint: f32, scale: Scale) -> Queued<()> {
+        self.queue(move |device| device.set_setpoint(setpoint.to_bits(), scale))
+    }
+
+    /// Stops polling and returns the device once the command or poll in progress has finished.
+    /// Commands still queued are dropped.
+    // the thread is only taken here and when dropped, nothing a device sends can make it missing
+    #[allow(clippy::expect_used)]
+    pub fn shutdown(mut self) -> Device<T> {
+        let _ = self.commands.send(Message::Stop);
+        let thread = self.thread.take().expect("the thread is only taken here");
+        thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+/// Stops the thread and drops the device, [Monitor::shutdown] returns it instead
+impl<T: Transport + Send + 'static> Drop for Monitor<T> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.send(Message::Stop);
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run<T: Transport>(
+    mut device: Device<T>,
+    interval: Duration,
+    scale: Scale,
+    snapshot: &RwLock<Snapshot>,
+    commands: &mpsc::Receiver<Message<T>>,
+) -> Device<T> {
+    let mut due = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= due {
+            poll(&mut device, scale, snapshot);
+            due = due.max(now) + interval;
+            continue;
+        }
+        match commands.recv_timeout(due - now) {
+            Ok(Message::Run(command)) => command(&mut device),
+            Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => return device,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+fn poll<T: Transport>(device: &mut Device<T>, scale: Scale, snapshot: &RwLock<Snapshot>) {
+    let measurement = device.read_measured_flow_recorded(scale);
+    let error_state = device.get_device_error_state(false);
+    let mut snapshot = snapshot.write().unwrap_or_else(PoisonError::into_inner);
+    let mut error = None;
+    match measurement {
+        Ok(measurement) => snapshot.measurement = Some(measurement),
+        Err(e) => error = Some(e),
+    }
+    match error_state {
+        Ok(error_state) => snapshot.error_state = Some(error_state),
+        Err(e) => error = error.or(Some(e)),
+    }
+    snapshot.consecutive_failures = match error {
+        Some(_) => snapshot.consecutive_failures + 1,
+        None => 0,
+    };
+    snapshot.error = error;
+    snapshot.updated = Some(Instant::now());
+    snapshot.polls += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorHandle, Sfc5xxxEmulator};
+
+    const INTERVAL: Duration = Duration::from_millis(50);
+
+    fn spawn_monitor() -> (Monitor<Sfc5xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc5xxxEmulator::default();
+        let handle = emulator.handle();
+        let device = Device::new(emulator, 0).unwrap();
+        (
+            Monitor::spawn(device, INTERVAL, Scale::PhysicalValue),
+            handle,
+        )
+    }
+
+    fn wait_for_poll(monitor: &Monitor<Sfc5xxxEmulator>, after: u64) {
+        let start = Instant::now();
+        while monitor.snapshot().polls <= after {
+            assert!(
+                start.elapsed() < INTERVAL * 4,
+                "no poll within four intervals"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn snapshot_stays_fresh() {
+        let (monitor, handle) = spawn_monitor();
+        wait_for_poll(&monitor, 0);
+        let first = monitor.snapshot().updated.unwrap();
+        assert_eq!(monitor.snapshot().error_state, Some((0, 0)));
+        assert_eq!(monitor.snapshot().measurement.as_ref().unwrap().value, 0.0);
+
+        handle.set_device_error(0x0102, 0x04);
+        let polls = monitor.snapshot().polls;
+        wait_for_poll(&monitor, polls);
+        let snapshot = monitor.snapshot();
+        assert!(snapshot.updated.unwrap() > first);
+        assert!(snapshot.age().unwrap() < INTERVAL);
+        assert_eq!(snapshot.error_state, Some((0x0102, 0x04)));
+        assert!(snapshot.has_device_error());
+        assert!(snapshot.error.is_none());
+    }
+
+    #[test]
+    fn queued_setpoint_within_one_poll() {
+        let (monitor, handle) = spawn_monitor();
+        wait_for_poll(&monitor, 0);
+        let polls = monitor.snapshot().polls;
+        let queued = Instant::now();
+        monitor
+            .queue_setpoint(2.5, Scale::PhysicalValue)
+            .wait()
+            .unwrap();
+        assert!(queued.elapsed() < INTERVAL);
+        assert_eq!(handle.setpoint() * 5.0, 2.5);
+
+        // the next poll already reads the new flow
+        wait_for_poll(&monitor, polls);
+        assert!(queued.elapsed() < INTERVAL * 2);
+        assert_eq!(monitor.snapshot().measurement.as_ref().unwrap().value, 2.5);
+    }
+
+    #[test]
+    fn failed_polls_keep_the_last_values() {
+        let (monitor, handle) = spawn_monitor();
+        wait_for_poll(&monitor, 0);
+        handle.unplug();
+        let polls = monitor.snapshot().polls;
+        wait_for_poll(&monitor, polls + 1);
+        let snapshot = monitor.snapshot();
+        assert!(matches!(snapshot.error, Some(DeviceError::IoError(_))));
+        assert!(snapshot.consecutive_failures >= 2);
+        assert!(snapshot.measurement.is_some());
+        assert_eq!(snapshot.error_state, Some((0, 0)));
+    }
+
+    #[test]
+    fn shutdown_returns_the_device() {
+        let (monitor, handle) = spawn_monitor();
+        let pending = monitor.queue(|device| device.get_serial_number());
+        let mut device = monitor.shutdown();
+        // the command was either run before the stop or dropped with the queue
+        let _ = pending.wait();
+        let requests = handle.requests().len();
+        device.get_device_error_state(false).unwrap();
+        assert_eq!(handle.requests().len(), requests + 1);
+
+        let (monitor, _handle) = spawn_monitor();
+        let shared = monitor.shared_snapshot();
+        drop(monitor);
+        let polls = shared.read().unwrap().polls;
+        thread::sleep(INTERVAL * 2);
+        assert_eq!(shared.read().unwrap().polls, polls);
+    }
+}
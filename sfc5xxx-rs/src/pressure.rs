@@ -0,0 +1,215 @@
+//! SFC5xxx calibrated in a pressure unit control pressure instead of flow. [PressureController]
+//! wraps such a device with a surface named after pressure, taking and returning values in
+//! whichever [PressureUnit] the caller works in:
+//! ```no_run
+//! # fn run(device: sfc5xxx_rs::device::Device<sfc_core::discovery::NativePort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use sfc5xxx_rs::pressure::{PressureController, PressureUnit};
+//!
+//! let mut controller = PressureController::new(device)?;
+//! controller.set_pressure_setpoint(250.0, PressureUnit::Pascal)?;
+//! let pressure = controller.read_measured_pressure(PressureUnit::InchH2O)?;
+//! println!("{} inH2O", pressure);
+//! # Ok(())
+//! # }
+//! ```
+//! Wrapping a device calibrated for flow fails with [DeviceError::NotAPressure], and the wrapper
+//! has no flow commands. [PressureController::into_inner] returns the device.
+
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::{GasUnit, Prefixes, TimeBases, UnitKind, Units};
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+use crate::scaling::Scale;
+
+/// The pressure units a [PressureController] converts between
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PressureUnit {
+    Pascal,
+    Bar,
+    /// Meters of water column
+    MeterH2O,
+    /// Inches of water column
+    InchH2O,
+}
+
+impl From<PressureUnit> for GasUnit {
+    fn from(unit: PressureUnit) -> Self {
+        GasUnit {
+            unit_prefex: Prefixes::Base,
+            medium_unit: match unit {
+                PressureUnit::Pascal => Units::Pascal,
+                PressureUnit::Bar => Units::Bar,
+                PressureUnit::MeterH2O => Units::MeterH20,
+                PressureUnit::InchH2O => Units::InchH20,
+            },
+            timebase: TimeBases::None,
+        }
+    }
+}
+
+/// A device calibrated for pressure, see the [module documentation](self)
+pub struct PressureController<T: Transport> {
+    device: Device<T>,
+    /// The unit the device measures in and takes setpoints in
+    unit: GasUnit,
+}
+
+impl<T: Transport> PressureController<T> {
+    /// Wraps the device if its active calibration, read with [Device::get_current_gas_unit], is
+    /// in a pressure unit. Fails with [DeviceError::NotAPressure] otherwise, and also if the
+    /// medium unit it reports its values in was configured to something else.
+    pub fn new(mut device: Device<T>) -> Result<Self, DeviceError> {
+        let calibration = device.get_current_gas_unit()?;
+        if calibration.kind() != UnitKind::Pressure {
+            return Err(DeviceError::NotAPressure(calibration));
+        }
+        let unit = device.get_medium_unit_configuration(false)?;
+        if unit.kind() != UnitKind::Pressure {
+            return Err(DeviceError::NotAPressure(unit));
+        }
+        Ok(Self { device, unit })
+    }
+
+    /// The unit the device measures in and takes setpoints in, like mbar
+    pub fn device_unit(&self) -> GasUnit {
+        self.unit
+    }
+
+    /// Sets the pressure setpoint, converted from `unit` into the unit of the device. It is
+    /// checked against the soft limits of the device, which are in the unit of the device.
+    pub fn set_pressure_setpoint(&mut self, pressure: f32, unit: PressureUnit) -> Result<(), DeviceError> {
+        let setpoint = GasUnit::from(unit).convert_pressure(pressure, self.unit)?;
+        self.device.set_setpoint(setpoint.to_bits(), Scale::PhysicalValue)
+    }
+
+    /// Returns the pressure setpoint in `unit`
+    pub fn get_pressure_setpoint(&mut self, unit: PressureUnit) -> Result<f32, DeviceError> {
+        let setpoint = f32::from_bits(self.device.get_setpoint(Scale::PhysicalValue)?);
+        self.unit.convert_pressure(setpoint, unit.into())
+    }
+
+    /// Reads the measured pressure in `unit`
+    pub fn read_measured_pressure(&mut self, unit: PressureUnit) -> Result<f32, DeviceError> {
+        let pressure = f32::from_bits(self.device.read_measured_flow(Scale::PhysicalValue)?);
+        self.unit.convert_pressure(pressure, unit.into())
+    }
+
+    /// Returns the highest pressure the device controls in `unit`, the full scale of the active
+    /// calibration
+    pub fn full_scale(&mut self, unit: PressureUnit) -> Result<f32, DeviceError> {
+        let full_scale = self.device.get_converted_fullscale()?;
+        self.unit.convert_pressure(full_scale, unit.into())
+    }
+
+    /// Configures the unit the device measures in and takes setpoints in, like mbar or kPa. Flow
+    /// units are refused with [DeviceError::NotAPressure] without sending anything.
+    pub fn set_device_unit(&mut self, unit: GasUnit) -> Result<(), DeviceError> {
+        if unit.kind() != UnitKind::Pressure {
+            return Err(DeviceError::NotAPressure(unit));
+        }
+        self.device.set_medium_unit_configuration(unit)?;
+        self.unit = self.device.get_medium_unit_configuration(false)?;
+        Ok(())
+    }
+
+    /// Returns the wrapped device
+    pub fn into_inner(self) -> Device<T> {
+        self.device
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorConfig, EmulatorHandle, Sfc5xxxEmulator};
+
+    /// A device with one calibration up to `full_scale` in the unit
+    fn pressure_device(unit: GasUnit, full_scale: f32) -> (Device<Sfc5xxxEmulator>, EmulatorHandle) {
+        let mut config = EmulatorConfig::default();
+        let mut slot = config.calibrations[0].take().unwrap();
+        slot.unit = unit;
+        slot.full_scale = full_scale;
+        config.calibrations = vec![Some(slot)];
+        let emulator = Sfc5xxxEmulator::new(config);
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    fn unit(unit_prefex: Prefixes, medium_unit: Units) -> GasUnit {
+        GasUnit {
+            unit_prefex,
+            medium_unit,
+            timebase: TimeBases::None,
+        }
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!((value - expected).abs() <= expected.abs() * 1e-5, "{} is not {}", value, expected);
+    }
+
+    #[test]
+    fn pascal_device() {
+        let (device, handle) = pressure_device(unit(Prefixes::Kilo, Units::Pascal), 200.0);
+        let mut controller = PressureController::new(device).unwrap();
+        assert_eq!(controller.device_unit(), unit(Prefixes::Kilo, Units::Pascal));
+        controller.set_pressure_setpoint(0.5, PressureUnit::Bar).unwrap();
+        assert_close(handle.setpoint(), 0.25);
+        assert_close(controller.read_measured_pressure(PressureUnit::Pascal).unwrap(), 50_000.0);
+        assert_close(controller.get_pressure_setpoint(PressureUnit::Bar).unwrap(), 0.5);
+        assert_close(controller.full_scale(PressureUnit::Bar).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn bar_device() {
+        let (device, handle) = pressure_device(unit(Prefixes::Milli, Units::Bar), 4000.0);
+        let mut controller = PressureController::new(device).unwrap();
+        controller.set_pressure_setpoint(100_000.0, PressureUnit::Pascal).unwrap();
+        assert_close(handle.setpoint(), 0.25);
+        assert_close(controller.read_measured_pressure(PressureUnit::Bar).unwrap(), 1.0);
+        assert_close(controller.read_measured_pressure(PressureUnit::MeterH2O).unwrap(), 10.197_16);
+
+        // the device can be switched to another prefix of its unit
+        controller.set_device_unit(unit(Prefixes::Base, Units::Bar)).unwrap();
+        assert_eq!(controller.device_unit(), unit(Prefixes::Base, Units::Bar));
+        assert_close(controller.read_measured_pressure(PressureUnit::Pascal).unwrap(), 100_000.0);
+    }
+
+    #[test]
+    fn meter_water_column_device() {
+        let (device, handle) = pressure_device(unit(Prefixes::Base, Units::MeterH20), 10.0);
+        let mut controller = PressureController::new(device).unwrap();
+        controller.set_pressure_setpoint(9806.65, PressureUnit::Pascal).unwrap();
+        assert_close(handle.setpoint(), 0.1);
+        assert_close(controller.read_measured_pressure(PressureUnit::MeterH2O).unwrap(), 1.0);
+        assert_close(controller.read_measured_pressure(PressureUnit::InchH2O).unwrap(), 39.370_08);
+    }
+
+    #[test]
+    fn inch_water_column_device() {
+        let (device, handle) = pressure_device(unit(Prefixes::Base, Units::InchH20), 40.0);
+        let mut controller = PressureController::new(device).unwrap();
+        controller.set_pressure_setpoint(0.5, PressureUnit::MeterH2O).unwrap();
+        assert_close(handle.setpoint(), 19.685_04 / 40.0);
+        assert_close(controller.read_measured_pressure(PressureUnit::Pascal).unwrap(), 4903.325);
+        assert_close(controller.full_scale(PressureUnit::Pascal).unwrap(), 9963.556);
+    }
+
+    #[test]
+    fn flow_devices_and_units_are_refused() {
+        let device = Device::new(Sfc5xxxEmulator::default(), 0).unwrap();
+        let slm = unit(Prefixes::Base, Units::StandardLiter);
+        assert!(matches!(
+            PressureController::new(device),
+            Err(DeviceError::NotAPressure(GasUnit { medium_unit: Units::StandardLiter, .. }))
+        ));
+
+        let (device, handle) = pressure_device(unit(Prefixes::Milli, Units::Bar), 4000.0);
+        let mut controller = PressureController::new(device).unwrap();
+        let requests = handle.requests().len();
+        let error = controller.set_device_unit(GasUnit { timebase: TimeBases::Minute, ..slm }).unwrap_err();
+        assert_eq!(error.to_string(), "a value in l/min is not a pressure");
+        assert_eq!(handle.requests().len(), requests);
+    }
+}
@@ -1,7 +1,22 @@
+use sfc_core::measurement::ValueScale;
+
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Scale {
+    #[cfg_attr(feature = "serde", serde(rename = "Normalized"))]
     Normilized,
     PhysicalValue,
     UserDefined
 }
+
+impl From<Scale> for ValueScale {
+    fn from(scale: Scale) -> Self {
+        match scale {
+            Scale::Normilized => ValueScale::Normalized,
+            Scale::PhysicalValue => ValueScale::Physical,
+            Scale::UserDefined => ValueScale::UserDefined,
+        }
+    }
+}
@@ -1,4 +1,6 @@
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InputSourceConfig {
     Controller,
     ForceClosed,
@@ -7,14 +9,28 @@ pub enum InputSourceConfig {
     UserDefined(f32),
 }
 
-impl Into<u8> for InputSourceConfig {
-    fn into(self) -> u8 {
-        match self {
-            Self::Controller => 0x01,
-            Self::ForceClosed => 0x02,
-            Self::ForceOpen => 0x03,
-            Self::Hold => 0x04,
-            Self::UserDefined(_) => 0x10,
+impl From<InputSourceConfig> for u8 {
+    fn from(value: InputSourceConfig) -> Self {
+        match value {
+            InputSourceConfig::Controller => 0x00,
+            InputSourceConfig::ForceClosed => 0x01,
+            InputSourceConfig::ForceOpen => 0x02,
+            InputSourceConfig::Hold => 0x03,
+            InputSourceConfig::UserDefined(_) => 0x10,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_sources_are_numbered_from_zero() {
+        assert_eq!(u8::from(InputSourceConfig::Controller), 0x00);
+        assert_eq!(u8::from(InputSourceConfig::ForceClosed), 0x01);
+        assert_eq!(u8::from(InputSourceConfig::ForceOpen), 0x02);
+        assert_eq!(u8::from(InputSourceConfig::Hold), 0x03);
+        assert_eq!(u8::from(InputSourceConfig::UserDefined(0.5)), 0x10);
+    }
+}
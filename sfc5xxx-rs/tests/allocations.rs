@@ -0,0 +1,57 @@
+//! Counts the allocations of the offline parsers, which read the response where it was received
+//! and only allocate for the strings they keep. A binary of its own, the allocator counts for
+//! every test in it.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use sfc5xxx_rs::calibration::CalibrationCondition;
+
+/// The system allocator, counting the allocations of every thread separately so tests running
+/// next to each other don't count for each other
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// The allocations `f` made on this thread
+fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[test]
+fn calibration_condition_allocates_only_its_strings() {
+    let mut block = [0_u8; 127];
+    block[..9].copy_from_slice(b"Sensirion");
+    block[50..58].copy_from_slice(b"operator");
+    block[100..102].copy_from_slice(&2024_u16.to_be_bytes());
+    block[102] = 3;
+    block[103] = 14;
+    block[106..110].copy_from_slice(&23.0_f32.to_be_bytes());
+    block[118] = 1;
+
+    let (condition, count) = allocations(|| CalibrationCondition::from_bytes(&block).unwrap());
+    assert_eq!(condition.company, "Sensirion");
+    assert_eq!(condition.operator, "operator");
+    assert_eq!(condition.calibration_year, 2024);
+    assert_eq!(condition.calibration_temperature, 23.0);
+    assert!(condition.real_gas_calibration);
+    assert_eq!(count, 2);
+}
@@ -0,0 +1,197 @@
+//! A cascade of two emulated controllers, the measured flow of the first setting the second.
+//!
+//! Run with `cargo test -p sfc5xxx-rs --features emulator --test cascade`.
+#![cfg(feature = "emulator")]
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc_core::cascade::{Cascade, CascadeConfig, CascadeError, FailurePolicy};
+use sfc_core::error::DeviceError;
+use sfc_core::flow_controller::FlowController;
+use sfc5xxx_rs::device::Device;
+use sfc5xxx_rs::emulator::{EmulatorHandle, Fault, Sfc5xxxEmulator};
+
+/// A controller with a full scale of 5 that measures the flow it is set to
+fn controller() -> (Device<Sfc5xxxEmulator>, EmulatorHandle) {
+    let emulator = Sfc5xxxEmulator::default();
+    let handle = emulator.handle();
+    (Device::new(emulator, 0).unwrap(), handle)
+}
+
+type Halving = Cascade<Device<Sfc5xxxEmulator>, Device<Sfc5xxxEmulator>, fn(f32) -> f32>;
+
+/// A cascade setting the downstream controller to half the upstream flow of 2, returns the
+/// handles of the upstream and the downstream emulator
+fn cascade(config: CascadeConfig) -> (Halving, EmulatorHandle, EmulatorHandle) {
+    let (mut upstream, upstream_handle) = controller();
+    let (downstream, downstream_handle) = controller();
+    FlowController::set_setpoint(&mut upstream, 2.0).unwrap();
+    let mut cascade = Cascade::new(upstream, downstream, (|flow| flow / 2.0) as fn(f32) -> f32);
+    cascade.set_config(config);
+    (cascade, upstream_handle, downstream_handle)
+}
+
+/// The physical setpoint of an emulated controller with a full scale of 5
+fn setpoint(handle: &EmulatorHandle) -> f32 {
+    handle.setpoint() * 5.0
+}
+
+#[test]
+fn downstream_follows_the_transfer_of_the_upstream_flow() {
+    let (mut cascade, _, downstream) = cascade(CascadeConfig::default());
+    assert_eq!(cascade.step().unwrap(), 1.0);
+    assert_eq!(setpoint(&downstream), 1.0);
+    assert_eq!(cascade.measured(), Some(2.0));
+    assert_eq!(cascade.setpoint(), Some(1.0));
+
+    FlowController::set_setpoint(cascade.upstream_mut(), 3.0).unwrap();
+    assert_eq!(cascade.step().unwrap(), 1.5);
+    assert_eq!(setpoint(&downstream), 1.5);
+}
+
+#[test]
+fn setpoints_stay_within_the_downstream_full_scale() {
+    let (upstream, _) = controller();
+    let (downstream, handle) = controller();
+    let mut cascade = Cascade::new(upstream, downstream, |_| 20.0);
+    assert_eq!(cascade.step().unwrap(), 5.0);
+    assert_eq!(setpoint(&handle), 5.0);
+
+    let (upstream, downstream) = cascade.into_inner();
+    let mut cascade = Cascade::new(upstream, downstream, |_| -1.0);
+    assert_eq!(cascade.step().unwrap(), 0.0);
+    let (upstream, downstream) = cascade.into_inner();
+    let mut cascade = Cascade::new(upstream, downstream, |_| f32::NAN);
+    assert_eq!(cascade.step().unwrap(), 0.0);
+    assert_eq!(setpoint(&handle), 0.0);
+}
+
+#[test]
+fn the_rate_limit_ramps_the_setpoint() {
+    let (mut cascade, _, downstream) = cascade(CascadeConfig {
+        max_rate: Some(1.0),
+        ..CascadeConfig::default()
+    });
+    let start = Instant::now();
+    // the first update may move by one interval of 100ms from the setpoint of the device
+    assert!((cascade.step_at(start).unwrap() - 0.1).abs() < 1e-6);
+    assert!((cascade.step_at(start + Duration::from_millis(500)).unwrap() - 0.6).abs() < 1e-6);
+    assert!((setpoint(&downstream) - 0.6).abs() < 1e-6);
+    assert_eq!(cascade.step_at(start + Duration::from_secs(2)).unwrap(), 1.0);
+
+    // down as well as up
+    FlowController::set_setpoint(cascade.upstream_mut(), 0.0).unwrap();
+    let later = start + Duration::from_millis(2250);
+    assert!((cascade.step_at(later).unwrap() - 0.75).abs() < 1e-6);
+}
+
+#[test]
+fn an_upstream_failure_holds_the_last_setpoint() {
+    let (mut cascade, upstream, downstream) = cascade(CascadeConfig::default());
+    cascade.step().unwrap();
+    upstream.unplug();
+    let requests = downstream.requests().len();
+    match cascade.step() {
+        Err(CascadeError::Upstream(DeviceError::IoError(_))) => {}
+        other => panic!("expected the upstream to fail, got {:?}", other),
+    }
+    assert_eq!(setpoint(&downstream), 1.0);
+    assert_eq!(cascade.setpoint(), Some(1.0));
+    assert_eq!(downstream.requests().len(), requests);
+}
+
+#[test]
+fn an_upstream_failure_zeroes_the_setpoint() {
+    let (mut cascade, upstream, downstream) = cascade(CascadeConfig {
+        max_rate: Some(1.0),
+        failure_policy: FailurePolicy::Zero,
+        ..CascadeConfig::default()
+    });
+    let start = Instant::now();
+    cascade.step_at(start).unwrap();
+    cascade.step_at(start + Duration::from_secs(2)).unwrap();
+    assert_eq!(setpoint(&downstream), 1.0);
+
+    upstream.inject_fault(Fault::ErrorState(0x42));
+    let failed = start + Duration::from_secs(3);
+    assert!(matches!(cascade.step_at(failed), Err(CascadeError::Upstream(_))));
+    assert_eq!(setpoint(&downstream), 0.0);
+    assert_eq!(cascade.setpoint(), Some(0.0));
+
+    // the next update ramps up from zero again
+    let recovered = failed + Duration::from_millis(300);
+    assert!((cascade.step_at(recovered).unwrap() - 0.3).abs() < 1e-6);
+}
+
+#[test]
+fn a_downstream_failure_applies_the_policy() {
+    let (mut cascade, _, downstream) = cascade(CascadeConfig::default());
+    cascade.step().unwrap();
+    downstream.inject_fault(Fault::ErrorState(0x42));
+    assert!(matches!(cascade.step(), Err(CascadeError::Downstream(_))));
+    assert_eq!(setpoint(&downstream), 1.0);
+
+    cascade.set_config(CascadeConfig {
+        failure_policy: FailurePolicy::Zero,
+        ..CascadeConfig::default()
+    });
+    downstream.inject_fault(Fault::ErrorState(0x42));
+    assert!(matches!(cascade.step(), Err(CascadeError::Downstream(_))));
+    assert_eq!(setpoint(&downstream), 0.0);
+    assert_eq!(cascade.setpoint(), Some(0.0));
+
+    // a downstream controller that can't be zeroed leaves the setpoint unknown
+    downstream.unplug();
+    assert!(matches!(cascade.step(), Err(CascadeError::Downstream(DeviceError::IoError(_)))));
+    assert_eq!(cascade.setpoint(), None);
+}
+
+#[test]
+fn a_read_only_downstream_is_never_sent_anything() {
+    let (upstream, upstream_handle) = controller();
+    let emulator = Sfc5xxxEmulator::default();
+    let handle = emulator.handle();
+    let downstream = Device::builder(emulator).read_only(true).build().unwrap();
+    let mut cascade = Cascade::new(upstream, downstream, |flow| flow);
+    cascade.set_config(CascadeConfig {
+        failure_policy: FailurePolicy::Zero,
+        ..CascadeConfig::default()
+    });
+    assert!(matches!(cascade.step(), Err(CascadeError::Downstream(DeviceError::ReadOnly(_)))));
+    assert!(handle.requests().is_empty());
+    assert!(upstream_handle.requests().is_empty());
+}
+
+#[test]
+fn a_running_cascade_updates_until_stopped() {
+    let (cascade, upstream, downstream) = cascade(CascadeConfig {
+        interval: Duration::from_millis(10),
+        ..CascadeConfig::default()
+    });
+    let running = cascade.start();
+    let start = Instant::now();
+    while running.status().updates < 3 {
+        assert!(start.elapsed() < Duration::from_secs(1), "no updates within a second");
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(running.status().setpoint, Some(1.0));
+    assert_eq!(running.status().measured, Some(2.0));
+    assert_eq!(setpoint(&downstream), 1.0);
+
+    upstream.unplug();
+    let updates = running.status().updates;
+    while running.status().updates < updates + 2 {
+        assert!(start.elapsed() < Duration::from_secs(1), "no updates within a second");
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(matches!(running.status().error, Some(CascadeError::Upstream(_))));
+    assert!(running.status().consecutive_failures >= 1);
+
+    let mut cascade = running.stop();
+    let requests = downstream.requests().len();
+    thread::sleep(Duration::from_millis(30));
+    assert_eq!(downstream.requests().len(), requests);
+    assert_eq!(cascade.setpoint(), Some(1.0));
+    FlowController::set_setpoint(cascade.downstream_mut(), 0.0).unwrap();
+}
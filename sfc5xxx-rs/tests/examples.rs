@@ -0,0 +1,121 @@
+//! The examples against the emulator, so they keep compiling and working with the driver.
+//!
+//! Run with `cargo test -p sfc5xxx-rs --features emulator --test examples`.
+#![cfg(feature = "emulator")]
+
+use std::time::Duration;
+
+use sfc5xxx_rs::device::Device;
+use sfc5xxx_rs::emulator::{EmulatorConfig, Fault, Sfc5xxxEmulator};
+use sfc_core::cascade::CascadeError;
+use sfc_core::error::{DeviceError, StateResponseError};
+use sfc_core::flow_controller::FlowController;
+
+#[allow(dead_code)]
+#[path = "../examples/sfc5xxx-demo.rs"]
+mod demo;
+
+#[allow(dead_code)]
+#[path = "../examples/cascade.rs"]
+mod cascade;
+
+#[test]
+fn the_demo_runs_and_closes_the_valve() {
+    let emulator = Sfc5xxxEmulator::new(EmulatorConfig::default());
+    let handle = emulator.handle();
+    let mut device = Device::builder(emulator).address(0).build().unwrap();
+
+    demo::run(&mut device).unwrap();
+    assert_eq!(handle.setpoint(), 0.0);
+}
+
+#[test]
+fn the_demo_takes_the_port_baudrate_and_address() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert_eq!(demo::parse(&args(&["/dev/ttyUSB0"])), Some(("/dev/ttyUSB0", 115200, 0)));
+    assert_eq!(demo::parse(&args(&["COM3", "19200", "4"])), Some(("COM3", 19200, 4)));
+    assert_eq!(demo::parse(&args(&[])), None);
+    assert_eq!(demo::parse(&args(&["COM3", "fast"])), None);
+    assert_eq!(demo::parse(&args(&["COM3", "19200", "256"])), None);
+    assert_eq!(demo::parse(&args(&["COM3", "19200", "4", "more"])), None);
+}
+
+#[test]
+fn the_demo_exits_with_the_class_of_the_failure() {
+    let emulator = Sfc5xxxEmulator::new(EmulatorConfig::default());
+    let handle = emulator.handle();
+    let mut device = Device::new(emulator, 0).unwrap();
+    device.set_response_timeout(Duration::from_millis(20));
+
+    handle.inject_fault(Fault::DropResponse);
+    let error = demo::run(&mut device).unwrap_err();
+    assert!(matches!(error, DeviceError::Timeout));
+    assert_eq!(demo::exit_code(&error), 3);
+
+    handle.inject_fault(Fault::CorruptChecksum);
+    assert_eq!(demo::exit_code(&demo::run(&mut device).unwrap_err()), 5);
+
+    // the overheating protection stopped the measure loop
+    let unit = device.get_current_gas_unit().unwrap();
+    handle.inject_fault(Fault::ErrorState(0x2D));
+    let error = demo::read_averages(&mut device, &unit).unwrap_err();
+    assert!(matches!(
+        error,
+        DeviceError::StateResponse(StateResponseError::MeasureLoopNotRunning)
+    ));
+    assert_eq!(demo::exit_code(&error), 6);
+    assert_eq!(demo::exit_code(&DeviceError::RetriesExhausted(3, Box::new(error))), 6);
+}
+
+fn emulator_at(address: u8) -> Sfc5xxxEmulator {
+    Sfc5xxxEmulator::new(EmulatorConfig {
+        address,
+        ..EmulatorConfig::default()
+    })
+}
+
+#[test]
+fn the_cascade_follows_the_upstream_flow_and_closes_the_valve() {
+    let mut upstream = Device::new(emulator_at(1), 1).unwrap();
+    FlowController::set_setpoint(&mut upstream, 4.0).unwrap();
+    let emulator = emulator_at(2);
+    let handle = emulator.handle();
+    let downstream = Device::new(emulator, 2).unwrap();
+
+    cascade::run(upstream, downstream, Duration::from_millis(500)).unwrap();
+    assert_eq!(cascade::transfer(4.0), 2.0);
+    // the setpoint ramped up before the valve was closed
+    let setpoints: Vec<f32> = handle
+        .requests()
+        .into_iter()
+        .filter(|(_, command, data)| *command == 0x00 && data.len() == 5)
+        .map(|(_, _, data)| f32::from_be_bytes(data[1..].try_into().unwrap()))
+        .collect();
+    assert!(setpoints.len() > 2);
+    // by at most a tenth per second towards the half of the upstream flow
+    assert!(setpoints.windows(2).any(|pair| pair[1] > pair[0]));
+    assert!(setpoints.iter().all(|&setpoint| setpoint < 0.1));
+    assert_eq!(setpoints.last(), Some(&0.0));
+    assert_eq!(handle.setpoint(), 0.0);
+}
+
+#[test]
+fn the_cascade_fails_before_running_unattended() {
+    let upstream = emulator_at(1);
+    upstream.handle().unplug();
+    let upstream = Device::new(upstream, 1).unwrap();
+    let downstream = Device::new(emulator_at(2), 2).unwrap();
+    let error = cascade::run(upstream, downstream, Duration::from_secs(60)).unwrap_err();
+    assert!(matches!(error, CascadeError::Upstream(DeviceError::IoError(_))));
+}
+
+#[test]
+fn the_cascade_takes_the_port_addresses_and_duration() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let seconds = Duration::from_secs;
+    assert_eq!(cascade::parse(&args(&["/dev/ttyUSB0", "1", "2"])), Some(("/dev/ttyUSB0", 1, 2, seconds(30))));
+    assert_eq!(cascade::parse(&args(&["COM3", "1", "2", "60"])), Some(("COM3", 1, 2, seconds(60))));
+    assert_eq!(cascade::parse(&args(&["COM3", "1"])), None);
+    assert_eq!(cascade::parse(&args(&["COM3", "1", "256"])), None);
+    assert_eq!(cascade::parse(&args(&["COM3", "1", "2", "60", "more"])), None);
+}
@@ -0,0 +1,282 @@
+//! Every reading of the driver against a device that answers with arbitrary states and data.
+//! Whatever the response carries, the driver returns an error and doesn't panic.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use proptest::prelude::*;
+
+use sfc5xxx_rs::calibration::CalibrationCondition;
+use sfc5xxx_rs::device::Device;
+use sfc5xxx_rs::scaling::Scale;
+use sfc_core::error::DeviceError;
+use sfc_core::health::HealthRequirements;
+use sfc_core::replay::Capture;
+use sfc_core::shdlc::{MISOFrame, MOSIFrame};
+use sfc_core::transport::Transport;
+
+/// Answers every request with a well formed frame for its address and command, the state and data are the next of
+/// `answers`. Times out once they ran out.
+struct Answering {
+    answers: VecDeque<(u8, Vec<u8>)>,
+    request: Vec<u8>,
+    response: VecDeque<u8>,
+}
+
+impl Read for Answering {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut count = 0;
+        for slot in buf.iter_mut() {
+            let Some(byte) = self.response.pop_front() else { break };
+            *slot = byte;
+            count += 1;
+        }
+        if count == 0 {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        Ok(count)
+    }
+}
+
+impl Write for Answering {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request.extend_from_slice(buf);
+        if let Ok(request) = MOSIFrame::decode(&self.request) {
+            self.request.clear();
+            if let Some((state, data)) = self.answers.pop_front() {
+                let frame = MISOFrame::from_parts(request.get_address(), request.get_command_number(), state, &data).unwrap();
+                self.response.extend(frame.to_raw());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Answering {
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// Reads that the driver may make of one input before it is stuck in a loop
+const MAX_READS: usize = 4096;
+
+/// Hands out the input as the bytes received, whatever was written. The size of every read is taken from the input
+/// as well, from its last byte backwards, and is 0 to 8 bytes. Times out once the input ran out.
+struct Chunked {
+    input: Vec<u8>,
+    read: usize,
+    reads: usize,
+}
+
+impl Read for Chunked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        assert!(self.reads < MAX_READS, "the driver kept reading");
+        let rest = self.input.get(self.read..).unwrap_or_default();
+        if rest.is_empty() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        let size = self.input.iter().rev().nth(self.reads % self.input.len()).unwrap_or(&0) % 9;
+        let count = rest.len().min(buf.len()).min(size.into());
+        buf[..count].copy_from_slice(&rest[..count]);
+        self.read += count;
+        Ok(count)
+    }
+}
+
+impl Write for Chunked {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Chunked {
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// Every public reading of the device, the fuzz input picks the one to start with
+const METHODS: &[fn(&mut Device<Chunked>)] = &[
+    |device| drop(device.get_product_name()),
+    |device| drop(device.get_article_code()),
+    |device| drop(device.get_serial_number()),
+    |device| drop(device.get_version()),
+    |device| drop(device.get_device_error_state(false)),
+    |device| drop(device.get_device_address()),
+    |device| drop(device.get_baudrate()),
+    |device| drop(device.get_setpoint(Scale::PhysicalValue)),
+    |device| drop(device.read_measured_flow(Scale::PhysicalValue)),
+    |device| drop(device.read_measured_flow_recorded(Scale::PhysicalValue)),
+    |device| drop(device.read_measured_flow_buffered(Scale::PhysicalValue)),
+    |device| drop(device.read_measured_flow_two_sensors(Scale::PhysicalValue)),
+    |device| drop(device.set_setpoint_and_read_measured_value(Scale::PhysicalValue, 0.5)),
+    |device| drop(device.set_setpoint_and_read_measured_value_two_sensors(Scale::PhysicalValue, 0.5)),
+    |device| drop(device.is_setpoint_persistant()),
+    |device| drop(device.get_valve_input_source()),
+    |device| drop(device.get_medium_unit_configuration(true)),
+    |device| drop(device.get_converted_fullscale()),
+    |device| drop(device.get_user_controller_gain()),
+    |device| drop(device.get_pressure_dependant_gain()),
+    |device| drop(device.get_gas_temperature_compensation()),
+    |device| drop(device.measure_raw_flow()),
+    |device| drop(device.measure_raw_thermal_conductivity(true)),
+    |device| drop(device.get_calibration_validity(0)),
+    |device| drop(device.get_calibration_gas_description(0)),
+    |device| drop(device.get_calibration_gas_id(0)),
+    |device| drop(device.get_calibration_gas_unit(0)),
+    |device| drop(device.get_calibration_fullscale(0)),
+    |device| drop(device.get_calibration_initial_conditions(0)),
+    |device| drop(device.get_calibration_recalibration_conditions(0)),
+    |device| drop(device.get_calibration_thermal_conductivity_refrence(0)),
+    |device| drop(device.get_current_gas_description()),
+    |device| drop(device.get_current_initial_calibration_conditions()),
+    |device| drop(device.get_current_recalibration_condition()),
+    |device| drop(device.read_user_memory(0, 32)),
+];
+
+/// Feeds the input through the whole receive path of a device: the reads of the connection, the frame decoder, the
+/// length checks and the decoders of every reading, strings and calibration conditions among them
+fn receive(input: &[u8]) {
+    let first = input.first().map_or(0, |&first| usize::from(first));
+    let port = Chunked { input: input.to_vec(), read: 0, reads: 0 };
+    let mut device = Device::builder(port)
+        .address(0)
+        .timeout(Duration::from_millis(1))
+        .inter_byte_timeout(Duration::from_millis(1))
+        .probe(false)
+        .build()
+        .unwrap();
+    for method in METHODS.iter().cycle().skip(first % METHODS.len()).take(METHODS.len()) {
+        method(&mut device);
+    }
+}
+
+/// The received frames of the golden captures, each one a seed of the fuzz input
+fn corpus() -> Vec<Vec<u8>> {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+    let mut corpus = Vec::new();
+    for entry in fs::read_dir(fixtures).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "frames") {
+            let capture = Capture::parse(&fs::read_to_string(path).unwrap()).unwrap();
+            corpus.extend(capture.exchanges.into_iter().map(|(_, received)| received));
+        }
+    }
+    corpus.sort();
+    corpus
+}
+
+/// A seed of the corpus with a few bytes changed, several seeds in a row or arbitrary bytes
+fn input() -> impl Strategy<Value = Vec<u8>> {
+    let corpus = corpus();
+    let mutated = (prop::sample::select(corpus.clone()), prop::collection::vec(any::<(usize, u8)>(), 0..4))
+        .prop_map(|(mut seed, changes)| {
+            for (at, byte) in changes {
+                let at = at % seed.len();
+                seed[at] = byte;
+            }
+            seed
+        });
+    let sequence = prop::collection::vec(prop::sample::select(corpus), 1..8).prop_map(|seeds| seeds.concat());
+    prop_oneof![
+        2 => mutated,
+        2 => sequence,
+        1 => prop::collection::vec(any::<u8>(), 0..256),
+    ]
+}
+
+/// Mostly successful answers, with the data of the response a device sends most of the time
+fn answers() -> impl Strategy<Value = Vec<(u8, Vec<u8>)>> {
+    let state = prop_oneof![6 => Just(0x00), 1 => any::<u8>()];
+    let data = prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..13),
+        prop::collection::vec(any::<u8>(), 0..=255),
+    ];
+    prop::collection::vec((state, data), 0..80)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn any_response_is_an_error_or_a_value(answers in answers()) {
+        let port = Answering { answers: answers.into(), request: Vec::new(), response: VecDeque::new() };
+        let mut device = Device::builder(port)
+            .address(0)
+            .timeout(Duration::from_millis(1))
+            .inter_byte_timeout(Duration::from_millis(1))
+            .probe(false)
+            .build()
+            .unwrap();
+
+        let _ = device.get_product_name();
+        let _ = device.get_article_code();
+        let _ = device.get_serial_number();
+        let _ = device.get_version();
+        let _ = device.get_device_error_state(false);
+        let _ = device.get_device_address();
+        let _ = device.get_baudrate();
+        for scale in [Scale::Normilized, Scale::PhysicalValue, Scale::UserDefined] {
+            let _ = device.get_setpoint(scale);
+            let _ = device.read_measured_flow(scale);
+            let _ = device.read_measured_flow_recorded(scale);
+            let _ = device.read_measured_flow_buffered(scale);
+            let _ = device.read_measured_flow_two_sensors(scale);
+            let _ = device.set_setpoint_and_read_measured_value(scale, 0.5);
+            let _ = device.set_setpoint_and_read_measured_value_two_sensors(scale, 0.5);
+        }
+        let _ = device.is_setpoint_persistant();
+        let _ = device.get_valve_input_source();
+        let _ = device.get_medium_unit_configuration(true);
+        let _ = device.get_converted_fullscale();
+        let _ = device.get_user_controller_gain();
+        let _ = device.get_pressure_dependant_gain();
+        let _ = device.get_gas_temperature_compensation();
+        let _ = device.measure_raw_flow();
+        let _ = device.measure_raw_thermal_conductivity(true);
+        let _ = device.get_calibration_validity(0);
+        let _ = device.get_calibration_gas_description(0);
+        let _ = device.get_calibration_gas_id(0);
+        let _ = device.get_calibration_gas_unit(0);
+        let _ = device.get_calibration_fullscale(0);
+        let _ = device.get_calibration_initial_conditions(0);
+        let _ = device.get_calibration_recalibration_conditions(0);
+        let _ = device.get_calibration_thermal_conductivity_refrence(0);
+        let _ = device.get_current_gas_description();
+        let _ = device.get_current_initial_calibration_conditions();
+        let _ = device.get_current_recalibration_condition();
+        let _ = device.read_user_memory(0, 32);
+        let _ = device.health_check(HealthRequirements { min_firmware: Some((1, 0)), ..HealthRequirements::default() });
+    }
+
+    #[test]
+    fn calibration_conditions_take_any_bytes(data in prop::collection::vec(any::<u8>(), 0..255)) {
+        let _ = CalibrationCondition::from_bytes(&data);
+    }
+
+    #[test]
+    fn the_receive_path_takes_any_input(input in input()) {
+        receive(&input);
+    }
+}
@@ -0,0 +1,61 @@
+//! A sustained capture from the measurement buffer of the emulator at its fastest sampling time,
+//! with the emulator sampling in real time on a thread of its own. Fails when the host loop
+//! falls far enough behind for the buffer to overflow.
+//!
+//! Run with `cargo test -p sfc5xxx-rs --features emulator --test throughput`.
+#![cfg(feature = "emulator")]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc5xxx_rs::capture::{BufferedCapture, ThroughputConfig};
+use sfc5xxx_rs::device::Device;
+use sfc5xxx_rs::emulator::{EmulatorConfig, Sfc5xxxEmulator};
+use sfc5xxx_rs::scaling::Scale;
+
+/// The fastest sampling time of the SFC5xxx, in seconds
+const FASTEST_SAMPLING_TIME: f32 = 0.001;
+
+#[test]
+fn no_values_lost_at_the_fastest_sampling_time() {
+    let config = EmulatorConfig {
+        sampling_time: FASTEST_SAMPLING_TIME,
+        buffer_depth: 100,
+        ..Default::default()
+    };
+    let emulator = Sfc5xxxEmulator::new(config);
+    let handle = emulator.handle();
+    let mut device = Device::new(emulator, 0).unwrap();
+
+    // the device samples as real time passes
+    let running = Arc::new(AtomicBool::new(true));
+    let sampler = {
+        let running = Arc::clone(&running);
+        thread::spawn(move || {
+            let mut last = Instant::now();
+            while running.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+                let now = Instant::now();
+                handle.advance(now - last);
+                last = now;
+            }
+        })
+    };
+
+    let mut values = Vec::with_capacity(4000);
+    let capture = BufferedCapture::new(ThroughputConfig::default(), Scale::Normilized);
+    let report = capture
+        .run(&mut device, Duration::from_secs(3), |read| values.extend_from_slice(read))
+        .unwrap();
+    running.store(false, Ordering::Relaxed);
+    sampler.join().unwrap();
+
+    assert!(report.is_lossless(), "{:?}", report);
+    assert_eq!(report.samples, values.len() as u64);
+    assert!((report.nominal_samples_per_second() - 1000.0).abs() < 1e-3);
+    // every value sampled up to the last poll was read
+    assert!(report.samples_per_second() > 950.0, "{:?}", report);
+    assert!(report.samples_per_second() < 1050.0, "{:?}", report);
+}
@@ -0,0 +1,282 @@
+//! # sfc6xxx
+//! A Python module on top of sfc6xxx-rs. `Sfc6xxxDevice` has the method names of Sensirion's
+//! [python-uart-sfx6xxx](https://sensirion.github.io/python-uart-sfx6xxx/) so existing scripts
+//! keep working, while the protocol is the one implementation of this repository. The GIL is
+//! released while a command waits on the serial port.
+//!
+//! Build and install it into the active virtual environment with
+//! `maturin develop -m sfc6xxx-py/Cargo.toml`.
+
+use pyo3::prelude::*;
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+use sfc6xxx_rs::sfc_core::discovery::NativePort;
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::gasunit::GasUnit;
+
+#[cfg(all(unix, feature = "emulator"))]
+mod testing;
+
+/// The Python exceptions a [DeviceError] is raised as. Every message is the `Display` of the
+/// error.
+mod exceptions {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyException;
+
+    create_exception!(sfc6xxx, DeviceError, PyException, "Communicating with the device failed");
+    create_exception!(
+        sfc6xxx,
+        StateResponseError,
+        DeviceError,
+        "The device answered with an error state"
+    );
+    create_exception!(
+        sfc6xxx,
+        DeviceTimeoutError,
+        DeviceError,
+        "The device did not respond in time"
+    );
+    create_exception!(
+        sfc6xxx,
+        FrameError,
+        DeviceError,
+        "The device sent a frame that could not be read"
+    );
+}
+
+/// Raises the error as the exception of its cause, a command that failed after retrying is
+/// raised like its last attempt with the whole message
+fn to_exception(error: DeviceError) -> PyErr {
+    let message = error.to_string();
+    match cause(&error) {
+        DeviceError::StateResponse(_) => exceptions::StateResponseError::new_err(message),
+        DeviceError::Timeout | DeviceError::DeadlineExceeded => {
+            exceptions::DeviceTimeoutError::new_err(message)
+        }
+        DeviceError::ShdlcError(_)
+        | DeviceError::InvalidChecksum(_, _)
+        | DeviceError::InvalidString
+        | DeviceError::IncompleteFrame => exceptions::FrameError::new_err(message),
+        _ => exceptions::DeviceError::new_err(message),
+    }
+}
+
+fn cause(error: &DeviceError) -> &DeviceError {
+    match error {
+        DeviceError::RetriesExhausted(_, last) => cause(last),
+        error => error,
+    }
+}
+
+/// The raw codes of the unit prefix, medium unit and timebase, like the official library
+fn unit_codes(unit: GasUnit) -> (i8, u8, u8) {
+    (unit.unit_prefex.into(), unit.medium_unit.into(), unit.timebase.into())
+}
+
+/// An SFC6xxx on a serial port
+#[pyclass(module = "sfc6xxx")]
+struct Sfc6xxxDevice {
+    device: Device<NativePort>,
+}
+
+impl Sfc6xxxDevice {
+    /// Runs a command with the GIL released
+    fn run<R: Send>(
+        &mut self,
+        py: Python<'_>,
+        command: impl FnOnce(&mut Device<NativePort>) -> Result<R, DeviceError> + Send,
+    ) -> PyResult<R> {
+        let device = &mut self.device;
+        py.allow_threads(|| command(device)).map_err(to_exception)
+    }
+}
+
+#[pymethods]
+impl Sfc6xxxDevice {
+    /// Opens the serial port and checks that the device answers
+    #[new]
+    #[pyo3(signature = (port, slave_address = 0, baudrate = 115200))]
+    fn new(py: Python<'_>, port: &str, slave_address: u8, baudrate: u32) -> PyResult<Self> {
+        py.allow_threads(|| Device::open(port, Baudrate::try_from(baudrate)?, slave_address))
+        .map(|device| Self { device })
+        .map_err(to_exception)
+    }
+
+    fn get_setpoint(&mut self, py: Python<'_>) -> PyResult<f32> {
+        self.run(py, |device| device.get_setpoint())
+    }
+
+    fn set_setpoint(&mut self, py: Python<'_>, setpoint: f32) -> PyResult<()> {
+        self.run(py, |device| device.set_setpoint(setpoint))
+    }
+
+    fn read_measured_value(&mut self, py: Python<'_>) -> PyResult<f32> {
+        self.run(py, |device| device.read_measured_value())
+    }
+
+    fn read_average_measured_value(
+        &mut self,
+        py: Python<'_>,
+        measurement_count: u8,
+    ) -> PyResult<f32> {
+        self.run(py, |device| device.read_average_measured_value(measurement_count))
+    }
+
+    fn set_setpoint_and_read_measured_value(
+        &mut self,
+        py: Python<'_>,
+        setpoint: f32,
+    ) -> PyResult<f32> {
+        self.run(py, |device| device.set_setpoint_and_read_measured_value(setpoint))
+    }
+
+    fn get_user_controller_gain(&mut self, py: Python<'_>) -> PyResult<f32> {
+        self.run(py, |device| device.get_controller_gain())
+    }
+
+    fn set_user_controller_gain(&mut self, py: Python<'_>, gain: f32) -> PyResult<()> {
+        self.run(py, |device| device.set_controller_gain(gain))
+    }
+
+    fn get_user_init_step(&mut self, py: Python<'_>) -> PyResult<f32> {
+        self.run(py, |device| device.get_initial_step())
+    }
+
+    fn set_user_init_step(&mut self, py: Python<'_>, init_step: f32) -> PyResult<()> {
+        self.run(py, |device| device.set_initial_step(init_step))
+    }
+
+    fn measure_raw_flow(&mut self, py: Python<'_>) -> PyResult<u16> {
+        self.run(py, |device| device.measure_raw_flow())
+    }
+
+    fn measure_raw_thermal_conductivity(&mut self, py: Python<'_>) -> PyResult<u16> {
+        self.run(py, |device| device.measure_raw_thermal_conductivity())
+    }
+
+    fn measure_temperature(&mut self, py: Python<'_>) -> PyResult<f32> {
+        self.run(py, |device| device.measure_temperature())
+    }
+
+    fn get_number_of_calibrations(&mut self, py: Python<'_>) -> PyResult<u32> {
+        self.run(py, |device| device.get_number_of_calibrations())
+    }
+
+    fn get_calibration_validity(&mut self, py: Python<'_>, calibration_index: u32) -> PyResult<bool> {
+        self.run(py, |device| device.get_calibration_validity(calibration_index))
+    }
+
+    fn get_calibration_gas_id(&mut self, py: Python<'_>, calibration_index: u32) -> PyResult<u32> {
+        self.run(py, |device| device.get_calibration_gas_id(calibration_index))
+    }
+
+    /// Returns the codes of the unit prefix, medium unit and timebase
+    fn get_calibration_gas_unit(
+        &mut self,
+        py: Python<'_>,
+        calibration_index: u32,
+    ) -> PyResult<(i8, u8, u8)> {
+        self.run(py, |device| device.get_calibration_gas_unit(calibration_index))
+            .map(unit_codes)
+    }
+
+    fn get_calibration_fullscale(
+        &mut self,
+        py: Python<'_>,
+        calibration_index: u32,
+    ) -> PyResult<f32> {
+        self.run(py, |device| device.get_calibration_full_scale(calibration_index))
+    }
+
+    fn get_current_gas_id(&mut self, py: Python<'_>) -> PyResult<u32> {
+        self.run(py, |device| device.get_current_gas_id())
+    }
+
+    /// Returns the codes of the unit prefix, medium unit and timebase
+    fn get_current_gas_unit(&mut self, py: Python<'_>) -> PyResult<(i8, u8, u8)> {
+        self.run(py, |device| device.get_current_gas_unit()).map(unit_codes)
+    }
+
+    fn get_current_fullscale(&mut self, py: Python<'_>) -> PyResult<f32> {
+        self.run(py, |device| device.get_current_full_scale())
+    }
+
+    fn get_calibration_number(&mut self, py: Python<'_>) -> PyResult<u32> {
+        self.run(py, |device| device.get_calliration_number())
+    }
+
+    fn set_calibration(&mut self, py: Python<'_>, calibration_index: u32) -> PyResult<()> {
+        self.run(py, |device| device.set_callibration(calibration_index))
+    }
+
+    fn set_calibration_volatile(&mut self, py: Python<'_>, calibration_index: u32) -> PyResult<()> {
+        self.run(py, |device| device.set_callibration_volitile(calibration_index))
+    }
+
+    fn get_slave_address(&mut self, py: Python<'_>) -> PyResult<u8> {
+        self.run(py, |device| device.get_slave_adress())
+    }
+
+    fn set_slave_address(&mut self, py: Python<'_>, slave_address: u8) -> PyResult<()> {
+        self.run(py, |device| device.set_slave_adress(slave_address))
+    }
+
+    fn get_baudrate(&mut self, py: Python<'_>) -> PyResult<u32> {
+        self.run(py, |device| device.get_baudrate().map(u32::from))
+    }
+
+    fn set_baudrate(&mut self, py: Python<'_>, baudrate: u32) -> PyResult<()> {
+        let baudrate = Baudrate::try_from(baudrate).map_err(to_exception)?;
+        self.run(py, |device| device.set_baudrate(baudrate))
+    }
+
+    fn get_product_type(&mut self, py: Python<'_>) -> PyResult<String> {
+        self.run(py, |device| device.get_product_type())
+    }
+
+    fn get_product_name(&mut self, py: Python<'_>) -> PyResult<String> {
+        self.run(py, |device| device.get_product_name())
+    }
+
+    fn get_article_code(&mut self, py: Python<'_>) -> PyResult<String> {
+        self.run(py, |device| device.get_article_code())
+    }
+
+    fn get_serial_number(&mut self, py: Python<'_>) -> PyResult<String> {
+        self.run(py, |device| device.get_serial_number())
+    }
+
+    /// Returns the firmware major and minor, the debug flag, the hardware major and minor and
+    /// the protocol major and minor
+    fn get_version(&mut self, py: Python<'_>) -> PyResult<(u8, u8, bool, u8, u8, u8, u8)> {
+        self.run(py, |device| device.get_version()).map(|version| {
+            (
+                version.firmware_major,
+                version.firmware_minor,
+                version.debug,
+                version.hardware_major,
+                version.hardware_minor,
+                version.protocol_major,
+                version.protocol_minor,
+            )
+        })
+    }
+
+    fn device_reset(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.run(py, |device| device.reset_device())
+    }
+}
+
+#[pymodule]
+fn sfc6xxx(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add_class::<Sfc6xxxDevice>()?;
+    m.add("DeviceError", py.get_type::<exceptions::DeviceError>())?;
+    m.add("StateResponseError", py.get_type::<exceptions::StateResponseError>())?;
+    m.add("DeviceTimeoutError", py.get_type::<exceptions::DeviceTimeoutError>())?;
+    m.add("FrameError", py.get_type::<exceptions::FrameError>())?;
+    #[cfg(all(unix, feature = "emulator"))]
+    m.add_class::<testing::EmulatorPort>()?;
+    Ok(())
+}
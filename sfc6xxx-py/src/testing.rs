@@ -0,0 +1,111 @@
+//! An emulated SFC6xxx behind a pseudo terminal, so the Python tests open it by name like a
+//! real port. Only built with the `emulator` feature.
+
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use serialport::{SerialPort, TTYPort};
+use sfc6xxx_rs::emulator::{EmulatorHandle, Fault, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::transport::Transport;
+
+/// An emulated device on a pseudo terminal, pass `path` to `Sfc6xxxDevice`
+#[pyclass(module = "sfc6xxx")]
+pub(crate) struct EmulatorPort {
+    #[pyo3(get)]
+    path: String,
+    handle: EmulatorHandle,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    // the slave end stays open so the master never sees a hang up
+    _slave: TTYPort,
+}
+
+#[pymethods]
+impl EmulatorPort {
+    #[new]
+    fn new() -> PyResult<Self> {
+        let mut emulator = Sfc6xxxEmulator::default();
+        let handle = emulator.handle();
+        let (mut master, slave) =
+            TTYPort::pair().map_err(|e| PyOSError::new_err(e.to_string()))?;
+        let path = slave.name().unwrap_or_default();
+        SerialPort::set_timeout(&mut master, Duration::from_millis(5))
+            .map_err(|e| PyOSError::new_err(e.to_string()))?;
+        Transport::set_timeout(&mut emulator, Duration::from_millis(1))
+            .map_err(crate::to_exception)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut buf = [0_u8; 64];
+            while !running.load(Ordering::Relaxed) {
+                match master.read(&mut buf) {
+                    Ok(read) => {
+                        if emulator.write_all(&buf[..read]).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                    Err(_) => return,
+                }
+                while let Ok(read) = emulator.read(&mut buf) {
+                    if master.write_all(&buf[..read]).and_then(|_| master.flush()).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            path,
+            handle,
+            stop,
+            thread: Some(thread),
+            _slave: slave,
+        })
+    }
+
+    /// The setpoint the emulated device was last given
+    fn setpoint(&self) -> f32 {
+        self.handle.setpoint()
+    }
+
+    /// The number of requests the emulated device has received
+    fn request_count(&self) -> usize {
+        self.handle.requests().len()
+    }
+
+    /// The next response is never sent
+    fn drop_next_response(&self) {
+        self.handle.inject_fault(Fault::DropResponse);
+    }
+
+    /// The next response is sent with a wrong checksum
+    fn corrupt_next_checksum(&self) {
+        self.handle.inject_fault(Fault::CorruptChecksum);
+    }
+
+    /// Stops the emulated device, it is also stopped when garbage collected
+    fn close(&mut self, py: Python<'_>) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            py.allow_threads(|| {
+                let _ = thread.join();
+            });
+        }
+    }
+}
+
+impl Drop for EmulatorPort {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,16 @@
+import pytest
+
+import sfc6xxx
+
+
+@pytest.fixture
+def emulator():
+    port = sfc6xxx.EmulatorPort()
+    yield port
+    port.close()
+
+
+@pytest.fixture
+def device(emulator):
+    # the constructor probes the device
+    return sfc6xxx.Sfc6xxxDevice(emulator.path)
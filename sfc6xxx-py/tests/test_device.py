@@ -0,0 +1,94 @@
+"""Runs the module against the emulator on a pseudo terminal.
+
+Build it with the emulator first:
+    maturin develop -m sfc6xxx-py/Cargo.toml --features emulator
+    pytest sfc6xxx-py/tests
+"""
+
+import threading
+import time
+
+import pytest
+
+import sfc6xxx
+
+
+def test_command_sequence(device, emulator):
+    assert emulator.request_count() == 1
+
+    device.set_setpoint(2.5)
+    assert device.get_setpoint() == 2.5
+    assert device.read_measured_value() == 2.5
+    assert device.read_average_measured_value(50) == 2.5
+    assert device.set_setpoint_and_read_measured_value(1.5) == 1.5
+    assert emulator.setpoint() == 1.5
+
+    assert device.get_serial_number() == "EMU6000001"
+    assert device.get_product_name() == "SFC6000D-5SLM"
+    assert device.get_version() == (1, 0, False, 1, 0, 2, 0)
+    assert device.get_baudrate() == 115200
+    assert device.get_slave_address() == 0
+
+
+def test_calibrations(device):
+    assert device.get_number_of_calibrations() == 4
+    assert device.get_calibration_validity(0)
+    assert not device.get_calibration_validity(3)
+    assert device.get_calibration_gas_id(2) == 3
+    assert device.get_calibration_fullscale(2) == 2.5
+    # standard liter per minute
+    assert device.get_calibration_gas_unit(0) == (0, 1, 4)
+
+    device.set_calibration_volatile(2)
+    assert device.get_calibration_number() == 2
+    assert device.get_current_gas_id() == 3
+    assert device.get_current_fullscale() == 2.5
+    assert device.get_current_gas_unit() == (0, 1, 4)
+
+
+def test_device_errors_are_raised_with_their_message(device):
+    with pytest.raises(sfc6xxx.StateResponseError, match="the sent parameter was out of range"):
+        device.read_average_measured_value(101)
+    with pytest.raises(sfc6xxx.StateResponseError, match="no valid gas calibration"):
+        device.get_calibration_gas_id(3)
+    assert issubclass(sfc6xxx.StateResponseError, sfc6xxx.DeviceError)
+
+
+def test_transmission_errors(device, emulator):
+    emulator.drop_next_response()
+    with pytest.raises(sfc6xxx.DeviceTimeoutError, match="did not respond in time"):
+        device.get_baudrate()
+
+    emulator.corrupt_next_checksum()
+    with pytest.raises(sfc6xxx.FrameError, match="checksum"):
+        device.get_baudrate()
+
+    assert device.get_baudrate() == 115200
+
+
+def test_missing_port_raises_a_device_error():
+    with pytest.raises(sfc6xxx.DeviceError):
+        sfc6xxx.Sfc6xxxDevice("/dev/does-not-exist")
+
+
+def test_the_gil_is_released_while_waiting(device, emulator):
+    errors = []
+
+    def wait_for_the_timeout():
+        try:
+            device.get_baudrate()
+        except sfc6xxx.DeviceTimeoutError as error:
+            errors.append(error)
+
+    emulator.drop_next_response()
+    waiting = threading.Thread(target=wait_for_the_timeout)
+    ticks = 0
+    waiting.start()
+    start = time.monotonic()
+    while waiting.is_alive():
+        ticks += 1
+        time.sleep(0.01)
+    # the other thread ran for the whole response timeout
+    assert time.monotonic() - start > 0.3
+    assert ticks > 10
+    assert len(errors) == 1
@@ -1,9 +1,10 @@
 // example taken from https://sensirion.github.io/python-uart-sfx6xxx/execute-measurements.html#example-script
-use sfc6xxx_rs::device::{Device, DeviceError, StateResponseError};
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+use sfc6xxx_rs::sfc_core::error::{DeviceError, StateResponseError};
 
 fn main() {
-    let port = serialport::new("/dev/ttyUSB0", 115200).open_native().unwrap();
-    let mut device = Device::new(port, 0).unwrap();
+    let mut device = Device::open("/dev/ttyUSB0", Baudrate::B115200, 0).unwrap();
     device.reset_device().unwrap();
     std::thread::sleep(std::time::Duration::from_secs(2));
 
@@ -0,0 +1,140 @@
+// a tour of the driver on a device at a serial port: what it is, the calibrations it holds and a
+// few averaged readings at a tenth of the full scale. Run with
+// `cargo run -p sfc6xxx-rs --example sfc6xxx-demo -- /dev/ttyUSB0 115200 0`, the baudrate
+// defaults to 115200 and the address to 0. The exit code tells what went wrong like the one of
+// sfcctl: 2 for invalid arguments, 3 when the device didn't answer in time, 4 when the port
+// failed, 5 for a garbled answer, 6 for an error state of the device and 1 for anything else.
+// tests/examples.rs runs it against the emulator.
+use std::process::ExitCode;
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::sfc_core::connection::RetryConfig;
+use sfc6xxx_rs::sfc_core::discovery::open_port;
+use sfc6xxx_rs::sfc_core::error::{DeviceError, StateResponseError};
+use sfc6xxx_rs::sfc_core::gasunit::GasUnit;
+use sfc6xxx_rs::sfc_core::transport::Transport;
+
+/// The setpoint of the readings as a fraction of the full scale
+const SETPOINT: f32 = 0.1;
+/// How many averaged readings are taken
+const READINGS: usize = 10;
+/// How many measurements the device averages for each reading
+const AVERAGED: u8 = 50;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((port, baudrate, address)) = parse(&args) else {
+        eprintln!("usage: sfc6xxx-demo <port> [baudrate] [address]");
+        return ExitCode::from(2);
+    };
+
+    let result = open_port(port, baudrate)
+        .and_then(|port| {
+            Device::builder(port)
+                .address(address)
+                .retries(RetryConfig::default())
+                .build()
+        })
+        .and_then(|mut device| run(&mut device));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("sfc6xxx-demo: {}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// The port, baudrate and address from the arguments, [None] if they are not valid
+pub fn parse(args: &[String]) -> Option<(&str, u32, u8)> {
+    let (port, rest) = args.split_first()?;
+    if rest.len() > 2 {
+        return None;
+    }
+    let baudrate = rest.first().map_or(Ok(115200), |baudrate| baudrate.parse()).ok()?;
+    let address = rest.get(1).map_or(Ok(0), |address| address.parse()).ok()?;
+    Some((port, baudrate, address))
+}
+
+/// Prints what the device is and its valid calibrations, then flows at a tenth of the full
+/// scale for the readings and closes the valve again, also when a reading failed
+pub fn run<T: Transport>(device: &mut Device<T>) -> Result<(), DeviceError> {
+    println!(
+        "{} {}, serial number {}",
+        device.get_product_name()?,
+        device.get_article_code()?,
+        device.get_serial_number()?
+    );
+    let version = device.get_version()?;
+    println!(
+        "firmware {}.{}, hardware {}.{}, SHDLC {}.{}",
+        version.firmware_major,
+        version.firmware_minor,
+        version.hardware_major,
+        version.hardware_minor,
+        version.protocol_major,
+        version.protocol_minor
+    );
+
+    let active = device.get_calliration_number()?;
+    for index in 0..device.get_number_of_calibrations()? {
+        if !device.get_calibration_validity(index)? {
+            continue;
+        }
+        let marker = if index == active { '*' } else { ' ' };
+        println!(
+            "{} calibration {}: gas {}, full scale {} {}",
+            marker,
+            index,
+            device.get_calibration_gas_id(index)?,
+            device.get_calibration_full_scale(index)?,
+            device.get_calibration_gas_unit(index)?
+        );
+    }
+
+    let unit = device.get_current_gas_unit()?;
+    let setpoint = device.get_current_full_scale()? * SETPOINT;
+    println!("setpoint {} {}", setpoint, unit);
+    device.set_setpoint(setpoint)?;
+    let readings = read_averages(device, &unit);
+    // the valve is closed whatever happened to the readings
+    device.set_setpoint(0.0)?;
+    readings
+}
+
+/// Prints the averaged readings, stopping at the first that fails
+pub fn read_averages<T: Transport>(
+    device: &mut Device<T>,
+    unit: &GasUnit,
+) -> Result<(), DeviceError> {
+    for _ in 0..READINGS {
+        match device.read_average_measured_value(AVERAGED) {
+            Ok(value) => println!("average of {} measurements: {} {}", AVERAGED, value, unit),
+            Err(e @ DeviceError::StateResponse(StateResponseError::MeasureLoopNotRunning)) => {
+                eprintln!(
+                    "most likely the valve was closed by the overheating protection, make sure \
+                     gas flows and start again"
+                );
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The exit code for the error, grouped like the ones of sfcctl
+pub fn exit_code(error: &DeviceError) -> u8 {
+    match error {
+        // a command that failed after retrying failed like its last attempt
+        DeviceError::RetriesExhausted(_, last) => exit_code(last),
+        DeviceError::Timeout | DeviceError::DeadlineExceeded => 3,
+        DeviceError::IoError(_) | DeviceError::PortError(_) => 4,
+        DeviceError::ShdlcError(_)
+        | DeviceError::InvalidChecksum(_, _)
+        | DeviceError::InvalidString
+        | DeviceError::IncompleteFrame => 5,
+        DeviceError::StateResponse(_) => 6,
+        _ => 1,
+    }
+}
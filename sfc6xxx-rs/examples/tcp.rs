@@ -0,0 +1,13 @@
+// talks to a device connected to a serial device server such as ser2net or a Moxa NPort
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::transport::TcpTransport;
+
+fn main() -> Result<(), DeviceError> {
+    let mut device = Device::new(TcpTransport::connect("10.0.0.5:4001")?, 0)?;
+
+    println!("serial number: {}", device.get_serial_number()?);
+    println!("measured value: {}", device.read_measured_value()?);
+
+    Ok(())
+}
@@ -0,0 +1,561 @@
+//! The SFC6xxx on an async stream, available with the `async` feature. [AsyncDevice] has the
+//! same commands as the blocking [Device](crate::device::Device) and shares the code that builds
+//! their frames and reads their responses with it. It runs on any executor, the stream and timer
+//! are given as an [AsyncTransport] and a [Delay].
+
+use core::time::Duration;
+
+#[cfg(feature = "stream")]
+use futures_util::Stream;
+use sfc_core::async_connection::{AsyncConnection, RetryConfig, Timeouts, ValidationLevel};
+use sfc_core::async_transport::{AsyncTransport, Delay};
+use sfc_core::baudrate::Baudrate;
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::GasUnit;
+#[cfg(feature = "stream")]
+use sfc_core::measurement::{Measurement, ValueScale};
+use sfc_core::shdlc::{DeviceString, Version};
+use sfc_core::stats::CommStats;
+
+use crate::commands::{self, Request};
+
+/// An SFC6XXX on an [AsyncTransport], for example a `tokio_serial::SerialStream` wrapped in
+/// `FromTokio` or an Embassy UART wrapped in `FromEmbeddedIo`. Every command waits for the response without blocking the thread, the
+/// response and inter-byte timeouts are measured with the [Delay].
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn run() -> Result<(), sfc6xxx_rs::sfc_core::error::DeviceError> {
+/// use sfc6xxx_rs::async_device::AsyncDevice;
+/// use sfc6xxx_rs::sfc_core::async_transport::{FromTokio, TokioDelay};
+/// # let stream = tokio::io::duplex(64).0;
+/// // let stream = tokio_serial::new("/dev/ttyUSB0", 115200).open_native_async()?;
+/// let mut device = AsyncDevice::new(FromTokio(stream), TokioDelay, 0).await?;
+/// device.set_setpoint(2.5).await?;
+/// let flow = device.read_measured_value().await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Cancellation
+/// Every command can be cancelled by dropping its future, for example with a timeout or in a
+/// `select!`. The next command waits for the response of the cancelled one and discards it, see
+/// [AsyncConnection#cancellation]. A cancelled read leaves nothing behind. A cancelled command
+/// that changes the device, like [AsyncDevice::set_setpoint], may or may not have taken effect;
+/// read the value back when it matters. The commands that are retried may have been sent more
+/// than once.
+#[derive(Debug)]
+pub struct AsyncDevice<T: AsyncTransport, D: Delay> {
+    connection: AsyncConnection<T, D>,
+    slave_adress: u8,
+}
+
+impl<T: AsyncTransport, D: Delay> AsyncDevice<T, D> {
+    /// Creates the device and probes it with [AsyncDevice::get_baudrate], like
+    /// [Device::new](crate::device::Device::new). The timeouts are measured with the delay.
+    pub async fn new(stream: T, delay: D, slave_adress: u8) -> Result<Self, DeviceError> {
+        let mut device = Self {
+            connection: AsyncConnection::new(stream, delay),
+            slave_adress,
+        };
+
+        let _ = device.get_baudrate().await?;
+
+        Ok(device)
+    }
+
+    /// Returns how long the device has to start answering a query, see [AsyncDevice::timeouts]
+    pub fn response_timeout(&self) -> Duration {
+        self.connection.response_timeout()
+    }
+
+    /// Sets how long the device has to start answering a command before the command fails with
+    /// [DeviceError::Timeout], the same for every kind of command
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.connection.set_response_timeout(timeout);
+    }
+
+    /// Returns how long the device has to start answering each kind of command
+    pub fn timeouts(&self) -> Timeouts {
+        self.connection.timeouts()
+    }
+
+    /// Sets how long the device has to start answering each kind of command, so a short query
+    /// timeout doesn't cut off a long average
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.connection.set_timeouts(timeouts);
+    }
+
+    /// Returns the time allowed between two bytes of a response
+    pub fn inter_byte_timeout(&self) -> Duration {
+        self.connection.inter_byte_timeout()
+    }
+
+    /// Sets the time allowed between two bytes of a response before the command fails with
+    /// [DeviceError::IncompleteFrame]
+    pub fn set_inter_byte_timeout(&mut self, timeout: Duration) {
+        self.connection.set_inter_byte_timeout(timeout);
+    }
+
+    /// Returns true if pending input is discarded before every command
+    pub fn clear_stale_input(&self) -> bool {
+        self.connection.clear_stale_input()
+    }
+
+    /// Sets whether input that is pending when a command is sent is discarded first. On by
+    /// default.
+    pub fn set_clear_stale_input(&mut self, clear: bool) {
+        self.connection.set_clear_stale_input(clear);
+    }
+
+    /// Returns the retry configuration, [None] if commands are never retried
+    pub fn retry(&self) -> Option<RetryConfig> {
+        self.connection.retry()
+    }
+
+    /// Sets how failed commands are retried, off by default. Commands that would have a
+    /// different effect when executed twice are only retried with
+    /// [RetryConfig::retry_non_idempotent].
+    pub fn set_retry(&mut self, retry: Option<RetryConfig>) {
+        self.connection.set_retry(retry);
+    }
+
+    /// Returns how closely responses are checked
+    pub fn validation_level(&self) -> ValidationLevel {
+        self.connection.validation_level()
+    }
+
+    /// Sets which checks of a response fail a command, which are only logged and which are
+    /// skipped, see [ValidationLevel]. [ValidationLevel::Standard] by default.
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.connection.set_validation_level(level);
+    }
+
+    /// Returns how many commands were sent, retried and failed, and how long the device takes to
+    /// answer, since the device was created or [Self::reset_stats]
+    pub fn stats(&self) -> &CommStats {
+        self.connection.stats()
+    }
+
+    /// Sets the counters of [Self::stats] back to zero
+    pub fn reset_stats(&mut self) {
+        self.connection.reset_stats();
+    }
+
+    /// Gets back in step with the device after the serial stream got garbled, see
+    /// [Device::resync](crate::device::Device::resync). Cancelling it is safe, it starts over
+    /// the next time.
+    pub async fn resync(&mut self) -> Result<(), DeviceError> {
+        self.connection.resync(self.slave_adress).await
+    }
+
+    /// Replaces the stream after the old one stopped working and probes the device with
+    /// [AsyncDevice::get_baudrate]. The settings are kept, bytes received from the old stream
+    /// are dropped. Cancelled during the probe the new stream is kept and the next command discards
+    /// the response to the probe.
+    pub async fn reconnect(&mut self, new_stream: T) -> Result<(), DeviceError> {
+        self.connection.reconnect(new_stream);
+        let _ = self.get_baudrate().await?;
+        Ok(())
+    }
+
+    /// Returns the underlying stream, for example to change its baudrate
+    pub fn get_mut(&mut self) -> &mut T {
+        self.connection.get_mut()
+    }
+
+    /// Returns the underlying stream
+    pub fn into_inner(self) -> T {
+        self.connection.into_inner()
+    }
+
+    /// Returns the current flow setpoint as a physical value in SLM
+    pub async fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_setpoint(self.slave_adress)?).await
+    }
+
+    /// Sets the flow setpoint as a physical value. The range of valid set points is 0.0 to
+    /// [AsyncDevice::get_current_full_scale]. The setpoint will be set to 0 if the calibration is
+    /// ever changed.
+    pub async fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError> {
+        self.run(commands::set_setpoint(self.slave_adress, setpoint)?).await
+    }
+
+    /// Returns the latest measured flow as physical value
+    pub async fn read_measured_value(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::read_measured_value(self.slave_adress)?).await
+    }
+
+    /// Returns the average of given numbers of flow measurment as a physical value. Each
+    /// measurment takes 1ms so the command response time depends on the number of measurements.
+    /// Addtionaly the number of measurments must be between 0 and 100 other wise it will return a
+    /// [StateResponseError::ParameterError](sfc_core::error::StateResponseError::ParameterError).
+    pub async fn read_average_measured_value(
+        &mut self,
+        measurment_count: u8,
+    ) -> Result<f32, DeviceError> {
+        self.run(commands::read_average_measured_value(self.slave_adress, measurment_count)?).await
+    }
+
+    /// Sets the set point and reads the measured value in one SHDLC command
+    pub async fn set_setpoint_and_read_measured_value(
+        &mut self,
+        setpoint: f32,
+    ) -> Result<f32, DeviceError> {
+        self.run(commands::set_setpoint_and_read_measured_value(self.slave_adress, setpoint)?).await
+    }
+
+    /// Returns the controller gain
+    pub async fn get_controller_gain(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_controller_gain(self.slave_adress)?).await
+    }
+
+    /// Sets the controller gain to the desired value
+    pub async fn set_controller_gain(&mut self, gain: f32) -> Result<(), DeviceError> {
+        self.run(commands::set_controller_gain(self.slave_adress, gain)?).await
+    }
+
+    /// Gets the device intital step
+    pub async fn get_initial_step(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_initial_step(self.slave_adress)?).await
+    }
+
+    /// Sets the initial step. This is stored in non-volatile memory and will be cleared
+    /// after a device reset.
+    pub async fn set_initial_step(&mut self, step: f32) -> Result<(), DeviceError> {
+        self.run(commands::set_initial_step(self.slave_adress, step)?).await
+    }
+
+    /// Returns the measured flow in raw ticks
+    pub async fn measure_raw_flow(&mut self) -> Result<u16, DeviceError> {
+        self.run(commands::measure_raw_flow(self.slave_adress)?).await
+    }
+
+    /// Preforms a thermal conductivity measurement and returns the measured raw tick value.
+    /// The valve is automatically closed during the measurement
+    pub async fn measure_raw_thermal_conductivity(&mut self) -> Result<u16, DeviceError> {
+        self.run(commands::measure_raw_thermal_conductivity(self.slave_adress)?).await
+    }
+
+    /// Measures the temperature of the flow sensor in degrees celcius
+    pub async fn measure_temperature(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::measure_temperature(self.slave_adress)?).await
+    }
+
+    /// [AsyncDevice::read_measured_value] as a volume rate in the unit of the active
+    /// calibration, which is read first. Available with the `uom` feature.
+    #[cfg(feature = "uom")]
+    pub async fn read_measured_value_uom(
+        &mut self,
+    ) -> Result<uom::si::f32::VolumeRate, DeviceError> {
+        let unit = self.get_current_gas_unit().await?;
+        unit.to_volume_rate(self.read_measured_value().await?)
+    }
+
+    /// [AsyncDevice::measure_temperature] as a temperature, available with the `uom` feature
+    #[cfg(feature = "uom")]
+    pub async fn measure_temperature_uom(
+        &mut self,
+    ) -> Result<uom::si::f32::ThermodynamicTemperature, DeviceError> {
+        use uom::si::thermodynamic_temperature::degree_celsius;
+        let celsius = self.measure_temperature().await?;
+        Ok(uom::si::f32::ThermodynamicTemperature::new::<degree_celsius>(celsius))
+    }
+
+    /// Gets the number of calibrations that the device memory is able to hold.
+    /// Not all calibrations actually contain a valid calibration. Use
+    /// [AsyncDevice::get_calibration_validity] to see which calibrations are valid and can be used
+    pub async fn get_number_of_calibrations(&mut self) -> Result<u32, DeviceError> {
+        self.run(commands::get_number_of_calibrations(self.slave_adress)?).await
+    }
+
+    /// Checks if a calibration at the specific index is valid
+    pub async fn get_calibration_validity(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<bool, DeviceError> {
+        self.run(commands::get_calibration_validity(self.slave_adress, calibration_index)?).await
+    }
+
+    /// Gets the gas ID of the specifc calibration index.
+    pub async fn get_calibration_gas_id(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<u32, DeviceError> {
+        self.run(commands::get_calibration_gas_id(self.slave_adress, calibration_index)?).await
+    }
+
+    /// Gets the gas unit of a specifc calibration index see [GasUnit] for more information.
+    pub async fn get_calibration_gas_unit(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<GasUnit, DeviceError> {
+        self.run(commands::get_calibration_gas_unit(self.slave_adress, calibration_index)?).await
+    }
+
+    /// Returns the full scale flow of a specifc calibration index.
+    pub async fn get_calibration_full_scale(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<f32, DeviceError> {
+        self.run(commands::get_calibration_full_scale(self.slave_adress, calibration_index)?).await
+    }
+
+    /// Gets the gas ID of the currently active calibration
+    pub async fn get_current_gas_id(&mut self) -> Result<u32, DeviceError> {
+        self.run(commands::get_current_gas_id(self.slave_adress)?).await
+    }
+
+    /// Gets the gas unit of the currently active calibration. See [GasUnit] for more
+    /// information
+    pub async fn get_current_gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
+        self.run(commands::get_current_gas_unit(self.slave_adress)?).await
+    }
+
+    /// Gets the full scale flow of the currently active calibration.
+    pub async fn get_current_full_scale(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_current_full_scale(self.slave_adress)?).await
+    }
+
+    /// Gets the calibration index of the currently active calibration.
+    pub async fn get_calliration_number(&mut self) -> Result<u32, DeviceError> {
+        self.run(commands::get_calliration_number(self.slave_adress)?).await
+    }
+
+    /// Changes the calibration to the new calibration at the specified index. This command
+    /// stops the controller by closing the valve. Additonly this is stored in presitent memory and
+    /// will remain after a device reset. Only retried with [RetryConfig::retry_non_idempotent].
+    pub async fn set_callibration(&mut self, calibration_index: u32) -> Result<(), DeviceError> {
+        self.run(commands::set_callibration(self.slave_adress, calibration_index)?).await
+    }
+
+    /// Changes the calibration to the new calibration at the specified index. This command stops
+    /// the controller by closing the valve. This will be stored in volatile memory and will not
+    /// presit after a device reset. Only retried with [RetryConfig::retry_non_idempotent].
+    pub async fn set_callibration_volitile(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<(), DeviceError> {
+        self.run(commands::set_callibration_volitile(self.slave_adress, calibration_index)?).await
+    }
+
+    /// Returns the slave adress of the SHDLC device
+    pub async fn get_slave_adress(&mut self) -> Result<u8, DeviceError> {
+        self.run(commands::get_slave_adress(self.slave_adress)?).await
+    }
+
+    /// Sets slave adress of the SHDLC device. The slave adress is stored in non-volatile memory
+    /// and therefore will presist after a device reset. Next time the device is connected be sure
+    /// to use the new address. Aditionally make sure there is only one device with this address on
+    /// the bus. Otherwise there will be communication errors that can only be fixed by
+    /// disconnecting one of the devices. This command is only retried with
+    /// [RetryConfig::retry_non_idempotent]. Cancelled, the device may have taken the new
+    /// address while this keeps using the old one.
+    pub async fn set_slave_adress(&mut self, new_adress: u8) -> Result<(), DeviceError> {
+        self.run(commands::set_slave_adress(self.slave_adress, new_adress)?).await?;
+        self.slave_adress = new_adress;
+        Ok(())
+    }
+
+    /// Gets the baudrate of the SHDLC device, [Baudrate::Other] if it isn't a documented one.
+    pub async fn get_baudrate(&mut self) -> Result<Baudrate, DeviceError> {
+        self.run(commands::get_baudrate(self.slave_adress)?).await
+    }
+
+    /// Sets the buadrate of the device. The buadrate is stored in non-volatile memory
+    /// and will presist after a device reset. The device refuses a [Baudrate::Other]. This
+    /// command is only retried with [RetryConfig::retry_non_idempotent]. The speed of the
+    /// stream itself is not changed, for a `tokio_serial::SerialStream` change it through
+    /// [AsyncDevice::get_mut] once this returns. Cancelled, the device may already be on the
+    /// new baudrate.
+    pub async fn set_baudrate(&mut self, baudrate: Baudrate) -> Result<(), DeviceError> {
+        self.run(commands::set_baudrate(self.slave_adress, baudrate)?).await
+    }
+
+    /// [AsyncDevice::set_baudrate] with a bare number, failing with
+    /// [DeviceError::UnsupportedBaudrate] without sending anything for a rate the device
+    /// doesn't support
+    #[deprecated(note = "use set_baudrate with a Baudrate")]
+    pub async fn set_baudrate_u32(&mut self, baudrate: u32) -> Result<(), DeviceError> {
+        self.set_baudrate(Baudrate::try_from(baudrate)?).await
+    }
+
+    /// [AsyncDevice::get_baudrate] as a bare number
+    #[deprecated(note = "use get_baudrate, which returns a Baudrate")]
+    pub async fn get_baudrate_u32(&mut self) -> Result<u32, DeviceError> {
+        self.get_baudrate().await.map(u32::from)
+    }
+
+    /// Gets the product type from the device
+    pub async fn get_product_type(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_product_type(self.slave_adress)?).await
+    }
+
+    /// Gets the product name from the device
+    pub async fn get_product_name(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_product_name(self.slave_adress)?).await
+    }
+
+    /// Gets the article code of the device. This information is also contained on the
+    /// product label.
+    pub async fn get_article_code(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_article_code(self.slave_adress)?).await
+    }
+
+    /// Gets the serial number of the SFC6xxx sensor as a hex String matching the 
+    /// serial number printed on the device.
+    pub async fn get_serial_number(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_serial_number(self.slave_adress)?).await
+    }
+
+    /// Gets the version information for the hardware, firmware, and SHDLC protocol.
+    pub async fn get_version(&mut self) -> Result<Version, DeviceError> {
+        self.run(commands::get_version(self.slave_adress)?).await
+    }
+
+    /// Resets the device which has the same effect as a power cycle. Please allow 300ms for the
+    /// device to power on. This command is only retried with
+    /// [RetryConfig::retry_non_idempotent]. Cancelled, the device may be resetting.
+    pub async fn reset_device(&mut self) -> Result<(), DeviceError> {
+        self.run(commands::reset_device(self.slave_adress)?).await
+    }
+
+    async fn run<R>(&mut self, command: Request<R>) -> Result<R, DeviceError> {
+        let (frame, read) = command.split();
+        let response = self.connection.transact(frame).await?;
+        read(&response.into_data())
+    }
+}
+
+/// How [AsyncDevice::measurement_stream] reads the device
+#[cfg(feature = "stream")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// The time from the start of one read to the start of the next
+    pub interval: Duration,
+    /// The stream ends after yielding this many errors in a row, 0 never ends it
+    pub max_consecutive_errors: u32,
+}
+
+#[cfg(feature = "stream")]
+impl Default for StreamConfig {
+    /// A read every 100ms, ending after three failed reads in a row
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            max_consecutive_errors: 3,
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T: AsyncTransport, D: Delay> AsyncDevice<T, D> {
+    /// Reads a [Measurement] every [StreamConfig::interval], available with the `stream`
+    /// feature. The gas unit, serial number and setpoint are read once before the first value,
+    /// nothing else can change them while the stream borrows the device. A failed read is
+    /// yielded and the next one tried at the next interval, the stream ends after
+    /// [StreamConfig::max_consecutive_errors] of them in a row.
+    ///
+    /// The device is only read while the stream is polled, a consumer that lags behind gets
+    /// the next value when it asks for it and the schedule moves instead of catching up with a
+    /// burst. The times are measured with the [Delay], without a clock the interval is the
+    /// pause between two reads. A `next()` that loses a `select!` keeps the running read in
+    /// the stream, dropping the stream itself cancels it like any other command.
+    /// ```no_run
+    /// # #[cfg(feature = "tokio")]
+    /// # async fn run(
+    /// #     device: &mut sfc6xxx_rs::async_device::AsyncDevice<
+    /// #         sfc6xxx_rs::sfc_core::async_transport::FromTokio<tokio::io::DuplexStream>,
+    /// #         sfc6xxx_rs::sfc_core::async_transport::TokioDelay,
+    /// #     >,
+    /// # ) {
+    /// use futures_util::StreamExt;
+    /// use sfc6xxx_rs::async_device::StreamConfig;
+    ///
+    /// let mut measurements = std::pin::pin!(device.measurement_stream(StreamConfig::default()));
+    /// while let Some(measurement) = measurements.next().await {
+    ///     match measurement {
+    ///         Ok(measurement) => println!("{}", measurement.to_csv_row()),
+    ///         Err(e) => eprintln!("reading failed: {}", e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn measurement_stream(
+        &mut self,
+        config: StreamConfig,
+    ) -> impl Stream<Item = Result<Measurement, DeviceError>> + '_ {
+        let poller = Poller {
+            device: self,
+            config,
+            context: None,
+            due: None,
+            started: false,
+            errors: 0,
+        };
+        futures_util::stream::unfold(poller, |mut poller| async move {
+            let limit = poller.config.max_consecutive_errors;
+            if limit > 0 && poller.errors >= limit {
+                return None;
+            }
+            poller.pace().await;
+            let measurement = poller.measure().await;
+            poller.errors = if measurement.is_ok() { 0 } else { poller.errors + 1 };
+            Some((measurement, poller))
+        })
+    }
+}
+
+/// The state of a [AsyncDevice::measurement_stream] between two reads
+#[cfg(feature = "stream")]
+struct Poller<'a, T: AsyncTransport, D: Delay> {
+    device: &'a mut AsyncDevice<T, D>,
+    config: StreamConfig,
+    context: Option<(GasUnit, String, f32, f32)>,
+    /// When the next read is due on the clock of the delay
+    due: Option<Duration>,
+    started: bool,
+    errors: u32,
+}
+
+#[cfg(feature = "stream")]
+impl<T: AsyncTransport, D: Delay> Poller<'_, T, D> {
+    /// Waits until the next read is due
+    async fn pace(&mut self) {
+        let delay = self.device.connection.delay();
+        let Some(now) = delay.now() else {
+            if self.started {
+                delay.delay(self.config.interval).await;
+            }
+            self.started = true;
+            return;
+        };
+        let due = *self.due.get_or_insert(now);
+        if due > now {
+            delay.delay(due - now).await;
+        }
+        // a late read moves the schedule instead of catching up with a burst
+        self.due = Some(due.max(now) + self.config.interval);
+    }
+
+    async fn measure(&mut self) -> Result<Measurement, DeviceError> {
+        let (unit, serial_number, setpoint, full_scale) = match &self.context {
+            Some(context) => context.clone(),
+            None => {
+                let context = (
+                    self.device.get_current_gas_unit().await?,
+                    self.device.get_serial_number().await?.to_string(),
+                    self.device.get_setpoint().await?,
+                    self.device.get_current_full_scale().await?,
+                );
+                self.context.insert(context).clone()
+            }
+        };
+        let value = self.device.read_measured_value().await?;
+        Ok(Measurement {
+            serial_number: Some(serial_number),
+            setpoint: Some(setpoint),
+            full_scale: Some(full_scale),
+            ..Measurement::new(value, unit, ValueScale::Physical)
+        })
+    }
+}
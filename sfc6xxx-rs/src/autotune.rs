@@ -0,0 +1,385 @@
+//! Tuning the controller gain from step responses. [Device::autotune_gain] steps the setpoint
+//! with different gains, measures how fast the flow rises and how far it overshoots, and
+//! searches for the highest gain that stays within an overshoot limit:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use sfc6xxx_rs::autotune::AutotuneConfig;
+//!
+//! let report = device.autotune_gain(AutotuneConfig {
+//!     apply: true,
+//!     ..AutotuneConfig::new(0.5, 2.5)
+//! })?;
+//! println!("gain {} after {} steps", report.recommended_gain, report.trials.len());
+//! # Ok(())
+//! # }
+//! ```
+//! The gain and setpoint from before the tuning are set again when it ends, also when it fails.
+//! Only with [AutotuneConfig::apply] the recommended gain is kept.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc_core::error::DeviceError;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+/// How [Device::autotune_gain] steps the setpoint and searches for a gain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutotuneConfig {
+    /// The setpoint the flow settles at before each step
+    pub base_setpoint: f32,
+    /// The setpoint stepped to
+    pub step_setpoint: f32,
+    /// Neither setpoint may be larger than this, the full step by default
+    pub max_setpoint: f32,
+    /// The lowest and highest gain tried, 0.1 to 10 by default
+    pub min_gain: f32,
+    pub max_gain: f32,
+    /// How many steps are made, each halving the range of gains left, 8 by default
+    pub iterations: u32,
+    /// The largest overshoot accepted as a fraction of the step, 5% by default
+    pub max_overshoot: f32,
+    /// How long the flow settles at the base setpoint before a step, one second by default
+    pub settle_time: Duration,
+    /// How long the response to a step is recorded, one second by default
+    pub record_time: Duration,
+    /// The time between two samples of the response, 10ms by default
+    pub sample_interval: Duration,
+    /// How many measurements the device averages for a sample, 5 by default
+    pub average_count: u8,
+    /// No further step is started if it would end after this, one minute by default
+    pub max_duration: Duration,
+    /// Keep the recommended gain instead of restoring the one from before, off by default
+    pub apply: bool,
+}
+
+impl AutotuneConfig {
+    pub fn new(base_setpoint: f32, step_setpoint: f32) -> Self {
+        Self {
+            base_setpoint,
+            step_setpoint,
+            max_setpoint: base_setpoint.max(step_setpoint),
+            min_gain: 0.1,
+            max_gain: 10.0,
+            iterations: 8,
+            max_overshoot: 0.05,
+            settle_time: Duration::from_secs(1),
+            record_time: Duration::from_secs(1),
+            sample_interval: Duration::from_millis(10),
+            average_count: 5,
+            max_duration: Duration::from_secs(60),
+            apply: false,
+        }
+    }
+}
+
+/// The response to a single setpoint step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutotuneTrial {
+    pub gain: f32,
+    /// The time the flow took from 10% to 90% of the step, [None] if it never got there
+    pub rise_time: Option<Duration>,
+    /// How far the flow went past the step setpoint as a fraction of the step
+    pub overshoot: f32,
+}
+
+impl AutotuneTrial {
+    /// Evaluates samples of the flow, given as the time since the step and the value
+    fn evaluate(gain: f32, base: f32, step: f32, samples: &[(Duration, f32)]) -> Self {
+        let size = step - base;
+        // positive while the flow moves in the direction of the step
+        let progress = |value: f32| (value - base) / size;
+        let reached = |fraction: f32| {
+            samples
+                .iter()
+                .find(|&&(_, value)| progress(value) >= fraction)
+                .map(|&(time, _)| time)
+        };
+        let rise_time = match (reached(0.1), reached(0.9)) {
+            (Some(start), Some(end)) => Some(end - start),
+            _ => None,
+        };
+        let peak = samples
+            .iter()
+            .map(|&(_, value)| progress(value))
+            .fold(0.0, f32::max);
+        Self {
+            gain,
+            rise_time,
+            overshoot: (peak - 1.0).max(0.0),
+        }
+    }
+}
+
+/// The outcome of [Device::autotune_gain]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutotuneReport {
+    /// The highest gain tried that stayed within [AutotuneConfig::max_overshoot], or
+    /// [AutotuneConfig::min_gain] if none did
+    pub recommended_gain: f32,
+    /// The gain the device had before the tuning
+    pub original_gain: f32,
+    /// Every step in the order they were made
+    pub trials: Vec<AutotuneTrial>,
+    /// True if the search stopped before all iterations because of
+    /// [AutotuneConfig::max_duration]
+    pub stopped_early: bool,
+    /// True if the recommended gain was kept on the device
+    pub applied: bool,
+}
+
+/// Sets the gain and setpoint the device had before the tuning again when dropped
+struct RestoreController<'a, T: Transport> {
+    device: &'a mut Device<T>,
+    gain: f32,
+    setpoint: f32,
+    restored: bool,
+}
+
+impl<T: Transport> RestoreController<'_, T> {
+    /// Restores the setpoint and the given gain and reports if that failed
+    fn restore(mut self, gain: f32) -> Result<(), DeviceError> {
+        self.restored = true;
+        self.device.set_controller_gain(gain)?;
+        self.device.set_setpoint(self.setpoint)
+    }
+}
+
+impl<T: Transport> Drop for RestoreController<'_, T> {
+    fn drop(&mut self) {
+        if !self.restored {
+            // the tuning already failed, that error is the one returned
+            let _ = self.device.set_controller_gain(self.gain);
+            let _ = self.device.set_setpoint(self.setpoint);
+        }
+    }
+}
+
+impl<T: Transport> Device<T> {
+    /// Searches for the controller gain with the fastest response that overshoots by at most
+    /// [AutotuneConfig::max_overshoot]. Each iteration sets a gain, settles the flow at the base
+    /// setpoint, steps to the step setpoint and records the response with
+    /// [Device::read_average_measured_value]. The range of gains is halved on a logarithmic
+    /// scale after every step, towards higher gains if the step stayed within the limit.
+    ///
+    /// The gain and setpoint from before are restored afterwards, unless
+    /// [AutotuneConfig::apply] is set and the recommended gain is kept instead. If a command
+    /// fails the tuning stops with that error after trying to restore them.
+    ///
+    /// # Panics
+    /// If a setpoint is larger than [AutotuneConfig::max_setpoint], the setpoints are equal, the
+    /// gains are not positive or the sample interval is zero
+    pub fn autotune_gain(&mut self, config: AutotuneConfig) -> Result<AutotuneReport, DeviceError> {
+        assert!(
+            config.base_setpoint <= config.max_setpoint
+                && config.step_setpoint <= config.max_setpoint,
+            "the setpoints must not be larger than the maximum setpoint"
+        );
+        assert!(config.base_setpoint != config.step_setpoint, "the setpoints must differ");
+        assert!(
+            0.0 < config.min_gain && config.min_gain <= config.max_gain,
+            "the gains must be positive and in order"
+        );
+        assert!(!config.sample_interval.is_zero(), "the sample interval must not be zero");
+        self.writable("autotune gain")?;
+
+        let original_gain = self.get_controller_gain()?;
+        let setpoint = self.get_setpoint()?;
+        let guard = RestoreController {
+            device: self,
+            gain: original_gain,
+            setpoint,
+            restored: false,
+        };
+
+        let start = Instant::now();
+        let trial_time = config.settle_time + config.record_time;
+        let (mut low, mut high) = (config.min_gain.ln(), config.max_gain.ln());
+        let mut recommended_gain = config.min_gain;
+        let mut trials = Vec::new();
+        let mut stopped_early = false;
+        for _ in 0..config.iterations {
+            if start.elapsed() + trial_time > config.max_duration {
+                stopped_early = true;
+                break;
+            }
+            let gain = ((low + high) / 2.0).exp();
+            let trial = guard.device.step_response(gain, &config)?;
+            if trial.overshoot <= config.max_overshoot {
+                recommended_gain = gain;
+                low = gain.ln();
+            } else {
+                high = gain.ln();
+            }
+            trials.push(trial);
+        }
+
+        let kept_gain = if config.apply { recommended_gain } else { original_gain };
+        guard.restore(kept_gain)?;
+        Ok(AutotuneReport {
+            recommended_gain,
+            original_gain,
+            trials,
+            stopped_early,
+            applied: config.apply,
+        })
+    }
+
+    /// Sets the gain, settles the flow at the base setpoint and records a step
+    fn step_response(
+        &mut self,
+        gain: f32,
+        config: &AutotuneConfig,
+    ) -> Result<AutotuneTrial, DeviceError> {
+        self.set_controller_gain(gain)?;
+        self.set_setpoint(config.base_setpoint)?;
+        thread::sleep(config.settle_time);
+
+        self.set_setpoint(config.step_setpoint)?;
+        let step = Instant::now();
+        let mut samples = Vec::new();
+        let mut offset = Duration::ZERO;
+        while offset < config.record_time {
+            let scheduled = step + offset;
+            let now = Instant::now();
+            if scheduled > now {
+                thread::sleep(scheduled - now);
+            }
+            let value = self.read_average_measured_value(config.average_count)?;
+            samples.push((step.elapsed(), value));
+            offset += config.sample_interval;
+        }
+        Ok(AutotuneTrial::evaluate(gain, config.base_setpoint, config.step_setpoint, &samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorConfig, EmulatorHandle, Fault, Sfc6xxxEmulator, StepResponse};
+
+    fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::new(EmulatorConfig {
+            response: Some(StepResponse {
+                time_constant: Duration::from_millis(10),
+                critical_gain: 1.0,
+            }),
+            ..EmulatorConfig::default()
+        });
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    fn config() -> AutotuneConfig {
+        AutotuneConfig {
+            min_gain: 0.25,
+            max_gain: 16.0,
+            iterations: 6,
+            settle_time: Duration::from_millis(100),
+            record_time: Duration::from_millis(150),
+            sample_interval: Duration::from_millis(2),
+            average_count: 1,
+            ..AutotuneConfig::new(0.5, 2.5)
+        }
+    }
+
+    #[test]
+    fn evaluate_a_step() {
+        let ms = Duration::from_millis;
+        let samples = [(ms(0), 1.0), (ms(1), 1.5), (ms(2), 2.5), (ms(3), 3.2), (ms(4), 3.0)];
+        let trial = AutotuneTrial::evaluate(2.0, 1.0, 3.0, &samples);
+        assert_eq!(trial.rise_time, Some(ms(2)));
+        assert!((trial.overshoot - 0.1).abs() < 1e-6);
+
+        // a step down rises the other way
+        let samples = [(ms(0), 3.0), (ms(1), 2.0), (ms(2), 1.0)];
+        let trial = AutotuneTrial::evaluate(2.0, 3.0, 1.0, &samples);
+        assert_eq!(trial.rise_time, Some(ms(1)));
+        assert_eq!(trial.overshoot, 0.0);
+
+        let trial = AutotuneTrial::evaluate(2.0, 1.0, 3.0, &[(ms(0), 1.0), (ms(1), 1.5)]);
+        assert_eq!(trial.rise_time, None);
+    }
+
+    #[test]
+    fn finds_the_gain_for_the_overshoot() {
+        let (mut device, handle) = emulated_device();
+        device.set_controller_gain(1.5).unwrap();
+        device.set_setpoint(1.0).unwrap();
+        let report = device.autotune_gain(config()).unwrap();
+
+        // 5% overshoot needs a damping of 0.69, which the emulator has at 2.1 times its
+        // critical gain
+        let optimum = 2.1;
+        assert!(
+            (report.recommended_gain / optimum - 1.0).abs() < 0.15,
+            "recommended {}",
+            report.recommended_gain
+        );
+        assert_eq!(report.trials.len(), 6);
+        assert!(!report.stopped_early);
+        // higher gains rise faster
+        let fastest = report.trials.iter().max_by(|a, b| a.gain.total_cmp(&b.gain)).unwrap();
+        let slowest = report.trials.iter().min_by(|a, b| a.gain.total_cmp(&b.gain)).unwrap();
+        assert!(fastest.rise_time < slowest.rise_time);
+
+        assert_eq!(report.original_gain, 1.5);
+        assert!(!report.applied);
+        assert_eq!(device.get_controller_gain().unwrap(), 1.5);
+        assert_eq!(handle.setpoint(), 1.0);
+    }
+
+    #[test]
+    fn applies_the_recommended_gain() {
+        let (mut device, _) = emulated_device();
+        let report = device
+            .autotune_gain(AutotuneConfig {
+                apply: true,
+                iterations: 2,
+                ..config()
+            })
+            .unwrap();
+        assert!(report.applied);
+        assert_eq!(device.get_controller_gain().unwrap(), report.recommended_gain);
+    }
+
+    #[test]
+    fn stops_at_the_maximum_duration() {
+        let (mut device, _) = emulated_device();
+        let report = device
+            .autotune_gain(AutotuneConfig {
+                max_duration: Duration::from_millis(600),
+                ..config()
+            })
+            .unwrap();
+        assert!(report.stopped_early);
+        assert_eq!(report.trials.len(), 2);
+    }
+
+    #[test]
+    fn gain_is_restored_after_an_error() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(2.0).unwrap();
+        device.set_retry(None);
+        // the gain and setpoint are read and the first gain is set, setting the setpoint fails
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x04));
+        assert!(device.autotune_gain(config()).is_err());
+        assert_eq!(device.get_controller_gain().unwrap(), 1.0);
+        assert_eq!(handle.setpoint(), 2.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "maximum setpoint")]
+    fn setpoint_limit_is_enforced() {
+        let (mut device, _) = emulated_device();
+        let _ = device.autotune_gain(AutotuneConfig {
+            max_setpoint: 1.0,
+            ..config()
+        });
+    }
+}
@@ -0,0 +1,187 @@
+//! Averaging the flow over longer than a single command can.
+//! [Device::read_average_measured_value] averages at most 100 measurements of 1ms each,
+//! [Device::read_average_over] splits a longer window into as many of those as needed:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//!
+//! let reading = device.read_average_over(Duration::from_secs(5))?;
+//! println!("{} ± {} over {:?}", reading.mean, reading.std_dev, reading.covered);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use sfc_core::error::{DeviceError, StateResponseError};
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+/// The most measurements the device averages in one command
+pub const MAX_BATCH: u8 = 100;
+
+/// A flow averaged over several commands by [Device::read_average_over]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AveragedReading {
+    /// The mean of every measurement as a physical value
+    pub mean: f32,
+    /// The standard deviation of the batch averages around the mean, each weighted by the
+    /// measurements in its batch. The device only reports the average of a batch, so this is how
+    /// much the flow drifted from batch to batch, not the noise of the single measurements.
+    pub std_dev: f32,
+    /// How many measurements were averaged
+    pub samples: u32,
+    /// How many commands they were read with
+    pub batches: u32,
+    /// The time from sending the first command to receiving the last response
+    pub covered: Duration,
+    /// True if the device stopped measuring before the window was covered, the reading then
+    /// only covers the batches before that
+    pub interrupted: bool,
+}
+
+/// Splits a window of 1ms measurements into batches of at most [MAX_BATCH], always at least one
+/// measurement
+fn batches(duration: Duration) -> impl Iterator<Item = u8> {
+    let samples = duration.as_millis().max(1);
+    let full = samples / MAX_BATCH as u128;
+    let rest = (samples % MAX_BATCH as u128) as u8;
+    std::iter::repeat_n(MAX_BATCH, full as usize).chain((rest > 0).then_some(rest))
+}
+
+/// The mean and standard deviation of the batch averages, each weighted by its measurements
+fn weighted(averages: &[(f32, u32)], samples: u32) -> (f32, f32) {
+    let mean = averages
+        .iter()
+        .map(|&(average, count)| average * count as f32)
+        .sum::<f32>()
+        / samples as f32;
+    let variance = averages
+        .iter()
+        .map(|&(average, count)| (average - mean).powi(2) * count as f32)
+        .sum::<f32>()
+        / samples as f32;
+    (mean, variance.sqrt())
+}
+
+impl<T: Transport> Device<T> {
+    /// Averages the measured flow over the given window by reading
+    /// [Device::read_average_measured_value] with 100 measurements back to back, and fewer for
+    /// the rest of the window. The mean is weighted by the measurements in each batch.
+    ///
+    /// If the measure loop stops after the first batch
+    /// ([StateResponseError::MeasureLoopNotRunning]), the batches read so far are returned with
+    /// [AveragedReading::interrupted] set. Every other error, and that one in the first batch,
+    /// is returned as is.
+    pub fn read_average_over(&mut self, duration: Duration) -> Result<AveragedReading, DeviceError> {
+        let start = Instant::now();
+        let mut averages = Vec::new();
+        let mut interrupted = false;
+        for count in batches(duration) {
+            match self.read_average_measured_value(count) {
+                Ok(average) => averages.push((average, count as u32)),
+                Err(DeviceError::StateResponse(StateResponseError::MeasureLoopNotRunning))
+                    if !averages.is_empty() =>
+                {
+                    interrupted = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let covered = start.elapsed();
+
+        let samples: u32 = averages.iter().map(|&(_, count)| count).sum();
+        let (mean, std_dev) = weighted(&averages, samples);
+        Ok(AveragedReading {
+            mean,
+            std_dev,
+            samples,
+            batches: averages.len() as u32,
+            covered,
+            interrupted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorHandle, Fault, Sfc6xxxEmulator};
+
+    fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::default();
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    /// The measurement counts of the averaged reads the emulator received
+    fn issued_batches(handle: &EmulatorHandle) -> Vec<u8> {
+        handle
+            .requests()
+            .into_iter()
+            .filter_map(|(_, command, data)| match (command, data.as_slice()) {
+                (0x08, [0x11, count]) => Some(*count),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batches_cover_the_window() {
+        let ms = Duration::from_millis;
+        for (duration, expected) in [
+            (ms(40), vec![40]),
+            (ms(100), vec![100]),
+            (ms(250), vec![100, 100, 50]),
+            (Duration::from_secs(5), vec![100; 50]),
+            (Duration::ZERO, vec![1]),
+        ] {
+            let (mut device, handle) = emulated_device();
+            device.set_setpoint(2.0).unwrap();
+            let reading = device.read_average_over(duration).unwrap();
+            assert_eq!(issued_batches(&handle), expected, "{:?}", duration);
+            assert_eq!(reading.batches as usize, expected.len());
+            assert_eq!(reading.samples, expected.iter().map(|&n| n as u32).sum::<u32>());
+            assert_eq!(reading.mean, 2.0);
+            assert_eq!(reading.std_dev, 0.0);
+            assert!(!reading.interrupted);
+        }
+    }
+
+    #[test]
+    fn batches_are_weighted_by_their_measurements() {
+        // 100 measurements of 1.0 and 50 of 4.0
+        let (mean, std_dev) = weighted(&[(1.0, 100), (4.0, 50)], 150);
+        assert_eq!(mean, 2.0);
+        assert!((std_dev - 2.0_f32.sqrt()).abs() < 1e-6, "{}", std_dev);
+    }
+
+    #[test]
+    fn stopped_measure_loop_returns_a_partial_reading() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(1.0).unwrap();
+        device.capabilities().unwrap();
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        let reading = device.read_average_over(Duration::from_millis(300)).unwrap();
+        assert!(reading.interrupted);
+        assert_eq!(reading.batches, 1);
+        assert_eq!(reading.samples, 100);
+        assert_eq!(reading.mean, 1.0);
+        // nothing is read after the interruption
+        assert_eq!(issued_batches(&handle), vec![100, 100]);
+    }
+
+    #[test]
+    fn stopped_measure_loop_in_the_first_batch_is_an_error() {
+        let (mut device, handle) = emulated_device();
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        match device.read_average_over(Duration::from_millis(300)) {
+            Err(DeviceError::StateResponse(StateResponseError::MeasureLoopNotRunning)) => {}
+            other => panic!("expected MeasureLoopNotRunning, got {:?}", other),
+        }
+    }
+}
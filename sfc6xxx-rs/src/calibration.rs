@@ -0,0 +1,215 @@
+//! Switching to the calibration of a gas by its ID instead of its index in the calibration
+//! table:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use sfc6xxx_rs::calibration::SwitchMode;
+//!
+//! // CO2, only if the thermal conductivity says CO2 is plumbed
+//! let mode = SwitchMode::volatile().verified(1520, 40);
+//! let selected = device.select_calibration_for_gas(8, mode)?;
+//! println!("slot {} up to {} {}", selected.index, selected.full_scale, selected.unit);
+//! # Ok(())
+//! # }
+//! ```
+//! Like every calibration change, switching closes the valve.
+
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::GasUnit;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+/// How [Device::select_calibration_for_gas] switches the calibration
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SwitchMode {
+    /// Store the calibration in non-volatile memory so it is kept after a reset, see
+    /// [Device::set_callibration]
+    pub persistent: bool,
+    /// Only switch if the gas in the device matches, see [GasCheck]
+    pub verify: Option<GasCheck>,
+}
+
+impl SwitchMode {
+    /// Switches until the next reset, see [Device::set_callibration_volitile]
+    pub fn volatile() -> Self {
+        Self {
+            persistent: false,
+            verify: None,
+        }
+    }
+
+    /// Switches and keeps the calibration after a reset, see [Device::set_callibration]
+    pub fn persistent() -> Self {
+        Self {
+            persistent: true,
+            verify: None,
+        }
+    }
+
+    /// Measures the raw thermal conductivity first and only switches if it is within
+    /// `tolerance` ticks of `expected`
+    pub fn verified(self, expected: u16, tolerance: u16) -> Self {
+        Self {
+            verify: Some(GasCheck {
+                expected,
+                tolerance,
+            }),
+            ..self
+        }
+    }
+}
+
+/// The raw thermal conductivity the plumbed gas should have. The device has no reference values
+/// for its calibrations, they have to be measured once with the gas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasCheck {
+    /// In ticks of [Device::measure_raw_thermal_conductivity]
+    pub expected: u16,
+    pub tolerance: u16,
+}
+
+/// The calibration [Device::select_calibration_for_gas] switched to
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectedCalibration {
+    pub index: u32,
+    pub gas_id: u32,
+    pub unit: GasUnit,
+    pub full_scale: f32,
+    /// The raw thermal conductivity, if it was verified
+    pub thermal_conductivity: Option<u16>,
+    /// Other valid slots for the same gas that were not chosen
+    pub alternatives: Vec<u32>,
+}
+
+impl<T: Transport> Device<T> {
+    /// Switches to the valid calibration for the gas with the given ID. If several slots hold
+    /// the gas the lowest index is chosen, the others are listed in
+    /// [SelectedCalibration::alternatives]. Fails with [DeviceError::NoCalibrationForGas] if no
+    /// valid slot does and, when verifying, with [DeviceError::GasMismatch] if the thermal
+    /// conductivity doesn't match. Nothing is switched in both cases.
+    ///
+    /// The calibration table is scanned on every call. Switching closes the valve, the setpoint
+    /// is zero afterwards.
+    pub fn select_calibration_for_gas(
+        &mut self,
+        gas_id: u32,
+        mode: SwitchMode,
+    ) -> Result<SelectedCalibration, DeviceError> {
+        self.writable("select calibration for gas")?;
+        let mut candidates = Vec::new();
+        for index in 0..self.get_number_of_calibrations()? {
+            if self.get_calibration_validity(index)?
+                && self.get_calibration_gas_id(index)? == gas_id
+            {
+                candidates.push(index);
+            }
+        }
+        let Some((&index, alternatives)) = candidates.split_first() else {
+            return Err(DeviceError::NoCalibrationForGas(gas_id));
+        };
+
+        let thermal_conductivity = match mode.verify {
+            Some(check) => {
+                let measured = self.measure_raw_thermal_conductivity()?;
+                if measured.abs_diff(check.expected) > check.tolerance {
+                    return Err(DeviceError::GasMismatch(measured, check.expected));
+                }
+                Some(measured)
+            }
+            None => None,
+        };
+
+        let unit = self.get_calibration_gas_unit(index)?;
+        let full_scale = self.get_calibration_full_scale(index)?;
+        if mode.persistent {
+            self.set_callibration(index)?;
+        } else {
+            self.set_callibration_volitile(index)?;
+        }
+        Ok(SelectedCalibration {
+            index,
+            gas_id,
+            unit,
+            full_scale,
+            thermal_conductivity,
+            alternatives: alternatives.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{CalibrationSlot, EmulatorConfig, EmulatorHandle, Sfc6xxxEmulator};
+
+    /// Gas 1 in slots 0 and 4, gas 2 in slot 1 and gas 3 in slot 2, slot 3 is empty
+    fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let mut config = EmulatorConfig::default();
+        let mut duplicate: CalibrationSlot = config.calibrations[0].clone().unwrap();
+        duplicate.full_scale = 10.0;
+        config.calibrations.push(Some(duplicate));
+        let emulator = Sfc6xxxEmulator::new(config);
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    #[test]
+    fn selects_the_slot_of_the_gas() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(1.0).unwrap();
+        let selected = device.select_calibration_for_gas(3, SwitchMode::volatile()).unwrap();
+        assert_eq!(selected.index, 2);
+        assert_eq!(selected.gas_id, 3);
+        assert_eq!(selected.full_scale, 2.5);
+        assert_eq!(selected.thermal_conductivity, None);
+        assert!(selected.alternatives.is_empty());
+        assert_eq!(handle.active_calibration(), 2);
+        assert_eq!(handle.setpoint(), 0.0);
+
+        // volatile, a reset goes back to the stored calibration
+        device.reset_device().unwrap();
+        assert_eq!(handle.active_calibration(), 0);
+
+        device.select_calibration_for_gas(2, SwitchMode::persistent()).unwrap();
+        device.reset_device().unwrap();
+        assert_eq!(handle.active_calibration(), 1);
+    }
+
+    #[test]
+    fn absent_gas_is_an_error() {
+        let (mut device, handle) = emulated_device();
+        match device.select_calibration_for_gas(7, SwitchMode::volatile()) {
+            Err(DeviceError::NoCalibrationForGas(7)) => {}
+            other => panic!("expected NoCalibrationForGas(7), got {:?}", other),
+        }
+        assert_eq!(handle.active_calibration(), 0);
+    }
+
+    #[test]
+    fn ambiguous_gas_takes_the_lowest_slot() {
+        let (mut device, _) = emulated_device();
+        device.set_callibration_volitile(2).unwrap();
+        let selected = device.select_calibration_for_gas(1, SwitchMode::volatile()).unwrap();
+        assert_eq!(selected.index, 0);
+        assert_eq!(selected.full_scale, 5.0);
+        assert_eq!(selected.alternatives, vec![4]);
+    }
+
+    #[test]
+    fn verifies_the_gas_before_switching() {
+        let (mut device, handle) = emulated_device();
+        // the emulator measures 1200 ticks
+        let selected = device
+            .select_calibration_for_gas(2, SwitchMode::volatile().verified(1210, 20))
+            .unwrap();
+        assert_eq!(selected.thermal_conductivity, Some(1200));
+        assert_eq!(handle.active_calibration(), 1);
+
+        match device.select_calibration_for_gas(3, SwitchMode::volatile().verified(1500, 20)) {
+            Err(DeviceError::GasMismatch(1200, 1500)) => {}
+            other => panic!("expected GasMismatch(1200, 1500), got {:?}", other),
+        }
+        assert_eq!(handle.active_calibration(), 1);
+    }
+}
@@ -0,0 +1,798 @@
+//! Every command of the SFC6xxx as a [Command], for tooling that needs to know what the device
+//! understands without a [Device](crate::device::Device) at hand:
+//! ```
+//! use sfc6xxx_rs::commands::Command;
+//!
+//! let command = Command::SetSetpoint { value: 2.5 };
+//! assert_eq!(command.name(), "setpoint");
+//! let frame = command.encode(0).unwrap();
+//! assert_eq!(frame.get_command_number(), 0x00);
+//! assert_eq!(frame.get_data_length(), 5);
+//! ```
+//! The devices build their frames from the same [Command]s and read the responses with the
+//! functions here, so the blocking, async and embedded devices send the same bytes and decode
+//! them the same way.
+
+use sfc_core::baudrate::Baudrate;
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::{GasUnit, Prefixes, TimeBases, Units};
+use sfc_core::names::{DeviceFamily, command_name};
+use sfc_core::shdlc::{
+    CommandKind, DeviceString, Idempotency, MOSIFrame, ResponseLength, SmallFrame,
+    TranslationError, Version, nul_terminated, parse_string,
+};
+
+/// A command of the SFC6xxx with its arguments. Calibrations are picked by their index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    GetSetpoint,
+    /// The setpoint as a physical value in the unit of the active calibration
+    SetSetpoint {
+        value: f32,
+    },
+    ReadMeasuredValue,
+    /// The average of `count` measurements taken by the device
+    ReadAverageMeasuredValue {
+        count: u8,
+    },
+    SetSetpointAndReadMeasuredValue {
+        value: f32,
+    },
+    GetControllerGain,
+    SetControllerGain {
+        gain: f32,
+    },
+    GetInitialStep,
+    SetInitialStep {
+        step: f32,
+    },
+    MeasureRawFlow,
+    MeasureRawThermalConductivity,
+    MeasureTemperature,
+    GetNumberOfCalibrations,
+    GetCalibrationValidity {
+        index: u32,
+    },
+    GetCalibrationGasId {
+        index: u32,
+    },
+    GetCalibrationGasUnit {
+        index: u32,
+    },
+    GetCalibrationFullScale {
+        index: u32,
+    },
+    GetCurrentGasId,
+    GetCurrentGasUnit,
+    GetCurrentFullScale,
+    /// The index of the active calibration
+    GetCalibration,
+    /// Switches the calibration and keeps it after a reset
+    SetCalibration {
+        index: u32,
+    },
+    /// Switches the calibration until the next reset
+    SetCalibrationVolatile {
+        index: u32,
+    },
+    GetSlaveAddress,
+    SetSlaveAddress {
+        address: u8,
+    },
+    GetBaudrate,
+    SetBaudrate {
+        baudrate: u32,
+    },
+    GetProductType,
+    GetProductName,
+    GetArticleCode,
+    GetSerialNumber,
+    GetVersion,
+    DeviceReset,
+}
+
+impl Command {
+    /// The command byte of the frame
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::GetSetpoint | Self::SetSetpoint { .. } => 0x00,
+            Self::SetSetpointAndReadMeasuredValue { .. } => 0x03,
+            Self::ReadMeasuredValue | Self::ReadAverageMeasuredValue { .. } => 0x08,
+            Self::GetControllerGain
+            | Self::SetControllerGain { .. }
+            | Self::GetInitialStep
+            | Self::SetInitialStep { .. } => 0x22,
+            Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity
+            | Self::MeasureTemperature => 0x30,
+            Self::GetNumberOfCalibrations
+            | Self::GetCalibrationValidity { .. }
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationGasUnit { .. }
+            | Self::GetCalibrationFullScale { .. } => 0x40,
+            Self::GetCurrentGasId | Self::GetCurrentGasUnit | Self::GetCurrentFullScale => 0x44,
+            Self::GetCalibration | Self::SetCalibration { .. } => 0x45,
+            Self::SetCalibrationVolatile { .. } => 0x46,
+            Self::GetSlaveAddress | Self::SetSlaveAddress { .. } => 0x90,
+            Self::GetBaudrate | Self::SetBaudrate { .. } => 0x91,
+            Self::GetProductType
+            | Self::GetProductName
+            | Self::GetArticleCode
+            | Self::GetSerialNumber => 0xD0,
+            Self::GetVersion => 0xD1,
+            Self::DeviceReset => 0xD3,
+        }
+    }
+
+    /// What the command makes the device do, which decides its response timeout
+    pub fn kind(&self) -> CommandKind {
+        match *self {
+            Self::ReadMeasuredValue
+            | Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity
+            | Self::MeasureTemperature => CommandKind::Measurement,
+            Self::ReadAverageMeasuredValue { count } => CommandKind::AveragedMeasurement(count),
+            Self::SetCalibration { .. }
+            | Self::SetCalibrationVolatile { .. }
+            | Self::DeviceReset => CommandKind::Reset,
+            _ => CommandKind::Query,
+        }
+    }
+
+    /// Whether the command may be sent again when its response got lost. Writing a value is
+    /// idempotent, a new address or baudrate, switching the calibration and the reset are not.
+    pub fn idempotency(&self) -> Idempotency {
+        // every command is listed so a new one has to be classified
+        match self {
+            Self::GetSetpoint
+            | Self::SetSetpoint { .. }
+            | Self::ReadMeasuredValue
+            | Self::ReadAverageMeasuredValue { .. }
+            | Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::GetControllerGain
+            | Self::SetControllerGain { .. }
+            | Self::GetInitialStep
+            | Self::SetInitialStep { .. }
+            | Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity
+            | Self::MeasureTemperature
+            | Self::GetNumberOfCalibrations
+            | Self::GetCalibrationValidity { .. }
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationGasUnit { .. }
+            | Self::GetCalibrationFullScale { .. }
+            | Self::GetCurrentGasId
+            | Self::GetCurrentGasUnit
+            | Self::GetCurrentFullScale
+            | Self::GetCalibration
+            | Self::GetSlaveAddress
+            | Self::GetBaudrate
+            | Self::GetProductType
+            | Self::GetProductName
+            | Self::GetArticleCode
+            | Self::GetSerialNumber
+            | Self::GetVersion => Idempotency::Idempotent,
+            Self::SetCalibration { .. }
+            | Self::SetCalibrationVolatile { .. }
+            | Self::SetSlaveAddress { .. }
+            | Self::SetBaudrate { .. }
+            | Self::DeviceReset => Idempotency::NonIdempotent,
+        }
+    }
+
+    /// Whether the command changes the device: the setpoint, a setting, the calibration, the
+    /// address or baudrate, or its state with a reset. A read-only device refuses these.
+    pub fn writes(&self) -> bool {
+        // every command is listed so a new one has to be classified
+        match self {
+            Self::SetSetpoint { .. }
+            | Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::SetControllerGain { .. }
+            | Self::SetInitialStep { .. }
+            | Self::SetCalibration { .. }
+            | Self::SetCalibrationVolatile { .. }
+            | Self::SetSlaveAddress { .. }
+            | Self::SetBaudrate { .. }
+            | Self::DeviceReset => true,
+            Self::GetSetpoint
+            | Self::ReadMeasuredValue
+            | Self::ReadAverageMeasuredValue { .. }
+            | Self::GetControllerGain
+            | Self::GetInitialStep
+            | Self::MeasureRawFlow
+            | Self::MeasureRawThermalConductivity
+            | Self::MeasureTemperature
+            | Self::GetNumberOfCalibrations
+            | Self::GetCalibrationValidity { .. }
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationGasUnit { .. }
+            | Self::GetCalibrationFullScale { .. }
+            | Self::GetCurrentGasId
+            | Self::GetCurrentGasUnit
+            | Self::GetCurrentFullScale
+            | Self::GetCalibration
+            | Self::GetSlaveAddress
+            | Self::GetBaudrate
+            | Self::GetProductType
+            | Self::GetProductName
+            | Self::GetArticleCode
+            | Self::GetSerialNumber
+            | Self::GetVersion => false,
+        }
+    }
+
+    /// How much data the device answers with, checked before the response is decoded
+    pub fn response_length(&self) -> ResponseLength {
+        use ResponseLength::Exact;
+        // every command is listed so a new one has to declare its response
+        match self {
+            Self::SetSetpoint { .. }
+            | Self::SetControllerGain { .. }
+            | Self::SetInitialStep { .. }
+            | Self::SetCalibration { .. }
+            | Self::SetCalibrationVolatile { .. }
+            | Self::SetSlaveAddress { .. }
+            | Self::SetBaudrate { .. }
+            | Self::DeviceReset => Exact(0),
+            Self::GetCalibrationValidity { .. } | Self::GetSlaveAddress => Exact(1),
+            Self::MeasureRawFlow | Self::MeasureRawThermalConductivity => Exact(2),
+            Self::GetCalibrationGasUnit { .. } | Self::GetCurrentGasUnit => Exact(3),
+            Self::GetSetpoint
+            | Self::ReadMeasuredValue
+            | Self::ReadAverageMeasuredValue { .. }
+            | Self::SetSetpointAndReadMeasuredValue { .. }
+            | Self::GetControllerGain
+            | Self::GetInitialStep
+            | Self::MeasureTemperature
+            | Self::GetNumberOfCalibrations
+            | Self::GetCalibrationGasId { .. }
+            | Self::GetCalibrationFullScale { .. }
+            | Self::GetCurrentGasId
+            | Self::GetCurrentFullScale
+            | Self::GetCalibration
+            | Self::GetBaudrate => Exact(4),
+            Self::GetVersion => Exact(7),
+            Self::GetProductType
+            | Self::GetProductName
+            | Self::GetArticleCode
+            | Self::GetSerialNumber => ResponseLength::Variable {
+                min: 1,
+                validate: nul_terminated,
+            },
+        }
+    }
+
+    /// A short name for logs and transcripts, like `setpoint`, the [command_name] of the command
+    /// and the subcommand it is sent with
+    pub fn name(&self) -> &'static str {
+        let (data, length) = self.data();
+        let subcommand = data.get(..length).and_then(<[u8]>::first).copied();
+        command_name(DeviceFamily::Sfc6xxx, self.code(), subcommand).unwrap_or("unknown command")
+    }
+
+    /// The frame that sends the command to the device at `address`
+    pub fn encode(&self, address: u8) -> Result<MOSIFrame, TranslationError> {
+        self.encode_sized(address)
+    }
+
+    /// [Command::encode] into a [SmallFrame], which holds any command of the SFC6xxx. The
+    /// devices send these.
+    pub fn encode_small(&self, address: u8) -> Result<SmallFrame, TranslationError> {
+        self.encode_sized(address)
+    }
+
+    fn encode_sized<const N: usize>(&self, address: u8) -> Result<MOSIFrame<N>, TranslationError> {
+        let (data, length) = self.data();
+        let data = data.get(..length).ok_or(TranslationError::DataTooLarge)?;
+        let frame = MOSIFrame::sized(address, self.code(), data)?;
+        Ok(frame
+            .with_kind(self.kind())
+            .with_idempotency(self.idempotency())
+            .with_family(DeviceFamily::Sfc6xxx))
+    }
+
+    /// The data of the request and how many bytes of it are used
+    fn data(&self) -> ([u8; 5], usize) {
+        let mut data = [0; 5];
+        let length = match *self {
+            Self::GetSetpoint | Self::ReadMeasuredValue => put(&mut data, &[0x01]),
+            Self::SetSetpoint { value } | Self::SetSetpointAndReadMeasuredValue { value } => {
+                put(&mut data, &with_value(0x01, value.to_be_bytes()))
+            }
+            Self::ReadAverageMeasuredValue { count } => put(&mut data, &[0x11, count]),
+            Self::GetControllerGain => put(&mut data, &[0x00]),
+            Self::SetControllerGain { gain } => {
+                put(&mut data, &with_value(0x00, gain.to_be_bytes()))
+            }
+            Self::GetInitialStep => put(&mut data, &[0x03]),
+            Self::SetInitialStep { step } => put(&mut data, &with_value(0x03, step.to_be_bytes())),
+            Self::MeasureRawFlow => put(&mut data, &[0x00]),
+            Self::MeasureRawThermalConductivity => put(&mut data, &[0x02]),
+            Self::MeasureTemperature => put(&mut data, &[0x10]),
+            Self::GetNumberOfCalibrations => put(&mut data, &[0x00]),
+            Self::GetCalibrationValidity { index } => {
+                put(&mut data, &with_value(0x10, index.to_be_bytes()))
+            }
+            Self::GetCalibrationGasId { index } => {
+                put(&mut data, &with_value(0x12, index.to_be_bytes()))
+            }
+            Self::GetCalibrationGasUnit { index } => {
+                put(&mut data, &with_value(0x13, index.to_be_bytes()))
+            }
+            Self::GetCalibrationFullScale { index } => {
+                put(&mut data, &with_value(0x14, index.to_be_bytes()))
+            }
+            Self::GetCurrentGasId => put(&mut data, &[0x12]),
+            Self::GetCurrentGasUnit => put(&mut data, &[0x13]),
+            Self::GetCurrentFullScale => put(&mut data, &[0x14]),
+            Self::SetCalibration { index } | Self::SetCalibrationVolatile { index } => {
+                put(&mut data, &index.to_be_bytes())
+            }
+            Self::SetSlaveAddress { address } => put(&mut data, &[address]),
+            Self::SetBaudrate { baudrate } => put(&mut data, &baudrate.to_be_bytes()),
+            Self::GetProductType => put(&mut data, &[0x00]),
+            Self::GetProductName => put(&mut data, &[0x01]),
+            Self::GetArticleCode => put(&mut data, &[0x02]),
+            Self::GetSerialNumber => put(&mut data, &[0x03]),
+            Self::GetCalibration
+            | Self::GetSlaveAddress
+            | Self::GetBaudrate
+            | Self::GetVersion
+            | Self::DeviceReset => 0,
+        };
+        (data, length)
+    }
+}
+
+// the data of every command fits into a SmallFrame
+const _: () = assert!(SmallFrame::MAX_DATA >= 5);
+
+/// Copies `bytes` to the start of `data` and returns how many there are
+fn put(data: &mut [u8; 5], bytes: &[u8]) -> usize {
+    for (slot, &byte) in data.iter_mut().zip(bytes) {
+        *slot = byte;
+    }
+    bytes.len()
+}
+
+/// A request to the device together with the function that reads its response. Whether it may
+/// be retried is the [Idempotency] of its frame.
+pub(crate) struct Request<R> {
+    pub(crate) frame: SmallFrame,
+    /// what the blocking device checks against its read-only mode
+    #[cfg(feature = "std")]
+    pub(crate) command: Command,
+    length: ResponseLength,
+    decode: fn(&[u8]) -> Result<R, DeviceError>,
+}
+
+impl<R> Request<R> {
+    pub(crate) fn new(
+        address: u8,
+        command: Command,
+        decode: fn(&[u8]) -> Result<R, DeviceError>,
+    ) -> Result<Self, DeviceError> {
+        Ok(Self {
+            frame: command.encode_small(address)?,
+            #[cfg(feature = "std")]
+            command,
+            length: command.response_length(),
+            decode,
+        })
+    }
+
+    /// The frame to send and the function that reads the data of its response, which decodes
+    /// it once it has the [Command::response_length]
+    pub(crate) fn split(self) -> (SmallFrame, impl Fn(&[u8]) -> Result<R, DeviceError>) {
+        let Self { frame, length, decode, .. } = self;
+        let read = move |data: &[u8]| {
+            length.check(data)?;
+            decode(data)
+        };
+        (frame, read)
+    }
+}
+
+/// A sub command followed by a big endian value
+fn with_value(sub_command: u8, value: [u8; 4]) -> [u8; 5] {
+    [sub_command, value[0], value[1], value[2], value[3]]
+}
+
+fn nothing(_: &[u8]) -> Result<(), DeviceError> {
+    Ok(())
+}
+
+/// The first `N` bytes of the data, as an array
+fn take<const N: usize>(data: &[u8]) -> Result<[u8; N], DeviceError> {
+    match data.first_chunk() {
+        Some(&bytes) => Ok(bytes),
+        None => Err(TranslationError::NotEnoughData(N as u8, data.len() as u8))?,
+    }
+}
+
+fn float(data: &[u8]) -> Result<f32, DeviceError> {
+    Ok(f32::from_be_bytes(take(data)?))
+}
+
+fn integer(data: &[u8]) -> Result<u32, DeviceError> {
+    Ok(u32::from_be_bytes(take(data)?))
+}
+
+fn baudrate(data: &[u8]) -> Result<Baudrate, DeviceError> {
+    integer(data).map(Baudrate::from_device)
+}
+
+fn ticks(data: &[u8]) -> Result<u16, DeviceError> {
+    Ok(u16::from_be_bytes(take(data)?))
+}
+
+fn byte(data: &[u8]) -> Result<u8, DeviceError> {
+    let [byte] = take(data)?;
+    Ok(byte)
+}
+
+fn flag(data: &[u8]) -> Result<bool, DeviceError> {
+    Ok(byte(data)? > 0)
+}
+
+fn gas_unit(data: &[u8]) -> Result<GasUnit, DeviceError> {
+    let [prefix, unit, timebase] = take(data)?;
+    Ok(GasUnit {
+        unit_prefex: Prefixes::from(i8::from_be_bytes([prefix])),
+        medium_unit: Units::from(unit),
+        timebase: TimeBases::from(timebase),
+    })
+}
+
+fn version(data: &[u8]) -> Result<Version, DeviceError> {
+    let [
+        firmware_major,
+        firmware_minor,
+        debug,
+        hardware_major,
+        hardware_minor,
+        protocol_major,
+        protocol_minor,
+    ] = take(data)?;
+    Ok(Version {
+        firmware_major,
+        firmware_minor,
+        debug: debug > 0,
+        hardware_major,
+        hardware_minor,
+        protocol_major,
+        protocol_minor,
+    })
+}
+
+pub(crate) fn get_setpoint(address: u8) -> Result<Request<f32>, DeviceError> {
+    Request::new(address, Command::GetSetpoint, float)
+}
+
+pub(crate) fn set_setpoint(address: u8, setpoint: f32) -> Result<Request<()>, DeviceError> {
+    Request::new(address, Command::SetSetpoint { value: setpoint }, nothing)
+}
+
+pub(crate) fn read_measured_value(address: u8) -> Result<Request<f32>, DeviceError> {
+    Request::new(address, Command::ReadMeasuredValue, float)
+}
+
+pub(crate) fn read_average_measured_value(
+    address: u8,
+    measurment_count: u8,
+) -> Result<Request<f32>, DeviceError> {
+    Request::new(
+        address,
+        Command::ReadAverageMeasuredValue {
+            count: measurment_count,
+        },
+        float,
+    )
+}
+
+pub(crate) fn set_setpoint_and_read_measured_value(
+    address: u8,
+    setpoint: f32,
+) -> Result<Request<f32>, DeviceError> {
+    Request::new(
+        address,
+        Command::SetSetpointAndReadMeasuredValue { value: setpoint },
+        float,
+    )
+}
+
+pub(crate) fn get_controller_gain(address: u8) -> Result<Request<f32>, DeviceError> {
+    Request::new(address, Command::GetControllerGain, float)
+}
+
+pub(crate) fn set_controller_gain(address: u8, gain: f32) -> Result<Request<()>, DeviceError> {
+    Request::new(address, Command::SetControllerGain { gain }, nothing)
+}
+
+pub(crate) fn get_initial_step(address: u8) -> Result<Request<f32>, DeviceError> {
+    Request::new(address, Command::GetInitialStep, float)
+}
+
+pub(crate) fn set_initial_step(address: u8, step: f32) -> Result<Request<()>, DeviceError> {
+    Request::new(address, Command::SetInitialStep { step }, nothing)
+}
+
+pub(crate) fn measure_raw_flow(address: u8) -> Result<Request<u16>, DeviceError> {
+    Request::new(address, Command::MeasureRawFlow, ticks)
+}
+
+pub(crate) fn measure_raw_thermal_conductivity(address: u8) -> Result<Request<u16>, DeviceError> {
+    Request::new(address, Command::MeasureRawThermalConductivity, ticks)
+}
+
+pub(crate) fn measure_temperature(address: u8) -> Result<Request<f32>, DeviceError> {
+    Request::new(address, Command::MeasureTemperature, float)
+}
+
+pub(crate) fn get_number_of_calibrations(address: u8) -> Result<Request<u32>, DeviceError> {
+    Request::new(address, Command::GetNumberOfCalibrations, integer)
+}
+
+pub(crate) fn get_calibration_validity(
+    address: u8,
+    index: u32,
+) -> Result<Request<bool>, DeviceError> {
+    Request::new(address, Command::GetCalibrationValidity { index }, flag)
+}
+
+pub(crate) fn get_calibration_gas_id(address: u8, index: u32) -> Result<Request<u32>, DeviceError> {
+    Request::new(address, Command::GetCalibrationGasId { index }, integer)
+}
+
+pub(crate) fn get_calibration_gas_unit(
+    address: u8,
+    index: u32,
+) -> Result<Request<GasUnit>, DeviceError> {
+    Request::new(address, Command::GetCalibrationGasUnit { index }, gas_unit)
+}
+
+pub(crate) fn get_calibration_full_scale(
+    address: u8,
+    index: u32,
+) -> Result<Request<f32>, DeviceError> {
+    Request::new(address, Command::GetCalibrationFullScale { index }, float)
+}
+
+pub(crate) fn get_current_gas_id(address: u8) -> Result<Request<u32>, DeviceError> {
+    Request::new(address, Command::GetCurrentGasId, integer)
+}
+
+pub(crate) fn get_current_gas_unit(address: u8) -> Result<Request<GasUnit>, DeviceError> {
+    Request::new(address, Command::GetCurrentGasUnit, gas_unit)
+}
+
+pub(crate) fn get_current_full_scale(address: u8) -> Result<Request<f32>, DeviceError> {
+    Request::new(address, Command::GetCurrentFullScale, float)
+}
+
+pub(crate) fn get_calliration_number(address: u8) -> Result<Request<u32>, DeviceError> {
+    Request::new(address, Command::GetCalibration, integer)
+}
+
+pub(crate) fn set_callibration(address: u8, index: u32) -> Result<Request<()>, DeviceError> {
+    Request::new(address, Command::SetCalibration { index }, nothing)
+}
+
+pub(crate) fn set_callibration_volitile(
+    address: u8,
+    index: u32,
+) -> Result<Request<()>, DeviceError> {
+    Request::new(address, Command::SetCalibrationVolatile { index }, nothing)
+}
+
+pub(crate) fn get_slave_adress(address: u8) -> Result<Request<u8>, DeviceError> {
+    Request::new(address, Command::GetSlaveAddress, byte)
+}
+
+pub(crate) fn set_slave_adress(address: u8, new_adress: u8) -> Result<Request<()>, DeviceError> {
+    Request::new(
+        address,
+        Command::SetSlaveAddress {
+            address: new_adress,
+        },
+        nothing,
+    )
+}
+
+pub(crate) fn get_baudrate(address: u8) -> Result<Request<Baudrate>, DeviceError> {
+    Request::new(address, Command::GetBaudrate, baudrate)
+}
+
+pub(crate) fn set_baudrate(address: u8, baudrate: Baudrate) -> Result<Request<()>, DeviceError> {
+    Request::new(
+        address,
+        Command::SetBaudrate {
+            baudrate: baudrate.into(),
+        },
+        nothing,
+    )
+}
+
+pub(crate) fn get_product_type(address: u8) -> Result<Request<DeviceString>, DeviceError> {
+    Request::new(address, Command::GetProductType, parse_string)
+}
+
+pub(crate) fn get_product_name(address: u8) -> Result<Request<DeviceString>, DeviceError> {
+    Request::new(address, Command::GetProductName, parse_string)
+}
+
+pub(crate) fn get_article_code(address: u8) -> Result<Request<DeviceString>, DeviceError> {
+    Request::new(address, Command::GetArticleCode, parse_string)
+}
+
+pub(crate) fn get_serial_number(address: u8) -> Result<Request<DeviceString>, DeviceError> {
+    Request::new(address, Command::GetSerialNumber, parse_string)
+}
+
+pub(crate) fn get_version(address: u8) -> Result<Request<Version>, DeviceError> {
+    Request::new(address, Command::GetVersion, version)
+}
+
+pub(crate) fn reset_device(address: u8) -> Result<Request<()>, DeviceError> {
+    Request::new(address, Command::DeviceReset, nothing)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Every command with the command byte and data the device methods sent before they were
+    /// built from [Command]
+    pub(crate) fn catalog() -> Vec<(Command, u8, Vec<u8>)> {
+        let value = 2.5_f32.to_be_bytes();
+        vec![
+            (Command::GetSetpoint, 0x00, vec![0x01]),
+            (
+                Command::SetSetpoint { value: 2.5 },
+                0x00,
+                [&[0x01], &value[..]].concat(),
+            ),
+            (Command::ReadMeasuredValue, 0x08, vec![0x01]),
+            (
+                Command::ReadAverageMeasuredValue { count: 50 },
+                0x08,
+                vec![0x11, 50],
+            ),
+            (
+                Command::SetSetpointAndReadMeasuredValue { value: 2.5 },
+                0x03,
+                [&[0x01], &value[..]].concat(),
+            ),
+            (Command::GetControllerGain, 0x22, vec![0x00]),
+            (
+                Command::SetControllerGain { gain: 2.5 },
+                0x22,
+                [&[0x00], &value[..]].concat(),
+            ),
+            (Command::GetInitialStep, 0x22, vec![0x03]),
+            (
+                Command::SetInitialStep { step: 2.5 },
+                0x22,
+                [&[0x03], &value[..]].concat(),
+            ),
+            (Command::MeasureRawFlow, 0x30, vec![0x00]),
+            (Command::MeasureRawThermalConductivity, 0x30, vec![0x02]),
+            (Command::MeasureTemperature, 0x30, vec![0x10]),
+            (Command::GetNumberOfCalibrations, 0x40, vec![0x00]),
+            (
+                Command::GetCalibrationValidity { index: 2 },
+                0x40,
+                vec![0x10, 0, 0, 0, 2],
+            ),
+            (
+                Command::GetCalibrationGasId { index: 2 },
+                0x40,
+                vec![0x12, 0, 0, 0, 2],
+            ),
+            (
+                Command::GetCalibrationGasUnit { index: 2 },
+                0x40,
+                vec![0x13, 0, 0, 0, 2],
+            ),
+            (
+                Command::GetCalibrationFullScale { index: 2 },
+                0x40,
+                vec![0x14, 0, 0, 0, 2],
+            ),
+            (Command::GetCurrentGasId, 0x44, vec![0x12]),
+            (Command::GetCurrentGasUnit, 0x44, vec![0x13]),
+            (Command::GetCurrentFullScale, 0x44, vec![0x14]),
+            (Command::GetCalibration, 0x45, vec![]),
+            (Command::SetCalibration { index: 2 }, 0x45, vec![0, 0, 0, 2]),
+            (
+                Command::SetCalibrationVolatile { index: 2 },
+                0x46,
+                vec![0, 0, 0, 2],
+            ),
+            (Command::GetSlaveAddress, 0x90, vec![]),
+            // an escaped byte
+            (Command::SetSlaveAddress { address: 0x11 }, 0x90, vec![0x11]),
+            (Command::GetBaudrate, 0x91, vec![]),
+            (
+                Command::SetBaudrate { baudrate: 115200 },
+                0x91,
+                vec![0x00, 0x01, 0xC2, 0x00],
+            ),
+            (Command::GetProductType, 0xD0, vec![0x00]),
+            (Command::GetProductName, 0xD0, vec![0x01]),
+            (Command::GetArticleCode, 0xD0, vec![0x02]),
+            (Command::GetSerialNumber, 0xD0, vec![0x03]),
+            (Command::GetVersion, 0xD1, vec![]),
+            (Command::DeviceReset, 0xD3, vec![]),
+        ]
+    }
+
+    #[test]
+    fn encodes_the_frames_of_the_device() {
+        for (command, code, data) in catalog() {
+            let frame = command.encode(3).unwrap();
+            assert_eq!(frame.get_address(), 3, "{}", command.name());
+            assert_eq!(frame.get_command_number(), code, "{}", command.name());
+            let expected = MOSIFrame::new(3, code, &data).unwrap().into_raw();
+            let small = command.encode_small(3).unwrap();
+            assert_eq!(small.into_raw().as_slice(), expected.as_slice(), "{}", command.name());
+            assert_eq!(frame.into_raw(), expected, "{}", command.name());
+        }
+    }
+
+    #[test]
+    fn frames_carry_the_kind_of_command() {
+        let kind = |command: Command| command.encode(0).unwrap().kind();
+        assert_eq!(kind(Command::GetSerialNumber), CommandKind::Query);
+        assert_eq!(kind(Command::ReadMeasuredValue), CommandKind::Measurement);
+        assert_eq!(
+            kind(Command::ReadAverageMeasuredValue { count: 40 }),
+            CommandKind::AveragedMeasurement(40)
+        );
+        assert_eq!(kind(Command::SetCalibration { index: 1 }), CommandKind::Reset);
+        assert_eq!(kind(Command::DeviceReset), CommandKind::Reset);
+    }
+
+    #[test]
+    fn every_command_is_listed_once() {
+        let variants: std::collections::HashSet<_> = catalog()
+            .iter()
+            .map(|(command, ..)| std::mem::discriminant(command))
+            .collect();
+        assert_eq!(variants.len(), catalog().len());
+        // one entry for each variant
+        assert_eq!(variants.len(), 33);
+    }
+
+    #[test]
+    fn every_command_is_named_by_sfc_core() {
+        for (command, ..) in catalog() {
+            let frame = command.encode(0).unwrap();
+            assert_eq!(frame.family(), Some(DeviceFamily::Sfc6xxx));
+            assert_eq!(frame.name(), Some(command.name()), "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn only_commands_that_change_the_device_for_good_are_not_idempotent() {
+        let not_idempotent: Vec<_> = catalog()
+            .iter()
+            .map(|(command, ..)| command)
+            .filter(|command| command.idempotency() == Idempotency::NonIdempotent)
+            .map(Command::name)
+            .collect();
+        assert_eq!(
+            not_idempotent,
+            [
+                "calibration",
+                "volatile calibration",
+                "slave address",
+                "baudrate",
+                "device reset",
+            ]
+        );
+        let request = reset_device(0).unwrap();
+        assert_eq!(request.frame.idempotency(), Idempotency::NonIdempotent);
+    }
+}
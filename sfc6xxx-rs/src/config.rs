@@ -0,0 +1,187 @@
+//! Converging a device to a [DeviceConfig], see [sfc_core::config]:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use sfc_core::config::DeviceConfig;
+//!
+//! let desired = DeviceConfig {
+//!     controller_gain: Some(1.5),
+//!     calibration: Some(2),
+//!     ..DeviceConfig::default()
+//! };
+//! println!("{}", device.apply_config(&desired, true)?);
+//! let diff = device.apply_config(&desired, false)?;
+//! assert!(diff.is_converged());
+//! # Ok(())
+//! # }
+//! ```
+
+use sfc_core::baudrate::Baudrate;
+use sfc_core::config::{ConfigDiff, DeviceConfig, Setting, SkipReason};
+use sfc_core::error::DeviceError;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+impl<T: Transport> Device<T> {
+    /// Reads the settings `desired` asks for, compares them and, unless it's a dry run, writes
+    /// the ones that differ in the order of [DeviceConfig::settings]. The SFC6xxx has no setpoint
+    /// persistence or medium unit, those are skipped as [SkipReason::Unsupported]. The
+    /// calibration is switched persistently, which closes the valve.
+    ///
+    /// A failed read is returned as an error before anything is written. A failed write stops
+    /// the apply and is reported in [ConfigDiff::failed], the settings written before it stay.
+    /// A [read-only](Device::set_read_only) device only takes dry runs.
+    pub fn apply_config(
+        &mut self,
+        desired: &DeviceConfig,
+        dry_run: bool,
+    ) -> Result<ConfigDiff, DeviceError> {
+        if !dry_run {
+            self.writable("apply config")?;
+        }
+        let current = DeviceConfig {
+            address: desired.address.map(|_| self.get_slave_adress()).transpose()?,
+            baudrate: desired
+                .baudrate
+                .map(|_| self.get_baudrate().map(u32::from))
+                .transpose()?,
+            controller_gain: desired.controller_gain.map(|_| self.get_controller_gain()).transpose()?,
+            initial_step: desired.initial_step.map(|_| self.get_initial_step()).transpose()?,
+            calibration: desired.calibration.map(|_| self.get_calliration_number()).transpose()?,
+            setpoint_persistent: None,
+            unit: None,
+        };
+        let mut diff = ConfigDiff::compare(desired, current, |setting| {
+            matches!(setting, Setting::SetpointPersistent(_) | Setting::Unit(_))
+                .then_some(SkipReason::Unsupported)
+        });
+        if dry_run {
+            return Ok(diff);
+        }
+
+        diff.apply(|setting| match setting {
+            Setting::Calibration(index) => self.set_callibration(index),
+            Setting::ControllerGain(gain) => self.set_controller_gain(gain),
+            Setting::InitialStep(step) => self.set_initial_step(step),
+            Setting::Baudrate(baudrate) => {
+                Baudrate::try_from(baudrate).and_then(|baudrate| self.set_baudrate(baudrate))
+            }
+            Setting::Address(address) => self.set_slave_adress(address),
+            // skipped as unsupported, never applied
+            Setting::SetpointPersistent(_) | Setting::Unit(_) => Ok(()),
+        });
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorHandle, Fault, Sfc6xxxEmulator};
+    use sfc_core::error::StateResponseError;
+
+    fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::default();
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    /// The commands of the requests after the first `skip`
+    fn commands(handle: &EmulatorHandle, skip: usize) -> Vec<(u8, Vec<u8>)> {
+        handle
+            .requests()
+            .into_iter()
+            .skip(skip)
+            .map(|(_, command, data)| (command, data))
+            .collect()
+    }
+
+    #[test]
+    fn matching_config_only_reads() {
+        let (mut device, handle) = emulated_device();
+        let desired = DeviceConfig {
+            address: Some(0),
+            baudrate: Some(115200),
+            calibration: Some(0),
+            ..DeviceConfig::default()
+        };
+        let before = handle.requests().len();
+        let diff = device.apply_config(&desired, false).unwrap();
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.unchanged.len(), 3);
+        assert!(diff.is_converged());
+        // one read per setting and nothing written
+        assert!(commands(&handle, before).iter().all(|(_, data)| data.is_empty()));
+        assert_eq!(handle.requests().len() - before, 3);
+    }
+
+    #[test]
+    fn applies_the_differing_settings() {
+        let (mut device, handle) = emulated_device();
+        device.set_controller_gain(1.0).unwrap();
+        let desired = DeviceConfig {
+            address: Some(4),
+            controller_gain: Some(1.0),
+            initial_step: Some(0.5),
+            calibration: Some(2),
+            unit: Some(device.get_current_gas_unit().unwrap()),
+            ..DeviceConfig::default()
+        };
+
+        let dry = device.apply_config(&desired, true).unwrap();
+        assert!(dry.dry_run);
+        assert_eq!(
+            dry.changed,
+            vec![Setting::Calibration(2), Setting::InitialStep(0.5), Setting::Address(4)]
+        );
+        assert_eq!(handle.active_calibration(), 0);
+        assert_eq!(handle.address(), 0);
+
+        let before = handle.requests().len();
+        let diff = device.apply_config(&desired, false).unwrap();
+        assert_eq!(diff.changed, dry.changed);
+        assert_eq!(diff.unchanged, vec![Setting::ControllerGain(1.0)]);
+        assert_eq!(diff.current.calibration, Some(0));
+        assert!(matches!(diff.skipped[..], [(Setting::Unit(_), SkipReason::Unsupported)]));
+        // the address is written last, reading the controller settings takes a subcommand byte
+        let writes: Vec<u8> = commands(&handle, before)
+            .into_iter()
+            .filter(|(command, data)| data.len() > usize::from(*command == 0x22))
+            .map(|(command, _)| command)
+            .collect();
+        assert_eq!(writes, vec![0x45, 0x22, 0x90]);
+        assert_eq!(handle.active_calibration(), 2);
+        assert_eq!(handle.address(), 4);
+        assert_eq!(device.get_initial_step().unwrap(), 0.5);
+    }
+
+    #[test]
+    fn failed_write_stops_the_apply() {
+        let (mut device, handle) = emulated_device();
+        let desired = DeviceConfig {
+            address: Some(4),
+            controller_gain: Some(2.0),
+            calibration: Some(1),
+            ..DeviceConfig::default()
+        };
+        // three reads and the calibration go through, the gain is refused
+        for _ in 0..4 {
+            handle.inject_fault(Fault::DelayMs(0));
+        }
+        handle.inject_fault(Fault::ErrorState(0x04));
+        let diff = device.apply_config(&desired, false).unwrap();
+        assert_eq!(diff.changed, vec![Setting::Calibration(1)]);
+        match &diff.failed {
+            Some((
+                Setting::ControllerGain(_),
+                DeviceError::StateResponse(StateResponseError::ParameterError),
+            )) => {}
+            other => panic!("expected the gain to fail, got {:?}", other),
+        }
+        assert_eq!(diff.skipped, vec![(Setting::Address(4), SkipReason::AfterFailure)]);
+        assert!(!diff.is_converged());
+        assert_eq!(handle.active_calibration(), 1);
+        assert_eq!(handle.address(), 0);
+    }
+}
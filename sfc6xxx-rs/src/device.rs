@@ -1,259 +1,606 @@
 //! The SFC6xxx device and associated functions
 
-use std::ffi::CString;
-
-use arrayvec::ArrayVec;
-use serialport::SerialPort;
-
-use sfc_core::error::{DeviceError, StateResponseError};
-use sfc_core::gasunit::{GasUnit, Prefixes, TimeBases, Units};
-use sfc_core::shdlc::{MISOFrame, MOSIFrame, TranslationError, Version};
-
-/// A representation of a physical SFC6XXX. It must be given a valid serial port
-/// in order to operate.
-#[derive(Debug)]
-pub struct Device<T: SerialPort> {
-    port: T,
+use std::time::{Duration, Instant};
+
+use sfc_core::baudrate::Baudrate;
+use sfc_core::capabilities::{Capabilities, Feature};
+use sfc_core::discovery::{
+    self, ConnectionProfile, DiscoveryPolicy, NativePort, ProfiledDevice, open_first_detected,
+    open_port,
+};
+use sfc_core::error::DeviceError;
+use sfc_core::flow_controller::FlowController;
+use sfc_core::gasunit::GasUnit;
+use sfc_core::health::{self, CheckOutcome, HealthReport, HealthRequirements};
+use sfc_core::identity::{IdentityField, IdentityFilter};
+use sfc_core::limits::{LimitPolicy, SoftLimits};
+use sfc_core::measurement::{Measurement, ValueScale};
+use sfc_core::middleware::FrameMiddleware;
+use sfc_core::names::DeviceFamily;
+use sfc_core::shdlc::{Idempotency, MISOFrame, MISOFrameRef, MOSIFrame, Version};
+use sfc_core::bus::SharedBus;
+use sfc_core::connection::{
+    BootGrace, BusyPolicy, Connection, DEFAULT_INTER_BYTE_TIMEOUT, PendingCommand, RetryConfig,
+    Rs485Config, SpinPolicy, Timeouts, ValidationLevel,
+};
+use sfc_core::transport::Transport;
+use sfc_core::stats::CommStats;
+
+use crate::commands::{self, Command, Request};
+
+/// How long [Device::reset_device] waits for a busy sensor to take the reset
+const RESET_BUSY: BusyPolicy =
+    BusyPolicy::new(Duration::from_millis(500), Duration::from_millis(20));
+/// How long the calibration switches wait for a busy sensor to finish its measurement
+const CALIBRATION_BUSY: BusyPolicy =
+    BusyPolicy::new(Duration::from_secs(1), Duration::from_millis(20));
+
+/// A representation of a physical SFC6XXX. It must be given a valid serial port, or any other
+/// [Transport], in order to operate.
+pub struct Device<T: Transport> {
+    connection: Connection<T>,
     slave_adress: u8,
+    /// the gas unit and serial number attached to recorded values, read with the first of them
+    metadata: Option<(GasUnit, String, f32)>,
+    /// what the firmware supports, derived from the version read for the first of them
+    capabilities: Option<Capabilities>,
+    soft_limits: SoftLimits,
+    read_only: bool,
+}
+
+/// Formats what the device knows without asking it: the address, the settings and counters of
+/// the connection, whether the metadata of recorded values is cached, the capabilities if they
+/// were read, the soft limits and whether it is read-only.
+impl<T: Transport> std::fmt::Debug for Device<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Device")
+            .field("slave_adress", &self.slave_adress)
+            .field("connection", &self.connection)
+            .field("metadata_cached", &self.metadata.is_some())
+            .field("capabilities", &self.capabilities)
+            .field("soft_limits", &self.soft_limits)
+            .field("read_only", &self.read_only)
+            .finish()
+    }
 }
 
-impl<T: SerialPort> Device<T> {
+impl<T: Transport> Device<T> {
     /// The device can be created by passing a serial port and slave adress like so:
     /// ```no_run
     /// use sfc6xxx_rs::device::Device;
     /// let test_port = serialport::new("ttyUSB0", 115200).open_native().unwrap();
     /// let device = Device::new(test_port, 0).unwrap();
     /// ```
+    /// [Device::open] does both for a serial port.
     /// This function also sends the [Device::get_baudrate] command to ensure
     /// its connected to a valid shdlc device.
-    pub fn new(mut serial_port: T, slave_adress: u8) -> Result<Self, DeviceError> {
-        serial_port.set_timeout(std::time::Duration::from_millis(600))?;
+    pub fn new(serial_port: T, slave_adress: u8) -> Result<Self, DeviceError> {
+        Self::builder(serial_port).address(slave_adress).build()
+    }
 
-        let mut device = Self {
+    /// Configures the device before creating it, for settings [Device::new] doesn't take:
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// use sfc6xxx_rs::device::Device;
+    /// use sfc6xxx_rs::sfc_core::connection::RetryConfig;
+    /// let port = serialport::new("/dev/ttyUSB0", 115200).open_native().unwrap();
+    /// let device = Device::builder(port)
+    ///     .address(0)
+    ///     .timeout(Duration::from_millis(300))
+    ///     .retries(RetryConfig::default())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder(serial_port: T) -> DeviceBuilder<T> {
+        DeviceBuilder {
             port: serial_port,
+            address: 0,
+            timeouts: Timeouts::default(),
+            inter_byte_timeout: DEFAULT_INTER_BYTE_TIMEOUT,
+            clear_stale_input: true,
+            retry: None,
+            boot_grace: None,
+            busy_retry: None,
+            rs485: None,
+            spin: SpinPolicy::default(),
+            validation: ValidationLevel::default(),
+            baudrate: None,
+            probe: true,
+            identity: IdentityFilter::default(),
+            read_only: false,
+        }
+    }
+
+    /// Creates a device for one slave address on a [SharedBus] so several devices can share one
+    /// RS-485 line. Like [Device::new] it probes the device with [Device::get_baudrate].
+    pub fn on_bus(bus: &SharedBus<T>, slave_adress: u8) -> Result<Self, DeviceError> {
+        let mut device = Self {
+            connection: bus.handle(slave_adress).into(),
             slave_adress,
+            metadata: None,
+            capabilities: None,
+            soft_limits: SoftLimits::default(),
+            read_only: false,
         };
 
-        // simple command ot check if its a valid SHDLC device
         let _ = device.get_baudrate()?;
 
         Ok(device)
     }
 
-    /// Returns the current flow setpoint as a physical value in SLM
-    pub fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x00, &[0x01])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
+    /// Connects to the device of a profile like [Device::connect_with_profile], opening the
+    /// ports with `open`, which is given the name of a port and the baudrate to open it at
+    pub fn connect_with_profile_using<F>(
+        profile: &ConnectionProfile,
+        fallback: &DiscoveryPolicy,
+        open: F,
+    ) -> Result<(Self, ConnectionProfile), DeviceError>
+    where
+        F: FnMut(&str, u32) -> Result<T, DeviceError>,
+    {
+        discovery::connect_using(profile, fallback, open)
+    }
+
+    /// Returns how long the device has to start answering a query, see [Device::timeouts]
+    pub fn response_timeout(&self) -> Duration {
+        self.connection.response_timeout()
+    }
+
+    /// Sets how long the device has to start answering a command before the command fails with
+    /// [DeviceError::Timeout], the same for every kind of command. Keep this short to notice a
+    /// missing device quickly.
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.connection.set_response_timeout(timeout);
+    }
+
+    /// Returns how long the device has to start answering each kind of command
+    pub fn timeouts(&self) -> Timeouts {
+        self.connection.timeouts()
+    }
+
+    /// Sets how long the device has to start answering each kind of command. Reads, averages and
+    /// resets are timed by their own budget, so a short query timeout still notices a missing
+    /// device quickly while a long average gets the time it needs.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.connection.set_timeouts(timeouts);
+    }
+
+    /// Returns the time allowed between two bytes of a response
+    pub fn inter_byte_timeout(&self) -> Duration {
+        self.connection.inter_byte_timeout()
+    }
+
+    /// Sets the time allowed between two bytes of a response before the command fails with
+    /// [DeviceError::IncompleteFrame]
+    pub fn set_inter_byte_timeout(&mut self, timeout: Duration) {
+        self.connection.set_inter_byte_timeout(timeout);
+    }
+
+    /// Returns true if pending input is discarded before every command
+    pub fn clear_stale_input(&self) -> bool {
+        self.connection.clear_stale_input()
+    }
+
+    /// Sets whether input that is still pending when a command is sent, like the response to a
+    /// command that timed out, is discarded first so it can't be mistaken for the new response.
+    /// On by default. When off, bytes read past the end of a response are kept and looked at by
+    /// the next command before reading from the port.
+    pub fn set_clear_stale_input(&mut self, clear: bool) {
+        self.connection.set_clear_stale_input(clear);
+    }
+
+    /// Returns the retry configuration, [None] if commands are never retried
+    pub fn retry(&self) -> Option<RetryConfig> {
+        self.connection.retry()
+    }
+
+    /// Sets how failed commands are retried, off by default. Commands that would have a
+    /// different effect when executed twice are only retried with
+    /// [RetryConfig::retry_non_idempotent], their documentation says so and
+    /// [Command::idempotency](crate::commands::Command::idempotency) lists them.
+    pub fn set_retry(&mut self, retry: Option<RetryConfig>) {
+        self.connection.set_retry(retry);
+    }
+
+    /// Returns how the device is waited for while it boots after [Device::reset_device], [None]
+    /// if it isn't
+    pub fn boot_grace(&self) -> Option<BootGrace> {
+        self.connection.boot_grace()
+    }
+
+    /// Sets how long after [Device::reset_device] a [StateResponseError::FatalError] means the
+    /// device is still booting, and how often the command is sent again until it answers, see
+    /// [BootGrace]. Later, or without a reset through this device, the error fails the command
+    /// as usual. [None], the default, leaves the grace off, [BootGrace::default] suits the devices.
+    ///
+    /// [StateResponseError::FatalError]: sfc_core::error::StateResponseError::FatalError
+    pub fn set_boot_grace(&mut self, grace: Option<BootGrace>) {
+        self.connection.set_boot_grace(grace);
+    }
+
+    /// Returns how long ago [Device::reset_device] was last sent, [None] if it never was
+    pub fn since_reset(&self) -> Option<Duration> {
+        self.connection.since_reset()
+    }
+
+    /// Returns how commands the device answers as busy are sent again, [None] if they aren't
+    pub fn busy_retry(&self) -> Option<BusyPolicy> {
+        self.connection.busy_retry()
+    }
+
+    /// Sets how long a command the device answers with [StateResponseError::SensorBusy] is sent
+    /// again before it fails with [DeviceError::StillBusy], see [BusyPolicy]. Off by default,
+    /// the busy state then fails the command. [Device::reset_device] and the calibration
+    /// switches wait with a budget of their own while it is off.
+    ///
+    /// [StateResponseError::SensorBusy]: sfc_core::error::StateResponseError::SensorBusy
+    pub fn set_busy_retry(&mut self, policy: Option<BusyPolicy>) {
+        self.connection.set_busy_retry(policy);
+    }
+
+    /// Hands every request to the middleware before it is sent and every response to it once
+    /// received, see [FrameMiddleware]. A request it blocks fails with
+    /// [DeviceError::BlockedByMiddleware] without being sent. Replaces the middleware set before.
+    pub fn set_middleware(&mut self, middleware: Box<dyn FrameMiddleware>) {
+        self.connection.set_middleware(middleware);
+    }
+
+    /// Removes the middleware and returns it, [None] if there is none
+    pub fn take_middleware(&mut self) -> Option<Box<dyn FrameMiddleware>> {
+        self.connection.take_middleware()
+    }
+
+    /// Returns how closely responses are checked
+    pub fn validation_level(&self) -> ValidationLevel {
+        self.connection.validation_level()
+    }
+
+    /// Sets which checks of a response fail a command, which are only logged and which are
+    /// skipped, see [ValidationLevel]. [ValidationLevel::Standard] by default.
+    pub fn set_validation_level(&mut self, level: ValidationLevel) {
+        self.connection.set_validation_level(level);
+    }
+
+    /// Returns how many requests [Device::pipeline] writes ahead of their responses
+    pub fn pipeline_window(&self) -> usize {
+        self.connection.pipeline_window()
+    }
+
+    /// Sets how many requests [Device::pipeline] may have written that were not answered yet.
+    /// 1, the default, sends one request at a time.
+    pub fn set_pipeline_window(&mut self, window: usize) {
+        self.connection.set_pipeline_window(window);
+    }
+
+    /// Returns how a transport that returns empty reads is waited on
+    pub fn spin_policy(&self) -> SpinPolicy {
+        self.connection.spin_policy()
+    }
+
+    /// Sets how often a transport that returns from a read with no bytes, like a port opened
+    /// with a zero timeout, is read again before the device sleeps between reads, see
+    /// [SpinPolicy]. By default it sleeps for the inter-byte timeout after every empty read.
+    pub fn set_spin_policy(&mut self, policy: SpinPolicy) {
+        self.connection.set_spin_policy(policy);
+    }
+
+    /// Returns how many commands were sent, retried and failed, and how long the device takes to
+    /// answer, since the device was created or [Self::reset_stats]
+    pub fn stats(&self) -> &CommStats {
+        self.connection.stats()
+    }
+
+    /// Sets the counters of [Self::stats] back to zero
+    pub fn reset_stats(&mut self) {
+        self.connection.reset_stats();
+    }
+
+    /// Returns how commands are sent over RS-485, [None] if the adapter switches direction by
+    /// itself
+    pub fn rs485(&self) -> Option<Rs485Config> {
+        self.connection.rs485()
+    }
+
+    /// Sets up an RS-485 adapter with manual direction control: the request to send line is
+    /// raised while a command is written and the turnaround delay is waited before reading the
+    /// response. Off by default, which suits adapters that switch direction automatically.
+    pub fn set_rs485(&mut self, rs485: Option<Rs485Config>) {
+        self.connection.set_rs485(rs485);
+    }
+
+    /// Returns whether commands that change the device are refused, see [Device::set_read_only]
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Refuses every command that [changes the device](Command::writes) with
+    /// [DeviceError::ReadOnly] before anything is sent, for diagnostics on a live process line.
+    /// Readings work as usual. Helpers that change the device, like [Device::apply_config] or
+    /// [Device::leak_test], fail before their first command, and so do raw commands from
+    /// [Device::start_command], which can't be classified. Off by default.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Fails with [DeviceError::ReadOnly] in read-only mode, `name` is what was refused
+    pub(crate) fn writable(&self, name: &'static str) -> Result<(), DeviceError> {
+        if self.read_only {
+            return Err(DeviceError::ReadOnly(name));
         }
+        Ok(())
+    }
 
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    /// Gets back in step with the device after the serial stream got garbled, for example by
+    /// unplugging the cable mid frame. Discards pending input, ends any frame the device is
+    /// still receiving and checks that it answers again. Retries do this by themselves when
+    /// [RetryConfig::resync_after] is set.
+    pub fn resync(&mut self) -> Result<(), DeviceError> {
+        self.connection.resync(self.slave_adress)
+    }
+
+    /// Replaces the transport after the old one stopped working, like a USB adapter that was
+    /// unplugged and plugged back in, and probes the device with [Device::get_baudrate].
+    ///
+    /// The timeouts, retry, RS-485 and stale input settings are kept and the baudrate last set
+    /// on the transport is applied to the new one. Bytes received from the old transport are
+    /// dropped. The device keeps no other state on this side, the setpoint and calibration are
+    /// whatever the device itself holds after being replugged, and the unit and serial number of
+    /// recorded values are read again, and so are the capabilities. If the probe fails the new
+    /// transport stays in place so the call can be repeated.
+    pub fn reconnect(&mut self, new_port: T) -> Result<(), DeviceError> {
+        self.metadata = None;
+        self.capabilities = None;
+        self.connection.reconnect(new_port)?;
+        let _ = self.get_baudrate()?;
+        Ok(())
+    }
+
+    /// Sends a raw command without waiting for the response. The returned [PendingCommand] is
+    /// polled until the response arrived and borrows the device until then, it is never retried.
+    /// ```no_run
+    /// # fn run(mut device: sfc6xxx_rs::device::Device<sfc_core::transport::TcpTransport>) {
+    /// let mut pending = device.start_command(0x08, &[0x01]).unwrap();
+    /// let response = loop {
+    ///     if let std::task::Poll::Ready(response) = pending.poll() {
+    ///         break response;
+    ///     }
+    ///     // do other work in the meantime
+    /// };
+    /// # }
+    /// ```
+    pub fn start_command(
+        &mut self,
+        command: u8,
+        data: &[u8],
+    ) -> Result<PendingCommand<'_, T>, DeviceError> {
+        self.writable("raw command")?;
+        let frame = MOSIFrame::new(self.slave_adress, command, data)?;
+        self.connection.start(frame)
+    }
+
+    /// Sends the commands and returns their responses in the same order, writing up to
+    /// [Device::pipeline_window] requests before reading a response. When a response doesn't
+    /// match its request or fails the remaining commands are sent one at a time, so a command
+    /// may be executed twice: only pipeline commands that read from the device.
+    /// ```no_run
+    /// # fn run(mut device: sfc6xxx_rs::device::Device<sfc_core::transport::TcpTransport>) {
+    /// use sfc6xxx_rs::commands::Command;
+    /// device.set_pipeline_window(4);
+    /// let responses = device
+    ///     .pipeline(&[
+    ///         Command::GetCalibrationGasId { index: 0 },
+    ///         Command::GetCalibrationFullScale { index: 0 },
+    ///     ])
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn pipeline(&mut self, commands: &[Command]) -> Result<Vec<MISOFrame>, DeviceError> {
+        for command in commands.iter().filter(|command| command.writes()) {
+            self.writable(command.name())?;
+        }
+        let frames = commands
+            .iter()
+            .map(|command| command.encode_small(self.slave_adress))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.connection.pipeline(frames)
+    }
+
+    /// Returns the current flow setpoint as a physical value in SLM
+    pub fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_setpoint(self.slave_adress)?)
     }
 
     /// Sets the flow setpoint as a physical value. The range of valid set points is 0.0 to
-    /// [Device::get_current_full_scale]. The setpoint will be set to 0 if the calibration is ever
-    /// changed.
+    /// [Device::get_current_full_scale], and within the [soft limits](Device::set_soft_limits).
+    /// The setpoint will be set to 0 if the calibration is ever changed.
     pub fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError> {
-        let setpoint_bytes = setpoint.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x00,
-            &[
-                0x01,
-                setpoint_bytes[0],
-                setpoint_bytes[1],
-                setpoint_bytes[2],
-                setpoint_bytes[3],
-            ],
-        )?;
-
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        let setpoint = self.soft_limits.apply(setpoint)?;
+        self.run(commands::set_setpoint(self.slave_adress, setpoint)?)
+    }
+
+    /// Returns the limits every setpoint is checked against before it is sent
+    pub fn soft_limits(&self) -> SoftLimits {
+        self.soft_limits
+    }
+
+    /// Limits the setpoints this device sends to `min..=max`, physical values like the setpoint,
+    /// independent of the full scale of the device. Every method writing a setpoint checks it
+    /// first, and so do profiles and everything else built on them. Raw commands from
+    /// [Device::start_command] are not checked. Setpoints outside the limits fail with
+    /// [DeviceError::SoftLimit] until [Device::set_soft_limit_policy] is set to clamp them.
+    ///
+    /// # Panics
+    /// If `min` is above `max` or either is NaN
+    pub fn set_soft_limits(&mut self, min: f32, max: f32) {
+        self.soft_limits = SoftLimits::new(min, max).with_policy(self.soft_limits.policy);
+    }
+
+    /// Sets whether setpoints outside the soft limits fail or are clamped to them
+    pub fn set_soft_limit_policy(&mut self, policy: LimitPolicy) {
+        self.soft_limits.policy = policy;
+    }
+
+    /// Allows every setpoint again, keeping the policy
+    pub fn clear_soft_limits(&mut self) {
+        self.soft_limits = SoftLimits::default().with_policy(self.soft_limits.policy);
     }
 
     /// Returns the latest measured flow as physical value
     pub fn read_measured_value(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x08, &[0x01])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
+        self.run(commands::read_measured_value(self.slave_adress)?)
+    }
+
+    /// Reads the latest measured flow as a [Measurement] with the gas unit, serial number, full
+    /// scale and address of the device. The unit, serial number and full scale are read with the
+    /// first recorded value and kept, later ones cost no more commands than
+    /// [Device::read_measured_value]. They are read again after the calibration was changed or
+    /// the device reset through this device.
+    pub fn read_measured_value_recorded(&mut self) -> Result<Measurement, DeviceError> {
+        let metadata = self.metadata()?;
+        let value = self.read_measured_value()?;
+        Ok(self.record(value, metadata))
+    }
+
+    /// [Device::read_average_measured_value] as a [Measurement], see
+    /// [Device::read_measured_value_recorded]
+    pub fn read_average_measured_value_recorded(
+        &mut self,
+        measurment_count: u8,
+    ) -> Result<Measurement, DeviceError> {
+        let metadata = self.metadata()?;
+        let value = self.read_average_measured_value(measurment_count)?;
+        Ok(self.record(value, metadata))
+    }
+
+    fn metadata(&mut self) -> Result<(GasUnit, String, f32), DeviceError> {
+        if let Some(metadata) = &self.metadata {
+            return Ok(metadata.clone());
+        }
+        let metadata = (
+            self.get_current_gas_unit()?,
+            self.get_serial_number()?,
+            self.get_current_full_scale()?,
+        );
+        Ok(self.metadata.insert(metadata).clone())
+    }
+
+    fn record(
+        &self,
+        value: f32,
+        (unit, serial_number, full_scale): (GasUnit, String, f32),
+    ) -> Measurement {
+        Measurement {
+            serial_number: Some(serial_number),
+            address: Some(self.slave_adress),
+            full_scale: Some(full_scale),
+            ..Measurement::new(value, unit, ValueScale::Physical)
         }
+    }
 
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    /// [Device::set_setpoint] for a time budget, gives up at the deadline with
+    /// [DeviceError::DeadlineExceeded], see [Connection::transact_with_deadline]
+    pub fn set_setpoint_with_deadline(
+        &mut self,
+        setpoint: f32,
+        deadline: Instant,
+    ) -> Result<(), DeviceError> {
+        let setpoint = self.soft_limits.apply(setpoint)?;
+        self.run_with_deadline(commands::set_setpoint(self.slave_adress, setpoint)?, deadline)
+    }
+
+    /// [Device::read_measured_value] for a time budget, gives up at the deadline with
+    /// [DeviceError::DeadlineExceeded], see [Connection::transact_with_deadline]
+    pub fn read_measured_value_with_deadline(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<f32, DeviceError> {
+        self.run_with_deadline(commands::read_measured_value(self.slave_adress)?, deadline)
     }
 
     /// Returns the average of given numbers of flow measurment as a physical value. Each
     /// measurment takes 1ms so the command response time depends on the number of measurements.
-    /// Addtionaly the number of measurments must be between 0 and 100 other wise it will return a
-    /// [StateResponseError::ParameterError].
+    /// Addtionaly the number of measurments must be at most
+    /// [Capabilities::max_averaging_count], 100 on every firmware so far, other wise it will
+    /// return a [DeviceError::UnsupportedFirmware] without sending the command.
     pub fn read_average_measured_value(
         &mut self,
         measurment_count: u8,
     ) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x08, &[0x11, measurment_count])?;
-        let raw = frame.into_raw();
-
-        let _ = self.port.write(&raw)?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.require(Feature::Averaging(measurment_count))?;
+        self.run(commands::read_average_measured_value(self.slave_adress, measurment_count)?)
     }
 
-    /// Sets the set point and reads the measured value in one SHDLC command
+    /// Sets the set point and reads the measured value in one SHDLC command. The setpoint is
+    /// checked against the [soft limits](Device::set_soft_limits) first.
     pub fn set_setpoint_and_read_measured_value(
         &mut self,
         setpoint: f32,
     ) -> Result<f32, DeviceError> {
-        let setpoint_bytes = setpoint.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x03,
-            &[
-                0x01,
-                setpoint_bytes[0],
-                setpoint_bytes[1],
-                setpoint_bytes[2],
-                setpoint_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        let setpoint = self.soft_limits.apply(setpoint)?;
+        self.run(commands::set_setpoint_and_read_measured_value(self.slave_adress, setpoint)?)
     }
 
     /// Returns the controller gain
     pub fn get_controller_gain(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x22, &[0x00])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_controller_gain(self.slave_adress)?)
     }
 
     /// Sets the controller gain to the desired value
     pub fn set_controller_gain(&mut self, gain: f32) -> Result<(), DeviceError> {
-        let gain_bytes = gain.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x22,
-            &[
-                0x00,
-                gain_bytes[0],
-                gain_bytes[1],
-                gain_bytes[2],
-                gain_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.run(commands::set_controller_gain(self.slave_adress, gain)?)
     }
 
     /// Gets the device intital step
     pub fn get_initial_step(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x22, &[0x03])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_initial_step(self.slave_adress)?)
     }
 
     /// Sets the initial step. This is stored in non-volatile memory and will be cleared
     /// after a device reset.
     pub fn set_initial_step(&mut self, step: f32) -> Result<(), DeviceError> {
-        let step_bytes = step.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x22,
-            &[
-                0x03,
-                step_bytes[0],
-                step_bytes[1],
-                step_bytes[2],
-                step_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.run(commands::set_initial_step(self.slave_adress, step)?)
     }
 
     /// Returns the measured flow in raw ticks
     pub fn measure_raw_flow(&mut self) -> Result<u16, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x30, &[0x00])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 2 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(u16::from_be_bytes([data[0], data[1]]))
+        self.run(commands::measure_raw_flow(self.slave_adress)?)
     }
 
     /// Preforms a thermal conductivity measurement and returns the measured raw tick value.
     /// The valve is automatically closed during the measurement
     pub fn measure_raw_thermal_conductivity(&mut self) -> Result<u16, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x30, &[0x02])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 2 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(u16::from_be_bytes([data[0], data[1]]))
+        self.run(commands::measure_raw_thermal_conductivity(self.slave_adress)?)
     }
 
     /// Measures the temperature of the flow sensor in degrees celcius
     pub fn measure_temperature(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x30, &[0x10])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
+        self.run(commands::measure_temperature(self.slave_adress)?)
+    }
 
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
+    /// [Device::read_measured_value] as a volume rate in the unit of the active calibration,
+    /// which is read first. Available with the `uom` feature. Calibrations to a mass flow return
+    /// [DeviceError::NotAVolumeRate].
+    #[cfg(feature = "uom")]
+    pub fn read_measured_value_uom(&mut self) -> Result<uom::si::f32::VolumeRate, DeviceError> {
+        let unit = self.get_current_gas_unit()?;
+        unit.to_volume_rate(self.read_measured_value()?)
+    }
 
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    /// [Device::measure_temperature] as a temperature, available with the `uom` feature
+    #[cfg(feature = "uom")]
+    pub fn measure_temperature_uom(
+        &mut self,
+    ) -> Result<uom::si::f32::ThermodynamicTemperature, DeviceError> {
+        use uom::si::thermodynamic_temperature::degree_celsius;
+        let celsius = self.measure_temperature()?;
+        Ok(uom::si::f32::ThermodynamicTemperature::new::<degree_celsius>(celsius))
     }
 
     /// Gets the number of calibrations that the device memory is able to hold.
     /// Not all calibrations actually contain a valid calibration. Use [Device::get_calibration_validity]
     /// to see which calibrations are valid and can be used
     pub fn get_number_of_calibrations(&mut self) -> Result<u32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x40, &[0x00])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_number_of_calibrations(self.slave_adress)?)
     }
 
     /// Checks if a calibration at the specific index is valid
@@ -261,50 +608,12 @@ impl<T: SerialPort> Device<T> {
         &mut self,
         calibration_index: u32,
     ) -> Result<bool, DeviceError> {
-        let index_bytes = calibration_index.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x40,
-            &[
-                0x10,
-                index_bytes[0],
-                index_bytes[1],
-                index_bytes[2],
-                index_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.is_empty() {
-            Err(TranslationError::NotEnoughData(1, data.len() as u8))?;
-        }
-
-        Ok(data[0] > 0)
+        self.run(commands::get_calibration_validity(self.slave_adress, calibration_index)?)
     }
 
     /// Gets the gas ID of the specifc calibration index.
     pub fn get_calibration_gas_id(&mut self, calibration_index: u32) -> Result<u32, DeviceError> {
-        let index_bytes = calibration_index.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x40,
-            &[
-                0x12,
-                index_bytes[0],
-                index_bytes[1],
-                index_bytes[2],
-                index_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(1, data.len() as u8))?;
-        }
-
-        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_calibration_gas_id(self.slave_adress, calibration_index)?)
     }
 
     /// Gets the gas unit of a specifc calibration index see [GasUnit] for more information.
@@ -312,33 +621,7 @@ impl<T: SerialPort> Device<T> {
         &mut self,
         calibration_index: u32,
     ) -> Result<GasUnit, DeviceError> {
-        let index_bytes = calibration_index.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x40,
-            &[
-                0x13,
-                index_bytes[0],
-                index_bytes[1],
-                index_bytes[2],
-                index_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 3 {
-            Err(TranslationError::NotEnoughData(3, data.len() as u8))?;
-        }
-
-        let prefix = Prefixes::from(i8::from_be_bytes([data[0]]));
-        let unit = Units::from(data[1]);
-        let time_base = TimeBases::from(data[2]);
-        Ok(GasUnit {
-            unit_prefex: prefix,
-            medium_unit: unit,
-            timebase: time_base,
-        })
+        self.run(commands::get_calibration_gas_unit(self.slave_adress, calibration_index)?)
     }
 
     /// Returns the full scale flow of a specifc calibration index.
@@ -346,301 +629,520 @@ impl<T: SerialPort> Device<T> {
         &mut self,
         calibration_index: u32,
     ) -> Result<f32, DeviceError> {
-        let index_bytes = calibration_index.to_be_bytes();
-        let frame = MOSIFrame::new(
-            self.slave_adress,
-            0x40,
-            &[
-                0x14,
-                index_bytes[0],
-                index_bytes[1],
-                index_bytes[2],
-                index_bytes[3],
-            ],
-        )?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_calibration_full_scale(self.slave_adress, calibration_index)?)
     }
 
     /// Gets the gas ID of the currently active calibration
     pub fn get_current_gas_id(&mut self) -> Result<u32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x44, &[0x12])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_current_gas_id(self.slave_adress)?)
     }
 
     /// Gets the gas unit of the currently active calibration. See [GasUnit] for more
     /// information
     pub fn get_current_gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x44, &[0x13])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 3 {
-            Err(TranslationError::NotEnoughData(3, data.len() as u8))?;
-        }
-
-        let prefix = Prefixes::from(i8::from_be_bytes([data[0]]));
-        let unit = Units::from(data[1]);
-        let time_base = TimeBases::from(data[2]);
-        Ok(GasUnit {
-            unit_prefex: prefix,
-            medium_unit: unit,
-            timebase: time_base,
-        })
+        self.run(commands::get_current_gas_unit(self.slave_adress)?)
     }
 
     /// Gets the full scale flow of the currently active calibration.
     pub fn get_current_full_scale(&mut self) -> Result<f32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x44, &[0x14])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(f32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_current_full_scale(self.slave_adress)?)
     }
 
     /// Gets the calibration index of the currently active calibration.
     pub fn get_calliration_number(&mut self) -> Result<u32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x45, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let res = self.read_response()?;
-        let data = res.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        self.run(commands::get_calliration_number(self.slave_adress)?)
     }
 
     /// Changes the calibration to the new calibration at the specified index. This command
     /// stops the controller by closing the valve. Additonly this is stored in presitent memory and
-    /// will remain after a device reset.
+    /// will remain after a device reset. A busy sensor is waited for up to a second unless
+    /// [Device::set_busy_retry] says otherwise. Only retried with
+    /// [RetryConfig::retry_non_idempotent].
     pub fn set_callibration(&mut self, calibration_index: u32) -> Result<(), DeviceError> {
-        let cal_bytes = calibration_index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_adress, 0x45, &cal_bytes)?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-
-        Ok(())
+        self.metadata = None;
+        let command = commands::set_callibration(self.slave_adress, calibration_index)?;
+        self.run_busy(command, CALIBRATION_BUSY)
     }
 
     /// Changes the calibration to the new calibration at the specified index. This command stops
     /// the controller by closing the valve. This will be stored in volatile memory and will not
-    /// presit after a device reset.
+    /// presit after a device reset. A busy sensor is waited for up to a second unless
+    /// [Device::set_busy_retry] says otherwise. Only retried with
+    /// [RetryConfig::retry_non_idempotent].
     pub fn set_callibration_volitile(&mut self, calibration_index: u32) -> Result<(), DeviceError> {
-        let cal_bytes = calibration_index.to_be_bytes();
-        let frame = MOSIFrame::new(self.slave_adress, 0x46, &cal_bytes)?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-        Ok(())
+        self.metadata = None;
+        let command = commands::set_callibration_volitile(self.slave_adress, calibration_index)?;
+        self.run_busy(command, CALIBRATION_BUSY)
     }
 
     /// Returns the slave adress of the SHDLC device
     pub fn get_slave_adress(&mut self) -> Result<u8, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x90, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.is_empty() {
-            Err(TranslationError::NotEnoughData(1, 0))?;
-        }
-
-        Ok(data[0])
+        self.run(commands::get_slave_adress(self.slave_adress)?)
     }
 
     /// Sets slave adress of the SHDLC device. The slave adress is stored in non-volatile memory
     /// and therefore will presist after a device reset. Next time the device is connected be sure
     /// to use the new address. Aditionally make sure there is only one device with this address on
     /// the bus. Otherwise there will be communication errors that can only be fixed by
-    /// disconnecting one of the devices.
+    /// disconnecting one of the devices. This command is only retried with
+    /// [RetryConfig::retry_non_idempotent].
     pub fn set_slave_adress(&mut self, new_adress: u8) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x90, &[new_adress])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
-
+        self.run(commands::set_slave_adress(self.slave_adress, new_adress)?)?;
         self.slave_adress = new_adress;
         Ok(())
     }
 
-    /// Gets the baudrate of the SHDLC device.
-    pub fn get_baudrate(&mut self) -> Result<u32, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x91, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-
-        let response = self.read_response()?;
-        let data = response.into_data();
-
-        if data.len() < 4 {
-            Err(TranslationError::NotEnoughData(4, data.len() as u8))?;
-        }
-
-        let res = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-
-        Ok(res)
+    /// Gets the baudrate of the SHDLC device, [Baudrate::Other] if it isn't a documented one.
+    pub fn get_baudrate(&mut self) -> Result<Baudrate, DeviceError> {
+        self.run(commands::get_baudrate(self.slave_adress)?)
     }
 
     /// Sets the buadrate of the device. The buadrate is stored in non-volatile memory
     /// and will presist after a device reset. The next time you connect to the device make
-    /// sure to use the new baudrate. Allowed buadrate values are `19200`, `38400`, `57600`,
-    /// and `115200`.
-    pub fn set_baudrate(&mut self, baudrate: u32) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0x91, &baudrate.to_be_bytes())?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
+    /// sure to use the new baudrate. The device refuses a [Baudrate::Other]. This command is
+    /// only retried with [RetryConfig::retry_non_idempotent]. On a [SharedBus] the line speed
+    /// changes for every device on the bus.
+    pub fn set_baudrate(&mut self, baudrate: Baudrate) -> Result<(), DeviceError> {
+        self.run(commands::set_baudrate(self.slave_adress, baudrate)?)?;
+        self.connection.set_baud_rate(baudrate.into())
+    }
 
-        self.port.set_baud_rate(baudrate)?;
+    /// [Device::set_baudrate] with a bare number, failing with
+    /// [DeviceError::UnsupportedBaudrate] without sending anything for a rate the device
+    /// doesn't support
+    #[deprecated(note = "use set_baudrate with a Baudrate")]
+    pub fn set_baudrate_u32(&mut self, baudrate: u32) -> Result<(), DeviceError> {
+        self.set_baudrate(Baudrate::try_from(baudrate)?)
+    }
 
-        Ok(())
+    /// [Device::get_baudrate] as a bare number
+    #[deprecated(note = "use get_baudrate, which returns a Baudrate")]
+    pub fn get_baudrate_u32(&mut self) -> Result<u32, DeviceError> {
+        self.get_baudrate().map(u32::from)
     }
 
     /// Gets the product type from the device
     pub fn get_product_type(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0xD0, &[0x00])?;
-        let _ = self.port.write(&frame.into_raw())?;
-
-        let response = self.read_response()?;
-        let string = match CString::from_vec_with_nul(response.into_data().to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-
-        Ok(string)
+        self.run(commands::get_product_type(self.slave_adress)?)
     }
 
     /// Gets the product name from the device
     pub fn get_product_name(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0xD0, &[0x01])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let response = self.read_response()?;
-        let string = match CString::from_vec_with_nul(response.into_data().to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-
-        Ok(string)
+        self.run(commands::get_product_name(self.slave_adress)?)
     }
 
     /// Gets the article code of the device. This information is also contained on the
     /// product label.
     pub fn get_article_code(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0xD0, &[0x02])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let response = self.read_response()?;
-        let string = match CString::from_vec_with_nul(response.into_data().to_vec()) {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-
-        Ok(string)
+        self.run(commands::get_article_code(self.slave_adress)?)
     }
 
     /// Gets the serial number of the SFC6xxx sensor as a hex String matching the 
     /// serial number printed on the device.
     pub fn get_serial_number(&mut self) -> Result<String, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0xD0, &[0x03])?;
-        let data = frame.into_raw();
-
-        let _ = self.port.write(&data)?;
-        let response = self.read_response()?;
-
-        let string = CString::from_vec_with_nul(response.into_data().to_vec());
-        let string = match string {
-            Ok(s) => match s.into_string() {
-                Ok(st) => st,
-                Err(_) => Err(DeviceError::InvalidString)?,
-            },
-            Err(_) => Err(DeviceError::InvalidString)?,
-        };
-
-        Ok(string)
+        self.run(commands::get_serial_number(self.slave_adress)?)
     }
 
     /// Gets the version information for the hardware, firmware, and SHDLC protocol.
     pub fn get_version(&mut self) -> Result<Version, DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0xD1, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let data = self.read_response()?.into_data();
-
-        if data.len() < 7 {
-            Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(
-                7,
-                data.len() as u8,
-            )))?;
-        }
-
-        Ok(Version {
-            firmware_major: data[0],
-            firmware_minor: data[1],
-            debug: data[2] > 0,
-            hardware_major: data[3],
-            hardware_minor: data[4],
-            protocol_major: data[5],
-            protocol_minor: data[6],
-        })
+        self.run(commands::get_version(self.slave_adress)?)
+    }
+
+    /// Returns what the firmware of the device supports, see [sfc_core::capabilities]. The
+    /// version is read with the first call and kept, later calls cost no command and neither do
+    /// the checks of the methods that depend on a [Feature]. It is read again after
+    /// [Device::reconnect].
+    pub fn capabilities(&mut self) -> Result<Capabilities, DeviceError> {
+        if let Some(capabilities) = self.capabilities {
+            return Ok(capabilities);
+        }
+        let version = self.get_version()?;
+        let capabilities = Capabilities::for_version(DeviceFamily::Sfc6xxx, &version);
+        Ok(*self.capabilities.insert(capabilities))
+    }
+
+    /// Fails with [DeviceError::UnsupportedFirmware] if the firmware doesn't support the feature
+    fn require(&mut self, feature: Feature) -> Result<(), DeviceError> {
+        self.capabilities()?.require(feature)
     }
 
     /// Resets the device which has the same effect as a power cycle. Please allow 300ms for the
-    /// device to power on
+    /// device to power on, commands it answers with a fatal error before then are sent again
+    /// with a [boot grace](Device::set_boot_grace). A busy sensor is waited for up to 500ms
+    /// unless [Device::set_busy_retry] says otherwise. This command is only retried with
+    /// [RetryConfig::retry_non_idempotent].
     pub fn reset_device(&mut self) -> Result<(), DeviceError> {
-        let frame = MOSIFrame::new(self.slave_adress, 0xD3, &[])?;
-        let _ = self.port.write(&frame.into_raw())?;
-        let _ = self.read_response()?;
+        self.metadata = None;
+        self.connection.mark_reset();
+        self.run_busy(commands::reset_device(self.slave_adress)?, RESET_BUSY)
+    }
 
-        Ok(())
+    /// Reads what the filter asks for and fails with [DeviceError::IdentityMismatch] if this is
+    /// not the expected device, so a wrong port or address doesn't command another unit. See
+    /// [identity](sfc_core::identity).
+    /// ```no_run
+    /// use sfc6xxx_rs::device::Device;
+    /// use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+    /// use sfc6xxx_rs::sfc_core::identity::IdentityFilter;
+    ///
+    /// let mut device = Device::open("/dev/ttyUSB0", Baudrate::B115200, 0).unwrap();
+    /// device.assert_identity(&IdentityFilter::serial_number("23170002")).unwrap();
+    /// device.set_setpoint(1.0).unwrap();
+    /// ```
+    pub fn assert_identity(&mut self, expected: &IdentityFilter) -> Result<(), DeviceError> {
+        expected.check(|field| match field {
+            IdentityField::SerialNumber => self.get_serial_number(),
+            IdentityField::ArticleCode => self.get_article_code(),
+            IdentityField::ProductType => self.get_product_type(),
+        })
     }
 
-    fn read_response(&mut self) -> Result<MISOFrame, DeviceError> {
-        let mut buff = [0_u8; 20];
-        let mut out = ArrayVec::<u8, 518>::new();
-        loop {
-            let s = self.port.read(&mut buff)?;
-            out.try_extend_from_slice(&buff[..s])?;
-            if buff[s - 1] == 0x7E && (s > 1 || out.len() > 1) {
-                break;
-            }
+    /// The profile to connect to this device again with [Device::connect_with_profile], with
+    /// the serial number and baudrate read from the device. The device doesn't know the name of
+    /// its port, the hint is kept as given.
+    pub fn connection_profile(
+        &mut self,
+        port_hint: Option<&str>,
+    ) -> Result<ConnectionProfile, DeviceError> {
+        discovery::profile_of(self, port_hint)
+    }
+
+    /// Checks that the device is ready for a run: it answers, its firmware is recent enough,
+    /// the active calibration is valid and no flow is measured while the setpoint is zero. See
+    /// [health](sfc_core::health) for the details. The SFC6xxx has no command to read latched
+    /// errors, that item is always skipped. Failing commands fail their item, the check itself
+    /// never fails.
+    pub fn health_check(&mut self, requirements: HealthRequirements) -> HealthReport {
+        let mut report = health::check(self, &requirements);
+        report.device_errors = CheckOutcome::Skipped("the SFC6xxx can't report latched errors");
+        report.calibration = match self
+            .get_calliration_number()
+            .and_then(|index| self.get_calibration_validity(index))
+        {
+            Ok(true) => CheckOutcome::Passed,
+            Ok(false) => CheckOutcome::Failed("the active calibration is not valid".to_string()),
+            Err(e) => e.into(),
+        };
+        report
+    }
+
+    fn run<R>(&mut self, command: Request<R>) -> Result<R, DeviceError> {
+        if command.command.writes() {
+            self.writable(command.command.name())?;
+        }
+        // decoded where it was received, without copying the response
+        let (frame, read) = command.split();
+        let decode = |response: MISOFrameRef<'_>| read(response.data());
+        self.connection.transact_and_read(frame, decode)
+    }
+
+    /// [Device::run] waiting for a busy sensor with `budget` if [Device::set_busy_retry] is off
+    fn run_busy<R>(&mut self, command: Request<R>, budget: BusyPolicy) -> Result<R, DeviceError> {
+        let policy = self.connection.busy_retry();
+        self.connection.set_busy_retry(policy.or(Some(budget)));
+        let result = self.run(command);
+        self.connection.set_busy_retry(policy);
+        result
+    }
+
+    /// Only for the commands that may be retried
+    fn run_with_deadline<R>(
+        &mut self,
+        command: Request<R>,
+        deadline: Instant,
+    ) -> Result<R, DeviceError> {
+        debug_assert_eq!(command.frame.idempotency(), Idempotency::Idempotent);
+        if command.command.writes() {
+            self.writable(command.command.name())?;
         }
+        let (frame, read) = command.split();
+        let decode = |response: MISOFrameRef<'_>| read(response.data());
+        self.connection.transact_with_deadline_and_read(frame, deadline, decode)
+    }
+}
 
-        let frame = MISOFrame::from_bytes(&out)?;
+/// Lets [discovery::connect_using] build the device at the candidates it tries
+impl<T: Transport> ProfiledDevice for Device<T> {
+    type Port = T;
 
-        if !frame.is_ok() {
-            Err(StateResponseError::from(frame.get_state()))?;
+    fn on_port(port: T, address: u8, timeout: Option<Duration>) -> Result<Self, DeviceError> {
+        let mut builder = Self::builder(port).address(address);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
         }
+        builder.build()
+    }
+
+    fn reset_timeouts(&mut self) {
+        self.set_timeouts(Timeouts::default());
+    }
+
+    fn address(&self) -> u8 {
+        self.slave_adress
+    }
+
+    fn read_baudrate(&mut self) -> Result<Baudrate, DeviceError> {
+        self.get_baudrate()
+    }
+}
+
+impl<T: Transport> FlowController for Device<T> {
+    fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError> {
+        Device::set_setpoint(self, setpoint)
+    }
+
+    fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
+        Device::get_setpoint(self)
+    }
+
+    fn read_measured_value(&mut self) -> Result<f32, DeviceError> {
+        Device::read_measured_value(self)
+    }
+
+    fn read_average_measured_value(&mut self, measurement_count: u8) -> Result<f32, DeviceError> {
+        Device::read_average_measured_value(self, measurement_count)
+    }
+
+    fn get_full_scale(&mut self) -> Result<f32, DeviceError> {
+        self.get_current_full_scale()
+    }
+
+    fn get_gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
+        self.get_current_gas_unit()
+    }
+
+    fn reset(&mut self) -> Result<(), DeviceError> {
+        self.reset_device()
+    }
+
+    fn get_version(&mut self) -> Result<Version, DeviceError> {
+        Device::get_version(self)
+    }
+
+    fn get_serial_number(&mut self) -> Result<String, DeviceError> {
+        Device::get_serial_number(self)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// Configures a [Device] before it is created, returned by [Device::builder]. Every setting
+/// starts out the way [Device::new] leaves it.
+#[derive(Debug)]
+pub struct DeviceBuilder<T: Transport> {
+    port: T,
+    address: u8,
+    timeouts: Timeouts,
+    inter_byte_timeout: Duration,
+    clear_stale_input: bool,
+    retry: Option<RetryConfig>,
+    boot_grace: Option<BootGrace>,
+    busy_retry: Option<BusyPolicy>,
+    rs485: Option<Rs485Config>,
+    spin: SpinPolicy,
+    validation: ValidationLevel,
+    baudrate: Option<Baudrate>,
+    probe: bool,
+    identity: IdentityFilter,
+    read_only: bool,
+}
+
+impl<T: Transport> DeviceBuilder<T> {
+    /// The slave address of the device, 0 by default
+    pub fn address(mut self, address: u8) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// How long the device has to start answering a command, see [Device::set_response_timeout]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts = Timeouts::uniform(timeout);
+        self
+    }
+
+    /// How long the device has to start answering each kind of command, see
+    /// [Device::set_timeouts]
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// The time allowed between two bytes of a response, see [Device::set_inter_byte_timeout]
+    pub fn inter_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.inter_byte_timeout = timeout;
+        self
+    }
+
+    /// Whether pending input is discarded before every command, see
+    /// [Device::set_clear_stale_input]
+    pub fn clear_stale_input(mut self, clear: bool) -> Self {
+        self.clear_stale_input = clear;
+        self
+    }
+
+    /// Retries failed commands, see [Device::set_retry]
+    pub fn retries(mut self, retry: RetryConfig) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// How the device is waited for while it boots after a reset, see [Device::set_boot_grace]
+    pub fn boot_grace(mut self, grace: Option<BootGrace>) -> Self {
+        self.boot_grace = grace;
+        self
+    }
+
+    /// Sends commands the device answers as busy again, see [Device::set_busy_retry]
+    pub fn busy_retry(mut self, policy: BusyPolicy) -> Self {
+        self.busy_retry = Some(policy);
+        self
+    }
+
+    /// Drives an RS-485 adapter with manual direction control, see [Device::set_rs485]
+    pub fn rs485(mut self, rs485: Rs485Config) -> Self {
+        self.rs485 = Some(rs485);
+        self
+    }
+
+    /// How a transport that returns empty reads is waited on, see [Device::set_spin_policy]
+    pub fn spin_policy(mut self, policy: SpinPolicy) -> Self {
+        self.spin = policy;
+        self
+    }
 
-        if !frame.validate_checksum() {
-            Err(DeviceError::InvalidChecksum(
-                frame.get_checksum(),
-                frame.calculate_check_sum(),
-            ))?;
+    /// How closely responses are checked, see [Device::set_validation_level]
+    pub fn validation_level(mut self, level: ValidationLevel) -> Self {
+        self.validation = level;
+        self
+    }
+
+    /// Switches the port to this line speed before the device is probed, for a device that was
+    /// set to another rate than the port was opened with. The port is left as it is by default.
+    pub fn baudrate(mut self, baudrate: Baudrate) -> Self {
+        self.baudrate = Some(baudrate);
+        self
+    }
+
+    /// Whether [Device::get_baudrate] is sent to check that the device answers before
+    /// [DeviceBuilder::build] returns. On by default.
+    pub fn probe(mut self, probe: bool) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Fails [DeviceBuilder::build] with [DeviceError::IdentityMismatch] unless the device is
+    /// the one the filter asks for, see [Device::assert_identity]. Any device by default.
+    pub fn identity(mut self, filter: IdentityFilter) -> Self {
+        self.identity = filter;
+        self
+    }
+
+    /// Whether commands that change the device are refused, see [Device::set_read_only]. Off by
+    /// default.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Creates the device with these settings, probes it if enabled and checks its identity
+    pub fn build(self) -> Result<Device<T>, DeviceError> {
+        let mut connection = Connection::new(self.port);
+        connection.set_timeouts(self.timeouts);
+        connection.set_inter_byte_timeout(self.inter_byte_timeout);
+        connection.set_clear_stale_input(self.clear_stale_input);
+        connection.set_retry(self.retry);
+        connection.set_boot_grace(self.boot_grace);
+        connection.set_busy_retry(self.busy_retry);
+        connection.set_rs485(self.rs485);
+        connection.set_spin_policy(self.spin);
+        connection.set_validation_level(self.validation);
+        if let Some(baudrate) = self.baudrate {
+            connection.set_baud_rate(baudrate.into())?;
         }
+        let mut device = Device {
+            connection,
+            slave_adress: self.address,
+            metadata: None,
+            capabilities: None,
+            soft_limits: SoftLimits::default(),
+            read_only: self.read_only,
+        };
+        if self.probe {
+            let _ = device.get_baudrate()?;
+        }
+        device.assert_identity(&self.identity)?;
+        Ok(device)
+    }
+}
+
+impl Device<NativePort> {
+    /// Opens the serial port with the given name with the settings SHDLC needs (see
+    /// [open_port](sfc_core::discovery::open_port)) and probes the device like [Device::new].
+    /// ```no_run
+    /// use sfc6xxx_rs::device::Device;
+    /// use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+    ///
+    /// let device = Device::open("/dev/ttyUSB0", Baudrate::B115200, 0).unwrap();
+    /// ```
+    pub fn open(
+        port_name: &str,
+        baudrate: Baudrate,
+        slave_adress: u8,
+    ) -> Result<Self, DeviceError> {
+        Self::new(open_port(port_name, baudrate.into())?, slave_adress)
+    }
+
+    /// [Device::open] with a bare number, failing with [DeviceError::UnsupportedBaudrate] for a
+    /// rate the device doesn't support
+    #[deprecated(note = "use open with a Baudrate")]
+    pub fn open_u32(
+        port_name: &str,
+        baud_rate: u32,
+        slave_adress: u8,
+    ) -> Result<Self, DeviceError> {
+        Self::open(port_name, Baudrate::try_from(baud_rate)?, slave_adress)
+    }
 
-        Ok(frame)
+    /// Opens the first Sensirion cable or USB serial bridge found by
+    /// [find_sensirion_ports](sfc_core::discovery::find_sensirion_ports) at 115200 baud.
+    /// ```no_run
+    /// use sfc6xxx_rs::device::Device;
+    /// let device = Device::open_first_detected(0).unwrap();
+    /// ```
+    pub fn open_first_detected(slave_adress: u8) -> Result<Self, DeviceError> {
+        let (port, _) = open_first_detected(115200)?;
+        Self::new(port, slave_adress)
+    }
+
+    /// Opens the port of a profile at its baudrate and checks that the device at its address
+    /// has its serial number. Another device or none at all is looked for as `fallback` says.
+    /// Returns the device with the profile it was found with, which is the given one unless it
+    /// had to be looked for. See [discovery](sfc_core::discovery).
+    /// ```no_run
+    /// use sfc6xxx_rs::device::Device;
+    /// use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+    /// use sfc6xxx_rs::sfc_core::discovery::{ConnectionProfile, DiscoveryPolicy};
+    ///
+    /// let mut device = Device::open("/dev/ttyUSB0", Baudrate::B115200, 0).unwrap();
+    /// let saved = device.connection_profile(Some("/dev/ttyUSB0")).unwrap();
+    /// drop(device);
+    /// let fallback = DiscoveryPolicy::scan();
+    /// let (device, profile) = Device::connect_with_profile(&saved, &fallback).unwrap();
+    /// ```
+    pub fn connect_with_profile(
+        profile: &ConnectionProfile,
+        fallback: &DiscoveryPolicy,
+    ) -> Result<(Self, ConnectionProfile), DeviceError> {
+        Self::connect_with_profile_using(profile, fallback, open_port)
+    }
+
+    /// Opens the serial port with the given name again and [reconnects](Device::reconnect) to
+    /// it, using the baudrate last set on the port or 115200 if it was never changed.
+    pub fn reopen(&mut self, port_name: &str) -> Result<(), DeviceError> {
+        let baud_rate = self.connection.baud_rate().unwrap_or(115200);
+        self.reconnect(open_port(port_name, baud_rate)?)
     }
 }
 
@@ -648,6 +1150,8 @@ impl<T: SerialPort> Device<T> {
 mod tests {
     use approx::assert_relative_eq;
     use serial_test::serial;
+    use sfc_core::error::StateResponseError;
+    use sfc_core::gasunit::{Prefixes, TimeBases, Units};
 
     #[cfg(target_os = "windows")]
     use serialport::COMPort;
@@ -667,8 +1171,30 @@ mod tests {
     type SP = COMPort;
 
     fn create_device() -> Device<SP> {
-        let test_port = serialport::new(PORT, 115200).open_native().unwrap();
-        Device::new(test_port, 0).unwrap()
+        Device::open(PORT, Baudrate::B115200, 0).unwrap()
+    }
+
+    #[test]
+    fn open_missing_port() {
+        let result = Device::open("/dev/does-not-exist", Baudrate::B115200, 0);
+        assert!(matches!(result, Err(DeviceError::PortError(_))));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn open_u32_shim() {
+        let result = Device::open_u32("/dev/does-not-exist", 9600, 0);
+        assert!(matches!(result, Err(DeviceError::UnsupportedBaudrate(9600))));
+        let result = Device::open_u32("/dev/does-not-exist", 115200, 0);
+        assert!(matches!(result, Err(DeviceError::PortError(_))));
+    }
+
+    #[test]
+    #[serial]
+    #[ignore = "needs a device on a USB cable"]
+    fn open_first_detected_device() {
+        let mut device = Device::open_first_detected(0).unwrap();
+        let _ = device.get_baudrate().unwrap();
     }
 
     #[test]
@@ -708,31 +1234,31 @@ mod tests {
     fn get_baudrate() {
         let mut device = create_device();
         let br = device.get_baudrate().unwrap();
-        assert_eq!(br, 115200);
+        assert_eq!(br, Baudrate::B115200);
     }
 
     #[test]
     #[serial]
     fn set_baudrate() {
         let mut device = create_device();
-        device.set_baudrate(115200).unwrap();
+        device.set_baudrate(Baudrate::B115200).unwrap();
     }
 
     #[test]
     #[serial]
     fn set_and_read_buadrate() {
         let mut device = create_device();
-        device.set_baudrate(57600).unwrap();
+        device.set_baudrate(Baudrate::B57600).unwrap();
         let br = device.get_baudrate().unwrap();
-        device.set_baudrate(115200).unwrap();
-        assert_eq!(br, 57600);
+        device.set_baudrate(Baudrate::B115200).unwrap();
+        assert_eq!(br, Baudrate::B57600);
     }
 
     #[test]
     #[serial]
     fn set_invalid_buadrate() {
         let mut device = create_device();
-        let res = device.set_baudrate(57601);
+        let res = device.set_baudrate(Baudrate::Other(57601));
         match res {
             Err(DeviceError::StateResponse(StateResponseError::ParameterError)) => {}
             _ => panic!("expected, StateResponseError::ParameterError"),
@@ -915,4 +1441,1000 @@ mod tests {
         let v = device.get_version().unwrap();
         println!("{:?}", v);
     }
+
+    /// The tests of a device on a cable above, against a port that answers the frames they send
+    mod mocked {
+        use super::*;
+        use sfc_core::middleware::CommandBlacklist;
+        use sfc_core::mock::{Expectation, MockHandle, MockPort};
+
+        fn mocked_device() -> (Device<MockPort>, MockHandle) {
+            let port = MockPort::new();
+            let handle = port.handle();
+            (Device::builder(port).address(0).probe(false).build().unwrap(), handle)
+        }
+
+        /// The setpoint command with the value
+        fn setpoint(value: f32) -> Expectation {
+            let [a, b, c, d] = value.to_be_bytes();
+            Expectation::new(0, 0x00, &[0x01, a, b, c, d])
+        }
+
+        #[test]
+        fn get_baudrate() {
+            let (mut device, handle) = mocked_device();
+            handle.expect(Expectation::new(0, 0x91, &[]).respond(&115200u32.to_be_bytes()));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+            handle.assert_done();
+        }
+
+        #[test]
+        fn set_and_read_baudrate() {
+            let (mut device, handle) = mocked_device();
+            let b57600 = 57600u32.to_be_bytes();
+            handle.expect(Expectation::new(0, 0x91, &b57600).respond(&[]));
+            handle.expect(Expectation::new(0, 0x91, &[]).respond(&b57600));
+            device.set_baudrate(Baudrate::B57600).unwrap();
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B57600);
+            handle.assert_done();
+        }
+
+        #[test]
+        fn set_invalid_baudrate() {
+            let (mut device, handle) = mocked_device();
+            let data = 57601u32.to_be_bytes();
+            handle.expect(Expectation::new(0, 0x91, &data).respond_with_state(0x04, &[]));
+            match device.set_baudrate(Baudrate::Other(57601)) {
+                Err(DeviceError::StateResponse(StateResponseError::ParameterError)) => {}
+                other => panic!("expected a parameter error, got {:?}", other),
+            }
+            handle.assert_done();
+        }
+
+        #[test]
+        fn set_get_set_setpoint() {
+            let (mut device, handle) = mocked_device();
+            handle.expect(setpoint(2.0).respond(&[]));
+            handle.expect(Expectation::new(0, 0x00, &[0x01]).respond(&2.0f32.to_be_bytes()));
+            handle.expect(setpoint(0.0).respond(&[]));
+            device.set_setpoint(2.0).unwrap();
+            assert_eq!(device.get_setpoint().unwrap(), 2.0);
+            device.set_setpoint(0.0).unwrap();
+            handle.assert_done();
+        }
+
+        #[test]
+        fn setpoints_with_special_bytes_are_stuffed() {
+            let (mut device, handle) = mocked_device();
+            // 0x7E is the frame delimiter and 0x7D the escape byte
+            let value = f32::from_be_bytes([0x3F, 0x7E, 0x7D, 0x11]);
+            assert!(setpoint(value).request().len() > 11);
+            handle.expect(setpoint(value).respond(&[]));
+            handle.expect(Expectation::new(0, 0x00, &[0x01]).respond(&value.to_be_bytes()));
+            device.set_setpoint(value).unwrap();
+            assert_eq!(device.get_setpoint().unwrap(), value);
+            handle.assert_done();
+        }
+
+        #[test]
+        fn a_corrupted_setpoint_fails() {
+            let (mut device, handle) = mocked_device();
+            let response = Expectation::new(0, 0x00, &[0x01]).respond(&2.0f32.to_be_bytes());
+            handle.expect(response.corrupt_checksum());
+            assert!(matches!(device.get_setpoint(), Err(DeviceError::InvalidChecksum(..))));
+        }
+
+        #[test]
+        fn default_calibration_gas_unit() {
+            let (mut device, handle) = mocked_device();
+            // base prefix, standard liter per minute, read a byte at a time
+            let request = Expectation::new(0, 0x40, &[0x13, 0, 0, 0, 0]);
+            handle.expect(request.respond(&[0, 1, 4]).in_chunks(1));
+            let unit = device.get_calibration_gas_unit(0).unwrap();
+            let expected = GasUnit {
+                unit_prefex: Prefixes::Base,
+                timebase: TimeBases::Minute,
+                medium_unit: Units::StandardLiter,
+            };
+            assert_eq!(unit, expected);
+            assert_eq!(handle.reads(), 10);
+            handle.assert_done();
+        }
+
+        #[test]
+        fn current_gas_unit() {
+            let (mut device, handle) = mocked_device();
+            // milligram per second
+            let milli = (-3i8).to_be_bytes()[0];
+            handle.expect(Expectation::new(0, 0x44, &[0x13]).respond(&[milli, 9, 3]));
+            let unit = device.get_current_gas_unit().unwrap();
+            assert_eq!(unit.unit_prefex, Prefixes::Milli);
+            assert_eq!(unit.medium_unit, Units::Gram);
+            assert_eq!(unit.timebase, TimeBases::Second);
+            handle.assert_done();
+        }
+
+        #[test]
+        fn a_blacklisted_command_is_never_sent() {
+            let (mut device, handle) = mocked_device();
+            device.set_middleware(Box::new(CommandBlacklist::new([0x91])));
+            handle.expect(setpoint(2.0).respond(&[]));
+            match device.set_baudrate(Baudrate::B57600) {
+                Err(DeviceError::BlockedByMiddleware(0x91, reason)) => {
+                    assert!(reason.contains("blacklisted"), "{}", reason)
+                }
+                other => panic!("expected the baudrate change to be blocked, got {:?}", other),
+            }
+            // other commands pass through
+            device.set_setpoint(2.0).unwrap();
+            assert_eq!(handle.sent(), [setpoint(2.0).request()]);
+            handle.assert_done();
+
+            assert!(device.take_middleware().is_some());
+            handle.expect(Expectation::new(0, 0x91, &[]).respond(&57600u32.to_be_bytes()));
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B57600);
+            handle.assert_done();
+        }
+    }
+
+    /// Tests that run against the emulator and need no hardware
+    mod emulated {
+        use super::*;
+        use sfc_core::connection::DEFAULT_RESPONSE_TIMEOUT;
+        use sfc_core::shdlc::TranslationError;
+        use crate::emulator::{EmulatorConfig, EmulatorHandle, Fault, Sfc6xxxEmulator};
+
+        fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+            let emulator = Sfc6xxxEmulator::default();
+            let handle = emulator.handle();
+            (Device::new(emulator, 0).unwrap(), handle)
+        }
+
+        /// A port that fails the test as soon as anything is read or written
+        struct Untouchable;
+
+        impl std::io::Read for Untouchable {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                panic!("read from the port")
+            }
+        }
+
+        impl std::io::Write for Untouchable {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                panic!("wrote to the port")
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                panic!("flushed the port")
+            }
+        }
+
+        impl Transport for Untouchable {
+            fn timeout(&self) -> Duration {
+                Duration::ZERO
+            }
+
+            fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+                panic!("set the timeout of the port")
+            }
+        }
+
+        #[test]
+        fn debug_formatting_never_touches_the_port() {
+            let device = Device::builder(Untouchable)
+                .address(3)
+                .baudrate(Baudrate::B19200)
+                .probe(false)
+                .build()
+                .unwrap();
+            let debug = format!("{:?}", device);
+            let start = "Device { slave_adress: 3, connection: Connection {";
+            assert!(debug.starts_with(start), "{}", debug);
+            assert!(debug.contains("baud_rate: Some(19200)"), "{}", debug);
+            assert!(debug.contains("commands: 0"), "{}", debug);
+            assert!(debug.contains("metadata_cached: false"), "{}", debug);
+        }
+
+        /// A change of the device and the name it is refused with
+        type Change = (&'static str, fn(&mut Device<Untouchable>) -> Result<(), DeviceError>);
+
+        #[test]
+        fn read_only_refuses_every_change_before_the_port() {
+            use crate::autotune::AutotuneConfig;
+            use crate::calibration::SwitchMode;
+            use crate::leak_test::LeakTestConfig;
+            use sfc_core::config::DeviceConfig;
+            use sfc_core::profile::Profile;
+
+            let mut device = Device::builder(Untouchable)
+                .probe(false)
+                .read_only(true)
+                .build()
+                .unwrap();
+            let changes: &[Change] = &[
+                ("setpoint", |device| device.set_setpoint(1.0)),
+                ("setpoint", |device| {
+                    let deadline = Instant::now() + Duration::from_secs(1);
+                    device.set_setpoint_with_deadline(1.0, deadline)
+                }),
+                ("set setpoint and read measured value", |device| {
+                    device.set_setpoint_and_read_measured_value(1.0).map(drop)
+                }),
+                ("controller gain", |device| device.set_controller_gain(2.0)),
+                ("initial step", |device| device.set_initial_step(0.5)),
+                ("calibration", |device| device.set_callibration(1)),
+                ("volatile calibration", |device| device.set_callibration_volitile(1)),
+                ("slave address", |device| device.set_slave_adress(4)),
+                ("baudrate", |device| device.set_baudrate(Baudrate::B19200)),
+                ("device reset", |device| device.reset_device()),
+                ("raw command", |device| device.start_command(0x00, &[0x01]).map(drop)),
+                ("select calibration for gas", |device| {
+                    device.select_calibration_for_gas(2, SwitchMode::persistent()).map(drop)
+                }),
+                ("apply config", |device| {
+                    let config = DeviceConfig {
+                        address: Some(4),
+                        ..DeviceConfig::default()
+                    };
+                    device.apply_config(&config, false).map(drop)
+                }),
+                ("leak test", |device| {
+                    device.leak_test(LeakTestConfig::new(Duration::from_secs(1), 0.1)).map(drop)
+                }),
+                ("exercise valve", |device| {
+                    device.exercise_valve(1, 1.0, Duration::from_millis(10)).map(drop)
+                }),
+                ("autotune gain", |device| {
+                    device.autotune_gain(AutotuneConfig::new(0.5, 1.0)).map(drop)
+                }),
+                ("run profile", |device| {
+                    let profile = Profile::new().ramp(1.0, Duration::from_secs(1));
+                    device.run_profile(&profile, Duration::from_millis(10)).map(drop)
+                }),
+            ];
+            for (name, change) in changes {
+                match change(&mut device) {
+                    Err(DeviceError::ReadOnly(refused)) => assert_eq!(refused, *name),
+                    other => panic!("{} was not refused: {:?}", name, other),
+                }
+            }
+            for (command, ..) in commands::tests::catalog() {
+                if command.writes() {
+                    match device.pipeline(&[Command::GetSetpoint, command]) {
+                        Err(DeviceError::ReadOnly(refused)) => assert_eq!(refused, command.name()),
+                        other => panic!("{} was not refused: {:?}", command.name(), other),
+                    }
+                }
+            }
+            assert_eq!(device.stats().commands, 0);
+        }
+
+        #[test]
+        fn capabilities_are_read_once() {
+            let (mut device, handle) = emulated_device();
+            let before = handle.requests().len();
+            let capabilities = device.capabilities().unwrap();
+            assert_eq!(capabilities.firmware, (1, 0));
+            assert_eq!(capabilities.max_averaging_count, 100);
+            assert_eq!(device.capabilities().unwrap(), capabilities);
+            device.read_average_measured_value(100).unwrap();
+            assert_eq!(handle.requests().len(), before + 2);
+
+            // too many measurements are refused on the host
+            match device.read_average_measured_value(101) {
+                Err(DeviceError::UnsupportedFirmware { feature, firmware }) => {
+                    assert_eq!(feature, Feature::Averaging(101));
+                    assert_eq!(firmware, (1, 0));
+                }
+                other => panic!("expected unsupported firmware, got {:?}", other),
+            }
+            assert_eq!(handle.requests().len(), before + 2);
+        }
+
+        #[test]
+        fn firmware_without_averaging_fails_fast() {
+            let mut config = EmulatorConfig::default();
+            config.version.firmware_major = 0;
+            let emulator = Sfc6xxxEmulator::new(config);
+            let handle = emulator.handle();
+            let mut device = Device::new(emulator, 0).unwrap();
+            assert!(matches!(
+                device.read_average_measured_value(10),
+                Err(DeviceError::UnsupportedFirmware { firmware: (0, 0), .. })
+            ));
+            assert!(handle.requests().iter().all(|&(_, command, _)| command != 0x08));
+            // the other readings don't depend on the firmware
+            device.read_measured_value().unwrap();
+        }
+
+        fn booting_device(boot_errors: Duration) -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+            let emulator = Sfc6xxxEmulator::new(EmulatorConfig {
+                boot_errors,
+                ..Default::default()
+            });
+            let handle = emulator.handle();
+            (Device::new(emulator, 0).unwrap(), handle)
+        }
+
+        #[test]
+        fn fatal_errors_while_booting_are_waited_out() {
+            let (mut device, handle) = booting_device(Duration::from_millis(100));
+            device.set_boot_grace(Some(BootGrace::default()));
+            assert_eq!(device.since_reset(), None);
+            device.set_setpoint(2.5).unwrap();
+
+            device.reset_device().unwrap();
+            let start = Instant::now();
+            assert!(device.since_reset().unwrap() < BootGrace::default().window);
+            assert_eq!(device.get_setpoint().unwrap(), 0.0);
+            assert!(start.elapsed() >= Duration::from_millis(80));
+            assert!(device.stats().retries > 0);
+            assert_eq!(handle.resets(), 1);
+        }
+
+        #[test]
+        fn fatal_errors_past_the_boot_grace_fail() {
+            let (mut device, _handle) = booting_device(Duration::from_millis(500));
+            device.set_boot_grace(Some(BootGrace {
+                window: Duration::from_millis(100),
+                ..BootGrace::default()
+            }));
+            device.reset_device().unwrap();
+            let start = Instant::now();
+            assert!(matches!(
+                device.get_setpoint(),
+                Err(DeviceError::StateResponse(StateResponseError::FatalError))
+            ));
+            // sent again until the window closed, not until the device booted
+            assert!(start.elapsed() >= Duration::from_millis(80));
+            assert!(start.elapsed() < Duration::from_millis(400));
+
+            // without the grace the first fatal error fails the command
+            let (mut device, handle) = booting_device(Duration::from_millis(500));
+            device.set_boot_grace(None);
+            device.reset_device().unwrap();
+            let before = handle.requests().len();
+            assert!(device.get_setpoint().is_err());
+            assert_eq!(handle.requests().len(), before + 1);
+        }
+
+        #[test]
+        fn fatal_errors_in_steady_state_stay_fatal() {
+            let (mut device, handle) = emulated_device();
+            device.set_boot_grace(Some(BootGrace::default()));
+            handle.inject_fault(Fault::ErrorState(0x7F));
+            let before = handle.requests().len();
+            assert!(matches!(
+                device.get_setpoint(),
+                Err(DeviceError::StateResponse(StateResponseError::FatalError))
+            ));
+            assert_eq!(handle.requests().len(), before + 1);
+            assert_eq!(device.stats().retries, 0);
+
+            // nor are other error states retried within the window
+            device.reset_device().unwrap();
+            handle.inject_fault(Fault::ErrorState(0x04));
+            let before = handle.requests().len();
+            assert!(device.get_setpoint().is_err());
+            assert_eq!(handle.requests().len(), before + 1);
+        }
+
+        #[test]
+        fn busy_sensor_is_polled_until_it_answers() {
+            let (mut device, handle) = emulated_device();
+            device.set_busy_retry(Some(BusyPolicy::new(
+                Duration::from_millis(500),
+                Duration::from_millis(5),
+            )));
+            device.set_setpoint(2.5).unwrap();
+            for _ in 0..3 {
+                handle.inject_fault(Fault::ErrorState(0x42));
+            }
+            let before = handle.requests().len();
+            assert_eq!(device.get_setpoint().unwrap(), 2.5);
+            assert_eq!(handle.requests().len(), before + 4);
+            assert_eq!(device.stats().retries, 3);
+        }
+
+        #[test]
+        fn sensor_that_stays_busy_fails_with_the_time_waited() {
+            let (mut device, handle) = emulated_device();
+            handle.set_busy(true);
+            let before = handle.requests().len();
+            assert!(matches!(
+                device.get_setpoint(),
+                Err(DeviceError::StateResponse(StateResponseError::SensorBusy))
+            ));
+            assert_eq!(handle.requests().len(), before + 1);
+
+            device.set_busy_retry(Some(BusyPolicy::new(
+                Duration::from_millis(50),
+                Duration::from_millis(10),
+            )));
+            match device.get_setpoint() {
+                Err(DeviceError::StillBusy(waited)) => {
+                    assert!(waited >= Duration::from_millis(50));
+                    assert!(waited < Duration::from_millis(200));
+                }
+                other => panic!("expected still busy, got {:?}", other),
+            }
+            let error = device.get_setpoint().unwrap_err();
+            assert_eq!(error.code(), 0x0384);
+            assert!(error.to_string().starts_with("the sensor was still busy after waiting"));
+        }
+
+        #[test]
+        fn resets_and_calibration_switches_wait_with_their_own_budget() {
+            let (mut device, handle) = emulated_device();
+            assert_eq!(device.busy_retry(), None);
+            for _ in 0..3 {
+                handle.inject_fault(Fault::ErrorState(0x42));
+            }
+            device.set_callibration_volitile(1).unwrap();
+            assert_eq!(handle.active_calibration(), 1);
+
+            handle.set_busy(true);
+            let start = Instant::now();
+            assert!(matches!(device.reset_device(), Err(DeviceError::StillBusy(_))));
+            assert!(start.elapsed() >= Duration::from_millis(500));
+            assert_eq!(handle.resets(), 0);
+            // the budget of the reset was only for the reset
+            assert_eq!(device.busy_retry(), None);
+            assert!(matches!(
+                device.get_setpoint(),
+                Err(DeviceError::StateResponse(StateResponseError::SensorBusy))
+            ));
+        }
+
+        #[test]
+        fn read_only_still_reads() {
+            let emulator = Sfc6xxxEmulator::default();
+            let handle = emulator.handle();
+            let mut device = Device::builder(emulator).read_only(true).build().unwrap();
+            assert!(device.read_only());
+            assert_eq!(device.get_serial_number().unwrap(), "EMU6000001");
+            device.read_measured_value().unwrap();
+            device.get_setpoint().unwrap();
+            let config = sfc_core::config::DeviceConfig {
+                address: Some(4),
+                ..Default::default()
+            };
+            assert_eq!(device.apply_config(&config, true).unwrap().changed.len(), 1);
+            assert!(matches!(device.set_setpoint(2.0), Err(DeviceError::ReadOnly(_))));
+            assert_eq!(handle.setpoint(), 0.0);
+            assert_eq!(handle.address(), 0);
+
+            device.set_read_only(false);
+            device.set_setpoint(2.0).unwrap();
+            assert_eq!(handle.setpoint(), 2.0);
+        }
+
+        /// Every command is answered with the length it declares, a response short of it fails
+        /// with the same error before it is decoded
+        #[test]
+        fn responses_have_the_declared_length() {
+            for (command, ..) in commands::tests::catalog() {
+                let request = || Request::new(0, command, |data| Ok(data.len())).unwrap();
+                let (mut device, _) = emulated_device();
+                let length = device.run(request()).unwrap();
+                assert!(command.response_length().check(&vec![0; length]).is_ok());
+
+                let Some(short) = command.response_length().min().checked_sub(1) else {
+                    continue;
+                };
+                let (mut device, handle) = emulated_device();
+                handle.inject_fault(Fault::ShortData(short.into()));
+                assert!(
+                    matches!(
+                        device.run(request()),
+                        Err(DeviceError::ShdlcError(TranslationError::NotEnoughData(min, found)))
+                            if min == short + 1 && found == short
+                    ),
+                    "{}",
+                    command.name()
+                );
+            }
+        }
+
+        /// The unit, serial number and full scale are read with the first recorded value only,
+        /// and again after the calibration changed
+        #[test]
+        fn recorded_values_read_the_metadata_once() {
+            let (mut device, handle) = emulated_device();
+            device.set_setpoint(2.0).unwrap();
+            device.capabilities().unwrap();
+            let before = handle.requests().len();
+            let first = device.read_measured_value_recorded().unwrap();
+            assert_eq!(handle.requests().len(), before + 4);
+            let second = device.read_measured_value_recorded().unwrap();
+            let average = device.read_average_measured_value_recorded(5).unwrap();
+            assert_eq!(handle.requests().len(), before + 6);
+
+            assert_eq!(first.serial_number, Some(device.get_serial_number().unwrap()));
+            assert_eq!(second.address, Some(0));
+            assert_eq!(second.full_scale, Some(5.0));
+            assert_eq!(average.unit, device.get_current_gas_unit().unwrap());
+            assert!(first < second && second < average);
+
+            device.set_callibration_volitile(2).unwrap();
+            let before = handle.requests().len();
+            let recalibrated = device.read_measured_value_recorded().unwrap();
+            assert_eq!(handle.requests().len(), before + 4);
+            assert_eq!(recalibrated.full_scale, Some(2.5));
+            assert_eq!(recalibrated.unit, device.get_calibration_gas_unit(2).unwrap());
+        }
+
+        #[test]
+        fn builder_applies_every_setting() {
+            let emulator = Sfc6xxxEmulator::new(EmulatorConfig {
+                address: 3,
+                ..Default::default()
+            });
+            let handle = emulator.handle();
+            let rs485 = Rs485Config {
+                rts_on_send: true,
+                turnaround: Duration::from_millis(1),
+            };
+            let spin = SpinPolicy {
+                spins: 16,
+                sleep: Some(Duration::from_millis(1)),
+            };
+            let mut device = Device::builder(emulator)
+                .address(3)
+                .timeout(Duration::from_millis(30))
+                .inter_byte_timeout(Duration::from_millis(5))
+                .clear_stale_input(false)
+                .retries(RetryConfig::default())
+                .boot_grace(None)
+                .busy_retry(BusyPolicy::new(
+                    Duration::from_millis(50),
+                    Duration::from_millis(5),
+                ))
+                .rs485(rs485)
+                .spin_policy(spin)
+                .validation_level(ValidationLevel::Strict)
+                .build()
+                .unwrap();
+            assert_eq!(handle.requests().len(), 1);
+            assert_eq!(device.validation_level(), ValidationLevel::Strict);
+            assert_eq!(device.boot_grace(), None);
+            assert_eq!(
+                device.busy_retry().map(|policy| policy.max_wait),
+                Some(Duration::from_millis(50))
+            );
+            assert_eq!(device.inter_byte_timeout(), Duration::from_millis(5));
+            assert!(!device.clear_stale_input());
+            assert_eq!(device.rs485(), Some(rs485));
+            assert_eq!(device.spin_policy(), spin);
+
+            // retried past the corrupted response
+            handle.inject_fault(Fault::CorruptChecksum);
+            device.set_setpoint(2.5).unwrap();
+            assert_eq!(handle.setpoint(), 2.5);
+
+            for _ in 0..3 {
+                handle.inject_fault(Fault::DropResponse);
+            }
+            let start = Instant::now();
+            assert!(device.get_setpoint().is_err());
+            // three attempts, each waiting the 30ms timeout instead of the default 600ms
+            assert!(start.elapsed() < Duration::from_millis(300));
+        }
+
+        #[test]
+        fn each_kind_of_command_has_its_own_timeout() {
+            let emulator = Sfc6xxxEmulator::default();
+            let handle = emulator.handle();
+            let timeouts = Timeouts {
+                query: Duration::from_millis(30),
+                measurement: Duration::from_millis(300),
+                averaged_measurement: Duration::from_millis(30),
+                per_averaged_value: Duration::from_millis(5),
+                reset: Duration::from_millis(300),
+            };
+            let mut device = Device::builder(emulator).timeouts(timeouts).build().unwrap();
+            device.set_retry(None);
+            device.capabilities().unwrap();
+            assert_eq!(device.timeouts(), timeouts);
+
+            // a measurement gets longer than a query
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(device.read_measured_value().is_ok());
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(matches!(device.get_setpoint(), Err(DeviceError::Timeout)));
+            std::thread::sleep(Duration::from_millis(80));
+
+            // an average gets more time the more values it takes, 30ms + 20 * 5ms
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(device.read_average_measured_value(20).is_ok());
+            handle.inject_fault(Fault::DelayMs(80));
+            assert!(matches!(
+                device.read_average_measured_value(2),
+                Err(DeviceError::Timeout)
+            ));
+            std::thread::sleep(Duration::from_millis(80));
+
+            handle.inject_fault(Fault::DelayMs(80));
+            device.set_callibration_volitile(2).unwrap();
+            handle.inject_fault(Fault::DelayMs(80));
+            device.reset_device().unwrap();
+            assert_eq!(handle.resets(), 1);
+        }
+
+        #[test]
+        fn builder_skips_the_probe() {
+            let emulator = Sfc6xxxEmulator::default();
+            let handle = emulator.handle();
+            let device = Device::builder(emulator).probe(false).build().unwrap();
+            assert!(handle.requests().is_empty());
+            assert_eq!(device.response_timeout(), DEFAULT_RESPONSE_TIMEOUT);
+            assert_eq!(device.timeouts(), Timeouts::default());
+            assert_eq!(device.retry(), None);
+        }
+
+        #[test]
+        fn builder_switches_the_port_baudrate() {
+            let emulator = Sfc6xxxEmulator::new(EmulatorConfig {
+                baudrate: 38400,
+                ..Default::default()
+            });
+            let handle = emulator.handle();
+            let mut device = Device::builder(emulator)
+                .baudrate(Baudrate::B38400)
+                .build()
+                .unwrap();
+            assert_eq!(handle.line_baudrate(), 38400);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B38400);
+        }
+
+        #[test]
+        fn identity_of_the_emulator() {
+            let (mut device, _) = emulated_device();
+            let filter = IdentityFilter {
+                serial_number: Some("EMU6000001".to_string()),
+                article_code_prefix: Some("3.000.".to_string()),
+                product_type: Some("SFC6000".to_string()),
+            };
+            device.assert_identity(&filter).unwrap();
+
+            let filter = IdentityFilter::serial_number("EMU6000002");
+            match device.assert_identity(&filter) {
+                Err(DeviceError::IdentityMismatch {
+                    field,
+                    expected,
+                    found,
+                }) => {
+                    assert_eq!(field, IdentityField::SerialNumber);
+                    assert_eq!(expected, "EMU6000002");
+                    assert_eq!(found, "EMU6000001");
+                }
+                other => panic!("expected an identity mismatch, got {:?}", other),
+            }
+        }
+
+        #[test]
+        fn builder_checks_the_identity() {
+            let device = Device::builder(Sfc6xxxEmulator::default())
+                .identity(IdentityFilter::serial_number("EMU6000001"))
+                .build();
+            assert!(device.is_ok());
+
+            let emulator = Sfc6xxxEmulator::default();
+            let handle = emulator.handle();
+            let result = Device::builder(emulator)
+                .probe(false)
+                .identity(IdentityFilter::serial_number("EMU6000002"))
+                .build();
+            assert!(matches!(result, Err(DeviceError::IdentityMismatch { .. })));
+            // only the serial number was read
+            assert_eq!(handle.requests().len(), 1);
+        }
+
+        /// Opens the emulators by the names of their ports, other ports don't exist
+        fn ports(
+            ports: Vec<(&'static str, EmulatorHandle)>,
+        ) -> impl FnMut(&str, u32) -> Result<Sfc6xxxEmulator, DeviceError> {
+            move |name, _| match ports.iter().find(|(port, _)| *port == name) {
+                Some((_, handle)) => Ok(handle.replug()),
+                None => Err(serialport::Error::new(serialport::ErrorKind::NoDevice, name).into()),
+            }
+        }
+
+        #[test]
+        fn a_profile_connects_to_the_same_device_again() {
+            let (mut device, handle) = emulated_device();
+            let profile = device.connection_profile(Some("/dev/ttyUSB0")).unwrap();
+            assert_eq!(
+                profile,
+                ConnectionProfile {
+                    port_hint: Some("/dev/ttyUSB0".to_string()),
+                    serial: "EMU6000001".to_string(),
+                    address: 0,
+                    baudrate: Baudrate::B115200,
+                }
+            );
+            drop(device);
+
+            let requests = handle.requests().len();
+            let open = ports(vec![("/dev/ttyUSB0", handle.clone())]);
+            let (mut device, found) =
+                Device::connect_with_profile_using(&profile, &DiscoveryPolicy::scan(), open)
+                    .unwrap();
+            assert_eq!(found, profile);
+            // the serial number was the last thing read, nothing was set
+            assert_eq!(handle.last_request().map(|(_, command, _)| command), Some(0xD0));
+            assert_eq!(handle.requests().len(), requests + 2);
+            assert_eq!(device.response_timeout(), DEFAULT_RESPONSE_TIMEOUT);
+            device.set_setpoint(0.5).unwrap();
+        }
+
+        #[test]
+        fn another_serial_number_falls_back_to_discovery() {
+            let stranger = Sfc6xxxEmulator::new(EmulatorConfig {
+                serial_number: "EMU6000002".to_string(),
+                ..Default::default()
+            });
+            let moved = Sfc6xxxEmulator::new(EmulatorConfig {
+                address: 2,
+                ..Default::default()
+            });
+            let profile = ConnectionProfile {
+                port_hint: Some("/dev/ttyUSB0".to_string()),
+                serial: "EMU6000001".to_string(),
+                address: 0,
+                baudrate: Baudrate::B115200,
+            };
+            let line = vec![("/dev/ttyUSB0", stranger.handle()), ("/dev/ttyUSB1", moved.handle())];
+
+            let never = DiscoveryPolicy::Never;
+            let result = Device::connect_with_profile_using(&profile, &never, ports(line.clone()));
+            assert!(matches!(
+                result,
+                Err(DeviceError::IdentityMismatch { found, .. }) if found == "EMU6000002"
+            ));
+
+            let fallback = DiscoveryPolicy::Scan {
+                ports: vec!["/dev/ttyUSB0".to_string(), "/dev/ttyUSB1".to_string()],
+                baudrates: vec![Baudrate::B115200],
+                addresses: 0..=3,
+                timeout: Duration::from_millis(10),
+            };
+            let (mut device, found) =
+                Device::connect_with_profile_using(&profile, &fallback, ports(line)).unwrap();
+            assert_eq!(found.port_hint.as_deref(), Some("/dev/ttyUSB1"));
+            assert_eq!(found.address, 2);
+            assert_eq!(device.get_serial_number().unwrap(), "EMU6000001");
+            assert_eq!(device.response_timeout(), DEFAULT_RESPONSE_TIMEOUT);
+        }
+
+        #[test]
+        fn baudrate_round_trip() {
+            let (mut device, handle) = emulated_device();
+            device.set_baudrate(Baudrate::B19200).unwrap();
+            assert_eq!(handle.baudrate(), 19200);
+            assert_eq!(handle.line_baudrate(), 19200);
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B19200);
+
+            // an unchecked rate is sent and refused by the device
+            assert!(matches!(
+                device.set_baudrate(Baudrate::Other(9600)),
+                Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+            ));
+            assert_eq!(handle.baudrate(), 19200);
+        }
+
+        #[test]
+        #[allow(deprecated)]
+        fn baudrate_u32_shims() {
+            let (mut device, handle) = emulated_device();
+            assert_eq!(device.get_baudrate_u32().unwrap(), 115200);
+            let requests = handle.requests().len();
+            assert!(matches!(
+                device.set_baudrate_u32(9600),
+                Err(DeviceError::UnsupportedBaudrate(9600))
+            ));
+            assert_eq!(handle.requests().len(), requests);
+            device.set_baudrate_u32(57600).unwrap();
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B57600);
+        }
+
+        #[test]
+        fn stats_count_retries_and_checksum_errors() {
+            let (mut device, handle) = emulated_device();
+            assert_eq!(device.stats().commands, 1);
+            device.reset_stats();
+            device.set_retry(Some(RetryConfig::default()));
+            handle.inject_fault(Fault::CorruptChecksum);
+            device.set_setpoint(2.5).unwrap();
+            handle.inject_fault(Fault::CorruptChecksum);
+            device.set_retry(None);
+            assert!(matches!(device.get_setpoint(), Err(DeviceError::InvalidChecksum(_, _))));
+
+            let stats = device.stats();
+            assert_eq!(stats.commands, 2);
+            assert_eq!(stats.retries, 1);
+            assert_eq!(stats.failures, 1);
+            assert_eq!(stats.checksum_errors, 2);
+            assert_eq!(stats.timeouts, 0);
+        }
+
+        #[test]
+        fn slow_response_is_abandoned_at_the_deadline() {
+            let (mut device, handle) = emulated_device();
+            handle.inject_fault(Fault::DelayMs(100));
+            let start = Instant::now();
+            let deadline = start + Duration::from_millis(20);
+            assert!(matches!(
+                device.read_measured_value_with_deadline(deadline),
+                Err(DeviceError::DeadlineExceeded)
+            ));
+            assert!(start.elapsed() < Duration::from_millis(50));
+
+            let deadline = Instant::now() + Duration::from_millis(500);
+            device.set_setpoint_with_deadline(2.5, deadline).unwrap();
+            assert_eq!(handle.setpoint(), 2.5);
+        }
+
+        #[test]
+        fn setpoint_beyond_full_scale_is_rejected() {
+            let (mut device, handle) = emulated_device();
+            assert!(matches!(
+                device.set_setpoint(5.5),
+                Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+            ));
+            device.set_setpoint(5.0).unwrap();
+            assert_eq!(handle.setpoint(), 5.0);
+        }
+
+        /// Every method writing a setpoint checks the soft limits before anything is sent
+        #[test]
+        fn soft_limits_on_every_setpoint() {
+            use sfc_core::limits::Limit;
+
+            let (mut device, handle) = emulated_device();
+            device.set_soft_limits(0.0, 4.0);
+            let requests = handle.requests().len();
+            let deadline = Instant::now() + Duration::from_secs(1);
+            for result in [
+                device.set_setpoint(4.5),
+                device.set_setpoint_with_deadline(4.5, deadline),
+                device.set_setpoint_and_read_measured_value(4.5).map(drop),
+                FlowController::set_setpoint(&mut device, 4.5),
+            ] {
+                assert!(matches!(result, Err(DeviceError::SoftLimit(4.5, Limit::Max(4.0)))));
+            }
+            assert!(matches!(
+                device.set_setpoint(-1.0),
+                Err(DeviceError::SoftLimit(-1.0, Limit::Min(0.0)))
+            ));
+            assert_eq!(handle.requests().len(), requests);
+
+            device.set_setpoint(4.0).unwrap();
+            assert_eq!(handle.setpoint(), 4.0);
+
+            device.set_soft_limit_policy(LimitPolicy::Clamp);
+            device.set_soft_limits(0.0, 3.0);
+            device.set_setpoint(4.5).unwrap();
+            assert_eq!(handle.setpoint(), 3.0);
+            device.set_setpoint_with_deadline(5.0, deadline).unwrap();
+            assert_eq!(handle.setpoint(), 3.0);
+            assert_eq!(device.set_setpoint_and_read_measured_value(4.5).unwrap(), 3.0);
+
+            device.clear_soft_limits();
+            assert_eq!(device.soft_limits().policy, LimitPolicy::Clamp);
+            device.set_setpoint(5.0).unwrap();
+            assert_eq!(handle.setpoint(), 5.0);
+        }
+
+        #[test]
+        fn reconnect_after_the_cable_was_pulled() {
+            let (mut device, handle) = emulated_device();
+            device.set_baudrate(Baudrate::B57600).unwrap();
+            device.set_response_timeout(Duration::from_millis(40));
+            handle.unplug();
+            match device.get_baudrate() {
+                Err(DeviceError::IoError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe),
+                other => panic!("expected a broken pipe, got {:?}", other),
+            }
+
+            device.reconnect(handle.replug()).unwrap();
+            assert_eq!(device.get_baudrate().unwrap(), Baudrate::B57600);
+            assert_eq!(handle.line_baudrate(), 57600);
+            assert_eq!(device.response_timeout(), Duration::from_millis(40));
+        }
+
+        #[test]
+        fn volatile_calibration_is_lost_on_reset() {
+            let (mut device, handle) = emulated_device();
+            device.set_callibration(1).unwrap();
+            device.set_callibration_volitile(2).unwrap();
+            assert_relative_eq!(device.get_current_full_scale().unwrap(), 2.5);
+            device.reset_device().unwrap();
+            assert_eq!(handle.active_calibration(), 1);
+            assert_eq!(device.get_calliration_number().unwrap(), 1);
+        }
+
+        /// The calibration table in one pipeline, from firmware that takes requests early and
+        /// from firmware that drops them while it answers
+        #[test]
+        fn calibration_table_in_a_pipeline() {
+            let commands: Vec<_> = (0..3)
+                .flat_map(|index| {
+                    [
+                        Command::GetCalibrationGasId { index },
+                        Command::GetCalibrationFullScale { index },
+                    ]
+                })
+                .collect();
+            for pipelining in [true, false] {
+                let emulator = Sfc6xxxEmulator::new(EmulatorConfig {
+                    pipelining,
+                    ..Default::default()
+                });
+                let handle = emulator.handle();
+                let mut device = Device::new(emulator, 0).unwrap();
+                device.set_response_timeout(Duration::from_millis(20));
+                device.set_pipeline_window(4);
+                let before = handle.requests().len();
+                let commands_before = device.stats().commands;
+
+                let responses = device.pipeline(&commands).unwrap();
+                let values: Vec<_> = responses
+                    .into_iter()
+                    .map(|response| <[u8; 4]>::try_from(&response.into_data()[..4]).unwrap())
+                    .collect();
+                let gas_ids = values.iter().step_by(2).map(|&v| u32::from_be_bytes(v));
+                let full_scales = values.iter().skip(1).step_by(2);
+                assert_eq!(gas_ids.collect::<Vec<_>>(), [1, 2, 3]);
+                assert_eq!(
+                    full_scales.map(|&v| f32::from_be_bytes(v)).collect::<Vec<_>>(),
+                    [5.0, 5.0, 2.5]
+                );
+                assert_eq!(device.stats().commands - commands_before, 6);
+
+                // the dropped requests of the first batch are sent again one at a time
+                let sent = handle.requests().len() - before;
+                if pipelining {
+                    assert_eq!(sent, 6);
+                } else {
+                    assert_eq!(sent, 4 + 5);
+                }
+            }
+        }
+
+        #[cfg(feature = "uom")]
+        #[test]
+        fn readings_as_quantities() {
+            use crate::emulator::{CalibrationSlot, EmulatorConfig};
+            use sfc_core::gasunit::{Prefixes, TimeBases, Units};
+            use uom::si::thermodynamic_temperature::degree_celsius;
+            use uom::si::volume_rate::liter_per_minute;
+
+            let (mut device, _handle) = emulated_device();
+            device.set_setpoint(2.5).unwrap();
+            let flow = device.read_measured_value_uom().unwrap();
+            assert_relative_eq!(flow.get::<liter_per_minute>(), 2.5);
+            let temperature = device.measure_temperature_uom().unwrap();
+            assert_relative_eq!(temperature.get::<degree_celsius>(), 24.5, epsilon = 1e-4);
+
+            let grams = GasUnit {
+                unit_prefex: Prefixes::Base,
+                medium_unit: Units::Gram,
+                timebase: TimeBases::Minute,
+            };
+            let config = EmulatorConfig {
+                calibrations: vec![Some(CalibrationSlot {
+                    gas_id: 1,
+                    unit: grams,
+                    full_scale: 5.0,
+                })],
+                ..Default::default()
+            };
+            let mut device = Device::new(Sfc6xxxEmulator::new(config), 0).unwrap();
+            assert!(matches!(
+                device.read_measured_value_uom(),
+                Err(DeviceError::NotAVolumeRate(unit)) if unit == grams
+            ));
+        }
+    }
 }
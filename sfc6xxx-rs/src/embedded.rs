@@ -0,0 +1,477 @@
+//! The SFC6xxx on a blocking embedded-io stream, available with the `embedded-io` feature.
+//! [EmbeddedDevice] needs neither std nor an allocator, so it runs on a bare-metal
+//! microcontroller talking to the device over a UART. It sends the same frames and decodes the
+//! responses with the same code as the other devices.
+//!
+//! There is no clock on this path: a read waits for as long as the stream does and commands are
+//! never retried. Use a UART whose reads time out, or the async device with a timer, when the
+//! device might not answer.
+
+use embedded_io::{ErrorKind, Read, Write};
+use sfc_core::baudrate::Baudrate;
+use sfc_core::error::{DeviceError, StateResponseError};
+use sfc_core::flow_controller::FlowController;
+use sfc_core::gasunit::GasUnit;
+use sfc_core::shdlc::{DeviceString, FrameDecoder, MAX_STUFFED_FRAME, MISOFrame, Version};
+
+use crate::commands::{self, Request};
+
+/// An SFC6XXX on a blocking [Read] and [Write] stream, like the UART of a HAL crate.
+/// ```ignore
+/// // the UART of the HAL, set up for 115200 baud
+/// let mut device = EmbeddedDevice::new(uart, 0)?;
+/// device.set_setpoint(2.5)?;
+/// let flow = device.read_measured_value()?;
+/// ```
+#[derive(Debug)]
+pub struct EmbeddedDevice<T: Read + Write> {
+    port: T,
+    decoder: FrameDecoder,
+    slave_adress: u8,
+}
+
+impl<T: Read + Write> EmbeddedDevice<T> {
+    /// Creates the device and probes it with [EmbeddedDevice::get_baudrate], like
+    /// [Device::new](crate::device::Device::new).
+    pub fn new(port: T, slave_adress: u8) -> Result<Self, DeviceError> {
+        let mut device = Self {
+            port,
+            decoder: FrameDecoder::for_responses(),
+            slave_adress,
+        };
+
+        let _ = device.get_baudrate()?;
+
+        Ok(device)
+    }
+
+    /// Returns the underlying stream, for example to change its baudrate
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.port
+    }
+
+    /// Returns the underlying stream
+    pub fn into_inner(self) -> T {
+        self.port
+    }
+
+    /// Returns the current flow setpoint as a physical value in SLM
+    pub fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_setpoint(self.slave_adress)?)
+    }
+
+    /// Sets the flow setpoint as a physical value. The range of valid set points is 0.0 to
+    /// [EmbeddedDevice::get_current_full_scale]. The setpoint will be set to 0 if the calibration is
+    /// ever changed.
+    pub fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError> {
+        self.run(commands::set_setpoint(self.slave_adress, setpoint)?)
+    }
+
+    /// Returns the latest measured flow as physical value
+    pub fn read_measured_value(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::read_measured_value(self.slave_adress)?)
+    }
+
+    /// Returns the average of given numbers of flow measurment as a physical value. Each
+    /// measurment takes 1ms so the command response time depends on the number of measurements.
+    /// Addtionaly the number of measurments must be between 0 and 100 other wise it will return a
+    /// [StateResponseError::ParameterError].
+    pub fn read_average_measured_value(
+        &mut self,
+        measurment_count: u8,
+    ) -> Result<f32, DeviceError> {
+        self.run(commands::read_average_measured_value(self.slave_adress, measurment_count)?)
+    }
+
+    /// Sets the set point and reads the measured value in one SHDLC command
+    pub fn set_setpoint_and_read_measured_value(
+        &mut self,
+        setpoint: f32,
+    ) -> Result<f32, DeviceError> {
+        self.run(commands::set_setpoint_and_read_measured_value(self.slave_adress, setpoint)?)
+    }
+
+    /// Returns the controller gain
+    pub fn get_controller_gain(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_controller_gain(self.slave_adress)?)
+    }
+
+    /// Sets the controller gain to the desired value
+    pub fn set_controller_gain(&mut self, gain: f32) -> Result<(), DeviceError> {
+        self.run(commands::set_controller_gain(self.slave_adress, gain)?)
+    }
+
+    /// Gets the device intital step
+    pub fn get_initial_step(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_initial_step(self.slave_adress)?)
+    }
+
+    /// Sets the initial step. This is stored in non-volatile memory and will be cleared
+    /// after a device reset.
+    pub fn set_initial_step(&mut self, step: f32) -> Result<(), DeviceError> {
+        self.run(commands::set_initial_step(self.slave_adress, step)?)
+    }
+
+    /// Returns the measured flow in raw ticks
+    pub fn measure_raw_flow(&mut self) -> Result<u16, DeviceError> {
+        self.run(commands::measure_raw_flow(self.slave_adress)?)
+    }
+
+    /// Preforms a thermal conductivity measurement and returns the measured raw tick value.
+    /// The valve is automatically closed during the measurement
+    pub fn measure_raw_thermal_conductivity(&mut self) -> Result<u16, DeviceError> {
+        self.run(commands::measure_raw_thermal_conductivity(self.slave_adress)?)
+    }
+
+    /// Measures the temperature of the flow sensor in degrees celcius
+    pub fn measure_temperature(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::measure_temperature(self.slave_adress)?)
+    }
+
+    /// Gets the number of calibrations that the device memory is able to hold.
+    /// Not all calibrations actually contain a valid calibration. Use
+    /// [EmbeddedDevice::get_calibration_validity] to see which calibrations are valid and can be used
+    pub fn get_number_of_calibrations(&mut self) -> Result<u32, DeviceError> {
+        self.run(commands::get_number_of_calibrations(self.slave_adress)?)
+    }
+
+    /// Checks if a calibration at the specific index is valid
+    pub fn get_calibration_validity(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<bool, DeviceError> {
+        self.run(commands::get_calibration_validity(self.slave_adress, calibration_index)?)
+    }
+
+    /// Gets the gas ID of the specifc calibration index.
+    pub fn get_calibration_gas_id(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<u32, DeviceError> {
+        self.run(commands::get_calibration_gas_id(self.slave_adress, calibration_index)?)
+    }
+
+    /// Gets the gas unit of a specifc calibration index see [GasUnit] for more information.
+    pub fn get_calibration_gas_unit(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<GasUnit, DeviceError> {
+        self.run(commands::get_calibration_gas_unit(self.slave_adress, calibration_index)?)
+    }
+
+    /// Returns the full scale flow of a specifc calibration index.
+    pub fn get_calibration_full_scale(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<f32, DeviceError> {
+        self.run(commands::get_calibration_full_scale(self.slave_adress, calibration_index)?)
+    }
+
+    /// Gets the gas ID of the currently active calibration
+    pub fn get_current_gas_id(&mut self) -> Result<u32, DeviceError> {
+        self.run(commands::get_current_gas_id(self.slave_adress)?)
+    }
+
+    /// Gets the gas unit of the currently active calibration. See [GasUnit] for more
+    /// information
+    pub fn get_current_gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
+        self.run(commands::get_current_gas_unit(self.slave_adress)?)
+    }
+
+    /// Gets the full scale flow of the currently active calibration.
+    pub fn get_current_full_scale(&mut self) -> Result<f32, DeviceError> {
+        self.run(commands::get_current_full_scale(self.slave_adress)?)
+    }
+
+    /// Gets the calibration index of the currently active calibration.
+    pub fn get_calliration_number(&mut self) -> Result<u32, DeviceError> {
+        self.run(commands::get_calliration_number(self.slave_adress)?)
+    }
+
+    /// Changes the calibration to the new calibration at the specified index. This command
+    /// stops the controller by closing the valve. Additonly this is stored in presitent memory and
+    /// will remain after a device reset.
+    pub fn set_callibration(&mut self, calibration_index: u32) -> Result<(), DeviceError> {
+        self.run(commands::set_callibration(self.slave_adress, calibration_index)?)
+    }
+
+    /// Changes the calibration to the new calibration at the specified index. This command stops
+    /// the controller by closing the valve. This will be stored in volatile memory and will not
+    /// presit after a device reset.
+    pub fn set_callibration_volitile(
+        &mut self,
+        calibration_index: u32,
+    ) -> Result<(), DeviceError> {
+        self.run(commands::set_callibration_volitile(self.slave_adress, calibration_index)?)
+    }
+
+    /// Returns the slave adress of the SHDLC device
+    pub fn get_slave_adress(&mut self) -> Result<u8, DeviceError> {
+        self.run(commands::get_slave_adress(self.slave_adress)?)
+    }
+
+    /// Sets slave adress of the SHDLC device. The slave adress is stored in non-volatile memory
+    /// and therefore will presist after a device reset. Next time the device is connected be sure
+    /// to use the new address. Aditionally make sure there is only one device with this address on
+    /// the bus. Otherwise there will be communication errors that can only be fixed by
+    /// disconnecting one of the devices.
+    pub fn set_slave_adress(&mut self, new_adress: u8) -> Result<(), DeviceError> {
+        self.run(commands::set_slave_adress(self.slave_adress, new_adress)?)?;
+        self.slave_adress = new_adress;
+        Ok(())
+    }
+
+    /// Gets the baudrate of the SHDLC device, [Baudrate::Other] if it isn't a documented one.
+    pub fn get_baudrate(&mut self) -> Result<Baudrate, DeviceError> {
+        self.run(commands::get_baudrate(self.slave_adress)?)
+    }
+
+    /// Sets the buadrate of the device. The buadrate is stored in non-volatile memory
+    /// and will presist after a device reset. The device refuses a [Baudrate::Other]. The speed
+    /// of the UART itself is not changed, change it through [EmbeddedDevice::get_mut] once this
+    /// returns.
+    pub fn set_baudrate(&mut self, baudrate: Baudrate) -> Result<(), DeviceError> {
+        self.run(commands::set_baudrate(self.slave_adress, baudrate)?)
+    }
+
+    /// [EmbeddedDevice::set_baudrate] with a bare number, failing with
+    /// [DeviceError::UnsupportedBaudrate] without sending anything for a rate the device
+    /// doesn't support
+    #[deprecated(note = "use set_baudrate with a Baudrate")]
+    pub fn set_baudrate_u32(&mut self, baudrate: u32) -> Result<(), DeviceError> {
+        self.set_baudrate(Baudrate::try_from(baudrate)?)
+    }
+
+    /// [EmbeddedDevice::get_baudrate] as a bare number
+    #[deprecated(note = "use get_baudrate, which returns a Baudrate")]
+    pub fn get_baudrate_u32(&mut self) -> Result<u32, DeviceError> {
+        self.get_baudrate().map(u32::from)
+    }
+
+    /// Gets the product type from the device
+    pub fn get_product_type(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_product_type(self.slave_adress)?)
+    }
+
+    /// Gets the product name from the device
+    pub fn get_product_name(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_product_name(self.slave_adress)?)
+    }
+
+    /// Gets the article code of the device. This information is also contained on the
+    /// product label.
+    pub fn get_article_code(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_article_code(self.slave_adress)?)
+    }
+
+    /// Gets the serial number of the SFC6xxx sensor as a hex String matching the 
+    /// serial number printed on the device.
+    pub fn get_serial_number(&mut self) -> Result<DeviceString, DeviceError> {
+        self.run(commands::get_serial_number(self.slave_adress)?)
+    }
+
+    /// Gets the version information for the hardware, firmware, and SHDLC protocol.
+    pub fn get_version(&mut self) -> Result<Version, DeviceError> {
+        self.run(commands::get_version(self.slave_adress)?)
+    }
+
+    /// Resets the device which has the same effect as a power cycle. Please allow 300ms for the
+    /// device to power on.
+    pub fn reset_device(&mut self) -> Result<(), DeviceError> {
+        self.run(commands::reset_device(self.slave_adress)?)
+    }
+
+    fn run<R>(&mut self, command: Request<R>) -> Result<R, DeviceError> {
+        let (request, read) = command.split();
+        let address = request.get_address();
+        let code = request.get_command_number();
+        self.send(&request.into_raw())?;
+
+        self.decoder.reset();
+        loop {
+            let raw = self.receive_frame()?;
+            let frame = MISOFrame::from_bytes(&raw)?;
+            // a late answer to an earlier command
+            if frame.get_address() != address || frame.get_command_number() != code {
+                continue;
+            }
+            if !frame.validate_checksum() {
+                Err(DeviceError::InvalidChecksum(
+                    frame.get_checksum(),
+                    frame.calculate_check_sum(),
+                ))?;
+            }
+            if !frame.is_ok() {
+                Err(StateResponseError::from(frame.get_state()))?;
+            }
+            return read(&frame.into_data());
+        }
+    }
+
+    /// Writes every byte of the request. A stream that takes no bytes fails the command instead
+    /// of panicking like [Write::write_all].
+    fn send(&mut self, raw: &[u8]) -> Result<(), DeviceError> {
+        let mut written = 0;
+        while written < raw.len() {
+            match self.port.write(raw.get(written..).unwrap_or_default()).map_err(io_error)? {
+                0 => return Err(DeviceError::EmbeddedIoError(ErrorKind::WriteZero)),
+                count => written += count,
+            }
+        }
+        self.port.flush().map_err(io_error)
+    }
+
+    /// Reads the bytes of one frame, from start to end delimiter. Bytes are read one at a time
+    /// so nothing after the end of the frame is taken from the stream.
+    fn receive_frame(&mut self) -> Result<arrayvec::ArrayVec<u8, MAX_STUFFED_FRAME>, DeviceError> {
+        let mut byte = [0_u8];
+        loop {
+            if self.port.read(&mut byte).map_err(io_error)? == 0 {
+                return Err(if self.decoder.in_frame() {
+                    DeviceError::IncompleteFrame
+                } else {
+                    DeviceError::Timeout
+                });
+            }
+            if let (_, Some(frame)) = self.decoder.decode(&byte)? {
+                return Ok(frame);
+            }
+        }
+    }
+}
+
+fn io_error<E: embedded_io::Error>(error: E) -> DeviceError {
+    DeviceError::EmbeddedIoError(error.kind())
+}
+
+impl<T: Read + Write> FlowController for EmbeddedDevice<T> {
+    fn set_setpoint(&mut self, setpoint: f32) -> Result<(), DeviceError> {
+        EmbeddedDevice::set_setpoint(self, setpoint)
+    }
+
+    fn get_setpoint(&mut self) -> Result<f32, DeviceError> {
+        EmbeddedDevice::get_setpoint(self)
+    }
+
+    fn read_measured_value(&mut self) -> Result<f32, DeviceError> {
+        EmbeddedDevice::read_measured_value(self)
+    }
+
+    fn read_average_measured_value(&mut self, measurement_count: u8) -> Result<f32, DeviceError> {
+        EmbeddedDevice::read_average_measured_value(self, measurement_count)
+    }
+
+    fn get_full_scale(&mut self) -> Result<f32, DeviceError> {
+        self.get_current_full_scale()
+    }
+
+    fn get_gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
+        self.get_current_gas_unit()
+    }
+
+    fn reset(&mut self) -> Result<(), DeviceError> {
+        self.reset_device()
+    }
+
+    fn get_version(&mut self) -> Result<Version, DeviceError> {
+        EmbeddedDevice::get_version(self)
+    }
+
+    fn get_serial_number(&mut self) -> Result<DeviceString, DeviceError> {
+        EmbeddedDevice::get_serial_number(self)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorHandle, Fault, Sfc6xxxEmulator};
+
+    /// The emulator behind the embedded-io traits, like a UART whose reads time out
+    struct Uart(Sfc6xxxEmulator);
+
+    impl embedded_io::ErrorType for Uart {
+        type Error = ErrorKind;
+    }
+
+    fn kind(error: std::io::Error) -> ErrorKind {
+        match error.kind() {
+            std::io::ErrorKind::TimedOut => ErrorKind::TimedOut,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    impl Read for Uart {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            std::io::Read::read(&mut self.0, buf).map_err(kind)
+        }
+    }
+
+    impl Write for Uart {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            std::io::Write::write(&mut self.0, buf).map_err(kind)
+        }
+
+        fn flush(&mut self) -> Result<(), ErrorKind> {
+            std::io::Write::flush(&mut self.0).map_err(kind)
+        }
+    }
+
+    fn emulated_device() -> (EmbeddedDevice<Uart>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::default();
+        let handle = emulator.handle();
+        (EmbeddedDevice::new(Uart(emulator), 0).unwrap(), handle)
+    }
+
+    #[test]
+    fn command_sequence() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(2.5).unwrap();
+        assert_eq!(device.get_setpoint().unwrap(), 2.5);
+        assert_eq!(device.read_measured_value().unwrap(), 2.5);
+        assert_eq!(device.get_serial_number().unwrap(), "EMU6000001");
+        assert_eq!(device.get_version().unwrap().protocol_major, 2);
+        device.reset_device().unwrap();
+        assert_eq!(handle.resets(), 1);
+    }
+
+    #[test]
+    fn late_response_is_skipped() {
+        let (mut device, handle) = emulated_device();
+        handle.inject_fault(Fault::DuplicateResponse);
+        device.set_setpoint(1.0).unwrap();
+        assert_eq!(device.read_measured_value().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn a_frame_cut_short_does_not_hide_the_response() {
+        let frame = MISOFrame::from_parts(0, 0x00, 0x00, &1.0_f32.to_be_bytes()).unwrap().to_raw();
+        for cut in 1..frame.len() - 1 {
+            let (mut device, handle) = emulated_device();
+            device.set_setpoint(2.5).unwrap();
+            handle.inject_fault(Fault::InjectBytes(frame[..cut].to_vec()));
+            assert_eq!(device.get_setpoint().unwrap(), 2.5, "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn transmission_errors() {
+        let (mut device, handle) = emulated_device();
+        handle.inject_fault(Fault::CorruptChecksum);
+        assert!(matches!(device.get_setpoint(), Err(DeviceError::InvalidChecksum(_, _))));
+
+        handle.inject_fault(Fault::ErrorState(0x04));
+        assert!(matches!(
+            device.get_setpoint(),
+            Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+        ));
+
+        // the emulator answers a read with nothing to send with a timeout error
+        handle.inject_fault(Fault::DropResponse);
+        assert!(matches!(
+            device.get_setpoint(),
+            Err(DeviceError::EmbeddedIoError(ErrorKind::TimedOut))
+        ));
+        assert_eq!(device.get_setpoint().unwrap(), 0.0);
+    }
+}
@@ -0,0 +1,572 @@
+//! An in-process SFC6xxx that answers SHDLC frames like a real device would. It implements
+//! [Transport] so it can be handed straight to [Device::new](crate::device::Device::new) and is
+//! meant for testing code built on the driver without any hardware attached.
+//!
+//! The emulated controller is perfect, the measured flow always equals the setpoint unless a
+//! [Drift] is set with [EmulatorHandle::set_drift] or the flow is given a [StepResponse]
+//! through [EmulatorConfig::response]. Faults can be queued with
+//! [inject_fault](sfc_core::emulator::EmulatorHandle::inject_fault) to test how the driver
+//! copes with a misbehaving line, the transport and faults are those of [sfc_core::emulator].
+//! ```
+//! use sfc6xxx_rs::device::Device;
+//! use sfc6xxx_rs::emulator::Sfc6xxxEmulator;
+//!
+//! let emulator = Sfc6xxxEmulator::default();
+//! let handle = emulator.handle();
+//! let mut device = Device::new(emulator, 0).unwrap();
+//! device.set_setpoint(2.0).unwrap();
+//! assert_eq!(device.read_measured_value().unwrap(), 2.0);
+//! assert_eq!(handle.setpoint(), 2.0);
+//! ```
+
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+use sfc_core::emulator::{
+    self, CommandTable, Emulator, STATE_DATA_SIZE, STATE_INVALID_CALIBRATION, STATE_PARAMETER,
+    STATE_UNKNOWN_COMMAND, encode_string, read_f32, read_u32,
+};
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::{GasUnit, Prefixes, TimeBases, Units};
+use sfc_core::shdlc::Version;
+use sfc_core::transport::Transport;
+
+pub use sfc_core::emulator::Fault;
+
+/// The baudrates the device can be configured to use
+pub const BAUDRATES: [u32; 4] = [19200, 38400, 57600, 115200];
+
+/// A measurement slowly moving away from where it should be, like the flow through a leak.
+/// The offsets grow linearly in real time from when the drift was set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Drift {
+    /// Added to the measured flow, in the unit of the active calibration per second
+    pub flow_per_second: f32,
+    /// Added to the raw thermal conductivity, in ticks per second
+    pub thermal_conductivity_per_second: f32,
+}
+
+/// How the emulated flow follows a setpoint change, like a valve behind a proportional
+/// controller. The gain set with the controller gain command decides the damping: at
+/// `critical_gain` the flow settles as fast as it can without overshooting, above it the flow
+/// overshoots and rings, below it the flow creeps towards the setpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepResponse {
+    /// How quickly the flow settles at the critical gain
+    pub time_constant: Duration,
+    pub critical_gain: f32,
+}
+
+impl StepResponse {
+    /// The fraction of a step the flow has covered after `elapsed` with the given gain. A
+    /// second order system with a damping of `sqrt(critical_gain / gain)`, whose decay rate is
+    /// the inverse of the time constant at every gain.
+    fn fraction(&self, gain: f32, elapsed: Duration) -> f32 {
+        if gain <= 0.0 {
+            return 0.0;
+        }
+        let t = elapsed.as_secs_f32();
+        let damping = (self.critical_gain / gain).sqrt();
+        let natural = (gain / self.critical_gain).sqrt() / self.time_constant.as_secs_f32();
+        if (damping - 1.0).abs() < 1e-4 {
+            1.0 - (1.0 + natural * t) * (-natural * t).exp()
+        } else if damping < 1.0 {
+            let root = (1.0 - damping * damping).sqrt();
+            let damped = natural * root;
+            1.0 - (-damping * natural * t).exp()
+                * ((damped * t).cos() + damping / root * (damped * t).sin())
+        } else {
+            let root = (damping * damping - 1.0).sqrt();
+            let fast = -natural * (damping + root);
+            let slow = -natural * (damping - root);
+            1.0 + (fast * (slow * t).exp() - slow * (fast * t).exp()) / (slow - fast)
+        }
+    }
+}
+
+/// A single calibration stored on the emulated device
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalibrationSlot {
+    pub gas_id: u32,
+    pub unit: GasUnit,
+    pub full_scale: f32,
+}
+
+/// Everything about the emulated device that is fixed when it is created
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmulatorConfig {
+    /// The slave address the emulator answers to
+    pub address: u8,
+    /// The calibration table. `None` entries are reported as invalid calibrations.
+    pub calibrations: Vec<Option<CalibrationSlot>>,
+    /// Index into `calibrations` that is active after start up
+    pub active_calibration: u32,
+    pub version: Version,
+    pub product_type: String,
+    pub product_name: String,
+    pub article_code: String,
+    pub serial_number: String,
+    pub baudrate: u32,
+    /// Raw flow reading in ticks
+    pub raw_flow: u16,
+    /// Raw thermal conductivity reading in ticks
+    pub raw_thermal_conductivity: u16,
+    /// Sensor temperature in degrees celcius
+    pub temperature: f32,
+    /// How long the device ignores requests after a reset, in real time. A real device needs
+    /// up to 300ms.
+    pub boot_time: Duration,
+    /// How long the device answers every request with a fatal error (0x7F) once the boot time
+    /// is over, like firmware that takes requests before it finished starting up. Zero by
+    /// default.
+    pub boot_errors: Duration,
+    /// How the flow follows the setpoint, instantly if [None]
+    pub response: Option<StepResponse>,
+    /// Whether a request is taken while the response to the previous one hasn't been read yet.
+    /// Firmware that can't pipeline requests drops it instead, on by default.
+    pub pipelining: bool,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        let slot = |gas_id, full_scale| {
+            Some(CalibrationSlot {
+                gas_id,
+                unit: GasUnit {
+                    unit_prefex: Prefixes::Base,
+                    medium_unit: Units::StandardLiter,
+                    timebase: TimeBases::Minute,
+                },
+                full_scale,
+            })
+        };
+
+        Self {
+            address: 0,
+            calibrations: vec![slot(1, 5.0), slot(2, 5.0), slot(3, 2.5), None],
+            active_calibration: 0,
+            version: Version {
+                firmware_major: 1,
+                firmware_minor: 0,
+                debug: false,
+                hardware_major: 1,
+                hardware_minor: 0,
+                protocol_major: 2,
+                protocol_minor: 0,
+            },
+            product_type: "SFC6000".to_string(),
+            product_name: "SFC6000D-5SLM".to_string(),
+            article_code: "3.000.001".to_string(),
+            serial_number: "EMU6000001".to_string(),
+            baudrate: 115200,
+            raw_flow: 0x2000,
+            raw_thermal_conductivity: 1200,
+            temperature: 24.5,
+            boot_time: Duration::ZERO,
+            boot_errors: Duration::ZERO,
+            response: None,
+            pipelining: true,
+        }
+    }
+}
+
+/// The emulated device. Hand it to [Device::new](crate::device::Device::new) and keep an
+/// [EmulatorHandle] around to inspect or manipulate it afterwards.
+#[derive(Debug)]
+pub struct Sfc6xxxEmulator(Emulator<EmulatorState>);
+
+impl Sfc6xxxEmulator {
+    pub fn new(config: EmulatorConfig) -> Self {
+        Self(Emulator::new(EmulatorState::new(config)))
+    }
+
+    /// Returns a handle sharing the state of this emulator
+    pub fn handle(&self) -> EmulatorHandle {
+        EmulatorHandle(self.0.handle())
+    }
+}
+
+impl Default for Sfc6xxxEmulator {
+    fn default() -> Self {
+        Self::new(EmulatorConfig::default())
+    }
+}
+
+impl Read for Sfc6xxxEmulator {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Sfc6xxxEmulator {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for Sfc6xxxEmulator {
+    fn timeout(&self) -> Duration {
+        self.0.timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.0.set_timeout(timeout)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), DeviceError> {
+        self.0.set_baud_rate(baud_rate)
+    }
+}
+
+/// A shared reference to the state of a [Sfc6xxxEmulator] that stays usable after the emulator
+/// has been moved into a device. Unplugging, faults, the busy state and the requests received
+/// are those of every emulator, see [emulator::EmulatorHandle].
+#[derive(Clone, Debug)]
+pub struct EmulatorHandle(emulator::EmulatorHandle<EmulatorState>);
+
+impl Deref for EmulatorHandle {
+    type Target = emulator::EmulatorHandle<EmulatorState>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl EmulatorHandle {
+    /// Plugs the cable back in and returns a fresh transport to the same device
+    pub fn replug(&self) -> Sfc6xxxEmulator {
+        Sfc6xxxEmulator(self.0.replug())
+    }
+
+    /// Makes the measured flow and thermal conductivity drift from now on, replacing the
+    /// previous drift
+    pub fn set_drift(&self, drift: Drift) {
+        self.0.with(|state| state.drift = Some((drift, Instant::now())));
+    }
+
+    /// Returns the current setpoint as a physical value
+    pub fn setpoint(&self) -> f32 {
+        self.0.with(|state| state.setpoint)
+    }
+
+    /// Returns the currently active calibration index
+    pub fn active_calibration(&self) -> u32 {
+        self.0.with(|state| state.active_calibration)
+    }
+
+    /// Returns the slave address the emulator currently answers to
+    pub fn address(&self) -> u8 {
+        self.0.with(|state| state.address)
+    }
+
+    /// Returns the baudrate the device has been configured to use
+    pub fn baudrate(&self) -> u32 {
+        self.0.with(|state| state.baudrate)
+    }
+
+    /// Returns the baudrate the driver last set on the transport
+    pub fn line_baudrate(&self) -> u32 {
+        self.0.with(|state| state.line_baudrate)
+    }
+
+    /// Returns how often the device was reset
+    pub fn resets(&self) -> u32 {
+        self.0.with(|state| state.resets)
+    }
+}
+
+/// The emulated SFC6xxx itself, the commands it knows and what they change
+#[derive(Debug)]
+pub struct EmulatorState {
+    config: EmulatorConfig,
+    address: u8,
+    baudrate: u32,
+    line_baudrate: u32,
+    /// the calibration stored in non-volatile memory
+    persistent_calibration: u32,
+    active_calibration: u32,
+    /// physical value in the unit of the active calibration
+    setpoint: f32,
+    controller_gain: f32,
+    initial_step: f32,
+    resets: u32,
+    /// requests are ignored until then, set by a reset
+    booting_until: Option<Instant>,
+    /// requests are answered with a fatal error until then, set by a reset
+    failing_until: Option<Instant>,
+    /// set by [EmulatorHandle::set_drift], with when it was set
+    drift: Option<(Drift, Instant)>,
+    /// the flow when the setpoint last changed and when that was, for [EmulatorConfig::response]
+    step: Option<(f32, Instant)>,
+}
+
+impl EmulatorState {
+    fn new(config: EmulatorConfig) -> Self {
+        Self {
+            address: config.address,
+            baudrate: config.baudrate,
+            line_baudrate: config.baudrate,
+            persistent_calibration: config.active_calibration,
+            active_calibration: config.active_calibration,
+            setpoint: 0.0,
+            controller_gain: 1.0,
+            initial_step: 0.0,
+            resets: 0,
+            booting_until: None,
+            failing_until: None,
+            drift: None,
+            step: None,
+            config,
+        }
+    }
+
+    fn calibration(&self, index: u32) -> Result<&CalibrationSlot, u8> {
+        match self.config.calibrations.get(index as usize) {
+            Some(Some(slot)) => Ok(slot),
+            Some(None) => Err(STATE_INVALID_CALIBRATION),
+            None => Err(STATE_PARAMETER),
+        }
+    }
+
+    fn full_scale(&self) -> Result<f32, u8> {
+        Ok(self.calibration(self.active_calibration)?.full_scale)
+    }
+
+    /// Converts a physical value to the requested scale, `0x00` normalized or `0x01` physical
+    fn scale_value(&self, physical: f32, scale: u8) -> Result<f32, u8> {
+        match scale {
+            0x00 => Ok(physical / self.full_scale()?),
+            0x01 => Ok(physical),
+            _ => Err(STATE_PARAMETER),
+        }
+    }
+
+    /// how far the drift has moved the flow and the thermal conductivity by now
+    fn drift(&self) -> (f32, f32) {
+        match self.drift {
+            Some((drift, since)) => {
+                let seconds = since.elapsed().as_secs_f32();
+                (
+                    drift.flow_per_second * seconds,
+                    drift.thermal_conductivity_per_second * seconds,
+                )
+            }
+            None => (0.0, 0.0),
+        }
+    }
+
+    /// the physical flow through the valve, following the setpoint as configured
+    fn controlled_flow(&self) -> f32 {
+        match (self.config.response, self.step) {
+            (Some(response), Some((from, since))) => {
+                let fraction = response.fraction(self.controller_gain, since.elapsed());
+                from + (self.setpoint - from) * fraction
+            }
+            _ => self.setpoint,
+        }
+    }
+
+    /// the physical flow as the sensor sees it
+    fn measured_flow(&self) -> f32 {
+        self.controlled_flow() + self.drift().0
+    }
+
+    fn set_setpoint(&mut self, scale: u8, value: &[u8]) -> Result<(), u8> {
+        let value = read_f32(value)?;
+        let physical = match scale {
+            0x00 => value * self.full_scale()?,
+            0x01 => value,
+            _ => return Err(STATE_PARAMETER),
+        };
+        if !(0.0..=self.full_scale()?).contains(&physical) {
+            return Err(STATE_PARAMETER);
+        }
+        self.step = Some((self.controlled_flow(), Instant::now()));
+        self.setpoint = physical;
+        Ok(())
+    }
+
+    fn switch_calibration(&mut self, index: &[u8], persistent: bool) -> Result<Vec<u8>, u8> {
+        let index = read_u32(index)?;
+        self.calibration(index)?;
+        self.active_calibration = index;
+        if persistent {
+            self.persistent_calibration = index;
+        }
+        // switching the calibration closes the valve
+        self.setpoint = 0.0;
+        self.step = None;
+        Ok(Vec::new())
+    }
+
+    fn reset(&mut self) {
+        self.setpoint = 0.0;
+        self.step = None;
+        self.active_calibration = self.persistent_calibration;
+        self.line_baudrate = self.baudrate;
+        self.resets += 1;
+        if !self.config.boot_time.is_zero() {
+            self.booting_until = Some(Instant::now() + self.config.boot_time);
+        }
+        if !self.config.boot_errors.is_zero() {
+            let booted = Instant::now() + self.config.boot_time;
+            self.failing_until = Some(booted + self.config.boot_errors);
+        }
+    }
+}
+
+impl CommandTable for EmulatorState {
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn set_line_baudrate(&mut self, baud_rate: u32) {
+        self.line_baudrate = baud_rate;
+    }
+
+    fn booting(&mut self) -> bool {
+        match self.booting_until {
+            Some(until) if Instant::now() < until => true,
+            _ => {
+                self.booting_until = None;
+                false
+            }
+        }
+    }
+
+    fn failing(&mut self) -> bool {
+        match self.failing_until {
+            Some(until) if Instant::now() < until => true,
+            _ => {
+                self.failing_until = None;
+                false
+            }
+        }
+    }
+
+    fn pipelining(&self) -> bool {
+        self.config.pipelining
+    }
+
+    fn execute(&mut self, command: u8, data: &[u8]) -> Result<Vec<u8>, u8> {
+        match (command, data) {
+            // setpoint
+            (0x00, [scale]) => Ok(self.scale_value(self.setpoint, *scale)?.to_be_bytes().to_vec()),
+            (0x00, [scale, value @ ..]) => {
+                self.set_setpoint(*scale, value)?;
+                Ok(Vec::new())
+            }
+            // set setpoint and read the measured value
+            (0x03, [scale, value @ ..]) => {
+                self.set_setpoint(*scale, value)?;
+                Ok(self.scale_value(self.measured_flow(), *scale)?.to_be_bytes().to_vec())
+            }
+            // measured value
+            (0x08, [scale]) => {
+                Ok(self.scale_value(self.measured_flow(), *scale)?.to_be_bytes().to_vec())
+            }
+            (0x08, [scale @ (0x10 | 0x11), count]) => {
+                if !(1..=100).contains(count) {
+                    return Err(STATE_PARAMETER);
+                }
+                let scale = scale - 0x10;
+                Ok(self.scale_value(self.measured_flow(), scale)?.to_be_bytes().to_vec())
+            }
+            // controller configuration
+            (0x22, [0x00]) => Ok(self.controller_gain.to_be_bytes().to_vec()),
+            (0x22, [0x00, gain @ ..]) => {
+                self.controller_gain = read_f32(gain)?;
+                Ok(Vec::new())
+            }
+            (0x22, [0x03]) => Ok(self.initial_step.to_be_bytes().to_vec()),
+            (0x22, [0x03, step @ ..]) => {
+                self.initial_step = read_f32(step)?;
+                Ok(Vec::new())
+            }
+            // raw measurements
+            (0x30, [0x00]) => Ok(self.config.raw_flow.to_be_bytes().to_vec()),
+            (0x30, [0x02]) => {
+                let drifted = self.config.raw_thermal_conductivity as f32 + self.drift().1;
+                Ok((drifted.clamp(0.0, u16::MAX as f32) as u16).to_be_bytes().to_vec())
+            }
+            (0x30, [0x10]) => Ok(self.config.temperature.to_be_bytes().to_vec()),
+            // calibration table
+            (0x40, [0x00]) => Ok((self.config.calibrations.len() as u32).to_be_bytes().to_vec()),
+            (0x40, [0x10, index @ ..]) => {
+                let index = read_u32(index)?;
+                match self.calibration(index) {
+                    Ok(_) => Ok(vec![1]),
+                    Err(STATE_INVALID_CALIBRATION) => Ok(vec![0]),
+                    Err(state) => Err(state),
+                }
+            }
+            (0x40, [subcommand, index @ ..]) => {
+                let slot = self.calibration(read_u32(index)?)?;
+                calibration_info(slot, *subcommand)
+            }
+            (0x44, [subcommand]) => calibration_info(self.calibration(self.active_calibration)?, *subcommand),
+            // active calibration
+            (0x45, []) => Ok(self.active_calibration.to_be_bytes().to_vec()),
+            (0x45, index) => self.switch_calibration(index, true),
+            (0x46, index) => self.switch_calibration(index, false),
+            // communication settings
+            (0x90, []) => Ok(vec![self.address]),
+            (0x90, [address]) => {
+                self.address = *address;
+                Ok(Vec::new())
+            }
+            (0x91, []) => Ok(self.baudrate.to_be_bytes().to_vec()),
+            (0x91, baudrate) => {
+                let baudrate = read_u32(baudrate)?;
+                if !BAUDRATES.contains(&baudrate) {
+                    return Err(STATE_PARAMETER);
+                }
+                self.baudrate = baudrate;
+                Ok(Vec::new())
+            }
+            // device information
+            (0xD0, [0x00]) => Ok(encode_string(&self.config.product_type)),
+            (0xD0, [0x01]) => Ok(encode_string(&self.config.product_name)),
+            (0xD0, [0x02]) => Ok(encode_string(&self.config.article_code)),
+            (0xD0, [0x03]) => Ok(encode_string(&self.config.serial_number)),
+            (0xD1, []) => {
+                let v = self.config.version;
+                Ok(vec![
+                    v.firmware_major,
+                    v.firmware_minor,
+                    v.debug as u8,
+                    v.hardware_major,
+                    v.hardware_minor,
+                    v.protocol_major,
+                    v.protocol_minor,
+                ])
+            }
+            (0xD3, []) => {
+                self.reset();
+                Ok(Vec::new())
+            }
+            (0x00 | 0x03 | 0x08 | 0x22 | 0x30 | 0x40 | 0x44 | 0xD0 | 0xD1 | 0xD3, _) => {
+                Err(STATE_DATA_SIZE)
+            }
+            _ => Err(STATE_UNKNOWN_COMMAND),
+        }
+    }
+}
+
+fn calibration_info(slot: &CalibrationSlot, subcommand: u8) -> Result<Vec<u8>, u8> {
+    match subcommand {
+        0x12 => Ok(slot.gas_id.to_be_bytes().to_vec()),
+        0x13 => Ok(vec![
+            i8::from(slot.unit.unit_prefex) as u8,
+            slot.unit.medium_unit.into(),
+            slot.unit.timebase.into(),
+        ]),
+        0x14 => Ok(slot.full_scale.to_be_bytes().to_vec()),
+        _ => Err(STATE_PARAMETER),
+    }
+}
@@ -0,0 +1,261 @@
+//! Leak checks of a gas line. With the valve held closed the measured flow should stay at
+//! zero, [Device::leak_test] watches it for a while and reports how much it moved:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//! use sfc6xxx_rs::leak_test::LeakTestConfig;
+//!
+//! let config = LeakTestConfig {
+//!     max_slope: Some(0.001),
+//!     ..LeakTestConfig::new(Duration::from_secs(120), 0.01)
+//! };
+//! let report = device.leak_test(config)?;
+//! println!("{:?}, {}", report.flow, if report.passed { "tight" } else { "leaking" });
+//! # Ok(())
+//! # }
+//! ```
+//! The device has no command to force the valve closed yet, the test holds it closed with a
+//! setpoint of zero. The setpoint from before the test is set again when it ends, also when it
+//! fails.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc_core::error::DeviceError;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+/// How [Device::leak_test] runs and when it passes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LeakTestConfig {
+    /// How long the flow is watched
+    pub duration: Duration,
+    /// The time between two samples, one second by default
+    pub sample_interval: Duration,
+    /// The test fails if the measured flow is ever further from zero than this, in the unit of
+    /// the active calibration
+    pub flow_threshold: f32,
+    /// The test fails if the flow changes faster than this per second, not checked by default
+    pub max_slope: Option<f32>,
+    /// Also sample [Device::measure_raw_thermal_conductivity], off by default
+    pub thermal_conductivity: bool,
+}
+
+impl LeakTestConfig {
+    pub fn new(duration: Duration, flow_threshold: f32) -> Self {
+        Self {
+            duration,
+            sample_interval: Duration::from_secs(1),
+            flow_threshold,
+            max_slope: None,
+            thermal_conductivity: false,
+        }
+    }
+}
+
+/// How a series of samples behaved over a leak test
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Trend {
+    pub mean: f32,
+    /// The sample furthest from zero
+    pub max: f32,
+    /// The least squares slope over time, per second
+    pub slope: f32,
+}
+
+impl Trend {
+    /// Fits the samples, given as seconds since the start and the value
+    fn fit(samples: &[(f32, f32)]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let count = samples.len() as f32;
+        let mean_time = samples.iter().map(|(time, _)| time).sum::<f32>() / count;
+        let mean = samples.iter().map(|(_, value)| value).sum::<f32>() / count;
+        let (covariance, variance) =
+            samples.iter().fold((0.0, 0.0), |(covariance, variance), (time, value)| {
+                let dt = time - mean_time;
+                (covariance + dt * (value - mean), variance + dt * dt)
+            });
+        let max = samples
+            .iter()
+            .map(|&(_, value)| value)
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or_default();
+        Self {
+            mean,
+            max,
+            slope: if variance > 0.0 { covariance / variance } else { 0.0 },
+        }
+    }
+}
+
+/// The outcome of [Device::leak_test]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LeakTestReport {
+    pub samples: usize,
+    /// The measured flow in the unit of the active calibration
+    pub flow: Trend,
+    /// The raw thermal conductivity in ticks, if it was sampled
+    pub thermal_conductivity: Option<Trend>,
+    /// Whether the flow stayed within [LeakTestConfig::flow_threshold] and
+    /// [LeakTestConfig::max_slope]
+    pub passed: bool,
+}
+
+/// Sets the setpoint the device had before the test again when dropped
+struct RestoreSetpoint<'a, T: Transport> {
+    device: &'a mut Device<T>,
+    setpoint: f32,
+    restored: bool,
+}
+
+impl<T: Transport> RestoreSetpoint<'_, T> {
+    /// Restores the setpoint and reports if that failed
+    fn restore(mut self) -> Result<(), DeviceError> {
+        self.restored = true;
+        self.device.set_setpoint(self.setpoint)
+    }
+}
+
+impl<T: Transport> Drop for RestoreSetpoint<'_, T> {
+    fn drop(&mut self) {
+        if !self.restored {
+            // the test already failed, that error is the one returned
+            let _ = self.device.set_setpoint(self.setpoint);
+        }
+    }
+}
+
+impl<T: Transport> Device<T> {
+    /// Holds the valve closed for [LeakTestConfig::duration] and samples the measured flow,
+    /// returning its mean, maximum and slope and whether it passed. The setpoint from before
+    /// the test is restored afterwards, if a command fails the test stops with that error after
+    /// trying to restore it.
+    ///
+    /// # Panics
+    /// If the sample interval is zero
+    pub fn leak_test(&mut self, config: LeakTestConfig) -> Result<LeakTestReport, DeviceError> {
+        assert!(!config.sample_interval.is_zero(), "the sample interval must not be zero");
+        self.writable("leak test")?;
+        let setpoint = self.get_setpoint()?;
+        let guard = RestoreSetpoint {
+            device: self,
+            setpoint,
+            restored: false,
+        };
+        guard.device.set_setpoint(0.0)?;
+
+        let mut flow = Vec::new();
+        let mut thermal_conductivity = Vec::new();
+        let start = Instant::now();
+        let mut offset = Duration::ZERO;
+        while offset < config.duration {
+            let scheduled = start + offset;
+            let now = Instant::now();
+            if scheduled > now {
+                thread::sleep(scheduled - now);
+            }
+            let time = start.elapsed().as_secs_f32();
+            flow.push((time, guard.device.read_measured_value()?));
+            if config.thermal_conductivity {
+                let ticks = guard.device.measure_raw_thermal_conductivity()?;
+                thermal_conductivity.push((time, ticks as f32));
+            }
+            offset += config.sample_interval;
+        }
+        guard.restore()?;
+
+        let trend = Trend::fit(&flow);
+        let passed = trend.max.abs() <= config.flow_threshold
+            && config.max_slope.is_none_or(|max_slope| trend.slope.abs() <= max_slope);
+        Ok(LeakTestReport {
+            samples: flow.len(),
+            flow: trend,
+            thermal_conductivity: config
+                .thermal_conductivity
+                .then(|| Trend::fit(&thermal_conductivity)),
+            passed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Drift, EmulatorHandle, Fault, Sfc6xxxEmulator};
+
+    fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::default();
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    fn config() -> LeakTestConfig {
+        LeakTestConfig {
+            sample_interval: Duration::from_millis(20),
+            ..LeakTestConfig::new(Duration::from_millis(200), 1.0)
+        }
+    }
+
+    #[test]
+    fn trend_of_a_line() {
+        let trend = Trend::fit(&[(0.0, 1.0), (1.0, 0.5), (2.0, 0.0), (3.0, -1.5)]);
+        assert_eq!(trend.mean, 0.0);
+        assert_eq!(trend.max, -1.5);
+        assert!((trend.slope + 0.8).abs() < 1e-6);
+        assert_eq!(Trend::fit(&[(0.0, 2.0)]).slope, 0.0);
+    }
+
+    #[test]
+    fn tight_line_passes() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(1.5).unwrap();
+        let report = device.leak_test(config()).unwrap();
+        assert!(report.passed);
+        assert_eq!(report.samples, 10);
+        assert_eq!(report.flow, Trend::default());
+        assert_eq!(report.thermal_conductivity, None);
+        assert_eq!(handle.setpoint(), 1.5);
+    }
+
+    #[test]
+    fn drift_is_measured_as_slope() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(1.5).unwrap();
+        handle.set_drift(Drift {
+            flow_per_second: 0.5,
+            thermal_conductivity_per_second: 1000.0,
+        });
+        let report = device
+            .leak_test(LeakTestConfig {
+                max_slope: Some(0.1),
+                thermal_conductivity: true,
+                ..config()
+            })
+            .unwrap();
+        assert!((report.flow.slope - 0.5).abs() < 0.05, "slope {}", report.flow.slope);
+        assert!(report.flow.max > report.flow.mean && report.flow.max < 0.2);
+        let thermal_conductivity = report.thermal_conductivity.unwrap();
+        assert!((thermal_conductivity.slope - 1000.0).abs() < 100.0);
+        assert!(thermal_conductivity.mean > 1200.0);
+        // within the flow threshold but rising too fast
+        assert!(!report.passed);
+        assert_eq!(handle.setpoint(), 1.5);
+    }
+
+    #[test]
+    fn setpoint_is_restored_after_an_error() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(2.0).unwrap();
+        // the setpoint is read and zeroed untouched, the first sample fails
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x04));
+        device.set_retry(None);
+        assert!(device.leak_test(config()).is_err());
+        assert_eq!(handle.setpoint(), 2.0);
+    }
+}
@@ -3,12 +3,58 @@
 //! Sensirion's SFC6xxx mass flow controllers. The code was based arround the official
 //! [python SHDLC](https://sensirion.github.io/python-uart-sfx6xxx/) library and should match
 //! match its interface, while using Rust's powerful Result type.
+//! ## no_std
+//! Without the default `std` feature the crate is `no_std`. The blocking `device` module is left
+//! out, the async device (with `async`) and the `embedded` module (with `embedded-io`) remain
+//! and need no allocator.
 //! ## Testing
 //! Several tests have been written to tests this library's functionality and a majority of them
 //! are in device.rs. Most test reads and checks values but several functions like
 //! [get_serial_number](device::Device::get_serial_number) and [get_article_code](device::Device::get_article_code)
 //! cannot be accuratley tested. In these cases the code checks to see if the response errored and nothing else.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+// nothing received from a device may panic the driver, see "Panics" in the sfc-core README
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::unreachable
+    )
+)]
 
+#[cfg(feature = "async")]
+pub mod async_device;
+#[cfg(feature = "std")]
+pub mod autotune;
+#[cfg(feature = "std")]
+pub mod averaging;
+#[cfg(feature = "std")]
+pub mod calibration;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(any(feature = "std", feature = "async", feature = "embedded-io"))]
+pub mod commands;
+#[cfg(feature = "std")]
 pub mod device;
+#[cfg(feature = "embedded-io")]
+pub mod embedded;
+#[cfg(feature = "std")]
+pub mod leak_test;
+#[cfg(feature = "poller")]
+pub mod poller;
+#[cfg(feature = "std")]
+pub mod startup;
+#[cfg(feature = "std")]
+pub mod statistics;
+#[cfg(feature = "supervisor")]
+pub mod supervisor;
+#[cfg(feature = "std")]
+pub mod valve_exercise;
+#[cfg(all(feature = "std", any(test, feature = "emulator")))]
+pub mod emulator;
+#[cfg(feature = "std")]
 pub use serialport;
 pub use sfc_core;
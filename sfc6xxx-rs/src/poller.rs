@@ -0,0 +1,362 @@
+//! An [AsyncDevice] polled by a tokio task of its own, available with the `poller` feature.
+//! [AsyncDevice::spawn_poller] moves the device into the task, which reads a [Measurement]
+//! every interval and publishes it as a [PollSnapshot] through a `tokio::sync::watch` channel.
+//! The [PollerHandle] pauses the polling, changes the interval and runs commands on the device
+//! between two polls:
+//! ```no_run
+//! # async fn run(
+//! #     device: sfc6xxx_rs::async_device::AsyncDevice<
+//! #         sfc6xxx_rs::sfc_core::async_transport::FromTokio<tokio::io::DuplexStream>,
+//! #         sfc6xxx_rs::sfc_core::async_transport::TokioDelay,
+//! #     >,
+//! # ) -> Result<(), sfc6xxx_rs::sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//!
+//! let (poller, mut snapshots) = device.spawn_poller(Duration::from_millis(100));
+//! poller.set_setpoint(2.5).await?;
+//! let serial = poller.run(|device| Box::pin(device.get_serial_number())).await?;
+//! while snapshots.changed().await.is_ok() {
+//!     if let Some(measurement) = &snapshots.borrow().measurement {
+//!         println!("{}: {}", serial, measurement);
+//!     }
+//! }
+//! // the task stopped after too many failed polls in a row
+//! let device = poller.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+//! A failed poll is published in the snapshot and the next one tried at the next interval. The
+//! task stops after [PollerConfig::max_consecutive_errors] of them in a row, which closes the
+//! channel, and [PollerHandle::shutdown] still returns the device.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sfc_core::async_transport::{FromTokio, TokioDelay};
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::GasUnit;
+use sfc_core::measurement::{Measurement, ValueScale};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::async_device::AsyncDevice;
+
+/// A stream a polled device is reached through
+pub trait PolledStream: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static> PolledStream for S {}
+
+/// An [AsyncDevice] on a tokio stream, the device a poller runs
+pub type TokioDevice<S> = AsyncDevice<FromTokio<S>, TokioDelay>;
+
+/// The future of a command run by [PollerHandle::run]
+pub type CommandFuture<'a, R> = Pin<Box<dyn Future<Output = Result<R, DeviceError>> + Send + 'a>>;
+
+/// How [AsyncDevice::spawn_poller_with] polls the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PollerConfig {
+    /// The time from the start of one poll to the start of the next
+    pub interval: Duration,
+    /// The task stops after this many failed polls in a row, 0 never stops it
+    pub max_consecutive_errors: u32,
+}
+
+impl Default for PollerConfig {
+    /// A poll every 100ms, stopping after five failed polls in a row
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            max_consecutive_errors: 5,
+        }
+    }
+}
+
+/// What a poller read in its latest polls
+#[derive(Clone, Debug, Default)]
+pub struct PollSnapshot {
+    /// The latest measurement. It is kept when a later poll fails.
+    pub measurement: Option<Measurement>,
+    /// The error of the latest poll, [None] if it succeeded
+    pub error: Option<Arc<DeviceError>>,
+    /// The number of polls so far, failed ones included
+    pub polls: u64,
+    /// The number of polls in a row that failed
+    pub consecutive_errors: u32,
+    /// True while [PollerHandle::pause] holds the polling
+    pub paused: bool,
+}
+
+type Job<S> = Box<
+    dyn for<'a> FnOnce(&'a mut TokioDevice<S>) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>
+        + Send,
+>;
+
+/// Boxes a command that answers through its own channel
+fn job<S: PolledStream, F>(command: F) -> Job<S>
+where
+    F: for<'a> FnOnce(&'a mut TokioDevice<S>) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>
+        + Send
+        + 'static,
+{
+    Box::new(command)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    interval: Duration,
+    paused: bool,
+}
+
+/// Controls the task started by [AsyncDevice::spawn_poller], see the
+/// [module documentation](self). Dropping the handle stops the task and drops the device,
+/// [PollerHandle::shutdown] returns it instead.
+pub struct PollerHandle<S: PolledStream> {
+    commands: mpsc::Sender<Job<S>>,
+    settings: watch::Sender<Settings>,
+    task: JoinHandle<TokioDevice<S>>,
+}
+
+impl<S: PolledStream> TokioDevice<S> {
+    /// Moves the device into a tokio task that polls it every `interval` with the default
+    /// [PollerConfig::max_consecutive_errors]. Must be called from within a tokio runtime.
+    pub fn spawn_poller(
+        self,
+        interval: Duration,
+    ) -> (PollerHandle<S>, watch::Receiver<PollSnapshot>) {
+        let config = PollerConfig {
+            interval,
+            ..PollerConfig::default()
+        };
+        self.spawn_poller_with(config)
+    }
+
+    /// Moves the device into a tokio task that polls it as configured. The first poll is right
+    /// away, a late poll moves the later ones instead of polling several times back to back.
+    pub fn spawn_poller_with(
+        self,
+        config: PollerConfig,
+    ) -> (PollerHandle<S>, watch::Receiver<PollSnapshot>) {
+        let (snapshot, snapshots) = watch::channel(PollSnapshot::default());
+        let (commands, commands_rx) = mpsc::channel(8);
+        let (settings, settings_rx) = watch::channel(Settings {
+            interval: config.interval,
+            paused: false,
+        });
+        let task = Task {
+            device: self,
+            max_consecutive_errors: config.max_consecutive_errors,
+            context: None,
+            snapshot,
+            commands: commands_rx,
+            settings: settings_rx,
+        };
+        let handle = PollerHandle {
+            commands,
+            settings,
+            task: tokio::spawn(task.run()),
+        };
+        (handle, snapshots)
+    }
+}
+
+impl<S: PolledStream> PollerHandle<S> {
+    /// Runs a command on the device between two polls and waits for its result. Commands run
+    /// in the order they were submitted. Fails with an [IoError](DeviceError::IoError) of kind
+    /// `BrokenPipe` if the task has stopped.
+    ///
+    /// The measurement is labelled with the unit, serial number, setpoint and full scale read
+    /// when polling starts. They are read again after every command, in case it changed them.
+    /// ```no_run
+    /// # async fn run(
+    /// #     poller: &sfc6xxx_rs::poller::PollerHandle<tokio::io::DuplexStream>,
+    /// # ) -> Result<(), sfc6xxx_rs::sfc_core::error::DeviceError> {
+    /// let gain = poller
+    ///     .run(|device| Box::pin(async move {
+    ///         device.set_controller_gain(0.8).await?;
+    ///         device.get_controller_gain().await
+    ///     }))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn run<R, F>(&self, command: F) -> Result<R, DeviceError>
+    where
+        R: Send + 'static,
+        F: for<'a> FnOnce(&'a mut TokioDevice<S>) -> CommandFuture<'a, R> + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job = job(move |device| {
+            Box::pin(async move {
+                let _ = reply.send(command(device).await);
+            })
+        });
+        if self.commands.send(job).await.is_err() {
+            return Err(stopped());
+        }
+        result.await.unwrap_or_else(|_| Err(stopped()))
+    }
+
+    /// Sets the setpoint between two polls, see [PollerHandle::run]
+    pub async fn set_setpoint(&self, setpoint: f32) -> Result<(), DeviceError> {
+        self.run(move |device| Box::pin(device.set_setpoint(setpoint)))
+            .await
+    }
+
+    /// Stops polling until [PollerHandle::resume], commands still run meanwhile
+    pub fn pause(&self) {
+        self.settings.send_modify(|settings| settings.paused = true);
+    }
+
+    /// Polls again, starting right away
+    pub fn resume(&self) {
+        self.settings
+            .send_modify(|settings| settings.paused = false);
+    }
+
+    /// Changes the interval, the next poll is one new interval after the change
+    ///
+    /// # Panics
+    /// If the interval is zero
+    pub fn set_interval(&self, interval: Duration) {
+        assert!(!interval.is_zero(), "the poll interval must not be zero");
+        self.settings
+            .send_modify(|settings| settings.interval = interval);
+    }
+
+    /// Returns true once the task has stopped after too many failed polls in a row
+    pub fn is_stopped(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stops polling and returns the device once the command or poll in progress has finished.
+    /// Commands submitted before run first.
+    pub async fn shutdown(self) -> TokioDevice<S> {
+        let Self {
+            commands,
+            settings,
+            task,
+        } = self;
+        drop(commands);
+        drop(settings);
+        task.await
+            .unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()))
+    }
+}
+
+fn stopped() -> DeviceError {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the poller has stopped").into()
+}
+
+/// The task polling one device
+struct Task<S: PolledStream> {
+    device: TokioDevice<S>,
+    max_consecutive_errors: u32,
+    /// The unit, serial number, setpoint and full scale, [None] until read or after a command
+    context: Option<(GasUnit, String, f32, f32)>,
+    snapshot: watch::Sender<PollSnapshot>,
+    commands: mpsc::Receiver<Job<S>>,
+    settings: watch::Receiver<Settings>,
+}
+
+impl<S: PolledStream> Task<S> {
+    /// Polls and runs the commands until the handle is gone or too many polls failed in a row
+    async fn run(mut self) -> TokioDevice<S> {
+        let mut settings = *self.settings.borrow_and_update();
+        let mut interval = poll_interval(settings.interval, false);
+        loop {
+            // the commands and polls run in the branches, so select! never cancels one
+            tokio::select! {
+                _ = interval.tick(), if !settings.paused => {
+                    if !self.poll().await {
+                        return self.device;
+                    }
+                }
+                command = self.commands.recv() => match command {
+                    Some(command) => {
+                        command(&mut self.device).await;
+                        self.context = None;
+                    }
+                    None => return self.device,
+                },
+                changed = self.settings.changed() => {
+                    if changed.is_err() {
+                        return self.device;
+                    }
+                    let new = *self.settings.borrow_and_update();
+                    if new.interval != settings.interval || (settings.paused && !new.paused) {
+                        // resuming polls right away, a new interval starts after it
+                        interval = poll_interval(new.interval, !settings.paused);
+                    }
+                    settings = new;
+                    self.snapshot.send_if_modified(|snapshot| {
+                        let modified = snapshot.paused != settings.paused;
+                        snapshot.paused = settings.paused;
+                        modified
+                    });
+                }
+            }
+        }
+    }
+
+    /// Reads a measurement and publishes it or the error. Returns false once the failures in a
+    /// row reached the limit.
+    async fn poll(&mut self) -> bool {
+        let result = self.measure().await;
+        let mut keep_polling = true;
+        self.snapshot.send_modify(|snapshot| {
+            snapshot.polls += 1;
+            match result {
+                Ok(measurement) => {
+                    snapshot.measurement = Some(measurement);
+                    snapshot.error = None;
+                    snapshot.consecutive_errors = 0;
+                }
+                Err(error) => {
+                    snapshot.error = Some(Arc::new(error));
+                    snapshot.consecutive_errors += 1;
+                    let limit = self.max_consecutive_errors;
+                    keep_polling = limit == 0 || snapshot.consecutive_errors < limit;
+                }
+            }
+        });
+        keep_polling
+    }
+
+    async fn measure(&mut self) -> Result<Measurement, DeviceError> {
+        let (unit, serial_number, setpoint, full_scale) = match &self.context {
+            Some(context) => context.clone(),
+            None => {
+                let context = (
+                    self.device.get_current_gas_unit().await?,
+                    self.device.get_serial_number().await?.to_string(),
+                    self.device.get_setpoint().await?,
+                    self.device.get_current_full_scale().await?,
+                );
+                self.context.insert(context).clone()
+            }
+        };
+        let value = self.device.read_measured_value().await?;
+        Ok(Measurement {
+            serial_number: Some(serial_number),
+            setpoint: Some(setpoint),
+            full_scale: Some(full_scale),
+            ..Measurement::new(value, unit, ValueScale::Physical)
+        })
+    }
+}
+
+/// Ticks every `period`, the first time right away or, if `delayed`, after one period
+fn poll_interval(period: Duration, delayed: bool) -> Interval {
+    let mut start = tokio::time::Instant::now();
+    if delayed {
+        start += period;
+    }
+    let mut interval = tokio::time::interval_at(start, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
@@ -0,0 +1,379 @@
+//! The checks a service runs before it starts using a controller. [Device::startup] makes sure
+//! the device answers, runs recent enough firmware, is the expected product, has no error
+//! pending and has a valid calibration active, and returns what it read as a [StartupReport]:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>) {
+//! use sfc6xxx_rs::startup::{LatchedErrors, StartupRequirements};
+//!
+//! let requirements = StartupRequirements {
+//!     min_firmware: Some((1, 2)),
+//!     product_type_prefix: Some("SFC6".to_string()),
+//!     latched_errors: LatchedErrors::Clear,
+//!     ..StartupRequirements::default()
+//! };
+//! match device.startup(requirements) {
+//!     Ok(report) => println!("{}", report),
+//!     Err(e) => panic!("refusing to run: {}", e),
+//! }
+//! # }
+//! ```
+//! The SFC6xxx has no command to read latched errors. A device in an error state refuses to
+//! measure with an error in the state of its response until it is reset, so that is what the
+//! startup checks for.
+
+use std::fmt::Display;
+use std::thread;
+use std::time::Duration;
+
+use sfc_core::error::{DeviceError, StateResponseError};
+use sfc_core::gasunit::GasUnit;
+use sfc_core::shdlc::Version;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+/// What [Device::startup] does about an error the device is in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LatchedErrors {
+    /// Fail with [StartupError::LatchedError]
+    #[default]
+    Fatal,
+    /// Reset the device and check again, failing if the error is still there
+    Clear,
+}
+
+/// What a device has to meet for [Device::startup] to succeed. The default checks the
+/// calibration and treats an error state as fatal, without a minimum firmware or product type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StartupRequirements {
+    /// The oldest accepted firmware as major and minor version
+    pub min_firmware: Option<(u8, u8)>,
+    /// The product type has to start with this, like `SFC6`
+    pub product_type_prefix: Option<String>,
+    pub latched_errors: LatchedErrors,
+    /// How long the device is given to start again after a reset that clears an error, 300ms
+    /// by default. A device that answers with a fatal error after that is still waited for
+    /// with a boot grace, see [Device::set_boot_grace].
+    pub reset_time: Duration,
+}
+
+impl Default for StartupRequirements {
+    fn default() -> Self {
+        Self {
+            min_firmware: None,
+            product_type_prefix: None,
+            latched_errors: LatchedErrors::default(),
+            reset_time: Duration::from_millis(300),
+        }
+    }
+}
+
+/// The requirement of a [Device::startup] that was not met
+#[derive(Debug)]
+pub enum StartupError {
+    /// The device did not answer the first command
+    Unreachable(DeviceError),
+    /// The firmware, the first value, is older than the required one, the second value. Both as
+    /// major and minor version.
+    Firmware((u8, u8), (u8, u8)),
+    /// The product type, the first value, does not start with the required prefix, the second
+    /// value
+    ProductType(String, String),
+    /// The device is in an error state, and was still after a reset if it was to be cleared
+    LatchedError(StateResponseError),
+    /// The active calibration with this index is not valid
+    InvalidCalibration(u32),
+    /// A command failed after the device answered
+    Device(DeviceError),
+}
+
+impl From<DeviceError> for StartupError {
+    fn from(error: DeviceError) -> Self {
+        Self::Device(error)
+    }
+}
+
+impl Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreachable(e) => write!(f, "the device does not answer: {}", e),
+            Self::Firmware(found, required) => write!(
+                f,
+                "firmware {}.{} is older than the required {}.{}",
+                found.0, found.1, required.0, required.1
+            ),
+            Self::ProductType(found, prefix) => {
+                write!(f, "product type {} does not start with {}", found, prefix)
+            }
+            Self::LatchedError(e) => write!(f, "the device is in an error state: {}", e),
+            Self::InvalidCalibration(index) => {
+                write!(f, "the active calibration {} is not valid", index)
+            }
+            Self::Device(e) => e.fmt(f),
+        }
+    }
+}
+
+/// The source is the error of the device, or its state for [StartupError::LatchedError]
+impl std::error::Error for StartupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unreachable(e) | Self::Device(e) => Some(e),
+            Self::LatchedError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// What [Device::startup] read from the device
+#[derive(Clone, Debug, PartialEq)]
+pub struct StartupReport {
+    pub version: Version,
+    pub product_type: String,
+    pub product_name: String,
+    pub article_code: String,
+    pub serial_number: String,
+    /// The index of the active calibration
+    pub calibration: u32,
+    pub gas_unit: GasUnit,
+    pub full_scale: f32,
+    /// The error a reset cleared, with [LatchedErrors::Clear]
+    pub cleared_error: Option<StateResponseError>,
+}
+
+/// One line for the log, like `SFC6000D-5SLM (3.000.001) serial EMU6000001, firmware 1.0,
+/// calibration 0 with 5 l/min full scale`
+impl Display for StartupReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) serial {}, firmware {}.{}, calibration {} with {} {} full scale",
+            self.product_name,
+            self.article_code,
+            self.serial_number,
+            self.version.firmware_major,
+            self.version.firmware_minor,
+            self.calibration,
+            self.full_scale,
+            self.gas_unit
+        )?;
+        if let Some(error) = &self.cleared_error {
+            write!(f, ", cleared: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport> Device<T> {
+    /// Checks the device against the requirements in the order a service needs them: it answers
+    /// [Device::get_version], the firmware and product type are as required, it measures
+    /// without an error state, resetting it once if [LatchedErrors::Clear], and the active
+    /// calibration is valid. The first requirement that is not met is returned as the error,
+    /// the checks after it don't run. The setpoint is not changed. A fatal error while the
+    /// device boots after the reset is not taken for a latched one with a boot grace, see
+    /// [Device::set_boot_grace].
+    pub fn startup(
+        &mut self,
+        requirements: StartupRequirements,
+    ) -> Result<StartupReport, StartupError> {
+        let version = self.get_version().map_err(StartupError::Unreachable)?;
+        let firmware = (version.firmware_major, version.firmware_minor);
+        if let Some(required) = requirements.min_firmware.filter(|&min| firmware < min) {
+            return Err(StartupError::Firmware(firmware, required));
+        }
+        let product_type = self.get_product_type()?;
+        if let Some(prefix) = requirements.product_type_prefix
+            && !product_type.starts_with(&prefix)
+        {
+            return Err(StartupError::ProductType(product_type, prefix));
+        }
+
+        let cleared_error = match (self.error_state()?, requirements.latched_errors) {
+            (None, _) => None,
+            (Some(error), LatchedErrors::Fatal) => return Err(StartupError::LatchedError(error)),
+            (Some(error), LatchedErrors::Clear) => {
+                self.reset_device()?;
+                thread::sleep(requirements.reset_time);
+                if let Some(error) = self.error_state()? {
+                    return Err(StartupError::LatchedError(error));
+                }
+                Some(error)
+            }
+        };
+
+        let calibration = self.get_calliration_number()?;
+        if !self.get_calibration_validity(calibration)? {
+            return Err(StartupError::InvalidCalibration(calibration));
+        }
+        Ok(StartupReport {
+            version,
+            product_type,
+            product_name: self.get_product_name()?,
+            article_code: self.get_article_code()?,
+            serial_number: self.get_serial_number()?,
+            calibration,
+            gas_unit: self.get_current_gas_unit()?,
+            full_scale: self.get_current_full_scale()?,
+            cleared_error,
+        })
+    }
+
+    /// The error state the device refuses to measure with, if any
+    fn error_state(&mut self) -> Result<Option<StateResponseError>, DeviceError> {
+        match self.read_measured_value() {
+            Ok(_) => Ok(None),
+            Err(DeviceError::StateResponse(error)) => Ok(Some(error)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorConfig, EmulatorHandle, Fault, Sfc6xxxEmulator};
+    use sfc_core::connection::BootGrace;
+
+    fn emulated_device(config: EmulatorConfig) -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::new(config);
+        let handle = emulator.handle();
+        let mut device = Device::new(emulator, 0).unwrap();
+        device.set_retry(None);
+        (device, handle)
+    }
+
+    fn requirements() -> StartupRequirements {
+        StartupRequirements {
+            min_firmware: Some((1, 0)),
+            product_type_prefix: Some("SFC6".to_string()),
+            reset_time: Duration::ZERO,
+            ..StartupRequirements::default()
+        }
+    }
+
+    #[test]
+    fn matching_device_starts() {
+        let (mut device, handle) = emulated_device(EmulatorConfig::default());
+        let report = device.startup(requirements()).unwrap();
+        assert_eq!(report.product_type, "SFC6000");
+        assert_eq!(report.serial_number, "EMU6000001");
+        assert_eq!(report.calibration, 0);
+        assert_eq!(report.full_scale, 5.0);
+        assert_eq!(report.cleared_error, None);
+        assert_eq!(
+            report.to_string(),
+            "SFC6000D-5SLM (3.000.001) serial EMU6000001, firmware 1.0, calibration 0 with 5 l/min \
+             full scale"
+        );
+        assert_eq!(handle.resets(), 0);
+    }
+
+    #[test]
+    fn old_firmware_is_rejected() {
+        let (mut device, handle) = emulated_device(EmulatorConfig::default());
+        let requirements = StartupRequirements {
+            min_firmware: Some((1, 2)),
+            ..requirements()
+        };
+        let error = device.startup(requirements).unwrap_err();
+        assert!(matches!(error, StartupError::Firmware((1, 0), (1, 2))));
+        assert_eq!(
+            error.to_string(),
+            "firmware 1.0 is older than the required 1.2"
+        );
+        // nothing after the version was read
+        assert_eq!(handle.requests().len(), 2);
+    }
+
+    #[test]
+    fn wrong_product_type_is_rejected() {
+        let (mut device, _handle) = emulated_device(EmulatorConfig {
+            product_type: "SFC5400".to_string(),
+            ..EmulatorConfig::default()
+        });
+        let error = device.startup(requirements()).unwrap_err();
+        assert!(
+            matches!(&error, StartupError::ProductType(found, prefix) if found == "SFC5400" && prefix == "SFC6")
+        );
+        assert_eq!(
+            error.to_string(),
+            "product type SFC5400 does not start with SFC6"
+        );
+    }
+
+    #[test]
+    fn latched_errors_are_fatal_or_cleared() {
+        let (mut device, handle) = emulated_device(EmulatorConfig::default());
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        let error = device.startup(requirements()).unwrap_err();
+        assert!(matches!(
+            error,
+            StartupError::LatchedError(StateResponseError::MeasureLoopNotRunning)
+        ));
+
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        let requirements = StartupRequirements {
+            latched_errors: LatchedErrors::Clear,
+            ..requirements()
+        };
+        let report = device.startup(requirements).unwrap();
+        assert_eq!(
+            report.cleared_error,
+            Some(StateResponseError::MeasureLoopNotRunning)
+        );
+        assert_eq!(handle.resets(), 1);
+    }
+
+    #[test]
+    fn a_device_booting_after_the_reset_is_waited_for() {
+        let (mut device, handle) = emulated_device(EmulatorConfig {
+            boot_errors: Duration::from_millis(50),
+            ..EmulatorConfig::default()
+        });
+        device.set_boot_grace(Some(BootGrace::default()));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        let requirements = StartupRequirements {
+            latched_errors: LatchedErrors::Clear,
+            ..requirements()
+        };
+        let report = device.startup(requirements).unwrap();
+        assert_eq!(
+            report.cleared_error,
+            Some(StateResponseError::MeasureLoopNotRunning)
+        );
+        assert!(device.stats().retries > 0);
+    }
+
+    #[test]
+    fn invalid_calibration_is_rejected() {
+        let (mut device, _handle) = emulated_device(EmulatorConfig {
+            active_calibration: 3,
+            ..EmulatorConfig::default()
+        });
+        let error = device.startup(StartupRequirements::default()).unwrap_err();
+        assert!(matches!(error, StartupError::InvalidCalibration(3)));
+    }
+
+    #[test]
+    fn unplugged_device_is_unreachable() {
+        let (mut device, handle) = emulated_device(EmulatorConfig::default());
+        handle.unplug();
+        let error = device.startup(requirements()).unwrap_err();
+        assert!(matches!(
+            error,
+            StartupError::Unreachable(DeviceError::IoError(_))
+        ));
+
+        // the io error of the port is at the end of the chain
+        let error: Box<dyn std::error::Error> = Box::new(error);
+        let device = error.source().unwrap();
+        assert!(device.downcast_ref::<DeviceError>().is_some());
+        assert!(device.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+    }
+}
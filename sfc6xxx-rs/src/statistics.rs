@@ -0,0 +1,158 @@
+//! Statistics over a burst of readings, the stability check before accepting a calibration
+//! point. [Device::sample_statistics] reads the measured flow a number of times at an interval
+//! and summarizes it with [sfc_core::statistics]:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//!
+//! let statistics = device.sample_statistics(50, Duration::from_millis(20))?;
+//! if statistics.peak_to_peak > 0.01 {
+//!     println!("not settled yet, {} ± {}", statistics.mean, statistics.std_dev);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc_core::error::DeviceError;
+use sfc_core::flow_statistics::{FlowStatistics, RunningStatistics};
+use sfc_core::shdlc::TranslationError;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+impl<T: Transport> Device<T> {
+    /// Reads the measured flow `count` times, `interval` apart, and returns the mean, standard
+    /// deviation, minimum, maximum and peak to peak of the values. The first value is read right
+    /// away, a late read moves the later ones instead of reading several back to back.
+    ///
+    /// If a read fails after the first one, the statistics of the values read so far are
+    /// returned with the error in [FlowStatistics::interrupted]. An error in the first read is
+    /// returned as is.
+    ///
+    /// # Panics
+    /// If `count` is zero
+    pub fn sample_statistics(
+        &mut self,
+        count: u32,
+        interval: Duration,
+    ) -> Result<FlowStatistics, DeviceError> {
+        self.sample(count, interval, false)
+    }
+
+    /// [Device::sample_statistics] that keeps every value in [FlowStatistics::samples]
+    ///
+    /// # Panics
+    /// If `count` is zero
+    pub fn sample_statistics_with_samples(
+        &mut self,
+        count: u32,
+        interval: Duration,
+    ) -> Result<FlowStatistics, DeviceError> {
+        self.sample(count, interval, true)
+    }
+
+    fn sample(
+        &mut self,
+        count: u32,
+        interval: Duration,
+        keep_samples: bool,
+    ) -> Result<FlowStatistics, DeviceError> {
+        assert!(count > 0, "a burst needs at least one reading");
+        let mut statistics = RunningStatistics::new();
+        let mut samples = Vec::new();
+        let mut interrupted = None;
+        let mut due = Instant::now();
+        for _ in 0..count {
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+            due = due.max(now) + interval;
+            match self.read_measured_value() {
+                Ok(value) => {
+                    statistics.push(value);
+                    if keep_samples {
+                        samples.push(value);
+                    }
+                }
+                Err(e) if statistics.count() == 0 => return Err(e),
+                Err(e) => {
+                    interrupted = Some(e);
+                    break;
+                }
+            }
+        }
+        // the first value was read or its error returned, a burst has at least one reading
+        let Some(summary) = statistics.summary() else {
+            return Err(TranslationError::NoData.into());
+        };
+        Ok(FlowStatistics {
+            samples: keep_samples.then_some(samples),
+            interrupted,
+            ..summary
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorHandle, Fault, Sfc6xxxEmulator};
+
+    fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::default();
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    #[test]
+    fn burst_at_the_interval() {
+        let (mut device, _handle) = emulated_device();
+        device.set_setpoint(2.0).unwrap();
+        let start = Instant::now();
+        let statistics = device
+            .sample_statistics_with_samples(5, Duration::from_millis(10))
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(statistics.count, 5);
+        assert_eq!(statistics.mean, 2.0);
+        assert_eq!(statistics.std_dev, 0.0);
+        assert_eq!(statistics.peak_to_peak, 0.0);
+        assert_eq!(statistics.samples, Some(vec![2.0; 5]));
+        assert!(statistics.is_complete());
+
+        let statistics = device.sample_statistics(3, Duration::ZERO).unwrap();
+        assert_eq!(statistics.count, 3);
+        assert!(statistics.samples.is_none());
+    }
+
+    #[test]
+    fn failed_read_returns_partial_statistics() {
+        let (mut device, handle) = emulated_device();
+        device.set_setpoint(1.0).unwrap();
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::DelayMs(0));
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        let statistics = device
+            .sample_statistics_with_samples(10, Duration::ZERO)
+            .unwrap();
+        assert_eq!(statistics.count, 2);
+        assert_eq!(statistics.samples.as_deref(), Some(&[1.0, 1.0][..]));
+        assert!(!statistics.is_complete());
+        assert!(matches!(
+            statistics.interrupted,
+            Some(DeviceError::StateResponse(_))
+        ));
+    }
+
+    #[test]
+    fn failed_first_read_is_an_error() {
+        let (mut device, handle) = emulated_device();
+        handle.inject_fault(Fault::ErrorState(0x2D));
+        assert!(device.sample_statistics(10, Duration::ZERO).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+//! A tokio service that keeps several SFC6xxx connected and polled, available with the
+//! `supervisor` feature. [Supervisor::spawn] starts a task per [DeviceDescriptor] that opens
+//! the port, reads a [Measurement] every poll interval and opens the port again with an
+//! exponential [Backoff] when the device stops answering or the adapter is pulled.
+//!
+//! The latest measurement and the [Health] of every device are published through
+//! `tokio::sync::watch` channels, commands are sent to the task of a device and answered once
+//! the device has:
+//! ```no_run
+//! # async fn run() -> Result<(), sfc6xxx_rs::sfc_core::error::DeviceError> {
+//! use sfc6xxx_rs::supervisor::{DeviceDescriptor, PortSource, Supervisor};
+//!
+//! let supervisor = Supervisor::spawn([
+//!     DeviceDescriptor::new(PortSource::Path("/dev/ttyUSB0".into()), 0),
+//!     DeviceDescriptor::new(PortSource::Path("/dev/ttyUSB1".into()), 0),
+//! ]);
+//! supervisor.set_setpoint(1, 2.5).await?;
+//! let mut flow = supervisor.measurement(0);
+//! while flow.changed().await.is_ok() {
+//!     if let Some(measurement) = &*flow.borrow() {
+//!         println!("{}", measurement.to_csv_row());
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sfc_core::async_transport::{FromTokio, TokioDelay};
+use sfc_core::discovery::{PortCandidate, find_sensirion_ports};
+use sfc_core::error::DeviceError;
+use sfc_core::gasunit::GasUnit;
+use sfc_core::measurement::{Measurement, ValueScale};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_serial::{DataBits, FlowControl, Parity, SerialPortBuilderExt, StopBits};
+
+use crate::async_device::AsyncDevice;
+
+/// A stream a supervised device is reached through
+pub trait SupervisedStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> SupervisedStream for S {}
+
+/// The future returned by a [PortSource::Custom] connector
+pub type ConnectFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn SupervisedStream>, DeviceError>> + Send>>;
+
+/// Where the stream to a device comes from. It is opened again for every reconnect.
+#[derive(Clone)]
+pub enum PortSource {
+    /// A serial port by name, like "/dev/ttyUSB0" or "COM4"
+    Path(String),
+    /// The first port found by [find_sensirion_ports] the filter accepts, looked up again on
+    /// every reconnect so the adapter may come back under another name
+    Discover(Arc<dyn Fn(&PortCandidate) -> bool + Send + Sync>),
+    /// Any other stream, like a TCP connection to a serial device server
+    Custom(Arc<dyn Fn() -> ConnectFuture + Send + Sync>),
+}
+
+impl PortSource {
+    /// A [PortSource::Custom] from a function returning the future that opens the stream
+    pub fn custom<F, Fut, S>(connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<S, DeviceError>> + Send + 'static,
+        S: SupervisedStream + 'static,
+    {
+        Self::Custom(Arc::new(move || {
+            let future = connect();
+            Box::pin(async move { Ok(Box::new(future.await?) as Box<dyn SupervisedStream>) })
+        }))
+    }
+
+    async fn open(&self, baud_rate: u32) -> Result<Box<dyn SupervisedStream>, DeviceError> {
+        let port_name = match self {
+            Self::Path(port_name) => port_name.clone(),
+            Self::Discover(filter) => find_sensirion_ports()
+                .into_iter()
+                .find(|candidate| filter(candidate))
+                .ok_or_else(|| {
+                    serialport::Error::new(
+                        serialport::ErrorKind::NoDevice,
+                        "no serial port matches the filter",
+                    )
+                })?
+                .port_name,
+            Self::Custom(connect) => return connect().await,
+        };
+        let stream = tokio_serial::new(port_name, baud_rate)
+            .data_bits(DataBits::Eight)
+            .parity(Parity::None)
+            .stop_bits(StopBits::One)
+            .flow_control(FlowControl::None)
+            .open_native_async()?;
+        Ok(Box::new(stream))
+    }
+}
+
+impl Debug for PortSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(port_name) => f.debug_tuple("Path").field(port_name).finish(),
+            Self::Discover(_) => f.write_str("Discover(..)"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// One device for the supervisor to look after
+#[derive(Clone, Debug)]
+pub struct DeviceDescriptor {
+    pub source: PortSource,
+    /// The baudrate serial ports are opened with
+    pub baud_rate: u32,
+    pub address: u8,
+    /// The time from the start of one read to the start of the next
+    pub poll_interval: Duration,
+    /// The port is opened again after this many failed reads in a row. A port that reports an
+    /// error itself, like a pulled adapter, is opened again straight away.
+    pub max_consecutive_errors: u32,
+}
+
+impl DeviceDescriptor {
+    /// A device at 115200 baud read once a second, reconnecting after three failed reads
+    pub fn new(source: PortSource, address: u8) -> Self {
+        Self {
+            source,
+            baud_rate: 115200,
+            address,
+            poll_interval: Duration::from_secs(1),
+            max_consecutive_errors: 3,
+        }
+    }
+}
+
+/// How long to wait between two attempts to connect. The wait doubles with every failed
+/// attempt up to the maximum and starts over once the device answers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    /// Starting at 100ms, waiting at most 10s
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The state of the connection to a supervised device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Health {
+    /// The first attempt to connect is running
+    Connecting,
+    /// The device answered and is polled
+    Online,
+    /// The device was lost or could not be reached, the next attempt to connect is waiting for
+    /// its backoff
+    Reconnecting {
+        /// The failed attempts since the device last answered
+        attempt: u32,
+        /// Why the last attempt failed or the connection was lost
+        last_error: String,
+    },
+}
+
+enum Request {
+    SetSetpoint(f32, oneshot::Sender<Result<(), DeviceError>>),
+}
+
+impl Request {
+    /// Answers a request that can't be sent because the device is not connected
+    fn reject(self) {
+        match self {
+            Self::SetSetpoint(_, reply) => {
+                let _ = reply.send(Err(not_connected()));
+            }
+        }
+    }
+}
+
+fn not_connected() -> DeviceError {
+    std::io::Error::new(std::io::ErrorKind::NotConnected, "the device is not connected").into()
+}
+
+/// Errors that mean the port itself is gone, there's no point in reading it again
+fn is_disconnect(error: &DeviceError) -> bool {
+    matches!(error, DeviceError::IoError(_) | DeviceError::PortError(_))
+}
+
+struct Supervised {
+    measurement: watch::Receiver<Option<Measurement>>,
+    health: watch::Receiver<Health>,
+    requests: mpsc::Sender<Request>,
+    task: JoinHandle<()>,
+}
+
+/// Runs a task per device that keeps it connected and polled, see the
+/// [module documentation](self). The devices are numbered in the order of their descriptors.
+/// Dropping the supervisor stops every task.
+pub struct Supervisor {
+    devices: Vec<Supervised>,
+}
+
+impl Supervisor {
+    /// Starts a task for every device with the default [Backoff]. Must be called from within a
+    /// tokio runtime.
+    pub fn spawn(descriptors: impl IntoIterator<Item = DeviceDescriptor>) -> Self {
+        Self::with_backoff(descriptors, Backoff::default())
+    }
+
+    /// Starts a task for every device that reconnects with the given backoff
+    pub fn with_backoff(
+        descriptors: impl IntoIterator<Item = DeviceDescriptor>,
+        backoff: Backoff,
+    ) -> Self {
+        let devices = descriptors
+            .into_iter()
+            .map(|descriptor| {
+                let (measurement_tx, measurement) = watch::channel(None);
+                let (health_tx, health) = watch::channel(Health::Connecting);
+                let (requests, requests_rx) = mpsc::channel(8);
+                let task = Task {
+                    descriptor,
+                    backoff,
+                    measurement: measurement_tx,
+                    health: health_tx,
+                    requests: requests_rx,
+                };
+                Supervised {
+                    measurement,
+                    health,
+                    requests,
+                    task: tokio::spawn(task.run()),
+                }
+            })
+            .collect();
+        Self { devices }
+    }
+
+    /// The number of supervised devices
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// The latest measurement of a device, [None] until it was first read
+    ///
+    /// # Panics
+    /// If there is no device with that number
+    pub fn measurement(&self, device: usize) -> watch::Receiver<Option<Measurement>> {
+        self.device(device).measurement.clone()
+    }
+
+    /// The state of the connection to a device
+    ///
+    /// # Panics
+    /// If there is no device with that number
+    pub fn health(&self, device: usize) -> watch::Receiver<Health> {
+        self.device(device).health.clone()
+    }
+
+    /// Sets the setpoint of a device and waits until it answered. Fails with an
+    /// [IoError](DeviceError::IoError) of kind `NotConnected` while the device is being
+    /// reconnected.
+    ///
+    /// # Panics
+    /// If there is no device with that number
+    pub async fn set_setpoint(&self, device: usize, setpoint: f32) -> Result<(), DeviceError> {
+        let (reply, response) = oneshot::channel();
+        let requests = &self.device(device).requests;
+        if requests.send(Request::SetSetpoint(setpoint, reply)).await.is_err() {
+            return Err(not_connected());
+        }
+        response.await.unwrap_or_else(|_| Err(not_connected()))
+    }
+
+    /// The channels of a device. A number out of range is a bug of the caller, not something a
+    /// device sent, and panics like indexing.
+    #[allow(clippy::indexing_slicing)]
+    fn device(&self, device: usize) -> &Supervised {
+        &self.devices[device]
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        for device in &self.devices {
+            device.task.abort();
+        }
+    }
+}
+
+type Connected = AsyncDevice<FromTokio<Box<dyn SupervisedStream>>, TokioDelay>;
+
+/// The task looking after one device
+struct Task {
+    descriptor: DeviceDescriptor,
+    backoff: Backoff,
+    measurement: watch::Sender<Option<Measurement>>,
+    health: watch::Sender<Health>,
+    requests: mpsc::Receiver<Request>,
+}
+
+impl Task {
+    async fn run(mut self) {
+        let mut attempt = 0;
+        let mut wait = self.backoff.initial;
+        loop {
+            let error = match self.connect().await {
+                Ok(device) => {
+                    attempt = 0;
+                    wait = self.backoff.initial;
+                    self.health.send_replace(Health::Online);
+                    match self.poll(device).await {
+                        Some(error) => error,
+                        // the supervisor was dropped
+                        None => return,
+                    }
+                }
+                Err(error) => error,
+            };
+            attempt += 1;
+            self.health.send_replace(Health::Reconnecting {
+                attempt,
+                last_error: error.to_string(),
+            });
+            if !self.wait(wait).await {
+                return;
+            }
+            wait = (wait * 2).min(self.backoff.max);
+        }
+    }
+
+    async fn connect(&self) -> Result<Connected, DeviceError> {
+        let stream = self.descriptor.source.open(self.descriptor.baud_rate).await?;
+        AsyncDevice::new(FromTokio(stream), TokioDelay, self.descriptor.address).await
+    }
+
+    /// Waits out a backoff, rejecting the requests that arrive meanwhile. Returns false once
+    /// the supervisor was dropped.
+    async fn wait(&mut self, duration: Duration) -> bool {
+        let sleep = tokio::time::sleep(duration);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return true,
+                request = self.requests.recv() => match request {
+                    Some(request) => request.reject(),
+                    None => return false,
+                },
+            }
+        }
+    }
+
+    /// Polls the device and runs the requests until the connection is lost, returning why.
+    /// Returns [None] once the supervisor was dropped.
+    async fn poll(&mut self, mut device: Connected) -> Option<DeviceError> {
+        let mut context = match read_context(&mut device).await {
+            Ok(context) => context,
+            Err(error) => return Some(error),
+        };
+        let mut interval = tokio::time::interval(self.descriptor.poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut errors = 0;
+        loop {
+            // the commands run in the branches, so select! never cancels one
+            tokio::select! {
+                _ = interval.tick() => match device.read_measured_value().await {
+                    Ok(value) => {
+                        errors = 0;
+                        self.measurement.send_replace(Some(context.measurement(value)));
+                    }
+                    Err(error) => {
+                        errors += 1;
+                        let limit = self.descriptor.max_consecutive_errors;
+                        if is_disconnect(&error) || errors >= limit {
+                            return Some(error);
+                        }
+                    }
+                },
+                request = self.requests.recv() => match request {
+                    Some(Request::SetSetpoint(setpoint, reply)) => {
+                        let result = device.set_setpoint(setpoint).await;
+                        let lost = result.as_ref().is_err_and(is_disconnect);
+                        if result.is_ok() {
+                            context.setpoint = setpoint;
+                        }
+                        let _ = reply.send(result);
+                        if lost {
+                            return Some(not_connected());
+                        }
+                    }
+                    None => return None,
+                },
+            }
+        }
+    }
+}
+
+/// What a measurement is labelled with, read once per connection
+struct Context {
+    unit: GasUnit,
+    serial_number: String,
+    setpoint: f32,
+}
+
+impl Context {
+    fn measurement(&self, value: f32) -> Measurement {
+        Measurement {
+            serial_number: Some(self.serial_number.clone()),
+            setpoint: Some(self.setpoint),
+            ..Measurement::new(value, self.unit, ValueScale::Physical)
+        }
+    }
+}
+
+async fn read_context(device: &mut Connected) -> Result<Context, DeviceError> {
+    Ok(Context {
+        unit: device.get_current_gas_unit().await?,
+        serial_number: device.get_serial_number().await?.to_string(),
+        setpoint: device.get_setpoint().await?,
+    })
+}
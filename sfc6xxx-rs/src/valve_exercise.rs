@@ -0,0 +1,261 @@
+//! Exercising the valve during maintenance, so it doesn't stick after sitting still for a long
+//! time. [Device::exercise_valve] moves the setpoint between zero and a high setpoint a number
+//! of times and checks that the measured flow follows every cycle:
+//! ```no_run
+//! # fn run(device: &mut sfc6xxx_rs::device::Device<sfc6xxx_rs::serialport::TTYPort>)
+//! #     -> Result<(), sfc_core::error::DeviceError> {
+//! use std::time::Duration;
+//!
+//! let report = device.exercise_valve(10, 2.5, Duration::from_secs(2))?;
+//! for (i, cycle) in report.cycles.iter().enumerate() {
+//!     println!("cycle {}: rise {:?}, fall {:?}", i, cycle.rise_time, cycle.fall_time);
+//! }
+//! if report.failures > 0 {
+//!     println!("the flow did not follow in {} cycles", report.failures);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//! The setpoint is zero when the exercise ends, also when a command fails.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sfc_core::error::DeviceError;
+use sfc_core::transport::Transport;
+
+use crate::device::Device;
+
+/// The flows the measured flow has to cross for a cycle of [Device::exercise_valve_with] to
+/// count as responding, in the unit of the active calibration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExerciseThresholds {
+    /// The flow has to rise above this at the high setpoint
+    pub rise_above: f32,
+    /// The flow has to fall below this at zero
+    pub fall_below: f32,
+}
+
+impl ExerciseThresholds {
+    /// 90% and 10% of the high setpoint, the thresholds [Device::exercise_valve] uses
+    pub fn for_setpoint(high_setpoint: f32) -> Self {
+        Self {
+            rise_above: high_setpoint * 0.9,
+            fall_below: high_setpoint * 0.1,
+        }
+    }
+}
+
+/// How the flow followed the setpoint in one cycle
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExerciseCycle {
+    /// The time from setting the high setpoint until the flow rose above the threshold, [None]
+    /// if it didn't within the dwell
+    pub rise_time: Option<Duration>,
+    /// The time from setting zero until the flow fell below the threshold, [None] if it didn't
+    /// within the dwell
+    pub fall_time: Option<Duration>,
+}
+
+impl ExerciseCycle {
+    /// Returns true if the flow crossed both thresholds
+    pub fn responded(&self) -> bool {
+        self.rise_time.is_some() && self.fall_time.is_some()
+    }
+}
+
+/// The outcome of [Device::exercise_valve]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExerciseReport {
+    /// Every cycle in the order they ran
+    pub cycles: Vec<ExerciseCycle>,
+    /// The number of cycles the flow did not follow the setpoint in
+    pub failures: u32,
+}
+
+impl ExerciseReport {
+    /// Returns true if the flow followed the setpoint in every cycle
+    pub fn passed(&self) -> bool {
+        self.failures == 0
+    }
+}
+
+/// Sets the setpoint to zero when dropped
+struct ZeroSetpoint<'a, T: Transport> {
+    device: &'a mut Device<T>,
+    zeroed: bool,
+}
+
+impl<T: Transport> ZeroSetpoint<'_, T> {
+    /// Zeroes the setpoint and reports if that failed
+    fn zero(mut self) -> Result<(), DeviceError> {
+        self.zeroed = true;
+        self.device.set_setpoint(0.0)
+    }
+}
+
+impl<T: Transport> Drop for ZeroSetpoint<'_, T> {
+    fn drop(&mut self) {
+        if !self.zeroed {
+            // the exercise already failed, that error is the one returned
+            let _ = self.device.set_setpoint(0.0);
+        }
+    }
+}
+
+impl<T: Transport> Device<T> {
+    /// Moves the setpoint `cycles` times from `high_setpoint` back to zero, staying at each for
+    /// `dwell`, and measures how long the flow takes to rise above 90% and fall below 10% of
+    /// the high setpoint. See [Device::exercise_valve_with] for other thresholds.
+    ///
+    /// # Panics
+    /// If `dwell` is zero
+    pub fn exercise_valve(
+        &mut self,
+        cycles: u32,
+        high_setpoint: f32,
+        dwell: Duration,
+    ) -> Result<ExerciseReport, DeviceError> {
+        let thresholds = ExerciseThresholds::for_setpoint(high_setpoint);
+        self.exercise_valve_with(cycles, high_setpoint, dwell, thresholds)
+    }
+
+    /// [Device::exercise_valve] with the given thresholds. The flow is read back to back after
+    /// every setpoint change until it crosses the threshold, so the response times are as
+    /// precise as one read, then the rest of the dwell is waited out. A cycle the flow doesn't
+    /// cross a threshold in within the dwell counts as a failure and the exercise goes on.
+    ///
+    /// The setpoint is set to zero at the end. If a command fails the exercise stops with that
+    /// error after trying to zero it.
+    ///
+    /// # Panics
+    /// If `dwell` is zero
+    pub fn exercise_valve_with(
+        &mut self,
+        cycles: u32,
+        high_setpoint: f32,
+        dwell: Duration,
+        thresholds: ExerciseThresholds,
+    ) -> Result<ExerciseReport, DeviceError> {
+        assert!(!dwell.is_zero(), "the dwell must not be zero");
+        self.writable("exercise valve")?;
+        let guard = ZeroSetpoint {
+            device: self,
+            zeroed: false,
+        };
+        let mut report = ExerciseReport::default();
+        for _ in 0..cycles {
+            let cycle = ExerciseCycle {
+                rise_time: guard
+                    .device
+                    .step_valve(high_setpoint, dwell, |flow| flow > thresholds.rise_above)?,
+                fall_time: guard
+                    .device
+                    .step_valve(0.0, dwell, |flow| flow < thresholds.fall_below)?,
+            };
+            if !cycle.responded() {
+                report.failures += 1;
+            }
+            report.cycles.push(cycle);
+        }
+        guard.zero()?;
+        Ok(report)
+    }
+
+    /// Sets the setpoint and reads the flow until it has crossed the threshold, returning how
+    /// long that took, and waits until the dwell is over
+    fn step_valve(
+        &mut self,
+        setpoint: f32,
+        dwell: Duration,
+        crossed: impl Fn(f32) -> bool,
+    ) -> Result<Option<Duration>, DeviceError> {
+        self.set_setpoint(setpoint)?;
+        let start = Instant::now();
+        let mut response_time = None;
+        while response_time.is_none() && start.elapsed() < dwell {
+            if crossed(self.read_measured_value()?) {
+                response_time = Some(start.elapsed());
+            }
+        }
+        if let Some(rest) = dwell.checked_sub(start.elapsed()) {
+            thread::sleep(rest);
+        }
+        Ok(response_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{EmulatorConfig, EmulatorHandle, Fault, Sfc6xxxEmulator, StepResponse};
+
+    const DWELL: Duration = Duration::from_millis(100);
+
+    /// The flow follows a step critically damped with a time constant of 10ms, it crosses 90%
+    /// of a step after about 39ms
+    fn emulated_device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+        let emulator = Sfc6xxxEmulator::new(EmulatorConfig {
+            response: Some(StepResponse {
+                time_constant: Duration::from_millis(10),
+                critical_gain: 1.0,
+            }),
+            ..EmulatorConfig::default()
+        });
+        let handle = emulator.handle();
+        (Device::new(emulator, 0).unwrap(), handle)
+    }
+
+    #[test]
+    fn response_times_of_every_cycle() {
+        let (mut device, handle) = emulated_device();
+        let start = Instant::now();
+        let report = device.exercise_valve(3, 2.0, DWELL).unwrap();
+        assert!(start.elapsed() >= DWELL * 6);
+        assert_eq!(report.cycles.len(), 3);
+        assert!(report.passed());
+        for cycle in &report.cycles {
+            for time in [cycle.rise_time.unwrap(), cycle.fall_time.unwrap()] {
+                assert!(
+                    time > Duration::from_millis(35) && time < Duration::from_millis(60),
+                    "response time {:?}",
+                    time
+                );
+            }
+        }
+        assert_eq!(handle.setpoint(), 0.0);
+    }
+
+    #[test]
+    fn flow_not_following_counts_as_failure() {
+        let (mut device, handle) = emulated_device();
+        let thresholds = ExerciseThresholds {
+            rise_above: 2.5,
+            ..ExerciseThresholds::for_setpoint(2.0)
+        };
+        let report = device
+            .exercise_valve_with(2, 2.0, Duration::from_millis(50), thresholds)
+            .unwrap();
+        assert_eq!(report.failures, 2);
+        assert!(!report.passed());
+        assert!(report.cycles.iter().all(|cycle| cycle.rise_time.is_none()));
+        assert!(report.cycles.iter().all(|cycle| cycle.fall_time.is_some()));
+        assert_eq!(handle.setpoint(), 0.0);
+    }
+
+    #[test]
+    fn setpoint_is_zeroed_after_an_error() {
+        let (mut device, handle) = emulated_device();
+        device.set_retry(None);
+        let faults = handle.clone();
+        // fails the first command after the second cycle went to the high setpoint
+        let injector = thread::spawn(move || {
+            thread::sleep(DWELL * 5 / 2);
+            faults.inject_fault(Fault::ErrorState(0x2D));
+        });
+        let result = device.exercise_valve(5, 2.0, DWELL);
+        injector.join().unwrap();
+        assert!(matches!(result, Err(DeviceError::StateResponse(_))));
+        assert_eq!(handle.setpoint(), 0.0);
+    }
+}
@@ -0,0 +1,233 @@
+//! Runs the async device against the emulator through a tokio duplex stream.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features tokio,emulator,stream --test async_device`.
+#![cfg(all(feature = "tokio", feature = "emulator"))]
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use sfc6xxx_rs::async_device::AsyncDevice;
+use sfc6xxx_rs::emulator::{EmulatorConfig, EmulatorHandle, Fault, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::async_transport::{FromTokio, TokioDelay};
+use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+use sfc6xxx_rs::sfc_core::connection::RetryConfig;
+use sfc6xxx_rs::sfc_core::error::{DeviceError, StateResponseError};
+use sfc6xxx_rs::sfc_core::transport::Transport;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+
+/// Moves bytes between the far end of a duplex stream and the emulator
+async fn bridge(mut stream: DuplexStream, mut emulator: Sfc6xxxEmulator) {
+    // the emulator never blocks, a response that isn't ready yet is picked up later
+    Transport::set_timeout(&mut emulator, Duration::ZERO).unwrap();
+    let mut buf = [0_u8; 64];
+    loop {
+        match tokio::time::timeout(Duration::from_millis(1), stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return,
+            Ok(Ok(read)) => emulator.write_all(&buf[..read]).unwrap(),
+            Err(_) => {}
+        }
+        loop {
+            match emulator.read(&mut buf) {
+                Ok(read) => stream.write_all(&buf[..read]).await.unwrap(),
+                Err(e) if e.kind() == ErrorKind::TimedOut => break,
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+type Duplex = AsyncDevice<FromTokio<DuplexStream>, TokioDelay>;
+
+async fn device_with(config: EmulatorConfig) -> (Duplex, EmulatorHandle) {
+    let emulator = Sfc6xxxEmulator::new(config);
+    let handle = emulator.handle();
+    let (stream, far_end) = duplex(256);
+    tokio::spawn(bridge(far_end, emulator));
+    // the constructor probes the device
+    let device = AsyncDevice::new(FromTokio(stream), TokioDelay, 0).await.unwrap();
+    (device, handle)
+}
+
+#[tokio::test]
+async fn command_sequence() {
+    let (mut device, handle) = device_with(EmulatorConfig::default()).await;
+    assert_eq!(handle.requests().len(), 1);
+
+    device.set_setpoint(2.5).await.unwrap();
+    assert_eq!(device.get_setpoint().await.unwrap(), 2.5);
+    assert_eq!(device.read_measured_value().await.unwrap(), 2.5);
+    assert_eq!(device.read_average_measured_value(50).await.unwrap(), 2.5);
+    assert!(matches!(
+        device.read_average_measured_value(101).await,
+        Err(DeviceError::StateResponse(StateResponseError::ParameterError))
+    ));
+    assert_eq!(device.get_serial_number().await.unwrap(), "EMU6000001");
+    assert_eq!(device.get_version().await.unwrap().protocol_major, 2);
+    assert_eq!(handle.setpoint(), 2.5);
+}
+
+#[tokio::test]
+async fn async_and_blocking_decode_the_same() {
+    let (mut device, _handle) = device_with(EmulatorConfig::default()).await;
+    let mut blocking = sfc6xxx_rs::device::Device::new(Sfc6xxxEmulator::default(), 0).unwrap();
+
+    assert_eq!(device.get_product_name().await.unwrap(), blocking.get_product_name().unwrap());
+    assert_eq!(device.get_version().await.unwrap(), blocking.get_version().unwrap());
+    assert_eq!(
+        device.get_current_gas_unit().await.unwrap(),
+        blocking.get_current_gas_unit().unwrap()
+    );
+    assert_eq!(
+        format!("{:?}", device.get_calibration_gas_id(99).await),
+        format!("{:?}", blocking.get_calibration_gas_id(99))
+    );
+}
+
+#[tokio::test]
+async fn dropped_response_times_out_and_is_retried() {
+    let (mut device, handle) = device_with(EmulatorConfig::default()).await;
+    device.set_response_timeout(Duration::from_millis(30));
+
+    handle.inject_fault(Fault::DropResponse);
+    let start = Instant::now();
+    assert!(matches!(device.get_baudrate().await, Err(DeviceError::Timeout)));
+    assert!(start.elapsed() >= Duration::from_millis(30));
+
+    handle.inject_fault(Fault::DropResponse);
+    device.set_retry(Some(RetryConfig {
+        delay: Duration::from_millis(1),
+        ..Default::default()
+    }));
+    assert_eq!(device.get_baudrate().await.unwrap(), Baudrate::B115200);
+}
+
+#[cfg(feature = "stream")]
+mod measurement_stream {
+    use std::pin::pin;
+    use std::time::UNIX_EPOCH;
+
+    use futures_util::StreamExt;
+    use sfc6xxx_rs::async_device::StreamConfig;
+
+    use super::*;
+
+    fn every(milliseconds: u64, max_consecutive_errors: u32) -> StreamConfig {
+        StreamConfig {
+            interval: Duration::from_millis(milliseconds),
+            max_consecutive_errors,
+        }
+    }
+
+    #[tokio::test]
+    async fn samples_at_the_interval() {
+        let (mut device, _handle) = device_with(EmulatorConfig::default()).await;
+        device.set_setpoint(2.5).await.unwrap();
+
+        let start = Instant::now();
+        let measurements: Vec<_> = device.measurement_stream(every(25, 3)).take(4).collect().await;
+        assert!(start.elapsed() >= Duration::from_millis(75));
+
+        let measurements: Vec<_> = measurements.into_iter().map(Result::unwrap).collect();
+        for measurement in &measurements {
+            assert_eq!(measurement.value, 2.5);
+            assert_eq!(measurement.setpoint, Some(2.5));
+            assert_eq!(measurement.serial_number.as_deref(), Some("EMU6000001"));
+            assert!(measurement.timestamp > UNIX_EPOCH);
+        }
+        for pair in measurements.windows(2) {
+            let gap = pair[1].timestamp.duration_since(pair[0].timestamp).unwrap();
+            assert!(gap >= Duration::from_millis(20), "{:?} between samples", gap);
+        }
+    }
+
+    #[tokio::test]
+    async fn ends_after_consecutive_errors() {
+        let (mut device, handle) = device_with(EmulatorConfig::default()).await;
+        device.set_response_timeout(Duration::from_millis(20));
+        let mut measurements = pin!(device.measurement_stream(every(1, 2)));
+        assert!(measurements.next().await.unwrap().is_ok());
+
+        for _ in 0..3 {
+            handle.inject_fault(Fault::DropResponse);
+        }
+        assert!(matches!(measurements.next().await, Some(Err(DeviceError::Timeout))));
+        assert!(matches!(measurements.next().await, Some(Err(DeviceError::Timeout))));
+        assert!(measurements.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn an_error_between_good_reads_does_not_end_it() {
+        let (mut device, handle) = device_with(EmulatorConfig::default()).await;
+        device.set_response_timeout(Duration::from_millis(20));
+        let mut measurements = pin!(device.measurement_stream(every(1, 2)));
+        assert!(measurements.next().await.unwrap().is_ok());
+        handle.inject_fault(Fault::DropResponse);
+        assert!(measurements.next().await.unwrap().is_err());
+        assert!(measurements.next().await.unwrap().is_ok());
+        handle.inject_fault(Fault::DropResponse);
+        assert!(measurements.next().await.unwrap().is_err());
+        assert!(measurements.next().await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn alarms() {
+        use sfc6xxx_rs::sfc_core::alarm::{
+            Alarm, AlarmConfig, AlarmKind, AlarmState, AlarmTransition, Bound,
+        };
+
+        let (mut device, _handle) = device_with(EmulatorConfig::default()).await;
+        device.set_setpoint(2.5).await.unwrap();
+
+        let mut alarm = Alarm::new(AlarmConfig {
+            high: Some(Bound::Absolute(2.0)),
+            low: Some(Bound::PercentOfSetpoint(10.0)),
+            ..AlarmConfig::default()
+        });
+        let annotated: Vec<_> = device
+            .measurement_stream(every(5, 3))
+            .map(move |measurement| alarm.annotate(measurement))
+            .take(2)
+            .collect()
+            .await;
+        let statuses: Vec<_> = annotated.into_iter().map(|m| m.unwrap().alarm).collect();
+        assert_eq!(
+            statuses[0].transition,
+            Some(AlarmTransition::Activated(AlarmKind::High))
+        );
+        assert_eq!(statuses[1].state, AlarmState::Active(AlarmKind::High));
+        assert_eq!(statuses[1].transition, None);
+    }
+
+    /// A timer that keeps winning the select must not lose or garble a read
+    #[tokio::test]
+    async fn next_can_lose_a_select() {
+        let (mut device, _handle) = device_with(EmulatorConfig::default()).await;
+        let mut measurements = pin!(device.measurement_stream(every(20, 1)));
+        let mut received = 0;
+        let mut ticks = 0;
+        while received < 3 {
+            tokio::select! {
+                measurement = measurements.next() => {
+                    measurement.unwrap().unwrap();
+                    received += 1;
+                }
+                _ = tokio::time::sleep(Duration::from_millis(3)) => ticks += 1,
+            }
+        }
+        assert!(ticks > 0);
+    }
+
+    /// The device is not read while the consumer isn't asking for values
+    #[tokio::test]
+    async fn a_lagging_consumer_is_not_buffered_for() {
+        let (mut device, handle) = device_with(EmulatorConfig::default()).await;
+        let mut measurements = pin!(device.measurement_stream(every(5, 3)));
+        measurements.next().await.unwrap().unwrap();
+        let requests = handle.requests().len();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(handle.requests().len(), requests);
+
+        measurements.next().await.unwrap().unwrap();
+        assert_eq!(handle.requests().len(), requests + 1);
+    }
+}
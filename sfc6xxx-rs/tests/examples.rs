@@ -0,0 +1,62 @@
+//! The examples against the emulator, so they keep compiling and working with the driver.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features emulator --test examples`.
+#![cfg(feature = "emulator")]
+
+use std::time::Duration;
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::emulator::{EmulatorConfig, Fault, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::error::{DeviceError, StateResponseError};
+
+#[allow(dead_code)]
+#[path = "../examples/sfc6xxx-demo.rs"]
+mod demo;
+
+#[test]
+fn the_demo_runs_and_closes_the_valve() {
+    let emulator = Sfc6xxxEmulator::new(EmulatorConfig::default());
+    let handle = emulator.handle();
+    let mut device = Device::builder(emulator).address(0).build().unwrap();
+
+    demo::run(&mut device).unwrap();
+    assert_eq!(handle.setpoint(), 0.0);
+}
+
+#[test]
+fn the_demo_takes_the_port_baudrate_and_address() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert_eq!(demo::parse(&args(&["/dev/ttyUSB0"])), Some(("/dev/ttyUSB0", 115200, 0)));
+    assert_eq!(demo::parse(&args(&["COM3", "19200", "4"])), Some(("COM3", 19200, 4)));
+    assert_eq!(demo::parse(&args(&[])), None);
+    assert_eq!(demo::parse(&args(&["COM3", "fast"])), None);
+    assert_eq!(demo::parse(&args(&["COM3", "19200", "256"])), None);
+    assert_eq!(demo::parse(&args(&["COM3", "19200", "4", "more"])), None);
+}
+
+#[test]
+fn the_demo_exits_with_the_class_of_the_failure() {
+    let emulator = Sfc6xxxEmulator::new(EmulatorConfig::default());
+    let handle = emulator.handle();
+    let mut device = Device::new(emulator, 0).unwrap();
+    device.set_response_timeout(Duration::from_millis(20));
+
+    handle.inject_fault(Fault::DropResponse);
+    let error = demo::run(&mut device).unwrap_err();
+    assert!(matches!(error, DeviceError::Timeout));
+    assert_eq!(demo::exit_code(&error), 3);
+
+    handle.inject_fault(Fault::CorruptChecksum);
+    assert_eq!(demo::exit_code(&demo::run(&mut device).unwrap_err()), 5);
+
+    // the overheating protection stopped the measure loop
+    let unit = device.get_current_gas_unit().unwrap();
+    handle.inject_fault(Fault::ErrorState(0x2D));
+    let error = demo::read_averages(&mut device, &unit).unwrap_err();
+    assert!(matches!(
+        error,
+        DeviceError::StateResponse(StateResponseError::MeasureLoopNotRunning)
+    ));
+    assert_eq!(demo::exit_code(&error), 6);
+    assert_eq!(demo::exit_code(&DeviceError::RetriesExhausted(3, Box::new(error))), 6);
+}
@@ -0,0 +1,120 @@
+//! Process code written once against [FlowController] and run on both device drivers, through
+//! their emulators.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features emulator --test flow_controller`.
+#![cfg(feature = "emulator")]
+
+use sfc6xxx_rs::sfc_core::alarm::{
+    Alarm, AlarmConfig, AlarmKind, AlarmState, AlarmTransition, Bound,
+};
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::flow_controller::FlowController;
+use sfc6xxx_rs::sfc_core::gasunit::{Prefixes, TimeBases, Units};
+use sfc6xxx_rs::sfc_core::measurement::{Format, MeasurementWriter, ValueScale};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// Sets the flow and waits until the measured flow is within 1% of the full scale
+fn settle<C: FlowController>(controller: &mut C, setpoint: f32) -> Result<f32, DeviceError> {
+    controller.set_setpoint(setpoint)?;
+    let tolerance = controller.get_full_scale()? * 0.01;
+    for _ in 0..100 {
+        let flow = controller.read_average_measured_value(10)?;
+        if (flow - setpoint).abs() <= tolerance {
+            return Ok(flow);
+        }
+    }
+    Err(DeviceError::Timeout)
+}
+
+fn check<C: FlowController>(controller: &mut C, serial_number: &str) {
+    assert!((settle(controller, 2.5).unwrap() - 2.5).abs() < 0.05);
+    assert_eq!(controller.get_setpoint().unwrap(), 2.5);
+    assert!((controller.read_measured_value().unwrap() - 2.5).abs() < 0.05);
+    assert_eq!(controller.get_full_scale().unwrap(), 5.0);
+
+    let unit = controller.get_gas_unit().unwrap();
+    assert_eq!(unit.unit_prefex, Prefixes::Base);
+    assert_eq!(unit.medium_unit, Units::StandardLiter);
+    assert_eq!(unit.timebase, TimeBases::Minute);
+    assert_eq!(controller.get_serial_number().unwrap(), serial_number);
+    assert_eq!(controller.get_version().unwrap().protocol_major, 2);
+
+    record(controller, serial_number);
+    alarm(controller);
+
+    controller.reset().unwrap();
+    assert_eq!(controller.get_setpoint().unwrap(), 0.0);
+}
+
+/// Three measurements at an interval, as CSV
+fn record<C: FlowController>(controller: &mut C, serial_number: &str) {
+    let interval = Duration::from_millis(20);
+    let start = Instant::now();
+    let mut writer = MeasurementWriter::new(Vec::new(), Format::Csv);
+    for measurement in controller.measurements(interval).take(3) {
+        let measurement = measurement.unwrap();
+        assert!((measurement.value - 2.5).abs() < 0.05);
+        assert_eq!(measurement.scale, ValueScale::Physical);
+        assert_eq!(measurement.serial_number.as_deref(), Some(serial_number));
+        assert_eq!(measurement.setpoint, Some(2.5));
+        writer.write(&measurement).unwrap();
+    }
+    assert!(start.elapsed() >= interval * 2);
+
+    let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows.len(), 4);
+    assert!(rows[1].contains(",l/min,physical,"));
+}
+
+/// A high flow alarm below the settled flow goes off once the persistence has passed
+fn alarm<C: FlowController>(controller: &mut C) {
+    let config = AlarmConfig {
+        high: Some(Bound::Absolute(2.0)),
+        persistence: Duration::from_millis(50),
+        ..AlarmConfig::default()
+    };
+    let activations = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&activations);
+    let alarm = Alarm::new(config).on_transition(move |_, _| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    let states: Vec<_> = controller
+        .measurements(Duration::from_millis(20))
+        .with_alarm(alarm)
+        .take(5)
+        .map(|measurement| measurement.unwrap().alarm)
+        .collect();
+    assert_eq!(states[0].state, AlarmState::Normal);
+    let activated = states
+        .iter()
+        .position(|status| status.transition == Some(AlarmTransition::Activated(AlarmKind::High)))
+        .unwrap();
+    assert!(
+        states[activated..]
+            .iter()
+            .all(|status| status.state == AlarmState::Active(AlarmKind::High))
+    );
+    assert_eq!(activations.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn sfc6xxx() {
+    use sfc6xxx_rs::device::Device;
+    use sfc6xxx_rs::emulator::Sfc6xxxEmulator;
+
+    let mut device = Device::new(Sfc6xxxEmulator::default(), 0).unwrap();
+    check(&mut device, "EMU6000001");
+}
+
+#[test]
+fn sfc5xxx() {
+    use sfc5xxx_rs::device::Device;
+    use sfc5xxx_rs::emulator::Sfc5xxxEmulator;
+
+    let mut device = Device::new(Sfc5xxxEmulator::default(), 0).unwrap();
+    let serial_number = device.get_serial_number().unwrap();
+    check(&mut device, &serial_number);
+}
@@ -0,0 +1,427 @@
+//! Replays the captures in `tests/fixtures` through the device methods and checks that the
+//! decoded values still match the recorded ones. See `tests/fixtures/README.md` for the file
+//! format and how to record captures from a device.
+#![cfg(feature = "std")]
+
+use std::fmt::Debug;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::gasunit::{GasUnit, Prefixes, TimeBases, Units};
+use sfc6xxx_rs::sfc_core::replay::{Capture, Expected, ReplayTransport, Value};
+use sfc6xxx_rs::sfc_core::shdlc::{MOSIFrame, START_STOP, Version, to_shdlc};
+use sfc6xxx_rs::sfc_core::transport::Transport;
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+fn arg<A: FromStr>(arg: Option<&String>) -> Result<A, String> {
+    let arg = arg.ok_or("missing argument")?;
+    arg.parse().map_err(|_| format!("invalid argument `{}`", arg))
+}
+
+/// A value a method returns, read from the `expect` of a capture to be compared with what the
+/// method returns now
+trait Expect: Sized + PartialEq + Debug {
+    fn read(value: &Value) -> Option<Self>;
+}
+
+macro_rules! numbers {
+    ($($ty:ty),*) => {
+        $(impl Expect for $ty {
+            fn read(value: &Value) -> Option<Self> {
+                value.number()
+            }
+        })*
+    };
+}
+
+numbers!(u8, u16, u32, f32);
+
+impl Expect for bool {
+    fn read(value: &Value) -> Option<Self> {
+        value.number()
+    }
+}
+
+impl Expect for () {
+    fn read(value: &Value) -> Option<Self> {
+        matches!(value, Value::Tuple(None, values) if values.is_empty()).then_some(())
+    }
+}
+
+impl Expect for String {
+    fn read(value: &Value) -> Option<Self> {
+        match value {
+            Value::Str(string) => Some(string.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Expect for Baudrate {
+    fn read(value: &Value) -> Option<Self> {
+        match value {
+            Value::Ident(name) => Baudrate::try_from(name.strip_prefix('B')?.parse::<u32>().ok()?).ok(),
+            Value::Tuple(Some(name), rate) if name == "Other" => Some(Baudrate::Other(rate.first()?.number()?)),
+            _ => None,
+        }
+    }
+}
+
+impl Expect for GasUnit {
+    fn read(value: &Value) -> Option<Self> {
+        Some(GasUnit {
+            unit_prefex: variant(value.field("unit_prefex")?, (i8::MIN..=i8::MAX).map(Prefixes::from))?,
+            medium_unit: variant(value.field("medium_unit")?, (0..=u8::MAX).map(Units::from))?,
+            timebase: variant(value.field("timebase")?, (0..=u8::MAX).map(TimeBases::from))?,
+        })
+    }
+}
+
+impl Expect for Version {
+    fn read(value: &Value) -> Option<Self> {
+        let field = |name| value.field(name)?.number();
+        Some(Version {
+            firmware_major: field("firmware_major")?,
+            firmware_minor: field("firmware_minor")?,
+            debug: bool::read(value.field("debug")?)?,
+            hardware_major: field("hardware_major")?,
+            hardware_minor: field("hardware_minor")?,
+            protocol_major: field("protocol_major")?,
+            protocol_minor: field("protocol_minor")?,
+        })
+    }
+}
+
+/// The variant of a fieldless enum named by the value, out of the ones its conversion from a
+/// byte makes
+fn variant<E: Debug>(value: &Value, variants: impl IntoIterator<Item = E>) -> Option<E> {
+    let Value::Ident(name) = value else {
+        return None;
+    };
+    variants.into_iter().find(|variant| format!("{:?}", variant) == *name)
+}
+
+/// Compares what a method returned with what the capture expects, if there is a capture, and
+/// returns the `expect` line of a capture of it, after a comment naming the error if it failed
+fn check<V: Expect>(returned: Result<V, DeviceError>, expected: Option<&Expected>) -> Result<String, String> {
+    let recorded = Expected::record(&returned);
+    let matches = match (expected, &returned) {
+        (None, _) => true,
+        (Some(Expected::Ok(value)), Ok(returned)) => {
+            V::read(value).ok_or_else(|| format!("{:?} is not what the method returns", value))? == *returned
+        }
+        (Some(Expected::Err(code)), Err(error)) => error.code() == *code,
+        _ => false,
+    };
+    match (matches, returned) {
+        (true, Ok(_)) => Ok(format!("expect: {}", recorded)),
+        (true, Err(error)) => Ok(format!("# {}\nexpect: {}", error, recorded)),
+        (false, Ok(_)) => Err(format!("returned {}", recorded)),
+        (false, Err(error)) => Err(format!("returned {} ({})", recorded, error)),
+    }
+}
+
+/// Lists the methods a capture can be made of, with the types of their arguments
+macro_rules! methods {
+    ($($name:ident($($arg:ty),*)),* $(,)?) => {
+        /// every method with its number of arguments
+        const METHODS: &[(&str, usize)] =
+            &[$((stringify!($name), <[&str]>::len(&[$(stringify!($arg)),*]))),*];
+
+        /// Calls the method, see [check] for what it returns
+        fn call<T: Transport>(
+            device: &mut Device<T>,
+            method: &str,
+            args: &[String],
+            expected: Option<&Expected>,
+        ) -> Result<String, String> {
+            #[allow(unused_mut, unused_variables)]
+            match method {
+                $(stringify!($name) => {
+                    let mut args = args.iter();
+                    check(device.$name($(arg::<$arg>(args.next())?),*), expected)
+                })*
+                _ => Err(format!("no method called `{}`", method)),
+            }
+        }
+    };
+}
+
+methods! {
+    get_setpoint(),
+    set_setpoint(f32),
+    read_measured_value(),
+    read_average_measured_value(u8),
+    set_setpoint_and_read_measured_value(f32),
+    get_controller_gain(),
+    set_controller_gain(f32),
+    get_initial_step(),
+    set_initial_step(f32),
+    measure_raw_flow(),
+    measure_raw_thermal_conductivity(),
+    measure_temperature(),
+    get_number_of_calibrations(),
+    get_calibration_validity(u32),
+    get_calibration_gas_id(u32),
+    get_calibration_gas_unit(u32),
+    get_calibration_full_scale(u32),
+    get_current_gas_id(),
+    get_current_gas_unit(),
+    get_current_full_scale(),
+    get_calliration_number(),
+    set_callibration(u32),
+    set_callibration_volitile(u32),
+    get_slave_adress(),
+    get_baudrate(),
+    get_product_type(),
+    get_product_name(),
+    get_article_code(),
+    get_serial_number(),
+    get_version(),
+}
+
+/// The calls with arguments [record_captures] makes, besides every getter without any. The
+/// out of range arguments record error responses.
+const PLAN: &[(&str, &[&str])] = &[
+    ("read_average_measured_value", &["10"]),
+    ("read_average_measured_value", &["101"]),
+    ("get_calibration_validity", &["0"]),
+    ("get_calibration_gas_id", &["0"]),
+    ("get_calibration_gas_id", &["99"]),
+    ("get_calibration_gas_unit", &["0"]),
+    ("get_calibration_full_scale", &["0"]),
+];
+
+/// The prefixes of the methods that leave the device as it is
+const READ_ONLY: &[&str] = &["get_", "read_", "measure_"];
+
+/// The exchange [Device::new] makes to probe the device, it is answered before every capture
+fn probe(address: u8) -> (Vec<u8>, Vec<u8>) {
+    let request = MOSIFrame::new(address, 0x91, &[]).unwrap().into_raw().to_vec();
+    let mut response = vec![address, 0x91, 0x00, 0x04];
+    response.extend(115200_u32.to_be_bytes());
+    (request, to_shdlc(&response).unwrap().to_vec())
+}
+
+/// The version [Device::capabilities] reads, answered after the probe with a firmware that has
+/// every feature the table knows
+fn version(address: u8) -> (Vec<u8>, Vec<u8>) {
+    let request = MOSIFrame::new(address, 0xD1, &[]).unwrap().into_raw().to_vec();
+    let response = [address, 0xD1, 0x00, 0x07, 1, 0, 0, 1, 0, 2, 0];
+    (request, to_shdlc(&response).unwrap().to_vec())
+}
+
+/// Checks one capture file, returning what went wrong
+fn replay(path: &Path) -> Result<(), String> {
+    let name = path.file_stem().and_then(|s| s.to_str()).ok_or("file name is not UTF-8")?;
+    let method = name.split("__").next().unwrap_or_default();
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let capture = Capture::parse(&text).map_err(|e| e.to_string())?;
+    let expected = capture.expected().map_err(|e| format!("invalid `expect`: {}", e))?;
+
+    let exchanges = [probe(capture.address), version(capture.address)];
+    let exchanges = exchanges.into_iter().chain(capture.exchanges);
+    let mut device = Device::new(ReplayTransport::new(exchanges), capture.address)
+        .map_err(|e| format!("the probe of the device failed: {}", e))?;
+    device.capabilities().map_err(|e| format!("reading the capabilities failed: {}", e))?;
+    // a missing response is recorded as a timeout, there is no point in waiting for it
+    device.set_response_timeout(Duration::from_millis(5));
+    call(&mut device, method, &capture.args, Some(&expected))
+        .map(drop)
+        .map_err(|e| format!("{}, the capture expects {}", e, capture.expect))
+}
+
+#[test]
+fn golden_captures() {
+    let mut paths: Vec<_> = fs::read_dir(fixtures())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "frames"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no captures found in {}", fixtures().display());
+
+    let failures: Vec<String> = paths
+        .iter()
+        .filter_map(|path| replay(path).err().map(|e| format!("{}: {}", path.display(), e)))
+        .collect();
+    assert!(failures.is_empty(), "{} captures failed:\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn capture_names_follow_the_scheme() {
+    for entry in fs::read_dir(fixtures()).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        if name == "README.md" {
+            continue;
+        }
+        let stem = name.strip_suffix(".frames").unwrap_or_else(|| panic!("{} is not a .frames file", name));
+        let parts: Vec<&str> = stem.split("__").collect();
+        assert!(METHODS.iter().any(|(m, _)| *m == parts[0]), "{} names no known method", name);
+        assert!((2..=3).contains(&parts.len()), "{} should be <method>__<source>[__<note>]", name);
+        let allowed = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || "-._".contains(c);
+        assert!(stem.chars().all(allowed), "{} should only use a-z, 0-9, '-', '.' and '_'", name);
+    }
+}
+
+/// Every frame sent with the bytes received after it
+type Log = Arc<Mutex<Vec<(Vec<u8>, Vec<u8>)>>>;
+
+/// Records every exchange made through the wrapped transport
+struct Recorder<T> {
+    inner: T,
+    log: Log,
+}
+
+impl<T: Read> Read for Recorder<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some((_, rx)) = self.log.lock().unwrap().last_mut() {
+            rx.extend_from_slice(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Recorder<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut log = self.log.lock().unwrap();
+        match log.last_mut() {
+            // the rest of a frame that took more than one write
+            Some((tx, rx)) if rx.is_empty() && !is_frame(tx) => tx.extend_from_slice(&buf[..written]),
+            _ => log.push((buf[..written].to_vec(), Vec::new())),
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn timeout(&self) -> Duration {
+        self.inner.timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), DeviceError> {
+        self.inner.set_baud_rate(baud_rate)
+    }
+
+    fn clear_input(&mut self) -> Result<(), DeviceError> {
+        self.inner.clear_input()
+    }
+}
+
+fn is_frame(bytes: &[u8]) -> bool {
+    bytes.len() > 1 && bytes.last() == Some(&START_STOP)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+fn record<T: Transport>(port: T, source: &str, slug: &str) {
+    let address = 0;
+    let log = Log::default();
+    let recorder = Recorder {
+        inner: port,
+        log: Arc::clone(&log),
+    };
+    let mut device = Device::new(recorder, address).unwrap();
+    // read before the first capture, like the replay does
+    device.capabilities().unwrap();
+
+    let read_only = |method: &str| READ_ONLY.iter().any(|p| method.starts_with(p));
+    let no_args: &[&str] = &[];
+    let plan = METHODS
+        .iter()
+        .filter(|(method, args)| *args == 0 && read_only(method))
+        .map(|&(method, _)| (method, no_args))
+        .chain(PLAN.iter().copied());
+    for (method, args) in plan {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        log.lock().unwrap().clear();
+        let expect = call(&mut device, method, &args, None).unwrap();
+
+        let mut text = format!(
+            "# recorded by the record_captures test\nsource: {}\naddress: {}\n",
+            source, address
+        );
+        if !args.is_empty() {
+            text += &format!("args: {}\n", args.join(" "));
+        }
+        for (tx, rx) in log.lock().unwrap().iter() {
+            text += &format!("tx: {}\nrx: {}\n", hex(tx), hex(rx));
+        }
+        text += &format!("{}\n", expect);
+
+        let mut name = format!("{}__{}", method, slug);
+        if !args.is_empty() {
+            name += &format!("__{}", args.join("_").to_ascii_lowercase());
+        }
+        fs::write(fixtures().join(format!("{}.frames", name)), text).unwrap();
+    }
+}
+
+/// Records a capture of every read-only method into `tests/fixtures`. Set
+/// `SFC_CAPTURE_PORT` to the serial port of the device, and optionally `SFC_CAPTURE_SOURCE`
+/// to describe it, the product name and firmware version are used otherwise. With the
+/// `emulator` feature the port `emulator` records the emulator instead.
+#[test]
+#[ignore = "records captures from a device, set SFC_CAPTURE_PORT"]
+fn record_captures() {
+    let port = std::env::var("SFC_CAPTURE_PORT").expect("SFC_CAPTURE_PORT is not set");
+
+    #[cfg(feature = "emulator")]
+    if port == "emulator" {
+        let emulator = sfc6xxx_rs::emulator::Sfc6xxxEmulator::default();
+        record(emulator, "emulator", "emulator");
+        return;
+    }
+
+    let open = || serialport::new(&port, 115200).timeout(Duration::from_millis(600)).open_native();
+    let (source, slug) = match std::env::var("SFC_CAPTURE_SOURCE") {
+        Ok(source) => (source.clone(), slug(&source)),
+        Err(_) => {
+            let mut device = Device::new(open().unwrap(), 0).unwrap();
+            let name = device.get_product_name().unwrap();
+            let version = device.get_version().unwrap();
+            let source = format!(
+                "{}, firmware {}.{}",
+                name, version.firmware_major, version.firmware_minor
+            );
+            let slug = slug(&format!(
+                "{}-fw{}.{}",
+                name, version.firmware_major, version.firmware_minor
+            ));
+            (source, slug)
+        }
+    };
+    record(open().unwrap(), &source, &slug);
+}
+
+/// Turns a description into something that fits the file name scheme
+fn slug(source: &str) -> String {
+    source
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+        .collect()
+}
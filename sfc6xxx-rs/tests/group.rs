@@ -0,0 +1,89 @@
+//! A group of emulated controllers that keeps going when one of them has been unplugged.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features emulator --test group`.
+#![cfg(feature = "emulator")]
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::emulator::{EmulatorConfig, EmulatorHandle, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::group::{DeviceGroup, Execution};
+
+/// Three controllers named a, b and c
+fn group(execution: Execution) -> (DeviceGroup<Device<Sfc6xxxEmulator>>, Vec<EmulatorHandle>) {
+    let mut handles = Vec::new();
+    let members = ["a", "b", "c"]
+        .into_iter()
+        .map(|name| {
+            let emulator = Sfc6xxxEmulator::new(EmulatorConfig::default());
+            handles.push(emulator.handle());
+            (name.to_string(), Device::new(emulator, 0).unwrap())
+        })
+        .collect();
+    let mut group = DeviceGroup::new(members);
+    group.set_execution(execution);
+    (group, handles)
+}
+
+fn failing_member_does_not_stop_the_others(execution: Execution) {
+    let (mut group, handles) = group(execution);
+    handles[1].unplug();
+
+    let result = group.set_all_setpoints(1.5);
+    assert!(!result.is_ok());
+    assert!(result.get("a").unwrap().is_ok());
+    assert!(result.get("c").unwrap().is_ok());
+    assert_eq!(handles[0].setpoint(), 1.5);
+    assert_eq!(handles[2].setpoint(), 1.5);
+
+    let error = result.into_result().unwrap_err();
+    assert_eq!(error.total, 3);
+    assert_eq!(error.failed.len(), 1);
+    assert_eq!(error.failed[0].device, "b");
+    assert!(matches!(error.failed[0].error, DeviceError::IoError(_)));
+    assert!(error.to_string().starts_with("1 of 3 devices failed: b: "));
+
+    let flows = group.read_all();
+    assert_eq!(
+        flows
+            .failures()
+            .map(|(device, _)| device)
+            .collect::<Vec<_>>(),
+        ["b"]
+    );
+    assert!((flows.get("a").unwrap().as_ref().unwrap() - 1.5).abs() < 0.1);
+
+    assert!(!group.zero_all().is_ok());
+    assert_eq!(handles[0].setpoint(), 0.0);
+    assert_eq!(handles[2].setpoint(), 0.0);
+}
+
+#[test]
+fn sequential() {
+    failing_member_does_not_stop_the_others(Execution::Sequential);
+}
+
+#[test]
+fn parallel() {
+    failing_member_does_not_stop_the_others(Execution::Parallel);
+}
+
+#[test]
+fn values_of_every_device() {
+    let (mut group, _handles) = group(Execution::Parallel);
+    let serial_numbers = group
+        .for_each(|controller| controller.get_serial_number())
+        .into_result()
+        .unwrap();
+    assert_eq!(serial_numbers.len(), 3);
+    assert_eq!(serial_numbers.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+}
+
+#[test]
+#[should_panic(expected = "the device name a is used twice")]
+fn names_are_unique() {
+    let device = || Device::new(Sfc6xxxEmulator::new(EmulatorConfig::default()), 0).unwrap();
+    DeviceGroup::new(vec![
+        ("a".to_string(), device()),
+        ("a".to_string(), device()),
+    ]);
+}
@@ -0,0 +1,95 @@
+//! Health checks of both device drivers, through their emulators.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features emulator --test health`.
+#![cfg(feature = "emulator")]
+
+use sfc6xxx_rs::sfc_core::health::{CheckOutcome, HealthRequirements};
+
+#[test]
+fn healthy_sfc6xxx() {
+    use sfc6xxx_rs::device::Device;
+    use sfc6xxx_rs::emulator::Sfc6xxxEmulator;
+
+    let mut device = Device::new(Sfc6xxxEmulator::default(), 0).unwrap();
+    let report = device.health_check(HealthRequirements {
+        min_firmware: Some((1, 0)),
+        ..HealthRequirements::default()
+    });
+    assert!(report.is_healthy(), "{}", report);
+    assert_eq!(report.link, CheckOutcome::Passed);
+    assert_eq!(report.firmware, CheckOutcome::Passed);
+    assert!(matches!(report.device_errors, CheckOutcome::Skipped(_)));
+    assert_eq!(report.calibration, CheckOutcome::Passed);
+    assert_eq!(report.zero_flow, CheckOutcome::Passed);
+    assert_eq!(report.version.unwrap().firmware_major, 1);
+}
+
+#[test]
+fn sfc6xxx_old_firmware_and_running_flow() {
+    use sfc6xxx_rs::device::Device;
+    use sfc6xxx_rs::emulator::Sfc6xxxEmulator;
+
+    let mut device = Device::new(Sfc6xxxEmulator::default(), 0).unwrap();
+    device.set_setpoint(2.0).unwrap();
+    let report = device.health_check(HealthRequirements {
+        min_firmware: Some((1, 3)),
+        ..HealthRequirements::default()
+    });
+    assert!(!report.is_healthy());
+    assert!(report.firmware.is_failed());
+    // the setpoint is left alone
+    assert!(matches!(report.zero_flow, CheckOutcome::Skipped(_)));
+    assert_eq!(device.get_setpoint().unwrap(), 2.0);
+}
+
+#[test]
+fn sfc6xxx_failing_command_fails_its_item() {
+    use sfc6xxx_rs::device::Device;
+    use sfc6xxx_rs::emulator::{Fault, Sfc6xxxEmulator};
+
+    let emulator = Sfc6xxxEmulator::default();
+    let handle = emulator.handle();
+    let mut device = Device::new(emulator, 0).unwrap();
+    handle.inject_fault(Fault::ErrorState(0x42));
+    let report = device.health_check(HealthRequirements::default());
+    assert!(report.link.is_failed());
+    assert_eq!(report.calibration, CheckOutcome::Passed);
+    assert_eq!(report.zero_flow, CheckOutcome::Passed);
+    assert!(report.to_string().starts_with("unhealthy\nlink: failed"));
+}
+
+#[test]
+fn healthy_sfc5xxx() {
+    use sfc5xxx_rs::device::Device;
+    use sfc5xxx_rs::emulator::Sfc5xxxEmulator;
+
+    let mut device = Device::new(Sfc5xxxEmulator::default(), 0).unwrap();
+    let report = device.health_check(HealthRequirements::default());
+    assert!(report.is_healthy(), "{}", report);
+    assert!(matches!(report.firmware, CheckOutcome::Skipped(_)));
+    assert_eq!(report.device_errors, CheckOutcome::Passed);
+    assert_eq!(report.calibration, CheckOutcome::Passed);
+    assert_eq!(report.zero_flow, CheckOutcome::Passed);
+}
+
+#[test]
+fn sfc5xxx_latched_error() {
+    use sfc5xxx_rs::device::Device;
+    use sfc5xxx_rs::emulator::Sfc5xxxEmulator;
+
+    let emulator = Sfc5xxxEmulator::default();
+    let handle = emulator.handle();
+    let mut device = Device::new(emulator, 0).unwrap();
+    handle.set_device_error(0x0000_0100, 0x2D);
+    let report = device.health_check(HealthRequirements::default());
+    assert!(!report.is_healthy());
+    assert_eq!(
+        report.device_errors,
+        CheckOutcome::Failed(
+            "error flags 0x00000100 are set, the last error was 0x2d".to_string()
+        )
+    );
+    assert_eq!(report.link, CheckOutcome::Passed);
+    // the check reads the errors without clearing them
+    assert_eq!(device.get_device_error_state(false).unwrap(), (0x100, 0x2D));
+}
@@ -0,0 +1,100 @@
+//! A mixer blending through several emulated controllers, including one of each family.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features emulator --test mixer`.
+#![cfg(feature = "emulator")]
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::emulator::{EmulatorConfig, EmulatorHandle, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::flow_controller::FlowController;
+use sfc6xxx_rs::sfc_core::mixer::{Mixer, MixerError, SaturationPolicy};
+
+/// A controller with the given calibration of the emulator active, 0 has a full scale of 5 and
+/// 2 one of 2.5
+fn controller(calibration: u32) -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+    let emulator = Sfc6xxxEmulator::new(EmulatorConfig {
+        active_calibration: calibration,
+        ..Default::default()
+    });
+    let handle = emulator.handle();
+    (Device::new(emulator, 0).unwrap(), handle)
+}
+
+/// One part through a controller with a full scale of 5, three through one with 2.5
+fn mixer() -> (Mixer<Device<Sfc6xxxEmulator>>, [EmulatorHandle; 2]) {
+    let (first, first_handle) = controller(0);
+    let (second, second_handle) = controller(2);
+    (Mixer::new(vec![(first, 1.0), (second, 3.0)]), [first_handle, second_handle])
+}
+
+#[test]
+fn total_flow_is_split_by_the_ratio() {
+    let (mut mixer, handles) = mixer();
+    assert_eq!(mixer.ratio(), [0.25, 0.75]);
+    assert_eq!(mixer.set_total_flow(2.0).unwrap(), 2.0);
+    assert_eq!(handles[0].setpoint(), 0.5);
+    assert_eq!(handles[1].setpoint(), 1.5);
+
+    let ratio = mixer.read_actual_ratio().unwrap();
+    assert!((ratio[0] - 0.25).abs() < 0.01 && (ratio[1] - 0.75).abs() < 0.01);
+}
+
+#[test]
+fn saturated_channel_fails_without_changing_the_mix() {
+    let (mut mixer, handles) = mixer();
+    mixer.set_total_flow(2.0).unwrap();
+    // the second channel would need 3, above its full scale of 2.5
+    match mixer.set_total_flow(4.0) {
+        Err(MixerError::Saturated {
+            channel: 1,
+            setpoint,
+            full_scale,
+        }) => assert_eq!((setpoint, full_scale), (3.0, 2.5)),
+        other => panic!("expected the second channel to saturate, got {:?}", other),
+    }
+    assert_eq!(handles[0].setpoint(), 0.5);
+    assert_eq!(handles[1].setpoint(), 1.5);
+}
+
+#[test]
+fn saturated_channel_rescales_the_total() {
+    let (mut mixer, handles) = mixer();
+    mixer.set_saturation_policy(SaturationPolicy::Rescale);
+    let total = mixer.set_total_flow(4.0).unwrap();
+    assert!((total - 10.0 / 3.0).abs() < 1e-5);
+    assert_eq!(handles[1].setpoint(), 2.5);
+    assert!((handles[0].setpoint() - 2.5 / 3.0).abs() < 1e-5);
+}
+
+#[test]
+fn stop_reaches_every_channel_that_answers() {
+    let channels: Vec<_> = (0..3).map(|_| controller(0)).collect();
+    let handles: Vec<EmulatorHandle> = channels.iter().map(|(_, handle)| handle.clone()).collect();
+    let mut mixer = Mixer::new(channels.into_iter().map(|(device, _)| (device, 1.0)).collect());
+    mixer.set_total_flow(3.0).unwrap();
+
+    handles[1].unplug();
+    let errors = mixer.stop_all().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].channel, 1);
+    assert!(matches!(errors[0].error, DeviceError::IoError(_)));
+    assert_eq!(handles[0].setpoint(), 0.0);
+    assert_eq!(handles[1].setpoint(), 1.0);
+    assert_eq!(handles[2].setpoint(), 0.0);
+}
+
+#[test]
+fn both_families_in_one_mix() {
+    let sfc6xxx: Box<dyn FlowController> = Box::new(controller(0).0);
+    let sfc5xxx: Box<dyn FlowController> = Box::new(
+        sfc5xxx_rs::device::Device::new(sfc5xxx_rs::emulator::Sfc5xxxEmulator::default(), 0)
+            .unwrap(),
+    );
+    let mut mixer = Mixer::new(vec![(sfc6xxx, 1.0), (sfc5xxx, 1.0)]);
+    mixer.set_total_flow(4.0).unwrap();
+    let ratio = mixer.read_actual_ratio().unwrap();
+    assert!((ratio[0] - 0.5).abs() < 0.01 && (ratio[1] - 0.5).abs() < 0.01);
+    mixer.stop_all().unwrap();
+    let mut channels = mixer.into_inner();
+    assert_eq!(channels[1].get_setpoint().unwrap(), 0.0);
+}
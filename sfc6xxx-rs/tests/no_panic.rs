@@ -0,0 +1,267 @@
+//! Every reading of the driver against a device that answers with arbitrary states and data.
+//! Whatever the response carries, the driver returns an error and doesn't panic.
+#![cfg(feature = "std")]
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use proptest::prelude::*;
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::health::HealthRequirements;
+use sfc6xxx_rs::sfc_core::replay::Capture;
+use sfc6xxx_rs::sfc_core::shdlc::{MISOFrame, MOSIFrame};
+use sfc6xxx_rs::sfc_core::transport::Transport;
+
+/// Answers every request with a well formed frame for its address and command, the state and
+/// data are the next of `answers`. Times out once they ran out.
+struct Answering {
+    answers: VecDeque<(u8, Vec<u8>)>,
+    request: Vec<u8>,
+    response: VecDeque<u8>,
+}
+
+impl Read for Answering {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut count = 0;
+        for slot in buf.iter_mut() {
+            let Some(byte) = self.response.pop_front() else { break };
+            *slot = byte;
+            count += 1;
+        }
+        if count == 0 {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        Ok(count)
+    }
+}
+
+impl Write for Answering {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request.extend_from_slice(buf);
+        if let Ok(request) = MOSIFrame::decode(&self.request) {
+            self.request.clear();
+            if let Some((state, data)) = self.answers.pop_front() {
+                let address = request.get_address();
+                let command = request.get_command_number();
+                let frame = MISOFrame::from_parts(address, command, state, &data).unwrap();
+                self.response.extend(frame.to_raw());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Answering {
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// Reads that the driver may make of one input before it is stuck in a loop
+const MAX_READS: usize = 4096;
+
+/// Hands out the input as the bytes received, whatever was written. The size of every read is
+/// taken from the input as well, from its last byte backwards, and is 0 to 8 bytes. Times out
+/// once the input ran out.
+struct Chunked {
+    input: Vec<u8>,
+    read: usize,
+    reads: usize,
+}
+
+impl Read for Chunked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        assert!(self.reads < MAX_READS, "the driver kept reading");
+        let rest = self.input.get(self.read..).unwrap_or_default();
+        if rest.is_empty() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        let size = self.input.iter().rev().nth(self.reads % self.input.len()).unwrap_or(&0) % 9;
+        let count = rest.len().min(buf.len()).min(size.into());
+        buf[..count].copy_from_slice(&rest[..count]);
+        self.read += count;
+        Ok(count)
+    }
+}
+
+impl Write for Chunked {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Chunked {
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _: Duration) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// Every public reading of the device, the fuzz input picks the one to start with
+const METHODS: &[fn(&mut Device<Chunked>)] = &[
+    |device| drop(device.get_setpoint()),
+    |device| drop(device.read_measured_value()),
+    |device| drop(device.read_measured_value_recorded()),
+    |device| drop(device.read_average_measured_value(10)),
+    |device| drop(device.set_setpoint_and_read_measured_value(0.5)),
+    |device| drop(device.get_controller_gain()),
+    |device| drop(device.get_initial_step()),
+    |device| drop(device.measure_raw_flow()),
+    |device| drop(device.measure_raw_thermal_conductivity()),
+    |device| drop(device.measure_temperature()),
+    |device| drop(device.get_number_of_calibrations()),
+    |device| drop(device.get_calibration_validity(0)),
+    |device| drop(device.get_calibration_gas_id(0)),
+    |device| drop(device.get_calibration_gas_unit(0)),
+    |device| drop(device.get_calibration_full_scale(0)),
+    |device| drop(device.get_current_gas_id()),
+    |device| drop(device.get_current_gas_unit()),
+    |device| drop(device.get_current_full_scale()),
+    |device| drop(device.get_calliration_number()),
+    |device| drop(device.get_slave_adress()),
+    |device| drop(device.get_baudrate()),
+    |device| drop(device.get_product_type()),
+    |device| drop(device.get_product_name()),
+    |device| drop(device.get_article_code()),
+    |device| drop(device.get_serial_number()),
+    |device| drop(device.get_version()),
+];
+
+/// Feeds the input through the whole receive path of a device: the reads of the connection,
+/// the frame decoder, the length checks and the decoders of every reading
+fn receive(input: &[u8]) {
+    let first = input.first().map_or(0, |&first| usize::from(first));
+    let port = Chunked { input: input.to_vec(), read: 0, reads: 0 };
+    let mut device = Device::builder(port)
+        .address(0)
+        .timeout(Duration::from_millis(1))
+        .inter_byte_timeout(Duration::from_millis(1))
+        .probe(false)
+        .build()
+        .unwrap();
+    for method in METHODS.iter().cycle().skip(first % METHODS.len()).take(METHODS.len()) {
+        method(&mut device);
+    }
+}
+
+/// The received frames of the golden captures, each one a seed of the fuzz input
+fn corpus() -> Vec<Vec<u8>> {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+    let mut corpus = Vec::new();
+    for entry in fs::read_dir(fixtures).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "frames") {
+            let capture = Capture::parse(&fs::read_to_string(path).unwrap()).unwrap();
+            corpus.extend(capture.exchanges.into_iter().map(|(_, received)| received));
+        }
+    }
+    corpus.sort();
+    corpus
+}
+
+/// A seed of the corpus with a few bytes changed, several seeds in a row or arbitrary bytes
+fn input() -> impl Strategy<Value = Vec<u8>> {
+    let corpus = corpus();
+    let changes = prop::collection::vec(any::<(usize, u8)>(), 0..4);
+    let mutated = (prop::sample::select(corpus.clone()), changes).prop_map(|(mut seed, changes)| {
+        for (at, byte) in changes {
+            let at = at % seed.len();
+            seed[at] = byte;
+        }
+        seed
+    });
+    let sequence = prop::collection::vec(prop::sample::select(corpus), 1..8)
+        .prop_map(|seeds| seeds.concat());
+    prop_oneof![
+        2 => mutated,
+        2 => sequence,
+        1 => prop::collection::vec(any::<u8>(), 0..256),
+    ]
+}
+
+/// Mostly successful answers, with the data of the response a device sends most of the time
+fn answers() -> impl Strategy<Value = Vec<(u8, Vec<u8>)>> {
+    let state = prop_oneof![6 => Just(0x00), 1 => any::<u8>()];
+    let data = prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..9),
+        prop::collection::vec(any::<u8>(), 0..=255),
+    ];
+    prop::collection::vec((state, data), 0..64)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn any_response_is_an_error_or_a_value(answers in answers()) {
+        let port = Answering {
+            answers: answers.into(),
+            request: Vec::new(),
+            response: VecDeque::new(),
+        };
+        let mut device = Device::builder(port)
+            .address(0)
+            .timeout(Duration::from_millis(1))
+            .inter_byte_timeout(Duration::from_millis(1))
+            .probe(false)
+            .build()
+            .unwrap();
+
+        let _ = device.get_setpoint();
+        let _ = device.read_measured_value();
+        let _ = device.read_measured_value_recorded();
+        let _ = device.read_average_measured_value(10);
+        let _ = device.set_setpoint_and_read_measured_value(0.5);
+        let _ = device.get_controller_gain();
+        let _ = device.get_initial_step();
+        let _ = device.measure_raw_flow();
+        let _ = device.measure_raw_thermal_conductivity();
+        let _ = device.measure_temperature();
+        let _ = device.get_number_of_calibrations();
+        let _ = device.get_calibration_validity(0);
+        let _ = device.get_calibration_gas_id(0);
+        let _ = device.get_calibration_gas_unit(0);
+        let _ = device.get_calibration_full_scale(0);
+        let _ = device.get_current_gas_id();
+        let _ = device.get_current_gas_unit();
+        let _ = device.get_current_full_scale();
+        let _ = device.get_calliration_number();
+        let _ = device.get_slave_adress();
+        let _ = device.get_baudrate();
+        let _ = device.get_product_type();
+        let _ = device.get_product_name();
+        let _ = device.get_article_code();
+        let _ = device.get_serial_number();
+        let _ = device.get_version();
+        let _ = device.health_check(HealthRequirements {
+            min_firmware: Some((1, 0)),
+            ..HealthRequirements::default()
+        });
+    }
+
+    #[test]
+    fn the_receive_path_takes_any_input(input in input()) {
+        receive(&input);
+    }
+}
@@ -0,0 +1,208 @@
+//! Runs the poller task against the emulator through a tokio duplex stream.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features poller,emulator --test poller`.
+#![cfg(all(feature = "poller", feature = "emulator"))]
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+
+use sfc6xxx_rs::async_device::AsyncDevice;
+use sfc6xxx_rs::emulator::{EmulatorHandle, Fault, Sfc6xxxEmulator};
+use sfc6xxx_rs::poller::{PollSnapshot, PollerConfig, TokioDevice};
+use sfc6xxx_rs::sfc_core::async_transport::{FromTokio, TokioDelay};
+use sfc6xxx_rs::sfc_core::error::{DeviceError, StateResponseError};
+use sfc6xxx_rs::sfc_core::transport::Transport;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+use tokio::sync::watch;
+use tokio::time::{Instant, sleep};
+
+/// Moves bytes between the far end of a duplex stream and the emulator
+async fn bridge(mut stream: DuplexStream, mut emulator: Sfc6xxxEmulator) {
+    Transport::set_timeout(&mut emulator, Duration::ZERO).unwrap();
+    let mut buf = [0_u8; 64];
+    loop {
+        match tokio::time::timeout(Duration::from_millis(1), stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return,
+            Ok(Ok(read)) => {
+                if emulator.write_all(&buf[..read]).is_err() {
+                    return;
+                }
+            }
+            Err(_) => {}
+        }
+        loop {
+            match emulator.read(&mut buf) {
+                Ok(read) => stream.write_all(&buf[..read]).await.unwrap(),
+                Err(e) if e.kind() == ErrorKind::TimedOut => break,
+                // unplugged, dropping the stream closes the driver's end
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+async fn device() -> (TokioDevice<DuplexStream>, EmulatorHandle) {
+    let emulator = Sfc6xxxEmulator::default();
+    let handle = emulator.handle();
+    let (stream, far_end) = duplex(256);
+    tokio::spawn(bridge(far_end, emulator));
+    let mut device = AsyncDevice::new(FromTokio(stream), TokioDelay, 0)
+        .await
+        .unwrap();
+    device.set_retry(None);
+    (device, handle)
+}
+
+/// The number of measured values the emulator was asked for
+fn reads(handle: &EmulatorHandle) -> usize {
+    handle
+        .requests()
+        .iter()
+        .filter(|(_, command, data)| *command == 0x08 && data == &[0x01])
+        .count()
+}
+
+/// Waits until the snapshot matches, failing after a second
+async fn wait_for(
+    snapshots: &mut watch::Receiver<PollSnapshot>,
+    done: impl FnMut(&PollSnapshot) -> bool,
+) -> PollSnapshot {
+    tokio::time::timeout(Duration::from_secs(1), snapshots.wait_for(done))
+        .await
+        .expect("the snapshot did not change in time")
+        .expect("the poller stopped")
+        .clone()
+}
+
+#[tokio::test]
+async fn interval_changes() {
+    let (device, handle) = device().await;
+    let (poller, mut snapshots) = device.spawn_poller(Duration::from_millis(10));
+    wait_for(&mut snapshots, |snapshot| snapshot.polls >= 1).await;
+
+    let before = reads(&handle);
+    sleep(Duration::from_millis(200)).await;
+    let fast = reads(&handle) - before;
+    assert!((15..=21).contains(&fast), "{} reads at 10ms", fast);
+
+    poller.set_interval(Duration::from_millis(50));
+    let before = reads(&handle);
+    sleep(Duration::from_millis(200)).await;
+    let slow = reads(&handle) - before;
+    assert!((3..=4).contains(&slow), "{} reads at 50ms", slow);
+
+    poller.pause();
+    wait_for(&mut snapshots, |snapshot| snapshot.paused).await;
+    let before = reads(&handle);
+    sleep(Duration::from_millis(150)).await;
+    assert_eq!(reads(&handle), before);
+
+    // resuming polls right away
+    let polls = snapshots.borrow().polls;
+    let resumed = Instant::now();
+    poller.resume();
+    wait_for(&mut snapshots, |snapshot| snapshot.polls > polls).await;
+    assert!(resumed.elapsed() < Duration::from_millis(40));
+    assert!(!snapshots.borrow().paused);
+}
+
+#[tokio::test]
+async fn commands_run_between_polls() {
+    let (device, handle) = device().await;
+    let (poller, mut snapshots) = device.spawn_poller(Duration::from_millis(5));
+    let first = wait_for(&mut snapshots, |snapshot| snapshot.polls >= 1).await;
+    let measurement = first.measurement.unwrap();
+    assert_eq!(measurement.setpoint, Some(0.0));
+    assert_eq!(measurement.serial_number.as_deref(), Some("EMU6000001"));
+    assert_eq!(measurement.full_scale, Some(5.0));
+
+    // commands from several places are queued and each answered with its own result
+    let (setpoint, serial, gain) = tokio::join!(
+        poller.set_setpoint(2.5),
+        poller.run(|device| Box::pin(device.get_serial_number())),
+        poller.run(|device| Box::pin(async move {
+            device.set_controller_gain(0.8).await?;
+            device.get_controller_gain().await
+        })),
+    );
+    setpoint.unwrap();
+    assert_eq!(serial.unwrap(), "EMU6000001");
+    assert_eq!(gain.unwrap(), 0.8);
+    assert_eq!(handle.setpoint(), 2.5);
+
+    // the setpoint is read again for the label after a command
+    let polls = snapshots.borrow().polls;
+    let snapshot = wait_for(&mut snapshots, |snapshot| snapshot.polls > polls).await;
+    let measurement = snapshot.measurement.unwrap();
+    assert_eq!(measurement.value, 2.5);
+    assert_eq!(measurement.setpoint, Some(2.5));
+
+    // polling went on while the commands ran
+    let polls = snapshots.borrow().polls;
+    for setpoint in [1.0, 2.0, 3.0] {
+        poller.set_setpoint(setpoint).await.unwrap();
+    }
+    wait_for(&mut snapshots, |snapshot| snapshot.polls > polls + 2).await;
+
+    let mut device = poller.shutdown().await;
+    assert_eq!(device.get_setpoint().await.unwrap(), 3.0);
+}
+
+#[tokio::test]
+async fn failed_polls_are_published() {
+    let (device, handle) = device().await;
+    let config = PollerConfig {
+        interval: Duration::from_millis(10),
+        max_consecutive_errors: 3,
+    };
+    let (poller, mut snapshots) = device.spawn_poller_with(config);
+    wait_for(&mut snapshots, |snapshot| snapshot.polls >= 1).await;
+
+    // a single failed read is published and the next poll recovers
+    handle.inject_fault(Fault::ErrorState(0x2D));
+    let failed = wait_for(&mut snapshots, |snapshot| snapshot.error.is_some()).await;
+    assert!(matches!(
+        failed.error.as_deref(),
+        Some(DeviceError::StateResponse(
+            StateResponseError::MeasureLoopNotRunning
+        ))
+    ));
+    assert_eq!(failed.consecutive_errors, 1);
+    assert!(failed.measurement.is_some());
+    let recovered = wait_for(&mut snapshots, |snapshot| snapshot.error.is_none()).await;
+    assert_eq!(recovered.consecutive_errors, 0);
+
+    // the task stops after three failures in a row
+    handle.unplug();
+    let closed = tokio::time::timeout(Duration::from_secs(2), async {
+        while snapshots.changed().await.is_ok() {}
+    });
+    closed.await.expect("the poller did not stop");
+    let last = snapshots.borrow().clone();
+    assert_eq!(last.consecutive_errors, 3);
+    assert!(last.measurement.is_some());
+    // the channel closes as the task returns
+    sleep(Duration::from_millis(10)).await;
+    assert!(poller.is_stopped());
+
+    let error = poller.set_setpoint(1.0).await.unwrap_err();
+    assert!(matches!(error, DeviceError::IoError(e) if e.kind() == ErrorKind::BrokenPipe));
+    // the device is still handed back
+    let _device = poller.shutdown().await;
+}
+
+#[tokio::test]
+async fn dropping_the_handle_stops_polling() {
+    let (device, handle) = device().await;
+    let (poller, mut snapshots) = device.spawn_poller(Duration::from_millis(10));
+    wait_for(&mut snapshots, |snapshot| snapshot.polls >= 1).await;
+    drop(poller);
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while snapshots.changed().await.is_ok() {}
+    })
+    .await
+    .expect("the poller did not stop");
+    let before = reads(&handle);
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(reads(&handle), before);
+}
@@ -0,0 +1,134 @@
+//! Setpoint profiles run on an emulated controller.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features emulator --test profile`.
+#![cfg(feature = "emulator")]
+
+use std::thread;
+use std::time::Duration;
+
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::emulator::{EmulatorHandle, Fault, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::error::{DeviceError, StateResponseError};
+use sfc6xxx_rs::sfc_core::flow_controller::FlowController;
+use sfc6xxx_rs::sfc_core::limits::{Limit, LimitPolicy};
+use sfc6xxx_rs::sfc_core::profile::{Profile, SegmentKind};
+
+const INTERVAL: Duration = Duration::from_millis(10);
+
+fn device() -> (Device<Sfc6xxxEmulator>, EmulatorHandle) {
+    let emulator = Sfc6xxxEmulator::default();
+    let handle = emulator.handle();
+    (Device::new(emulator, 0).unwrap(), handle)
+}
+
+fn profile() -> Profile {
+    Profile::new()
+        .hold(0.5, Duration::from_millis(50))
+        .hold(2.0, Duration::from_millis(100))
+        .ramp(0.0, Duration::from_millis(40))
+}
+
+#[test]
+fn segments_run_in_order() {
+    let (mut device, handle) = device();
+    let profile = profile();
+    let result = device.run_profile(&profile, INTERVAL).unwrap();
+    assert!(result.is_complete());
+    assert_eq!(handle.setpoint(), 0.0);
+
+    let counts: Vec<usize> = result.segments.iter().map(|s| s.measurements.len()).collect();
+    assert_eq!(counts, [5, 10, 4]);
+    assert_eq!(result.timing.samples, 19);
+    assert!(result.timing.elapsed >= profile.duration());
+    assert!(result.timing.max_lateness >= result.timing.mean_lateness);
+
+    for (record, target) in result.segments[..2].iter().zip([0.5, 2.0]) {
+        assert_eq!(record.segment.kind, SegmentKind::Hold);
+        for measurement in &record.measurements {
+            assert_eq!(measurement.setpoint, Some(target));
+            assert_eq!(measurement.value, target);
+        }
+    }
+    // the ramp starts where the hold left off and walks down to zero
+    let ramp: Vec<f32> = result.segments[2].measurements.iter().map(|m| m.value).collect();
+    assert_eq!(ramp, [2.0, 1.5, 1.0, 0.5]);
+    let timestamps: Vec<_> = result.segments.iter().flat_map(|s| &s.measurements).collect();
+    assert!(timestamps.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+}
+
+#[test]
+fn failed_command_zeroes_the_setpoint() {
+    let (mut device, handle) = device();
+    let injector = {
+        let handle = handle.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(80));
+            handle.inject_fault(Fault::ErrorState(0x04));
+        })
+    };
+    let result = device.run_profile(&profile(), INTERVAL).unwrap();
+    injector.join().unwrap();
+
+    let abort = result.aborted.as_ref().expect("the profile should have stopped");
+    assert_eq!(abort.segment, 1);
+    assert!(matches!(
+        abort.error,
+        DeviceError::StateResponse(StateResponseError::ParameterError)
+    ));
+    assert!(abort.stop_error.is_none());
+    assert_eq!(handle.setpoint(), 0.0);
+    assert_eq!(result.segments.len(), 2);
+    assert_eq!(result.segments[0].measurements.len(), 5);
+    assert!(result.segments[1].measurements.len() < 10);
+}
+
+/// The setpoints the emulator was sent, in order
+fn sent_setpoints(handle: &EmulatorHandle) -> Vec<f32> {
+    handle
+        .requests()
+        .into_iter()
+        .filter_map(|(_, command, data)| match (command, data.as_slice()) {
+            (0x00, &[0x01, a, b, c, d]) => Some(f32::from_be_bytes([a, b, c, d])),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn ramp_steps_are_clamped_to_the_soft_limits() {
+    let (mut device, handle) = device();
+    device.set_soft_limits(0.0, 1.5);
+    device.set_soft_limit_policy(LimitPolicy::Clamp);
+    let profile = Profile::new().ramp(3.0, Duration::from_millis(60));
+    let result = device.run_profile(&profile, INTERVAL).unwrap();
+    assert!(result.is_complete());
+
+    let sent = sent_setpoints(&handle);
+    assert!(sent.len() > 3, "{:?}", sent);
+    assert!(sent.iter().all(|&setpoint| setpoint <= 1.5), "{:?}", sent);
+    assert!(sent.contains(&1.5));
+    assert_eq!(handle.setpoint(), 1.5);
+}
+
+#[test]
+fn ramp_past_the_soft_limit_aborts() {
+    let (mut device, handle) = device();
+    device.set_soft_limits(0.0, 1.5);
+    let profile = Profile::new().ramp(3.0, Duration::from_millis(60));
+    let result = device.run_profile(&profile, INTERVAL).unwrap();
+
+    let abort = result.aborted.as_ref().expect("the ramp should have stopped");
+    assert!(matches!(abort.error, DeviceError::SoftLimit(_, Limit::Max(1.5))));
+    assert!(abort.stop_error.is_none());
+    assert!(sent_setpoints(&handle).iter().all(|&setpoint| setpoint <= 1.5));
+    assert_eq!(handle.setpoint(), 0.0);
+}
+
+#[test]
+fn unreachable_device_fails_before_starting() {
+    let (mut device, handle) = device();
+    device.set_setpoint(1.0).unwrap();
+    handle.unplug();
+    assert!(matches!(device.run_profile(&profile(), INTERVAL), Err(DeviceError::IoError(_))));
+    assert_eq!(handle.setpoint(), 1.0);
+}
@@ -0,0 +1,150 @@
+//! Runs the driver through a real tty. The emulator is bridged to the master end of a pseudo
+//! terminal and the device opens the slave end by name like any other serial port, so the
+//! termios setup, read timeouts and short reads of an actual `TTYPort` are exercised.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features emulator --test pty_loopback`.
+#![cfg(all(target_os = "linux", feature = "emulator"))]
+
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serialport::{SerialPort, TTYPort};
+use sfc6xxx_rs::device::Device;
+use sfc6xxx_rs::emulator::{EmulatorConfig, EmulatorHandle, Fault, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::transport::Transport;
+
+/// Moves bytes between the master end of a pty and the emulator until dropped
+struct PtyBridge {
+    path: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    // the slave end stays open so the master never sees a hang up
+    _slave: TTYPort,
+}
+
+impl PtyBridge {
+    fn start(mut emulator: Sfc6xxxEmulator) -> Self {
+        let (mut master, slave) = TTYPort::pair().unwrap();
+        let path = slave.name().unwrap();
+        SerialPort::set_timeout(&mut master, Duration::from_millis(5)).unwrap();
+        Transport::set_timeout(&mut emulator, Duration::from_millis(1)).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut buf = [0_u8; 64];
+            while !running.load(Ordering::Relaxed) {
+                match master.read(&mut buf) {
+                    Ok(read) => emulator.write_all(&buf[..read]).unwrap(),
+                    Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                    Err(e) => panic!("pty master failed: {}", e),
+                }
+                while let Ok(read) = emulator.read(&mut buf) {
+                    // a few bytes at a time so the driver sees short reads
+                    for chunk in buf[..read].chunks(4) {
+                        master.write_all(chunk).unwrap();
+                        master.flush().unwrap();
+                        thread::sleep(Duration::from_micros(200));
+                    }
+                }
+            }
+        });
+
+        Self {
+            path,
+            stop,
+            thread: Some(thread),
+            _slave: slave,
+        }
+    }
+
+    fn open(&self) -> TTYPort {
+        serialport::new(&self.path, 115200)
+            .timeout(Duration::from_millis(100))
+            .open_native()
+            .unwrap()
+    }
+}
+
+impl Drop for PtyBridge {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn device_with(config: EmulatorConfig) -> (Device<TTYPort>, EmulatorHandle, PtyBridge) {
+    let emulator = Sfc6xxxEmulator::new(config);
+    let handle = emulator.handle();
+    let bridge = PtyBridge::start(emulator);
+    // the constructor probes the device
+    let device = Device::new(bridge.open(), 0).unwrap();
+    (device, handle, bridge)
+}
+
+#[test]
+fn command_sequence() {
+    let config = EmulatorConfig {
+        boot_time: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let (mut device, handle, _bridge) = device_with(config);
+    assert_eq!(handle.requests().len(), 1);
+
+    device.set_setpoint(2.5).unwrap();
+    assert_eq!(device.get_setpoint().unwrap(), 2.5);
+    assert_eq!(device.read_average_measured_value(50).unwrap(), 2.5);
+    assert!(matches!(
+        device.read_average_measured_value(101),
+        Err(DeviceError::UnsupportedFirmware { .. })
+    ));
+    assert_eq!(device.get_serial_number().unwrap(), "EMU6000001");
+
+    // the device does not answer while it boots, wait until it does
+    device.reset_device().unwrap();
+    device.set_response_timeout(Duration::from_millis(20));
+    let start = Instant::now();
+    let baudrate = loop {
+        match device.get_baudrate() {
+            Ok(baudrate) => break baudrate,
+            Err(DeviceError::Timeout) if start.elapsed() < Duration::from_secs(1) => {}
+            Err(e) => panic!("device did not come back after the reset: {}", e),
+        }
+    };
+    assert_eq!(baudrate, Baudrate::B115200);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(handle.resets(), 1);
+    assert_eq!(device.get_setpoint().unwrap(), 0.0);
+}
+
+#[test]
+fn timeouts_on_a_real_tty() {
+    let (mut device, handle, _bridge) = device_with(EmulatorConfig::default());
+    device.set_response_timeout(Duration::from_millis(50));
+
+    handle.inject_fault(Fault::DropResponse);
+    let start = Instant::now();
+    assert!(matches!(device.get_baudrate(), Err(DeviceError::Timeout)));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(50));
+    assert!(elapsed < Duration::from_millis(250));
+
+    handle.inject_fault(Fault::TruncateAfter(6));
+    assert!(matches!(device.get_baudrate(), Err(DeviceError::IncompleteFrame)));
+    assert_eq!(device.get_baudrate().unwrap(), Baudrate::B115200);
+}
+
+#[test]
+fn baudrate_change_reconfigures_the_tty() {
+    let (mut device, handle, _bridge) = device_with(EmulatorConfig::default());
+    device.set_baudrate(Baudrate::B57600).unwrap();
+    assert_eq!(handle.baudrate(), 57600);
+    assert_eq!(device.get_baudrate().unwrap(), Baudrate::B57600);
+}
@@ -0,0 +1,177 @@
+//! Runs the supervisor against two emulators reached through tokio duplex streams, one of them
+//! is unplugged and comes back while the other one keeps being polled.
+//!
+//! Run with `cargo test -p sfc6xxx-rs --features supervisor,emulator --test supervisor`.
+#![cfg(all(feature = "supervisor", feature = "emulator"))]
+
+use std::future::Future;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+use sfc6xxx_rs::emulator::{EmulatorHandle, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::transport::Transport;
+use sfc6xxx_rs::supervisor::{Backoff, DeviceDescriptor, Health, PortSource, Supervisor};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+use tokio::sync::watch;
+
+/// Moves bytes between the far end of a duplex stream and the emulator
+async fn bridge(mut stream: DuplexStream, mut emulator: Sfc6xxxEmulator) {
+    Transport::set_timeout(&mut emulator, Duration::ZERO).unwrap();
+    let mut buf = [0_u8; 64];
+    loop {
+        match tokio::time::timeout(Duration::from_millis(1), stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return,
+            Ok(Ok(read)) => {
+                if emulator.write_all(&buf[..read]).is_err() {
+                    return;
+                }
+            }
+            Err(_) => {}
+        }
+        loop {
+            match emulator.read(&mut buf) {
+                Ok(read) => stream.write_all(&buf[..read]).await.unwrap(),
+                Err(e) if e.kind() == ErrorKind::TimedOut => break,
+                // unplugged, dropping the stream closes the driver's end
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+/// An emulator that can be taken away and brought back, reached through a new duplex stream
+/// on every connect
+#[derive(Clone)]
+struct Plug {
+    handle: EmulatorHandle,
+    plugged_in: Arc<AtomicBool>,
+    connects: Arc<AtomicU32>,
+}
+
+impl Plug {
+    fn new() -> Self {
+        Self {
+            handle: Sfc6xxxEmulator::default().handle(),
+            plugged_in: Arc::new(AtomicBool::new(true)),
+            connects: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    fn unplug(&self) {
+        self.plugged_in.store(false, Ordering::SeqCst);
+        self.handle.unplug();
+    }
+
+    fn replug(&self) {
+        self.plugged_in.store(true, Ordering::SeqCst);
+    }
+
+    fn descriptor(&self) -> DeviceDescriptor {
+        let plug = self.clone();
+        let source = PortSource::custom(move || {
+            let plug = plug.clone();
+            async move {
+                plug.connects.fetch_add(1, Ordering::SeqCst);
+                if !plug.plugged_in.load(Ordering::SeqCst) {
+                    return Err(std::io::Error::from(ErrorKind::NotFound).into());
+                }
+                let (stream, far_end) = duplex(256);
+                tokio::spawn(bridge(far_end, plug.handle.replug()));
+                Ok::<_, DeviceError>(stream)
+            }
+        });
+        DeviceDescriptor {
+            poll_interval: Duration::from_millis(10),
+            ..DeviceDescriptor::new(source, 0)
+        }
+    }
+}
+
+fn fast_backoff() -> Backoff {
+    Backoff {
+        initial: Duration::from_millis(10),
+        max: Duration::from_millis(40),
+    }
+}
+
+/// Waits for a value the condition holds for, failing the test after two seconds
+async fn until<T, F>(receiver: &mut watch::Receiver<T>, condition: F)
+where
+    F: FnMut(&T) -> bool,
+{
+    tokio::time::timeout(Duration::from_secs(2), receiver.wait_for(condition))
+        .await
+        .expect("timed out")
+        .expect("the supervisor stopped");
+}
+
+async fn eventually<F: Future>(future: F) -> F::Output {
+    tokio::time::timeout(Duration::from_secs(2), future).await.expect("timed out")
+}
+
+#[tokio::test]
+async fn polls_and_reconnects_each_device() {
+    let plugs = [Plug::new(), Plug::new()];
+    let supervisor =
+        Supervisor::with_backoff(plugs.iter().map(Plug::descriptor), fast_backoff());
+    assert_eq!(supervisor.len(), 2);
+
+    for device in 0..2 {
+        until(&mut supervisor.health(device), |h| *h == Health::Online).await;
+    }
+    supervisor.set_setpoint(0, 2.0).await.unwrap();
+    supervisor.set_setpoint(1, 1.0).await.unwrap();
+    assert_eq!(plugs[0].handle.setpoint(), 2.0);
+    let mut first = supervisor.measurement(0);
+    until(&mut first, |m| m.as_ref().is_some_and(|m| m.value == 2.0)).await;
+    assert_eq!(first.borrow().as_ref().unwrap().setpoint, Some(2.0));
+
+    // the second adapter is pulled, the first device keeps being polled
+    plugs[1].unplug();
+    let mut second = supervisor.health(1);
+    until(&mut second, |h| matches!(h, Health::Reconnecting { attempt: 2.., .. })).await;
+    assert!(supervisor.set_setpoint(1, 3.0).await.is_err());
+    first.mark_unchanged();
+    eventually(first.changed()).await.unwrap();
+    assert_eq!(*supervisor.health(0).borrow(), Health::Online);
+
+    let connects = plugs[1].connects.load(Ordering::SeqCst);
+    plugs[1].replug();
+    until(&mut second, |h| *h == Health::Online).await;
+    assert!(plugs[1].connects.load(Ordering::SeqCst) > connects);
+    supervisor.set_setpoint(1, 3.0).await.unwrap();
+    let mut measurement = supervisor.measurement(1);
+    until(&mut measurement, |m| m.as_ref().is_some_and(|m| m.value == 3.0)).await;
+    assert_eq!(plugs[0].connects.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn backoff_grows_while_a_device_is_missing() {
+    let plug = Plug::new();
+    plug.unplug();
+    let supervisor = Supervisor::with_backoff([plug.descriptor()], fast_backoff());
+    let mut health = supervisor.health(0);
+    until(&mut health, |h| matches!(h, Health::Reconnecting { attempt: 4.., .. })).await;
+    // 10, 20 and 40ms between the first four attempts
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let attempts = plug.connects.load(Ordering::SeqCst);
+    assert!((6..=12).contains(&attempts), "connected {} times", attempts);
+
+    plug.replug();
+    until(&mut health, |h| *h == Health::Online).await;
+}
+
+#[tokio::test]
+async fn missing_serial_port_is_reported() {
+    let source = PortSource::Path("/dev/does-not-exist".into());
+    let supervisor = Supervisor::with_backoff([DeviceDescriptor::new(source, 0)], fast_backoff());
+    let mut health = supervisor.health(0);
+    until(&mut health, |h| matches!(h, Health::Reconnecting { .. })).await;
+    assert!(matches!(
+        supervisor.set_setpoint(0, 1.0).await,
+        Err(DeviceError::IoError(e)) if e.kind() == ErrorKind::NotConnected
+    ));
+}
@@ -0,0 +1,428 @@
+//! The device a command runs on, for either model
+
+use std::time::Duration;
+
+use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+use sfc6xxx_rs::sfc_core::discovery::{NativePort, open_port};
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::flow_controller::FlowController;
+use sfc6xxx_rs::sfc_core::gasunit::GasUnit;
+use sfc6xxx_rs::sfc_core::identity::IdentityFilter;
+use sfc6xxx_rs::sfc_core::shdlc::{MISOFrame, TranslationError, Version, parse_string};
+
+use crate::CliError;
+
+/// The device families sfcctl can talk to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Model {
+    Sfc6xxx,
+    Sfc5xxx,
+}
+
+/// An open device of either model
+pub enum Controller {
+    Sfc6xxx(sfc6xxx_rs::device::Device<NativePort>),
+    Sfc5xxx(sfc5xxx_rs::device::Device<NativePort>),
+}
+
+/// What `info` prints
+pub struct Info {
+    pub product_name: String,
+    pub serial_number: String,
+    pub article_code: String,
+    pub version: Version,
+    pub address: u8,
+    pub baudrate: Baudrate,
+    pub gas_unit: GasUnit,
+    pub full_scale: f32,
+}
+
+/// A slot of the calibration table
+pub struct Calibration {
+    pub index: u32,
+    pub valid: bool,
+    pub gas_id: Option<u32>,
+    pub gas_unit: Option<GasUnit>,
+    pub full_scale: Option<f32>,
+    /// Only the SFC5xxx stores a description
+    pub description: Option<String>,
+}
+
+impl Controller {
+    /// Opens the port with the SHDLC settings, probes the device and checks that it is the one
+    /// `identity` asks for
+    pub fn open(
+        model: Model,
+        port_name: &str,
+        baud_rate: u32,
+        address: u8,
+        timeout: Duration,
+        identity: IdentityFilter,
+    ) -> Result<Self, DeviceError> {
+        let port = open_port(port_name, baud_rate)?;
+        Ok(match model {
+            Model::Sfc6xxx => Self::Sfc6xxx(
+                sfc6xxx_rs::device::Device::builder(port)
+                    .address(address)
+                    .timeout(timeout)
+                    .identity(identity)
+                    .build()?,
+            ),
+            Model::Sfc5xxx => Self::Sfc5xxx(
+                sfc5xxx_rs::device::Device::builder(port)
+                    .address(address)
+                    .timeout(timeout)
+                    .identity(identity)
+                    .build()?,
+            ),
+        })
+    }
+
+    /// The commands both models share
+    pub fn flow(&mut self) -> &mut dyn FlowController {
+        match self {
+            Self::Sfc6xxx(device) => device,
+            Self::Sfc5xxx(device) => device,
+        }
+    }
+
+    /// Sets how many requests [Controller::info] and [Controller::calibrations] write ahead of
+    /// their responses, 1 sends them one at a time
+    pub fn set_pipeline_window(&mut self, window: usize) {
+        match self {
+            Self::Sfc6xxx(device) => device.set_pipeline_window(window),
+            Self::Sfc5xxx(device) => device.set_pipeline_window(window),
+        }
+    }
+
+    fn pipelined(&self) -> bool {
+        let window = match self {
+            Self::Sfc6xxx(device) => device.pipeline_window(),
+            Self::Sfc5xxx(device) => device.pipeline_window(),
+        };
+        window > 1
+    }
+
+    pub fn info(&mut self) -> Result<Info, DeviceError> {
+        if self.pipelined() {
+            return self.info_pipelined();
+        }
+        let (product_name, article_code, address, baudrate) = match self {
+            Self::Sfc6xxx(device) => (
+                device.get_product_name()?,
+                device.get_article_code()?,
+                device.get_slave_adress()?,
+                device.get_baudrate()?,
+            ),
+            Self::Sfc5xxx(device) => (
+                device.get_product_name()?,
+                device.get_article_code()?,
+                device.get_device_address()?,
+                device.get_baudrate()?,
+            ),
+        };
+        let flow = self.flow();
+        Ok(Info {
+            product_name,
+            serial_number: flow.get_serial_number()?,
+            article_code,
+            version: flow.get_version()?,
+            address,
+            baudrate,
+            gas_unit: flow.get_gas_unit()?,
+            full_scale: flow.get_full_scale()?,
+        })
+    }
+
+    /// [Controller::info] with every command in one pipeline
+    fn info_pipelined(&mut self) -> Result<Info, DeviceError> {
+        let responses = match self {
+            Self::Sfc6xxx(device) => {
+                use sfc6xxx_rs::commands::Command;
+                device.pipeline(&[
+                    Command::GetProductName,
+                    Command::GetSerialNumber,
+                    Command::GetArticleCode,
+                    Command::GetVersion,
+                    Command::GetSlaveAddress,
+                    Command::GetBaudrate,
+                    Command::GetCurrentGasUnit,
+                    Command::GetCurrentFullScale,
+                ])?
+            }
+            Self::Sfc5xxx(device) => {
+                use sfc5xxx_rs::commands::Command;
+                device.pipeline(&[
+                    Command::GetProductName,
+                    Command::GetSerialNumber,
+                    Command::GetArticleCode,
+                    Command::GetVersion,
+                    Command::GetSlaveAddress,
+                    Command::GetBaudrate,
+                    Command::GetMediumUnit {
+                        include_wild_cards: false,
+                    },
+                    Command::GetConvertedFullScale,
+                ])?
+            }
+        };
+        let mut responses = Responses(responses.into_iter());
+        Ok(Info {
+            product_name: responses.string()?,
+            serial_number: responses.string()?,
+            article_code: responses.string()?,
+            version: responses.version()?,
+            address: responses.byte()?,
+            baudrate: Baudrate::from_device(responses.integer()?),
+            gas_unit: responses.gas_unit()?,
+            full_scale: responses.float()?,
+        })
+    }
+
+    pub fn set_address(&mut self, address: u8) -> Result<(), DeviceError> {
+        match self {
+            Self::Sfc6xxx(device) => device.set_slave_adress(address),
+            Self::Sfc5xxx(device) => device.set_slave_address(address),
+        }
+    }
+
+    pub fn set_baudrate(&mut self, baudrate: Baudrate) -> Result<(), DeviceError> {
+        match self {
+            Self::Sfc6xxx(device) => device.set_baudrate(baudrate),
+            Self::Sfc5xxx(device) => device.set_baudrate(baudrate),
+        }
+    }
+
+    /// The index of the active calibration, the SFC5xxx can't report it
+    pub fn active_calibration(&mut self) -> Result<Option<u32>, DeviceError> {
+        match self {
+            Self::Sfc6xxx(device) => device.get_calliration_number().map(Some),
+            Self::Sfc5xxx(_) => Ok(None),
+        }
+    }
+
+    /// Reads the whole calibration table, the details only of valid slots
+    pub fn calibrations(&mut self) -> Result<Vec<Calibration>, DeviceError> {
+        if self.pipelined() {
+            return self.calibrations_pipelined();
+        }
+        let mut calibrations = Vec::new();
+        match self {
+            Self::Sfc6xxx(device) => {
+                for index in 0..device.get_number_of_calibrations()? {
+                    let valid = device.get_calibration_validity(index)?;
+                    calibrations.push(Calibration {
+                        index,
+                        valid,
+                        gas_id: valid
+                            .then(|| device.get_calibration_gas_id(index))
+                            .transpose()?,
+                        gas_unit: valid
+                            .then(|| device.get_calibration_gas_unit(index))
+                            .transpose()?,
+                        full_scale: valid
+                            .then(|| device.get_calibration_full_scale(index))
+                            .transpose()?,
+                        description: None,
+                    });
+                }
+            }
+            Self::Sfc5xxx(device) => {
+                for index in 0..device.get_number_of_calibrations()? {
+                    let valid = device.get_calibration_validity(index)?;
+                    calibrations.push(Calibration {
+                        index,
+                        valid,
+                        gas_id: valid
+                            .then(|| device.get_calibration_gas_id(index))
+                            .transpose()?,
+                        gas_unit: valid
+                            .then(|| device.get_calibration_gas_unit(index))
+                            .transpose()?,
+                        full_scale: valid
+                            .then(|| device.get_calibration_fullscale(index))
+                            .transpose()?,
+                        description: valid
+                            .then(|| device.get_calibration_gas_description(index))
+                            .transpose()?,
+                    });
+                }
+            }
+        }
+        Ok(calibrations)
+    }
+
+    /// [Controller::calibrations] in two pipelines, the validity of every slot and then the
+    /// details of the valid ones
+    fn calibrations_pipelined(&mut self) -> Result<Vec<Calibration>, DeviceError> {
+        let mut calibrations = Vec::new();
+        match self {
+            Self::Sfc6xxx(device) => {
+                use sfc6xxx_rs::commands::Command;
+                let count = device.get_number_of_calibrations()?;
+                let validity: Vec<_> = (0..count)
+                    .map(|index| Command::GetCalibrationValidity { index })
+                    .collect();
+                let valid = Responses(device.pipeline(&validity)?.into_iter()).flags(count)?;
+                let details: Vec<_> = valid_slots(&valid)
+                    .flat_map(|index| {
+                        [
+                            Command::GetCalibrationGasId { index },
+                            Command::GetCalibrationGasUnit { index },
+                            Command::GetCalibrationFullScale { index },
+                        ]
+                    })
+                    .collect();
+                let mut details = Responses(device.pipeline(&details)?.into_iter());
+                for (index, valid) in (0..count).zip(valid) {
+                    calibrations.push(Calibration {
+                        index,
+                        valid,
+                        gas_id: valid.then(|| details.integer()).transpose()?,
+                        gas_unit: valid.then(|| details.gas_unit()).transpose()?,
+                        full_scale: valid.then(|| details.float()).transpose()?,
+                        description: None,
+                    });
+                }
+            }
+            Self::Sfc5xxx(device) => {
+                use sfc5xxx_rs::commands::Command;
+                let count = device.get_number_of_calibrations()?;
+                let validity: Vec<_> = (0..count)
+                    .map(|index| Command::GetCalibrationValidity { index })
+                    .collect();
+                let valid = Responses(device.pipeline(&validity)?.into_iter()).flags(count)?;
+                let details: Vec<_> = valid_slots(&valid)
+                    .flat_map(|index| {
+                        [
+                            Command::GetCalibrationGasId { index },
+                            Command::GetCalibrationGasUnit { index },
+                            Command::GetCalibrationFullScale { index },
+                            Command::GetCalibrationGasDescription { index },
+                        ]
+                    })
+                    .collect();
+                let mut details = Responses(device.pipeline(&details)?.into_iter());
+                for (index, valid) in (0..count).zip(valid) {
+                    calibrations.push(Calibration {
+                        index,
+                        valid,
+                        gas_id: valid.then(|| details.integer()).transpose()?,
+                        gas_unit: valid.then(|| details.gas_unit()).transpose()?,
+                        full_scale: valid.then(|| details.float()).transpose()?,
+                        description: valid.then(|| details.string()).transpose()?,
+                    });
+                }
+            }
+        }
+        Ok(calibrations)
+    }
+
+    /// Switches to the calibration at the index, the SFC5xxx only stores it persistently
+    pub fn select_calibration(&mut self, index: u32, persistent: bool) -> Result<(), CliError> {
+        match self {
+            Self::Sfc6xxx(device) if persistent => device.set_callibration(index)?,
+            Self::Sfc6xxx(device) => device.set_callibration_volitile(index)?,
+            Self::Sfc5xxx(device) if persistent => device.set_callibration(index)?,
+            Self::Sfc5xxx(_) => {
+                return Err(CliError::Unsupported(
+                    "the SFC5xxx only switches calibrations persistently",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches to the calibration of a gas and returns its index, only on the SFC6xxx
+    pub fn select_calibration_for_gas(
+        &mut self,
+        gas_id: u32,
+        persistent: bool,
+    ) -> Result<u32, CliError> {
+        use sfc6xxx_rs::calibration::SwitchMode;
+
+        match self {
+            Self::Sfc6xxx(device) => {
+                let mode = if persistent {
+                    SwitchMode::persistent()
+                } else {
+                    SwitchMode::volatile()
+                };
+                Ok(device.select_calibration_for_gas(gas_id, mode)?.index)
+            }
+            Self::Sfc5xxx(_) => Err(CliError::Unsupported(
+                "selecting a calibration by gas is only supported on the SFC6xxx",
+            )),
+        }
+    }
+}
+
+/// The indices of the valid calibration slots
+fn valid_slots(valid: &[bool]) -> impl Iterator<Item = u32> + '_ {
+    (0..)
+        .zip(valid)
+        .filter(|(_, valid)| **valid)
+        .map(|(index, _)| index)
+}
+
+/// Decodes the responses of a pipeline in the order of its commands, both models encode their
+/// values the same way
+struct Responses(std::vec::IntoIter<MISOFrame>);
+
+impl Responses {
+    /// The data of the next response, at least `length` bytes
+    fn data(&mut self, length: usize) -> Result<Vec<u8>, DeviceError> {
+        let data = self
+            .0
+            .next()
+            .map(|response| response.into_data().to_vec())
+            .unwrap_or_default();
+        if data.len() < length {
+            Err(TranslationError::NotEnoughData(
+                length as u8,
+                data.len() as u8,
+            ))?;
+        }
+        Ok(data)
+    }
+
+    fn byte(&mut self) -> Result<u8, DeviceError> {
+        Ok(self.data(1)?[0])
+    }
+
+    /// The flags of the next `count` responses
+    fn flags(&mut self, count: u32) -> Result<Vec<bool>, DeviceError> {
+        (0..count).map(|_| Ok(self.byte()? > 0)).collect()
+    }
+
+    fn integer(&mut self) -> Result<u32, DeviceError> {
+        let data = self.data(4)?;
+        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    }
+
+    fn float(&mut self) -> Result<f32, DeviceError> {
+        Ok(f32::from_bits(self.integer()?))
+    }
+
+    fn gas_unit(&mut self) -> Result<GasUnit, DeviceError> {
+        let data = self.data(3)?;
+        Ok(GasUnit::from_be_bytes([data[0], data[1], data[2]]))
+    }
+
+    fn string(&mut self) -> Result<String, DeviceError> {
+        parse_string(&self.data(0)?)
+    }
+
+    fn version(&mut self) -> Result<Version, DeviceError> {
+        let data = self.data(7)?;
+        Ok(Version {
+            firmware_major: data[0],
+            firmware_minor: data[1],
+            debug: data[2] > 0,
+            hardware_major: data[3],
+            hardware_minor: data[4],
+            protocol_major: data[5],
+            protocol_minor: data[6],
+        })
+    }
+}
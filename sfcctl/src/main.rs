@@ -0,0 +1,436 @@
+//! # sfcctl
+//! One-off commands for bringing up SFC6xxx and SFC5xxx mass flow controllers: reading their
+//! identity, changing the address and baudrate, setting a setpoint, measuring and switching
+//! calibrations.
+//! ```text
+//! sfcctl --port /dev/ttyUSB0 info
+//! sfcctl --port /dev/ttyUSB0 --json measure --watch --interval-ms 200
+//! sfcctl --port /dev/ttyUSB0 --model sfc5xxx calibrations list
+//! sfcctl --port /dev/ttyUSB0 --serial 23170002 setpoint 1.5
+//! ```
+//! The exit code tells scripts what went wrong, see [CliError::exit_code].
+
+mod controller;
+mod output;
+
+use std::fmt::Display;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{ArgGroup, Parser, Subcommand};
+use serde_json::{Value, json};
+use sfc6xxx_rs::sfc_core::baudrate::Baudrate;
+use sfc6xxx_rs::sfc_core::discovery::find_sensirion_ports;
+use sfc6xxx_rs::sfc_core::error::DeviceError;
+use sfc6xxx_rs::sfc_core::identity::IdentityFilter;
+use sfc6xxx_rs::sfc_core::shdlc::Version;
+
+use controller::{Controller, Model};
+use output::Output;
+
+#[derive(Parser)]
+#[command(name = "sfcctl", version, about)]
+struct Cli {
+    /// The serial port the device is connected to, like /dev/ttyUSB0 or COM4
+    #[arg(short, long, global = true)]
+    port: Option<String>,
+    /// The baudrate of the port
+    #[arg(short, long, default_value_t = 115200, global = true)]
+    baud: u32,
+    /// The slave address of the device
+    #[arg(short, long, default_value_t = 0, global = true)]
+    address: u8,
+    /// The device family
+    #[arg(short, long, value_enum, default_value_t = Model::Sfc6xxx, global = true)]
+    model: Model,
+    /// How long the device has to start answering a command
+    #[arg(long, default_value_t = 500, global = true)]
+    timeout_ms: u64,
+    /// How many requests `info` and `calibrations` send before reading the responses, 1 sends
+    /// one at a time
+    #[arg(long, default_value_t = 1, global = true)]
+    pipeline: usize,
+    /// Only change the device with this serial number, the commands that change a device fail
+    /// before sending anything else to another one
+    #[arg(long, global = true)]
+    serial: Option<String>,
+    /// Print the results, and errors, as JSON
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the product, serial number, versions and communication settings
+    Info,
+    /// List the serial ports that look like a Sensirion cable
+    Scan {
+        /// Also probe the addresses up to this one on --port
+        #[arg(long)]
+        probe: Option<u8>,
+    },
+    /// Change the slave address, the device keeps it after a reset
+    SetAddress {
+        // not `address`, that id belongs to the global option
+        #[arg(value_name = "ADDRESS")]
+        new_address: u8,
+    },
+    /// Change the baudrate, the device keeps it after a reset
+    SetBaud { baudrate: u32 },
+    /// Print the setpoint, or set it if a value is given
+    Setpoint { value: Option<f32> },
+    /// Print the measured flow
+    Measure {
+        /// Keep measuring until interrupted
+        #[arg(long)]
+        watch: bool,
+        /// The time between two measurements while watching
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Stop watching after this many measurements
+        #[arg(long)]
+        count: Option<u32>,
+    },
+    /// List or switch the calibrations
+    Calibrations {
+        #[command(subcommand)]
+        command: CalibrationCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum CalibrationCommand {
+    /// Print the calibration table
+    List,
+    /// Switch to a calibration by its index or its gas
+    #[command(group(ArgGroup::new("slot").required(true).args(["index", "gas"])))]
+    Select {
+        index: Option<u32>,
+        /// The gas ID to switch to, only on the SFC6xxx
+        #[arg(long)]
+        gas: Option<u32>,
+        /// Only switch until the next reset, only on the SFC6xxx
+        #[arg(long)]
+        volatile: bool,
+    },
+}
+
+impl Command {
+    /// Whether the command changes the device, these check --serial before they do
+    fn is_destructive(&self) -> bool {
+        match self {
+            Self::SetAddress { .. }
+            | Self::SetBaud { .. }
+            | Self::Calibrations {
+                command: CalibrationCommand::Select { .. },
+            } => true,
+            Self::Setpoint { value } => value.is_some(),
+            Self::Info
+            | Self::Scan { .. }
+            | Self::Measure { .. }
+            | Self::Calibrations {
+                command: CalibrationCommand::List,
+            } => false,
+        }
+    }
+}
+
+/// Why a command failed
+#[derive(Debug)]
+enum CliError {
+    Device(DeviceError),
+    /// The command needs --port
+    NoPort,
+    /// The model can't do this
+    Unsupported(&'static str),
+}
+
+impl CliError {
+    /// The exit code for scripts, grouped by what went wrong:
+    /// - 1: anything not listed below, like an unsupported command
+    /// - 2: invalid arguments
+    /// - 3: the device did not answer in time
+    /// - 4: the port could not be opened or used
+    /// - 5: the device's answer was garbled
+    /// - 6: the device answered with an error state, like a parameter out of range
+    /// - 7: the device is not the one --serial names
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::Device(error) => device_exit_code(error),
+            Self::NoPort => 2,
+            Self::Unsupported(_) => 1,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.exit_code() {
+            2 => "usage",
+            3 => "timeout",
+            4 => "port",
+            5 => "frame",
+            6 => "device_state",
+            7 => "identity",
+            _ => "other",
+        }
+    }
+}
+
+fn device_exit_code(error: &DeviceError) -> u8 {
+    match error {
+        // a command that failed after retrying failed like its last attempt
+        DeviceError::RetriesExhausted(_, last) => device_exit_code(last),
+        DeviceError::Timeout | DeviceError::DeadlineExceeded => 3,
+        DeviceError::IoError(_) | DeviceError::PortError(_) => 4,
+        DeviceError::ShdlcError(_)
+        | DeviceError::InvalidChecksum(_, _)
+        | DeviceError::InvalidString
+        | DeviceError::IncompleteFrame => 5,
+        DeviceError::StateResponse(_) => 6,
+        DeviceError::IdentityMismatch { .. } => 7,
+        _ => 1,
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Device(e) => e.fmt(f),
+            Self::NoPort => write!(f, "this command needs a --port"),
+            Self::Unsupported(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<DeviceError> for CliError {
+    fn from(value: DeviceError) -> Self {
+        Self::Device(value)
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if json {
+                eprintln!("{}", json!({ "error": e.to_string(), "kind": e.kind() }));
+            } else {
+                eprintln!("sfcctl: {}", e);
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), CliError> {
+    let output = Output { json: cli.json };
+    let timeout = Duration::from_millis(cli.timeout_ms);
+    if let Command::Scan { probe } = cli.command {
+        return scan(&cli, probe, output);
+    }
+
+    let port = cli.port.as_deref().ok_or(CliError::NoPort)?;
+    let identity = match &cli.serial {
+        Some(serial) if cli.command.is_destructive() => IdentityFilter::serial_number(serial),
+        _ => IdentityFilter::default(),
+    };
+    let mut controller =
+        Controller::open(cli.model, port, cli.baud, cli.address, timeout, identity)?;
+    controller.set_pipeline_window(cli.pipeline);
+    match cli.command {
+        Command::Scan { .. } => unreachable!("scanning needs no open device"),
+        Command::Info => {
+            let info = controller.info()?;
+            output.record(&[
+                ("product_name", json!(info.product_name)),
+                ("serial_number", json!(info.serial_number)),
+                ("article_code", json!(info.article_code)),
+                ("firmware", json!(firmware(&info.version))),
+                (
+                    "hardware",
+                    json!(format!(
+                        "{}.{}",
+                        info.version.hardware_major, info.version.hardware_minor
+                    )),
+                ),
+                (
+                    "protocol",
+                    json!(format!(
+                        "{}.{}",
+                        info.version.protocol_major, info.version.protocol_minor
+                    )),
+                ),
+                ("address", json!(info.address)),
+                ("baudrate", json!(u32::from(info.baudrate))),
+                ("gas_unit", json!(info.gas_unit.to_string())),
+                ("full_scale", json!(info.full_scale)),
+            ]);
+        }
+        Command::SetAddress { new_address } => {
+            controller.set_address(new_address)?;
+            output.record(&[("address", json!(new_address))]);
+        }
+        Command::SetBaud { baudrate } => {
+            controller.set_baudrate(Baudrate::try_from(baudrate)?)?;
+            output.record(&[("baudrate", json!(baudrate))]);
+        }
+        Command::Setpoint { value } => {
+            let flow = controller.flow();
+            if let Some(value) = value {
+                flow.set_setpoint(value)?;
+            }
+            let setpoint = flow.get_setpoint()?;
+            let unit = flow.get_gas_unit()?;
+            output.record(&[
+                ("setpoint", json!(setpoint)),
+                ("unit", json!(unit.to_string())),
+            ]);
+        }
+        Command::Measure {
+            watch,
+            interval_ms,
+            count,
+        } => measure(
+            &mut controller,
+            watch,
+            Duration::from_millis(interval_ms),
+            count,
+            output,
+        )?,
+        Command::Calibrations {
+            command: CalibrationCommand::List,
+        } => {
+            let active = controller.active_calibration()?;
+            let rows: Vec<Vec<Value>> = controller
+                .calibrations()?
+                .into_iter()
+                .map(|calibration| {
+                    vec![
+                        json!(calibration.index),
+                        json!(calibration.valid),
+                        json!(active.map(|active| active == calibration.index)),
+                        json!(calibration.gas_id),
+                        json!(calibration.gas_unit.map(|unit| unit.to_string())),
+                        json!(calibration.full_scale),
+                        json!(calibration.description),
+                    ]
+                })
+                .collect();
+            let columns = [
+                "index",
+                "valid",
+                "active",
+                "gas_id",
+                "unit",
+                "full_scale",
+                "description",
+            ];
+            output.table(&columns, &rows);
+        }
+        Command::Calibrations {
+            command:
+                CalibrationCommand::Select {
+                    index,
+                    gas,
+                    volatile,
+                },
+        } => {
+            let index = match (index, gas) {
+                (Some(index), _) => {
+                    controller.select_calibration(index, !volatile)?;
+                    index
+                }
+                (None, Some(gas)) => controller.select_calibration_for_gas(gas, !volatile)?,
+                (None, None) => unreachable!("clap requires an index or a gas"),
+            };
+            output.record(&[("index", json!(index)), ("persistent", json!(!volatile))]);
+        }
+    }
+    Ok(())
+}
+
+fn firmware(version: &Version) -> String {
+    let debug = if version.debug { " (debug)" } else { "" };
+    format!(
+        "{}.{}{}",
+        version.firmware_major, version.firmware_minor, debug
+    )
+}
+
+/// Lists the detected ports and probes the addresses on --port
+fn scan(cli: &Cli, probe: Option<u8>, output: Output) -> Result<(), CliError> {
+    let rows: Vec<Vec<Value>> = find_sensirion_ports()
+        .into_iter()
+        .map(|port| {
+            vec![
+                json!(port.port_name),
+                json!(format!("{:?}", port.kind)),
+                json!(format!("{:04x}:{:04x}", port.vid, port.pid)),
+                json!(port.serial_number),
+            ]
+        })
+        .collect();
+    if probe.is_none() || !output.json {
+        output.table(&["port", "kind", "usb_id", "serial_number"], &rows);
+    }
+
+    let Some(last) = probe else {
+        return Ok(());
+    };
+    let port = cli.port.as_deref().ok_or(CliError::NoPort)?;
+    let timeout = Duration::from_millis(cli.timeout_ms);
+    let mut found = Vec::new();
+    for address in 0..=last {
+        match Controller::open(cli.model, port, cli.baud, address, timeout, Default::default()) {
+            Ok(mut controller) => {
+                let serial_number = controller.flow().get_serial_number()?;
+                found.push(vec![json!(address), json!(serial_number)]);
+            }
+            Err(DeviceError::Timeout) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if !output.json {
+        println!();
+    }
+    output.table(&["address", "serial_number"], &found);
+    Ok(())
+}
+
+fn measure(
+    controller: &mut Controller,
+    watch: bool,
+    interval: Duration,
+    count: Option<u32>,
+    output: Output,
+) -> Result<(), CliError> {
+    let unit = controller.flow().get_gas_unit()?.to_string();
+    let count = if watch { count } else { Some(1) };
+    let start = Instant::now();
+    let mut taken = 0;
+    while count.is_none_or(|count| taken < count) {
+        if taken > 0 {
+            let next = start + interval * taken;
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+        let flow = controller.flow().read_measured_value()?;
+        let elapsed = start.elapsed().as_secs_f64();
+        if output.json {
+            output.record(&[
+                ("time_s", json!(elapsed)),
+                ("flow", json!(flow)),
+                ("unit", json!(unit)),
+            ]);
+        } else if watch {
+            println!("{:8.3}s  {} {}", elapsed, flow, unit);
+        } else {
+            println!("{} {}", flow, unit);
+        }
+        taken += 1;
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Printing results for people or, with `--json`, for scripts
+
+use serde_json::{Map, Value};
+
+/// Where command results go, human readable or one JSON document per result
+#[derive(Clone, Copy, Debug)]
+pub struct Output {
+    pub json: bool,
+}
+
+impl Output {
+    /// Prints named values, as `name: value` lines or one JSON object
+    pub fn record(&self, fields: &[(&str, Value)]) {
+        if self.json {
+            println!("{}", Value::Object(object(fields)));
+            return;
+        }
+        let width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, value) in fields {
+            println!(
+                "{:width$}  {}",
+                format!("{}:", name),
+                human(value),
+                width = width + 1
+            );
+        }
+    }
+
+    /// Prints rows of named values, as an aligned table or a JSON array of objects
+    pub fn table(&self, columns: &[&str], rows: &[Vec<Value>]) {
+        if self.json {
+            let rows = rows
+                .iter()
+                .map(|row| {
+                    let fields: Vec<(&str, Value)> =
+                        columns.iter().copied().zip(row.iter().cloned()).collect();
+                    Value::Object(object(&fields))
+                })
+                .collect();
+            println!("{}", Value::Array(rows));
+            return;
+        }
+        let cells: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.iter().map(human).collect())
+            .collect();
+        let widths: Vec<usize> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].len())
+                    .chain([column.len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |row: Vec<String>| {
+            let padded: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+                .collect();
+            println!("{}", padded.join("  ").trim_end());
+        };
+        line(columns.iter().map(|column| column.to_string()).collect());
+        for row in cells {
+            line(row);
+        }
+    }
+}
+
+fn object(fields: &[(&str, Value)]) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+/// A value the way a person reads it, strings without quotes and nothing as a dash
+fn human(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,256 @@
+//! Runs the sfcctl binary against emulated devices. Each emulator is bridged to the master end of
+//! a pseudo terminal and sfcctl opens the slave end by name like a real port.
+#![cfg(target_os = "linux")]
+
+use std::io::{ErrorKind, Read, Write};
+use std::process::{Command, Output};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde_json::Value;
+use serialport::{SerialPort, TTYPort};
+use sfc5xxx_rs::emulator::Sfc5xxxEmulator;
+use sfc6xxx_rs::emulator::{EmulatorHandle, Sfc6xxxEmulator};
+use sfc6xxx_rs::sfc_core::transport::Transport;
+
+/// Moves bytes between the master end of a pty and an emulator until dropped
+struct PtyBridge {
+    path: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    // the slave end stays open so the master never sees a hang up between two runs
+    _slave: TTYPort,
+}
+
+impl PtyBridge {
+    fn start<E: Read + Write + Transport + Send + 'static>(mut emulator: E) -> Self {
+        let (mut master, slave) = TTYPort::pair().unwrap();
+        let path = slave.name().unwrap();
+        SerialPort::set_timeout(&mut master, Duration::from_millis(5)).unwrap();
+        Transport::set_timeout(&mut emulator, Duration::from_millis(1)).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut buf = [0_u8; 64];
+            while !running.load(Ordering::Relaxed) {
+                match master.read(&mut buf) {
+                    Ok(read) => emulator.write_all(&buf[..read]).unwrap(),
+                    Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                    Err(e) => panic!("pty master failed: {}", e),
+                }
+                while let Ok(read) = emulator.read(&mut buf) {
+                    master.write_all(&buf[..read]).unwrap();
+                    master.flush().unwrap();
+                }
+            }
+        });
+
+        Self {
+            path,
+            stop,
+            thread: Some(thread),
+            _slave: slave,
+        }
+    }
+
+    /// Runs sfcctl on the bridged port
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_sfcctl"))
+            .args(["--port", &self.path])
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs sfcctl with `--json`, expects it to succeed and parses what it printed
+    fn json(&self, args: &[&str]) -> Value {
+        let output = self.run(&[&["--json"], args].concat());
+        assert!(
+            output.status.success(),
+            "sfcctl {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+}
+
+impl Drop for PtyBridge {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn sfc6xxx() -> (PtyBridge, EmulatorHandle) {
+    let emulator = Sfc6xxxEmulator::default();
+    let handle = emulator.handle();
+    (PtyBridge::start(emulator), handle)
+}
+
+#[test]
+fn info() {
+    let (bridge, _) = sfc6xxx();
+    let info = bridge.json(&["info"]);
+    assert_eq!(info["product_name"], "SFC6000D-5SLM");
+    assert_eq!(info["serial_number"], "EMU6000001");
+    assert_eq!(info["firmware"], "1.0");
+    assert_eq!(info["address"], 0);
+    assert_eq!(info["baudrate"], 115200);
+    assert_eq!(info["gas_unit"], "l/min");
+    assert_eq!(info["full_scale"], 5.0);
+
+    let output = bridge.run(&["info"]);
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("serial_number:  EMU6000001"), "{}", text);
+}
+
+#[test]
+fn setpoint() {
+    let (bridge, handle) = sfc6xxx();
+    let set = bridge.json(&["setpoint", "2.5"]);
+    assert_eq!(set["setpoint"], 2.5);
+    assert_eq!(set["unit"], "l/min");
+    assert_eq!(handle.setpoint(), 2.5);
+    assert_eq!(bridge.json(&["setpoint"])["setpoint"], 2.5);
+
+    // above full scale the device answers with an error state
+    let output = bridge.run(&["setpoint", "50"]);
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn measure() {
+    let (bridge, _) = sfc6xxx();
+    bridge.json(&["setpoint", "1.5"]);
+    assert_eq!(bridge.json(&["measure"])["flow"], 1.5);
+
+    let output = bridge.run(&[
+        "--json",
+        "measure",
+        "--watch",
+        "--count",
+        "3",
+        "--interval-ms",
+        "10",
+    ]);
+    assert!(output.status.success());
+    let readings: Vec<Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(readings.len(), 3);
+    assert!(readings.iter().all(|reading| reading["flow"] == 1.5));
+}
+
+#[test]
+fn calibrations() {
+    let (bridge, handle) = sfc6xxx();
+    let table = bridge.json(&["calibrations", "list"]);
+    let table = table.as_array().unwrap();
+    assert_eq!(table.len(), 4);
+    assert_eq!(table[0]["active"], true);
+    assert_eq!(table[2]["gas_id"], 3);
+    assert_eq!(table[2]["full_scale"], 2.5);
+    assert_eq!(table[3]["valid"], false);
+    assert_eq!(table[3]["gas_id"], Value::Null);
+
+    bridge.json(&["calibrations", "select", "1", "--volatile"]);
+    assert_eq!(handle.active_calibration(), 1);
+    let selected = bridge.json(&["calibrations", "select", "--gas", "3"]);
+    assert_eq!(selected["index"], 2);
+    assert_eq!(handle.active_calibration(), 2);
+
+    // neither an index nor a gas
+    assert_eq!(
+        bridge.run(&["calibrations", "select"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn pipelined_reads() {
+    let (bridge, _) = sfc6xxx();
+    for command in [&["info"][..], &["calibrations", "list"]] {
+        let pipelined = bridge.json(&[&["--pipeline", "8"], command].concat());
+        assert_eq!(pipelined, bridge.json(command));
+    }
+
+    let bridge = PtyBridge::start(Sfc5xxxEmulator::default());
+    for command in [&["info"][..], &["calibrations", "list"]] {
+        let command = [&["--model", "sfc5xxx"], command].concat();
+        let pipelined = bridge.json(&[&["--pipeline", "8"], &command[..]].concat());
+        assert_eq!(pipelined, bridge.json(&command));
+    }
+}
+
+#[test]
+fn set_address() {
+    let (bridge, handle) = sfc6xxx();
+    bridge.json(&["set-address", "7"]);
+    assert_eq!(handle.address(), 7);
+    assert_eq!(bridge.json(&["--address", "7", "info"])["address"], 7);
+}
+
+#[test]
+fn serial_guards_the_commands_that_change_the_device() {
+    let (bridge, handle) = sfc6xxx();
+    let output = bridge.run(&["--json", "--serial", "EMU6000002", "setpoint", "2.5"]);
+    assert_eq!(output.status.code(), Some(7));
+    let error: Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["kind"], "identity");
+    assert_eq!(handle.setpoint(), 0.0);
+
+    bridge.json(&["--serial", "EMU6000001", "setpoint", "2.5"]);
+    assert_eq!(handle.setpoint(), 2.5);
+    // reading is allowed whatever the serial number
+    assert_eq!(bridge.json(&["--serial", "EMU6000002", "setpoint"])["setpoint"], 2.5);
+}
+
+#[test]
+fn silent_device_is_a_timeout() {
+    let (bridge, _) = sfc6xxx();
+    let output = bridge.run(&["--json", "--address", "3", "--timeout-ms", "50", "info"]);
+    assert_eq!(output.status.code(), Some(3));
+    let error: Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["kind"], "timeout");
+}
+
+#[test]
+fn missing_port_is_a_usage_error() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sfcctl"))
+        .arg("info")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn sfc5xxx() {
+    let bridge = PtyBridge::start(Sfc5xxxEmulator::default());
+    let info = bridge.json(&["--model", "sfc5xxx", "info"]);
+    assert_eq!(info["product_name"], "SFC5400");
+    assert_eq!(info["firmware"], "1.48");
+
+    let table = bridge.json(&["--model", "sfc5xxx", "calibrations", "list"]);
+    assert_eq!(table[2]["description"], "CO2");
+    assert_eq!(table[0]["active"], Value::Null);
+
+    // the SFC5xxx can't switch calibrations until the next reset
+    let output = bridge.run(&[
+        "--model",
+        "sfc5xxx",
+        "calibrations",
+        "select",
+        "1",
+        "--volatile",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+}